@@ -1,6 +1,7 @@
 if call_type == "on_cutscene_end" then
     if zone == "cutscene" then
         unlock_quest(sender, 700020)
+        clear_quest(sender)
         move_lobby(sender)
     end
 end
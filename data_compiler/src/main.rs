@@ -1,17 +1,28 @@
 mod ice;
 use data_structs::{
-    inventory::{DefaultClassesData, DefaultClassesDataReadable, ItemName},
-    map::MapData,
+    affix::AffixData,
+    craft::Recipe,
+    inventory::{
+        DefaultClassesData, DefaultClassesDataReadable, DiskItem, ItemName, ItemPrice, LobbyItem,
+        StorageExpansion,
+    },
+    map::{EnemySpawnType, MapData, ZoneData},
+    mission::ArksMission,
+    missionpass::MissionPassSeason,
     name_to_id,
+    order::{ClientOrder, ClientOrderList},
     quest::QuestData,
+    shop::ShopData,
     stats::{
-        AllEnemyStats, AttackStats, AttackStatsReadable, ClassStatsStored, EnemyBaseStats,
-        EnemyLevelBaseStats, NamedEnemyStats, PlayerStats, RaceModifierStored,
+        AllEnemyStats, AttackStats, AttackStatsReadable, ClassStatsStored, CombatFormula,
+        EnemyBaseStats, EnemyLevelBaseStats, NamedEnemyStats, PlayerStats, RaceModifierStored,
     },
+    title::Title,
     SerDeFile as _, ServerData,
 };
 use pso2packetlib::protocol::models::item_attrs;
 use std::{
+    collections::HashMap,
     env,
     error::Error,
     fs,
@@ -51,6 +62,46 @@ fn main() {
         server_data.item_params.names = data;
     }
 
+    // parse lobby items
+    println!("Parsing lobby items...");
+    let mut lobby_items_file = filename.to_path_buf();
+    lobby_items_file.push("lobby_items");
+    lobby_items_file = select_ext(lobby_items_file);
+    if lobby_items_file.is_file() {
+        let data = Vec::<LobbyItem>::load_file(&lobby_items_file).unwrap();
+        server_data.item_params.lobby_items = data;
+    }
+
+    // parse item prices
+    println!("Parsing item prices...");
+    let mut prices_file = filename.to_path_buf();
+    prices_file.push("item_prices");
+    prices_file = select_ext(prices_file);
+    if prices_file.is_file() {
+        let data = Vec::<ItemPrice>::load_file(&prices_file).unwrap();
+        server_data.item_params.prices = data;
+    }
+
+    // parse storage expansions
+    println!("Parsing storage expansions...");
+    let mut expansions_file = filename.to_path_buf();
+    expansions_file.push("storage_expansions");
+    expansions_file = select_ext(expansions_file);
+    if expansions_file.is_file() {
+        let data = Vec::<StorageExpansion>::load_file(&expansions_file).unwrap();
+        server_data.item_params.storage_expansions = data;
+    }
+
+    // parse disks
+    println!("Parsing disks...");
+    let mut disks_file = filename.to_path_buf();
+    disks_file.push("disks");
+    disks_file = select_ext(disks_file);
+    if disks_file.is_file() {
+        let data = Vec::<DiskItem>::load_file(&disks_file).unwrap();
+        server_data.item_params.disks = data;
+    }
+
     // parse item attributes
     println!("Parsing item attributes...");
     let mut attrs_file = filename.to_path_buf();
@@ -87,6 +138,60 @@ fn main() {
     class_data_dir.push("class_data");
     server_data.default_classes = parse_default_classes(&class_data_dir).unwrap();
 
+    // parse combat formula
+    println!("Parsing combat formula...");
+    let mut combat_file = filename.to_path_buf();
+    combat_file.push("combat");
+    combat_file = select_ext(combat_file);
+    if combat_file.is_file() {
+        server_data.combat = CombatFormula::load_file(&combat_file).unwrap();
+    }
+
+    // parse shops
+    println!("Parsing shops...");
+    let mut shops_dir = filename.to_path_buf();
+    shops_dir.push("shops");
+    server_data.shops = parse_shops(&shops_dir).unwrap();
+
+    // parse affix data
+    println!("Parsing affix data...");
+    let mut affix_file = filename.to_path_buf();
+    affix_file.push("affixes");
+    affix_file = select_ext(affix_file);
+    if affix_file.is_file() {
+        server_data.affixes = AffixData::load_file(&affix_file).unwrap();
+    }
+
+    // parse recipes
+    println!("Parsing recipes...");
+    let mut recipes_dir = filename.to_path_buf();
+    recipes_dir.push("recipes");
+    server_data.recipes = parse_recipes(&recipes_dir).unwrap();
+
+    // parse titles
+    println!("Parsing titles...");
+    let mut titles_dir = filename.to_path_buf();
+    titles_dir.push("titles");
+    server_data.titles = parse_titles(&titles_dir).unwrap();
+
+    // parse client orders
+    println!("Parsing client orders...");
+    let mut orders_dir = filename.to_path_buf();
+    orders_dir.push("client_orders");
+    server_data.client_orders = parse_client_orders(&orders_dir).unwrap();
+
+    // parse mission pass seasons
+    println!("Parsing mission passes...");
+    let mut passes_dir = filename.to_path_buf();
+    passes_dir.push("mission_passes");
+    server_data.mission_passes = parse_mission_passes(&passes_dir).unwrap();
+
+    // parse arks missions
+    println!("Parsing ARKS missions...");
+    let mut missions_dir = filename.to_path_buf();
+    missions_dir.push("arks_missions");
+    server_data.arks_missions = parse_arks_missions(&missions_dir).unwrap();
+
     println!("Saving data...");
     let mut out_filename = filename.to_path_buf();
     out_filename.push("com_data.mp");
@@ -136,6 +241,13 @@ fn collect_map_data(map_path: &Path, map: &mut MapData) -> Result<(), Box<dyn Er
         })?;
     }
 
+    // precompute vita data of objects without lua scripts
+    for object in map.objects.iter_mut() {
+        if object.vita_data.is_none() && !map.luas.contains_key(object.data.name.as_str()) {
+            object.vita_data = Some(object.default_vita_data());
+        }
+    }
+
     // load transporters files
     let mut transporter_dir = map_path.to_path_buf();
     transporter_dir.push("transporters");
@@ -189,6 +301,73 @@ fn collect_map_data(map_path: &Path, map: &mut MapData) -> Result<(), Box<dyn Er
     }
     map.map_data.other_settings = other_settings;
 
+    for zone in &map.zones {
+        validate_encounters(zone)
+            .map_err(|e| format!("Invalid enemy spawns in zone {}: {e}", zone.zone_id))?;
+    }
+
+    Ok(())
+}
+
+fn validate_encounters(zone: &ZoneData) -> Result<(), String> {
+    for enemy in &zone.enemies {
+        if enemy.weight == 0 {
+            return Err(format!("enemy {} has zero weight", enemy.enemy_name));
+        }
+    }
+    for chunk in &zone.chunks {
+        let (min, max, encounter) = match &chunk.enemy_spawn_type {
+            EnemySpawnType::Automatic {
+                min,
+                max,
+                encounter,
+            }
+            | EnemySpawnType::AutomaticWithRespawn {
+                min,
+                max,
+                encounter,
+                ..
+            } => (*min, *max, encounter),
+            _ => continue,
+        };
+        let chunk_id = chunk.chunk_id;
+        if min > max {
+            return Err(format!("chunk {chunk_id} has min count larger than max"));
+        }
+        let mut categories: Vec<_> = encounter.categories.iter().map(|c| c.category).collect();
+        if categories.is_empty() {
+            categories = zone.enemies.iter().map(|e| e.spawn_category).collect();
+        }
+        if categories.is_empty() {
+            return Err(format!(
+                "chunk {chunk_id} spawns enemies, but zone has none"
+            ));
+        }
+        if !encounter.categories.is_empty() && encounter.categories.iter().all(|c| c.weight == 0) {
+            return Err(format!("chunk {chunk_id} has only zero weight categories"));
+        }
+        for category in categories {
+            let cheapest = zone
+                .enemies
+                .iter()
+                .filter(|e| e.spawn_category == category)
+                .map(|e| e.cost)
+                .min();
+            match cheapest {
+                None => {
+                    return Err(format!(
+                        "chunk {chunk_id} uses category {category} without enemies"
+                    ))
+                }
+                Some(cost) if encounter.budget != 0 && cost > encounter.budget => {
+                    return Err(format!(
+                        "chunk {chunk_id} budget is too small for category {category}"
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+    }
     Ok(())
 }
 
@@ -263,8 +442,10 @@ fn parse_player_stats(path: &Path) -> Result<PlayerStats, Box<dyn Error>> {
         if class_int >= max_class {
             max_class = class_int;
             data.stats.resize(class_int + 1, Default::default());
+            data.level_caps.resize(class_int + 1, 0);
         }
         data.stats[class_int] = stats.stats;
+        data.level_caps[class_int] = stats.level_cap;
         Ok(())
     })?;
 
@@ -309,8 +490,13 @@ fn parse_enemy_stats(
 
         let mut base = EnemyBaseStats::load_file(base_stats_path)?;
         let mut stats = std::mem::take(&mut base.levels);
-        stats.sort_by(|a, b| a.level.cmp(&b.level));
+        stats.sort_by_key(|a| a.level);
         base.levels = duplicate_stats(stats);
+        for table in base.difficulties.iter_mut().filter(|t| !t.is_empty()) {
+            let mut stats = std::mem::take(table);
+            stats.sort_by_key(|a| a.level);
+            *table = duplicate_stats(stats);
+        }
 
         data.base = base;
     }
@@ -323,7 +509,7 @@ fn parse_enemy_stats(
         {
             let base = &mut stats.stats;
             let mut stats = std::mem::take(&mut base.levels);
-            stats.sort_by(|a, b| a.level.cmp(&b.level));
+            stats.sort_by_key(|a| a.level);
             base.levels = duplicate_stats(stats);
         }
 
@@ -374,6 +560,69 @@ fn parse_default_classes(classes_path: &Path) -> Result<DefaultClassesData, Box<
     Ok(data)
 }
 
+fn parse_recipes(recipes_path: &Path) -> Result<Vec<Recipe>, Box<dyn Error>> {
+    let mut recipes = vec![];
+    traverse_data_dir(recipes_path, &mut |p| {
+        println!("\tParsing recipes {}...", p.display());
+        recipes.append(&mut Vec::load_file(p)?);
+        Ok(())
+    })?;
+    Ok(recipes)
+}
+
+fn parse_titles(titles_path: &Path) -> Result<Vec<Title>, Box<dyn Error>> {
+    let mut titles = vec![];
+    traverse_data_dir(titles_path, &mut |p| {
+        println!("\tParsing titles {}...", p.display());
+        titles.append(&mut Vec::load_file(p)?);
+        Ok(())
+    })?;
+    Ok(titles)
+}
+
+fn parse_client_orders(
+    orders_path: &Path,
+) -> Result<HashMap<String, Vec<ClientOrder>>, Box<dyn Error>> {
+    let mut orders: HashMap<_, Vec<_>> = HashMap::new();
+    traverse_data_dir(orders_path, &mut |p| {
+        println!("\tParsing client orders {}...", p.display());
+        let list = ClientOrderList::load_file(p)?;
+        orders.entry(list.npc).or_default().extend(list.orders);
+        Ok(())
+    })?;
+    Ok(orders)
+}
+
+fn parse_mission_passes(passes_path: &Path) -> Result<Vec<MissionPassSeason>, Box<dyn Error>> {
+    let mut seasons = vec![];
+    traverse_data_dir(passes_path, &mut |p| {
+        println!("\tParsing mission pass {}...", p.display());
+        seasons.push(MissionPassSeason::load_file(p)?);
+        Ok(())
+    })?;
+    Ok(seasons)
+}
+
+fn parse_arks_missions(missions_path: &Path) -> Result<Vec<ArksMission>, Box<dyn Error>> {
+    let mut missions = vec![];
+    traverse_data_dir(missions_path, &mut |p| {
+        println!("\tParsing ARKS missions {}...", p.display());
+        missions.append(&mut Vec::load_file(p)?);
+        Ok(())
+    })?;
+    Ok(missions)
+}
+
+fn parse_shops(shops_path: &Path) -> Result<Vec<ShopData>, Box<dyn Error>> {
+    let mut shops = vec![];
+    traverse_data_dir(shops_path, &mut |p| {
+        println!("\tParsing shop {}...", p.display());
+        shops.push(ShopData::load_file(p)?);
+        Ok(())
+    })?;
+    Ok(shops)
+}
+
 fn find_data_dir<P, F>(
     path: P,
     callback: F,
@@ -426,39 +675,31 @@ fn create_attr_files(path: &Path, srv_data: &mut ServerData) -> Result<(), Box<d
     let attrs = item_attrs::ItemAttributes::load_file(path)?;
 
     // PC attributes
-    let outdata_pc = Cursor::new(vec![]);
     let attrs: item_attrs::ItemAttributesPC = attrs.into();
     srv_data.item_params.attrs = attrs.clone();
-    let mut attrs_data_pc = Cursor::new(vec![]);
-    attrs.write_attrs(&mut attrs_data_pc)?;
-    attrs_data_pc.set_position(0);
-    let mut ice_writer = IceWriter::new(outdata_pc)?;
-    ice_writer.load_group(ice::Group::Group2);
-    ice_writer.new_file(IceFileInfo {
-        filename: "item_parameter.bin".into(),
-        file_extension: "bin".into(),
-        ..Default::default()
-    })?;
-    std::io::copy(&mut attrs_data_pc, &mut ice_writer)?;
-    srv_data.item_params.pc_attrs = ice_writer.into_inner()?.into_inner();
+    let mut attrs_data_pc = vec![];
+    attrs.write_attrs(&mut Cursor::new(&mut attrs_data_pc))?;
+    srv_data.item_params.pc_attrs = build_attr_ice(&attrs_data_pc)?;
 
     // Vita attributes
-    let outdata_vita = Cursor::new(vec![]);
     let attrs: item_attrs::ItemAttributesVita = attrs.into();
-    let mut attrs_data_vita = Cursor::new(vec![]);
-    attrs.write_attrs(&mut attrs_data_vita)?;
-    attrs_data_vita.set_position(0);
-    let mut ice_writer = IceWriter::new(outdata_vita)?;
+    let mut attrs_data_vita = vec![];
+    attrs.write_attrs(&mut Cursor::new(&mut attrs_data_vita))?;
+    srv_data.item_params.vita_attrs = build_attr_ice(&attrs_data_vita)?;
+
+    Ok(())
+}
+
+/// Packs serialized item attributes into the ICE archive sent to clients.
+fn build_attr_ice(attrs_data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut ice_writer = IceWriter::new(Cursor::new(vec![]))?;
     ice_writer.load_group(ice::Group::Group2);
     ice_writer.new_file(IceFileInfo {
         filename: "item_parameter.bin".into(),
         file_extension: "bin".into(),
-        ..Default::default()
+        data: attrs_data.to_vec(),
     })?;
-    std::io::copy(&mut attrs_data_vita, &mut ice_writer)?;
-    srv_data.item_params.vita_attrs = ice_writer.into_inner()?.into_inner();
-
-    Ok(())
+    Ok(ice_writer.into_inner()?.into_inner())
 }
 
 fn select_ext<P: AsRef<Path>>(path: P) -> PathBuf {
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// Special ability (affix) transfer settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AffixData {
+    /// Maximum number of abilities on an item.
+    pub max_slots: usize,
+    /// Meseta cost per source item.
+    pub fee_per_source: u64,
+    /// Maximum number of abilities rolled on a newly obtained weapon or unit.
+    pub max_rolled: usize,
+    pub abilities: Vec<Ability>,
+    /// Success rates of abilities transferred together.
+    pub combinations: Vec<AffixCombination>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ability {
+    pub id: u16,
+    pub name: String,
+    /// Success rate (in percent) if one of the items has the ability.
+    pub rate: u8,
+    /// Chance (in percent) that a newly obtained weapon or unit has the ability.
+    pub roll_rate: u8,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AffixCombination {
+    /// Abilities that must all be chosen for the rate to apply.
+    pub abilities: Vec<u16>,
+    /// Success rate (in percent) of each ability of the combination.
+    pub rate: u8,
+}
+
+impl AffixData {
+    pub fn ability(&self, id: u16) -> Option<&Ability> {
+        self.abilities.iter().find(|a| a.id == id)
+    }
+    /// Returns the success rate (in percent) of the ability if `count` items have it and
+    /// `chosen` abilities are transferred.
+    pub fn success_rate(&self, id: u16, count: usize, chosen: &[u16]) -> u8 {
+        if count == 0 {
+            return 0;
+        }
+        let combination = self
+            .combinations
+            .iter()
+            .filter(|c| c.abilities.contains(&id) && c.abilities.iter().all(|a| chosen.contains(a)))
+            .map(|c| c.rate)
+            .max();
+        match (combination, self.ability(id)) {
+            (Some(rate), _) => rate.min(100),
+            (None, Some(ability)) => (ability.rate as usize * count).min(100) as u8,
+            (None, None) => 0,
+        }
+    }
+    /// Rolls abilities of a newly obtained weapon or unit. `chance` returns `true` with the
+    /// given probability (in percent).
+    pub fn roll(&self, mut chance: impl FnMut(u8) -> bool) -> Vec<u16> {
+        self.abilities
+            .iter()
+            .filter(|a| a.roll_rate != 0 && chance(a.roll_rate.min(100)))
+            .map(|a| a.id)
+            .take(self.max_rolled.min(self.max_slots))
+            .collect()
+    }
+}
+
+impl Default for AffixData {
+    fn default() -> Self {
+        Self {
+            max_slots: 5,
+            fee_per_source: 0,
+            max_rolled: 3,
+            abilities: vec![],
+            combinations: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ability, AffixCombination, AffixData};
+
+    #[test]
+    fn test_success_rate() {
+        let data = AffixData {
+            abilities: vec![
+                Ability {
+                    id: 1,
+                    rate: 40,
+                    ..Default::default()
+                },
+                Ability {
+                    id: 2,
+                    rate: 60,
+                    ..Default::default()
+                },
+            ],
+            combinations: vec![AffixCombination {
+                abilities: vec![1, 2],
+                rate: 20,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(data.success_rate(1, 1, &[1]), 40);
+        assert_eq!(data.success_rate(1, 2, &[1]), 80);
+        assert_eq!(data.success_rate(2, 2, &[2]), 100);
+        assert_eq!(data.success_rate(1, 2, &[1, 2]), 20);
+        assert_eq!(data.success_rate(1, 0, &[1]), 0);
+        assert_eq!(data.success_rate(3, 1, &[3]), 0);
+    }
+
+    #[test]
+    fn test_roll() {
+        let ability = |id, roll_rate| Ability {
+            id,
+            roll_rate,
+            ..Default::default()
+        };
+        let data = AffixData {
+            max_rolled: 2,
+            abilities: vec![
+                ability(1, 50),
+                ability(2, 0),
+                ability(3, 10),
+                ability(4, 90),
+            ],
+            ..Default::default()
+        };
+        // abilities with zero rate are never rolled
+        assert_eq!(data.roll(|_| true), [1, 3]);
+        assert_eq!(data.roll(|rate| rate >= 50), [1, 4]);
+        assert!(data.roll(|_| false).is_empty());
+    }
+}
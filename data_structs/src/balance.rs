@@ -0,0 +1,107 @@
+//! Shared logic of the ship list and block balance listeners.
+use crate::Error;
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+use pso2packetlib::{
+    protocol::{login, Packet, PacketType},
+    Connection, PrivateKey, PublicKey,
+};
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::net::TcpStream;
+
+enum AddrType {
+    Loopback,
+    Local,
+    Global,
+}
+
+/// Remote and local addresses of the client connection.
+pub fn connection_addrs(stream: &TcpStream) -> Result<(Ipv4Addr, Ipv4Addr), Error> {
+    let (IpAddr::V4(remote_ip), IpAddr::V4(local_ip)) =
+        (stream.peer_addr()?.ip(), stream.local_addr()?.ip())
+    else {
+        return Err(Error::InvalidInput);
+    };
+    Ok((remote_ip, local_ip))
+}
+
+fn get_addr_type(chk_addr: Ipv4Addr) -> Result<AddrType, Error> {
+    if chk_addr.is_loopback() {
+        return Ok(AddrType::Loopback);
+    }
+    let interfaces = NetworkInterface::show()?;
+    for addr in interfaces.into_iter().flat_map(|i| i.addr.into_iter()) {
+        let IpAddr::V4(local_addr) = addr.ip() else {
+            continue;
+        };
+        let Some(IpAddr::V4(mask)) = addr.netmask() else {
+            continue;
+        };
+        let local_addr = local_addr.octets();
+        let mask = mask.octets();
+        let chk_addr = chk_addr.octets();
+        let mut masked_local_addr = [0; 4];
+        let mut masked_chk_addr = [0; 4];
+        masked_local_addr
+            .iter_mut()
+            .zip(local_addr.iter().zip(mask.iter()).map(|(a, b)| a & b))
+            .for_each(|(a, b)| *a = b);
+        masked_chk_addr
+            .iter_mut()
+            .zip(chk_addr.iter().zip(mask.iter()).map(|(a, b)| a & b))
+            .for_each(|(a, b)| *a = b);
+        if masked_chk_addr == masked_local_addr {
+            return Ok(AddrType::Local);
+        }
+    }
+    Ok(AddrType::Global)
+}
+
+/// Returns the address of the server (ship or block) that is reachable by the client.
+pub fn select_addr(
+    remote_ip: Ipv4Addr,
+    local_ip: Ipv4Addr,
+    server_ip: Ipv4Addr,
+) -> Result<Ipv4Addr, Error> {
+    // server is listening on all interfaces of this pc
+    if server_ip.is_unspecified() {
+        return Ok(local_ip);
+    }
+    let remote_addr_type = get_addr_type(remote_ip)?;
+    let server_addr_type = get_addr_type(server_ip)?;
+    let send_ip = match (remote_addr_type, server_addr_type) {
+        // if the server is connected via global ip then it is reachable by anyone
+        (_, AddrType::Global) => server_ip,
+        // if the client is connected via loopback then it is the same pc, thus any server ip works
+        (AddrType::Loopback, _) => server_ip,
+        // server is on the same pc, so return connected to address
+        (AddrType::Local, AddrType::Loopback) => local_ip,
+        // assume that the server is in the same network as the client
+        (AddrType::Local, AddrType::Local) => server_ip,
+        // if the server is local or on the same pc
+        (AddrType::Global, _) => local_ip,
+    };
+
+    Ok(send_ip)
+}
+
+pub fn block_balance_packet(ip: Ipv4Addr, port: u16, name: String) -> Packet {
+    Packet::BlockBalance(login::BlockBalancePacket {
+        ip,
+        port,
+        blockname: name.into(),
+        ..Default::default()
+    })
+}
+
+/// Sends a single packet to the client of the query or balance listener.
+pub async fn send_once(stream: TcpStream, packet: &Packet) -> Result<(), Error> {
+    stream.set_nodelay(true)?;
+    let mut con = Connection::<Packet>::new_async(
+        stream,
+        PacketType::Classic,
+        PrivateKey::None,
+        PublicKey::None,
+    );
+    con.write_packet_async(packet).await?;
+    Ok(())
+}
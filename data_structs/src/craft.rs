@@ -0,0 +1,25 @@
+use pso2packetlib::protocol::items::ItemId;
+use serde::{Deserialize, Serialize};
+
+/// Crafting recipe.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Recipe {
+    pub id: u32,
+    pub name: String,
+    /// Items consumed from the inventory and storages.
+    pub materials: Vec<Material>,
+    /// Meseta cost of the craft.
+    pub meseta: u64,
+    pub result: ItemId,
+    pub amount: u16,
+    /// Time in seconds the craft takes.
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Material {
+    pub id: ItemId,
+    pub amount: u16,
+}
@@ -0,0 +1,71 @@
+//! Startup self-test checks shared by the ship and the master ship (`--doctor`).
+use crate::{Error, SerDeFile, ServerData};
+use sha2::Digest;
+use std::{fmt::Display, path::Path, time::Duration};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// Results of the self-test.
+#[derive(Default)]
+pub struct Report {
+    checks: Vec<(String, Result<String, String>)>,
+}
+
+impl Report {
+    /// Records the result of the check. `Ok` contains the details of the successful check.
+    pub fn add(&mut self, name: impl Into<String>, result: Result<String, impl Display>) {
+        self.checks
+            .push((name.into(), result.map_err(|e| e.to_string())));
+    }
+    /// Returns the number of failed checks.
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|(_, r)| r.is_err()).count()
+    }
+    /// Prints the report to the standard output.
+    pub fn print(&self) {
+        for (name, result) in &self.checks {
+            match result {
+                Ok(details) if details.is_empty() => println!("[ OK ] {name}"),
+                Ok(details) => println!("[ OK ] {name}: {details}"),
+                Err(e) => println!("[FAIL] {name}: {e}"),
+            }
+        }
+        println!("{} checks, {} failed", self.checks.len(), self.failures());
+    }
+}
+
+/// Checks that the address is free by binding to it.
+pub async fn check_port(addr: impl ToSocketAddrs) -> Result<String, Error> {
+    let listener = TcpListener::bind(addr).await?;
+    Ok(format!("{} is free", listener.local_addr()?))
+}
+
+/// Checks that the address accepts connections.
+pub async fn check_reachable(addr: &str) -> Result<String, Error> {
+    let stream = tokio::time::timeout(Duration::from_secs(5), TcpStream::connect(addr))
+        .await
+        .map_err(|_| Error::Timeout)??;
+    Ok(format!("connected to {}", stream.peer_addr()?))
+}
+
+/// Checks that the server data file can be loaded. If `<path>.sha256` exists then the file hash
+/// must match it. Returns the hash of the file.
+pub async fn check_data_file(path: &str) -> Result<String, Error> {
+    let data = tokio::fs::read(path).await?;
+    let hash: String = sha2::Sha256::digest(&data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let hash_path = format!("{path}.sha256");
+    if Path::new(&hash_path).exists() {
+        let expected = tokio::fs::read_to_string(&hash_path).await?;
+        let expected = expected.split_whitespace().next().unwrap_or_default();
+        if !expected.eq_ignore_ascii_case(&hash) {
+            return Err(Error::HashMismatch(hash));
+        }
+    }
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || ServerData::load_from_mp_comp(path))
+        .await
+        .map_err(|e| Error::IOError(e.into()))??;
+    Ok(format!("sha256 {hash}"))
+}
@@ -1,9 +1,11 @@
 use pso2packetlib::protocol::{
-    items::{Item, ItemId, StorageInfo},
+    items::{Item, ItemId, ItemType, StorageInfo},
     models::{character::Class, item_attrs::ItemAttributesPC},
-    palette::{SubPalette, WeaponPalette},
+    palette::{PalettePA, SubPalette, WeaponPalette},
+    spawn::ObjectSpawnPacket,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
@@ -21,6 +23,64 @@ pub struct ItemParameters {
     pub vita_attrs: Vec<u8>,
     pub attrs: ItemAttributesPC,
     pub names: Vec<ItemName>,
+    pub lobby_items: Vec<LobbyItem>,
+    pub prices: Vec<ItemPrice>,
+    pub storage_expansions: Vec<StorageExpansion>,
+    pub disks: Vec<DiskItem>,
+}
+
+/// Price of one item when sold to an NPC.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ItemPrice {
+    pub id: ItemId,
+    pub price: u64,
+}
+
+/// Item that increases the capacity of the character storage when used.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageExpansion {
+    pub id: ItemId,
+    /// Added number of slots.
+    pub space: u32,
+    /// Capacity above which the item can't be used.
+    pub max_space: u32,
+}
+
+/// Disk item that teaches a photon art or technique.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiskItem {
+    pub id: ItemId,
+    /// Weapon category the art is learned for (0 for techniques and subpalette skills).
+    pub weapon: u16,
+    /// Learned art and its level.
+    pub pa: PalettePA,
+}
+
+impl ItemParameters {
+    /// Returns the NPC price of one item. Items without a price can't be sold.
+    pub fn sell_price(&self, id: ItemId) -> Option<u64> {
+        self.prices.iter().find(|p| p.id == id).map(|p| p.price)
+    }
+}
+
+/// Item usable in the lobby that spawns a short-lived object next to the player.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LobbyItem {
+    pub id: ItemId,
+    /// Spawned object. Its id and position are set when the item is used.
+    pub object: ObjectSpawnPacket,
+    /// Time in seconds before the object is despawned.
+    pub lifetime: u64,
+    /// Time in seconds before the player can use another lobby item.
+    pub cooldown: u64,
+    /// Maximum distance to the players that see the object (0 - whole zone).
+    pub range: f32,
+    /// Item is removed from the inventory when used.
+    pub consumable: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +90,8 @@ pub struct AccountStorages {
     pub default: StorageInventory,
     pub premium: StorageInventory,
     pub extend1: StorageInventory,
+    /// Special abilities of stored items by item uuid.
+    pub augments: BTreeMap<u64, Vec<u16>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,8 +134,172 @@ pub struct DefaultItem {
     pub unit_equiped_id: u8,
 }
 
+impl AccountStorages {
+    /// Applies local changes (difference between `self` and `base`) to the `remote` storages.
+    /// Returns `None` if an item was changed both locally and remotely and the changes can't be
+    /// combined.
+    pub fn merge(&self, base: &Self, mut remote: Self) -> Option<Self> {
+        let meseta = remote.storage_meseta as i128 + self.storage_meseta as i128
+            - base.storage_meseta as i128;
+        remote.storage_meseta = meseta.clamp(0, u64::MAX as i128) as u64;
+        remote.default.merge(&self.default, &base.default)?;
+        remote.premium.merge(&self.premium, &base.premium)?;
+        remote.extend1.merge(&self.extend1, &base.extend1)?;
+        // abilities never change while stored, so only additions and removals are merged
+        for (uuid, augments) in &self.augments {
+            if !base.augments.contains_key(uuid) {
+                remote.augments.insert(*uuid, augments.clone());
+            }
+        }
+        for uuid in base.augments.keys() {
+            if !self.augments.contains_key(uuid) {
+                remote.augments.remove(uuid);
+            }
+        }
+        Some(remote)
+    }
+    /// Returns the storage bank by its id (0 - default, 1 - premium, 2 - extended).
+    pub fn bank(&self, id: u8) -> Option<&StorageInventory> {
+        match id {
+            0 => Some(&self.default),
+            1 => Some(&self.premium),
+            2 => Some(&self.extend1),
+            _ => None,
+        }
+    }
+    pub fn bank_mut(&mut self, id: u8) -> Option<&mut StorageInventory> {
+        match id {
+            0 => Some(&mut self.default),
+            1 => Some(&mut self.premium),
+            2 => Some(&mut self.extend1),
+            _ => None,
+        }
+    }
+    /// Gives every stored item a new UUID starting from `last_uuid`.
+    pub fn renumber(&mut self, last_uuid: &mut u64) {
+        let mut augments = BTreeMap::new();
+        for bank in [&mut self.default, &mut self.premium, &mut self.extend1] {
+            for item in bank.items.iter_mut() {
+                if let Some(abilities) = self.augments.remove(&item.uuid) {
+                    augments.insert(*last_uuid, abilities);
+                }
+                item.uuid = *last_uuid;
+                *last_uuid += 1;
+            }
+        }
+        self.augments = augments;
+    }
+    /// Moves items and meseta of `other` into these storages. Consumables are added to existing
+    /// stacks, other items get new UUIDs and are put into the same bank or any other bank with
+    /// free space. Returns the number of items that didn't fit, in which case `self` is left
+    /// partially changed.
+    pub fn absorb(&mut self, other: Self, last_uuid: &mut u64) -> Result<(), usize> {
+        self.storage_meseta = self.storage_meseta.saturating_add(other.storage_meseta);
+        let mut leftover = 0;
+        for (id, bank) in [(0, other.default), (1, other.premium), (2, other.extend1)] {
+            for mut item in bank.items {
+                if let ItemType::Consumable(data) = &item.data {
+                    if self.stack(&item, data.amount) {
+                        continue;
+                    }
+                }
+                if let Some(abilities) = other.augments.get(&item.uuid) {
+                    self.augments.insert(*last_uuid, abilities.clone());
+                }
+                item.uuid = *last_uuid;
+                *last_uuid += 1;
+                let target = [id, 0, 1, 2].into_iter().find(|&b| {
+                    self.bank(b)
+                        .is_some_and(|s| s.is_enabled && s.items.len() < s.total_space as usize)
+                });
+                match target.and_then(|b| self.bank_mut(b)) {
+                    Some(storage) => storage.items.push(item),
+                    None => leftover += 1,
+                }
+            }
+        }
+        if leftover != 0 {
+            return Err(leftover);
+        }
+        Ok(())
+    }
+    /// Adds the amount to an existing stack of the consumable. Returns `false` if there is no
+    /// stack that can hold it.
+    fn stack(&mut self, item: &Item, amount: u16) -> bool {
+        for bank in [&mut self.default, &mut self.premium, &mut self.extend1] {
+            for stored in bank.items.iter_mut().filter(|i| i.id == item.id) {
+                let ItemType::Consumable(data) = &mut stored.data else {
+                    continue;
+                };
+                if let Some(sum) = data.amount.checked_add(amount) {
+                    data.amount = sum;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    /// Copies capacities of the banks from `other`.
+    pub fn copy_capacities(&mut self, other: &Self) {
+        for id in 0..3 {
+            let (Some(bank), Some(other)) = (self.bank_mut(id), other.bank(id)) else {
+                continue;
+            };
+            bank.set_capacity(other.total_space, other.is_enabled);
+        }
+    }
+}
+
 impl StorageInventory {
-    pub fn generate_info(&self) -> StorageInfo {
+    /// Applies local changes to this (remote) storage. Items are matched by their UUIDs. Stack
+    /// changes made on both sides are added up, other items changed on both sides are a conflict.
+    fn merge(&mut self, local: &Self, base: &Self) -> Option<()> {
+        let contains = |items: &[Item], uuid| items.iter().any(|i| i.uuid == uuid);
+        // drop items that were removed locally
+        self.items
+            .retain(|i| !contains(&base.items, i.uuid) || contains(&local.items, i.uuid));
+        for item in &local.items {
+            let base_item = base.items.iter().find(|i| i.uuid == item.uuid);
+            match (
+                self.items.iter_mut().find(|i| i.uuid == item.uuid),
+                base_item,
+            ) {
+                // item wasn't changed locally
+                (Some(_), Some(base_item)) if same_item(item, base_item) => {}
+                // item wasn't changed remotely
+                (Some(remote_item), Some(base_item)) if same_item(remote_item, base_item) => {
+                    *remote_item = item.clone()
+                }
+                (Some(remote_item), Some(base_item)) => merge_stack(remote_item, item, base_item)?,
+                // same item was added on both sides
+                (Some(remote_item), None) if same_item(remote_item, item) => {}
+                (Some(_), None) => return None,
+                // item was added locally
+                (None, None) => self.items.push(item.clone()),
+                // item was removed remotely
+                (None, Some(_)) => {}
+            }
+        }
+        Some(())
+    }
+    /// Sets the capacity of the storage. Disabled storages keep their items.
+    pub fn set_capacity(&mut self, total_space: u32, is_enabled: bool) {
+        self.total_space = total_space;
+        self.is_enabled = is_enabled;
+        self.is_purchased = is_enabled;
+    }
+    /// Returns `true` if the storage can hold the items (matched by item id) in addition to the
+    /// stored ones.
+    pub fn has_space_for<'a>(&self, items: impl IntoIterator<Item = &'a Item>) -> bool {
+        let mut new_ids = vec![];
+        for item in items {
+            if !self.items.iter().any(|i| i.id == item.id) && !new_ids.contains(&item.id) {
+                new_ids.push(item.id);
+            }
+        }
+        self.is_enabled && self.items.len() + new_ids.len() <= self.total_space as usize
+    }
+    pub const fn generate_info(&self) -> StorageInfo {
         StorageInfo {
             total_space: self.total_space,
             used_space: self.items.len() as u32,
@@ -85,6 +311,27 @@ impl StorageInventory {
     }
 }
 
+/// Checks if the items are identical.
+fn same_item(a: &Item, b: &Item) -> bool {
+    // items don't implement `PartialEq`, but their debug output contains all fields
+    a.id == b.id && format!("{:?}", a.data) == format!("{:?}", b.data)
+}
+
+/// Applies the local change of the stack size to the remote stack.
+fn merge_stack(remote: &mut Item, local: &Item, base: &Item) -> Option<()> {
+    if remote.id != local.id || local.id != base.id {
+        return None;
+    }
+    let (ItemType::Consumable(remote), ItemType::Consumable(local), ItemType::Consumable(base)) =
+        (&mut remote.data, &local.data, &base.data)
+    else {
+        return None;
+    };
+    let amount = i32::from(remote.amount) + i32::from(local.amount) - i32::from(base.amount);
+    remote.amount = u16::try_from(amount).ok()?;
+    Some(())
+}
+
 impl Default for AccountStorages {
     fn default() -> Self {
         Self {
@@ -113,6 +360,7 @@ impl Default for AccountStorages {
                 storage_type: 2,
                 items: vec![],
             },
+            augments: BTreeMap::new(),
         }
     }
 }
@@ -128,3 +376,131 @@ impl Default for StorageInventory {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountStorages, StorageInventory};
+    use pso2packetlib::protocol::items::{ConsumableItem, Item, ItemId, ItemType};
+
+    fn storages(meseta: u64, uuids: &[u64]) -> AccountStorages {
+        AccountStorages {
+            storage_meseta: meseta,
+            default: StorageInventory {
+                items: uuids
+                    .iter()
+                    .map(|&uuid| Item {
+                        uuid,
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_storage_merge() {
+        let base = storages(100, &[1, 2]);
+        let local = storages(150, &[2, 3]);
+        let remote = storages(80, &[1, 4]);
+
+        let merged = local.merge(&base, remote).unwrap();
+        let uuids: Vec<_> = merged.default.items.iter().map(|i| i.uuid).collect();
+        assert_eq!(merged.storage_meseta, 130);
+        assert_eq!(uuids, [4, 3]);
+    }
+
+    #[test]
+    fn test_storage_merge_conflict() {
+        let stack = |amount| Item {
+            uuid: 1,
+            data: ItemType::Consumable(ConsumableItem {
+                amount,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let with_item = |item| {
+            let mut storages = storages(0, &[]);
+            storages.default.items.push(item);
+            storages
+        };
+        // stack changes are added up
+        let base = with_item(stack(10));
+        let merged = with_item(stack(7))
+            .merge(&base, with_item(stack(12)))
+            .unwrap();
+        let ItemType::Consumable(data) = &merged.default.items[0].data else {
+            unreachable!()
+        };
+        assert_eq!(data.amount, 9);
+        // the same stack can't be replaced by different items on both sides
+        let mut local = with_item(stack(10));
+        local.default.items[0].id.id = 2;
+        let mut remote = with_item(stack(10));
+        remote.default.items[0].id.id = 3;
+        assert!(local.merge(&base, remote).is_none());
+    }
+
+    #[test]
+    fn test_storage_space() {
+        let mut storage = storages(0, &[1, 2]).default;
+        let new_item = Item {
+            id: ItemId {
+                item_type: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // stored items share the default id
+        let stored = storage.items[0].clone();
+        storage.set_capacity(2, true);
+        assert!(storage.has_space_for([&stored]));
+        assert!(!storage.has_space_for([&new_item]));
+        storage.set_capacity(3, true);
+        assert!(storage.has_space_for([&new_item]));
+        storage.set_capacity(3, false);
+        assert!(!storage.has_space_for([&new_item]));
+    }
+
+    #[test]
+    fn test_storage_absorb() {
+        let mut target = storages(100, &[1, 2]);
+        target.default.set_capacity(3, true);
+        // items without stacks in the target
+        let mut source = storages(50, &[1, 2]);
+        for item in source.default.items.iter_mut() {
+            item.id.id = 5 + item.uuid as u16;
+        }
+        let mut uuid = 10;
+        target
+            .clone()
+            .absorb(source.clone(), &mut uuid)
+            .unwrap_err();
+
+        target.premium.set_capacity(10, true);
+        uuid = 10;
+        target.absorb(source, &mut uuid).unwrap();
+        let uuids: Vec<_> = target.default.items.iter().map(|i| i.uuid).collect();
+        assert_eq!(uuids, [1, 2, 10]);
+        assert_eq!(target.premium.items[0].uuid, 11);
+        assert_eq!(target.storage_meseta, 150);
+        assert_eq!(uuid, 12);
+    }
+
+    #[test]
+    fn test_storage_renumber() {
+        let mut storage = storages(0, &[1, 500]);
+        storage.premium.items.push(Item {
+            uuid: 3,
+            ..Default::default()
+        });
+        let mut uuid = 10;
+        storage.renumber(&mut uuid);
+        let uuids: Vec<_> = storage.default.items.iter().map(|i| i.uuid).collect();
+        assert_eq!(uuids, [10, 11]);
+        assert_eq!(storage.premium.items[0].uuid, 12);
+        assert_eq!(uuid, 13);
+    }
+}
@@ -1,13 +1,26 @@
 #![deny(unsafe_code)]
 #![warn(clippy::missing_const_for_fn)]
 
+pub mod affix;
+#[cfg(feature = "balance")]
+pub mod balance;
+pub mod craft;
+#[cfg(feature = "ship")]
+pub mod doctor;
 pub mod flags;
 pub mod inventory;
 pub mod map;
 #[cfg(feature = "ship")]
 pub mod master_ship;
+pub mod mission;
+pub mod missionpass;
+pub mod order;
 pub mod quest;
+pub mod reward;
+pub mod secrets;
+pub mod shop;
 pub mod stats;
+pub mod title;
 
 use inventory::DefaultClassesData;
 use serde::{de::DeserializeOwned, Serialize};
@@ -24,6 +37,10 @@ pub enum Error {
     Timeout,
     #[error("No ship discovery response")]
     NoDiscoverResponse,
+    #[error("Unknown zone: {0}")]
+    UnknownZone(String),
+    #[error("Secret file {0} is accessible by other users, expected mode {1:o}")]
+    InsecurePermissions(String, u32),
 
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
@@ -60,6 +77,15 @@ pub enum Error {
     #[cfg(feature = "ship")]
     #[error("AEAD error: {0}")]
     AEADError(String),
+    #[cfg(feature = "ship")]
+    #[error("File hash {0} doesn't match the expected one")]
+    HashMismatch(String),
+    #[cfg(feature = "balance")]
+    #[error("Network interfaces error: {0}")]
+    NetworkInterfacesError(#[from] network_interface::Error),
+    #[cfg(feature = "balance")]
+    #[error("Client connection error: {0}")]
+    ConnError(#[from] pso2packetlib::connection::ConnectionError),
 }
 
 pub trait SerDeFile: Serialize + DeserializeOwned {
@@ -157,6 +183,15 @@ pub struct ServerData {
     pub enemy_stats: stats::AllEnemyStats,
     pub attack_stats: Vec<stats::AttackStats>,
     pub default_classes: DefaultClassesData,
+    pub combat: stats::CombatFormula,
+    pub shops: Vec<shop::ShopData>,
+    pub affixes: affix::AffixData,
+    pub recipes: Vec<craft::Recipe>,
+    pub titles: Vec<title::Title>,
+    /// Client orders by NPC name.
+    pub client_orders: HashMap<String, Vec<order::ClientOrder>>,
+    pub mission_passes: Vec<missionpass::MissionPassSeason>,
+    pub arks_missions: Vec<mission::ArksMission>,
 }
 
 pub fn name_to_id(name: &str) -> u32 {
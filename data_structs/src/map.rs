@@ -19,6 +19,9 @@ pub struct MapData {
     pub luas: HashMap<String, String>,
     pub init_map: ZoneId,
     pub zones: Vec<ZoneData>,
+    /// Time after which automatically spawned enemies are despawned if their chunk has no
+    /// players. If `None` then enemies are never despawned.
+    pub enemy_despawn_time: Option<Duration>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -28,6 +31,28 @@ pub struct ObjectData {
     pub is_active: bool,
     pub data: ObjectSpawnPacket,
     pub lua_data: Option<String>,
+    /// Object data converted for Vita clients. If not set then the data is converted when the
+    /// map is created.
+    pub vita_data: Option<Vec<u32>>,
+}
+
+impl ObjectData {
+    /// Converts object data for Vita clients if the object has no lua script.
+    pub fn default_vita_data(&self) -> Vec<u32> {
+        self.data
+            .data
+            .as_slice()
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                if i % 2 == 0 && v > 50 && v < 80 {
+                    v - 1
+                } else {
+                    v
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -57,11 +82,15 @@ pub struct TransporterData {
     pub lua_data: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct EnemySpawn {
     pub enemy_name: String,
     pub spawn_category: u32,
+    /// Chance of this enemy being picked relative to other enemies in the category.
+    pub weight: u32,
+    /// Cost of the enemy in the encounter budget.
+    pub cost: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -74,6 +103,8 @@ pub struct ZoneData {
     pub default_location: Position,
     pub enemies: Vec<EnemySpawn>,
     pub chunks: Vec<ZoneChunk>,
+    /// If true then symbol arts are not delivered in this zone.
+    pub disable_symbol_arts: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -83,15 +114,119 @@ pub enum EnemySpawnType {
     Automatic {
         min: u32,
         max: u32,
+        #[serde(default)]
+        encounter: Encounter,
     },
     AutomaticWithRespawn {
         min: u32,
         max: u32,
         respawn_time: Duration,
+        #[serde(default)]
+        encounter: Encounter,
     },
     Manual,
 }
 
+/// Rules for picking enemies of an automatic spawn.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Encounter {
+    /// Categories to pick from. If empty then all categories of the zone have equal weights.
+    pub categories: Vec<SpawnCategoryWeight>,
+    /// Maximum total cost of enemies spawned at once (0 - unlimited).
+    pub budget: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SpawnCategoryWeight {
+    pub category: u32,
+    pub weight: u32,
+}
+
+impl Default for EnemySpawn {
+    fn default() -> Self {
+        Self {
+            enemy_name: String::new(),
+            spawn_category: 0,
+            weight: 1,
+            cost: 1,
+        }
+    }
+}
+
+impl Default for SpawnCategoryWeight {
+    fn default() -> Self {
+        Self {
+            category: 0,
+            weight: 1,
+        }
+    }
+}
+
+/// Operator provided overrides layered over compiled map data.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct MapOverride {
+    /// Zone in which players are placed on map load.
+    pub init_map: Option<ZoneId>,
+    /// Idle enemy despawn time in seconds (0 - never despawn).
+    pub enemy_despawn_time: Option<u64>,
+    /// Zone overrides by zone name.
+    pub zones: HashMap<String, ZoneOverride>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ZoneOverride {
+    /// Location where players spawn in the zone.
+    pub default_location: Option<Position>,
+    /// Zone settings sent to the client on transfer (map id, area, etc.).
+    pub settings: Option<ZoneSettings>,
+    /// Allow sending symbol arts in the zone.
+    pub symbol_arts: Option<bool>,
+    /// Allow sitting on chairs in the zone.
+    pub chairs: Option<bool>,
+}
+
+/// Name of the lua script that handles sitting on chairs.
+pub const CHAIR_LUA: &str = "oa_sit_point";
+
+impl MapData {
+    /// Applies operator overrides to the map.
+    pub fn apply_override(&mut self, map_override: &MapOverride) -> Result<(), crate::Error> {
+        if let Some(init_map) = map_override.init_map {
+            if !self.zones.iter().any(|z| z.zone_id == init_map) {
+                return Err(crate::Error::UnknownZone(init_map.to_string()));
+            }
+            self.init_map = init_map;
+        }
+        if let Some(time) = map_override.enemy_despawn_time {
+            self.enemy_despawn_time = (time != 0).then(|| Duration::from_secs(time));
+        }
+        for (name, zone_override) in &map_override.zones {
+            let Some(zone) = self.zones.iter_mut().find(|z| &z.name == name) else {
+                return Err(crate::Error::UnknownZone(name.clone()));
+            };
+            if let Some(location) = zone_override.default_location {
+                zone.default_location = location;
+            }
+            if let Some(settings) = &zone_override.settings {
+                zone.settings = settings.clone();
+            }
+            if let Some(symbol_arts) = zone_override.symbol_arts {
+                zone.disable_symbol_arts = !symbol_arts;
+            }
+            if zone_override.chairs == Some(false) {
+                let zone_id = zone.zone_id;
+                self.objects
+                    .retain(|o| o.zone_id != zone_id || o.data.name.as_str() != CHAIR_LUA);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(default)]
 pub struct ZoneChunk {
@@ -100,3 +235,57 @@ pub struct ZoneChunk {
     pub enemy_spawn_type: EnemySpawnType,
     pub enemy_spawn_points: Vec<Position>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{MapData, MapOverride, ObjectData, ZoneData, ZoneOverride};
+
+    #[test]
+    fn test_apply_override() {
+        let mut map = MapData {
+            init_map: 1,
+            zones: vec![
+                ZoneData {
+                    name: "lobby".into(),
+                    zone_id: 1,
+                    ..Default::default()
+                },
+                ZoneData {
+                    name: "casino".into(),
+                    zone_id: 2,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let mut map_override = MapOverride {
+            init_map: Some(2),
+            ..Default::default()
+        };
+        map_override.zones.insert(
+            "casino".into(),
+            ZoneOverride {
+                symbol_arts: Some(false),
+                ..Default::default()
+            },
+        );
+        map.apply_override(&map_override).unwrap();
+        assert_eq!(map.init_map, 2);
+        assert!(!map.zones[0].disable_symbol_arts);
+        assert!(map.zones[1].disable_symbol_arts);
+
+        map_override
+            .zones
+            .insert("unknown".into(), Default::default());
+        assert!(map.apply_override(&map_override).is_err());
+    }
+
+    #[test]
+    fn test_default_vita_data() {
+        let mut object = ObjectData::default();
+        object.data.data = vec![51, 60, 80, 79, 50, 0].into();
+        assert_eq!(object.default_vita_data(), [50, 60, 80, 79, 50, 0]);
+        object.data.data = vec![79, 79, 60].into();
+        assert_eq!(object.default_vita_data(), [78, 79, 59]);
+    }
+}
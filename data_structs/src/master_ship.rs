@@ -12,7 +12,10 @@ use p256::{
     PublicKey,
 };
 use pso2packetlib::{
-    protocol::login::{LoginAttempt, ShipStatus, UserInfoPacket},
+    protocol::{
+        login::{LoginAttempt, LoginResult, ShipStatus, UserInfoPacket},
+        PacketType,
+    },
     AsciiString,
 };
 use rand_core::{OsRng, RngCore};
@@ -32,7 +35,7 @@ pub struct MasterShipComm {
     pub action: MasterShipAction,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, strum::IntoStaticStr)]
 pub enum MasterShipAction {
     /// (S->MS) Ship wants to login.
     ShipLogin(ShipLogin),
@@ -75,19 +78,223 @@ pub enum MasterShipAction {
         player_id: u32,
     },
     GetStorage(u32),
-    GetStorageResult(AccountStorages),
+    GetStorageResult {
+        storage: AccountStorages,
+        version: u64,
+    },
     PutStorage {
         id: u32,
         storage: AccountStorages,
+        /// Version of the storage that the changes are based on.
+        version: u64,
     },
-    GetLogins(u32),
-    GetLoginsResult(Vec<LoginAttempt>),
+    PutStorageResult(PutStorageResult),
+    /// Get the latest login attempts of the account, newest first.
+    GetLogins {
+        id: u32,
+        limit: u32,
+    },
+    GetLoginsResult(Vec<LoginEntry>),
     GetSettings(u32),
     GetSettingsResult(AsciiString),
     PutSettings {
         id: u32,
         settings: AsciiString,
     },
+    /// Set an unverified email of the user. The verification code is sent to it.
+    SetEmail {
+        id: u32,
+        email: String,
+    },
+    VerifyEmail {
+        id: u32,
+        code: String,
+    },
+    /// Result of the email verification. Parameter is true if the code was correct.
+    VerifyEmailResult(bool),
+    /// Send a password reset code to the verified email of the SEGA ID user.
+    RequestPasswordReset {
+        username: String,
+        /// Address of the requester. Requests are throttled per account and per address.
+        ip: Ipv4Addr,
+    },
+    /// Set a new password using the single-use reset code. Response is [`MasterShipAction::UserLoginResult`].
+    ConfirmReset {
+        username: String,
+        code: String,
+        password: String,
+    },
+    /// Generate a new pending TOTP secret. Parameter is the user id.
+    NewTotpSecret(u32),
+    /// Base32 encoded TOTP secret.
+    TotpSecretResult(String),
+    /// Enable 2FA using a code generated from the pending secret.
+    EnableTotp {
+        id: u32,
+        code: String,
+    },
+    DisableTotp {
+        id: u32,
+        code: String,
+    },
+    /// Result of the 2FA change. Parameter is true if the code was correct.
+    TotpResult(bool),
+    /// Check the current password of the SEGA ID user before changing it. Response is
+    /// [`Self::PasswordChallengeResult`].
+    NewPasswordChallenge {
+        id: u32,
+        password: String,
+    },
+    /// Challenge for [`Self::ChangePassword`] or `None` if the password was incorrect.
+    PasswordChallengeResult(Option<String>),
+    /// Set a new password using the re-authentication challenge. Response is
+    /// [`Self::PasswordChangeResult`].
+    ChangePassword {
+        id: u32,
+        challenge: String,
+        password: String,
+    },
+    /// Result of the password change. Parameter is true if the challenge was correct.
+    PasswordChangeResult(bool),
+    /// Change the nickname of an existing account. Response is [`Self::ChangeNicknameResult`].
+    ChangeNickname {
+        id: u32,
+        nickname: String,
+    },
+    ChangeNicknameResult(ChangeNicknameResult),
+    /// Admin message for all players. Sent by the master ship with the message id 0.
+    Broadcast {
+        /// Ids of the target ships. If empty then the message is sent to all ships.
+        ships: Vec<u32>,
+        message: String,
+    },
+    /// Get a value from the account key-value store.
+    GetAccountValue {
+        id: u32,
+        namespace: String,
+        key: String,
+    },
+    /// Stored value or `None` if the key doesn't exist.
+    AccountValue(Option<Vec<u8>>),
+    /// Set a value in the account key-value store. If `value` is `None` then the key is deleted.
+    PutAccountValue {
+        id: u32,
+        namespace: String,
+        key: String,
+        value: Option<Vec<u8>>,
+    },
+    /// Get all account merges as (from, into) pairs.
+    GetAccountMerges,
+    AccountMerges(Vec<(u32, u32)>),
+    /// (MS->S) Account was merged into another one. Sent by the master ship with the message id 0.
+    AccountsMerged {
+        from: u32,
+        into: u32,
+    },
+    /// Reserve a range of item UUIDs of the account.
+    ReserveUUIDs {
+        id: u32,
+        count: u64,
+    },
+    /// First reserved UUID.
+    UUIDsReserved(u64),
+    /// Set GM permission level of the user (see [`gm_level`]).
+    SetGmLevel {
+        id: u32,
+        level: u8,
+    },
+    /// Attach a GM note to the account.
+    AddAccountNote {
+        id: u32,
+        /// Id of the GM that wrote the note.
+        author_id: u32,
+        note: String,
+    },
+    /// Get GM notes of the account, newest first. Response is [`Self::AccountNotes`].
+    GetAccountNotes(u32),
+    AccountNotes(Vec<AccountNote>),
+    /// Mute the chat of the account. GM level of [`Mute::muted_by`] must be higher than the
+    /// level of the account.
+    SetMute {
+        id: u32,
+        mute: Mute,
+    },
+    /// Remove the chat mute of the account. GM level of `gm_id` must be higher than the level of
+    /// the account. Response is [`Self::MuteRemoved`].
+    RemoveMute {
+        id: u32,
+        gm_id: u32,
+    },
+    /// Parameter is false if the account wasn't muted.
+    MuteRemoved(bool),
+    /// Get the chat mute of the account. Response is [`Self::MuteResult`].
+    GetMute(u32),
+    MuteResult(Option<Mute>),
+    /// (S->MS) Open a support ticket for the player. Response is [`Self::TicketCreated`].
+    NewTicket {
+        user_id: u32,
+        nickname: String,
+        message: String,
+    },
+    /// Id of the created ticket.
+    TicketCreated(u32),
+    /// Get tickets that aren't resolved, oldest first. Response is [`Self::Tickets`].
+    GetOpenTickets,
+    Tickets(Vec<SupportTicket>),
+    /// Assign the ticket to the GM.
+    ClaimTicket {
+        id: u32,
+        gm_id: u32,
+    },
+    ResolveTicket {
+        id: u32,
+        gm_id: u32,
+        resolution: String,
+    },
+    /// (MS->S) Ticket was opened or changed. Sent by the master ship with the message id 0.
+    TicketUpdate(SupportTicket),
+    /// (S->MS) Periodic ship occupancy update.
+    ShipStatusUpdate {
+        players: u32,
+        max_players: u32,
+        status: ShipStatus,
+        blocks: Vec<BlockStatus>,
+        /// Number of client handler failures by error code.
+        errors: Vec<(String, u64)>,
+        /// Map and party lifecycle counters by name.
+        objects: Vec<(String, u64)>,
+        /// Number of client disconnects by reason.
+        disconnects: Vec<(String, u64)>,
+        /// Bytes received from and sent to clients by packet id.
+        traffic: Vec<(String, u64, u64)>,
+        /// Clients waiting for the ship to free up.
+        queue: ShipQueue,
+    },
+    /// (S->MS) Hourly aggregate statistics of the ship.
+    ShipStatsReport(ShipStats),
+    /// Create a code for linking an external identity via the admin API. Parameter is the user
+    /// id.
+    NewLinkCode(u32),
+    LinkCodeResult(String),
+    /// (standby MS->MS) Login of a standby master ship. Response is [`Self::ShipLoginResult`].
+    ReplicationLogin(ShipLogin),
+    /// (MS->standby MS) Current state of an account.
+    ReplicatedUser(ReplicatedUser),
+    /// (MS->standby MS) Known ship PSK.
+    ReplicatedShip(ShipKey),
+    /// (MS->standby MS) Full contents of a replicated table.
+    ReplicatedTable(ReplicatedTable),
+    /// Create a new PSK for the named ship credential, revoking the previous ones. Response is
+    /// [`Self::ShipKeyResult`]. Not available to ships.
+    NewShipKey(String),
+    /// New ship PSK.
+    ShipKeyResult(String),
+    /// Revoke all PSKs of the named ship credential. Not available to ships.
+    RevokeShipKey(String),
+    /// Back up the database now. Response is [`Self::BackupResult`]. Not available to ships.
+    Backup,
+    /// Path of the created backup.
+    BackupResult(String),
     /// Delete ship from the list. Parameter is the id of the ship
     UnregisterShip(u32),
     SetFormat(SerializerFormat),
@@ -98,15 +305,159 @@ pub enum MasterShipAction {
     Error(String),
 }
 
+/// Prefix of the login password that sets a new password with a reset code, the full password
+/// is `!reset:<code>:<new password>`. Passwords can't start with it.
+pub const RESET_PREFIX: &str = "!reset:";
+
+/// GM permission levels. Each level includes permissions of the lower ones.
+pub mod gm_level {
+    /// Regular player.
+    pub const PLAYER: u8 = 0;
+    /// Can bypass chat filters and spam protection.
+    pub const MODERATOR: u8 = 1;
+    /// Can use game master commands (broadcasts, event lobbies).
+    pub const GM: u8 = 2;
+    /// Can change GM levels of other accounts.
+    pub const ADMIN: u8 = 3;
+}
+
+/// Chat mute of an account set by a GM.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Mute {
+    /// Time (since UNIX epoch) when the mute ends.
+    pub until: Duration,
+    pub reason: String,
+    /// Id of the GM that muted the player.
+    pub muted_by: u32,
+}
+
+/// GM note attached to an account.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountNote {
+    /// Id of the GM that wrote the note. 0 if it was added on the master ship or automatically
+    /// by a ship.
+    pub author_id: u32,
+    pub note: String,
+    /// Time (since UNIX epoch) when the note was added.
+    pub timestamp: Duration,
+}
+
+/// Aggregate statistics of a ship for one reporting period.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ShipStats {
+    /// Maximum number of concurrent players.
+    pub peak_players: u32,
+    /// Number of started quests.
+    pub quests: u64,
+    /// Meseta given to players by the server.
+    pub meseta: u64,
+}
+
+/// Player request for GM assistance.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SupportTicket {
+    pub id: u32,
+    pub user_id: u32,
+    pub nickname: String,
+    /// Id of the ship the player was on when the ticket was opened.
+    pub ship_id: u32,
+    pub message: String,
+    pub status: TicketStatus,
+    /// Id of the GM that claimed the ticket.
+    pub gm_id: Option<u32>,
+    pub resolution: String,
+    /// Time (since UNIX epoch) when the ticket was opened.
+    pub timestamp: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TicketStatus {
+    Open,
+    Claimed,
+    Resolved,
+}
+
+/// Login attempt of an account.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LoginEntry {
+    pub ip: Ipv4Addr,
+    pub status: LoginResult,
+    /// Client platform of the attempt.
+    pub platform: PacketType,
+    /// Time (since UNIX epoch) of the attempt.
+    pub timestamp: Duration,
+}
+
+/// Raw account row replicated to standby master ships.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplicatedUser {
+    pub id: u32,
+    pub username: Vec<u8>,
+    /// Password hash.
+    pub password: Vec<u8>,
+    pub psn_username: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Rows of a table replicated to standby master ships.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplicatedTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<ReplicatedValue>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReplicatedValue {
+    Null,
+    Integer(i64),
+    Blob(Vec<u8>),
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ShipLogin {
     pub psk: Vec<u8>,
+    /// Highest term of the primary master ship known to the client. Master ships with a lower
+    /// term were replaced by a standby and reject the login.
+    #[serde(default)]
+    pub term: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ShipLoginResult {
-    Ok,
+    /// Parameter is the term of the master ship.
+    Ok(u64),
     UnknownShip,
+    /// PSK was revoked.
+    Revoked,
+    /// Ship was kicked by an admin and stays out until unblocked.
+    Blocked,
+    /// Master ship was replaced by a standby with a higher term.
+    Fenced,
+}
+
+/// Named ship PSK.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ShipKey {
+    pub name: String,
+    pub psk: Vec<u8>,
+    /// Revoked keys are rejected on login.
+    pub revoked: bool,
+    /// Kicked ships are rejected on login until an admin unblocks them.
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum PutStorageResult {
+    Ok {
+        version: u64,
+    },
+    /// Storage was changed after it was read. Contains the current storage.
+    Conflict {
+        storage: AccountStorages,
+        version: u64,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -115,15 +466,49 @@ pub enum SetNicknameResult {
     AlreadyTaken,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChangeNicknameResult {
+    Ok,
+    AlreadyTaken,
+    /// Nickname was changed recently. Contains the time until the next change is allowed.
+    Cooldown(Duration),
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ShipInfo {
     pub ip: Ipv4Addr,
     pub port: u16,
     pub id: u32,
     pub max_players: u32,
+    /// Number of players currently on the ship.
+    pub players: u32,
     pub name: String,
     pub status: ShipStatus,
     pub key: KeyInfo,
+    /// Occupancy of ship blocks.
+    #[serde(default)]
+    pub blocks: Vec<BlockStatus>,
+    /// Clients waiting for the ship to free up.
+    #[serde(default)]
+    pub queue: ShipQueue,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockStatus {
+    pub id: u32,
+    pub name: String,
+    pub players: u32,
+    pub max_players: u32,
+}
+
+/// Clients waiting for a full ship.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShipQueue {
+    /// Addresses of waiting clients in the order of their first attempt.
+    pub waiting: Vec<Ipv4Addr>,
+    /// Average number of seconds between players leaving the ship. `None` if nobody left
+    /// recently.
+    pub departure_interval: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -140,11 +525,34 @@ pub enum UserLoginResult {
         id: u32,
         nickname: String,
         accountflags: Flags,
-        isgm: bool,
+        /// GM permission level (see [`gm_level`]).
+        gm_level: u8,
         last_uuid: u64,
     },
     InvalidPassword(u32),
     NotFound,
+    /// User is banned.
+    Banned {
+        /// Time (since UNIX epoch) when the ban expires. If `None` then the ban is permanent.
+        until: Option<Duration>,
+        reason: String,
+    },
+    /// Credentials are correct, but the account requires a one-time code.
+    OtpRequired,
+    /// Too many failed login attempts, login is temporarily locked.
+    TooManyAttempts {
+        /// Time until the lockout expires.
+        retry_after: Duration,
+    },
+    /// Account registration was denied.
+    RegistrationDenied(String),
+    /// Server is under maintenance and only GM accounts can log in. Contains the notice.
+    Maintenance(String),
+    /// Client is older than the minimum supported version.
+    OutdatedClient {
+        /// Minimum supported client version.
+        min_version: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -158,6 +566,12 @@ pub struct UserCreds {
     pub username: String,
     pub password: String,
     pub ip: Ipv4Addr,
+    /// Client platform of the login.
+    pub platform: PacketType,
+    /// One-time code for accounts with 2FA enabled.
+    pub otp: Option<String>,
+    /// Version string reported by the client (empty if unknown).
+    pub client_version: String,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -388,10 +802,10 @@ impl ShipConnection {
             .map_err(|_| Error::HKDFError)?;
         Ok(output)
     }
-    pub fn set_format(&mut self, format: SerializerFormat) {
+    pub const fn set_format(&mut self, format: SerializerFormat) {
         self.format = format;
     }
-    pub fn set_deferred_fmt(&mut self, format: SerializerFormat) {
+    pub const fn set_deferred_fmt(&mut self, format: SerializerFormat) {
         self.deferred_fmt = Some(format);
     }
 }
@@ -431,16 +845,54 @@ impl std::fmt::Debug for ShipLogin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ShipLogin")
             .field("psk", &"[REDACTED]")
+            .field("term", &self.term)
             .finish()
     }
 }
 
+impl std::fmt::Debug for ReplicatedUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicatedUser")
+            .field("id", &self.id)
+            .field("username", &String::from_utf8_lossy(&self.username))
+            .finish_non_exhaustive()
+    }
+}
+
+impl ShipQueue {
+    /// Returns the 1-based queue position of the client.
+    pub fn position(&self, ip: Ipv4Addr) -> Option<usize> {
+        self.waiting.iter().position(|i| *i == ip).map(|p| p + 1)
+    }
+    /// Returns the estimated wait in seconds at the queue position.
+    pub fn estimated_wait(&self, position: usize) -> Option<u32> {
+        self.departure_interval
+            .map(|i| i.saturating_mul(position as u32))
+    }
+}
+
+impl From<LoginEntry> for LoginAttempt {
+    fn from(entry: LoginEntry) -> Self {
+        Self {
+            ip: entry.ip,
+            status: entry.status,
+            timestamp: entry.timestamp,
+            // the history entry has no named platform slot
+            unk: entry.platform as u32,
+            ..Default::default()
+        }
+    }
+}
+
 impl std::fmt::Debug for UserCreds {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("UserCreds")
             .field("username", &self.username)
             .field("password", &"[REDACTED]")
             .field("ip", &self.ip)
+            .field("platform", &self.platform)
+            .field("otp", &self.otp.as_ref().map(|_| "[REDACTED]"))
+            .field("client_version", &self.client_version)
             .finish()
     }
 }
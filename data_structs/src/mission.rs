@@ -0,0 +1,41 @@
+use crate::reward::Reward;
+use serde::{Deserialize, Serialize};
+
+/// ARKS mission completed by reaching a condition.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArksMission {
+    pub id: u32,
+    pub name: String,
+    /// Mission type sent to the client.
+    pub mission_type: u32,
+    /// When the progress of the mission is reset.
+    pub reset: MissionReset,
+    pub condition: MissionCondition,
+    /// Value that should be reached to complete the mission.
+    pub value: u64,
+    /// Items given when the mission is claimed.
+    pub rewards: Vec<Reward>,
+    /// Experience given when the mission is claimed.
+    pub exp: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MissionReset {
+    /// Mission can be completed only once.
+    #[default]
+    Never,
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MissionCondition {
+    /// Log in on a number of days.
+    #[default]
+    Logins,
+    /// Clear a number of quests.
+    QuestsCleared,
+    /// Kill a number of enemies.
+    EnemiesKilled,
+}
@@ -0,0 +1,86 @@
+use crate::reward::Reward;
+use serde::{Deserialize, Serialize};
+
+/// Mission pass season.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MissionPassSeason {
+    pub id: u32,
+    pub name: String,
+    /// Banner image shown in the pass menu.
+    pub banner: String,
+    /// Start of the season in seconds since UNIX epoch.
+    pub start: u64,
+    /// End of the season in seconds since UNIX epoch.
+    pub end: u64,
+    /// Stars required to reach the next tier.
+    pub stars_per_tier: u32,
+    /// Stars given for clearing a quest.
+    pub quest_stars: u32,
+    /// Stars given for a level up.
+    pub level_up_stars: u32,
+    /// Meseta price of the gold pass.
+    pub gold_pass_price: u64,
+    pub tiers: Vec<PassTier>,
+    /// Tiers available after reaching all regular tiers.
+    pub overrun_tiers: Vec<PassTier>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassTier {
+    /// Rewards available to all players.
+    pub rewards: Vec<Reward>,
+    /// Rewards available to gold pass owners.
+    pub gold_rewards: Vec<Reward>,
+}
+
+impl MissionPassSeason {
+    /// Checks if the season is running at the time.
+    pub const fn is_active(&self, now: u64) -> bool {
+        self.start <= now && now < self.end
+    }
+    pub const fn total_tiers(&self) -> u32 {
+        (self.tiers.len() + self.overrun_tiers.len()) as u32
+    }
+    /// Returns the number of tiers reached with the stars.
+    pub fn tier_at(&self, stars: u32) -> u32 {
+        stars
+            .checked_div(self.stars_per_tier)
+            .unwrap_or(0)
+            .min(self.total_tiers())
+    }
+    /// Returns the tier by its index, counting overrun tiers after regular ones.
+    pub fn tier(&self, index: u32) -> Option<&PassTier> {
+        let index = index as usize;
+        self.tiers
+            .get(index)
+            .or_else(|| self.overrun_tiers.get(index.checked_sub(self.tiers.len())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MissionPassSeason, PassTier};
+
+    #[test]
+    fn test_tiers() {
+        let season = MissionPassSeason {
+            start: 100,
+            end: 200,
+            stars_per_tier: 10,
+            tiers: vec![PassTier::default(); 3],
+            overrun_tiers: vec![PassTier::default(); 2],
+            ..Default::default()
+        };
+        assert!(season.is_active(100));
+        assert!(!season.is_active(200));
+        assert_eq!(season.tier_at(9), 0);
+        assert_eq!(season.tier_at(35), 3);
+        assert_eq!(season.tier_at(1000), 5);
+        assert!(season.tier(4).is_some());
+        assert!(season.tier(5).is_none());
+        let no_stars = MissionPassSeason::default();
+        assert_eq!(no_stars.tier_at(100), 0);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::reward::Reward;
+use pso2packetlib::protocol::items::ItemId;
+use serde::{Deserialize, Serialize};
+
+/// Client orders given by one NPC.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientOrderList {
+    pub npc: String,
+    pub orders: Vec<ClientOrder>,
+}
+
+/// Task given by an NPC.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientOrder {
+    /// Unique id of the order across all NPCs.
+    pub id: u32,
+    pub name: String,
+    pub kind: OrderKind,
+    /// Enemy name for `Kill` orders. Empty - any enemy.
+    pub enemy: String,
+    /// Item for `Collect` orders.
+    pub item: ItemId,
+    /// Number of enemies or items required.
+    pub amount: u16,
+    /// Items given on turn-in.
+    pub rewards: Vec<Reward>,
+    /// Meseta given on turn-in.
+    pub meseta: u64,
+    /// Experience given on turn-in.
+    pub exp: u32,
+    /// Set if the order can be completed again after turning in.
+    pub repeatable: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderKind {
+    /// Kill enemies.
+    #[default]
+    Kill,
+    /// Bring items, taken from the inventory on turn-in.
+    Collect,
+}
+
+impl ClientOrder {
+    /// Checks if killing the enemy progresses the order.
+    pub fn counts_kill(&self, enemy: &str) -> bool {
+        self.kind == OrderKind::Kill
+            && (self.enemy.is_empty() || self.enemy.eq_ignore_ascii_case(enemy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientOrder, OrderKind};
+
+    #[test]
+    fn test_counts_kill() {
+        let order = ClientOrder {
+            kind: OrderKind::Kill,
+            enemy: "Falspawn".into(),
+            ..Default::default()
+        };
+        assert!(order.counts_kill("falspawn"));
+        assert!(!order.counts_kill("Soldier Ant"));
+        let any = ClientOrder {
+            enemy: String::new(),
+            ..order.clone()
+        };
+        assert!(any.counts_kill("Soldier Ant"));
+        let collect = ClientOrder {
+            kind: OrderKind::Collect,
+            ..order
+        };
+        assert!(!collect.counts_kill("Falspawn"));
+    }
+}
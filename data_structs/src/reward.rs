@@ -0,0 +1,10 @@
+use pso2packetlib::protocol::items::ItemId;
+use serde::{Deserialize, Serialize};
+
+/// Items given by titles, client orders, ARKS missions, the mission pass and daily bonuses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reward {
+    pub id: ItemId,
+    pub amount: u16,
+}
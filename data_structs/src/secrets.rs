@@ -0,0 +1,70 @@
+//! Loading of keys and credentials from environment variables and files.
+use crate::Error;
+use std::{io::Write, path::Path};
+
+/// Returns the value of the environment variable if it is set and not empty.
+pub fn from_env(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|v| !v.is_empty())
+}
+
+/// Reads the secret file. On Unix the file is refused if group or others can access it.
+pub fn read_file(path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    check_permissions(path)?;
+    Ok(std::fs::read(path)?)
+}
+
+/// Writes the secret file that is only accessible by the owner.
+pub fn write_file(path: impl AsRef<Path>, data: &[u8]) -> Result<(), Error> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(Error::InsecurePermissions(
+            path.display().to_string(),
+            0o600,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(path: &Path) -> Result<(), Error> {
+    std::fs::metadata(path)?;
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{read_file, write_file};
+    use crate::Error;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_permissions() {
+        let path = "test_secret.bin";
+        let _ = std::fs::remove_file(path);
+        write_file(path, b"secret").unwrap();
+        assert_eq!(read_file(path).unwrap(), b"secret");
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            read_file(path),
+            Err(Error::InsecurePermissions(_, 0o600))
+        ));
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,75 @@
+use pso2packetlib::protocol::items::ItemId;
+use serde::{Deserialize, Serialize};
+
+/// NPC shop.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShopData {
+    pub id: u32,
+    pub name: String,
+    /// Names of the NPCs that open the shop.
+    pub npcs: Vec<String>,
+    /// Items that are always sold.
+    pub items: Vec<ShopItem>,
+    /// Item sets that are sold in turns.
+    pub rotation: Vec<Vec<ShopItem>>,
+    /// Time in seconds each rotation set is sold for.
+    pub rotation_period: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShopItem {
+    pub id: ItemId,
+    /// Price of one item in meseta.
+    pub price: u64,
+    /// Maximum amount bought at once.
+    pub max_amount: u16,
+}
+
+impl ShopData {
+    /// Returns items sold at the time (in seconds since UNIX epoch).
+    pub fn items_at(&self, time: u64) -> impl Iterator<Item = &ShopItem> {
+        let rotation = match self.rotation.len() as u64 {
+            0 => &[][..],
+            len => {
+                let period = self.rotation_period.max(1);
+                &self.rotation[((time / period) % len) as usize][..]
+            }
+        };
+        self.items.iter().chain(rotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShopData, ShopItem};
+    use pso2packetlib::protocol::items::ItemId;
+
+    fn item(id: u16) -> ShopItem {
+        ShopItem {
+            id: ItemId {
+                item_type: 3,
+                id,
+                ..Default::default()
+            },
+            price: 100,
+            max_amount: 10,
+        }
+    }
+
+    #[test]
+    fn test_shop_rotation() {
+        let shop = ShopData {
+            items: vec![item(1)],
+            rotation: vec![vec![item(2)], vec![item(3), item(4)]],
+            rotation_period: 60,
+            ..Default::default()
+        };
+        let ids = |time| shop.items_at(time).map(|i| i.id.id).collect::<Vec<_>>();
+        assert_eq!(ids(0), vec![1, 2]);
+        assert_eq!(ids(59), vec![1, 2]);
+        assert_eq!(ids(60), vec![1, 3, 4]);
+        assert_eq!(ids(120), vec![1, 2]);
+    }
+}
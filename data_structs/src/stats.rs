@@ -22,6 +22,8 @@ pub struct LevelStats {
 pub struct ClassStatsStored {
     pub class: Class,
     pub stats: Vec<LevelStats>,
+    /// Maximum level of the class. 0 - limited only by `stats`.
+    pub level_cap: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -29,6 +31,20 @@ pub struct ClassStatsStored {
 pub struct PlayerStats {
     pub stats: Vec<Vec<LevelStats>>,
     pub modifiers: Vec<StatMultipliers>,
+    /// Maximum levels by class. 0 - limited only by `stats`.
+    pub level_caps: Vec<u32>,
+}
+
+impl PlayerStats {
+    /// Returns the maximum level of the class. Levels without stats can't be reached, so the cap
+    /// never exceeds the stats table.
+    pub fn level_cap(&self, class: usize) -> u32 {
+        let max = self.stats.get(class).map_or(0, |s| s.len() as u32);
+        match self.level_caps.get(class) {
+            Some(&cap) if cap != 0 => cap.min(max),
+            _ => max,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -96,6 +112,21 @@ pub struct EnemyHitbox {
 #[serde(default)]
 pub struct EnemyBaseStats {
     pub levels: Vec<EnemyLevelBaseStats>,
+    /// Stat tables by quest difficulty (Normal, Hard, Very Hard, Super Hard, Extra Hard, ...).
+    /// Difficulties without a table use `levels`.
+    pub difficulties: Vec<Vec<EnemyLevelBaseStats>>,
+}
+
+impl EnemyBaseStats {
+    /// Returns the base stats of the level on the quest difficulty.
+    pub fn level_stats(&self, difficulty: u8, level: u32) -> Option<&EnemyLevelBaseStats> {
+        let table = self
+            .difficulties
+            .get(difficulty as usize)
+            .filter(|t| !t.is_empty())
+            .unwrap_or(&self.levels);
+        table.get((level as usize).checked_sub(1)?)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -179,3 +210,69 @@ impl From<DamageTypeReadable> for DamageType {
         }
     }
 }
+
+/// Constants of the damage formulas.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CombatFormula {
+    /// Fraction of the weapon power used for the minimum damage.
+    pub min_weapon_mul: f32,
+    /// Multiplier of the target defense subtracted from the attack power.
+    pub defense_mul: f32,
+    /// Divisor of the attack power after the defense is subtracted.
+    pub damage_divisor: f32,
+    /// Multiplier applied to all damage.
+    pub damage_mul: f32,
+    /// Chance of a critical hit (maximum damage) in percent.
+    pub crit_chance: f32,
+    /// Damage change in percent per level of the attacker above the target (negative levels
+    /// reduce the damage).
+    pub level_diff_mul: f32,
+    /// Maximum damage change in percent caused by the level difference.
+    pub max_level_diff_mul: f32,
+}
+
+impl CombatFormula {
+    /// Returns the damage multiplier for the level difference between the attacker and the
+    /// target.
+    pub fn level_mul(&self, level_diff: i32) -> f32 {
+        let max = self.max_level_diff_mul.abs();
+        let change = (level_diff as f32 * self.level_diff_mul).max(-max).min(max);
+        (1.0 + change / 100.0).max(0.0)
+    }
+}
+
+impl Default for CombatFormula {
+    fn default() -> Self {
+        Self {
+            min_weapon_mul: 0.9,
+            defense_mul: 1.0,
+            damage_divisor: 5.0,
+            damage_mul: 1.05,
+            crit_chance: 5.0,
+            level_diff_mul: 0.0,
+            max_level_diff_mul: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LevelStats, PlayerStats};
+
+    #[test]
+    fn test_level_cap() {
+        let stats = PlayerStats {
+            stats: vec![
+                vec![LevelStats::default(); 80],
+                vec![LevelStats::default(); 80],
+            ],
+            level_caps: vec![75, 0],
+            ..Default::default()
+        };
+        assert_eq!(stats.level_cap(0), 75);
+        assert_eq!(stats.level_cap(1), 80);
+        // no stats for the class
+        assert_eq!(stats.level_cap(2), 0);
+    }
+}
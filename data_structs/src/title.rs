@@ -0,0 +1,92 @@
+use crate::reward::Reward;
+use pso2packetlib::protocol::models::character::Class;
+use serde::{Deserialize, Serialize};
+
+/// Title earned by reaching a condition.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Title {
+    pub id: u32,
+    pub name: String,
+    pub condition: TitleCondition,
+    /// Class required by the `Level` condition. `None` - any class.
+    pub class: Option<Class>,
+    /// Value that should be reached to earn the title.
+    pub value: u64,
+    /// Items given when the title is claimed.
+    pub rewards: Vec<Reward>,
+    /// Meseta given when the title is claimed.
+    pub meseta: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TitleCondition {
+    /// Reach the level on a class.
+    #[default]
+    Level,
+    /// Clear a number of quests.
+    QuestsCleared,
+    /// Kill a number of enemies.
+    EnemiesKilled,
+}
+
+/// Player progress checked by title conditions.
+#[derive(Debug, Clone, Default)]
+pub struct TitleProgress {
+    /// Class and its level after the last level up.
+    pub level: Option<(Class, u32)>,
+    pub quests_cleared: u64,
+    pub enemies_killed: u64,
+}
+
+impl Title {
+    /// Checks if the progress satisfies the condition of the title.
+    pub fn is_met(&self, progress: &TitleProgress) -> bool {
+        match self.condition {
+            TitleCondition::Level => progress.level.as_ref().is_some_and(|(class, level)| {
+                self.class.as_ref().is_none_or(|c| c == class) && u64::from(*level) >= self.value
+            }),
+            TitleCondition::QuestsCleared => progress.quests_cleared >= self.value,
+            TitleCondition::EnemiesKilled => progress.enemies_killed >= self.value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Title, TitleCondition, TitleProgress};
+    use pso2packetlib::protocol::models::character::Class;
+
+    #[test]
+    fn test_title_conditions() {
+        let level = Title {
+            condition: TitleCondition::Level,
+            class: Some(Class::Hunter),
+            value: 20,
+            ..Default::default()
+        };
+        let kills = Title {
+            condition: TitleCondition::EnemiesKilled,
+            value: 100,
+            ..Default::default()
+        };
+        let mut progress = TitleProgress {
+            level: Some((Class::Ranger, 30)),
+            enemies_killed: 99,
+            ..Default::default()
+        };
+        assert!(!level.is_met(&progress));
+        assert!(!kills.is_met(&progress));
+        progress.level = Some((Class::Hunter, 20));
+        progress.enemies_killed = 100;
+        assert!(level.is_met(&progress));
+        assert!(kills.is_met(&progress));
+        // any class
+        let any = Title {
+            class: None,
+            ..level
+        };
+        progress.level = Some((Class::Force, 25));
+        assert!(any.is_met(&progress));
+    }
+}
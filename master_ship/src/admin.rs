@@ -0,0 +1,469 @@
+//! Optional HTTP API for server administration.
+//!
+//! All requests must contain `Authorization: Bearer <token>` header with the token from the
+//! settings file.
+use crate::{Error, MSData};
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use data_structs::master_ship::gm_level;
+use pso2packetlib::protocol::login::ShipStatus;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::Ipv4Addr,
+    ops::Add,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::net::TcpListener;
+
+#[derive(Clone)]
+struct AdminState {
+    ms_data: Arc<MSData>,
+    token: Arc<str>,
+}
+
+struct ApiError(Error);
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PasswordReset {
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct GmLevelRequest {
+    /// GM permission level (see [`data_structs::master_ship::gm_level`]).
+    level: u8,
+}
+
+#[derive(Deserialize)]
+struct StorageCapacityRequest {
+    /// Number of item slots (0 - bank is disabled).
+    capacity: u32,
+}
+
+#[derive(Deserialize)]
+struct BanRequest {
+    reason: String,
+    /// Ban duration in seconds. If not set then the ban is permanent.
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct NoteRequest {
+    note: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    /// Id of the account merged into the target one.
+    from: u32,
+}
+
+#[derive(Deserialize)]
+struct LinkRequest {
+    /// Code generated in game with `!linkaccount`.
+    code: String,
+    /// Name of the external service (e.g. "discord").
+    service: String,
+    /// Id of the user in the external service.
+    external_id: String,
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    /// Ids of the target ships. If empty then all ships receive the message.
+    #[serde(default)]
+    ships: Vec<u32>,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ResolveRequest {
+    /// Id of the GM account resolving the ticket.
+    gm_id: u32,
+    resolution: String,
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// Id of the ship. If not set then statistics of all ships are returned.
+    ship: Option<u32>,
+    /// Number of past hours to return (default is 24).
+    hours: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+    /// Notice shown to players. If not set then the current one is kept.
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ShipEntry {
+    id: u32,
+    name: String,
+    ip: Ipv4Addr,
+    port: u16,
+    max_players: u32,
+    players: u32,
+    status: ShipStatus,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+pub(crate) async fn start_admin_api(
+    ms_data: Arc<MSData>,
+    addr: &str,
+    token: String,
+) -> Result<(), Error> {
+    let state = AdminState {
+        ms_data,
+        token: token.into(),
+    };
+    let router = Router::new()
+        .route("/accounts", get(lookup_account))
+        .route("/accounts/{id}", get(get_account))
+        .route("/accounts/{id}/password", post(reset_password))
+        .route("/accounts/{id}/gm", post(set_gm))
+        .route("/accounts/{id}/totp", delete(disable_totp))
+        .route("/accounts/{id}/storage/{bank}", post(set_storage_capacity))
+        .route(
+            "/accounts/{id}/ban",
+            get(get_ban).post(ban_user).delete(unban_user),
+        )
+        .route("/accounts/{id}/notes", get(get_notes).post(add_note))
+        .route("/accounts/{id}/links", get(get_links))
+        .route("/accounts/{id}/links/{service}", delete(unlink_account))
+        .route("/accounts/{id}/merge", post(merge_account))
+        .route("/link", post(link_account))
+        .route("/ships", get(list_ships))
+        .route("/server_data/reload", post(reload_server_data))
+        .route("/broadcast", post(broadcast))
+        .route("/tickets", get(list_tickets))
+        .route("/tickets/{id}/resolve", post(resolve_ticket))
+        .route("/stats", get(get_stats))
+        .route("/stats/network", get(get_network_stats))
+        .route("/maintenance", post(set_maintenance))
+        .layer(middleware::from_fn_with_state(state.clone(), check_auth))
+        .with_state(state);
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Admin API listening on {addr}");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            log::error!("Admin API failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn check_auth(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), state.token.as_bytes()));
+    if !authorized {
+        log::warn!("Unauthorized admin API request to {}", request.uri());
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+async fn lookup_account(
+    State(state): State<AdminState>,
+    Query(query): Query<LookupQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let account = state.ms_data.sql.find_account(&query.name).await?;
+    Ok(Json(account))
+}
+
+async fn get_account(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+) -> ApiResult<impl IntoResponse> {
+    let account = state.ms_data.sql.get_account_info(id).await?;
+    Ok(Json(account))
+}
+
+async fn reset_password(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Json(data): Json<PasswordReset>,
+) -> ApiResult<impl IntoResponse> {
+    state.ms_data.sql.set_password(id, &data.password).await?;
+    log::info!("Admin API: password reset for user {id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_gm(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Json(data): Json<GmLevelRequest>,
+) -> ApiResult<impl IntoResponse> {
+    state.ms_data.sql.set_gm_level(id, data.level).await?;
+    log::info!("Admin API: set GM level of user {id} to {}", data.level);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_storage_capacity(
+    State(state): State<AdminState>,
+    Path((id, bank)): Path<(u32, u8)>,
+    Json(data): Json<StorageCapacityRequest>,
+) -> ApiResult<impl IntoResponse> {
+    state
+        .ms_data
+        .sql
+        .set_storage_capacity(id, bank, data.capacity)
+        .await?;
+    log::info!(
+        "Admin API: set capacity of storage {bank} of user {id} to {}",
+        data.capacity
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn disable_totp(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+) -> ApiResult<impl IntoResponse> {
+    state.ms_data.sql.disable_totp(id, None).await?;
+    log::info!("Admin API: disabled 2FA of user {id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn link_account(
+    State(state): State<AdminState>,
+    Json(data): Json<LinkRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let sql = &state.ms_data.sql;
+    let Some(id) = sql
+        .link_account(&data.code, &data.service, &data.external_id)
+        .await?
+    else {
+        return Err(Error::InvalidCode.into());
+    };
+    log::info!(
+        "Admin API: linked {} account {} to user {id}",
+        data.service,
+        data.external_id
+    );
+    let account = sql.get_account_info(id).await?;
+    Ok(Json(account))
+}
+
+async fn get_notes(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+) -> ApiResult<impl IntoResponse> {
+    let notes = state.ms_data.sql.get_account_notes(id).await?;
+    Ok(Json(notes))
+}
+
+async fn add_note(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Json(data): Json<NoteRequest>,
+) -> ApiResult<impl IntoResponse> {
+    state
+        .ms_data
+        .sql
+        .add_account_note(id, 0, &data.note)
+        .await?;
+    log::info!("Admin API: added a note to user {id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_links(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+) -> ApiResult<impl IntoResponse> {
+    let links = state.ms_data.sql.get_account_links(id).await?;
+    Ok(Json(links))
+}
+
+async fn unlink_account(
+    State(state): State<AdminState>,
+    Path((id, service)): Path<(u32, String)>,
+) -> ApiResult<impl IntoResponse> {
+    state.ms_data.sql.unlink_account(id, &service).await?;
+    log::info!("Admin API: unlinked {service} account from user {id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn merge_account(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Json(data): Json<MergeRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let sql = &state.ms_data.sql;
+    crate::merge_accounts(&state.ms_data, data.from, id).await?;
+    log::info!("Admin API: merged user {} into user {id}", data.from);
+    let account = sql.get_account_info(id).await?;
+    Ok(Json(account))
+}
+
+async fn get_ban(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+) -> ApiResult<impl IntoResponse> {
+    let ban = state.ms_data.sql.get_ban(id).await?;
+    Ok(Json(ban))
+}
+
+async fn ban_user(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Json(data): Json<BanRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let until = data.duration.map(|d| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .add(Duration::from_secs(d))
+    });
+    state.ms_data.sql.ban_user(id, &data.reason, until).await?;
+    log::info!("Admin API: banned user {id}, reason: {}", data.reason);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unban_user(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+) -> ApiResult<impl IntoResponse> {
+    state.ms_data.sql.unban_user(id).await?;
+    log::info!("Admin API: unbanned user {id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_ships(State(state): State<AdminState>) -> impl IntoResponse {
+    let ships: Vec<_> = state
+        .ms_data
+        .ships
+        .read()
+        .iter()
+        .map(|s| ShipEntry {
+            id: s.id,
+            name: s.name.clone(),
+            ip: s.ip,
+            port: s.port,
+            max_players: s.max_players,
+            players: s.players,
+            status: s.status,
+        })
+        .collect();
+    Json(ships)
+}
+
+async fn reload_server_data(State(state): State<AdminState>) -> ApiResult<impl IntoResponse> {
+    crate::reload_server_data(&state.ms_data).await?;
+    log::info!("Admin API: reloaded server data");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn broadcast(
+    State(state): State<AdminState>,
+    Json(data): Json<BroadcastRequest>,
+) -> ApiResult<impl IntoResponse> {
+    if data.message.is_empty() {
+        return Err(Error::InvalidData.into());
+    }
+    crate::broadcast(&state.ms_data, data.ships, data.message);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_tickets(State(state): State<AdminState>) -> ApiResult<impl IntoResponse> {
+    let tickets = state.ms_data.sql.get_open_tickets().await?;
+    Ok(Json(tickets))
+}
+
+async fn resolve_ticket(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Json(data): Json<ResolveRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let gm = state.ms_data.sql.get_account_info(data.gm_id).await?;
+    if gm.gm_level < gm_level::GM {
+        return Err(Error::GmLevelTooLow.into());
+    }
+    crate::resolve_ticket(&state.ms_data, id, data.gm_id, &data.resolution).await?;
+    log::info!("Admin API: user {} resolved ticket {id}", data.gm_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_stats(
+    State(state): State<AdminState>,
+    Query(query): Query<StatsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let period = Duration::from_secs(query.hours.unwrap_or(24).saturating_mul(3600));
+    let stats = state.ms_data.sql.get_ship_stats(query.ship, period).await?;
+    Ok(Json(stats))
+}
+
+/// Returns hourly statistics summed over all ships.
+async fn get_network_stats(
+    State(state): State<AdminState>,
+    Query(query): Query<StatsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let period = Duration::from_secs(query.hours.unwrap_or(24).saturating_mul(3600));
+    let stats = state.ms_data.sql.get_network_stats(period).await?;
+    Ok(Json(stats))
+}
+
+async fn set_maintenance(
+    State(state): State<AdminState>,
+    Json(data): Json<MaintenanceRequest>,
+) -> impl IntoResponse {
+    crate::set_maintenance(&state.ms_data, data.enabled, data.message);
+    StatusCode::NO_CONTENT
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+impl From<Error> for ApiError {
+    fn from(value: Error) -> Self {
+        Self(value)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self.0 {
+            Error::NoUser => StatusCode::NOT_FOUND,
+            Error::InvalidData => StatusCode::BAD_REQUEST,
+            Error::InvalidCode => StatusCode::NOT_FOUND,
+            Error::MergeConflict(_) => StatusCode::CONFLICT,
+            Error::GmLevelTooLow => StatusCode::FORBIDDEN,
+            _ => {
+                log::warn!("Admin API error: {}", self.0);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        let body = ErrorResponse {
+            error: self.0.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,124 @@
+//! Scheduled and on-demand database backups.
+use crate::{Error, MSData, IS_RUNNING};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const BACKUP_PREFIX: &str = "master_ship_";
+const BACKUP_EXTENSION: &str = "db";
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    /// Directory for the backups.
+    pub directory: String,
+    /// Interval between scheduled backups in seconds (0 - only on-demand backups).
+    pub interval: u64,
+    /// Number of backups to keep (0 - unlimited).
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            directory: String::from("backups"),
+            interval: 0,
+            keep: 7,
+        }
+    }
+}
+
+/// Creates a new backup and removes the old ones. Returns the path of the backup.
+pub(crate) async fn make_backup(ms_data: &MSData) -> Result<PathBuf, Error> {
+    let settings = &ms_data.backup;
+    tokio::fs::create_dir_all(&settings.directory).await?;
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    // backups made in the same millisecond get the next free timestamp
+    let path = loop {
+        let path = Path::new(&settings.directory)
+            .join(format!("{BACKUP_PREFIX}{timestamp}.{BACKUP_EXTENSION}"));
+        if !tokio::fs::try_exists(&path).await? {
+            break path;
+        }
+        timestamp += 1;
+    };
+    ms_data.sql.backup(&path.to_string_lossy()).await?;
+    log::info!("Database backup saved to {}", path.display());
+    if settings.keep != 0 {
+        remove_old_backups(Path::new(&settings.directory), settings.keep).await?;
+    }
+    Ok(path)
+}
+
+/// Removes all backups in the directory except the `keep` newest ones.
+async fn remove_old_backups(dir: &Path, keep: usize) -> Result<(), Error> {
+    let mut backups = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let timestamp = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.strip_prefix(BACKUP_PREFIX))
+            .and_then(|s| s.parse::<u64>().ok());
+        let is_backup = path.extension().is_some_and(|e| e == BACKUP_EXTENSION);
+        if let (Some(timestamp), true) = (timestamp, is_backup) {
+            backups.push((timestamp, path));
+        }
+    }
+    backups.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+    for (_, path) in backups.into_iter().skip(keep) {
+        log::debug!("Removing old backup {}", path.display());
+        tokio::fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// Periodically backs up the database.
+pub(crate) async fn backup_loop(ms_data: Arc<MSData>) {
+    let interval = ms_data.backup.interval;
+    if interval == 0 {
+        return;
+    }
+    let mut timer = tokio::time::interval(Duration::from_secs(interval));
+    // first tick completes immediately
+    timer.tick().await;
+    while IS_RUNNING.load(Ordering::Relaxed) {
+        timer.tick().await;
+        if let Err(e) = make_backup(&ms_data).await {
+            log::error!("Database backup failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::remove_old_backups;
+
+    #[tokio::test]
+    async fn test_remove_old_backups() {
+        let dir = std::path::Path::new("test_backups");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        for name in [
+            "master_ship_100.db",
+            "master_ship_300.db",
+            "master_ship_200.db",
+            "other.db",
+        ] {
+            std::fs::write(dir.join(name), []).unwrap();
+        }
+        remove_old_backups(dir, 2).await.unwrap();
+        assert!(!dir.join("master_ship_100.db").exists());
+        assert!(dir.join("master_ship_200.db").exists());
+        assert!(dir.join("master_ship_300.db").exists());
+        assert!(dir.join("other.db").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,240 @@
+//! Interactive administration console on the standard input.
+use crate::{run_action, Error, MSData, IS_RUNNING};
+use data_structs::master_ship::{MasterShipAction, MasterShipComm};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const HELP: &str = "\
+Commands:
+  list-ships                              List registered ships
+  kick-ship <id>                          Disconnect the ship and block it from reconnecting
+  unblock-ship <name>                     Allow the kicked ship to reconnect
+  ship-keys                               List ship keys
+  new-ship-key <name>                     Create a new key for the ship, revoking the old ones
+  revoke-ship-key <name>                  Revoke keys of the ship
+  set-gm <username> <level>               Set GM level of the account (0-3)
+  reset-password <username> <password>    Set password of the account
+  set-storage <username> <bank> <slots>   Set storage capacity (bank 0-2, 0 slots - disabled)
+  notes <username>                        List GM notes of the account
+  add-note <username> <note>              Add a GM note to the account
+  merge-accounts <from> <into>            Merge the first account into the second one
+  reload-data                             Reload server data
+  backup                                  Back up the database
+  broadcast <message>                     Send a message to all ships
+  maintenance <on|off> [notice]           Toggle maintenance mode (only GMs can log in)
+  help                                    Show this message";
+
+pub(crate) async fn run_console(ms_data: Arc<MSData>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while IS_RUNNING.load(Ordering::Relaxed) {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            // stdin is closed (e.g. running as a service)
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("Console read error: {e}");
+                return;
+            }
+        };
+        match run_command(&ms_data, line.trim()).await {
+            Ok(output) if !output.is_empty() => println!("{output}"),
+            Ok(_) => {}
+            Err(e) => println!("Error: {e}"),
+        }
+    }
+}
+
+async fn run_command(ms_data: &MSData, line: &str) -> Result<String, Error> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+    match command {
+        "" => Ok(String::new()),
+        "help" => Ok(HELP.to_string()),
+        "list-ships" => {
+            let ships = ms_data.ships.read();
+            if ships.is_empty() {
+                return Ok("No ships registered".to_string());
+            }
+            Ok(ships
+                .iter()
+                .map(|s| {
+                    format!(
+                        "{}: {} ({}:{}) {}/{} players, {:?}",
+                        s.id, s.name, s.ip, s.port, s.players, s.max_players, s.status
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        "kick-ship" => {
+            let id: u32 = args.parse().map_err(|_| Error::InvalidData)?;
+            if !ms_data.ships.read().iter().any(|s| s.id == id) {
+                return Err(Error::UnknownShip);
+            }
+            let comm = MasterShipComm {
+                id: 0,
+                action: MasterShipAction::UnregisterShip(id),
+            };
+            run_action(ms_data, comm, Some(id)).await?;
+            // error means that the ship is already disconnected
+            let _ = ms_data.kicks.send(id);
+            log::info!("Console: kicked ship {id}");
+            Ok(format!("Ship {id} kicked"))
+        }
+        "unblock-ship" => {
+            ms_data.sql.set_ship_blocked(args, false).await?;
+            log::info!("Console: unblocked ship \"{args}\"");
+            Ok(format!("Ship {args} unblocked"))
+        }
+        "ship-keys" => {
+            let keys = ms_data.sql.get_ship_keys().await?;
+            if keys.is_empty() {
+                return Ok("No ship keys".to_string());
+            }
+            Ok(keys
+                .iter()
+                .map(|k| {
+                    let status = if k.revoked {
+                        "revoked"
+                    } else if k.blocked {
+                        "blocked"
+                    } else {
+                        "active"
+                    };
+                    format!("{}: {status}", k.name)
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        "new-ship-key" => {
+            let comm = MasterShipComm {
+                id: 0,
+                action: MasterShipAction::NewShipKey(args.to_string()),
+            };
+            match run_action(ms_data, comm, None).await?.action {
+                MasterShipAction::ShipKeyResult(psk) => {
+                    log::info!("Console: created a new key for ship \"{args}\"");
+                    Ok(format!("New key of {args}: {psk}"))
+                }
+                MasterShipAction::Error(e) => Ok(format!("Error: {e}")),
+                _ => Err(Error::InvalidAction),
+            }
+        }
+        "revoke-ship-key" => {
+            let comm = MasterShipComm {
+                id: 0,
+                action: MasterShipAction::RevokeShipKey(args.to_string()),
+            };
+            match run_action(ms_data, comm, None).await?.action {
+                MasterShipAction::Error(e) => Ok(format!("Error: {e}")),
+                _ => {
+                    log::info!("Console: revoked keys of ship \"{args}\"");
+                    Ok(format!("Keys of {args} revoked"))
+                }
+            }
+        }
+        "set-gm" => {
+            let (username, level) = args.split_once(' ').ok_or(Error::InvalidData)?;
+            let level: u8 = level.trim().parse().map_err(|_| Error::InvalidData)?;
+            let account = ms_data.sql.find_account(username).await?;
+            ms_data.sql.set_gm_level(account.id, level).await?;
+            log::info!("Console: set GM level of user {} to {level}", account.id);
+            Ok(format!("GM level of {username} set to {level}"))
+        }
+        "reset-password" => {
+            let (username, password) = args.split_once(' ').ok_or(Error::InvalidData)?;
+            let account = ms_data.sql.find_account(username).await?;
+            ms_data
+                .sql
+                .set_password(account.id, password.trim())
+                .await?;
+            log::info!("Console: password reset for user {}", account.id);
+            Ok(format!("Password of {username} reset"))
+        }
+        "set-storage" => {
+            let mut args = args.split_whitespace();
+            let (Some(username), Some(bank), Some(capacity)) =
+                (args.next(), args.next(), args.next())
+            else {
+                return Err(Error::InvalidData);
+            };
+            let bank: u8 = bank.parse().map_err(|_| Error::InvalidData)?;
+            let capacity: u32 = capacity.parse().map_err(|_| Error::InvalidData)?;
+            let account = ms_data.sql.find_account(username).await?;
+            ms_data
+                .sql
+                .set_storage_capacity(account.id, bank, capacity)
+                .await?;
+            log::info!(
+                "Console: set capacity of storage {bank} of user {} to {capacity}",
+                account.id
+            );
+            Ok(format!(
+                "Storage {bank} of {username} set to {capacity} slots"
+            ))
+        }
+        "notes" => {
+            let account = ms_data.sql.find_account(args).await?;
+            let notes = ms_data.sql.get_account_notes(account.id).await?;
+            if notes.is_empty() {
+                return Ok(format!("No notes for {args}"));
+            }
+            Ok(notes
+                .iter()
+                .map(|n| format!("[{}] by {}: {}", n.timestamp.as_secs(), n.author_id, n.note))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        "add-note" => {
+            let (username, note) = args.split_once(' ').ok_or(Error::InvalidData)?;
+            let account = ms_data.sql.find_account(username).await?;
+            ms_data.sql.add_account_note(account.id, 0, note).await?;
+            log::info!("Console: added a note to user {}", account.id);
+            Ok(format!("Note added to {username}"))
+        }
+        "merge-accounts" => {
+            let (from, into) = args.split_once(' ').ok_or(Error::InvalidData)?;
+            let from = ms_data.sql.find_account(from).await?;
+            let into = ms_data.sql.find_account(into.trim()).await?;
+            crate::merge_accounts(ms_data, from.id, into.id).await?;
+            log::info!("Console: merged user {} into user {}", from.id, into.id);
+            Ok(format!("{} merged into {}", from.id, into.id))
+        }
+        "reload-data" => {
+            crate::reload_server_data(ms_data).await?;
+            Ok("Server data reloaded".to_string())
+        }
+        "backup" => {
+            let comm = MasterShipComm {
+                id: 0,
+                action: MasterShipAction::Backup,
+            };
+            match run_action(ms_data, comm, None).await?.action {
+                MasterShipAction::BackupResult(path) => Ok(format!("Backup saved to {path}")),
+                MasterShipAction::Error(e) => Ok(format!("Error: {e}")),
+                _ => Err(Error::InvalidAction),
+            }
+        }
+        "broadcast" => {
+            if args.is_empty() {
+                return Err(Error::InvalidData);
+            }
+            crate::broadcast(ms_data, vec![], args.to_string());
+            Ok(String::new())
+        }
+        "maintenance" => {
+            let (mode, notice) = args.split_once(' ').unwrap_or((args, ""));
+            let enabled = match mode {
+                "on" => true,
+                "off" => false,
+                _ => return Err(Error::InvalidData),
+            };
+            let notice = Some(notice.trim()).filter(|n| !n.is_empty());
+            crate::set_maintenance(ms_data, enabled, notice.map(str::to_string));
+            Ok(String::new())
+        }
+        _ => Ok(format!(
+            "Unknown command: {command}. Type \"help\" for help"
+        )),
+    }
+}
@@ -0,0 +1,189 @@
+//! Optional web dashboard with the server status.
+//!
+//! Ship and block occupancy and queues of full ships are public. If the admin API token is set
+//! then recent logins and open support tickets are also available to requests with
+//! `Authorization: Bearer <token>` header.
+use crate::{admin::constant_time_eq, Error, MSData};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    routing::get,
+    Json, Router,
+};
+use data_structs::master_ship::BlockStatus;
+use pso2packetlib::protocol::login::ShipStatus;
+use serde::Serialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::TcpListener;
+
+const RECENT_LOGINS: u32 = 50;
+
+#[derive(Clone)]
+struct DashboardState {
+    ms_data: Arc<MSData>,
+    /// Token for the admin mode. If `None` then the dashboard is read-only.
+    token: Option<Arc<str>>,
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    online_players: u32,
+    /// Whether recent logins are available.
+    admin_mode: bool,
+    ships: Vec<ShipEntry>,
+}
+
+#[derive(Serialize)]
+struct ShipEntry {
+    id: u32,
+    name: String,
+    players: u32,
+    max_players: u32,
+    status: ShipStatus,
+    blocks: Vec<BlockStatus>,
+    /// Number of clients waiting for the ship to free up.
+    queue_length: usize,
+    /// Estimated wait in seconds for a client joining the queue now.
+    estimated_wait: Option<u32>,
+}
+
+/// Queue state of the requesting client.
+#[derive(Serialize)]
+struct QueueEntry {
+    ship_id: u32,
+    status: ShipStatus,
+    queue_length: usize,
+    /// 1-based queue position of the client.
+    position: usize,
+    /// Estimated wait in seconds.
+    estimated_wait: Option<u32>,
+}
+
+pub(crate) async fn start_dashboard(
+    ms_data: Arc<MSData>,
+    addr: &str,
+    token: Option<String>,
+) -> Result<(), Error> {
+    let state = DashboardState {
+        ms_data,
+        token: token.filter(|t| !t.is_empty()).map(Into::into),
+    };
+    if state.token.is_none() {
+        log::info!("Admin API token is not set, dashboard is read-only");
+    }
+    let router = Router::new()
+        .route("/", get(|| async { Html(include_str!("dashboard.html")) }))
+        .route("/api/status", get(get_status))
+        .route("/api/queue", get(get_queue))
+        .route("/api/logins", get(get_logins))
+        .route("/api/tickets", get(get_tickets))
+        .with_state(state);
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Dashboard listening on {addr}");
+    tokio::spawn(async move {
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, service).await {
+            log::error!("Dashboard failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn get_status(State(state): State<DashboardState>) -> impl IntoResponse {
+    let ships: Vec<_> = state
+        .ms_data
+        .ships
+        .read()
+        .iter()
+        .map(|s| ShipEntry {
+            id: s.id,
+            name: s.name.clone(),
+            players: s.players,
+            max_players: s.max_players,
+            status: s.status,
+            blocks: s.blocks.clone(),
+            queue_length: s.queue.waiting.len(),
+            estimated_wait: s.queue.estimated_wait(s.queue.waiting.len() + 1),
+        })
+        .collect();
+    Json(StatusResponse {
+        online_players: ships.iter().map(|s| s.players).sum(),
+        admin_mode: state.token.is_some(),
+        ships,
+    })
+}
+
+/// Returns queue positions of the requesting client on full ships.
+async fn get_queue(
+    State(state): State<DashboardState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped(),
+    };
+    let entries: Vec<_> = state
+        .ms_data
+        .ships
+        .read()
+        .iter()
+        .filter_map(|s| {
+            let position = s.queue.position(ip?)?;
+            Some(QueueEntry {
+                ship_id: s.id,
+                status: s.status,
+                queue_length: s.queue.waiting.len(),
+                position,
+                estimated_wait: s.queue.estimated_wait(position),
+            })
+        })
+        .collect();
+    Json(entries)
+}
+
+async fn get_logins(State(state): State<DashboardState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    match state.ms_data.sql.get_recent_logins(RECENT_LOGINS).await {
+        Ok(logins) => Json(logins).into_response(),
+        Err(e) => {
+            log::warn!("Dashboard error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_tickets(State(state): State<DashboardState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    match state.ms_data.sql.get_open_tickets().await {
+        Ok(tickets) => Json(tickets).into_response(),
+        Err(e) => {
+            log::warn!("Dashboard error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Checks the admin token of the request.
+fn authorize(state: &DashboardState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(token) = &state.token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()));
+    if !authorized {
+        log::warn!("Unauthorized dashboard request");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Self-test of the master ship configuration (`--doctor`).
+use crate::{read_key, Error, Settings};
+use data_structs::doctor::{self, Report};
+use sqlx::{migrate::MigrateDatabase, Row};
+
+pub(crate) async fn run(settings: &Settings) -> Result<(), Error> {
+    let mut report = Report::default();
+    report.add("Database", check_db(&settings.db_name).await);
+    report.add(
+        "Signing key",
+        read_key(&settings.signing_key_file).map(|key| match key {
+            Some(_) => String::new(),
+            None => format!("{} will be created", settings.signing_key_file),
+        }),
+    );
+
+    let mut addrs = vec![
+        (
+            "Ship connection port".to_string(),
+            "0.0.0.0:15000".to_string(),
+        ),
+        ("Key port".to_string(), "0.0.0.0:11000".to_string()),
+    ];
+    let ports = &settings.ports;
+    for i in 0..ports.ship_count {
+        let id = ports.first_ship_id + i;
+        for (name, base) in [
+            ("query", ports.query_port),
+            ("Vita query", ports.vita_query_port),
+            ("balance", ports.balance_port),
+            ("Vita balance", ports.vita_balance_port),
+        ] {
+            match ports.slot_port(base, i) {
+                Ok(port) => {
+                    addrs.push((format!("Ship {id} {name} port"), format!("0.0.0.0:{port}")))
+                }
+                Err(e) => report.add(format!("Ship {id} {name} port"), Err(e)),
+            }
+        }
+    }
+    for (name, addr) in [
+        ("Admin API address", &settings.admin_api_address),
+        ("Dashboard address", &settings.dashboard_address),
+        ("Metrics address", &settings.metrics_address),
+    ] {
+        if let Some(addr) = addr {
+            addrs.push((name.to_string(), addr.clone()));
+        }
+    }
+    for (name, addr) in addrs {
+        report.add(name, doctor::check_port(addr.as_str()).await);
+    }
+
+    match &settings.data_path {
+        Some(path) => report.add("Server data", doctor::check_data_file(path).await),
+        None => report.add("Server data", Ok::<_, Error>("not set".to_string())),
+    }
+    if let Some(primary) = &settings.replication.primary {
+        report.add(
+            "Primary master ship",
+            doctor::check_reachable(primary).await,
+        );
+    }
+
+    report.print();
+    match report.failures() {
+        0 => Ok(()),
+        n => Err(Error::ChecksFailed(n)),
+    }
+}
+
+async fn check_db(path: &str) -> Result<String, Error> {
+    if !sqlx::Sqlite::database_exists(path).await.unwrap_or(false) {
+        return Ok(format!("{path} will be created"));
+    }
+    let conn = sqlx::SqlitePool::connect(path).await?;
+    let row = sqlx::query("select count(*) as Count from Users")
+        .fetch_one(&conn)
+        .await?;
+    let count: i64 = row.try_get("Count")?;
+    Ok(format!("{count} accounts"))
+}
@@ -1,29 +1,39 @@
 #![deny(clippy::undocumented_unsafe_blocks)]
 #![warn(clippy::future_not_send)]
 #![allow(clippy::await_holding_lock)]
+pub mod admin;
+mod backup;
+mod console;
+mod dashboard;
+mod doctor;
+pub mod mail;
+mod metrics;
+pub mod replication;
 pub mod sql;
+mod totp;
 use clap::Parser;
 use data_structs::{
+    balance,
     master_ship::{
-        start_discovery_loop, MasterShipAction, MasterShipComm, RegisterShipResult,
-        ServerDataResult, SetNicknameResult, ShipConnection, ShipInfo, ShipLoginResult,
-        UserLoginResult,
+        gm_level, start_discovery_loop, MasterShipAction, MasterShipComm, RegisterShipResult,
+        ServerDataResult, SetNicknameResult, ShipConnection, ShipInfo, ShipKey, ShipLoginResult,
+        SupportTicket, UserCreds, UserLoginResult, RESET_PREFIX,
     },
-    SerDeFile, ServerData,
+    secrets, SerDeFile, ServerData,
 };
-use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+use metrics::METRICS;
 use p256::ecdsa::SigningKey;
 use parking_lot::{RwLock, RwLockWriteGuard};
-use pso2packetlib::{
-    protocol::{login, Packet, PacketType},
-    Connection, PrivateKey, PublicKey,
-};
+use pso2packetlib::protocol::{login, Packet};
 use rand_core::{OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::{
     io,
     net::{IpAddr, Ipv4Addr},
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::{
@@ -35,11 +45,80 @@ use tokio::{
 #[serde(default)]
 struct Settings {
     db_name: String,
+    /// Location of the ship connection signing key. `PHANTASY_MASTER_KEY` environment variable
+    /// with the hex encoded key overrides it.
+    signing_key_file: String,
     registration_enabled: bool,
     log_dir: String,
     file_log_level: log::LevelFilter,
     console_log_level: log::LevelFilter,
     data_path: Option<String>,
+    admin_api_address: Option<String>,
+    admin_api_token: Option<String>,
+    /// Address of the web status dashboard.
+    dashboard_address: Option<String>,
+    /// Address of the Prometheus metrics endpoint.
+    metrics_address: Option<String>,
+    login_limits: sql::LoginLimits,
+    registration_limits: sql::RegistrationLimits,
+    /// Password reset email limits.
+    reset_limits: sql::ResetLimits,
+    /// Initial flags and GM level of new accounts.
+    new_accounts: sql::AccountDefaults,
+    /// URL that is asked to approve each account registration.
+    registration_hook: Option<String>,
+    smtp: mail::SmtpSettings,
+    replication: replication::ReplicationSettings,
+    /// Maximum size (in bytes) of the key-value store of an account.
+    account_values_quota: usize,
+    /// Minimum time (in seconds) between nickname changes of an account from the game.
+    nickname_cooldown: u64,
+    ports: PortSettings,
+    backup: backup::BackupSettings,
+    maintenance: MaintenanceSettings,
+    /// GM levels to set on startup as `username:level`. Only set from the command line.
+    #[serde(skip)]
+    set_gm: Vec<String>,
+    /// Run the self-test instead of starting the server. Only set from the command line.
+    #[serde(skip)]
+    doctor: bool,
+    /// Account to export as `username:file`. Only set from the command line.
+    #[serde(skip)]
+    export_account: Option<String>,
+    /// Account to import as `username:file`. Only set from the command line.
+    #[serde(skip)]
+    import_account: Option<String>,
+}
+
+/// Ports of the ship list query and block balance listeners. Each ship slot gets its own set of
+/// ports starting from the base ones.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct PortSettings {
+    /// Number of ship slots.
+    ship_count: u32,
+    /// Id of the ship in the first slot.
+    first_ship_id: u32,
+    /// Difference between ports of neighbouring slots.
+    step: u16,
+    query_port: u16,
+    vita_query_port: u16,
+    balance_port: u16,
+    vita_balance_port: u16,
+}
+
+/// Maintenance mode and client version gating.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+struct MaintenanceSettings {
+    /// If set then ships are listed as offline and only GM accounts can log in. Already connected
+    /// players are not disconnected.
+    enabled: bool,
+    /// Notice shown to players that try to log in during maintenance.
+    message: String,
+    /// Minimum version of PC clients (e.g. "6.1201.3"). Empty - any version is allowed. Clients
+    /// that don't report a version are always allowed.
+    min_client_version: String,
 }
 
 #[derive(Parser, Debug)]
@@ -69,6 +148,18 @@ struct Args {
     /// Location of complied server data file
     #[arg(short, long)]
     data_path: Option<String>,
+    /// Set GM level of the account on startup (e.g. `--set-gm admin:3`). Can be repeated
+    #[arg(long, value_name = "USERNAME:LEVEL")]
+    set_gm: Vec<String>,
+    /// Check the configuration, database, keys, ports and server data, then exit
+    #[arg(long, default_value_t = false)]
+    doctor: bool,
+    /// Export storage and settings of the account to a JSON file, then exit
+    #[arg(long, value_name = "USERNAME:FILE")]
+    export_account: Option<String>,
+    /// Import storage and settings of the account from a JSON file, then exit
+    #[arg(long, value_name = "USERNAME:FILE")]
+    import_account: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -80,7 +171,36 @@ struct Keys {
 struct MSData {
     ships: RwLock<Vec<ShipInfo>>,
     sql: sql::Sql,
-    srv_data: Option<ServerData>,
+    srv_data: RwLock<Option<Arc<ServerData>>>,
+    data_path: Option<String>,
+    login_limits: sql::LoginLimits,
+    registration_limits: sql::RegistrationLimits,
+    reset_limits: sql::ResetLimits,
+    new_accounts: sql::AccountDefaults,
+    registration_hook: Option<String>,
+    http_client: reqwest::Client,
+    mailer: Option<mail::Mailer>,
+    account_values_quota: usize,
+    nickname_cooldown: Duration,
+    /// Key of standby master ships (empty - replication is disabled).
+    replication_psk: String,
+    /// Term of this primary master ship.
+    term: u64,
+    /// Set when a master ship with a higher term was seen. Fenced master ship doesn't accept
+    /// ships.
+    fenced: AtomicBool,
+    /// Broadcast messages with the target ship ids.
+    broadcasts: tokio::sync::broadcast::Sender<(Vec<u32>, String)>,
+    /// Ids of ships that should be disconnected.
+    kicks: tokio::sync::broadcast::Sender<u32>,
+    /// Names of ships whose keys were revoked.
+    revocations: tokio::sync::broadcast::Sender<String>,
+    /// Opened and changed support tickets.
+    tickets: tokio::sync::broadcast::Sender<SupportTicket>,
+    /// Merged accounts as (from, into) pairs.
+    merges: tokio::sync::broadcast::Sender<(u32, u32)>,
+    backup: backup::BackupSettings,
+    maintenance: RwLock<MaintenanceSettings>,
 }
 
 macro_rules! args_to_settings {
@@ -115,6 +235,10 @@ impl Settings {
         args_to_settings!(args.file_log_level => settings.file_log_level);
         args_to_settings!(args.console_log_level => settings.console_log_level);
         settings.data_path = args.data_path.or(settings.data_path);
+        settings.set_gm = args.set_gm;
+        settings.doctor = args.doctor;
+        settings.export_account = args.export_account;
+        settings.import_account = args.import_account;
         Ok(settings)
     }
 }
@@ -123,15 +247,71 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             db_name: String::from("master_ship.db"),
+            signing_key_file: String::from("master_key.bin"),
             registration_enabled: false,
             log_dir: String::from("logs"),
             file_log_level: log::LevelFilter::Info,
             console_log_level: log::LevelFilter::Debug,
             data_path: None,
+            admin_api_address: None,
+            admin_api_token: None,
+            dashboard_address: None,
+            metrics_address: None,
+            login_limits: Default::default(),
+            registration_limits: Default::default(),
+            reset_limits: Default::default(),
+            new_accounts: Default::default(),
+            registration_hook: None,
+            smtp: Default::default(),
+            replication: Default::default(),
+            account_values_quota: 64 * 1024,
+            nickname_cooldown: 7 * 24 * 60 * 60,
+            ports: Default::default(),
+            backup: Default::default(),
+            maintenance: Default::default(),
+            set_gm: vec![],
+            doctor: false,
+            export_account: None,
+            import_account: None,
+        }
+    }
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            message: String::from("The server is under maintenance. Please try again later"),
+            min_client_version: String::new(),
         }
     }
 }
 
+impl Default for PortSettings {
+    fn default() -> Self {
+        Self {
+            ship_count: 10,
+            first_ship_id: 1,
+            step: 100,
+            query_port: 12199,
+            vita_query_port: 12194,
+            balance_port: 12100,
+            vita_balance_port: 12193,
+        }
+    }
+}
+
+impl PortSettings {
+    /// Returns the port of the `slot` for the `base` port.
+    fn slot_port(&self, base: u16, slot: u32) -> Result<u16, Error> {
+        u16::try_from(slot)
+            .ok()
+            .and_then(|s| s.checked_mul(self.step))
+            .and_then(|offset| base.checked_add(offset))
+            .ok_or(Error::InvalidPortRange)
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Invalid arguments")]
@@ -140,14 +320,42 @@ pub enum Error {
     InvalidAction,
     #[error("Unknown ship")]
     UnknownShip,
+    #[error("Ship key was revoked")]
+    RevokedShipKey,
+    #[error("Ship was blocked")]
+    BlockedShip,
+    #[error("Port range is out of bounds")]
+    InvalidPortRange,
+    #[error("Replication login was rejected")]
+    ReplicationDenied,
+    #[error("Master ship was replaced by a standby")]
+    Fenced,
+    #[error("GM level is not higher than the level of the target")]
+    GmLevelTooLow,
     #[error("Invalid password for user id {0}")]
     InvalidPassword(u32),
     #[error("No user")]
     NoUser,
+    #[error("User {} is banned", .0.user_id)]
+    Banned(sql::Ban),
+    #[error("Invalid email address")]
+    InvalidEmail,
+    #[error("Email sending is not configured")]
+    NoMailer,
+    #[error("Mail error: {0}")]
+    MailError(String),
+    #[error("Invalid or expired code")]
+    InvalidCode,
+    #[error("Account storage quota exceeded")]
+    QuotaExceeded,
+    #[error("Server data path is not set")]
+    NoDataPath,
     #[error("Unable to hash the password")]
     HashError,
-    #[error("Failed to get network interfaces: {0}")]
-    NetworkInterfacesError(#[from] network_interface::Error),
+    #[error("{0} self-test checks failed")]
+    ChecksFailed(usize),
+    #[error("{0} items of the merged account don't fit into the storage")]
+    MergeConflict(usize),
 
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
@@ -163,17 +371,18 @@ pub enum Error {
     RMPEncodeError(#[from] rmp_serde::encode::Error),
     #[error("MP Deserialization error: {0}")]
     RMPDecodeError(#[from] rmp_serde::decode::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
     #[error("UTF-8 error: {0}")]
     UTF8Error(#[from] std::str::Utf8Error),
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] reqwest::Error),
     #[error("Client connection error: {0}")]
     ConnError(#[from] pso2packetlib::connection::ConnectionError),
 }
 
-enum AddrType {
-    Loopback,
-    Local,
-    Global,
-}
+/// Environment variable with the hex encoded signing key.
+const SIGNING_KEY_ENV: &str = "PHANTASY_MASTER_KEY";
 
 static IS_RUNNING: AtomicBool = AtomicBool::new(true);
 
@@ -181,8 +390,66 @@ async fn load_data(path: &str) -> Result<ServerData, Error> {
     Ok(ServerData::load_from_mp_comp(path)?)
 }
 
+/// Reloads server data from the data path. Ships receive new data on their next request.
+async fn reload_server_data(ms_data: &MSData) -> Result<(), Error> {
+    let Some(path) = &ms_data.data_path else {
+        return Err(Error::NoDataPath);
+    };
+    let data = Arc::new(load_data(path).await?);
+    *async_write(&ms_data.srv_data).await = Some(data);
+    log::info!("Server data reloaded");
+    Ok(())
+}
+
+/// Applies GM levels passed on the command line as `username:level`.
+async fn provision_gm_levels(sql: &sql::Sql, entries: &[String]) {
+    for entry in entries {
+        let Some((username, level)) = entry
+            .rsplit_once(':')
+            .and_then(|(u, l)| Some((u, l.parse::<u8>().ok()?)))
+        else {
+            log::error!("Invalid GM level entry: {entry}");
+            continue;
+        };
+        let result = match sql.find_account(username).await {
+            Ok(account) => sql.set_gm_level(account.id, level).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => log::info!("Set GM level of {username} to {level}"),
+            Err(e) => log::error!("Failed to set GM level of {username}: {e}"),
+        }
+    }
+}
+
+/// Runs `--export-account` and `--import-account`.
+async fn transfer_account(settings: &Settings) -> Result<(), Error> {
+    let sql = sql::Sql::new(&settings.db_name, settings.registration_enabled).await?;
+    if let Some(entry) = &settings.export_account {
+        let (username, path) = entry.split_once(':').ok_or(Error::InvalidData)?;
+        let account = sql.find_account(username).await?;
+        let data = sql.export_account(account.id).await?;
+        tokio::fs::write(path, serde_json::to_vec_pretty(&data)?).await?;
+        println!("Exported {username} to {path}");
+    }
+    if let Some(entry) = &settings.import_account {
+        let (username, path) = entry.split_once(':').ok_or(Error::InvalidData)?;
+        let account = sql.find_account(username).await?;
+        let data = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+        sql.import_account(account.id, data).await?;
+        println!("Imported {username} from {path}");
+    }
+    Ok(())
+}
+
 pub async fn run() -> Result<(), Error> {
     let settings = Settings::load("master_ship.toml").await?;
+    if settings.doctor {
+        return doctor::run(&settings).await;
+    }
+    if settings.export_account.is_some() || settings.import_account.is_some() {
+        return transfer_account(&settings).await;
+    }
     // setup logging
     {
         use simplelog::*;
@@ -210,10 +477,18 @@ pub async fn run() -> Result<(), Error> {
     log::info!("Starting master ship...");
     tokio::spawn(ctrl_c_handler());
     let sql = sql::Sql::new(&settings.db_name, settings.registration_enabled).await?;
+    if let Some(primary) = &settings.replication.primary {
+        log::info!("Running as a standby of {primary}");
+        replication::follow(&sql, &settings.replication, primary).await?;
+        if !IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+    }
+    provision_gm_levels(&sql, &settings.set_gm).await;
     let servers = RwLock::new(vec![]);
-    let server_data = if let Some(path) = settings.data_path {
-        match load_data(&path).await {
-            Ok(d) => Some(d),
+    let server_data = if let Some(path) = &settings.data_path {
+        match load_data(path).await {
+            Ok(d) => Some(Arc::new(d)),
             Err(e) => {
                 log::warn!("Failed to load server data: {e}");
                 None
@@ -222,16 +497,59 @@ pub async fn run() -> Result<(), Error> {
     } else {
         None
     };
+    let mailer = mail::Mailer::start(settings.smtp)?;
+    if mailer.is_none() {
+        log::info!("SMTP server is not set, email verification and password reset are disabled");
+    }
     let ms_data = Arc::new(MSData {
-        sql,
         ships: servers,
-        srv_data: server_data,
+        srv_data: RwLock::new(server_data),
+        data_path: settings.data_path,
+        login_limits: settings.login_limits,
+        registration_limits: settings.registration_limits,
+        reset_limits: settings.reset_limits,
+        new_accounts: settings.new_accounts,
+        registration_hook: settings.registration_hook.filter(|u| !u.is_empty()),
+        http_client: reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?,
+        mailer,
+        account_values_quota: settings.account_values_quota,
+        nickname_cooldown: Duration::from_secs(settings.nickname_cooldown),
+        replication_psk: settings.replication.psk,
+        term: sql.get_term().await?,
+        fenced: AtomicBool::new(false),
+        sql,
+        broadcasts: tokio::sync::broadcast::channel(16).0,
+        kicks: tokio::sync::broadcast::channel(16).0,
+        revocations: tokio::sync::broadcast::channel(16).0,
+        tickets: tokio::sync::broadcast::channel(16).0,
+        merges: tokio::sync::broadcast::channel(16).0,
+        backup: settings.backup,
+        maintenance: RwLock::new(settings.maintenance),
     });
+    if let Some(addr) = settings.dashboard_address {
+        let token = settings.admin_api_token.clone();
+        dashboard::start_dashboard(ms_data.clone(), &addr, token).await?;
+    }
+    if let Some(addr) = settings.admin_api_address {
+        match settings.admin_api_token {
+            Some(token) if !token.is_empty() => {
+                admin::start_admin_api(ms_data.clone(), &addr, token).await?
+            }
+            _ => log::warn!("Admin API address is set, but no token is provided, not starting"),
+        }
+    }
+    if let Some(addr) = settings.metrics_address {
+        metrics::start_metrics_server(&addr).await?;
+    }
     start_discovery_loop(15000).await?;
     tokio::spawn(make_keys(ms_data.clone()));
-    make_query(ms_data.clone()).await?;
-    make_block_balance(ms_data.clone()).await?;
-    ship_receiver(ms_data).await?;
+    tokio::spawn(console::run_console(ms_data.clone()));
+    tokio::spawn(backup::backup_loop(ms_data.clone()));
+    make_query(ms_data.clone(), &settings.ports).await?;
+    make_block_balance(ms_data.clone(), &settings.ports).await?;
+    ship_receiver(ms_data, &settings.signing_key_file).await?;
 
     Ok(())
 }
@@ -242,17 +560,51 @@ pub async fn ctrl_c_handler() {
     IS_RUNNING.swap(false, std::sync::atomic::Ordering::Relaxed);
 }
 
-pub async fn load_key() -> SigningKey {
-    let mut data = tokio::fs::read("master_key.bin").await.unwrap_or_default();
-    data.resize_with(32, || OsRng.next_u32() as u8);
-    let _ = tokio::fs::write("master_key.bin", &data).await;
-    SigningKey::from_slice(&data).unwrap()
+/// Loads the signing key from the environment variable (hex encoded) or the key file. The file
+/// is created if it doesn't exist.
+pub fn load_key(path: &str) -> Result<SigningKey, Error> {
+    if let Some(key) = read_key(path)? {
+        return Ok(key);
+    }
+    log::warn!("Signing key doesn't exist, creating...");
+    let mut data = vec![0; 32];
+    OsRng.fill_bytes(&mut data);
+    secrets::write_file(path, &data)?;
+    SigningKey::from_slice(&data).map_err(|_| Error::InvalidData)
 }
 
-async fn ship_receiver(ms_data: Arc<MSData>) -> Result<(), Error> {
+/// Reads the signing key from the environment variable or the key file. Returns `None` if the
+/// key file doesn't exist.
+fn read_key(path: &str) -> Result<Option<SigningKey>, Error> {
+    if let Some(hex) = secrets::from_env(SIGNING_KEY_ENV) {
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(Error::InvalidData)?;
+        return SigningKey::from_slice(&data)
+            .map(Some)
+            .map_err(|_| Error::InvalidData);
+    }
+    let data = match secrets::read_file(path) {
+        Ok(data) => data,
+        Err(data_structs::Error::IOError(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    SigningKey::from_slice(&data)
+        .map(Some)
+        .map_err(|_| Error::InvalidData)
+}
+
+async fn ship_receiver(ms_data: Arc<MSData>, signing_key_file: &str) -> Result<(), Error> {
     let listener = TcpListener::bind(("0.0.0.0", 15000)).await?;
     log::info!("Loading signing key...");
-    let signing_key = load_key().await;
+    let signing_key = load_key(signing_key_file)?;
     // this is 65 bytes
     let hostkey = signing_key.verifying_key().to_sec1_bytes().to_vec();
     log::info!("Started master server");
@@ -283,25 +635,133 @@ async fn ship_receiver(ms_data: Arc<MSData>) -> Result<(), Error> {
 }
 
 async fn connection_handler(mut conn: ShipConnection, ms_data: Arc<MSData>) {
-    match ship_login(&mut conn, &ms_data).await {
-        Ok(_) => {}
+    let key = match ship_login(&mut conn, &ms_data).await {
+        Ok(Peer::Ship(key)) => key,
+        Ok(Peer::Standby) => return replication::serve_standby(conn, ms_data).await,
         Err(e) => {
             log::warn!("Login error: {e}");
             return;
         }
     };
+    let _connection = METRICS.track_ship_connection();
+    let mut broadcasts = ms_data.broadcasts.subscribe();
+    let mut kicks = ms_data.kicks.subscribe();
+    let mut revocations = ms_data.revocations.subscribe();
+    let mut tickets = ms_data.tickets.subscribe();
+    let mut merges = ms_data.merges.subscribe();
+    let mut ship_id = None;
     loop {
-        match conn.read_for(Duration::from_secs(1)).await {
-            Ok(d) => match run_action(&ms_data, d).await {
-                Ok(a) => match conn.write(a).await {
+        if ms_data.fenced.load(Ordering::Relaxed) {
+            log::info!("Disconnecting ship {ship_id:?} from the fenced master ship");
+            return;
+        }
+        let result = tokio::select! {
+            result = conn.read_for(Duration::from_secs(1)) => result,
+            Ok((ships, message)) = broadcasts.recv() => {
+                let Some(id) = ship_id else { continue };
+                if !ships.is_empty() && !ships.contains(&id) {
+                    continue;
+                }
+                let comm = MasterShipComm {
+                    id: 0,
+                    action: MasterShipAction::Broadcast { ships, message },
+                };
+                if let Err(e) = conn.write(comm).await {
+                    log::warn!("Write error: {e}");
+                    return;
+                }
+                continue;
+            }
+            Ok(id) = kicks.recv() => {
+                if ship_id == Some(id) {
+                    log::info!("Ship {id} was kicked");
+                    // keep the ship from reconnecting until it's unblocked
+                    if let Err(e) = ms_data.sql.set_ship_blocked(&key.name, true).await {
+                        log::warn!("Failed to block ship \"{}\": {e}", key.name);
+                    }
+                    return;
+                }
+                continue;
+            }
+            Ok(name) = revocations.recv() => {
+                if name != key.name {
+                    continue;
+                }
+                // the ship could be using the new key of the same name
+                match ms_data.sql.get_ship_key(&key.psk).await {
+                    Ok(Some(k)) if !k.revoked => continue,
                     Ok(_) => {}
-                    Err(e) => {
+                    Err(e) => log::warn!("Failed to check key of ship \"{name}\": {e}"),
+                }
+                log::info!("Disconnecting ship {ship_id:?}, its key \"{name}\" was revoked");
+                if let Some(id) = ship_id {
+                    async_write(&ms_data.ships).await.retain(|s| s.id != id);
+                }
+                return;
+            }
+            Ok(ticket) = tickets.recv() => {
+                if ship_id.is_none() {
+                    continue;
+                }
+                let comm = MasterShipComm {
+                    id: 0,
+                    action: MasterShipAction::TicketUpdate(ticket),
+                };
+                if let Err(e) = conn.write(comm).await {
+                    log::warn!("Write error: {e}");
+                    return;
+                }
+                continue;
+            }
+            Ok((from, into)) = merges.recv() => {
+                if ship_id.is_none() {
+                    continue;
+                }
+                let comm = MasterShipComm {
+                    id: 0,
+                    action: MasterShipAction::AccountsMerged { from, into },
+                };
+                if let Err(e) = conn.write(comm).await {
+                    log::warn!("Write error: {e}");
+                    return;
+                }
+                continue;
+            }
+        };
+        match result {
+            Ok(d) => {
+                if let MasterShipAction::RegisterShip(ship) = &d.action {
+                    ship_id = Some(ship.id);
+                }
+                // these actions are available only locally (e.g. from the console)
+                if matches!(
+                    d.action,
+                    MasterShipAction::NewShipKey(_)
+                        | MasterShipAction::RevokeShipKey(_)
+                        | MasterShipAction::Backup
+                ) {
+                    log::warn!("Ship {ship_id:?} tried to use a local action");
+                    let response = MasterShipComm {
+                        id: d.id,
+                        action: MasterShipAction::Error(Error::InvalidAction.to_string()),
+                    };
+                    if let Err(e) = conn.write(response).await {
                         log::warn!("Write error: {e}");
                         return;
                     }
-                },
-                Err(e) => log::warn!("Action error: {e}"),
-            },
+                    continue;
+                }
+                match run_action(&ms_data, d, ship_id).await {
+                    Ok(a) => match conn.write(a).await {
+                        Ok(_) => {}
+                        Err(e) => {
+                            log::warn!("Write error: {e}");
+                            return;
+                        }
+                    },
+                    Err(e) => log::warn!("Action error: {e}"),
+                }
+            }
             Err(data_structs::Error::IOError(e))
                 if e.kind() == io::ErrorKind::ConnectionAborted =>
             {
@@ -323,42 +783,126 @@ async fn connection_handler(mut conn: ShipConnection, ms_data: Arc<MSData>) {
     }
 }
 
-async fn ship_login(conn: &mut ShipConnection, ms_data: &MSData) -> Result<(), Error> {
+/// Kind of the connected peer.
+enum Peer {
+    /// Parameter is the key the ship logged in with.
+    Ship(ShipKey),
+    Standby,
+}
+
+async fn ship_login(conn: &mut ShipConnection, ms_data: &MSData) -> Result<Peer, Error> {
     let action = conn.read_for(Duration::from_secs(10)).await?;
     let mut response = MasterShipComm {
         id: action.id,
         action: MasterShipAction::Ok,
     };
-    let MasterShipAction::ShipLogin(psk) = action.action else {
-        response.action = MasterShipAction::Error(String::from("Invalid action"));
+    let login = match &action.action {
+        MasterShipAction::ShipLogin(login) | MasterShipAction::ReplicationLogin(login) => login,
+        _ => {
+            response.action = MasterShipAction::Error(String::from("Invalid action"));
+            conn.write(response).await?;
+            return Err(Error::InvalidAction);
+        }
+    };
+    if login.term > ms_data.term {
+        log::error!(
+            "Master ship with term {} has taken over (this master ship has term {}), refusing \
+            connections. Run this master ship as a standby to rejoin",
+            login.term,
+            ms_data.term
+        );
+        ms_data.fenced.store(true, Ordering::Relaxed);
+    }
+    if ms_data.fenced.load(Ordering::Relaxed) {
+        response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::Fenced);
         conn.write(response).await?;
-        return Err(Error::InvalidAction);
+        return Err(Error::Fenced);
+    }
+    let psk = match action.action {
+        MasterShipAction::ShipLogin(login) => login.psk,
+        MasterShipAction::ReplicationLogin(login) => {
+            if ms_data.replication_psk.is_empty()
+                || !admin::constant_time_eq(&login.psk, ms_data.replication_psk.as_bytes())
+            {
+                response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::UnknownShip);
+                conn.write(response).await?;
+                return Err(Error::ReplicationDenied);
+            }
+            response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::Ok(ms_data.term));
+            conn.write(response).await?;
+            return Ok(Peer::Standby);
+        }
+        _ => {
+            response.action = MasterShipAction::Error(String::from("Invalid action"));
+            conn.write(response).await?;
+            return Err(Error::InvalidAction);
+        }
     };
 
-    let psk = psk.psk;
-    match ms_data.sql.get_ship_data(&psk).await? {
-        true => {}
-        false => {
+    let key = match ms_data.sql.get_ship_key(&psk).await? {
+        Some(key) if key.revoked => {
+            log::warn!("Ship tried to login with a revoked key of \"{}\"", key.name);
+            response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::Revoked);
+            conn.write(response).await?;
+            return Err(Error::RevokedShipKey);
+        }
+        Some(key) if key.blocked => {
+            log::warn!("Blocked ship \"{}\" tried to login", key.name);
+            response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::Blocked);
+            conn.write(response).await?;
+            return Err(Error::BlockedShip);
+        }
+        Some(key) => key,
+        None => {
             if !ms_data.sql.registration_enabled() {
                 response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::UnknownShip);
                 conn.write(response).await?;
                 return Err(Error::UnknownShip);
             }
-            ms_data.sql.put_ship_data(&psk).await?;
+            let key = ShipKey {
+                name: format!("auto_{:08x}", OsRng.next_u32()),
+                psk,
+                revoked: false,
+                blocked: false,
+            };
+            log::info!("Registered new ship key \"{}\"", key.name);
+            ms_data.sql.put_ship_key(&key).await?;
+            key
         }
     };
 
-    response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::Ok);
+    response.action = MasterShipAction::ShipLoginResult(ShipLoginResult::Ok(ms_data.term));
     conn.write(response).await?;
 
-    Ok(())
+    Ok(Peer::Ship(key))
 }
 
-async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterShipComm, Error> {
+async fn run_action(
+    ms_data: &MSData,
+    action: MasterShipComm,
+    ship_id: Option<u32>,
+) -> Result<MasterShipComm, Error> {
+    let _timer = METRICS.time_action((&action.action).into());
+    let is_login = matches!(
+        action.action,
+        MasterShipAction::UserLogin(_) | MasterShipAction::UserLoginVita(_)
+    );
+    let is_registration = matches!(
+        action.action,
+        MasterShipAction::UserRegister(_) | MasterShipAction::UserRegisterVita(_)
+    );
     let mut response = MasterShipComm {
         id: action.id,
         action: MasterShipAction::Ok,
     };
+    let refusal = maintenance_refusal(&ms_data.maintenance.read(), &action.action);
+    if let Some(notice) = refusal {
+        response.action = MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice));
+        if is_registration {
+            METRICS.registration(login_result_label(&response.action));
+        }
+        return Ok(response);
+    }
     let sql = &ms_data.sql;
     match action.action {
         MasterShipAction::RegisterShip(ship) => {
@@ -382,9 +926,21 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
         }
         MasterShipAction::Ok => {}
         MasterShipAction::Error(_) => {}
-        MasterShipAction::UserLogin(data) => {
+        MasterShipAction::UserLogin(data) => match sega_login(ms_data, data).await {
+            Ok(r) => response.action = MasterShipAction::UserLoginResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::UserRegister(data) => match register_sega_user(ms_data, data).await {
+            Ok(r) => response.action = MasterShipAction::UserLoginResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::UserLoginVita(data) => match psn_login(ms_data, data).await {
+            Ok(r) => response.action = MasterShipAction::UserLoginResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::UserRegisterVita(data) => {
             match sql
-                .get_sega_user(&data.username, &data.password, data.ip)
+                .create_psn_user(&data.username, &ms_data.new_accounts)
                 .await
             {
                 Ok(d) => {
@@ -392,59 +948,7 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
                         id: d.id,
                         nickname: d.nickname,
                         accountflags: d.account_flags,
-                        isgm: d.isgm,
-                        last_uuid: d.last_uuid,
-                    })
-                }
-                Err(ref e) if matches!(e, Error::NoUser) => {
-                    response.action = MasterShipAction::UserLoginResult(UserLoginResult::NotFound)
-                }
-                Err(Error::InvalidPassword(id)) => {
-                    response.action =
-                        MasterShipAction::UserLoginResult(UserLoginResult::InvalidPassword(id))
-                }
-                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
-            }
-        }
-        MasterShipAction::UserRegister(data) => {
-            match sql.create_sega_user(&data.username, &data.password).await {
-                Ok(d) => {
-                    response.action = MasterShipAction::UserLoginResult(UserLoginResult::Success {
-                        id: d.id,
-                        nickname: d.nickname,
-                        accountflags: d.account_flags,
-                        isgm: d.isgm,
-                        last_uuid: d.last_uuid,
-                    })
-                }
-                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
-            }
-        }
-        MasterShipAction::UserLoginVita(data) => {
-            match sql.get_psn_user(&data.username, data.ip).await {
-                Ok(d) => {
-                    response.action = MasterShipAction::UserLoginResult(UserLoginResult::Success {
-                        id: d.id,
-                        nickname: d.nickname,
-                        accountflags: d.account_flags,
-                        isgm: d.isgm,
-                        last_uuid: d.last_uuid,
-                    })
-                }
-                Err(ref e) if matches!(e, Error::NoUser) => {
-                    response.action = MasterShipAction::UserLoginResult(UserLoginResult::NotFound)
-                }
-                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
-            }
-        }
-        MasterShipAction::UserRegisterVita(data) => {
-            match sql.create_psn_user(&data.username).await {
-                Ok(d) => {
-                    response.action = MasterShipAction::UserLoginResult(UserLoginResult::Success {
-                        id: d.id,
-                        nickname: d.nickname,
-                        accountflags: d.account_flags,
-                        isgm: d.isgm,
+                        gm_level: d.gm_level,
                         last_uuid: d.last_uuid,
                     })
                 }
@@ -453,17 +957,21 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
         }
         MasterShipAction::UserLoginResult(_) => {}
         MasterShipAction::GetStorage(player_id) => match sql.get_account_storage(player_id).await {
-            Ok(d) => response.action = MasterShipAction::GetStorageResult(d),
+            Ok((storage, version)) => {
+                response.action = MasterShipAction::GetStorageResult { storage, version }
+            }
             Err(e) => response.action = MasterShipAction::Error(e.to_string()),
         },
-        MasterShipAction::GetStorageResult(_) => {}
-        MasterShipAction::PutStorage { id, storage } => {
-            match sql.put_account_storage(id, storage).await {
-                Ok(_) => {}
-                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
-            }
-        }
-        MasterShipAction::GetLogins(id) => match sql.get_logins(id).await {
+        MasterShipAction::GetStorageResult { .. } => {}
+        MasterShipAction::PutStorage {
+            id,
+            storage,
+            version,
+        } => match sql.put_account_storage(id, storage, version).await {
+            Ok(r) => response.action = MasterShipAction::PutStorageResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::GetLogins { id, limit } => match sql.get_logins(id, limit).await {
             Ok(d) => response.action = MasterShipAction::GetLoginsResult(d),
             Err(e) => response.action = MasterShipAction::Error(e.to_string()),
         },
@@ -479,27 +987,44 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
                 Err(e) => response.action = MasterShipAction::Error(e.to_string()),
             }
         }
-        MasterShipAction::NewBlockChallenge(id) => match sql.new_challenge(id).await {
-            Ok(challenge) => response.action = MasterShipAction::BlockChallengeResult(challenge),
-            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
-        },
+        MasterShipAction::NewBlockChallenge(id) => {
+            let result = match ship_id {
+                Some(ship_id) => sql.new_challenge(id, ship_id).await,
+                None => Err(Error::UnknownShip),
+            };
+            match result {
+                Ok(challenge) => {
+                    response.action = MasterShipAction::BlockChallengeResult(challenge)
+                }
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
         MasterShipAction::BlockChallengeResult(_) => {}
         MasterShipAction::ChallengeLogin {
             challenge,
             player_id,
-        } => match sql.login_challenge(player_id, challenge).await {
+        } => match sql
+            .login_challenge(player_id, ship_id.unwrap_or_default(), challenge)
+            .await
+        {
             Ok(d) => {
                 response.action = MasterShipAction::UserLoginResult(UserLoginResult::Success {
                     id: d.id,
                     nickname: d.nickname,
                     accountflags: d.account_flags,
-                    isgm: d.isgm,
+                    gm_level: d.gm_level,
                     last_uuid: d.last_uuid,
                 })
             }
             Err(ref e) if matches!(e, Error::NoUser) => {
                 response.action = MasterShipAction::UserLoginResult(UserLoginResult::NotFound)
             }
+            Err(Error::Banned(ban)) => {
+                response.action = MasterShipAction::UserLoginResult(UserLoginResult::Banned {
+                    until: ban.until,
+                    reason: ban.reason,
+                })
+            }
             Err(e) => response.action = MasterShipAction::Error(e.to_string()),
         },
         MasterShipAction::GetUserInfo(id) => match sql.get_user_info(id).await {
@@ -521,6 +1046,13 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
             Ok(_) => response.action = MasterShipAction::Ok,
             Err(e) => response.action = MasterShipAction::Error(e.to_string()),
         },
+        MasterShipAction::SetGmLevel { id, level } => match sql.set_gm_level(id, level).await {
+            Ok(_) => {
+                log::info!("Ship {ship_id:?}: set GM level of user {id} to {level}");
+                response.action = MasterShipAction::Ok
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
         MasterShipAction::ShipLogin { .. } => {
             response.action = MasterShipAction::Error(Error::InvalidAction.to_string())
         }
@@ -544,9 +1076,10 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
             response.action = MasterShipAction::Ok;
         }
         MasterShipAction::ServerDataRequest => {
-            if let Some(data) = ms_data.srv_data.as_ref() {
+            let data = ms_data.srv_data.read().clone();
+            if let Some(data) = data {
                 response.action = MasterShipAction::ServerDataResponse(ServerDataResult::Ok(
-                    Box::new(data.clone()),
+                    Box::new(ServerData::clone(&data)),
                 ));
             } else {
                 response.action =
@@ -554,10 +1087,652 @@ async fn run_action(ms_data: &MSData, action: MasterShipComm) -> Result<MasterSh
             }
         }
         MasterShipAction::ServerDataResponse(_) => {}
+        MasterShipAction::SetEmail { id, email } => match set_email(ms_data, id, &email).await {
+            Ok(_) => response.action = MasterShipAction::Ok,
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::VerifyEmail { id, code } => match sql.verify_email(id, &code).await {
+            Ok(r) => response.action = MasterShipAction::VerifyEmailResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::VerifyEmailResult(_) => {}
+        MasterShipAction::NewTotpSecret(id) => match sql.new_totp_secret(id).await {
+            Ok(secret) => {
+                response.action = MasterShipAction::TotpSecretResult(totp::to_base32(&secret))
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::TotpSecretResult(_) => {}
+        MasterShipAction::EnableTotp { id, code } => match sql.enable_totp(id, &code).await {
+            Ok(r) => response.action = MasterShipAction::TotpResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::DisableTotp { id, code } => {
+            match sql.disable_totp(id, Some(&code)).await {
+                Ok(r) => response.action = MasterShipAction::TotpResult(r),
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::TotpResult(_) => {}
+        MasterShipAction::NewPasswordChallenge { id, password } => {
+            match password_challenge(ms_data, id, &password).await {
+                Ok(c) => response.action = MasterShipAction::PasswordChallengeResult(c),
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::PasswordChallengeResult(_) => {}
+        MasterShipAction::ChangePassword {
+            id,
+            challenge,
+            password,
+        } => match sql.change_password(id, &challenge, &password).await {
+            Ok(r) => {
+                if r {
+                    log::info!("Ship {ship_id:?}: user {id} changed the password");
+                }
+                response.action = MasterShipAction::PasswordChangeResult(r)
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::PasswordChangeResult(_) => {}
+        MasterShipAction::ChangeNickname { id, nickname } => {
+            match sql
+                .change_nickname(id, &nickname, ms_data.nickname_cooldown)
+                .await
+            {
+                Ok(r) => response.action = MasterShipAction::ChangeNicknameResult(r),
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::ChangeNicknameResult(_) => {}
+        MasterShipAction::PutStorageResult(_) => {}
+        MasterShipAction::ReplicationLogin(_) => {}
+        MasterShipAction::ReplicatedUser(_) => {}
+        MasterShipAction::ReplicatedShip(_) => {}
+        MasterShipAction::ReplicatedTable(_) => {}
+        MasterShipAction::ShipKeyResult(_) => {}
+        MasterShipAction::BackupResult(_) => {}
+        MasterShipAction::Backup => match backup::make_backup(ms_data).await {
+            Ok(path) => {
+                response.action = MasterShipAction::BackupResult(path.display().to_string())
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::NewShipKey(name) => match sql.new_ship_key(&name).await {
+            Ok(psk) => {
+                // error means that no ships are connected
+                let _ = ms_data.revocations.send(name);
+                response.action = MasterShipAction::ShipKeyResult(psk)
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::RevokeShipKey(name) => match sql.revoke_ship_keys(&name).await {
+            Ok(_) => {
+                // error means that no ships are connected
+                let _ = ms_data.revocations.send(name);
+                response.action = MasterShipAction::Ok
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::Broadcast { ships, message } => broadcast(ms_data, ships, message),
+        MasterShipAction::ShipStatusUpdate {
+            players,
+            max_players,
+            status,
+            blocks,
+            errors,
+            objects,
+            disconnects,
+            traffic,
+            queue,
+        } => {
+            let mut lock = async_write(&ms_data.ships).await;
+            match lock.iter_mut().find(|s| Some(s.id) == ship_id) {
+                Some(ship) => {
+                    ship.players = players;
+                    ship.max_players = max_players;
+                    ship.status = status;
+                    ship.blocks = blocks;
+                    ship.queue = queue;
+                    METRICS.ship_errors(ship.id, errors);
+                    METRICS.ship_objects(ship.id, objects);
+                    METRICS.ship_disconnects(ship.id, disconnects);
+                    METRICS.ship_traffic(ship.id, traffic);
+                }
+                None => response.action = MasterShipAction::Error(Error::UnknownShip.to_string()),
+            }
+        }
+        MasterShipAction::ShipStatsReport(stats) => {
+            let result = match ship_id {
+                Some(ship_id) => sql.add_ship_stats(ship_id, &stats).await,
+                None => Err(Error::UnknownShip),
+            };
+            if let Err(e) = result {
+                response.action = MasterShipAction::Error(e.to_string());
+            }
+        }
+        MasterShipAction::NewLinkCode(id) => match sql.new_link_code(id).await {
+            Ok(code) => response.action = MasterShipAction::LinkCodeResult(code),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::LinkCodeResult(_) => {}
+        MasterShipAction::GetAccountValue { id, namespace, key } => {
+            match sql.get_account_value(id, &namespace, &key).await {
+                Ok(v) => response.action = MasterShipAction::AccountValue(v),
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::AccountValue(_) => {}
+        MasterShipAction::GetAccountMerges => match sql.get_account_merges().await {
+            Ok(merges) => response.action = MasterShipAction::AccountMerges(merges),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::AccountMerges(_) => {}
+        MasterShipAction::AccountsMerged { .. } => {}
+        MasterShipAction::ReserveUUIDs { id, count } => match sql.reserve_uuids(id, count).await {
+            Ok(first) => response.action = MasterShipAction::UUIDsReserved(first),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::UUIDsReserved(_) => {}
+        MasterShipAction::AddAccountNote {
+            id,
+            author_id,
+            note,
+        } => match sql.add_account_note(id, author_id, &note).await {
+            Ok(_) => {
+                log::info!("Ship {ship_id:?}: user {author_id} added a note to user {id}");
+                response.action = MasterShipAction::Ok
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::GetAccountNotes(id) => match sql.get_account_notes(id).await {
+            Ok(notes) => response.action = MasterShipAction::AccountNotes(notes),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::AccountNotes(_) => {}
+        MasterShipAction::SetMute { id, mute } => match sql.set_mute(id, &mute).await {
+            Ok(_) => {
+                log::info!(
+                    "Ship {ship_id:?}: user {} muted user {id} until {}",
+                    mute.muted_by,
+                    mute.until.as_secs()
+                );
+                response.action = MasterShipAction::Ok
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::RemoveMute { id, gm_id } => match sql.remove_mute(id, gm_id).await {
+            Ok(removed) => {
+                if removed {
+                    log::info!("Ship {ship_id:?}: user {gm_id} unmuted user {id}");
+                }
+                response.action = MasterShipAction::MuteRemoved(removed)
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::MuteRemoved(_) => {}
+        MasterShipAction::GetMute(id) => match sql.get_mute(id).await {
+            Ok(mute) => response.action = MasterShipAction::MuteResult(mute),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::MuteResult(_) => {}
+        MasterShipAction::NewTicket {
+            user_id,
+            nickname,
+            message,
+        } => {
+            let Some(ship_id) = ship_id else {
+                response.action = MasterShipAction::Error(Error::UnknownShip.to_string());
+                return Ok(response);
+            };
+            match sql.new_ticket(user_id, &nickname, ship_id, &message).await {
+                Ok(ticket) => {
+                    log::info!("Ship {ship_id}: user {user_id} opened ticket {}", ticket.id);
+                    response.action = MasterShipAction::TicketCreated(ticket.id);
+                    let _ = ms_data.tickets.send(ticket);
+                }
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::TicketCreated(_) => {}
+        MasterShipAction::GetOpenTickets => match sql.get_open_tickets().await {
+            Ok(tickets) => response.action = MasterShipAction::Tickets(tickets),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::Tickets(_) => {}
+        MasterShipAction::ClaimTicket { id, gm_id } => match sql.claim_ticket(id, gm_id).await {
+            Ok(ticket) => {
+                log::info!("Ship {ship_id:?}: user {gm_id} claimed ticket {id}");
+                let _ = ms_data.tickets.send(ticket);
+            }
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::ResolveTicket {
+            id,
+            gm_id,
+            resolution,
+        } => match resolve_ticket(ms_data, id, gm_id, &resolution).await {
+            Ok(_) => log::info!("Ship {ship_id:?}: user {gm_id} resolved ticket {id}"),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+        MasterShipAction::TicketUpdate(_) => {}
+        MasterShipAction::PutAccountValue {
+            id,
+            namespace,
+            key,
+            value,
+        } => {
+            let quota = ms_data.account_values_quota;
+            match sql
+                .put_account_value(id, &namespace, &key, value.as_deref(), quota)
+                .await
+            {
+                Ok(_) => response.action = MasterShipAction::Ok,
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::RequestPasswordReset { username, ip } => {
+            match request_reset(ms_data, &username, ip).await {
+                // don't reveal whether the user exists
+                Ok(_) | Err(Error::NoUser) => response.action = MasterShipAction::Ok,
+                Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+            }
+        }
+        MasterShipAction::ConfirmReset {
+            username,
+            code,
+            password,
+        } => match confirm_reset(ms_data, &username, &code, &password).await {
+            Ok(r) => response.action = MasterShipAction::UserLoginResult(r),
+            Err(e) => response.action = MasterShipAction::Error(e.to_string()),
+        },
+    }
+    // block changes (challenge logins) of connected players are not restricted
+    if is_login {
+        restrict_login(&ms_data.maintenance.read(), &mut response.action);
+    }
+    if is_login {
+        METRICS.login(login_result_label(&response.action));
+    } else if is_registration {
+        METRICS.registration(login_result_label(&response.action));
     }
     Ok(response)
 }
 
+fn login_result_label(action: &MasterShipAction) -> &'static str {
+    match action {
+        MasterShipAction::UserLoginResult(result) => match result {
+            UserLoginResult::Success { .. } => "success",
+            UserLoginResult::InvalidPassword(_) => "invalid_password",
+            UserLoginResult::NotFound => "not_found",
+            UserLoginResult::Banned { .. } => "banned",
+            UserLoginResult::OtpRequired => "otp_required",
+            UserLoginResult::TooManyAttempts { .. } => "throttled",
+            UserLoginResult::RegistrationDenied(_) => "denied",
+            UserLoginResult::Maintenance(_) => "maintenance",
+            UserLoginResult::OutdatedClient { .. } => "outdated_client",
+        },
+        _ => "error",
+    }
+}
+
+async fn set_email(ms_data: &MSData, user_id: u32, email: &str) -> Result<(), Error> {
+    let Some(mailer) = &ms_data.mailer else {
+        return Err(Error::NoMailer);
+    };
+    if !mail::is_valid_address(email) {
+        return Err(Error::InvalidEmail);
+    }
+    let code = ms_data.sql.set_email(user_id, email).await?;
+    mailer.send(mail::Mail {
+        to: email.to_string(),
+        subject: String::from("Email verification"),
+        body: format!("Your email verification code is: {code}\nThe code is valid for 1 hour."),
+    })
+}
+
+/// Checks the current password of the user and creates a password change challenge. Failed
+/// attempts are throttled like logins.
+async fn password_challenge(
+    ms_data: &MSData,
+    user_id: u32,
+    password: &str,
+) -> Result<Option<String>, Error> {
+    let sql = &ms_data.sql;
+    let key = format!("reauth:{user_id}");
+    if sql.get_login_lockout(&key).await?.is_some() {
+        return Ok(None);
+    }
+    if !sql.check_password(user_id, password).await? {
+        sql.add_login_failure(&key, &ms_data.login_limits).await?;
+        return Ok(None);
+    }
+    sql.reset_login_failures(&key).await?;
+    Ok(Some(sql.new_password_challenge(user_id).await?))
+}
+
+async fn request_reset(ms_data: &MSData, username: &str, ip: Ipv4Addr) -> Result<(), Error> {
+    let Some(mailer) = &ms_data.mailer else {
+        return Err(Error::NoMailer);
+    };
+    if !ms_data
+        .sql
+        .add_reset_request(username, ip, &ms_data.reset_limits)
+        .await?
+    {
+        log::info!("Password reset request for {username} from {ip} is throttled");
+        return Ok(());
+    }
+    let Some((email, code)) = ms_data.sql.new_reset_code(username).await? else {
+        return Ok(());
+    };
+    log::info!("Sending password reset code for {username}");
+    mailer.send(mail::Mail {
+        to: email,
+        subject: String::from("Password reset"),
+        body: format!(
+            "Your password reset code is: {code}\nTo set a new password log in with \
+            \"{RESET_PREFIX}{code}:<new password>\" as the password.\nThe code can be used \
+            once and is valid for 1 hour."
+        ),
+    })
+}
+
+async fn sega_login(ms_data: &MSData, data: UserCreds) -> Result<UserLoginResult, Error> {
+    let sql = &ms_data.sql;
+    let user_key = format!("user:{}", data.username);
+    let ip_key = format!("ip:{}", data.ip);
+    let lockout = sql
+        .get_login_lockout(&user_key)
+        .await?
+        .max(sql.get_login_lockout(&ip_key).await?);
+    if let Some(retry_after) = lockout {
+        log::info!("Login attempt for {} is throttled", data.username);
+        return Ok(UserLoginResult::TooManyAttempts { retry_after });
+    }
+    let min_version = ms_data.maintenance.read().min_client_version.clone();
+    if !is_version_allowed(&min_version, &data.client_version) {
+        log::info!(
+            "Login attempt for {} with outdated client {:?}",
+            data.username,
+            data.client_version
+        );
+        return Ok(UserLoginResult::OutdatedClient { min_version });
+    }
+    match sql
+        .get_sega_user(&data.username, &data.password, data.ip, data.platform)
+        .await
+    {
+        Ok(d) => {
+            let keys = [user_key.as_str(), ip_key.as_str()];
+            if let Some(result) = check_otp(ms_data, d.id, data.otp.as_deref(), &keys).await? {
+                return Ok(result);
+            }
+            sql.reset_login_failures(&user_key).await?;
+            Ok(UserLoginResult::Success {
+                id: d.id,
+                nickname: d.nickname,
+                accountflags: d.account_flags,
+                gm_level: d.gm_level,
+                last_uuid: d.last_uuid,
+            })
+        }
+        Err(Error::NoUser) => Ok(UserLoginResult::NotFound),
+        Err(Error::InvalidPassword(id)) => {
+            sql.add_login_failure(&user_key, &ms_data.login_limits)
+                .await?;
+            sql.add_login_failure(&ip_key, &ms_data.login_limits)
+                .await?;
+            Ok(UserLoginResult::InvalidPassword(id))
+        }
+        Err(Error::Banned(ban)) => Ok(UserLoginResult::Banned {
+            until: ban.until,
+            reason: ban.reason,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+async fn psn_login(ms_data: &MSData, data: UserCreds) -> Result<UserLoginResult, Error> {
+    let sql = &ms_data.sql;
+    let user_key = format!("psn:{}", data.username);
+    let ip_key = format!("ip:{}", data.ip);
+    let lockout = sql
+        .get_login_lockout(&user_key)
+        .await?
+        .max(sql.get_login_lockout(&ip_key).await?);
+    if let Some(retry_after) = lockout {
+        log::info!("Login attempt for {} is throttled", data.username);
+        return Ok(UserLoginResult::TooManyAttempts { retry_after });
+    }
+    match sql
+        .get_psn_user(&data.username, data.ip, data.platform)
+        .await
+    {
+        Ok(d) => {
+            let keys = [user_key.as_str(), ip_key.as_str()];
+            if let Some(result) = check_otp(ms_data, d.id, data.otp.as_deref(), &keys).await? {
+                return Ok(result);
+            }
+            sql.reset_login_failures(&user_key).await?;
+            Ok(UserLoginResult::Success {
+                id: d.id,
+                nickname: d.nickname,
+                accountflags: d.account_flags,
+                gm_level: d.gm_level,
+                last_uuid: d.last_uuid,
+            })
+        }
+        Err(Error::NoUser) => Ok(UserLoginResult::NotFound),
+        Err(Error::Banned(ban)) => Ok(UserLoginResult::Banned {
+            until: ban.until,
+            reason: ban.reason,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Checks the one-time code of users with 2FA enabled. Returns the login result if the login
+/// can't proceed. Wrong codes count as failures of the throttling keys.
+async fn check_otp(
+    ms_data: &MSData,
+    user_id: u32,
+    otp: Option<&str>,
+    keys: &[&str],
+) -> Result<Option<UserLoginResult>, Error> {
+    let sql = &ms_data.sql;
+    if sql.get_totp_secret(user_id).await?.is_none() {
+        return Ok(None);
+    }
+    let Some(code) = otp else {
+        return Ok(Some(UserLoginResult::OtpRequired));
+    };
+    if sql.verify_totp(user_id, code).await? {
+        return Ok(None);
+    }
+    for key in keys {
+        sql.add_login_failure(key, &ms_data.login_limits).await?;
+    }
+    Ok(Some(UserLoginResult::InvalidPassword(user_id)))
+}
+
+async fn register_sega_user(ms_data: &MSData, data: UserCreds) -> Result<UserLoginResult, Error> {
+    let sql = &ms_data.sql;
+    let limits = &ms_data.registration_limits;
+    if let Some(retry_after) = sql.get_registration_lockout(data.ip, limits).await? {
+        log::info!("Registration from {} is throttled", data.ip);
+        return Ok(UserLoginResult::RegistrationDenied(format!(
+            "Too many accounts were registered from this address. Try again in {} second(s)",
+            retry_after.as_secs()
+        )));
+    }
+    if let Some(url) = &ms_data.registration_hook {
+        if !verify_registration(&ms_data.http_client, url, &data).await {
+            log::info!("Registration of {} was rejected by the hook", data.username);
+            return Ok(UserLoginResult::RegistrationDenied(String::from(
+                "Registration was not verified",
+            )));
+        }
+    }
+    let user = sql
+        .create_sega_user(&data.username, &data.password, &ms_data.new_accounts)
+        .await?;
+    sql.add_registration(data.ip, limits).await?;
+    Ok(UserLoginResult::Success {
+        id: user.id,
+        nickname: user.nickname,
+        accountflags: user.account_flags,
+        gm_level: user.gm_level,
+        last_uuid: user.last_uuid,
+    })
+}
+
+/// Asks the registration hook to approve the registration. Any non-success response or request
+/// failure rejects it.
+async fn verify_registration(client: &reqwest::Client, url: &str, data: &UserCreds) -> bool {
+    let body = serde_json::json!({
+        "username": data.username,
+        "ip": data.ip,
+    });
+    match client.post(url).json(&body).send().await {
+        Ok(r) => r.status().is_success(),
+        Err(e) => {
+            log::warn!("Registration hook request failed: {e}");
+            false
+        }
+    }
+}
+
+/// Replaces logins of non-GM accounts with the maintenance notice. Unknown accounts also get
+/// the notice, so no accounts are registered during maintenance.
+fn restrict_login(maintenance: &MaintenanceSettings, action: &mut MasterShipAction) {
+    if !maintenance.enabled {
+        return;
+    }
+    let MasterShipAction::UserLoginResult(result) = action else {
+        return;
+    };
+    match result {
+        UserLoginResult::Success {
+            gm_level: level, ..
+        } if *level == gm_level::PLAYER => {}
+        UserLoginResult::NotFound => {}
+        _ => return,
+    }
+    *result = UserLoginResult::Maintenance(maintenance.message.clone());
+}
+
+/// Returns the maintenance notice if the action changes accounts and has to be refused before it
+/// runs. Registrations and password resets are refused for everyone, logins are checked by
+/// [`restrict_login`] once the account is known.
+fn maintenance_refusal(
+    maintenance: &MaintenanceSettings,
+    action: &MasterShipAction,
+) -> Option<String> {
+    let changes_account = matches!(
+        action,
+        MasterShipAction::UserRegister(_)
+            | MasterShipAction::UserRegisterVita(_)
+            | MasterShipAction::ConfirmReset { .. }
+    );
+    (maintenance.enabled && changes_account).then(|| maintenance.message.clone())
+}
+
+/// Enables or disables the maintenance mode. If `message` is set then it replaces the notice.
+fn set_maintenance(ms_data: &MSData, enabled: bool, message: Option<String>) {
+    let mut maintenance = ms_data.maintenance.write();
+    maintenance.enabled = enabled;
+    if let Some(message) = message {
+        maintenance.message = message;
+    }
+    log::info!(
+        "Maintenance mode is {}",
+        if enabled { "enabled" } else { "disabled" }
+    );
+}
+
+/// Checks that the client version is not older than the minimum one. Versions are compared by
+/// their numeric components. Clients that don't report a version are allowed.
+fn is_version_allowed(min_version: &str, version: &str) -> bool {
+    if min_version.is_empty() || version.is_empty() {
+        return true;
+    }
+    let parse = |v: &str| -> Vec<u64> {
+        v.split(|c: char| !c.is_ascii_digit())
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse().unwrap_or(u64::MAX))
+            .collect()
+    };
+    parse(version) >= parse(min_version)
+}
+
+async fn resolve_ticket(
+    ms_data: &MSData,
+    id: u32,
+    gm_id: u32,
+    resolution: &str,
+) -> Result<(), Error> {
+    let ticket = ms_data.sql.resolve_ticket(id, gm_id, resolution).await?;
+    // error means that no ships are connected
+    let _ = ms_data.tickets.send(ticket);
+    Ok(())
+}
+
+/// Merges the accounts and lets ships move the characters.
+pub(crate) async fn merge_accounts(ms_data: &MSData, from: u32, into: u32) -> Result<(), Error> {
+    ms_data.sql.merge_accounts(from, into, 0).await?;
+    // error means that no ships are connected, they move characters after connecting
+    let _ = ms_data.merges.send((from, into));
+    Ok(())
+}
+
+/// Sends an admin message to the ships. If `ships` is empty then all ships receive it.
+fn broadcast(ms_data: &MSData, ships: Vec<u32>, message: String) {
+    log::info!("Broadcasting message: {message}");
+    // error means that no ships are connected
+    let _ = ms_data.broadcasts.send((ships, message));
+}
+
+async fn confirm_reset(
+    ms_data: &MSData,
+    username: &str,
+    code: &str,
+    password: &str,
+) -> Result<UserLoginResult, Error> {
+    let sql = &ms_data.sql;
+    let user_key = format!("user:{username}");
+    // wrong codes count as failed logins of the account
+    if let Some(retry_after) = sql.get_login_lockout(&user_key).await? {
+        log::info!("Password reset for {username} is throttled");
+        return Ok(UserLoginResult::TooManyAttempts { retry_after });
+    }
+    match sql.confirm_reset(username, code, password).await {
+        // the new password is already set, so the next login only needs the one-time code
+        Ok(d) if sql.get_totp_secret(d.id).await?.is_some() => Ok(UserLoginResult::OtpRequired),
+        Ok(d) => {
+            sql.reset_login_failures(&user_key).await?;
+            Ok(UserLoginResult::Success {
+                id: d.id,
+                nickname: d.nickname,
+                accountflags: d.account_flags,
+                gm_level: d.gm_level,
+                last_uuid: d.last_uuid,
+            })
+        }
+        Err(Error::NoUser) => Ok(UserLoginResult::NotFound),
+        Err(Error::InvalidPassword(id)) => {
+            sql.add_login_failure(&user_key, &ms_data.login_limits)
+                .await?;
+            Ok(UserLoginResult::InvalidPassword(id))
+        }
+        Err(Error::Banned(ban)) => Ok(UserLoginResult::Banned {
+            until: ban.until,
+            reason: ban.reason,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
 async fn make_keys(servers: Arc<MSData>) -> io::Result<()> {
     let listener = TcpListener::bind(("0.0.0.0", 11000)).await?;
     loop {
@@ -573,13 +1748,15 @@ async fn make_keys(servers: Arc<MSData>) -> io::Result<()> {
     }
 }
 
-async fn make_query(servers: Arc<MSData>) -> io::Result<()> {
+async fn make_query(servers: Arc<MSData>, ports: &PortSettings) -> Result<(), Error> {
     let mut info_listeners: Vec<TcpListener> = vec![];
-    for i in 0..10 {
+    for i in 0..ports.ship_count {
         // pc ships
-        info_listeners.push(TcpListener::bind(("0.0.0.0", 12199 + (i * 100))).await?);
+        let port = ports.slot_port(ports.query_port, i)?;
+        info_listeners.push(TcpListener::bind(("0.0.0.0", port)).await?);
         // vita ships
-        info_listeners.push(TcpListener::bind(("0.0.0.0", 12194 + (i * 100))).await?);
+        let port = ports.slot_port(ports.vita_query_port, i)?;
+        info_listeners.push(TcpListener::bind(("0.0.0.0", port)).await?);
     }
     for listener in info_listeners {
         let servers = servers.clone();
@@ -604,51 +1781,56 @@ async fn query_listener(listener: TcpListener, servers: Arc<MSData>) {
 
 async fn send_query(stream: TcpStream, servers: Arc<MSData>) -> Result<(), Error> {
     log::debug!("Sending query information...");
-    stream.set_nodelay(true)?;
-    let mut con = Connection::<Packet>::new_async(
-        stream,
-        PacketType::Classic,
-        PrivateKey::None,
-        PublicKey::None,
-    );
+    let (remote_ip, local_ip) = balance::connection_addrs(&stream)?;
+    let maintenance = servers.maintenance.read().enabled;
     let mut ships = vec![];
     for server in servers.ships.read().iter() {
         ships.push(login::ShipEntry {
             id: server.id * 1000,
             name: format!("Ship{:02}", server.id).into(),
-            ip: server.ip,
-            status: server.status,
+            ip: balance::select_addr(remote_ip, local_ip, server.ip)?,
+            status: if maintenance {
+                login::ShipStatus::Offline
+            } else {
+                server.status
+            },
             order: server.id as u16,
         })
     }
-    con.write_packet_async(&Packet::ShipList(login::ShipListPacket {
+    let packet = Packet::ShipList(login::ShipListPacket {
         ships,
         ..Default::default()
-    }))
-    .await?;
+    });
+    balance::send_once(stream, &packet).await?;
     Ok(())
 }
 
-async fn make_block_balance(server_statuses: Arc<MSData>) -> Result<(), Error> {
+async fn make_block_balance(
+    server_statuses: Arc<MSData>,
+    ports: &PortSettings,
+) -> Result<(), Error> {
     let mut listeners = vec![];
-    for i in 0..10 {
+    for i in 0..ports.ship_count {
+        let ship_id = ports.first_ship_id + i;
         //pc balance
-        listeners.push(TcpListener::bind(("0.0.0.0", 12100 + (i * 100))).await?);
+        let port = ports.slot_port(ports.balance_port, i)?;
+        listeners.push((TcpListener::bind(("0.0.0.0", port)).await?, ship_id));
         //vita balance
-        listeners.push(TcpListener::bind(("0.0.0.0", 12193 + (i * 100))).await?);
+        let port = ports.slot_port(ports.vita_balance_port, i)?;
+        listeners.push((TcpListener::bind(("0.0.0.0", port)).await?, ship_id));
     }
-    for listener in listeners {
+    for (listener, ship_id) in listeners {
         let server_statuses = server_statuses.clone();
-        tokio::spawn(block_listener(listener, server_statuses));
+        tokio::spawn(block_listener(listener, server_statuses, ship_id));
     }
     Ok(())
 }
 
-async fn block_listener(listener: TcpListener, server_statuses: Arc<MSData>) {
+async fn block_listener(listener: TcpListener, server_statuses: Arc<MSData>, ship_id: u32) {
     loop {
         match listener.accept().await {
             Ok((s, _)) => {
-                let _ = send_block_balance(s, server_statuses.clone()).await;
+                let _ = send_block_balance(s, server_statuses.clone(), ship_id).await;
             }
             Err(e) => {
                 log::error!("Failed to accept connection: {e}");
@@ -658,65 +1840,29 @@ async fn block_listener(listener: TcpListener, server_statuses: Arc<MSData>) {
     }
 }
 
-async fn send_block_balance(stream: TcpStream, servers: Arc<MSData>) -> Result<(), Error> {
+async fn send_block_balance(stream: TcpStream, servers: Arc<MSData>, id: u32) -> Result<(), Error> {
     log::debug!("Sending block balance...");
-    stream.set_nodelay(true)?;
-    let port = stream.local_addr()?.port();
-    let id = if port % 3 == 0 {
-        (port - 12093) / 100
-    } else {
-        (port - 12000) / 100
-    } as u32;
-    let remote_ip = match stream.peer_addr()?.ip() {
-        IpAddr::V4(ipv4_addr) => ipv4_addr,
-        IpAddr::V6(_) => return Err(Error::InvalidData),
-    };
-    let local_ip = match stream.local_addr()?.ip() {
-        IpAddr::V4(ipv4_addr) => ipv4_addr,
-        IpAddr::V6(_) => return Err(Error::InvalidData),
-    };
-    let mut con = Connection::<Packet>::new_async(
-        stream,
-        PacketType::Classic,
-        PrivateKey::None,
-        PublicKey::None,
-    );
-    let servers = servers.ships.read();
-    let Some(server) = servers.iter().find(|x| x.id == id) else {
-        con.write_packet_async(&Packet::LoginResponse(login::LoginResponsePacket {
+    let (remote_ip, local_ip) = balance::connection_addrs(&stream)?;
+    let packet = match servers.ships.read().iter().find(|x| x.id == id) {
+        Some(server) => balance::block_balance_packet(
+            balance::select_addr(remote_ip, local_ip, server.ip)?,
+            server.port,
+            server.name.clone(),
+        ),
+        None => Packet::LoginResponse(login::LoginResponsePacket {
             status: login::LoginStatus::Failure,
             error: "Server is offline".to_string(),
             ..Default::default()
-        }))
-        .await?;
-        return Ok(());
-    };
-
-    let ship_ip = server.ip;
-    let send_ip = get_addr(remote_ip, local_ip, ship_ip)?;
-
-    let packet = login::BlockBalancePacket {
-        ip: send_ip,
-        port: server.port,
-        blockname: server.name.clone().into(),
-        ..Default::default()
+        }),
     };
-    con.write_packet_async(&Packet::BlockBalance(packet))
-        .await?;
+    balance::send_once(stream, &packet).await?;
     Ok(())
 }
 
 async fn send_keys(mut stream: TcpStream, servers: Arc<MSData>) -> Result<(), Error> {
     log::debug!("Sending keys...");
     stream.set_nodelay(true)?;
-    let remote_ip = match stream.peer_addr()?.ip() {
-        IpAddr::V4(ipv4_addr) => ipv4_addr,
-        IpAddr::V6(_) => return Err(Error::InvalidData),
-    };
-    let local_ip = match stream.local_addr()?.ip() {
-        IpAddr::V4(ipv4_addr) => ipv4_addr,
-        IpAddr::V6(_) => return Err(Error::InvalidData),
-    };
+    let (remote_ip, local_ip) = balance::connection_addrs(&stream)?;
     let lock = servers.ships.read();
     let mut data = vec![];
     for ship in lock.iter() {
@@ -727,7 +1873,7 @@ async fn send_keys(mut stream: TcpStream, servers: Arc<MSData>) -> Result<(), Er
         e.resize(4, 0);
         key.append(&mut e);
         key.append(&mut ship.key.n.to_vec());
-        let send_ip = get_addr(remote_ip, local_ip, ship.ip)?;
+        let send_ip = balance::select_addr(remote_ip, local_ip, ship.ip)?;
         data.push(Keys { ip: send_ip, key })
     }
     let mut data = rmp_serde::to_vec(&data)?;
@@ -738,7 +1884,7 @@ async fn send_keys(mut stream: TcpStream, servers: Arc<MSData>) -> Result<(), Er
     Ok(())
 }
 
-async fn async_write<T>(mutex: &RwLock<T>) -> RwLockWriteGuard<T>
+async fn async_write<T>(mutex: &RwLock<T>) -> RwLockWriteGuard<'_, T>
 where
     T: Send + Sync,
 {
@@ -750,53 +1896,109 @@ where
     }
 }
 
-fn get_addr_type(chk_addr: Ipv4Addr) -> Result<AddrType, Error> {
-    if chk_addr.is_loopback() {
-        return Ok(AddrType::Loopback);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pso2packetlib::protocol::PacketType;
+
+    #[test]
+    fn test_is_version_allowed() {
+        assert!(is_version_allowed("", "1.0"));
+        assert!(is_version_allowed("6.1201.3", "6.1201.3"));
+        assert!(is_version_allowed("6.1201.3", "6.1201.10"));
+        assert!(is_version_allowed("6.1201.3", "7.0"));
+        assert!(is_version_allowed("6.1201", "6.1201.1"));
+        assert!(!is_version_allowed("6.1201.3", "6.1201.2"));
+        assert!(!is_version_allowed("6.1201.3", "6.999"));
+        assert!(!is_version_allowed("6.1201.1", "6.1201"));
+        // unknown version
+        assert!(is_version_allowed("6.1201.3", ""));
     }
-    let interfaces = NetworkInterface::show()?;
-    for addr in interfaces.into_iter().flat_map(|i| i.addr.into_iter()) {
-        let IpAddr::V4(local_addr) = addr.ip() else {
-            continue;
+
+    fn login_success(level: u8) -> MasterShipAction {
+        MasterShipAction::UserLoginResult(UserLoginResult::Success {
+            id: 1,
+            nickname: String::new(),
+            accountflags: Default::default(),
+            gm_level: level,
+            last_uuid: 0,
+        })
+    }
+
+    fn is_maintenance(action: &MasterShipAction) -> bool {
+        matches!(
+            action,
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(m)) if m == "notice"
+        )
+    }
+
+    #[test]
+    fn test_restrict_login() {
+        let mut maintenance = MaintenanceSettings {
+            enabled: false,
+            message: String::from("notice"),
+            ..Default::default()
         };
-        let Some(IpAddr::V4(mask)) = addr.netmask() else {
-            continue;
+        let mut action = login_success(gm_level::PLAYER);
+        restrict_login(&maintenance, &mut action);
+        assert!(!is_maintenance(&action));
+
+        maintenance.enabled = true;
+        restrict_login(&maintenance, &mut action);
+        assert!(is_maintenance(&action));
+
+        let mut action = login_success(gm_level::GM);
+        restrict_login(&maintenance, &mut action);
+        assert!(!is_maintenance(&action));
+
+        // unknown accounts would be registered otherwise
+        let mut action = MasterShipAction::UserLoginResult(UserLoginResult::NotFound);
+        restrict_login(&maintenance, &mut action);
+        assert!(is_maintenance(&action));
+
+        let mut action = MasterShipAction::UserLoginResult(UserLoginResult::InvalidPassword(1));
+        restrict_login(&maintenance, &mut action);
+        assert!(!is_maintenance(&action));
+        let mut action = MasterShipAction::Ok;
+        restrict_login(&maintenance, &mut action);
+        assert!(!is_maintenance(&action));
+    }
+
+    #[test]
+    fn test_maintenance_refusal() {
+        let mut maintenance = MaintenanceSettings {
+            enabled: false,
+            message: String::from("notice"),
+            ..Default::default()
         };
-        let local_addr = local_addr.octets();
-        let mask = mask.octets();
-        let chk_addr = chk_addr.octets();
-        let mut masked_local_addr = [0; 4];
-        let mut masked_chk_addr = [0; 4];
-        masked_local_addr
-            .iter_mut()
-            .zip(local_addr.iter().zip(mask.iter()).map(|(a, b)| a & b))
-            .for_each(|(a, b)| *a = b);
-        masked_chk_addr
-            .iter_mut()
-            .zip(chk_addr.iter().zip(mask.iter()).map(|(a, b)| a & b))
-            .for_each(|(a, b)| *a = b);
-        if masked_chk_addr == masked_local_addr {
-            return Ok(AddrType::Local);
-        }
-    }
-    Ok(AddrType::Global)
-}
-
-fn get_addr(remote_ip: Ipv4Addr, local_ip: Ipv4Addr, ship_ip: Ipv4Addr) -> Result<Ipv4Addr, Error> {
-    let remote_addr_type = get_addr_type(remote_ip)?;
-    let ship_addr_type = get_addr_type(ship_ip)?;
-    let send_ip = match (remote_addr_type, ship_addr_type) {
-        // if the ship is connected via global ip then it is reachable by anyone
-        (_, AddrType::Global) => ship_ip,
-        // if the client is connected via loopback then it is the same pc, thus any ship ip works
-        (AddrType::Loopback, _) => ship_ip,
-        // ship is on the same pc as the master ship, so return connected to address
-        (AddrType::Local, AddrType::Loopback) => local_ip,
-        // assume that the ship is in the same network as the client
-        (AddrType::Local, AddrType::Local) => ship_ip,
-        // if the ship is local or on the same pc
-        (AddrType::Global, _) => local_ip,
-    };
+        let reset = MasterShipAction::ConfirmReset {
+            username: String::from("user"),
+            code: String::from("code"),
+            password: String::from("password"),
+        };
+        let creds = UserCreds {
+            username: String::from("user"),
+            password: String::new(),
+            ip: Ipv4Addr::UNSPECIFIED,
+            platform: PacketType::Vita,
+            otp: None,
+            client_version: String::new(),
+        };
+        let register = MasterShipAction::UserRegisterVita(creds.clone());
+        assert_eq!(maintenance_refusal(&maintenance, &reset), None);
+        assert_eq!(maintenance_refusal(&maintenance, &register), None);
 
-    Ok(send_ip)
+        maintenance.enabled = true;
+        assert_eq!(
+            maintenance_refusal(&maintenance, &reset).as_deref(),
+            Some("notice")
+        );
+        assert_eq!(
+            maintenance_refusal(&maintenance, &register).as_deref(),
+            Some("notice")
+        );
+        // logins are checked after the account is known
+        let login = MasterShipAction::UserLogin(creds);
+        assert_eq!(maintenance_refusal(&maintenance, &login), None);
+    }
 }
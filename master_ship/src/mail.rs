@@ -0,0 +1,95 @@
+//! SMTP sender for account emails (verification and password reset).
+use crate::Error;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmtpSettings {
+    /// Address of the SMTP server. If empty then emails are disabled.
+    pub server: String,
+    pub port: Option<u16>,
+    pub username: String,
+    pub password: String,
+    /// Sender address (e.g. "PSO2 Server <noreply@example.com>").
+    pub from: String,
+}
+
+#[derive(Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    sender: mpsc::UnboundedSender<Mail>,
+}
+
+impl Mailer {
+    /// Starts the sender task. Returns `None` if SMTP is not configured.
+    pub fn start(settings: SmtpSettings) -> Result<Option<Self>, Error> {
+        if settings.server.is_empty() {
+            return Ok(None);
+        }
+        let from: Mailbox = settings
+            .from
+            .parse()
+            .map_err(|e| Error::MailError(format!("{e}")))?;
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.server)
+            .map_err(|e| Error::MailError(e.to_string()))?
+            .credentials(Credentials::new(settings.username, settings.password));
+        if let Some(port) = settings.port {
+            transport = transport.port(port);
+        }
+        let transport = transport.build();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Mail>();
+        tokio::spawn(async move {
+            while let Some(mail) = receiver.recv().await {
+                let to = mail.to.clone();
+                if let Err(e) = send_mail(&transport, from.clone(), mail).await {
+                    log::warn!("Failed to send email to {to}: {e}");
+                }
+            }
+        });
+        log::info!("SMTP sender started");
+        Ok(Some(Self { sender }))
+    }
+    pub fn send(&self, mail: Mail) -> Result<(), Error> {
+        self.sender
+            .send(mail)
+            .map_err(|_| Error::MailError("Sender task has stopped".into()))
+    }
+}
+
+async fn send_mail(
+    transport: &AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    mail: Mail,
+) -> Result<(), Error> {
+    let to: Mailbox = mail
+        .to
+        .parse()
+        .map_err(|e| Error::MailError(format!("{e}")))?;
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(mail.subject)
+        .body(mail.body)
+        .map_err(|e| Error::MailError(e.to_string()))?;
+    transport
+        .send(message)
+        .await
+        .map_err(|e| Error::MailError(e.to_string()))?;
+    Ok(())
+}
+
+/// Checks that the address can be used as a recipient.
+pub fn is_valid_address(address: &str) -> bool {
+    address.parse::<Mailbox>().is_ok()
+}
@@ -0,0 +1,306 @@
+//! Optional Prometheus metrics endpoint.
+use crate::Error;
+use axum::{http::header, routing::get, Router};
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::atomic::{AtomicI64, Ordering},
+    time::Instant,
+};
+use tokio::net::TcpListener;
+
+pub(crate) static METRICS: Metrics = Metrics::new();
+
+pub(crate) struct Metrics {
+    /// Login attempts by result.
+    logins: Mutex<BTreeMap<&'static str, u64>>,
+    /// Registration attempts by result.
+    registrations: Mutex<BTreeMap<&'static str, u64>>,
+    ship_connections: AtomicI64,
+    /// Durations of handled actions by action name.
+    actions: Mutex<BTreeMap<&'static str, Timing>>,
+    /// Durations of database queries by query name.
+    queries: Mutex<BTreeMap<&'static str, Timing>>,
+    /// Client handler failures by ship id and error code.
+    ship_errors: Mutex<BTreeMap<(u32, String), u64>>,
+    /// Map and party lifecycle counters by ship id and counter name.
+    ship_objects: Mutex<BTreeMap<(u32, String), u64>>,
+    /// Client disconnects by ship id and reason.
+    ship_disconnects: Mutex<BTreeMap<(u32, String), u64>>,
+    /// Bytes received from and sent to clients by ship id and packet id.
+    ship_traffic: Mutex<BTreeMap<(u32, String), (u64, u64)>>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Timing {
+    count: u64,
+    sum: f64,
+}
+
+/// Records elapsed time when dropped.
+pub(crate) struct Timer {
+    map: &'static Mutex<BTreeMap<&'static str, Timing>>,
+    name: &'static str,
+    start: Instant,
+}
+
+/// Decrements the number of ship connections when dropped.
+pub(crate) struct ConnectionGuard;
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            logins: Mutex::new(BTreeMap::new()),
+            registrations: Mutex::new(BTreeMap::new()),
+            ship_connections: AtomicI64::new(0),
+            actions: Mutex::new(BTreeMap::new()),
+            queries: Mutex::new(BTreeMap::new()),
+            ship_errors: Mutex::new(BTreeMap::new()),
+            ship_objects: Mutex::new(BTreeMap::new()),
+            ship_disconnects: Mutex::new(BTreeMap::new()),
+            ship_traffic: Mutex::new(BTreeMap::new()),
+        }
+    }
+    pub fn login(&self, result: &'static str) {
+        *self.logins.lock().entry(result).or_default() += 1;
+    }
+    pub fn registration(&self, result: &'static str) {
+        *self.registrations.lock().entry(result).or_default() += 1;
+    }
+    /// Replaces handler failure counters reported by the ship.
+    pub fn ship_errors(&self, ship_id: u32, errors: Vec<(String, u64)>) {
+        let mut ship_errors = self.ship_errors.lock();
+        ship_errors.retain(|(id, _), _| *id != ship_id);
+        ship_errors.extend(
+            errors
+                .into_iter()
+                .map(|(code, count)| ((ship_id, code), count)),
+        );
+    }
+    /// Replaces lifecycle counters reported by the ship.
+    pub fn ship_objects(&self, ship_id: u32, objects: Vec<(String, u64)>) {
+        let mut ship_objects = self.ship_objects.lock();
+        ship_objects.retain(|(id, _), _| *id != ship_id);
+        ship_objects.extend(
+            objects
+                .into_iter()
+                .map(|(counter, count)| ((ship_id, counter), count)),
+        );
+    }
+    /// Replaces disconnect counters reported by the ship.
+    pub fn ship_disconnects(&self, ship_id: u32, disconnects: Vec<(String, u64)>) {
+        let mut ship_disconnects = self.ship_disconnects.lock();
+        ship_disconnects.retain(|(id, _), _| *id != ship_id);
+        ship_disconnects.extend(
+            disconnects
+                .into_iter()
+                .map(|(reason, count)| ((ship_id, reason), count)),
+        );
+    }
+    /// Replaces packet traffic counters reported by the ship.
+    pub fn ship_traffic(&self, ship_id: u32, traffic: Vec<(String, u64, u64)>) {
+        let mut ship_traffic = self.ship_traffic.lock();
+        ship_traffic.retain(|(id, _), _| *id != ship_id);
+        ship_traffic.extend(
+            traffic
+                .into_iter()
+                .map(|(packet, bytes_in, bytes_out)| ((ship_id, packet), (bytes_in, bytes_out))),
+        );
+    }
+    pub fn track_ship_connection(&'static self) -> ConnectionGuard {
+        self.ship_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard
+    }
+    pub fn time_action(&'static self, name: &'static str) -> Timer {
+        Timer {
+            map: &self.actions,
+            name,
+            start: Instant::now(),
+        }
+    }
+    pub fn time_query(&'static self, name: &'static str) -> Timer {
+        Timer {
+            map: &self.queries,
+            name,
+            start: Instant::now(),
+        }
+    }
+    fn render(&self) -> String {
+        let mut out = String::new();
+        render_counter(
+            &mut out,
+            "master_ship_logins_total",
+            "Login attempts by result.",
+            "result",
+            &self.logins.lock(),
+        );
+        render_counter(
+            &mut out,
+            "master_ship_registrations_total",
+            "Registration attempts by result.",
+            "result",
+            &self.registrations.lock(),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP master_ship_ship_connections Active ship connections.\n\
+            # TYPE master_ship_ship_connections gauge\n\
+            master_ship_ship_connections {}",
+            self.ship_connections.load(Ordering::Relaxed)
+        );
+        let name = "master_ship_ship_handler_errors_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Client handler failures on ships by error code.\n# TYPE {name} counter"
+        );
+        for ((ship_id, code), count) in self.ship_errors.lock().iter() {
+            let _ = writeln!(out, "{name}{{ship=\"{ship_id}\",code=\"{code}\"}} {count}");
+        }
+        let name = "master_ship_ship_objects_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Maps and parties created and dropped on ships.\n# TYPE {name} counter"
+        );
+        for ((ship_id, counter), count) in self.ship_objects.lock().iter() {
+            let _ = writeln!(
+                out,
+                "{name}{{ship=\"{ship_id}\",counter=\"{counter}\"}} {count}"
+            );
+        }
+        let name = "master_ship_ship_disconnects_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Client disconnects on ships by reason.\n# TYPE {name} counter"
+        );
+        for ((ship_id, reason), count) in self.ship_disconnects.lock().iter() {
+            let _ = writeln!(
+                out,
+                "{name}{{ship=\"{ship_id}\",reason=\"{reason}\"}} {count}"
+            );
+        }
+        let name = "master_ship_ship_packet_bytes_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Client traffic on ships by packet id.\n# TYPE {name} counter"
+        );
+        for ((ship_id, packet), (bytes_in, bytes_out)) in self.ship_traffic.lock().iter() {
+            let labels = format!("ship=\"{ship_id}\",packet=\"{packet}\"");
+            let _ = writeln!(out, "{name}{{{labels},direction=\"in\"}} {bytes_in}");
+            let _ = writeln!(out, "{name}{{{labels},direction=\"out\"}} {bytes_out}");
+        }
+        render_summary(
+            &mut out,
+            "master_ship_action_duration_seconds",
+            "Time spent handling ship actions.",
+            "action",
+            &self.actions.lock(),
+        );
+        render_summary(
+            &mut out,
+            "master_ship_query_duration_seconds",
+            "Time spent in database queries.",
+            "query",
+            &self.queries.lock(),
+        );
+        out
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let mut map = self.map.lock();
+        let timing = map.entry(self.name).or_default();
+        timing.count += 1;
+        timing.sum += self.start.elapsed().as_secs_f64();
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        METRICS.ship_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn render_counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: &BTreeMap<&'static str, u64>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+    for (value, count) in values {
+        let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+    }
+}
+
+fn render_summary(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: &BTreeMap<&'static str, Timing>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} summary");
+    for (value, timing) in values {
+        let _ = writeln!(out, "{name}_sum{{{label}=\"{value}\"}} {}", timing.sum);
+        let _ = writeln!(out, "{name}_count{{{label}=\"{value}\"}} {}", timing.count);
+    }
+}
+
+pub(crate) async fn start_metrics_server(addr: &str) -> Result<(), Error> {
+    let router = Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                METRICS.render(),
+            )
+        }),
+    );
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Metrics listening on {addr}");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            log::error!("Metrics server failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn test_render() {
+        static METRICS: Metrics = Metrics::new();
+        METRICS.login("success");
+        METRICS.login("success");
+        METRICS.registration("denied");
+        drop(METRICS.time_query("get_sega_user"));
+        METRICS.ship_errors(1, vec![("invalid_input".to_string(), 3)]);
+        METRICS.ship_objects(1, vec![("maps_created".to_string(), 4)]);
+        METRICS.ship_disconnects(1, vec![("timeout".to_string(), 2)]);
+        METRICS.ship_traffic(1, vec![("11-00".to_string(), 10, 20)]);
+        let out = METRICS.render();
+        assert!(out.contains("master_ship_logins_total{result=\"success\"} 2\n"));
+        assert!(out.contains("master_ship_registrations_total{result=\"denied\"} 1\n"));
+        assert!(
+            out.contains("master_ship_query_duration_seconds_count{query=\"get_sega_user\"} 1\n")
+        );
+        assert!(out.contains(
+            "master_ship_ship_handler_errors_total{ship=\"1\",code=\"invalid_input\"} 3\n"
+        ));
+        assert!(
+            out.contains("master_ship_ship_objects_total{ship=\"1\",counter=\"maps_created\"} 4\n")
+        );
+        assert!(
+            out.contains("master_ship_ship_disconnects_total{ship=\"1\",reason=\"timeout\"} 2\n")
+        );
+        assert!(out.contains(
+            "master_ship_ship_packet_bytes_total{ship=\"1\",packet=\"11-00\",direction=\"out\"} 20\n"
+        ));
+    }
+}
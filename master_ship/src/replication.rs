@@ -0,0 +1,191 @@
+//! Replication of accounts, account tables and ship PSKs to standby master ships.
+//!
+//! Each primary has a term that a standby increases when it takes over. Ships remember the
+//! highest term they have seen, and a master ship that sees a higher term stops accepting ships,
+//! so only one primary accepts writes.
+use crate::{
+    sql::{Change, Sql, REPLICATED_TABLES},
+    Error, MSData, IS_RUNNING,
+};
+use data_structs::master_ship::{
+    MasterShipAction, MasterShipComm, ShipConnection, ShipLogin, ShipLoginResult,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+/// Stores the host key of the primary master ship after the first connection.
+const PRIMARY_KEY_FILE: &str = "primary_key.bin";
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationSettings {
+    /// Address of the primary master ship. If set then this master ship runs as a standby.
+    pub primary: Option<String>,
+    /// Key used by standby master ships to authenticate (empty - replication is disabled).
+    pub psk: String,
+    /// Time (in seconds) without connection to the primary after which the standby takes over.
+    pub failover_timeout: u64,
+}
+
+impl Default for ReplicationSettings {
+    fn default() -> Self {
+        Self {
+            primary: None,
+            psk: String::new(),
+            failover_timeout: 30,
+        }
+    }
+}
+
+/// Sends the current state and all following changes to a standby master ship.
+pub(crate) async fn serve_standby(mut conn: ShipConnection, ms_data: Arc<MSData>) {
+    log::info!("Standby master ship connected");
+    let sql = &ms_data.sql;
+    let mut changes = sql.subscribe_changes();
+    if let Err(e) = send_snapshot(&mut conn, sql).await {
+        log::warn!("Failed to send snapshot to standby master ship: {e}");
+        return;
+    }
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    loop {
+        if ms_data.fenced.load(Ordering::Relaxed) {
+            return;
+        }
+        let result = tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) => send_change(&mut conn, sql, change).await,
+                Err(RecvError::Lagged(_)) => send_snapshot(&mut conn, sql).await,
+                Err(RecvError::Closed) => return,
+            },
+            _ = keepalive.tick() => write(&mut conn, MasterShipAction::Ok).await,
+            result = conn.read_for(Duration::from_secs(1)) => match result {
+                Ok(_) | Err(data_structs::Error::Timeout) => Ok(()),
+                Err(e) => Err(e.into()),
+            },
+        };
+        if let Err(e) = result {
+            log::info!("Standby master ship disconnected: {e}");
+            return;
+        }
+    }
+}
+
+async fn write(conn: &mut ShipConnection, action: MasterShipAction) -> Result<(), Error> {
+    conn.write(MasterShipComm { id: 0, action }).await?;
+    Ok(())
+}
+
+async fn send_snapshot(conn: &mut ShipConnection, sql: &Sql) -> Result<(), Error> {
+    for key in sql.get_ship_keys().await? {
+        write(conn, MasterShipAction::ReplicatedShip(key)).await?;
+    }
+    for user in sql.get_replicated_users().await? {
+        write(conn, MasterShipAction::ReplicatedUser(user)).await?;
+    }
+    for table in REPLICATED_TABLES {
+        let table = sql.get_replicated_table(table).await?;
+        write(conn, MasterShipAction::ReplicatedTable(table)).await?;
+    }
+    Ok(())
+}
+
+async fn send_change(conn: &mut ShipConnection, sql: &Sql, change: Change) -> Result<(), Error> {
+    match change {
+        Change::User(id) => {
+            if let Some(user) = sql.get_replicated_user(id).await? {
+                write(conn, MasterShipAction::ReplicatedUser(user)).await?;
+            }
+        }
+        Change::Ship(key) => write(conn, MasterShipAction::ReplicatedShip(key)).await?,
+        Change::Table(table) => {
+            let table = sql.get_replicated_table(table).await?;
+            write(conn, MasterShipAction::ReplicatedTable(table)).await?
+        }
+    }
+    Ok(())
+}
+
+/// Mirrors the primary master ship. Returns when the primary was unreachable for longer than the
+/// failover timeout, after increasing the term.
+pub(crate) async fn follow(
+    sql: &Sql,
+    settings: &ReplicationSettings,
+    primary: &str,
+) -> Result<(), Error> {
+    let timeout = Duration::from_secs(settings.failover_timeout);
+    let mut last_seen = Instant::now();
+    while IS_RUNNING.load(Ordering::Relaxed) {
+        let mut connected = false;
+        if let Err(e) =
+            sync_from_primary(sql, primary, settings.psk.as_bytes(), &mut connected).await
+        {
+            log::warn!("Replication error: {e}");
+        }
+        if connected {
+            last_seen = Instant::now();
+        }
+        if last_seen.elapsed() >= timeout {
+            let term = sql.get_term().await? + 1;
+            log::warn!("Primary master ship is unreachable, taking over with term {term}");
+            sql.set_term(term).await?;
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Ok(())
+}
+
+async fn sync_from_primary(
+    sql: &Sql,
+    primary: &str,
+    psk: &[u8],
+    connected: &mut bool,
+) -> Result<(), Error> {
+    let socket = tokio::net::TcpStream::connect(primary).await?;
+    let known_key = tokio::fs::read(PRIMARY_KEY_FILE).await.ok();
+    let mut received_key = vec![];
+    let mut conn = ShipConnection::new_client(socket, |_, key| {
+        received_key = key.to_vec();
+        known_key.as_ref().is_none_or(|k| k == key)
+    })
+    .await
+    .inspect_err(|e| {
+        if matches!(e, data_structs::Error::UnknownHostkey(_)) {
+            log::error!(
+                "Host key of the primary master ship has changed! If this is expected, delete \
+                {PRIMARY_KEY_FILE}"
+            );
+        }
+    })?;
+    if known_key.is_none() {
+        tokio::fs::write(PRIMARY_KEY_FILE, &received_key).await?;
+    }
+    conn.write(MasterShipComm {
+        id: 1,
+        action: MasterShipAction::ReplicationLogin(ShipLogin {
+            psk: psk.to_vec(),
+            term: sql.get_term().await?,
+        }),
+    })
+    .await?;
+    match conn.read_for(Duration::from_secs(10)).await?.action {
+        MasterShipAction::ShipLoginResult(ShipLoginResult::Ok(term)) => sql.set_term(term).await?,
+        _ => return Err(Error::ReplicationDenied),
+    }
+    log::info!("Connected to the primary master ship");
+    *connected = true;
+    while IS_RUNNING.load(Ordering::Relaxed) {
+        match conn.read_for(KEEPALIVE_INTERVAL * 3).await?.action {
+            MasterShipAction::ReplicatedUser(user) => sql.put_replicated_user(&user).await?,
+            MasterShipAction::ReplicatedShip(key) => sql.put_ship_key(&key).await?,
+            MasterShipAction::ReplicatedTable(table) => sql.put_replicated_table(&table).await?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
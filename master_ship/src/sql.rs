@@ -1,22 +1,61 @@
-use crate::Error;
+use crate::{admin::constant_time_eq, metrics::METRICS, totp, Error};
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use data_structs::{flags::Flags, inventory::AccountStorages};
+use data_structs::{
+    flags::Flags,
+    inventory::AccountStorages,
+    master_ship::{
+        gm_level, AccountNote, ChangeNicknameResult, LoginEntry, Mute, PutStorageResult,
+        ReplicatedTable, ReplicatedUser, ReplicatedValue, ShipKey, ShipStats, SupportTicket,
+        TicketStatus, RESET_PREFIX,
+    },
+};
 use pso2packetlib::{
-    protocol::login::{LoginAttempt, LoginResult, UserInfoPacket},
+    protocol::{
+        login::{LoginResult, UserInfoPacket},
+        PacketType,
+    },
     AsciiString,
 };
 use rand_core::{OsRng, RngCore};
-use sqlx::{migrate::MigrateDatabase, Executor, Row};
+use sqlx::{migrate::MigrateDatabase, Executor, Row, TypeInfo, ValueRef};
 use std::{
+    collections::HashSet,
     net::Ipv4Addr,
     ops::Add,
     str::from_utf8,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+/// Time a block login challenge is valid for.
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+/// Tables that are replicated as a whole to standby master ships. Users and ship keys are
+/// replicated by row, login history, statistics, throttling counters and short-lived codes are
+/// not replicated.
+pub const REPLICATED_TABLES: &[&str] = &[
+    "Bans",
+    "AccountLinks",
+    "AccountValues",
+    "AccountMerges",
+    "AccountNotes",
+    "SupportTickets",
+    "Mutes",
+];
+
 pub struct Sql {
     connection: sqlx::SqlitePool,
     registration_enabled: bool,
+    changes: tokio::sync::broadcast::Sender<Change>,
+}
+
+/// Change that should be replicated to standby master ships.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Account with this id was created or modified.
+    User(u32),
+    /// Ship PSK was added or revoked.
+    Ship(ShipKey),
+    /// Rows of one of the [`REPLICATED_TABLES`] were added, modified or deleted.
+    Table(&'static str),
 }
 
 #[derive(PartialEq, Debug)]
@@ -25,10 +64,174 @@ pub struct User {
 
     pub nickname: String,
     pub account_flags: Flags,
-    pub isgm: bool,
+    pub gm_level: u8,
     pub last_uuid: u64,
 }
 
+/// Account information for administrative purposes.
+#[derive(Debug, serde::Serialize)]
+pub struct AccountInfo {
+    pub id: u32,
+    pub username: String,
+    pub psn_username: String,
+    pub nickname: String,
+    pub gm_level: u8,
+    pub email: String,
+    pub email_verified: bool,
+}
+
+/// Login attempt with the name of the account.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RecentLogin {
+    pub user_id: u32,
+    pub username: String,
+    pub ip: Ipv4Addr,
+    pub status: LoginResult,
+    pub platform: PacketType,
+    /// Time (since UNIX epoch) of the attempt.
+    pub timestamp: Duration,
+}
+
+/// Statistics reported by a ship.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ShipStatsEntry {
+    /// Id of the ship or 0 for network totals.
+    pub ship_id: u32,
+    /// Time (since UNIX epoch) of the report.
+    pub timestamp: Duration,
+    #[serde(flatten)]
+    pub stats: ShipStats,
+}
+
+/// Type of one-time code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeKind {
+    EmailVerification = 0,
+    PasswordReset = 1,
+    AccountLink = 2,
+    /// Re-authentication before an in-game password change.
+    PasswordChange = 3,
+}
+
+/// Link between a game account and an external identity (e.g. forum or Discord account).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AccountLink {
+    pub user_id: u32,
+    pub service: String,
+    pub external_id: String,
+}
+
+/// Account ban record.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Ban {
+    pub user_id: u32,
+    pub reason: String,
+    /// Time (since UNIX epoch) when the ban expires. If `None` then the ban is permanent.
+    pub until: Option<Duration>,
+}
+
+/// Failed login throttling settings.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LoginLimits {
+    /// Number of failed attempts after which logins are locked.
+    pub max_attempts: u32,
+    /// Duration of the first lockout (in seconds). Doubles after each following failure.
+    pub base_lockout: u64,
+    /// Maximum lockout duration (in seconds).
+    pub max_lockout: u64,
+    /// Time (in seconds) without failures after which the counter is reset.
+    pub reset_after: u64,
+}
+
+/// Per IP address account registration limits.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RegistrationLimits {
+    /// Maximum number of accounts registered from one IP address during the interval
+    /// (0 - unlimited).
+    pub max_per_ip: u32,
+    /// Length of the interval in seconds.
+    pub interval: u64,
+}
+
+/// Password reset email limits.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ResetLimits {
+    /// Minimum time (in seconds) between reset requests for one account.
+    pub account_interval: u64,
+    /// Maximum number of reset requests from one IP address during the interval.
+    pub max_per_ip: u32,
+    /// Length of the IP address interval in seconds.
+    pub ip_interval: u64,
+}
+
+/// Initial state of new accounts.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AccountDefaults {
+    /// Ids of account flags that are set.
+    pub flags: Vec<usize>,
+    /// Account parameters as `[id, value]` pairs.
+    pub params: Vec<(usize, u32)>,
+    /// GM level (see [`gm_level`]).
+    pub gm_level: u8,
+    /// Capacities of the storage banks.
+    pub storage: StorageCapacities,
+}
+
+/// Capacities of the account storage banks. Bank with zero capacity is disabled.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StorageCapacities {
+    pub default: u32,
+    pub premium: u32,
+    pub extended: u32,
+}
+
+impl StorageCapacities {
+    fn storages(&self) -> AccountStorages {
+        let mut storages = AccountStorages::default();
+        for (id, capacity) in [self.default, self.premium, self.extended]
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(bank) = storages.bank_mut(id as u8) {
+                bank.set_capacity(capacity, capacity != 0);
+            }
+        }
+        storages
+    }
+}
+
+impl AccountDefaults {
+    fn user_data(&self) -> UserData {
+        let mut flags = Flags::new();
+        for &id in &self.flags {
+            flags.set(id, 1);
+        }
+        for &(id, value) in &self.params {
+            flags.set_param(id, value);
+        }
+        UserData {
+            last_uuid: 1,
+            flags,
+            gm_level: self.gm_level.min(gm_level::ADMIN),
+            storage: self.storage.storages(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Storage and settings of an account exported with `--export-account`.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AccountExport {
+    pub settings: String,
+    pub storage: AccountStorages,
+}
+
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 #[serde(default)]
 struct UserData {
@@ -37,20 +240,326 @@ struct UserData {
     storage: AccountStorages,
     info: UserInfoPacket,
     flags: Flags,
+    /// Legacy GM flag, replaced by `gm_level`. Set flag is treated as the admin level.
     isgm: bool,
+    gm_level: u8,
     last_uuid: u64,
+    email: String,
+    email_verified: bool,
+    /// TOTP secret. If empty then 2FA is disabled.
+    totp_secret: Vec<u8>,
+    /// TOTP secret waiting for confirmation.
+    pending_totp_secret: Vec<u8>,
+    /// Last accepted TOTP time step. Codes of this or earlier steps can't be reused.
+    totp_last_step: u64,
+    /// Incremented on each storage write.
+    storage_version: u64,
+    /// Time (since UNIX epoch, in seconds) of the last nickname change from the game.
+    nickname_changed: u64,
+}
+
+impl UserData {
+    fn gm_level(&self) -> u8 {
+        if self.isgm {
+            gm_level::ADMIN
+        } else {
+            self.gm_level
+        }
+    }
 }
 
 impl Sql {
     pub async fn new(path: &str, reg_enabled: bool) -> Result<Self, Error> {
-        if !sqlx::Sqlite::database_exists(path).await.unwrap_or(false) {
-            return Self::create_db(path, reg_enabled).await;
+        let sql = if !sqlx::Sqlite::database_exists(path).await.unwrap_or(false) {
+            Self::create_db(path, reg_enabled).await?
+        } else {
+            let conn = sqlx::SqlitePool::connect(path).await?;
+            Self {
+                connection: conn,
+                registration_enabled: reg_enabled,
+                changes: tokio::sync::broadcast::channel(1024).0,
+            }
+        };
+        sql.update_db().await?;
+        Ok(sql)
+    }
+    /// Creates tables that were added after the initial schema.
+    async fn update_db(&self) -> Result<(), Error> {
+        self.connection
+            .execute(
+                "
+            create table if not exists Bans (
+                Id integer primary key autoincrement,
+                UserId integer,
+                Reason blob,
+                Until integer default NULL,
+                Timestamp integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists EmailCodes (
+                UserId integer,
+                Kind integer,
+                Code blob,
+                Until integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists LoginThrottle (
+                Key blob primary key,
+                Failures integer default 0,
+                LastFailure integer default 0,
+                LockedUntil integer default 0
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists Registrations (
+                Ip blob,
+                Timestamp integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists ResetRequests (
+                Username blob,
+                Ip blob,
+                Timestamp integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists AccountLinks (
+                UserId integer,
+                Service blob,
+                ExternalId blob,
+                Timestamp integer,
+                primary key (Service, ExternalId)
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists AccountValues (
+                UserId integer,
+                Namespace blob,
+                Key blob,
+                Value blob,
+                primary key (UserId, Namespace, Key)
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists AccountMerges (
+                FromId integer primary key,
+                IntoId integer,
+                Timestamp integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists AccountNotes (
+                Id integer primary key autoincrement,
+                UserId integer,
+                AuthorId integer,
+                Note blob,
+                Timestamp integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists SupportTickets (
+                Id integer primary key autoincrement,
+                UserId integer,
+                Nickname blob,
+                ShipId integer,
+                Message blob,
+                Status integer default 0,
+                GmId integer default NULL,
+                Resolution blob default '',
+                Timestamp integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists ShipStats (
+                ShipId integer,
+                Timestamp integer,
+                PeakPlayers integer,
+                Quests integer,
+                Meseta integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists ShipKeys (
+                Id integer primary key autoincrement,
+                Name blob,
+                PSK blob unique,
+                Revoked integer default 0,
+                Timestamp integer,
+                Blocked integer default 0
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists Mutes (
+                UserId integer primary key,
+                Until integer,
+                Reason blob,
+                MutedBy integer
+            );
+        ",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "
+            create table if not exists Replication (
+                Term integer
+            );
+        ",
+            )
+            .await?;
+        // keys from the old unnamed table are named after their row id
+        let has_old_ships = sqlx::query(
+            "select count(*) from sqlite_master where type = 'table' and name = 'Ships'",
+        )
+        .fetch_one(&self.connection)
+        .await?
+        .try_get::<i64, _>(0)?
+            != 0;
+        if has_old_ships {
+            let mut transaction = self.connection.begin().await?;
+            sqlx::query(
+                "insert or ignore into ShipKeys (Name, PSK, Revoked, Timestamp)
+                select cast('ship_' || rowid as blob), PSK, 0, 0 from Ships",
+            )
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query("drop table Ships")
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
         }
-        let conn = sqlx::SqlitePool::connect(path).await?;
-        Ok(Self {
-            connection: conn,
-            registration_enabled: reg_enabled,
-        })
+        let has_platform =
+            sqlx::query("select count(*) from pragma_table_info('Logins') where name = 'Platform'")
+                .fetch_one(&self.connection)
+                .await?
+                .try_get::<i64, _>(0)?
+                != 0;
+        if !has_platform {
+            self.connection
+                .execute("alter table Logins add column Platform blob default NULL")
+                .await?;
+        }
+        let has_challenge_ship = sqlx::query(
+            "select count(*) from pragma_table_info('Challenges') where name = 'ShipId'",
+        )
+        .fetch_one(&self.connection)
+        .await?
+        .try_get::<i64, _>(0)?
+            != 0;
+        if !has_challenge_ship {
+            self.connection
+                .execute("alter table Challenges add column ShipId integer default 0")
+                .await?;
+        }
+        let has_blocked = sqlx::query(
+            "select count(*) from pragma_table_info('ShipKeys') where name = 'Blocked'",
+        )
+        .fetch_one(&self.connection)
+        .await?
+        .try_get::<i64, _>(0)?
+            != 0;
+        if !has_blocked {
+            self.connection
+                .execute("alter table ShipKeys add column Blocked integer default 0")
+                .await?;
+        }
+        let has_nickname =
+            sqlx::query("select count(*) from pragma_table_info('Users') where name = 'Nickname'")
+                .fetch_one(&self.connection)
+                .await?
+                .try_get::<i64, _>(0)?
+                != 0;
+        if !has_nickname {
+            self.connection
+                .execute("alter table Users add column Nickname text collate nocase default NULL")
+                .await?;
+            self.copy_nicknames().await?;
+        }
+        self.connection
+            .execute("create unique index if not exists UserNicknames on Users (Nickname)")
+            .await?;
+        Ok(())
+    }
+    /// Fills the nickname column from the user data. Only the first account of nicknames that
+    /// were taken more than once is indexed.
+    async fn copy_nicknames(&self) -> Result<(), Error> {
+        let rows = sqlx::query("select Id, Data from Users")
+            .fetch_all(&self.connection)
+            .await?;
+        let mut taken = HashSet::new();
+        let mut transaction = self.connection.begin().await?;
+        for row in rows {
+            let id = row.try_get::<i64, _>("Id")?;
+            let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+            if user_data.nickname.is_empty() {
+                continue;
+            }
+            if !taken.insert(user_data.nickname.to_ascii_lowercase()) {
+                log::warn!(
+                    "Nickname {} of account {id} is already taken by another account",
+                    user_data.nickname
+                );
+                continue;
+            }
+            sqlx::query("update Users set Nickname = ? where Id = ?")
+                .bind(&user_data.nickname)
+                .bind(id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
     }
     async fn create_db(path: &str, reg_enabled: bool) -> Result<Self, Error> {
         sqlx::Sqlite::create_database(path).await?;
@@ -83,23 +592,17 @@ impl Sql {
             "
             create table if not exists Challenges (
                 UserId integer default 0,
+                ShipId integer default 0,
                 Challenge integer default 0,
                 Until integer default 0
             );
         ",
         )
         .await?;
-        conn.execute(
-            "
-            create table if not exists Ships (
-                PSK blob
-            );
-        ",
-        )
-        .await?;
         Ok(Self {
             connection: conn,
             registration_enabled: reg_enabled,
+            changes: tokio::sync::broadcast::channel(1024).0,
         })
     }
     pub async fn get_sega_user(
@@ -107,7 +610,9 @@ impl Sql {
         username: &str,
         password: &str,
         ip: Ipv4Addr,
+        platform: PacketType,
     ) -> Result<User, Error> {
+        let _timer = METRICS.time_query("get_sega_user");
         if username.is_empty() || password.is_empty() {
             return Err(Error::InvalidData);
         }
@@ -138,17 +643,28 @@ impl Sql {
                 match login_result {
                     Ok(_) => {}
                     Err(e) => {
-                        self.put_login(id, ip, LoginResult::LoginError).await?;
+                        self.put_login(id, ip, platform, LoginResult::LoginError)
+                            .await?;
                         return Err(e);
                     }
                 }
-                self.put_login(id, ip, LoginResult::Successful).await?;
-                let user_data: UserData = rmp_serde::from_slice(data.try_get("Data")?)?;
+                // credentials left on a merged account log into the target account
+                let (id, user_data) = match self.merged_into(id).await? {
+                    Some(into) => (into, self.get_userdata(into).await?),
+                    None => (id, rmp_serde::from_slice(data.try_get("Data")?)?),
+                };
+                if let Some(ban) = self.get_ban(id).await? {
+                    self.put_login(id, ip, platform, LoginResult::LoginError)
+                        .await?;
+                    return Err(Error::Banned(ban));
+                }
+                self.put_login(id, ip, platform, LoginResult::Successful)
+                    .await?;
                 Ok(User {
                     id,
                     nickname: user_data.nickname,
                     account_flags: user_data.flags,
-                    isgm: user_data.isgm,
+                    gm_level: user_data.gm_level(),
                     last_uuid: user_data.last_uuid,
                 })
             }
@@ -156,6 +672,7 @@ impl Sql {
         }
     }
     pub async fn get_user_info(&self, user_id: u32) -> Result<UserInfoPacket, Error> {
+        let _timer = METRICS.time_query("get_user_info");
         let Some(row) = sqlx::query("select * from Users where Id = ?")
             .bind(user_id as i64)
             .fetch_optional(&self.connection)
@@ -167,14 +684,19 @@ impl Sql {
         Ok(user_data.info)
     }
     pub async fn put_user_info(&self, user_id: u32, info: UserInfoPacket) -> Result<(), Error> {
+        let _timer = METRICS.time_query("put_user_info");
         self.update_userdata(user_id, |user_data| user_data.info = info)
             .await
     }
     pub async fn put_account_flags(&self, user_id: u32, flags: Flags) -> Result<(), Error> {
+        let _timer = METRICS.time_query("put_account_flags");
         self.update_userdata(user_id, |user_data| user_data.flags = flags)
             .await
     }
-    pub async fn new_challenge(&self, user_id: u32) -> Result<u32, Error> {
+    /// Creates a block login challenge for the user on the ship. Previous challenges of the user
+    /// are invalidated.
+    pub async fn new_challenge(&self, user_id: u32, ship_id: u32) -> Result<u32, Error> {
+        let _timer = METRICS.time_query("new_challenge");
         if sqlx::query("select * from Users where Id = ?")
             .bind(user_id as i64)
             .fetch_optional(&self.connection)
@@ -187,17 +709,27 @@ impl Sql {
         let until = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
-            .add(Duration::from_secs(60))
+            .add(CHALLENGE_LIFETIME)
             .as_secs();
-        sqlx::query("insert into Challenges (UserId, Challenge, Until) values (?, ?, ?)")
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("delete from Challenges where UserId = ?")
             .bind(user_id as i64)
-            .bind(challenge as i64)
-            .bind(until as i64)
-            .execute(&self.connection)
+            .execute(&mut *transaction)
             .await?;
+        sqlx::query(
+            "insert into Challenges (UserId, ShipId, Challenge, Until) values (?, ?, ?, ?)",
+        )
+        .bind(user_id as i64)
+        .bind(ship_id as i64)
+        .bind(challenge as i64)
+        .bind(until as i64)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
         Ok(challenge)
     }
     pub async fn drop_challenges(&self) -> Result<(), Error> {
+        let _timer = METRICS.time_query("drop_challenges");
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -208,11 +740,18 @@ impl Sql {
             .await?;
         Ok(())
     }
-    pub async fn login_challenge(&self, user_id: u32, challenge: u32) -> Result<User, Error> {
+    /// Logs in the user with the challenge. Challenges can only be used once and only on the
+    /// ship that requested them.
+    pub async fn login_challenge(
+        &self,
+        user_id: u32,
+        ship_id: u32,
+        challenge: u32,
+    ) -> Result<User, Error> {
+        let _timer = METRICS.time_query("login_challenge");
         self.drop_challenges().await?;
-        let rows = sqlx::query("select * from Challenges where (UserId = ? and Challenge = ?)")
+        let rows = sqlx::query("select rowid as RowId, * from Challenges where UserId = ?")
             .bind(user_id as i64)
-            .bind(challenge as i64)
             .fetch_all(&self.connection)
             .await?;
         let now = SystemTime::now()
@@ -220,10 +759,26 @@ impl Sql {
             .unwrap()
             .as_secs();
         for row in rows {
+            let stored = row.try_get::<i64, _>("Challenge")? as u32;
+            if !constant_time_eq(&stored.to_le_bytes(), &challenge.to_le_bytes()) {
+                continue;
+            }
             let until = row.try_get::<i64, _>("Until")? as u64;
-            if until < now {
+            if until < now || row.try_get::<i64, _>("ShipId")? as u32 != ship_id {
+                continue;
+            }
+            // consume the challenge, concurrent logins with the same challenge will fail here
+            let deleted = sqlx::query("delete from Challenges where rowid = ?")
+                .bind(row.try_get::<i64, _>("RowId")?)
+                .execute(&self.connection)
+                .await?
+                .rows_affected();
+            if deleted == 0 {
                 continue;
             }
+            if let Some(ban) = self.get_ban(user_id).await? {
+                return Err(Error::Banned(ban));
+            }
             let row = sqlx::query("select * from Users where Id = ?")
                 .bind(user_id as i64)
                 .fetch_one(&self.connection)
@@ -233,13 +788,19 @@ impl Sql {
                 id: user_id,
                 nickname: user_data.nickname,
                 account_flags: user_data.flags,
-                isgm: user_data.isgm,
+                gm_level: user_data.gm_level(),
                 last_uuid: user_data.last_uuid,
             });
         }
         Err(Error::NoUser)
     }
-    pub async fn get_psn_user(&self, username: &str, ip: Ipv4Addr) -> Result<User, Error> {
+    pub async fn get_psn_user(
+        &self,
+        username: &str,
+        ip: Ipv4Addr,
+        platform: PacketType,
+    ) -> Result<User, Error> {
+        let _timer = METRICS.time_query("get_psn_user");
         if username.is_empty() {
             return Err(Error::InvalidData);
         }
@@ -250,25 +811,36 @@ impl Sql {
         match row {
             Some(data) => {
                 let id = data.try_get::<i64, _>("Id")? as u32;
-                let user_data: UserData = rmp_serde::from_slice(data.try_get("Data")?)?;
-                self.put_login(id, ip, LoginResult::Successful).await?;
+                let (id, user_data): (_, UserData) = match self.merged_into(id).await? {
+                    Some(into) => (into, self.get_userdata(into).await?),
+                    None => (id, rmp_serde::from_slice(data.try_get("Data")?)?),
+                };
+                if let Some(ban) = self.get_ban(id).await? {
+                    self.put_login(id, ip, platform, LoginResult::LoginError)
+                        .await?;
+                    return Err(Error::Banned(ban));
+                }
+                self.put_login(id, ip, platform, LoginResult::Successful)
+                    .await?;
                 Ok(User {
                     id,
                     nickname: user_data.nickname,
                     account_flags: user_data.flags,
-                    isgm: user_data.isgm,
+                    gm_level: user_data.gm_level(),
                     last_uuid: user_data.last_uuid,
                 })
             }
             None => Err(Error::NoUser),
         }
     }
-    pub async fn create_psn_user(&self, username: &str) -> Result<User, Error> {
+    pub async fn create_psn_user(
+        &self,
+        username: &str,
+        defaults: &AccountDefaults,
+    ) -> Result<User, Error> {
+        let _timer = METRICS.time_query("create_psn_user");
         let mut transaction = self.connection.begin().await?;
-        let user_data = UserData {
-            last_uuid: 1,
-            ..Default::default()
-        };
+        let user_data = defaults.user_data();
         let id = sqlx::query(
             "insert into Users (Username, Password, PSNUsername, Data) values (?, ?, ?, ?) 
             returning Id",
@@ -281,35 +853,30 @@ impl Sql {
         .await?
         .try_get::<i64, _>("Id")? as u32;
         transaction.commit().await?;
+        self.user_changed(id);
 
         Ok(User {
             id,
             nickname: user_data.nickname,
             account_flags: user_data.flags,
-            isgm: user_data.isgm,
+            gm_level: user_data.gm_level(),
             last_uuid: user_data.last_uuid,
         })
     }
-    pub async fn create_sega_user(&self, username: &str, password: &str) -> Result<User, Error> {
-        // SAFETY: references do not outlive the scope because the thread is immediately
-        // joined
-        let password: &'static str = unsafe { std::mem::transmute(password) };
-        let hash = tokio::task::spawn_blocking(|| {
-            let salt = SaltString::generate(&mut OsRng);
-            let argon2 = Argon2::default();
-            match argon2.hash_password(password.as_bytes(), &salt) {
-                Ok(x) => Ok(x.to_string()),
-                Err(_) => Err(Error::HashError),
-            }
-        })
-        .await
-        .unwrap()?;
+    pub async fn create_sega_user(
+        &self,
+        username: &str,
+        password: &str,
+        defaults: &AccountDefaults,
+    ) -> Result<User, Error> {
+        let _timer = METRICS.time_query("create_sega_user");
+        if password.starts_with(RESET_PREFIX) {
+            return Err(Error::InvalidData);
+        }
+        let hash = hash_password(password).await?;
 
         let mut transaction = self.connection.begin().await?;
-        let user_data = UserData {
-            last_uuid: 1,
-            ..Default::default()
-        };
+        let user_data = defaults.user_data();
         let id = sqlx::query(
             "insert into Users (Username, Password, PSNUsername, Data) values (?, ?, ?, ?) 
             returning Id",
@@ -322,65 +889,153 @@ impl Sql {
         .await?
         .try_get::<i64, _>("Id")? as u32;
         transaction.commit().await?;
+        self.user_changed(id);
 
         Ok(User {
             id,
             nickname: user_data.nickname,
             account_flags: user_data.flags,
-            isgm: user_data.isgm,
+            gm_level: user_data.gm_level(),
             last_uuid: user_data.last_uuid,
         })
     }
-    pub async fn get_logins(&self, id: u32) -> Result<Vec<LoginAttempt>, Error> {
+    /// Returns the latest login attempts of the account, newest first.
+    pub async fn get_logins(&self, id: u32, limit: u32) -> Result<Vec<LoginEntry>, Error> {
+        let _timer = METRICS.time_query("get_logins");
         let mut attempts = vec![];
         let rows =
-            sqlx::query("select * from Logins where UserId = ? order by Timestamp desc limit 50")
+            sqlx::query("select * from Logins where UserId = ? order by Timestamp desc limit ?")
                 .bind(id as i64)
+                .bind(limit as i64)
                 .fetch_all(&self.connection)
                 .await?;
         for row in rows {
-            attempts.push(LoginAttempt {
+            attempts.push(LoginEntry {
                 ip: rmp_serde::from_slice(row.try_get("IpAddress")?)?,
                 status: rmp_serde::from_slice(row.try_get("Status")?)?,
+                platform: read_platform(&row)?,
                 timestamp: Duration::from_secs(row.try_get::<i64, _>("Timestamp")? as u64),
-                ..Default::default()
             })
         }
         Ok(attempts)
     }
-    async fn put_login(&self, id: u32, ip: Ipv4Addr, status: LoginResult) -> Result<(), Error> {
+    /// Returns the latest login attempts of all users.
+    pub async fn get_recent_logins(&self, limit: u32) -> Result<Vec<RecentLogin>, Error> {
+        let _timer = METRICS.time_query("get_recent_logins");
+        let rows = sqlx::query(
+            "select Logins.*, Users.Username from Logins left join Users on Users.Id = \
+            Logins.UserId order by Logins.Timestamp desc limit ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.connection)
+        .await?;
+        let mut logins = vec![];
+        for row in rows {
+            let username: Option<&[u8]> = row.try_get("Username")?;
+            logins.push(RecentLogin {
+                user_id: row.try_get::<i64, _>("UserId")? as u32,
+                username: from_utf8(username.unwrap_or_default())?.to_string(),
+                ip: rmp_serde::from_slice(row.try_get("IpAddress")?)?,
+                status: rmp_serde::from_slice(row.try_get("Status")?)?,
+                platform: read_platform(&row)?,
+                timestamp: Duration::from_secs(row.try_get::<i64, _>("Timestamp")? as u64),
+            })
+        }
+        Ok(logins)
+    }
+    async fn put_login(
+        &self,
+        id: u32,
+        ip: Ipv4Addr,
+        platform: PacketType,
+        status: LoginResult,
+    ) -> Result<(), Error> {
         let timestamp_int = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
         sqlx::query(
-            "insert into Logins (UserId, IpAddress, Status, Timestamp) values (?, ?, ?, ?)",
+            "insert into Logins (UserId, IpAddress, Status, Platform, Timestamp) \
+            values (?, ?, ?, ?, ?)",
         )
         .bind(id as i64)
         .bind(rmp_serde::to_vec(&ip)?)
         .bind(rmp_serde::to_vec(&status)?)
+        .bind(rmp_serde::to_vec(&platform)?)
         .bind(timestamp_int as i64)
         .execute(&self.connection)
         .await?;
         Ok(())
     }
-    pub async fn get_account_storage(&self, user_id: u32) -> Result<AccountStorages, Error> {
+    /// Returns account storages and their version.
+    pub async fn get_account_storage(&self, user_id: u32) -> Result<(AccountStorages, u64), Error> {
+        let _timer = METRICS.time_query("get_account_storage");
         let row = sqlx::query("select Data from Users where Id = ?")
             .bind(user_id as i64)
             .fetch_one(&self.connection)
             .await?;
         let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
-        Ok(user_data.storage)
+        Ok((user_data.storage, user_data.storage_version))
     }
+    /// Saves account storages if they weren't changed since `version`.
     pub async fn put_account_storage(
         &self,
         user_id: u32,
-        storage: AccountStorages,
+        mut storage: AccountStorages,
+        version: u64,
+    ) -> Result<PutStorageResult, Error> {
+        let _timer = METRICS.time_query("put_account_storage");
+        let mut transaction = self.connection.begin().await?;
+        let row = sqlx::query("select Data from Users where Id = ?")
+            .bind(user_id as i64)
+            .fetch_one(&mut *transaction)
+            .await?;
+        let mut user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        if user_data.storage_version != version {
+            return Ok(PutStorageResult::Conflict {
+                storage: user_data.storage,
+                version: user_data.storage_version,
+            });
+        }
+        // capacities are managed only by the master ship
+        storage.copy_capacities(&user_data.storage);
+        user_data.storage = storage;
+        user_data.storage_version += 1;
+        sqlx::query("update Users set Data = ? where Id = ?")
+            .bind(rmp_serde::to_vec(&user_data)?)
+            .bind(user_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        self.user_changed(user_id);
+        Ok(PutStorageResult::Ok {
+            version: user_data.storage_version,
+        })
+    }
+    /// Sets the capacity of the storage bank (0 - default, 1 - premium, 2 - extended).
+    /// Zero capacity disables the bank.
+    pub async fn set_storage_capacity(
+        &self,
+        user_id: u32,
+        bank: u8,
+        capacity: u32,
     ) -> Result<(), Error> {
-        self.update_userdata(user_id, |user_data| user_data.storage = storage)
-            .await
+        let _timer = METRICS.time_query("set_storage_capacity");
+        if bank > 2 {
+            return Err(Error::InvalidData);
+        }
+        self.get_account_info(user_id).await?;
+        self.update_userdata(user_id, |user_data| {
+            if let Some(storage) = user_data.storage.bank_mut(bank) {
+                storage.set_capacity(capacity, capacity != 0);
+            }
+            // make ships reload the storage on the next write
+            user_data.storage_version += 1;
+        })
+        .await
     }
     pub async fn get_settings(&self, id: u32) -> Result<AsciiString, Error> {
+        let _timer = METRICS.time_query("get_settings");
         let row = sqlx::query("select Data from Users where Id = ?")
             .bind(id as i64)
             .fetch_one(&self.connection)
@@ -389,49 +1044,1523 @@ impl Sql {
         Ok(user_data.settings.into())
     }
     pub async fn save_settings(&self, id: u32, settings: &str) -> Result<(), Error> {
+        let _timer = METRICS.time_query("save_settings");
         self.update_userdata(id, |user_data| user_data.settings = settings.into())
             .await
     }
+    pub async fn export_account(&self, user_id: u32) -> Result<AccountExport, Error> {
+        let _timer = METRICS.time_query("export_account");
+        let row = sqlx::query("select Data from Users where Id = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?
+            .ok_or(Error::NoUser)?;
+        let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        Ok(AccountExport {
+            settings: user_data.settings,
+            storage: user_data.storage,
+        })
+    }
+    /// Replaces storage and settings of the account. Imported items get new UUIDs so that they
+    /// don't collide with items created after the export. Ships reload the storage on their next
+    /// write.
+    pub async fn import_account(&self, user_id: u32, data: AccountExport) -> Result<(), Error> {
+        let _timer = METRICS.time_query("import_account");
+        let mut storage = data.storage;
+        self.update_userdata(user_id, |user_data| {
+            storage.renumber(&mut user_data.last_uuid);
+            user_data.settings = data.settings;
+            user_data.storage = storage;
+            user_data.storage_version += 1;
+        })
+        .await
+    }
     pub async fn put_uuid(&self, user_id: u32, uuid: u64) -> Result<(), Error> {
-        self.update_userdata(user_id, |user_data| user_data.last_uuid = uuid)
-            .await
+        let _timer = METRICS.time_query("put_uuid");
+        // reserved UUIDs must not be handed out again
+        self.update_userdata(user_id, |user_data| {
+            user_data.last_uuid = user_data.last_uuid.max(uuid)
+        })
+        .await
+    }
+    /// Reserves `count` item UUIDs of the account. Returns the first reserved UUID.
+    pub async fn reserve_uuids(&self, user_id: u32, count: u64) -> Result<u64, Error> {
+        let _timer = METRICS.time_query("reserve_uuids");
+        let mut first = 0;
+        self.update_userdata(user_id, |user_data| {
+            first = user_data.last_uuid;
+            user_data.last_uuid += count;
+        })
+        .await?;
+        Ok(first)
     }
 
-    pub async fn get_ship_data(&self, psk: &[u8]) -> Result<bool, Error> {
-        let count = sqlx::query("select count(*) from Ships where PSK = ?")
+    /// Returns the ship key with this PSK.
+    pub async fn get_ship_key(&self, psk: &[u8]) -> Result<Option<ShipKey>, Error> {
+        let _timer = METRICS.time_query("get_ship_key");
+        let row = sqlx::query("select * from ShipKeys where PSK = ?")
             .bind(psk)
-            .fetch_one(&self.connection)
-            .await?
-            .try_get::<i64, _>(0)?;
-        Ok(count != 0)
+            .fetch_optional(&self.connection)
+            .await?;
+        row.as_ref().map(row_to_ship_key).transpose()
     }
     pub fn registration_enabled(&self) -> bool {
         self.registration_enabled
     }
-    pub async fn put_ship_data(&self, psk: &[u8]) -> Result<(), Error> {
-        sqlx::query("insert into Ships (PSK) values (?)")
-            .bind(psk)
-            .execute(&self.connection)
-            .await?;
-        Ok(())
+    /// Adds the ship key or updates the existing key with the same PSK.
+    pub async fn put_ship_key(&self, key: &ShipKey) -> Result<(), Error> {
+        let _timer = METRICS.time_query("put_ship_key");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        sqlx::query(
+            "insert into ShipKeys (Name, PSK, Revoked, Timestamp, Blocked) values (?, ?, ?, ?, ?)
+            on conflict (PSK) do update
+            set Name = excluded.Name, Revoked = excluded.Revoked, Blocked = excluded.Blocked",
+        )
+        .bind(key.name.as_bytes())
+        .bind(&key.psk)
+        .bind(key.revoked)
+        .bind(now as i64)
+        .bind(key.blocked)
+        .execute(&self.connection)
+        .await?;
+        let _ = self.changes.send(Change::Ship(key.clone()));
+        Ok(())
     }
-    pub async fn set_nickname(&self, user_id: u32, nickname: &str) -> Result<bool, Error> {
+    /// Creates a new PSK for the named ship, revoking the previous ones.
+    pub async fn new_ship_key(&self, name: &str) -> Result<String, Error> {
+        let _timer = METRICS.time_query("new_ship_key");
+        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        if name.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        // error means that there were no keys to revoke
+        let _ = self.revoke_ship_keys(name).await;
+        let psk = random_string(CHARSET, 32);
+        self.put_ship_key(&ShipKey {
+            name: name.to_string(),
+            psk: psk.as_bytes().to_vec(),
+            revoked: false,
+            blocked: false,
+        })
+        .await?;
+        Ok(psk)
+    }
+    /// Revokes all PSKs of the named ship.
+    pub async fn revoke_ship_keys(&self, name: &str) -> Result<(), Error> {
+        let _timer = METRICS.time_query("revoke_ship_keys");
+        let keys: Vec<_> = self
+            .get_ship_keys()
+            .await?
+            .into_iter()
+            .filter(|k| k.name == name && !k.revoked)
+            .collect();
+        if keys.is_empty() {
+            return Err(Error::UnknownShip);
+        }
+        for mut key in keys {
+            key.revoked = true;
+            self.put_ship_key(&key).await?;
+        }
+        Ok(())
+    }
+    /// Blocks or unblocks all PSKs of the named ship.
+    pub async fn set_ship_blocked(&self, name: &str, blocked: bool) -> Result<(), Error> {
+        let _timer = METRICS.time_query("set_ship_blocked");
+        let keys: Vec<_> = self
+            .get_ship_keys()
+            .await?
+            .into_iter()
+            .filter(|k| k.name == name)
+            .collect();
+        if keys.is_empty() {
+            return Err(Error::UnknownShip);
+        }
+        for mut key in keys.into_iter().filter(|k| k.blocked != blocked) {
+            key.blocked = blocked;
+            self.put_ship_key(&key).await?;
+        }
+        Ok(())
+    }
+    /// Writes a consistent copy of the database to the path.
+    pub async fn backup(&self, path: &str) -> Result<(), Error> {
+        let _timer = METRICS.time_query("backup");
+        sqlx::query("vacuum into ?")
+            .bind(path)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+    /// Subscribes to changes that should be replicated to standby master ships.
+    pub fn subscribe_changes(&self) -> tokio::sync::broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
+    fn user_changed(&self, user_id: u32) {
+        // error means that there are no standby master ships
+        let _ = self.changes.send(Change::User(user_id));
+    }
+    fn table_changed(&self, table: &'static str) {
+        let _ = self.changes.send(Change::Table(table));
+    }
+    /// Returns all rows of the replicated table.
+    pub async fn get_replicated_table(&self, name: &str) -> Result<ReplicatedTable, Error> {
+        let _timer = METRICS.time_query("get_replicated_table");
+        if !REPLICATED_TABLES.contains(&name) {
+            return Err(Error::InvalidData);
+        }
+        let rows = sqlx::query(&format!("select * from {name}"))
+            .fetch_all(&self.connection)
+            .await?;
+        let columns = sqlx::query("select name from pragma_table_info(?) order by cid")
+            .bind(name)
+            .fetch_all(&self.connection)
+            .await?
+            .iter()
+            .map(|r| r.try_get::<String, _>("name"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut table = ReplicatedTable {
+            name: name.to_string(),
+            columns,
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let mut values = Vec::with_capacity(table.columns.len());
+            for i in 0..table.columns.len() {
+                let raw = row.try_get_raw(i)?;
+                let value = if raw.is_null() {
+                    ReplicatedValue::Null
+                } else if raw.type_info().name() == "INTEGER" {
+                    ReplicatedValue::Integer(row.try_get(i)?)
+                } else {
+                    ReplicatedValue::Blob(row.try_get(i)?)
+                };
+                values.push(value);
+            }
+            table.rows.push(values);
+        }
+        Ok(table)
+    }
+    /// Replaces all rows of the table with the ones received from the primary master ship.
+    pub async fn put_replicated_table(&self, table: &ReplicatedTable) -> Result<(), Error> {
+        let _timer = METRICS.time_query("put_replicated_table");
+        let Some(name) = REPLICATED_TABLES.iter().find(|t| **t == table.name) else {
+            return Err(Error::InvalidData);
+        };
+        let known_columns = sqlx::query("select name from pragma_table_info(?)")
+            .bind(*name)
+            .fetch_all(&self.connection)
+            .await?
+            .iter()
+            .map(|r| r.try_get::<String, _>("name"))
+            .collect::<Result<Vec<_>, _>>()?;
+        if table.columns.is_empty() || table.columns.iter().any(|c| !known_columns.contains(c)) {
+            return Err(Error::InvalidData);
+        }
+        let insert = format!(
+            "insert into {name} ({}) values ({})",
+            table.columns.join(", "),
+            vec!["?"; table.columns.len()].join(", ")
+        );
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query(&format!("delete from {name}"))
+            .execute(&mut *transaction)
+            .await?;
+        for row in &table.rows {
+            if row.len() != table.columns.len() {
+                return Err(Error::InvalidData);
+            }
+            let mut query = sqlx::query(&insert);
+            for value in row {
+                query = match value {
+                    ReplicatedValue::Null => query.bind(None::<i64>),
+                    ReplicatedValue::Integer(x) => query.bind(*x),
+                    ReplicatedValue::Blob(x) => query.bind(x.as_slice()),
+                };
+            }
+            query.execute(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+    /// Returns the term of the primary master ship. It's increased on each failover.
+    pub async fn get_term(&self) -> Result<u64, Error> {
+        let _timer = METRICS.time_query("get_term");
+        let row = sqlx::query("select Term from Replication")
+            .fetch_optional(&self.connection)
+            .await?;
+        Ok(row
+            .map(|r| r.try_get::<i64, _>("Term"))
+            .transpose()?
+            .unwrap_or(0) as u64)
+    }
+    pub async fn set_term(&self, term: u64) -> Result<(), Error> {
+        let _timer = METRICS.time_query("set_term");
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("delete from Replication")
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("insert into Replication (Term) values (?)")
+            .bind(term as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    pub async fn get_replicated_user(&self, user_id: u32) -> Result<Option<ReplicatedUser>, Error> {
+        let _timer = METRICS.time_query("get_replicated_user");
+        let row = sqlx::query("select * from Users where Id = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?;
+        row.as_ref().map(row_to_replicated_user).transpose()
+    }
+    pub async fn get_replicated_users(&self) -> Result<Vec<ReplicatedUser>, Error> {
+        let _timer = METRICS.time_query("get_replicated_users");
         let rows = sqlx::query("select * from Users")
             .fetch_all(&self.connection)
             .await?;
-        for row in rows {
-            let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
-            if user_data.nickname == nickname {
-                return Ok(false);
-            }
+        rows.iter().map(row_to_replicated_user).collect()
+    }
+    /// Replaces the account with the one received from the primary master ship.
+    pub async fn put_replicated_user(&self, user: &ReplicatedUser) -> Result<(), Error> {
+        let _timer = METRICS.time_query("put_replicated_user");
+        let user_data: UserData = rmp_serde::from_slice(&user.data)?;
+        let nickname = nickname_column(&user_data.nickname);
+        let mut transaction = self.connection.begin().await?;
+        // the previous owner of the nickname may not be replicated yet
+        sqlx::query("update Users set Nickname = NULL where Nickname = ? and Id != ?")
+            .bind(nickname)
+            .bind(user.id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "insert or replace into Users (Id, Username, Password, PSNUsername, Data, Nickname) 
+            values (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(user.id as i64)
+        .bind(&user.username)
+        .bind(&user.password)
+        .bind(&user.psn_username)
+        .bind(&user.data)
+        .bind(nickname)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    pub async fn get_ship_keys(&self) -> Result<Vec<ShipKey>, Error> {
+        let _timer = METRICS.time_query("get_ship_keys");
+        let rows = sqlx::query("select * from ShipKeys")
+            .fetch_all(&self.connection)
+            .await?;
+        rows.iter().map(row_to_ship_key).collect()
+    }
+    pub async fn set_nickname(&self, user_id: u32, nickname: &str) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("set_nickname");
+        let result = self
+            .update_userdata(user_id, |user_data| {
+                user_data.nickname = nickname.to_string()
+            })
+            .await;
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if is_unique_violation(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_account_info(&self, user_id: u32) -> Result<AccountInfo, Error> {
+        let _timer = METRICS.time_query("get_account_info");
+        let Some(row) = sqlx::query("select * from Users where Id = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?
+        else {
+            return Err(Error::NoUser);
+        };
+        row_to_account_info(&row)
+    }
+    /// Finds an account by its SEGA ID, PSN username or nickname.
+    pub async fn find_account(&self, name: &str) -> Result<AccountInfo, Error> {
+        let _timer = METRICS.time_query("find_account");
+        if name.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        if let Some(row) = sqlx::query("select * from Users where Username = ? or PSNUsername = ?")
+            .bind(name.as_bytes())
+            .bind(name.as_bytes())
+            .fetch_optional(&self.connection)
+            .await?
+        {
+            return row_to_account_info(&row);
+        }
+        match sqlx::query("select * from Users where Nickname = ?")
+            .bind(name)
+            .fetch_optional(&self.connection)
+            .await?
+        {
+            Some(row) => row_to_account_info(&row),
+            None => Err(Error::NoUser),
+        }
+    }
+    pub async fn set_password(&self, user_id: u32, password: &str) -> Result<(), Error> {
+        let _timer = METRICS.time_query("set_password");
+        if password.is_empty() || password.starts_with(RESET_PREFIX) {
+            return Err(Error::InvalidData);
+        }
+        let hash = hash_password(password).await?;
+        let result = sqlx::query("update Users set Password = ? where Id = ?")
+            .bind(hash.as_bytes())
+            .bind(user_id as i64)
+            .execute(&self.connection)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::NoUser);
+        }
+        self.user_changed(user_id);
+        Ok(())
+    }
+    /// Checks the current password of the SEGA ID user.
+    pub async fn check_password(&self, user_id: u32, password: &str) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("check_password");
+        let Some(row) = sqlx::query("select Password from Users where Id = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?
+        else {
+            return Err(Error::NoUser);
+        };
+        let stored = from_utf8(row.try_get("Password")?)?.to_string();
+        // PSN accounts have no password
+        if stored.is_empty() {
+            return Ok(false);
+        }
+        let password = password.to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&stored).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap();
+        Ok(result)
+    }
+    /// Creates a challenge that allows the re-authenticated user to change the password.
+    pub async fn new_password_challenge(&self, user_id: u32) -> Result<String, Error> {
+        let _timer = METRICS.time_query("new_password_challenge");
+        self.new_email_code(user_id, CodeKind::PasswordChange).await
+    }
+    /// Sets a new password if the re-authentication challenge is correct.
+    pub async fn change_password(
+        &self,
+        user_id: u32,
+        challenge: &str,
+        password: &str,
+    ) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("change_password");
+        if password.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        if !self
+            .use_email_code(user_id, CodeKind::PasswordChange, challenge)
+            .await?
+        {
+            return Ok(false);
+        }
+        self.set_password(user_id, password).await?;
+        Ok(true)
+    }
+    /// Changes the nickname of the user if it isn't used by other accounts (case insensitive)
+    /// and the previous change was at least `cooldown` ago.
+    pub async fn change_nickname(
+        &self,
+        user_id: u32,
+        nickname: &str,
+        cooldown: Duration,
+    ) -> Result<ChangeNicknameResult, Error> {
+        let _timer = METRICS.time_query("change_nickname");
+        if nickname.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let changed_at = self.get_userdata(user_id).await?.nickname_changed;
+        let taken = sqlx::query("select Id from Users where Nickname = ? and Id != ?")
+            .bind(nickname)
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?
+            .is_some();
+        if taken {
+            return Ok(ChangeNicknameResult::AlreadyTaken);
+        }
+        let next_change = changed_at.saturating_add(cooldown.as_secs());
+        if changed_at != 0 && next_change > now {
+            return Ok(ChangeNicknameResult::Cooldown(Duration::from_secs(
+                next_change - now,
+            )));
+        }
+        let result = self
+            .update_userdata(user_id, |user_data| {
+                user_data.nickname = nickname.to_string();
+                user_data.nickname_changed = now;
+            })
+            .await;
+        match result {
+            Ok(()) => Ok(ChangeNicknameResult::Ok),
+            // the nickname was taken after the check
+            Err(e) if is_unique_violation(&e) => Ok(ChangeNicknameResult::AlreadyTaken),
+            Err(e) => Err(e),
+        }
+    }
+    /// Sets GM permission level of the user (see [`gm_level`]).
+    pub async fn set_gm_level(&self, user_id: u32, level: u8) -> Result<(), Error> {
+        let _timer = METRICS.time_query("set_gm_level");
+        if level > gm_level::ADMIN {
+            return Err(Error::InvalidData);
+        }
+        self.get_account_info(user_id).await?;
+        self.update_userdata(user_id, |user_data| {
+            user_data.isgm = false;
+            user_data.gm_level = level;
+        })
+        .await
+    }
+
+    /// Bans the user. If `until` is `None` then the ban is permanent.
+    pub async fn ban_user(
+        &self,
+        user_id: u32,
+        reason: &str,
+        until: Option<Duration>,
+    ) -> Result<(), Error> {
+        let _timer = METRICS.time_query("ban_user");
+        self.get_account_info(user_id).await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        sqlx::query("insert into Bans (UserId, Reason, Until, Timestamp) values (?, ?, ?, ?)")
+            .bind(user_id as i64)
+            .bind(reason.as_bytes())
+            .bind(until.map(|u| u.as_secs() as i64))
+            .bind(now as i64)
+            .execute(&self.connection)
+            .await?;
+        self.table_changed("Bans");
+        Ok(())
+    }
+    /// Removes all bans of the user.
+    pub async fn unban_user(&self, user_id: u32) -> Result<(), Error> {
+        let _timer = METRICS.time_query("unban_user");
+        sqlx::query("delete from Bans where UserId = ?")
+            .bind(user_id as i64)
+            .execute(&self.connection)
+            .await?;
+        self.table_changed("Bans");
+        Ok(())
+    }
+    /// Returns the longest active ban of the user.
+    pub async fn get_ban(&self, user_id: u32) -> Result<Option<Ban>, Error> {
+        let _timer = METRICS.time_query("get_ban");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let row = sqlx::query(
+            "select * from Bans where UserId = ? and (Until is null or Until > ?) 
+            order by Until is null desc, Until desc limit 1",
+        )
+        .bind(user_id as i64)
+        .bind(now as i64)
+        .fetch_optional(&self.connection)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(Ban {
+            user_id,
+            reason: from_utf8(row.try_get("Reason")?)?.to_string(),
+            until: row
+                .try_get::<Option<i64>, _>("Until")?
+                .map(|u| Duration::from_secs(u as u64)),
+        }))
+    }
+
+    /// Attaches a GM note to the account.
+    pub async fn add_account_note(
+        &self,
+        user_id: u32,
+        author_id: u32,
+        note: &str,
+    ) -> Result<(), Error> {
+        let _timer = METRICS.time_query("add_account_note");
+        if note.trim().is_empty() {
+            return Err(Error::InvalidData);
+        }
+        self.get_account_info(user_id).await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        sqlx::query(
+            "insert into AccountNotes (UserId, AuthorId, Note, Timestamp) values (?, ?, ?, ?)",
+        )
+        .bind(user_id as i64)
+        .bind(author_id as i64)
+        .bind(note.trim().as_bytes())
+        .bind(now as i64)
+        .execute(&self.connection)
+        .await?;
+        self.table_changed("AccountNotes");
+        Ok(())
+    }
+    /// Mutes the chat of the user. GM level of the muting GM must be higher than the user's.
+    pub async fn set_mute(&self, user_id: u32, mute: &Mute) -> Result<(), Error> {
+        let _timer = METRICS.time_query("set_mute");
+        self.check_gm_over(mute.muted_by, user_id).await?;
+        sqlx::query(
+            "insert or replace into Mutes (UserId, Until, Reason, MutedBy) values (?, ?, ?, ?)",
+        )
+        .bind(user_id as i64)
+        .bind(mute.until.as_secs() as i64)
+        .bind(mute.reason.as_bytes())
+        .bind(mute.muted_by as i64)
+        .execute(&self.connection)
+        .await?;
+        self.table_changed("Mutes");
+        Ok(())
+    }
+    /// Removes the mute of the user. Returns false if the user wasn't muted.
+    pub async fn remove_mute(&self, user_id: u32, gm_id: u32) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("remove_mute");
+        self.check_gm_over(gm_id, user_id).await?;
+        let result = sqlx::query("delete from Mutes where UserId = ?")
+            .bind(user_id as i64)
+            .execute(&self.connection)
+            .await?;
+        self.table_changed("Mutes");
+        Ok(result.rows_affected() != 0)
+    }
+    /// Returns the mute of the user. The mute might have already ended.
+    pub async fn get_mute(&self, user_id: u32) -> Result<Option<Mute>, Error> {
+        let _timer = METRICS.time_query("get_mute");
+        let row = sqlx::query("select * from Mutes where UserId = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(Mute {
+            until: Duration::from_secs(row.try_get::<i64, _>("Until")? as u64),
+            reason: from_utf8(row.try_get("Reason")?)?.to_string(),
+            muted_by: row.try_get::<i64, _>("MutedBy")? as u32,
+        }))
+    }
+    /// Checks that the GM level of `gm_id` is higher than the level of `user_id`.
+    async fn check_gm_over(&self, gm_id: u32, user_id: u32) -> Result<(), Error> {
+        let gm_level = self.get_userdata(gm_id).await?.gm_level();
+        if gm_level <= self.get_userdata(user_id).await?.gm_level() {
+            return Err(Error::GmLevelTooLow);
+        }
+        Ok(())
+    }
+    /// Returns GM notes of the account, newest first.
+    pub async fn get_account_notes(&self, user_id: u32) -> Result<Vec<AccountNote>, Error> {
+        let _timer = METRICS.time_query("get_account_notes");
+        let rows = sqlx::query("select * from AccountNotes where UserId = ? order by Id desc")
+            .bind(user_id as i64)
+            .fetch_all(&self.connection)
+            .await?;
+        let mut notes = vec![];
+        for row in rows {
+            notes.push(AccountNote {
+                author_id: row.try_get::<i64, _>("AuthorId")? as u32,
+                note: from_utf8(row.try_get("Note")?)?.to_string(),
+                timestamp: Duration::from_secs(row.try_get::<i64, _>("Timestamp")? as u64),
+            })
+        }
+        Ok(notes)
+    }
+
+    /// Opens a new support ticket.
+    pub async fn new_ticket(
+        &self,
+        user_id: u32,
+        nickname: &str,
+        ship_id: u32,
+        message: &str,
+    ) -> Result<SupportTicket, Error> {
+        let _timer = METRICS.time_query("new_ticket");
+        if message.trim().is_empty() {
+            return Err(Error::InvalidData);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let id = sqlx::query(
+            "insert into SupportTickets (UserId, Nickname, ShipId, Message, Timestamp) \
+            values (?, ?, ?, ?, ?) returning Id",
+        )
+        .bind(user_id as i64)
+        .bind(nickname.as_bytes())
+        .bind(ship_id as i64)
+        .bind(message.trim().as_bytes())
+        .bind(now as i64)
+        .fetch_one(&self.connection)
+        .await?
+        .try_get::<i64, _>("Id")? as u32;
+        self.table_changed("SupportTickets");
+        self.get_ticket(id).await?.ok_or(Error::InvalidData)
+    }
+    pub async fn get_ticket(&self, id: u32) -> Result<Option<SupportTicket>, Error> {
+        let _timer = METRICS.time_query("get_ticket");
+        let row = sqlx::query("select * from SupportTickets where Id = ?")
+            .bind(id as i64)
+            .fetch_optional(&self.connection)
+            .await?;
+        row.as_ref().map(row_to_ticket).transpose()
+    }
+    /// Returns tickets that aren't resolved, oldest first.
+    pub async fn get_open_tickets(&self) -> Result<Vec<SupportTicket>, Error> {
+        let _timer = METRICS.time_query("get_open_tickets");
+        let rows = sqlx::query("select * from SupportTickets where Status != ? order by Id")
+            .bind(ticket_status_id(TicketStatus::Resolved))
+            .fetch_all(&self.connection)
+            .await?;
+        rows.iter().map(row_to_ticket).collect()
+    }
+    /// Assigns the unresolved ticket to the GM. Tickets claimed by other GMs can't be claimed.
+    pub async fn claim_ticket(&self, id: u32, gm_id: u32) -> Result<SupportTicket, Error> {
+        let _timer = METRICS.time_query("claim_ticket");
+        let result = sqlx::query(
+            "update SupportTickets set Status = ?, GmId = ? \
+            where Id = ? and Status != ? and (GmId is null or GmId = ?)",
+        )
+        .bind(ticket_status_id(TicketStatus::Claimed))
+        .bind(gm_id as i64)
+        .bind(id as i64)
+        .bind(ticket_status_id(TicketStatus::Resolved))
+        .bind(gm_id as i64)
+        .execute(&self.connection)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::InvalidData);
+        }
+        self.table_changed("SupportTickets");
+        self.get_ticket(id).await?.ok_or(Error::InvalidData)
+    }
+    /// Closes the unresolved ticket. Tickets claimed by other GMs can't be resolved.
+    pub async fn resolve_ticket(
+        &self,
+        id: u32,
+        gm_id: u32,
+        resolution: &str,
+    ) -> Result<SupportTicket, Error> {
+        let _timer = METRICS.time_query("resolve_ticket");
+        let result = sqlx::query(
+            "update SupportTickets set Status = ?, GmId = ?, Resolution = ? \
+            where Id = ? and Status != ? and (GmId is null or GmId = ?)",
+        )
+        .bind(ticket_status_id(TicketStatus::Resolved))
+        .bind(gm_id as i64)
+        .bind(resolution.trim().as_bytes())
+        .bind(id as i64)
+        .bind(ticket_status_id(TicketStatus::Resolved))
+        .bind(gm_id as i64)
+        .execute(&self.connection)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::InvalidData);
+        }
+        self.table_changed("SupportTickets");
+        self.get_ticket(id).await?.ok_or(Error::InvalidData)
+    }
+    /// Stores statistics reported by the ship.
+    pub async fn add_ship_stats(&self, ship_id: u32, stats: &ShipStats) -> Result<(), Error> {
+        let _timer = METRICS.time_query("add_ship_stats");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        sqlx::query(
+            "insert into ShipStats (ShipId, Timestamp, PeakPlayers, Quests, Meseta) \
+            values (?, ?, ?, ?, ?)",
+        )
+        .bind(ship_id as i64)
+        .bind(now as i64)
+        .bind(stats.peak_players as i64)
+        .bind(stats.quests as i64)
+        .bind(stats.meseta as i64)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+    /// Returns statistics reported in the last `period`, oldest first. If `ship_id` is `None`,
+    /// returns statistics of all ships.
+    pub async fn get_ship_stats(
+        &self,
+        ship_id: Option<u32>,
+        period: Duration,
+    ) -> Result<Vec<ShipStatsEntry>, Error> {
+        let _timer = METRICS.time_query("get_ship_stats");
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .saturating_sub(period)
+            .as_secs();
+        let rows = sqlx::query(
+            "select * from ShipStats where Timestamp >= ? and (? is null or ShipId = ?) \
+            order by Timestamp, ShipId",
+        )
+        .bind(since as i64)
+        .bind(ship_id.map(|id| id as i64))
+        .bind(ship_id.map(|id| id as i64))
+        .fetch_all(&self.connection)
+        .await?;
+        rows.iter().map(row_to_ship_stats).collect()
+    }
+    /// Returns statistics of all ships reported in the last `period` summed up by hour.
+    pub async fn get_network_stats(&self, period: Duration) -> Result<Vec<ShipStatsEntry>, Error> {
+        let _timer = METRICS.time_query("get_network_stats");
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .saturating_sub(period)
+            .as_secs();
+        let rows = sqlx::query(
+            "select 0 as ShipId, Timestamp / 3600 * 3600 as Timestamp, \
+            sum(PeakPlayers) as PeakPlayers, sum(Quests) as Quests, sum(Meseta) as Meseta \
+            from ShipStats where Timestamp >= ? group by Timestamp / 3600 order by Timestamp",
+        )
+        .bind(since as i64)
+        .fetch_all(&self.connection)
+        .await?;
+        rows.iter().map(row_to_ship_stats).collect()
+    }
+
+    /// Returns remaining lockout time for the key (username or IP address).
+    pub async fn get_login_lockout(&self, key: &str) -> Result<Option<Duration>, Error> {
+        let _timer = METRICS.time_query("get_login_lockout");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let row = sqlx::query("select LockedUntil from LoginThrottle where Key = ?")
+            .bind(key.as_bytes())
+            .fetch_optional(&self.connection)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let until = row.try_get::<i64, _>("LockedUntil")? as u64;
+        if until <= now {
+            return Ok(None);
+        }
+        Ok(Some(Duration::from_secs(until - now)))
+    }
+    /// Registers failed login attempt for the key and locks it if there were too many failures.
+    pub async fn add_login_failure(&self, key: &str, limits: &LoginLimits) -> Result<(), Error> {
+        let _timer = METRICS.time_query("add_login_failure");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut transaction = self.connection.begin().await?;
+        let row = sqlx::query("select * from LoginThrottle where Key = ?")
+            .bind(key.as_bytes())
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let mut failures = match row {
+            Some(row) => {
+                let last_failure = row.try_get::<i64, _>("LastFailure")? as u64;
+                if now.saturating_sub(last_failure) > limits.reset_after {
+                    0
+                } else {
+                    row.try_get::<i64, _>("Failures")? as u32
+                }
+            }
+            None => 0,
+        };
+        failures += 1;
+        let locked_until = if failures >= limits.max_attempts {
+            let multiplier = 2u64.saturating_pow(failures - limits.max_attempts);
+            now + limits
+                .base_lockout
+                .saturating_mul(multiplier)
+                .min(limits.max_lockout)
+        } else {
+            0
+        };
+        sqlx::query(
+            "insert or replace into LoginThrottle (Key, Failures, LastFailure, LockedUntil) 
+            values (?, ?, ?, ?)",
+        )
+        .bind(key.as_bytes())
+        .bind(failures as i64)
+        .bind(now as i64)
+        .bind(locked_until as i64)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    pub async fn reset_login_failures(&self, key: &str) -> Result<(), Error> {
+        let _timer = METRICS.time_query("reset_login_failures");
+        sqlx::query("delete from LoginThrottle where Key = ?")
+            .bind(key.as_bytes())
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns time until a new account can be registered from the IP address.
+    pub async fn get_registration_lockout(
+        &self,
+        ip: Ipv4Addr,
+        limits: &RegistrationLimits,
+    ) -> Result<Option<Duration>, Error> {
+        let _timer = METRICS.time_query("get_registration_lockout");
+        if limits.max_per_ip == 0 {
+            return Ok(None);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let rows = sqlx::query(
+            "select Timestamp from Registrations where Ip = ? and Timestamp > ? 
+            order by Timestamp asc",
+        )
+        .bind(&ip.octets()[..])
+        .bind(now.saturating_sub(limits.interval) as i64)
+        .fetch_all(&self.connection)
+        .await?;
+        if rows.len() < limits.max_per_ip as usize {
+            return Ok(None);
+        }
+        let oldest =
+            rows[rows.len() - limits.max_per_ip as usize].try_get::<i64, _>("Timestamp")?;
+        let until = oldest as u64 + limits.interval;
+        Ok(Some(Duration::from_secs(until.saturating_sub(now).max(1))))
+    }
+    /// Records account registration from the IP address.
+    pub async fn add_registration(
+        &self,
+        ip: Ipv4Addr,
+        limits: &RegistrationLimits,
+    ) -> Result<(), Error> {
+        let _timer = METRICS.time_query("add_registration");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        sqlx::query("delete from Registrations where Timestamp <= ?")
+            .bind(now.saturating_sub(limits.interval) as i64)
+            .execute(&self.connection)
+            .await?;
+        sqlx::query("insert into Registrations (Ip, Timestamp) values (?, ?)")
+            .bind(&ip.octets()[..])
+            .bind(now as i64)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Records password reset request for the username from the IP address. Returns `false`
+    /// (and doesn't record it) if the account or the address requested a reset too recently.
+    pub async fn add_reset_request(
+        &self,
+        username: &str,
+        ip: Ipv4Addr,
+        limits: &ResetLimits,
+    ) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("add_reset_request");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("delete from ResetRequests where Timestamp <= ?")
+            .bind(now.saturating_sub(limits.account_interval.max(limits.ip_interval)) as i64)
+            .execute(&mut *transaction)
+            .await?;
+        let account_requests =
+            sqlx::query("select count(*) from ResetRequests where Username = ? and Timestamp > ?")
+                .bind(username.as_bytes())
+                .bind(now.saturating_sub(limits.account_interval) as i64)
+                .fetch_one(&mut *transaction)
+                .await?
+                .try_get::<i64, _>(0)?;
+        let ip_requests =
+            sqlx::query("select count(*) from ResetRequests where Ip = ? and Timestamp > ?")
+                .bind(&ip.octets()[..])
+                .bind(now.saturating_sub(limits.ip_interval) as i64)
+                .fetch_one(&mut *transaction)
+                .await?
+                .try_get::<i64, _>(0)?;
+        if account_requests != 0 || ip_requests >= limits.max_per_ip as i64 {
+            return Ok(false);
+        }
+        sqlx::query("insert into ResetRequests (Username, Ip, Timestamp) values (?, ?, ?)")
+            .bind(username.as_bytes())
+            .bind(&ip.octets()[..])
+            .bind(now as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    /// Sets unverified email of the user and returns the verification code.
+    pub async fn set_email(&self, user_id: u32, email: &str) -> Result<String, Error> {
+        let _timer = METRICS.time_query("set_email");
+        if email.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        self.get_account_info(user_id).await?;
+        self.update_userdata(user_id, |user_data| {
+            user_data.email = email.to_string();
+            user_data.email_verified = false;
+        })
+        .await?;
+        self.new_email_code(user_id, CodeKind::EmailVerification)
+            .await
+    }
+    /// Marks user's email as verified if the code is correct.
+    pub async fn verify_email(&self, user_id: u32, code: &str) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("verify_email");
+        if !self
+            .use_email_code(user_id, CodeKind::EmailVerification, code)
+            .await?
+        {
+            return Ok(false);
+        }
+        self.update_userdata(user_id, |user_data| user_data.email_verified = true)
+            .await?;
+        Ok(true)
+    }
+    /// Creates a password reset code for the SEGA ID user. Returns the verified email and the
+    /// code or `None` if the user has no verified email.
+    pub async fn new_reset_code(&self, username: &str) -> Result<Option<(String, String)>, Error> {
+        let _timer = METRICS.time_query("new_reset_code");
+        let account = self.find_sega_account(username).await?;
+        if !account.email_verified {
+            return Ok(None);
+        }
+        let code = self
+            .new_email_code(account.id, CodeKind::PasswordReset)
+            .await?;
+        Ok(Some((account.email, code)))
+    }
+    /// Sets a new password of the SEGA ID user if the reset code is correct. The code is
+    /// consumed.
+    pub async fn confirm_reset(
+        &self,
+        username: &str,
+        code: &str,
+        password: &str,
+    ) -> Result<User, Error> {
+        let _timer = METRICS.time_query("confirm_reset");
+        let account = self.find_sega_account(username).await?;
+        if let Some(ban) = self.get_ban(account.id).await? {
+            return Err(Error::Banned(ban));
+        }
+        // the code only allows setting a new password, it can't become one
+        if password.is_empty()
+            || password.eq_ignore_ascii_case(code)
+            || password.starts_with(RESET_PREFIX)
+        {
+            return Err(Error::InvalidData);
+        }
+        if !self
+            .use_email_code(account.id, CodeKind::PasswordReset, code)
+            .await?
+        {
+            return Err(Error::InvalidPassword(account.id));
+        }
+        self.set_password(account.id, password).await?;
+        let row = sqlx::query("select Data from Users where Id = ?")
+            .bind(account.id as i64)
+            .fetch_one(&self.connection)
+            .await?;
+        let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        Ok(User {
+            id: account.id,
+            nickname: user_data.nickname,
+            account_flags: user_data.flags,
+            gm_level: user_data.gm_level(),
+            last_uuid: user_data.last_uuid,
+        })
+    }
+    async fn find_sega_account(&self, username: &str) -> Result<AccountInfo, Error> {
+        if username.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        let Some(row) = sqlx::query("select * from Users where Username = ?")
+            .bind(username.as_bytes())
+            .fetch_optional(&self.connection)
+            .await?
+        else {
+            return Err(Error::NoUser);
+        };
+        row_to_account_info(&row)
+    }
+    async fn new_email_code(&self, user_id: u32, kind: CodeKind) -> Result<String, Error> {
+        const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+        let code = random_string(CHARSET, 8);
+        let valid_for = match kind {
+            CodeKind::AccountLink | CodeKind::PasswordChange => Duration::from_secs(600),
+            _ => Duration::from_secs(3600),
+        };
+        let until = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .add(valid_for)
+            .as_secs();
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("delete from EmailCodes where UserId = ? and Kind = ?")
+            .bind(user_id as i64)
+            .bind(kind as i64)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("insert into EmailCodes (UserId, Kind, Code, Until) values (?, ?, ?, ?)")
+            .bind(user_id as i64)
+            .bind(kind as i64)
+            .bind(code.as_bytes())
+            .bind(until as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(code)
+    }
+    /// Checks and consumes the code.
+    async fn use_email_code(
+        &self,
+        user_id: u32,
+        kind: CodeKind,
+        code: &str,
+    ) -> Result<bool, Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let result = sqlx::query(
+            "delete from EmailCodes where UserId = ? and Kind = ? and Code = ? and Until >= ?",
+        )
+        .bind(user_id as i64)
+        .bind(kind as i64)
+        .bind(code.to_uppercase().as_bytes())
+        .bind(now as i64)
+        .execute(&self.connection)
+        .await?;
+        Ok(result.rows_affected() != 0)
+    }
+
+    /// Returns the TOTP secret if 2FA is enabled for the user.
+    pub async fn get_totp_secret(&self, user_id: u32) -> Result<Option<Vec<u8>>, Error> {
+        let _timer = METRICS.time_query("get_totp_secret");
+        let user_data = self.get_userdata(user_id).await?;
+        Ok((!user_data.totp_secret.is_empty()).then_some(user_data.totp_secret))
+    }
+    /// Generates a new TOTP secret that is enabled after [`Self::enable_totp`].
+    pub async fn new_totp_secret(&self, user_id: u32) -> Result<Vec<u8>, Error> {
+        let _timer = METRICS.time_query("new_totp_secret");
+        let secret = totp::new_secret();
+        self.update_userdata(user_id, |user_data| {
+            user_data.pending_totp_secret = secret.clone()
+        })
+        .await?;
+        Ok(secret)
+    }
+    /// Checks the one-time code against the enabled secret. Each time step is accepted only once.
+    pub async fn verify_totp(&self, user_id: u32, code: &str) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("verify_totp");
+        let user_data = self.get_userdata(user_id).await?;
+        if user_data.totp_secret.is_empty() {
+            return Ok(false);
+        }
+        let Some(step) = totp::verify(&user_data.totp_secret, code, user_data.totp_last_step)
+        else {
+            return Ok(false);
+        };
+        self.use_totp_step(user_id, step).await
+    }
+    /// Enables 2FA if the code matches the pending secret.
+    pub async fn enable_totp(&self, user_id: u32, code: &str) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("enable_totp");
+        let user_data = self.get_userdata(user_id).await?;
+        if user_data.pending_totp_secret.is_empty() {
+            return Ok(false);
+        }
+        let Some(step) = totp::verify(&user_data.pending_totp_secret, code, 0) else {
+            return Ok(false);
+        };
+        self.update_userdata(user_id, |user_data| {
+            user_data.totp_secret = std::mem::take(&mut user_data.pending_totp_secret);
+            user_data.totp_last_step = step;
+        })
+        .await?;
+        Ok(true)
+    }
+    /// Disables 2FA. If the code is provided then it must match the current secret.
+    pub async fn disable_totp(&self, user_id: u32, code: Option<&str>) -> Result<bool, Error> {
+        let _timer = METRICS.time_query("disable_totp");
+        if let Some(code) = code {
+            if !self.verify_totp(user_id, code).await? {
+                return Ok(false);
+            }
+        }
+        self.update_userdata(user_id, |user_data| {
+            user_data.totp_secret.clear();
+            user_data.pending_totp_secret.clear();
+        })
+        .await?;
+        Ok(true)
+    }
+    /// Records the accepted time step. Returns `false` if a concurrent login already used it.
+    async fn use_totp_step(&self, user_id: u32, step: u64) -> Result<bool, Error> {
+        let mut accepted = false;
+        self.update_userdata(user_id, |user_data| {
+            if step > user_data.totp_last_step {
+                user_data.totp_last_step = step;
+                accepted = true;
+            }
+        })
+        .await?;
+        Ok(accepted)
+    }
+
+    /// Creates a short-lived code for linking an external identity to the account.
+    pub async fn new_link_code(&self, user_id: u32) -> Result<String, Error> {
+        let _timer = METRICS.time_query("new_link_code");
+        self.new_email_code(user_id, CodeKind::AccountLink).await
+    }
+    /// Consumes the link code and links the external identity to its account. Returns the user
+    /// id or `None` if the code is invalid.
+    pub async fn link_account(
+        &self,
+        code: &str,
+        service: &str,
+        external_id: &str,
+    ) -> Result<Option<u32>, Error> {
+        let _timer = METRICS.time_query("link_account");
+        if service.is_empty() || external_id.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut transaction = self.connection.begin().await?;
+        let Some(row) = sqlx::query(
+            "delete from EmailCodes where Kind = ? and Code = ? and Until >= ? returning UserId",
+        )
+        .bind(CodeKind::AccountLink as i64)
+        .bind(code.to_uppercase().as_bytes())
+        .bind(now as i64)
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let user_id = row.try_get::<i64, _>("UserId")? as u32;
+        sqlx::query(
+            "insert or replace into AccountLinks (UserId, Service, ExternalId, Timestamp) 
+            values (?, ?, ?, ?)",
+        )
+        .bind(user_id as i64)
+        .bind(service.as_bytes())
+        .bind(external_id.as_bytes())
+        .bind(now as i64)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        self.table_changed("AccountLinks");
+        Ok(Some(user_id))
+    }
+    pub async fn get_account_links(&self, user_id: u32) -> Result<Vec<AccountLink>, Error> {
+        let _timer = METRICS.time_query("get_account_links");
+        let rows = sqlx::query("select * from AccountLinks where UserId = ?")
+            .bind(user_id as i64)
+            .fetch_all(&self.connection)
+            .await?;
+        let mut links = vec![];
+        for row in rows {
+            links.push(AccountLink {
+                user_id,
+                service: from_utf8(row.try_get("Service")?)?.to_string(),
+                external_id: from_utf8(row.try_get("ExternalId")?)?.to_string(),
+            });
+        }
+        Ok(links)
+    }
+    pub async fn unlink_account(&self, user_id: u32, service: &str) -> Result<(), Error> {
+        let _timer = METRICS.time_query("unlink_account");
+        sqlx::query("delete from AccountLinks where UserId = ? and Service = ?")
+            .bind(user_id as i64)
+            .bind(service.as_bytes())
+            .execute(&self.connection)
+            .await?;
+        self.table_changed("AccountLinks");
+        Ok(())
+    }
+    /// Merges the `from` account into the `into` account. Storages are combined, credentials
+    /// missing from the target are moved, and logins, links, values, notes, bans and tickets are
+    /// re-homed. Remaining credentials of the merged account log into the target account.
+    pub async fn merge_accounts(&self, from: u32, into: u32, author_id: u32) -> Result<(), Error> {
+        let _timer = METRICS.time_query("merge_accounts");
+        if from == into
+            || self.merged_into(from).await?.is_some()
+            || self.merged_into(into).await?.is_some()
+        {
+            return Err(Error::InvalidData);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut transaction = self.connection.begin().await?;
+        let mut rows = vec![];
+        for id in [from, into] {
+            let Some(row) = sqlx::query("select * from Users where Id = ?")
+                .bind(id as i64)
+                .fetch_optional(&mut *transaction)
+                .await?
+            else {
+                return Err(Error::NoUser);
+            };
+            rows.push(row);
+        }
+        let (from_row, into_row) = (&rows[0], &rows[1]);
+        let mut from_data: UserData = rmp_serde::from_slice(from_row.try_get("Data")?)?;
+        let mut into_data: UserData = rmp_serde::from_slice(into_row.try_get("Data")?)?;
+
+        let mut storage = into_data.storage.clone();
+        storage
+            .absorb(from_data.storage.clone(), &mut into_data.last_uuid)
+            .map_err(Error::MergeConflict)?;
+        into_data.storage = storage;
+        // the source keeps its bank capacities, but not the contents
+        from_data.storage.storage_meseta = 0;
+        for bank in 0..3 {
+            if let Some(storage) = from_data.storage.bank_mut(bank) {
+                storage.items.clear();
+            }
+        }
+        from_data.storage.augments.clear();
+        into_data.storage_version += 1;
+        from_data.storage_version += 1;
+        if into_data.email.is_empty() {
+            into_data.email = std::mem::take(&mut from_data.email);
+            into_data.email_verified = from_data.email_verified;
+            from_data.email_verified = false;
+        }
+
+        // move credentials that the target doesn't have
+        let empty = |row: &sqlx::sqlite::SqliteRow, column| -> Result<bool, Error> {
+            Ok(row.try_get::<&[u8], _>(column)?.is_empty())
+        };
+        if empty(into_row, "Username")? && !empty(from_row, "Username")? {
+            sqlx::query(
+                "update Users set Username = (select Username from Users where Id = ?1), 
+                Password = (select Password from Users where Id = ?1) where Id = ?2",
+            )
+            .bind(from as i64)
+            .bind(into as i64)
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query("update Users set Username = ?, Password = ? where Id = ?")
+                .bind(&b""[..])
+                .bind(&b""[..])
+                .bind(from as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        if empty(into_row, "PSNUsername")? && !empty(from_row, "PSNUsername")? {
+            sqlx::query(
+                "update Users set PSNUsername = (select PSNUsername from Users where Id = ?1) 
+                where Id = ?2",
+            )
+            .bind(from as i64)
+            .bind(into as i64)
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query("update Users set PSNUsername = ? where Id = ?")
+                .bind(&b""[..])
+                .bind(from as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        for query in [
+            "update Logins set UserId = ?1 where UserId = ?2",
+            "update AccountLinks set UserId = ?1 where UserId = ?2",
+            // values of the target win
+            "insert or ignore into AccountValues (UserId, Namespace, Key, Value) 
+            select ?1, Namespace, Key, Value from AccountValues where UserId = ?2",
+            "delete from AccountValues where UserId = ?2",
+            "update AccountNotes set UserId = ?1 where UserId = ?2",
+            "update Bans set UserId = ?1 where UserId = ?2",
+            "update SupportTickets set UserId = ?1 where UserId = ?2",
+            "delete from EmailCodes where UserId = ?2",
+            "delete from Challenges where UserId = ?2",
+            // accounts previously merged into the source now lead to the target
+            "update AccountMerges set IntoId = ?1 where IntoId = ?2",
+        ] {
+            sqlx::query(query)
+                .bind(into as i64)
+                .bind(from as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        sqlx::query("insert into AccountMerges (FromId, IntoId, Timestamp) values (?, ?, ?)")
+            .bind(from as i64)
+            .bind(into as i64)
+            .bind(now as i64)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "insert into AccountNotes (UserId, AuthorId, Note, Timestamp) values (?, ?, ?, ?)",
+        )
+        .bind(into as i64)
+        .bind(author_id as i64)
+        .bind(format!("Merged account {from}").as_bytes())
+        .bind(now as i64)
+        .execute(&mut *transaction)
+        .await?;
+        for (id, data) in [(from, &from_data), (into, &into_data)] {
+            sqlx::query("update Users set Data = ? where Id = ?")
+                .bind(rmp_serde::to_vec(data)?)
+                .bind(id as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        self.user_changed(from);
+        self.user_changed(into);
+        for &table in REPLICATED_TABLES {
+            self.table_changed(table);
+        }
+        Ok(())
+    }
+    /// Returns the id of the account that the account was merged into.
+    pub async fn merged_into(&self, user_id: u32) -> Result<Option<u32>, Error> {
+        let _timer = METRICS.time_query("merged_into");
+        let row = sqlx::query("select IntoId from AccountMerges where FromId = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?;
+        Ok(row
+            .map(|r| r.try_get::<i64, _>("IntoId"))
+            .transpose()?
+            .map(|id| id as u32))
+    }
+    /// Returns all account merges as (from, into) pairs.
+    pub async fn get_account_merges(&self) -> Result<Vec<(u32, u32)>, Error> {
+        let _timer = METRICS.time_query("get_account_merges");
+        let rows = sqlx::query("select FromId, IntoId from AccountMerges order by FromId")
+            .fetch_all(&self.connection)
+            .await?;
+        let mut merges = vec![];
+        for row in rows {
+            merges.push((
+                row.try_get::<i64, _>("FromId")? as u32,
+                row.try_get::<i64, _>("IntoId")? as u32,
+            ));
+        }
+        Ok(merges)
+    }
+    pub async fn get_account_value(
+        &self,
+        user_id: u32,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let _timer = METRICS.time_query("get_account_value");
+        let row = sqlx::query(
+            "select Value from AccountValues where UserId = ? and Namespace = ? and Key = ?",
+        )
+        .bind(user_id as i64)
+        .bind(namespace.as_bytes())
+        .bind(key.as_bytes())
+        .fetch_optional(&self.connection)
+        .await?;
+        match row {
+            Some(row) => Ok(Some(row.try_get("Value")?)),
+            None => Ok(None),
+        }
+    }
+    /// Sets or deletes (if `value` is `None`) the account value. Total size of keys and values
+    /// of the account is limited by `quota` bytes.
+    pub async fn put_account_value(
+        &self,
+        user_id: u32,
+        namespace: &str,
+        key: &str,
+        value: Option<&[u8]>,
+        quota: usize,
+    ) -> Result<(), Error> {
+        let _timer = METRICS.time_query("put_account_value");
+        if namespace.is_empty() || key.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("delete from AccountValues where UserId = ? and Namespace = ? and Key = ?")
+            .bind(user_id as i64)
+            .bind(namespace.as_bytes())
+            .bind(key.as_bytes())
+            .execute(&mut *transaction)
+            .await?;
+        let Some(value) = value else {
+            transaction.commit().await?;
+            self.table_changed("AccountValues");
+            return Ok(());
+        };
+        let used: i64 = sqlx::query(
+            "select coalesce(sum(length(Namespace) + length(Key) + length(Value)), 0) as Used 
+            from AccountValues where UserId = ?",
+        )
+        .bind(user_id as i64)
+        .fetch_one(&mut *transaction)
+        .await?
+        .try_get("Used")?;
+        let size = namespace.len() + key.len() + value.len();
+        if used as usize + size > quota {
+            return Err(Error::QuotaExceeded);
         }
-        self.update_userdata(user_id, |user_data| {
-            user_data.nickname = nickname.to_string()
-        })
+        sqlx::query(
+            "insert into AccountValues (UserId, Namespace, Key, Value) values (?, ?, ?, ?)",
+        )
+        .bind(user_id as i64)
+        .bind(namespace.as_bytes())
+        .bind(key.as_bytes())
+        .bind(value)
+        .execute(&mut *transaction)
         .await?;
-        Ok(true)
+        transaction.commit().await?;
+        self.table_changed("AccountValues");
+        Ok(())
     }
 
+    async fn get_userdata(&self, user_id: u32) -> Result<UserData, Error> {
+        let Some(row) = sqlx::query("select Data from Users where Id = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?
+        else {
+            return Err(Error::NoUser);
+        };
+        Ok(rmp_serde::from_slice(row.try_get("Data")?)?)
+    }
     async fn update_userdata<F>(&self, user_id: u32, f: F) -> Result<(), Error>
     where
         F: FnOnce(&mut UserData) + Send,
@@ -442,25 +2571,209 @@ impl Sql {
             .fetch_one(&mut *transaction)
             .await?;
         let mut user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        let old_nickname = user_data.nickname.clone();
         f(&mut user_data);
         sqlx::query("update Users set Data = ? where Id = ?")
             .bind(rmp_serde::to_vec(&user_data)?)
             .bind(user_id as i64)
             .execute(&mut *transaction)
             .await?;
+        // only changed nicknames are written, so duplicates from before the column stay usable
+        if user_data.nickname != old_nickname {
+            sqlx::query("update Users set Nickname = ? where Id = ?")
+                .bind(nickname_column(&user_data.nickname))
+                .bind(user_id as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
         transaction.commit().await?;
+        self.user_changed(user_id);
         Ok(())
     }
 }
 
+/// Value of the nickname column. Accounts without a nickname are not indexed.
+fn nickname_column(nickname: &str) -> Option<&str> {
+    (!nickname.is_empty()).then_some(nickname)
+}
+
+fn is_unique_violation(e: &Error) -> bool {
+    matches!(e, Error::SqlError(sqlx::Error::Database(e)) if e.is_unique_violation())
+}
+
+/// Reads the client platform of the login row. Rows recorded before it was stored return the
+/// default platform.
+fn read_platform(row: &sqlx::sqlite::SqliteRow) -> Result<PacketType, Error> {
+    let platform: Option<&[u8]> = row.try_get("Platform")?;
+    match platform {
+        Some(platform) => Ok(rmp_serde::from_slice(platform)?),
+        None => Ok(PacketType::default()),
+    }
+}
+
+impl Default for StorageCapacities {
+    fn default() -> Self {
+        Self {
+            default: 200,
+            premium: 0,
+            extended: 0,
+        }
+    }
+}
+
+impl Default for RegistrationLimits {
+    fn default() -> Self {
+        Self {
+            max_per_ip: 3,
+            interval: 86400,
+        }
+    }
+}
+
+impl Default for ResetLimits {
+    fn default() -> Self {
+        Self {
+            account_interval: 900,
+            max_per_ip: 5,
+            ip_interval: 3600,
+        }
+    }
+}
+
+impl Default for LoginLimits {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_lockout: 30,
+            max_lockout: 3600,
+            reset_after: 900,
+        }
+    }
+}
+
+async fn hash_password(password: &str) -> Result<String, Error> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        let argon2 = Argon2::default();
+        match argon2.hash_password(password.as_bytes(), &salt) {
+            Ok(x) => Ok(x.to_string()),
+            Err(_) => Err(Error::HashError),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+fn row_to_account_info(row: &sqlx::sqlite::SqliteRow) -> Result<AccountInfo, Error> {
+    let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+    Ok(AccountInfo {
+        id: row.try_get::<i64, _>("Id")? as u32,
+        username: from_utf8(row.try_get("Username")?)?.to_string(),
+        psn_username: from_utf8(row.try_get("PSNUsername")?)?.to_string(),
+        nickname: user_data.nickname,
+        gm_level: user_data.gm_level(),
+        email: user_data.email,
+        email_verified: user_data.email_verified,
+    })
+}
+
+const fn ticket_status_id(status: TicketStatus) -> i64 {
+    match status {
+        TicketStatus::Open => 0,
+        TicketStatus::Claimed => 1,
+        TicketStatus::Resolved => 2,
+    }
+}
+
+fn row_to_ticket(row: &sqlx::sqlite::SqliteRow) -> Result<SupportTicket, Error> {
+    let status = match row.try_get::<i64, _>("Status")? {
+        0 => TicketStatus::Open,
+        1 => TicketStatus::Claimed,
+        _ => TicketStatus::Resolved,
+    };
+    Ok(SupportTicket {
+        id: row.try_get::<i64, _>("Id")? as u32,
+        user_id: row.try_get::<i64, _>("UserId")? as u32,
+        nickname: from_utf8(row.try_get("Nickname")?)?.to_string(),
+        ship_id: row.try_get::<i64, _>("ShipId")? as u32,
+        message: from_utf8(row.try_get("Message")?)?.to_string(),
+        status,
+        gm_id: row.try_get::<Option<i64>, _>("GmId")?.map(|id| id as u32),
+        resolution: from_utf8(row.try_get("Resolution")?)?.to_string(),
+        timestamp: Duration::from_secs(row.try_get::<i64, _>("Timestamp")? as u64),
+    })
+}
+
+fn row_to_ship_stats(row: &sqlx::sqlite::SqliteRow) -> Result<ShipStatsEntry, Error> {
+    Ok(ShipStatsEntry {
+        ship_id: row.try_get::<i64, _>("ShipId")? as u32,
+        timestamp: Duration::from_secs(row.try_get::<i64, _>("Timestamp")? as u64),
+        stats: ShipStats {
+            peak_players: row.try_get::<i64, _>("PeakPlayers")? as u32,
+            quests: row.try_get::<i64, _>("Quests")? as u64,
+            meseta: row.try_get::<i64, _>("Meseta")? as u64,
+        },
+    })
+}
+
+/// Returns a string of random characters from the charset (up to 256 characters). Bytes that
+/// would make some characters more likely than others are skipped.
+fn random_string(charset: &[u8], len: usize) -> String {
+    let limit = 256 - 256 % charset.len();
+    let mut result = String::with_capacity(len);
+    let mut buf = [0u8; 64];
+    while result.len() < len {
+        OsRng.fill_bytes(&mut buf);
+        let chars = buf
+            .iter()
+            .filter(|&&b| (b as usize) < limit)
+            .map(|&b| charset[b as usize % charset.len()] as char)
+            .take(len - result.len());
+        result.extend(chars);
+    }
+    result
+}
+
+fn row_to_ship_key(row: &sqlx::sqlite::SqliteRow) -> Result<ShipKey, Error> {
+    Ok(ShipKey {
+        name: from_utf8(row.try_get("Name")?)?.to_string(),
+        psk: row.try_get("PSK")?,
+        revoked: row.try_get("Revoked")?,
+        blocked: row.try_get("Blocked")?,
+    })
+}
+
+fn row_to_replicated_user(row: &sqlx::sqlite::SqliteRow) -> Result<ReplicatedUser, Error> {
+    Ok(ReplicatedUser {
+        id: row.try_get::<i64, _>("Id")? as u32,
+        username: row.try_get("Username")?,
+        password: row.try_get("Password")?,
+        psn_username: row.try_get("PSNUsername")?,
+        data: row.try_get("Data")?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::sql::Sql;
-    use data_structs::flags::Flags;
+    use crate::{
+        sql::{
+            random_string, AccountDefaults, AccountExport, Change, LoginLimits, RegistrationLimits,
+            ResetLimits, Sql, StorageCapacities,
+        },
+        Error,
+    };
+    use data_structs::{
+        flags::Flags,
+        master_ship::{
+            gm_level, ChangeNicknameResult, PutStorageResult, ShipStats, TicketStatus, RESET_PREFIX,
+        },
+    };
     use pso2packetlib::{
         protocol::{
-            login::{LoginResult, UserInfoPacket},
+            login::{LoginAttempt, LoginResult, UserInfoPacket},
             models::SGValue,
+            PacketType,
         },
         AsciiString,
     };
@@ -476,11 +2789,11 @@ mod tests {
         let (segaid, pass) = ("username", "password");
 
         let mut created_user = db
-            .create_sega_user(segaid, pass)
+            .create_sega_user(segaid, pass, &Default::default())
             .await
             .expect("SEGAID user creation failed");
         let login_user = db
-            .get_sega_user(segaid, pass, Ipv4Addr::UNSPECIFIED)
+            .get_sega_user(segaid, pass, Ipv4Addr::UNSPECIFIED, PacketType::NA)
             .await
             .expect("SEGAID user login failed");
         assert_eq!(created_user, login_user);
@@ -511,11 +2824,11 @@ mod tests {
         created_user.last_uuid = 199;
 
         let challenge = db
-            .new_challenge(created_user.id)
+            .new_challenge(created_user.id, 1)
             .await
             .expect("Challenge creation failed");
         let challenge_user = db
-            .login_challenge(created_user.id, challenge)
+            .login_challenge(created_user.id, 1, challenge)
             .await
             .expect("Challenge login failed");
         assert_eq!(created_user, challenge_user);
@@ -525,24 +2838,91 @@ mod tests {
 
         let psn_username = "psnusername";
 
+        let defaults = AccountDefaults {
+            flags: vec![3],
+            params: vec![(1, 5)],
+            gm_level: gm_level::MODERATOR,
+            storage: StorageCapacities {
+                premium: 400,
+                ..Default::default()
+            },
+        };
         let psn_user = db
-            .create_psn_user(psn_username)
+            .create_psn_user(psn_username, &defaults)
             .await
             .expect("PSN User creation failed");
+        assert_eq!(psn_user.account_flags.get(3), 1);
+        assert_eq!(psn_user.account_flags.get_param(1), 5);
+        assert_eq!(psn_user.gm_level, gm_level::MODERATOR);
         let login_psn_user = db
-            .get_psn_user(psn_username, Ipv4Addr::UNSPECIFIED)
+            .get_psn_user(psn_username, Ipv4Addr::UNSPECIFIED, PacketType::Vita)
             .await
             .expect("PSN User login failed");
         assert_eq!(psn_user, login_psn_user);
 
+        let (storage, version) = db
+            .get_account_storage(psn_user.id)
+            .await
+            .expect("Storage request failed");
+        assert!(storage.premium.is_enabled);
+        assert!(!storage.extend1.is_enabled);
+        db.set_storage_capacity(psn_user.id, 2, 500)
+            .await
+            .expect("Failed to set storage capacity");
+        let (mut storage, version) = match db
+            .put_account_storage(psn_user.id, storage, version)
+            .await
+            .expect("Storage write failed")
+        {
+            PutStorageResult::Conflict { storage, version } => (storage, version),
+            PutStorageResult::Ok { .. } => panic!("Capacity change didn't update the version"),
+        };
+        assert_eq!(storage.extend1.total_space, 500);
+        // ships can't change capacities
+        storage.premium.set_capacity(1000, true);
+        db.put_account_storage(psn_user.id, storage, version)
+            .await
+            .expect("Storage write failed");
+        let (storage, _) = db
+            .get_account_storage(psn_user.id)
+            .await
+            .expect("Storage request failed");
+        assert_eq!(storage.premium.total_space, 400);
+
+        db.save_settings(psn_user.id, "settings")
+            .await
+            .expect("Failed to save settings");
+        let export = db
+            .export_account(psn_user.id)
+            .await
+            .expect("Account export failed");
+        db.import_account(created_user.id, export)
+            .await
+            .expect("Account import failed");
+        let to_json = |e: AccountExport| serde_json::to_string(&e).unwrap();
+        assert_eq!(
+            to_json(db.export_account(created_user.id).await.unwrap()),
+            to_json(db.export_account(psn_user.id).await.unwrap())
+        );
+
         let logins = db
-            .get_logins(created_user.id)
+            .get_logins(created_user.id, 1)
             .await
             .expect("Login attempts request failed");
-        assert!(!logins.is_empty());
+        assert_eq!(logins.len(), 1);
         let login = &logins[0];
         assert_eq!(login.ip, Ipv4Addr::UNSPECIFIED);
         assert_eq!(login.status, LoginResult::Successful);
+        assert_eq!(login.platform, PacketType::NA);
+        let attempt = LoginAttempt::from(login.clone());
+        assert_eq!(attempt.unk, PacketType::NA as u32);
+        let recent_logins = db
+            .get_recent_logins(10)
+            .await
+            .expect("Recent logins request failed");
+        assert!(recent_logins
+            .iter()
+            .any(|l| l.user_id == created_user.id && l.username == segaid));
 
         let settings = AsciiString::from("a");
         db.save_settings(created_user.id, &settings)
@@ -554,6 +2934,639 @@ mod tests {
             .expect("Failed to read settings");
         assert_eq!(read_settings, settings);
 
+        let account = db
+            .find_account(segaid)
+            .await
+            .expect("Failed to find account");
+        assert_eq!(account.id, created_user.id);
+        db.set_gm_level(created_user.id, gm_level::MODERATOR)
+            .await
+            .expect("Failed to set GM level");
+        assert_eq!(
+            db.get_account_info(created_user.id)
+                .await
+                .expect("Failed to get account info")
+                .gm_level,
+            gm_level::MODERATOR
+        );
+        assert!(db.set_gm_level(created_user.id, 100).await.is_err());
+        db.set_password(created_user.id, "new_password")
+            .await
+            .expect("Failed to reset password");
+        db.get_sega_user(
+            segaid,
+            "new_password",
+            Ipv4Addr::UNSPECIFIED,
+            PacketType::NA,
+        )
+        .await
+        .expect("Login after password reset failed");
+
+        let code = db
+            .set_email(created_user.id, "user@example.com")
+            .await
+            .expect("Failed to set email");
+        assert!(!db.verify_email(created_user.id, "invalid").await.unwrap());
+        assert!(db.verify_email(created_user.id, &code).await.unwrap());
+        let (email, code) = db
+            .new_reset_code(segaid)
+            .await
+            .expect("Failed to create reset code")
+            .expect("Email should be verified");
+        assert_eq!(email, "user@example.com");
+        assert!(db.confirm_reset(segaid, &code, &code).await.is_err());
+        // passwords can't look like a reset
+        let reset_like = format!("{RESET_PREFIX}{code}:password");
+        assert!(db.confirm_reset(segaid, &code, &reset_like).await.is_err());
+        assert!(db.set_password(created_user.id, &reset_like).await.is_err());
+        db.confirm_reset(segaid, &code, "reset_password")
+            .await
+            .expect("Failed to confirm reset");
+        assert!(db.confirm_reset(segaid, &code, "password").await.is_err());
+        db.get_sega_user(
+            segaid,
+            "reset_password",
+            Ipv4Addr::UNSPECIFIED,
+            PacketType::NA,
+        )
+        .await
+        .expect("Login after password reset failed");
+        db.set_password(created_user.id, "new_password")
+            .await
+            .expect("Failed to reset password");
+
+        db.ban_user(created_user.id, "reason", None)
+            .await
+            .expect("Failed to ban user");
+        match db
+            .get_sega_user(
+                segaid,
+                "new_password",
+                Ipv4Addr::UNSPECIFIED,
+                PacketType::NA,
+            )
+            .await
+        {
+            Err(Error::Banned(ban)) => {
+                assert_eq!(ban.reason, "reason");
+                assert_eq!(ban.until, None);
+            }
+            _ => panic!("Banned user was able to login"),
+        }
+        db.unban_user(created_user.id)
+            .await
+            .expect("Failed to unban user");
+        db.ban_user(created_user.id, "expired", Some(Duration::from_secs(1)))
+            .await
+            .expect("Failed to ban user");
+        db.get_sega_user(
+            segaid,
+            "new_password",
+            Ipv4Addr::UNSPECIFIED,
+            PacketType::NA,
+        )
+        .await
+        .expect("Expired ban prevented login");
+
+        assert!(db.add_account_note(created_user.id, 1, " ").await.is_err());
+        db.add_account_note(created_user.id, 1, "first warning")
+            .await
+            .expect("Failed to add note");
+        db.add_account_note(created_user.id, 2, "second warning")
+            .await
+            .expect("Failed to add note");
+        let notes = db
+            .get_account_notes(created_user.id)
+            .await
+            .expect("Failed to get notes");
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].note, "second warning");
+        assert_eq!(notes[1].author_id, 1);
+
         let _ = std::fs::remove_file("test.db");
     }
+
+    #[tokio::test]
+    async fn test_login_throttle() {
+        let _ = std::fs::remove_file("test_throttle.db");
+        let db = Sql::new("sqlite:test_throttle.db", false)
+            .await
+            .expect("DB creation failed");
+        let limits = LoginLimits {
+            max_attempts: 2,
+            base_lockout: 10,
+            max_lockout: 15,
+            reset_after: 60,
+        };
+        let key = "user:username";
+
+        db.add_login_failure(key, &limits)
+            .await
+            .expect("Failed to add login failure");
+        assert_eq!(db.get_login_lockout(key).await.unwrap(), None);
+        db.add_login_failure(key, &limits)
+            .await
+            .expect("Failed to add login failure");
+        let lockout = db
+            .get_login_lockout(key)
+            .await
+            .unwrap()
+            .expect("Key should be locked");
+        assert!(lockout <= Duration::from_secs(10));
+        db.add_login_failure(key, &limits)
+            .await
+            .expect("Failed to add login failure");
+        let lockout = db
+            .get_login_lockout(key)
+            .await
+            .unwrap()
+            .expect("Key should be locked");
+        assert!(lockout > Duration::from_secs(10) && lockout <= Duration::from_secs(15));
+        db.reset_login_failures(key)
+            .await
+            .expect("Failed to reset login failures");
+        assert_eq!(db.get_login_lockout(key).await.unwrap(), None);
+
+        let _ = std::fs::remove_file("test_throttle.db");
+    }
+
+    #[tokio::test]
+    async fn test_account_values() {
+        let _ = std::fs::remove_file("test_values.db");
+        let db = Sql::new("sqlite:test_values.db", false)
+            .await
+            .expect("DB creation failed");
+
+        assert_eq!(
+            db.get_account_value(1, "event", "progress").await.unwrap(),
+            None
+        );
+        db.put_account_value(1, "event", "progress", Some(&[1, 2, 3][..]), 32)
+            .await
+            .expect("Failed to put value");
+        assert_eq!(
+            db.get_account_value(1, "event", "progress").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert!(matches!(
+            db.put_account_value(1, "event", "other", Some(&[0; 16][..]), 32)
+                .await,
+            Err(Error::QuotaExceeded)
+        ));
+        // replacing the value doesn't count the old one
+        db.put_account_value(1, "event", "progress", Some(&[0; 16][..]), 32)
+            .await
+            .expect("Failed to replace value");
+        db.put_account_value(1, "event", "progress", None, 32)
+            .await
+            .expect("Failed to delete value");
+        assert_eq!(
+            db.get_account_value(1, "event", "progress").await.unwrap(),
+            None
+        );
+
+        let _ = std::fs::remove_file("test_values.db");
+    }
+
+    #[tokio::test]
+    async fn test_account_merge() {
+        let _ = std::fs::remove_file("test_merge.db");
+        let db = Sql::new("sqlite:test_merge.db", false)
+            .await
+            .expect("DB creation failed");
+        let target = db
+            .create_sega_user("target", "password", &Default::default())
+            .await
+            .unwrap();
+        let psn = db
+            .create_psn_user("psnuser", &Default::default())
+            .await
+            .unwrap();
+        let duplicate = db
+            .create_sega_user("duplicate", "password", &Default::default())
+            .await
+            .unwrap();
+        db.put_account_value(psn.id, "event", "progress", Some(&[1][..]), 32)
+            .await
+            .unwrap();
+
+        db.merge_accounts(psn.id, target.id, 0).await.unwrap();
+        db.merge_accounts(duplicate.id, target.id, 0).await.unwrap();
+        assert!(matches!(
+            db.merge_accounts(target.id, psn.id, 0).await,
+            Err(Error::InvalidData)
+        ));
+        assert_eq!(db.merged_into(psn.id).await.unwrap(), Some(target.id));
+        assert_eq!(
+            db.get_account_merges().await.unwrap(),
+            [(psn.id, target.id), (duplicate.id, target.id)]
+        );
+        assert_eq!(
+            db.get_account_value(target.id, "event", "progress")
+                .await
+                .unwrap(),
+            Some(vec![1])
+        );
+        // PSN username was moved to the target
+        let user = db
+            .get_psn_user("psnuser", Ipv4Addr::UNSPECIFIED, PacketType::Vita)
+            .await
+            .unwrap();
+        assert_eq!(user.id, target.id);
+        // conflicting SEGA ID stays on the merged account, but logs into the target
+        let user = db
+            .get_sega_user(
+                "duplicate",
+                "password",
+                Ipv4Addr::UNSPECIFIED,
+                PacketType::NA,
+            )
+            .await
+            .unwrap();
+        assert_eq!(user.id, target.id);
+
+        let _ = std::fs::remove_file("test_merge.db");
+    }
+
+    #[tokio::test]
+    async fn test_account_maintenance() {
+        let _ = std::fs::remove_file("test_maintenance.db");
+        let db = Sql::new("sqlite:test_maintenance.db", false)
+            .await
+            .expect("DB creation failed");
+        let user = db
+            .create_sega_user("user", "password", &Default::default())
+            .await
+            .unwrap();
+        let psn = db
+            .create_psn_user("psnuser", &Default::default())
+            .await
+            .unwrap();
+
+        assert!(!db.check_password(user.id, "wrong").await.unwrap());
+        assert!(!db.check_password(psn.id, "").await.unwrap());
+        assert!(db.check_password(user.id, "password").await.unwrap());
+        let challenge = db.new_password_challenge(user.id).await.unwrap();
+        assert!(!db
+            .change_password(user.id, "invalid", "new password")
+            .await
+            .unwrap());
+        assert!(db
+            .change_password(user.id, &challenge, "new password")
+            .await
+            .unwrap());
+        // challenges are single use
+        assert!(!db
+            .change_password(user.id, &challenge, "other password")
+            .await
+            .unwrap());
+        assert!(db.check_password(user.id, "new password").await.unwrap());
+
+        let cooldown = Duration::from_secs(3600);
+        db.set_nickname(psn.id, "Taken").await.unwrap();
+        assert_eq!(
+            db.change_nickname(user.id, "taken", cooldown)
+                .await
+                .unwrap(),
+            ChangeNicknameResult::AlreadyTaken
+        );
+        assert_eq!(
+            db.change_nickname(user.id, "Nick", cooldown).await.unwrap(),
+            ChangeNicknameResult::Ok
+        );
+        assert!(matches!(
+            db.change_nickname(user.id, "Other", cooldown).await.unwrap(),
+            ChangeNicknameResult::Cooldown(left) if left <= cooldown
+        ));
+        assert_eq!(
+            db.change_nickname(user.id, "Other", Duration::ZERO)
+                .await
+                .unwrap(),
+            ChangeNicknameResult::Ok
+        );
+        assert_eq!(
+            db.get_account_info(user.id).await.unwrap().nickname,
+            "Other"
+        );
+        assert_eq!(db.find_account("other").await.unwrap().id, user.id);
+        assert!(!db.set_nickname(psn.id, "OTHER").await.unwrap());
+        assert!(db.set_nickname(psn.id, "Nick").await.unwrap());
+
+        let _ = std::fs::remove_file("test_maintenance.db");
+    }
+
+    #[tokio::test]
+    async fn test_support_tickets() {
+        let _ = std::fs::remove_file("test_tickets.db");
+        let db = Sql::new("sqlite:test_tickets.db", false)
+            .await
+            .expect("DB creation failed");
+
+        let ticket = db
+            .new_ticket(1, "player", 2, "stuck in a wall")
+            .await
+            .expect("Failed to open ticket");
+        assert_eq!(ticket.status, TicketStatus::Open);
+        assert_eq!(ticket.ship_id, 2);
+        let ticket = db.claim_ticket(ticket.id, 10).await.unwrap();
+        assert_eq!(ticket.status, TicketStatus::Claimed);
+        assert_eq!(ticket.gm_id, Some(10));
+        assert_eq!(db.get_open_tickets().await.unwrap(), vec![ticket.clone()]);
+        // claimed by another GM
+        assert!(db.claim_ticket(ticket.id, 11).await.is_err());
+        assert!(db.resolve_ticket(ticket.id, 11, "").await.is_err());
+        assert_eq!(db.claim_ticket(ticket.id, 10).await.unwrap(), ticket);
+        let ticket = db.resolve_ticket(ticket.id, 10, "moved").await.unwrap();
+        assert_eq!(ticket.status, TicketStatus::Resolved);
+        assert_eq!(ticket.resolution, "moved");
+        assert!(db.get_open_tickets().await.unwrap().is_empty());
+        assert!(db.claim_ticket(ticket.id, 11).await.is_err());
+        assert!(db.resolve_ticket(ticket.id, 11, "").await.is_err());
+
+        let _ = std::fs::remove_file("test_tickets.db");
+    }
+
+    #[tokio::test]
+    async fn test_challenge_login() {
+        let _ = std::fs::remove_file("test_challenges.db");
+        let db = Sql::new("sqlite:test_challenges.db", false)
+            .await
+            .expect("DB creation failed");
+        let user = db
+            .create_psn_user("challenger", &Default::default())
+            .await
+            .expect("User creation failed");
+
+        // challenges are single-use
+        let challenge = db.new_challenge(user.id, 1).await.unwrap();
+        assert!(db.login_challenge(user.id, 2, challenge).await.is_err());
+        assert!(db.login_challenge(user.id + 1, 1, challenge).await.is_err());
+        assert!(db
+            .login_challenge(user.id, 1, challenge.wrapping_add(1))
+            .await
+            .is_err());
+        assert_eq!(
+            db.login_challenge(user.id, 1, challenge).await.unwrap(),
+            user
+        );
+        assert!(db.login_challenge(user.id, 1, challenge).await.is_err());
+
+        // new challenges invalidate older ones
+        let old = db.new_challenge(user.id, 1).await.unwrap();
+        let new = db.new_challenge(user.id, 1).await.unwrap();
+        if old != new {
+            assert!(db.login_challenge(user.id, 1, old).await.is_err());
+        }
+        assert!(db.login_challenge(user.id, 1, new).await.is_ok());
+
+        // expired challenges are rejected
+        let expired = db.new_challenge(user.id, 1).await.unwrap();
+        sqlx::query("update Challenges set Until = 0")
+            .execute(&db.connection)
+            .await
+            .unwrap();
+        assert!(db.login_challenge(user.id, 1, expired).await.is_err());
+        assert!(db.new_challenge(user.id + 1, 1).await.is_err());
+
+        let _ = std::fs::remove_file("test_challenges.db");
+    }
+
+    #[tokio::test]
+    async fn test_ship_stats() {
+        let _ = std::fs::remove_file("test_ship_stats.db");
+        let db = Sql::new("sqlite:test_ship_stats.db", false)
+            .await
+            .expect("DB creation failed");
+
+        let stats = |peak_players, quests, meseta| ShipStats {
+            peak_players,
+            quests,
+            meseta,
+        };
+        db.add_ship_stats(1, &stats(10, 5, 1000)).await.unwrap();
+        db.add_ship_stats(2, &stats(20, 1, 500)).await.unwrap();
+        let hour = Duration::from_secs(3600);
+        let ship_stats = db.get_ship_stats(Some(2), hour).await.unwrap();
+        assert_eq!(ship_stats.len(), 1);
+        assert_eq!(ship_stats[0].ship_id, 2);
+        assert_eq!(ship_stats[0].stats, stats(20, 1, 500));
+        assert_eq!(db.get_ship_stats(None, hour).await.unwrap().len(), 2);
+        let network = db.get_network_stats(hour).await.unwrap();
+        let total = network.iter().fold(stats(0, 0, 0), |acc, e| {
+            stats(
+                acc.peak_players + e.stats.peak_players,
+                acc.quests + e.stats.quests,
+                acc.meseta + e.stats.meseta,
+            )
+        });
+        assert_eq!(total, stats(30, 6, 1500));
+
+        let _ = std::fs::remove_file("test_ship_stats.db");
+    }
+
+    #[tokio::test]
+    async fn test_registration_limits() {
+        let _ = std::fs::remove_file("test_registrations.db");
+        let db = Sql::new("sqlite:test_registrations.db", false)
+            .await
+            .expect("Failed to create DB");
+        let limits = RegistrationLimits {
+            max_per_ip: 2,
+            interval: 60,
+        };
+        let ip = Ipv4Addr::new(127, 0, 0, 1);
+        for _ in 0..2 {
+            assert_eq!(
+                db.get_registration_lockout(ip, &limits).await.unwrap(),
+                None
+            );
+            db.add_registration(ip, &limits)
+                .await
+                .expect("Failed to add registration");
+        }
+        let lockout = db
+            .get_registration_lockout(ip, &limits)
+            .await
+            .unwrap()
+            .expect("Registrations should be limited");
+        assert!(lockout <= Duration::from_secs(60));
+        assert_eq!(
+            db.get_registration_lockout(Ipv4Addr::new(127, 0, 0, 2), &limits)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let _ = std::fs::remove_file("test_registrations.db");
+    }
+
+    #[tokio::test]
+    async fn test_reset_limits() {
+        let _ = std::fs::remove_file("test_reset_limits.db");
+        let db = Sql::new("sqlite:test_reset_limits.db", false)
+            .await
+            .expect("Failed to create DB");
+        let limits = ResetLimits {
+            account_interval: 60,
+            max_per_ip: 2,
+            ip_interval: 60,
+        };
+        let ip = Ipv4Addr::new(127, 0, 0, 1);
+        let other_ip = Ipv4Addr::new(127, 0, 0, 2);
+        assert!(db.add_reset_request("a", ip, &limits).await.unwrap());
+        // same account from another address
+        assert!(!db.add_reset_request("a", other_ip, &limits).await.unwrap());
+        assert!(db.add_reset_request("b", ip, &limits).await.unwrap());
+        // address limit is reached
+        assert!(!db.add_reset_request("c", ip, &limits).await.unwrap());
+        assert!(db.add_reset_request("c", other_ip, &limits).await.unwrap());
+
+        let _ = std::fs::remove_file("test_reset_limits.db");
+    }
+
+    #[test]
+    fn test_random_string() {
+        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        let value = random_string(CHARSET, 100);
+        assert_eq!(value.len(), 100);
+        assert!(value.bytes().all(|c| CHARSET.contains(&c)));
+        assert_ne!(value, random_string(CHARSET, 100));
+        assert!(random_string(b"AB", 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ship_keys() {
+        let _ = std::fs::remove_file("test_ship_keys.db");
+        let db = Sql::new("sqlite:test_ship_keys.db", false)
+            .await
+            .expect("Failed to create DB");
+
+        let old_psk = db.new_ship_key("ship1").await.expect("Failed to add key");
+        let new_psk = db
+            .new_ship_key("ship1")
+            .await
+            .expect("Failed to rotate key");
+        assert_ne!(old_psk, new_psk);
+        let old_key = db.get_ship_key(old_psk.as_bytes()).await.unwrap().unwrap();
+        assert!(old_key.revoked);
+        let new_key = db.get_ship_key(new_psk.as_bytes()).await.unwrap().unwrap();
+        assert_eq!(new_key.name, "ship1");
+        assert!(!new_key.revoked);
+
+        db.revoke_ship_keys("ship1")
+            .await
+            .expect("Failed to revoke keys");
+        assert!(
+            db.get_ship_key(new_psk.as_bytes())
+                .await
+                .unwrap()
+                .unwrap()
+                .revoked
+        );
+        assert!(matches!(
+            db.revoke_ship_keys("ship1").await,
+            Err(Error::UnknownShip)
+        ));
+        assert_eq!(db.get_ship_key(b"unknown").await.unwrap(), None);
+
+        let psk = db.new_ship_key("ship2").await.expect("Failed to add key");
+        db.set_ship_blocked("ship2", true)
+            .await
+            .expect("Failed to block ship");
+        assert!(
+            db.get_ship_key(psk.as_bytes())
+                .await
+                .unwrap()
+                .unwrap()
+                .blocked
+        );
+        db.set_ship_blocked("ship2", false)
+            .await
+            .expect("Failed to unblock ship");
+        assert!(
+            !db.get_ship_key(psk.as_bytes())
+                .await
+                .unwrap()
+                .unwrap()
+                .blocked
+        );
+        assert!(matches!(
+            db.set_ship_blocked("unknown", true).await,
+            Err(Error::UnknownShip)
+        ));
+
+        let _ = std::fs::remove_file("test_ship_keys_backup.db");
+        db.backup("test_ship_keys_backup.db")
+            .await
+            .expect("Failed to back up DB");
+        let backup = Sql::new("sqlite:test_ship_keys_backup.db", false)
+            .await
+            .expect("Failed to open backup");
+        assert_eq!(backup.get_ship_keys().await.unwrap().len(), 3);
+
+        let _ = std::fs::remove_file("test_ship_keys.db");
+        let _ = std::fs::remove_file("test_ship_keys_backup.db");
+    }
+
+    #[tokio::test]
+    async fn test_replication() {
+        let _ = std::fs::remove_file("test_primary.db");
+        let _ = std::fs::remove_file("test_standby.db");
+        let primary = Sql::new("sqlite:test_primary.db", false)
+            .await
+            .expect("Failed to create DB");
+        let standby = Sql::new("sqlite:test_standby.db", false)
+            .await
+            .expect("Failed to create DB");
+        let mut changes = primary.subscribe_changes();
+        let user = primary
+            .create_sega_user("replicated", "password", &Default::default())
+            .await
+            .expect("Failed to create user");
+        primary
+            .set_gm_level(user.id, gm_level::ADMIN)
+            .await
+            .expect("Failed to set GM level");
+        assert_eq!(changes.try_recv().unwrap(), Change::User(user.id));
+        assert_eq!(changes.try_recv().unwrap(), Change::User(user.id));
+
+        for replicated in primary.get_replicated_users().await.unwrap() {
+            standby.put_replicated_user(&replicated).await.unwrap();
+        }
+        let replica = standby
+            .get_sega_user(
+                "replicated",
+                "password",
+                Ipv4Addr::UNSPECIFIED,
+                PacketType::NA,
+            )
+            .await
+            .expect("Failed to get replicated user");
+        assert_eq!(replica.id, user.id);
+        assert_eq!(replica.gm_level, gm_level::ADMIN);
+
+        let sync = |table: &'static str| {
+            let (primary, standby) = (&primary, &standby);
+            async move {
+                let table = primary.get_replicated_table(table).await.unwrap();
+                standby.put_replicated_table(&table).await.unwrap();
+            }
+        };
+        primary.ban_user(user.id, "reason", None).await.unwrap();
+        assert_eq!(changes.try_recv().unwrap(), Change::Table("Bans"));
+        sync("Bans").await;
+        assert!(standby.get_ban(user.id).await.unwrap().is_some());
+        primary.unban_user(user.id).await.unwrap();
+        sync("Bans").await;
+        assert!(standby.get_ban(user.id).await.unwrap().is_none());
+        assert!(primary.get_replicated_table("Users").await.is_err());
+
+        assert_eq!(standby.get_term().await.unwrap(), 0);
+        standby.set_term(2).await.unwrap();
+        standby.set_term(3).await.unwrap();
+        assert_eq!(standby.get_term().await.unwrap(), 3);
+
+        let _ = std::fs::remove_file("test_primary.db");
+        let _ = std::fs::remove_file("test_standby.db");
+    }
 }
@@ -139,6 +139,7 @@ fn main() {
                         is_active: true,
                         data: p,
                         lua_data: None,
+                        vita_data: None,
                     });
                 }
             }
@@ -0,0 +1,73 @@
+//! Alliances of parties that share quest maps. A quest started by one party of the alliance is
+//! offered to the other parties that aren't on a quest. Once their leaders accept it, up to 12
+//! players play on one map.
+use crate::{mutex::RwLock, party::Party};
+use std::sync::{Arc, Weak};
+
+/// Maximum number of parties in an alliance.
+pub const MAX_PARTIES: usize = 3;
+
+pub struct Alliance {
+    parties: parking_lot::Mutex<Vec<Weak<RwLock<Party>>>>,
+}
+
+impl Alliance {
+    /// Creates an alliance of two parties.
+    pub fn new(first: &Arc<RwLock<Party>>, second: &Arc<RwLock<Party>>) -> Arc<Self> {
+        Arc::new(Self {
+            parties: parking_lot::Mutex::new(vec![Arc::downgrade(first), Arc::downgrade(second)]),
+        })
+    }
+    /// Returns parties of the alliance that still exist.
+    pub fn parties(&self) -> Vec<Arc<RwLock<Party>>> {
+        let mut parties = self.parties.lock();
+        parties.retain(|p| p.strong_count() != 0);
+        parties.iter().filter_map(Weak::upgrade).collect()
+    }
+    /// Adds the party to the alliance. Returns `false` if the alliance is full.
+    pub fn join(&self, party: &Arc<RwLock<Party>>) -> bool {
+        let mut parties = self.parties.lock();
+        parties.retain(|p| p.strong_count() != 0);
+        let party = Arc::downgrade(party);
+        if parties.iter().any(|p| p.ptr_eq(&party)) {
+            return true;
+        }
+        if parties.len() >= MAX_PARTIES {
+            return false;
+        }
+        parties.push(party);
+        true
+    }
+    /// Removes the party from the alliance and returns the remaining parties.
+    pub fn leave(&self, party: &Arc<RwLock<Party>>) -> Vec<Arc<RwLock<Party>>> {
+        let party = Arc::downgrade(party);
+        self.parties.lock().retain(|p| !p.ptr_eq(&party));
+        self.parties()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alliance, MAX_PARTIES};
+    use crate::{mutex::RwLock, party::Party};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_alliance() {
+        let parties: Vec<_> = (0..4)
+            .map(|i| Arc::new(RwLock::new(Party::new(i))))
+            .collect();
+        let alliance = Alliance::new(&parties[0], &parties[1]);
+        assert!(alliance.join(&parties[2]));
+        assert!(alliance.join(&parties[2]));
+        assert!(!alliance.join(&parties[3]));
+        assert_eq!(alliance.parties().len(), MAX_PARTIES);
+
+        assert_eq!(alliance.leave(&parties[0]).len(), 2);
+        let mut parties = parties;
+        // dropped parties free their slots
+        parties.remove(1);
+        assert_eq!(alliance.parties().len(), 1);
+        assert!(alliance.join(&parties[2]));
+    }
+}
@@ -0,0 +1,210 @@
+//! ARKS missions completed by reaching conditions defined in the server data.
+use crate::{
+    clock::ServerClock,
+    directory::PlayerDirectory,
+    events::{self, EventBus, GameEvent},
+    user::handlers,
+};
+use data_structs::mission::{ArksMission, MissionCondition, MissionReset};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// ARKS mission progress of a character.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArksMissions {
+    pub missions: Vec<MissionProgress>,
+    /// Daily reset period of the last counted login.
+    pub login_day: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MissionProgress {
+    pub id: u32,
+    /// Reset period of the progress, 0 for missions that are never reset.
+    pub period: u64,
+    pub value: u64,
+    /// Completion time in seconds since UNIX epoch.
+    pub completed: Option<u32>,
+    pub claimed: bool,
+}
+
+/// Current reset periods and time used to update the progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Periods {
+    pub day: u64,
+    pub week: u64,
+    pub now: u32,
+}
+
+impl Periods {
+    pub fn from_clock(clock: &ServerClock) -> Self {
+        Self {
+            day: clock.reset_day(),
+            week: clock.reset_week(),
+            now: clock.now_secs(),
+        }
+    }
+    const fn of(&self, mission: &ArksMission) -> u64 {
+        match mission.reset {
+            MissionReset::Never => 0,
+            MissionReset::Daily => self.day,
+            MissionReset::Weekly => self.week,
+        }
+    }
+}
+
+impl ArksMissions {
+    /// Returns the progress of the mission in the current reset period.
+    pub fn get(&self, mission: &ArksMission, periods: Periods) -> Option<&MissionProgress> {
+        self.missions
+            .iter()
+            .find(|p| p.id == mission.id && p.period == periods.of(mission))
+    }
+    /// Returns the progress of the mission, starting a new one if the mission was reset.
+    fn get_mut(&mut self, mission: &ArksMission, periods: Periods) -> &mut MissionProgress {
+        let period = periods.of(mission);
+        let index = match self.missions.iter().position(|p| p.id == mission.id) {
+            Some(index) => index,
+            None => {
+                self.missions.push(MissionProgress {
+                    id: mission.id,
+                    period,
+                    ..Default::default()
+                });
+                self.missions.len() - 1
+            }
+        };
+        let progress = &mut self.missions[index];
+        if progress.period != period {
+            *progress = MissionProgress {
+                id: mission.id,
+                period,
+                ..Default::default()
+            };
+        }
+        progress
+    }
+    /// Updates the progress with the event and returns newly completed missions.
+    pub fn record<'a>(
+        &mut self,
+        event: &GameEvent,
+        missions: &'a [ArksMission],
+        periods: Periods,
+    ) -> Vec<&'a ArksMission> {
+        let condition = match event {
+            GameEvent::EnemyKilled { .. } => MissionCondition::EnemiesKilled,
+            GameEvent::QuestCleared { .. } => MissionCondition::QuestsCleared,
+            GameEvent::LoggedIn { .. } if self.login_day != periods.day => {
+                self.login_day = periods.day;
+                MissionCondition::Logins
+            }
+            _ => return vec![],
+        };
+        let mut completed = vec![];
+        for mission in missions.iter().filter(|m| m.condition == condition) {
+            let progress = self.get_mut(mission, periods);
+            if progress.completed.is_some() {
+                continue;
+            }
+            progress.value += 1;
+            if progress.value >= mission.value {
+                progress.completed = Some(periods.now);
+                completed.push(mission);
+            }
+        }
+        completed
+    }
+    /// Checks if the mission is completed and not claimed yet.
+    pub fn is_claimable(&self, mission: &ArksMission, periods: Periods) -> bool {
+        self.get(mission, periods)
+            .is_some_and(|p| p.completed.is_some() && !p.claimed)
+    }
+    /// Marks the completed mission as claimed. Returns `false` if it's not claimable.
+    pub fn claim(&mut self, mission: &ArksMission, periods: Periods) -> bool {
+        let period = periods.of(mission);
+        match self
+            .missions
+            .iter_mut()
+            .find(|p| p.id == mission.id && p.period == period)
+        {
+            Some(progress) if progress.completed.is_some() && !progress.claimed => {
+                progress.claimed = true;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Starts tracking mission conditions of online players.
+pub fn subscribe(bus: &EventBus, directory: Arc<PlayerDirectory>) {
+    events::spawn_user_subscriber(
+        bus,
+        "arksmission",
+        directory,
+        |event| {
+            matches!(
+                event,
+                GameEvent::EnemyKilled { .. }
+                    | GameEvent::QuestCleared { .. }
+                    | GameEvent::LoggedIn { .. }
+            )
+        },
+        |user, event| Box::pin(handlers::arksmission::on_event(user, event)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArksMissions, Periods};
+    use crate::events::GameEvent;
+    use data_structs::mission::{ArksMission, MissionCondition, MissionReset};
+
+    #[test]
+    fn test_record() {
+        let missions = vec![
+            ArksMission {
+                id: 1,
+                condition: MissionCondition::Logins,
+                reset: MissionReset::Weekly,
+                value: 2,
+                ..Default::default()
+            },
+            ArksMission {
+                id: 2,
+                condition: MissionCondition::EnemiesKilled,
+                reset: MissionReset::Daily,
+                value: 1,
+                ..Default::default()
+            },
+        ];
+        let login = GameEvent::LoggedIn { player_id: 1 };
+        let mut periods = Periods {
+            day: 10,
+            week: 1,
+            now: 100,
+        };
+        let mut progress = ArksMissions::default();
+        assert!(progress.record(&login, &missions, periods).is_empty());
+        // only one login per day is counted
+        assert!(progress.record(&login, &missions, periods).is_empty());
+        periods.day += 1;
+        assert_eq!(progress.record(&login, &missions, periods)[0].id, 1);
+        assert!(progress.claim(&missions[0], periods));
+        assert!(!progress.claim(&missions[0], periods));
+
+        let kill = GameEvent::EnemyKilled {
+            player_id: 1,
+            exp: 10,
+        };
+        assert_eq!(progress.record(&kill, &missions, periods)[0].id, 2);
+        assert!(progress.record(&kill, &missions, periods).is_empty());
+        // daily missions are reset the next day
+        periods.day += 1;
+        assert!(progress.get(&missions[1], periods).is_none());
+        assert_eq!(progress.record(&kill, &missions, periods)[0].id, 2);
+        assert_eq!(progress.get(&missions[0], periods).unwrap().value, 2);
+    }
+}
@@ -0,0 +1,263 @@
+//! Bandwidth accounting of client connections by player and packet type.
+use parking_lot::Mutex;
+use pso2packetlib::protocol::{Packet, PacketType, ProtocolRW};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+/// Traffic by packet id and subid of closed connections.
+static CLOSED: Mutex<BTreeMap<(u8, u16), Traffic>> = Mutex::new(BTreeMap::new());
+/// Traffic counters of open connections.
+static CONNECTIONS: Mutex<Vec<Weak<ConnectionTraffic>>> = Mutex::new(Vec::new());
+
+/// How often the top talkers are logged.
+const LOG_PERIOD: Duration = Duration::from_secs(600);
+/// Number of logged top talkers.
+const TOP_TALKERS: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+}
+
+/// Traffic of one client connection.
+#[derive(Default)]
+pub struct ConnectionTraffic {
+    /// Id of the logged in player, 0 before the login.
+    player_id: AtomicU32,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    packets_in: AtomicU64,
+    packets_out: AtomicU64,
+    /// Bytes in both directions since the last top talkers log.
+    period_bytes: AtomicU64,
+    /// Traffic by packet id and subid. Only the connection task and reports lock it.
+    packets: Mutex<BTreeMap<(u8, u16), Traffic>>,
+}
+
+impl Traffic {
+    pub const fn total_bytes(&self) -> u64 {
+        self.bytes_in + self.bytes_out
+    }
+}
+
+impl std::fmt::Display for Traffic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} KiB in ({} packets), {} KiB out ({} packets)",
+            self.bytes_in.div_ceil(1024),
+            self.packets_in,
+            self.bytes_out.div_ceil(1024),
+            self.packets_out
+        )
+    }
+}
+
+impl ConnectionTraffic {
+    /// Creates counters of a new connection.
+    pub fn register() -> Arc<Self> {
+        let traffic = Arc::new(Self::default());
+        let mut connections = CONNECTIONS.lock();
+        connections.retain(|c| c.strong_count() != 0);
+        connections.push(Arc::downgrade(&traffic));
+        traffic
+    }
+    pub fn set_player(&self, id: u32) {
+        self.player_id.store(id, Ordering::Relaxed);
+    }
+    pub fn player_id(&self) -> u32 {
+        self.player_id.load(Ordering::Relaxed)
+    }
+    /// Records the packet received from the client. The connection doesn't expose read buffers,
+    /// so the packet is serialized to get its size.
+    pub fn record_in(&self, packet: &Packet, packet_type: PacketType) {
+        let data = packet.write(packet_type);
+        let (id, size) = measure(&data, packet_type);
+        self.bytes_in.fetch_add(size, Ordering::Relaxed);
+        self.packets_in.fetch_add(1, Ordering::Relaxed);
+        self.period_bytes.fetch_add(size, Ordering::Relaxed);
+        let mut packets = self.packets.lock();
+        let traffic = packets.entry(id).or_default();
+        traffic.bytes_in += size;
+        traffic.packets_in += 1;
+    }
+    /// Records the serialized packet sent to the client.
+    pub fn record_out(&self, data: &[u8], packet_type: PacketType) {
+        let (id, size) = measure(data, packet_type);
+        self.bytes_out.fetch_add(size, Ordering::Relaxed);
+        self.packets_out.fetch_add(1, Ordering::Relaxed);
+        self.period_bytes.fetch_add(size, Ordering::Relaxed);
+        let mut packets = self.packets.lock();
+        let traffic = packets.entry(id).or_default();
+        traffic.bytes_out += size;
+        traffic.packets_out += 1;
+    }
+    pub fn get(&self) -> Traffic {
+        Traffic {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            packets_in: self.packets_in.load(Ordering::Relaxed),
+            packets_out: self.packets_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for ConnectionTraffic {
+    fn drop(&mut self) {
+        add_packets(&mut CLOSED.lock(), self.packets.get_mut());
+    }
+}
+
+fn add_packets(totals: &mut BTreeMap<(u8, u16), Traffic>, packets: &BTreeMap<(u8, u16), Traffic>) {
+    for (id, traffic) in packets {
+        let total = totals.entry(*id).or_default();
+        total.bytes_in += traffic.bytes_in;
+        total.bytes_out += traffic.bytes_out;
+        total.packets_in += traffic.packets_in;
+        total.packets_out += traffic.packets_out;
+    }
+}
+
+/// Returns the traffic by packet id of all connections.
+fn all_packets() -> BTreeMap<(u8, u16), Traffic> {
+    let mut totals = CLOSED.lock().clone();
+    let connections: Vec<_> = CONNECTIONS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for connection in connections {
+        add_packets(&mut totals, &connection.packets.lock());
+    }
+    totals
+}
+
+/// Returns the packet id and the size of the unencrypted packet.
+fn measure(data: &[u8], packet_type: PacketType) -> ((u8, u16), u64) {
+    // headers start with the packet size followed by the id and the subid, which is 2 bytes long
+    // in NGS packets
+    let id = match (packet_type, data) {
+        (PacketType::NGS, [_, _, _, _, id, sub1, sub2, ..]) => {
+            (*id, u16::from_le_bytes([*sub1, *sub2]))
+        }
+        (_, [_, _, _, _, id, subid, ..]) => (*id, *subid as u16),
+        _ => (0, 0),
+    };
+    (id, data.len() as u64)
+}
+
+/// Formats the packet id as it's written in packet documentation.
+pub fn packet_name((id, subid): (u8, u16)) -> String {
+    format!("{id:02X}-{subid:02X}")
+}
+
+/// Returns the traffic by packet id, sorted by the total size.
+pub fn packet_totals() -> Vec<((u8, u16), Traffic)> {
+    let mut totals: Vec<_> = all_packets().into_iter().collect();
+    totals.sort_by_key(|(_, t)| std::cmp::Reverse(t.total_bytes()));
+    totals
+}
+
+/// Returns the traffic of open connections by player id, sorted by the total size.
+pub fn connection_totals() -> Vec<(u32, Traffic)> {
+    let mut totals: Vec<_> = CONNECTIONS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|c| (c.player_id(), c.get()))
+        .collect();
+    totals.sort_by_key(|(_, t)| std::cmp::Reverse(t.total_bytes()));
+    totals
+}
+
+/// Returns the traffic of the player's connection.
+pub fn player_traffic(player_id: u32) -> Option<Traffic> {
+    CONNECTIONS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|c| c.player_id() == player_id)
+        .map(|c| c.get())
+}
+
+/// Periodically logs players with the most traffic during the period.
+pub async fn log_task() {
+    let mut interval = tokio::time::interval(LOG_PERIOD);
+    // the first tick completes immediately
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let mut period: Vec<_> = CONNECTIONS
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|c| (c.player_id(), c.period_bytes.swap(0, Ordering::Relaxed)))
+            .filter(|(_, bytes)| *bytes != 0)
+            .collect();
+        if period.is_empty() {
+            continue;
+        }
+        period.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+        let top: Vec<_> = period
+            .iter()
+            .take(TOP_TALKERS)
+            .map(|(id, bytes)| format!("player {id}: {} KiB", bytes.div_ceil(1024)))
+            .collect();
+        log::info!("Top talkers: {}", top.join(", "));
+    }
+}
+
+/// Returns the traffic by packet id in the form reported to the master ship.
+pub fn to_list() -> Vec<(String, u64, u64)> {
+    all_packets()
+        .into_iter()
+        .map(|(id, t)| (packet_name(id), t.bytes_in, t.bytes_out))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{measure, packet_name, packet_totals, ConnectionTraffic};
+    use pso2packetlib::protocol::{Packet, PacketHeader, PacketType, ProtocolRW};
+
+    #[test]
+    fn test_measure() {
+        let packet = Packet::Unknown((
+            PacketHeader {
+                id: 0x49,
+                subid: 0x01,
+                ..Default::default()
+            },
+            vec![0; 16],
+        ));
+        let (id, size) = measure(&packet.write(PacketType::Classic), PacketType::Classic);
+        assert_eq!(id, (0x49, 0x01));
+        assert!(size >= 16);
+        assert_eq!(packet_name(id), "49-01");
+    }
+
+    #[test]
+    fn test_closed_connection_totals() {
+        let data = [8, 0, 0, 0, 0xEE, 0x7F, 0, 0];
+        let connection = ConnectionTraffic::register();
+        connection.record_out(&data, PacketType::Classic);
+        connection.record_out(&data, PacketType::Classic);
+        assert_eq!(connection.get().bytes_out, 16);
+        let live = packet_totals();
+        drop(connection);
+        // totals of closed connections are kept
+        for totals in [live, packet_totals()] {
+            let (_, traffic) = totals.iter().find(|(id, _)| *id == (0xEE, 0x7F)).unwrap();
+            assert_eq!(traffic.packets_out, 2);
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::{Error, User};
-use data_structs::{stats::EnemyHitbox, ServerData};
+use data_structs::{
+    stats::{CombatFormula, EnemyHitbox},
+    ServerData,
+};
 use pso2packetlib::protocol::{
     models::{character::Class, Position},
     objects::{DamageReceivePacket, EnemyKilledPacket},
@@ -10,6 +13,7 @@ use rand::distributions::Distribution;
 
 #[derive(Debug, Clone, Default)]
 pub struct PlayerStats {
+    level: u32,
     max_hp: u32,
     hp: u32,
     dex: u32,
@@ -73,6 +77,7 @@ impl PlayerStats {
         let class = char_data.classes.main_class as usize;
         let level = char_data.get_level().level1 as usize;
         let mut resulting_stats = Self::calculate_class_stats(user, class, level);
+        resulting_stats.level = level as u32;
 
         if char_data.classes.sub_class != Class::Unknown {
             // source: arks-visiphone
@@ -189,29 +194,31 @@ impl PlayerStats {
             data_structs::stats::AttackType::Rng => enemy.rng_def,
             data_structs::stats::AttackType::Tec => enemy.tec_def,
         };
-        let total_mul = 1.0 * hitbox.damage_mul;
-        let min_pure_attack =
-            (base_pwr as f32 + weapon_pwr as f32 * 0.9 - def as f32).clamp(1.0, f32::MAX);
-        let pure_attack = (base_pwr + weapon_pwr)
-            .saturating_sub(def)
-            .clamp(1, u32::MAX) as f32;
+        let formula = &srv_data.combat;
+        let level_diff = self.level as i32 - enemy.level as i32;
+        let total_mul = formula.level_mul(level_diff) * hitbox.damage_mul;
+        let def = def as f32 * formula.defense_mul;
+        let min_pure_attack = (base_pwr as f32 + weapon_pwr as f32 * formula.min_weapon_mul - def)
+            .clamp(1.0, f32::MAX);
+        let pure_attack = ((base_pwr + weapon_pwr) as f32 - def).clamp(1.0, f32::MAX);
         let damage_mul = match damage.damage {
             data_structs::stats::DamageType::Generic(m) => m,
             data_structs::stats::DamageType::PA(_) => todo!(),
         };
-        let min_weapon_attack = min_pure_attack / 5.0 * 1.05 * part_mul * damage_mul * total_mul;
-        let max_weapon_attack = pure_attack / 5.0 * 1.05 * part_mul * damage_mul * total_mul;
+        let min_weapon_attack = min_pure_attack / formula.damage_divisor
+            * formula.damage_mul
+            * part_mul
+            * damage_mul
+            * total_mul;
+        let max_weapon_attack = pure_attack / formula.damage_divisor
+            * formula.damage_mul
+            * part_mul
+            * damage_mul
+            * total_mul;
 
         //TODO: elemental dmg
 
-        let mut rng = rand::rngs::OsRng;
-        let crit_chance = rand::distributions::Uniform::new(0, 100).sample(&mut rng);
-        let dmg = if crit_chance < 5 || min_weapon_attack == max_weapon_attack {
-            max_weapon_attack
-        } else {
-            rand::distributions::Uniform::new(min_weapon_attack, max_weapon_attack).sample(&mut rng)
-        }
-        .round() as u32;
+        let dmg = roll_damage(formula, min_weapon_attack, max_weapon_attack);
         enemy.hp = enemy.hp.saturating_sub(dmg);
         let dmg_packet = DamageReceivePacket {
             dmg_target: attack.target,
@@ -257,7 +264,13 @@ impl PlayerStats {
 }
 
 impl EnemyStats {
-    pub fn build(name: &str, level: u32, pos: Position, data: &ServerData) -> Result<Self, Error> {
+    pub fn build(
+        name: &str,
+        level: u32,
+        difficulty: u8,
+        pos: Position,
+        data: &ServerData,
+    ) -> Result<Self, Error> {
         let mut resulting_stats = Self {
             name: name.to_string(),
             pos,
@@ -270,7 +283,9 @@ impl EnemyStats {
             .get(name)
             .ok_or(Error::NoEnemyData(name.to_string()))?;
         resulting_stats.hitboxes.clone_from(&enemy_stats.hitboxes);
-        let base_level_stats = &base_stats.levels[level as usize - 1];
+        let base_level_stats = base_stats
+            .level_stats(difficulty, level)
+            .ok_or(Error::NoEnemyData(name.to_string()))?;
         let level_stats = &enemy_stats.levels[level as usize - 1];
 
         resulting_stats.level = level_stats.level;
@@ -296,6 +311,9 @@ impl EnemyStats {
 
         Ok(resulting_stats)
     }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
     pub fn create_spawn_packet(&self, id: u32, map_id: u16) -> EnemySpawnPacket {
         EnemySpawnPacket {
             object: pso2packetlib::protocol::ObjectHeader {
@@ -346,26 +364,24 @@ impl EnemyStats {
             data_structs::stats::AttackType::Rng => player.base_rng_def,
             data_structs::stats::AttackType::Tec => player.base_tec_def,
         };
-        let total_mul = 1.0;
-        let min_pure_attack = min_pwr.saturating_sub(def).clamp(1, u32::MAX) as f32;
-        let pure_attack = max_pwr.saturating_sub(def).clamp(1, u32::MAX) as f32;
+        let formula = &srv_data.combat;
+        let level_diff = self.level as i32 - player.level as i32;
+        let total_mul = formula.level_mul(level_diff);
+        let def = def as f32 * formula.defense_mul;
+        let min_pure_attack = (min_pwr as f32 - def).clamp(1.0, f32::MAX);
+        let pure_attack = (max_pwr as f32 - def).clamp(1.0, f32::MAX);
         let damage_mul = match damage.damage {
             data_structs::stats::DamageType::Generic(m) => m,
             data_structs::stats::DamageType::PA(_) => unimplemented!(),
         };
-        let min_weapon_attack = min_pure_attack / 5.0 * 1.05 * damage_mul * total_mul;
-        let max_weapon_attack = pure_attack / 5.0 * 1.05 * damage_mul * total_mul;
+        let min_weapon_attack =
+            min_pure_attack / formula.damage_divisor * formula.damage_mul * damage_mul * total_mul;
+        let max_weapon_attack =
+            pure_attack / formula.damage_divisor * formula.damage_mul * damage_mul * total_mul;
 
         //TODO: elemental res
 
-        let mut rng = rand::rngs::OsRng;
-        let crit_chance = rand::distributions::Uniform::new(0, 100).sample(&mut rng);
-        let dmg = if crit_chance < 5 || min_weapon_attack == max_weapon_attack {
-            max_weapon_attack
-        } else {
-            rand::distributions::Uniform::new(min_weapon_attack, max_weapon_attack).sample(&mut rng)
-        }
-        .round() as u32;
+        let dmg = roll_damage(formula, min_weapon_attack, max_weapon_attack);
         player.hp = player.hp.saturating_sub(dmg);
         let dmg_packet = DamageReceivePacket {
             dmg_target: attack.target,
@@ -409,3 +425,15 @@ impl EnemyStats {
         })
     }
 }
+
+/// Rolls the damage between the minimum and maximum attack. Critical hits deal the maximum damage.
+fn roll_damage(formula: &CombatFormula, min_attack: f32, max_attack: f32) -> u32 {
+    let mut rng = rand::rngs::OsRng;
+    let crit_roll = rand::distributions::Uniform::new(0.0, 100.0).sample(&mut rng);
+    if crit_roll < formula.crit_chance || min_attack >= max_attack {
+        max_attack
+    } else {
+        rand::distributions::Uniform::new(min_attack, max_attack).sample(&mut rng)
+    }
+    .round() as u32
+}
@@ -1,13 +1,17 @@
 use crate::{
     map,
     mutex::{Mutex, RwLock},
+    queue,
+    resets::ResetEvent,
+    schedule::ScheduleChange,
     sql,
-    user::User,
+    user::{handlers, User, UserState},
     Action, BlockData, BlockInfo, Error,
 };
 use pso2packetlib::{connection::ConnectionError, PrivateKey};
 use std::{
     io,
+    net::IpAddr,
     sync::{atomic::AtomicU32, Arc},
 };
 use tokio::{
@@ -25,37 +29,60 @@ pub async fn init_block(
 
     let latest_mapid = AtomicU32::new(0);
 
-    let Some(lobby) = this_block.server_data.maps.get(&this_block.lobby_map) else {
+    let Some(lobby) = this_block.map_templates.get(&this_block.lobby_map) else {
         return Err(Error::NoMapFound(this_block.lobby_map.clone()));
     };
 
     let lobby = Arc::new(Mutex::new({
-        let mut map = map::Map::new_from_data(lobby.clone(), &latest_mapid)?;
+        let mut map = map::Map::new_from_template(lobby.clone(), &latest_mapid)?;
         map.set_map_type(map::MapType::Lobby);
+        map.set_name(&this_block.lobby_map);
         map
     }));
+    map::start_despawn_task(&lobby);
 
     let block_data = Arc::new(BlockData {
         sql,
         blocks,
         block_id: this_block.id,
         block_name: this_block.name,
-        lobby,
+        ngs: this_block.ngs,
+        lobby: RwLock::new(lobby.clone()),
+        default_lobby: lobby,
         key,
         latest_mapid,
-        latest_partyid: AtomicU32::new(0),
+        parties: this_block.parties,
+        directory: this_block.directory,
         server_data: this_block.server_data,
+        map_templates: this_block.map_templates,
         quests: this_block.quests,
+        counter: this_block.counter,
         clients: Mutex::new(vec![]),
+        chat_settings: this_block.chat_settings,
+        quest_settings: this_block.quest_settings,
+        shop_settings: this_block.shop_settings,
+        exp_settings: this_block.exp_settings,
+        clock: this_block.clock,
+        resets: this_block.resets,
+        events: this_block.events,
+        macro_settings: this_block.macro_settings,
+        unlocks: this_block.unlocks,
+        daily: this_block.daily,
+        casino: this_block.casino,
+        schedule: this_block.schedule,
     });
     // we are the only owner of the map, so this never blocks
     block_data
-        .lobby
+        .default_lobby
         .lock_blocking()
         .set_block_data(block_data.clone());
 
     let mut conn_id = 0usize;
     let (send, mut recv) = mpsc::channel(10);
+    let mut broadcasts = block_data.sql.subscribe_broadcasts();
+    let mut resets = block_data.resets.subscribe();
+    let mut tickets = block_data.sql.subscribe_tickets();
+    let mut schedule = block_data.schedule.subscribe();
 
     loop {
         tokio::select! {
@@ -78,10 +105,123 @@ pub async fn init_block(
                     Err(e) => log::warn!("Client error: {e}"),
                 };
             }
+            Ok(message) = broadcasts.recv() => {
+                let clients = block_data.clients.lock().await.clone();
+                tokio::spawn(async move {
+                    for (_, client) in clients {
+                        let _ = client.lock().await.send_admin_msg(&message).await;
+                    }
+                });
+            }
+            Ok(change) = schedule.recv() => {
+                if let Err(e) = on_schedule_change(&block_data, change).await {
+                    log::warn!("Failed to apply server event: {e}");
+                }
+            }
+            Ok(event) = resets.recv() => {
+                let clients = block_data.clients.lock().await.clone();
+                tokio::spawn(async move {
+                    for (_, client) in clients {
+                        let _ = on_reset(&mut *client.lock().await, event).await;
+                    }
+                });
+            }
+            Ok(ticket) = tickets.recv() => {
+                let clients = block_data.clients.lock().await.clone();
+                tokio::spawn(async move {
+                    for (_, client) in clients {
+                        let mut client = client.lock().await;
+                        let _ = handlers::support::on_ticket_update(&mut client, &ticket).await;
+                    }
+                });
+            }
         };
     }
 }
 
+/// Replaces the block lobby with the event one and moves all lobby players to it.
+pub async fn start_event_lobby(block_data: &Arc<BlockData>, map_name: &str) -> Result<(), Error> {
+    let Some(map_data) = block_data.map_templates.get(map_name) else {
+        return Err(Error::NoMapFound(map_name.to_string()));
+    };
+    let new_lobby = Arc::new(Mutex::new({
+        let mut map = map::Map::new_from_template(map_data.clone(), &block_data.latest_mapid)?;
+        map.set_map_type(map::MapType::Lobby);
+        map.set_name(map_name);
+        map.set_block_data(block_data.clone());
+        map
+    }));
+    map::start_despawn_task(&new_lobby);
+    log::info!(
+        "Block {}: starting event lobby {map_name}",
+        block_data.block_name
+    );
+    swap_lobby(block_data, new_lobby).await
+}
+
+/// Restores the default block lobby and moves all event lobby players back.
+pub async fn end_event_lobby(block_data: &Arc<BlockData>) -> Result<(), Error> {
+    log::info!("Block {}: ending event lobby", block_data.block_name);
+    swap_lobby(block_data, block_data.default_lobby.clone()).await
+}
+
+async fn swap_lobby(block_data: &BlockData, new_lobby: Arc<Mutex<map::Map>>) -> Result<(), Error> {
+    let old_lobby = {
+        let mut lobby = block_data.lobby.write().await;
+        if Arc::ptr_eq(&*lobby, &new_lobby) {
+            return Ok(());
+        }
+        std::mem::replace(&mut *lobby, new_lobby.clone())
+    };
+    old_lobby.lock().await.move_all_players(new_lobby).await
+}
+
+/// Applies or reverts changes of a server event and sends its announcements.
+async fn on_schedule_change(
+    block_data: &Arc<BlockData>,
+    change: ScheduleChange,
+) -> Result<(), Error> {
+    let message = match change {
+        ScheduleChange::Started(i) | ScheduleChange::Ended(i) => {
+            let Some(event) = block_data.schedule.get(i) else {
+                return Ok(());
+            };
+            if event.lobby.is_some() {
+                // another running event can still need an event lobby
+                match block_data.schedule.lobby() {
+                    Some(lobby) => start_event_lobby(block_data, lobby).await?,
+                    None => end_event_lobby(block_data).await?,
+                }
+            }
+            if matches!(change, ScheduleChange::Started(_)) {
+                format!("Event started: {}", event.name)
+            } else {
+                format!("Event ended: {}", event.name)
+            }
+        }
+        ScheduleChange::Announce(message) => message,
+    };
+    let clients = block_data.clients.lock().await.clone();
+    tokio::spawn(async move {
+        for (_, client) in clients {
+            let _ = client.lock().await.send_admin_msg(&message).await;
+        }
+    });
+    Ok(())
+}
+
+/// Refreshes periodic content of the player after the reset.
+async fn on_reset(user: &mut User, event: ResetEvent) -> Result<(), Error> {
+    if user.state != UserState::InGame {
+        return Ok(());
+    }
+    // weekly resets are always accompanied by daily ones
+    if event == ResetEvent::Daily {
+        handlers::arksmission::mission_list(user).await?;
+    }
+    Ok(())
+}
+
 async fn new_conn_handler(
     s: TcpStream,
     block_data: &Arc<BlockData>,
@@ -91,14 +231,26 @@ async fn new_conn_handler(
 ) -> Result<(), Error> {
     log::info!("Client connected");
 
+    let ip = s.peer_addr().ok().and_then(|a| match a.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped(),
+    });
     let mut lock = block_data.blocks.write().await;
+    let ship_full = lock.iter().all(|b| b.players >= b.max_players);
     if let Some(block) = lock.iter_mut().find(|x| x.id == block_id) {
         if block.players >= block.max_players {
+            // clients can pick another block if this one is full, so only a full ship has a queue
+            if let Some(ip) = ip.filter(|_| ship_full) {
+                queue::rejected(ip);
+            }
             return Ok(());
         }
         block.players += 1;
     }
     drop(lock);
+    if let Some(ip) = ip {
+        queue::admitted(ip);
+    }
 
     let conn_id = *conn_id_ref;
     let (client, mut read) = User::new(s, block_data.clone(), conn_id)?;
@@ -117,49 +269,36 @@ async fn new_conn_handler(
                             crate::user::packet_handler(lock, a).await
                         },
                         Err(ConnectionError::Io(e)) if matches!(e.kind(), io::ErrorKind::Interrupted) => Ok(Action::Nothing),
-                        Err(ConnectionError::Io(e))
-                            if matches!(
-                                e.kind(),
-                                io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
-                            ) =>
-                        {
-                            send.send((conn_id, Action::Disconnect)).await.unwrap();
-                            return;
-                        }
-                        Err(e) => {
-                            let error_msg = format!("Client error: {e}");
-                            let _ = client.lock().await.send_error(&error_msg).await;
-                            log::warn!("{}", error_msg);
-                            Ok(Action::Nothing)
-                        }
+                        Err(e) => Err(e.into()),
                     }
                 }
                 _ = interval.tick() => {
                     User::tick(client.lock().await).await
                 }
             };
-            match result {
-                Ok(Action::Nothing) => {}
-                Ok(Action::Disconnect) => {
-                    send.send((conn_id, Action::Disconnect)).await.unwrap();
-                    return;
+            let reason = match result {
+                Ok(Action::Nothing) => continue,
+                Ok(Action::Disconnect(reason)) => reason,
+                Err(Error::IOError(e)) if matches!(e.kind(), io::ErrorKind::Interrupted) => {
+                    continue
                 }
-                Err(Error::IOError(e)) if matches!(e.kind(), io::ErrorKind::Interrupted) => {}
-                Err(Error::IOError(e))
-                    if matches!(
-                        e.kind(),
-                        io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
-                    ) =>
-                {
-                    send.send((conn_id, Action::Disconnect)).await.unwrap();
-                    return;
-                }
-                Err(e) => {
-                    let error_msg = format!("Client error: {e}");
-                    let _ = client.lock().await.send_error(&error_msg).await;
-                    log::warn!("{}", error_msg);
-                }
-            }
+                Err(e) => match e.disconnect_reason() {
+                    Some(reason) => reason,
+                    None => {
+                        let code = e.code();
+                        code.record();
+                        let _ = client.lock().await.send_error_code(code).await;
+                        log::warn!("Client error ({}): {e}", <&str>::from(code));
+                        continue;
+                    }
+                },
+            };
+            reason.record();
+            client.lock().await.send_disconnect_message(reason);
+            send.send((conn_id, Action::Disconnect(reason)))
+                .await
+                .unwrap();
+            return;
         }
     });
 
@@ -186,14 +325,19 @@ async fn run_action(
     };
     match action {
         Action::Nothing => {}
-        Action::Disconnect => {
-            log::info!("Client disconnected");
-            clients.remove(pos);
+        Action::Disconnect(reason) => {
+            log::info!("Client disconnected ({})", <&str>::from(reason));
+            let (_, client) = clients.remove(pos);
+            drop(clients);
 
             let mut lock = block_data.blocks.write().await;
             if let Some(block) = lock.iter_mut().find(|x| x.id == block_data.block_id) {
                 block.players -= 1;
             }
+            drop(lock);
+            if client.lock().await.left_ship(reason) {
+                queue::departed();
+            }
         }
     }
     Ok(())
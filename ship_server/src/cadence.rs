@@ -0,0 +1,178 @@
+//! Detection of macros by the timing of player input.
+use pso2packetlib::protocol::Packet;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Macro detection settings.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct MacroSettings {
+    /// Number of inputs in a row with the same interval to flag the player (0 - disabled).
+    pub min_repeats: u32,
+    /// Maximum difference between intervals in milliseconds that is treated as the same interval.
+    pub tolerance: u64,
+    /// Minimum duration of the sequence in seconds to flag the player.
+    pub min_duration: u64,
+    pub enforcement: MacroEnforcement,
+}
+
+/// Action taken against flagged players.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MacroEnforcement {
+    /// Only record the anomaly.
+    Log,
+    /// Record the anomaly and disconnect the player.
+    Kick,
+}
+
+impl Default for MacroSettings {
+    fn default() -> Self {
+        Self {
+            min_repeats: 100,
+            tolerance: 2,
+            min_duration: 60,
+            enforcement: MacroEnforcement::Log,
+        }
+    }
+}
+
+/// Kind of tracked player input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputKind {
+    Action,
+    Interaction,
+}
+
+impl InputKind {
+    /// Returns the kind of input of the packet if it's tracked. Periodic packets (like movement
+    /// updates) aren't tracked.
+    pub fn of(packet: &Packet) -> Option<Self> {
+        match packet {
+            Packet::MovementAction(..) => Some(Self::Action),
+            Packet::Interact(..) => Some(Self::Interaction),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for InputKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Action => write!(f, "action"),
+            Self::Interaction => write!(f, "interaction"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CadenceCheck {
+    Normal,
+    /// Input has the same interval for too long. Reported once per sequence.
+    Suspicious {
+        repeats: u32,
+        interval: Duration,
+    },
+}
+
+/// Per-user input timing tracker.
+#[derive(Default)]
+pub struct CadenceTracker {
+    sequences: Vec<(InputKind, Sequence)>,
+}
+
+/// Inputs with the same interval.
+struct Sequence {
+    last: Instant,
+    start: Instant,
+    interval: Duration,
+    repeats: u32,
+    reported: bool,
+}
+
+impl CadenceTracker {
+    pub fn check(
+        &mut self,
+        settings: &MacroSettings,
+        kind: InputKind,
+        now: Instant,
+    ) -> CadenceCheck {
+        if settings.min_repeats == 0 {
+            return CadenceCheck::Normal;
+        }
+        let Some((_, seq)) = self.sequences.iter_mut().find(|(k, _)| *k == kind) else {
+            self.sequences.push((
+                kind,
+                Sequence {
+                    last: now,
+                    start: now,
+                    interval: Duration::ZERO,
+                    repeats: 0,
+                    reported: false,
+                },
+            ));
+            return CadenceCheck::Normal;
+        };
+        let interval = now.duration_since(seq.last);
+        let tolerance = Duration::from_millis(settings.tolerance);
+        if interval.abs_diff(seq.interval) <= tolerance && !interval.is_zero() {
+            seq.repeats += 1;
+        } else {
+            seq.start = seq.last;
+            seq.interval = interval;
+            seq.repeats = 1;
+            seq.reported = false;
+        }
+        seq.last = now;
+        let min_duration = Duration::from_secs(settings.min_duration);
+        if !seq.reported
+            && seq.repeats >= settings.min_repeats
+            && now.duration_since(seq.start) >= min_duration
+        {
+            seq.reported = true;
+            return CadenceCheck::Suspicious {
+                repeats: seq.repeats,
+                interval: seq.interval,
+            };
+        }
+        CadenceCheck::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cadence_tracker() {
+        let settings = MacroSettings {
+            min_repeats: 10,
+            tolerance: 2,
+            min_duration: 5,
+            enforcement: MacroEnforcement::Log,
+        };
+        let mut tracker = CadenceTracker::default();
+        let start = Instant::now();
+        let mut now = start;
+        // human-like input with varying intervals
+        for i in 0..30 {
+            now += Duration::from_millis(500 + (i % 5) * 40);
+            let check = tracker.check(&settings, InputKind::Action, now);
+            assert_eq!(check, CadenceCheck::Normal);
+        }
+        // perfect input is reported once
+        let mut reports = 0;
+        for i in 0..30 {
+            now += Duration::from_millis(600 + i % 2);
+            if tracker.check(&settings, InputKind::Action, now) != CadenceCheck::Normal {
+                reports += 1;
+            }
+        }
+        assert_eq!(reports, 1);
+        // other kinds of input are tracked separately
+        assert_eq!(
+            tracker.check(&settings, InputKind::Interaction, now),
+            CadenceCheck::Normal
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Casino coins and games played in the casino zone of the lobby.
+use pso2packetlib::protocol::items::ItemId;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Name of the lobby zone where casino games can be played.
+pub const CASINO_ZONE: &str = "casino";
+
+/// Coin exchange and game settings.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CasinoSettings {
+    /// Meseta price of one casino coin.
+    pub coin_price: u64,
+    /// Maximum number of coins bet on one game.
+    pub max_bet: u64,
+    /// Items that can be exchanged for coins at the exchange counter.
+    pub prizes: Vec<CasinoPrize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct CasinoPrize {
+    pub id: ItemId,
+    pub amount: u16,
+    /// Price in casino coins.
+    pub price: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotSymbol {
+    Rappy,
+    Seven,
+    Bar,
+    Bell,
+    Cherry,
+}
+
+/// Symbols of a reel with their weights. All reels are the same.
+const REEL: [(SlotSymbol, u32); 5] = [
+    (SlotSymbol::Rappy, 1),
+    (SlotSymbol::Seven, 2),
+    (SlotSymbol::Bar, 3),
+    (SlotSymbol::Bell, 4),
+    (SlotSymbol::Cherry, 6),
+];
+
+/// Bet multipliers for three matching symbols. Other combinations lose the bet.
+const PAYOUTS: [(SlotSymbol, u64); 5] = [
+    (SlotSymbol::Rappy, 400),
+    (SlotSymbol::Seven, 80),
+    (SlotSymbol::Bar, 30),
+    (SlotSymbol::Bell, 12),
+    (SlotSymbol::Cherry, 5),
+];
+
+impl Default for CasinoSettings {
+    fn default() -> Self {
+        Self {
+            coin_price: 100,
+            max_bet: 100,
+            prizes: vec![],
+        }
+    }
+}
+
+impl SlotSymbol {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Rappy => "Rappy",
+            Self::Seven => "7",
+            Self::Bar => "BAR",
+            Self::Bell => "Bell",
+            Self::Cherry => "Cherry",
+        }
+    }
+}
+
+/// Spins the rappy slots reels.
+pub fn spin(rng: &mut impl Rng) -> [SlotSymbol; 3] {
+    let total: u32 = REEL.iter().map(|(_, w)| w).sum();
+    std::array::from_fn(|_| {
+        let mut roll = rng.gen_range(0..total);
+        for (symbol, weight) in REEL {
+            if roll < weight {
+                return symbol;
+            }
+            roll -= weight;
+        }
+        unreachable!("roll is less than the total weight")
+    })
+}
+
+/// Returns the number of coins won with the reels.
+pub fn payout(reels: [SlotSymbol; 3], bet: u64) -> u64 {
+    if reels[0] != reels[1] || reels[1] != reels[2] {
+        return 0;
+    }
+    PAYOUTS
+        .iter()
+        .find(|(s, _)| *s == reels[0])
+        .map_or(0, |(_, m)| bet.saturating_mul(*m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payout, spin, SlotSymbol, PAYOUTS, REEL};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_slots() {
+        use SlotSymbol::*;
+        assert_eq!(payout([Rappy, Rappy, Rappy], 2), 800);
+        assert_eq!(payout([Cherry, Cherry, Bell], 2), 0);
+        // the expected return of a coin is below 1, but not by much
+        let total: u32 = REEL.iter().map(|(_, w)| w).sum();
+        let returned: u64 = REEL
+            .iter()
+            .zip(PAYOUTS)
+            .map(|((_, w), (_, m))| u64::from(w.pow(3)) * m)
+            .sum();
+        let rtp = returned as f64 / f64::from(total.pow(3));
+        assert!((0.85..1.0).contains(&rtp), "{rtp}");
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let cherries = (0..3000)
+            .flat_map(|_| spin(&mut rng))
+            .filter(|s| *s == Cherry)
+            .count();
+        // 6 of 16 weight
+        assert!((3000..4000).contains(&cherries), "{cherries}");
+    }
+}
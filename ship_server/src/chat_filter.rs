@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Sanitization rules applied to chat messages before relaying them.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ChatFilter {
+    /// Remove words that look like URLs.
+    pub strip_urls: bool,
+    /// Maximum message length in characters (0 - unlimited).
+    pub max_length: usize,
+    /// Maximum number of consecutive repeated characters (0 - unlimited).
+    pub max_repeats: usize,
+    /// Words that are replaced with asterisks (case insensitive).
+    pub banned_words: Vec<String>,
+}
+
+impl ChatFilter {
+    pub fn apply(&self, message: &str) -> String {
+        let mut message = if self.strip_urls {
+            message
+                .split(' ')
+                .filter(|w| !is_url(w))
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            message.to_string()
+        };
+        if !self.banned_words.is_empty() {
+            message = self.censor(&message);
+        }
+        if self.max_repeats != 0 {
+            message = collapse_repeats(&message, self.max_repeats);
+        }
+        if self.max_length != 0 {
+            message = message.chars().take(self.max_length).collect();
+        }
+        message
+    }
+    /// Checks if the message contains any of the banned words.
+    pub fn has_banned_words(&self, message: &str) -> bool {
+        let message = message.to_lowercase();
+        self.banned_words
+            .iter()
+            .any(|w| !w.is_empty() && message.contains(&w.to_lowercase()))
+    }
+    fn censor(&self, message: &str) -> String {
+        let mut chars: Vec<char> = message.chars().collect();
+        let lower: Vec<char> = chars.iter().map(|&c| to_lower(c)).collect();
+        for word in &self.banned_words {
+            let word: Vec<char> = word.chars().map(to_lower).collect();
+            if word.is_empty() || word.len() > lower.len() {
+                continue;
+            }
+            for start in 0..=lower.len() - word.len() {
+                if lower[start..start + word.len()] == word[..] {
+                    chars[start..start + word.len()].fill('*');
+                }
+            }
+        }
+        chars.into_iter().collect()
+    }
+}
+
+/// Chat spam protection settings.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SpamSettings {
+    /// Maximum number of messages sent during the interval (0 - unlimited).
+    pub max_messages: usize,
+    /// Length of the rate interval in seconds.
+    pub interval: u64,
+    /// Maximum number of identical messages in a row (0 - unlimited).
+    pub max_identical: u32,
+    /// Duration of the mute in seconds.
+    pub mute_duration: u64,
+}
+
+impl Default for SpamSettings {
+    fn default() -> Self {
+        Self {
+            max_messages: 5,
+            interval: 5,
+            max_identical: 3,
+            mute_duration: 30,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SpamCheck {
+    Allowed,
+    /// User was muted by this message.
+    Muted(Duration),
+    /// User is still muted.
+    StillMuted(Duration),
+}
+
+/// Per-user chat rate tracker.
+#[derive(Default)]
+pub struct SpamTracker {
+    recent: VecDeque<Instant>,
+    last_message: String,
+    identical: u32,
+    muted_until: Option<Instant>,
+}
+
+impl SpamTracker {
+    pub fn check(&mut self, settings: &SpamSettings, message: &str, now: Instant) -> SpamCheck {
+        if let Some(until) = self.muted_until {
+            if until > now {
+                return SpamCheck::StillMuted(until - now);
+            }
+            self.muted_until = None;
+        }
+        let interval = Duration::from_secs(settings.interval);
+        while self
+            .recent
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= interval)
+        {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        if self.last_message == message {
+            self.identical += 1;
+        } else {
+            self.last_message = message.to_string();
+            self.identical = 1;
+        }
+        let too_fast = settings.max_messages != 0 && self.recent.len() > settings.max_messages;
+        let too_many_identical =
+            settings.max_identical != 0 && self.identical > settings.max_identical;
+        if too_fast || too_many_identical {
+            let duration = Duration::from_secs(settings.mute_duration);
+            self.muted_until = Some(now + duration);
+            self.recent.clear();
+            self.identical = 0;
+            return SpamCheck::Muted(duration);
+        }
+        SpamCheck::Allowed
+    }
+}
+
+fn to_lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn is_url(word: &str) -> bool {
+    let word = word.to_lowercase();
+    word.contains("://") || word.starts_with("www.")
+}
+
+fn collapse_repeats(message: &str, max_repeats: usize) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut last = None;
+    let mut count = 0;
+    for c in message.chars() {
+        if Some(c) == last {
+            count += 1;
+        } else {
+            last = Some(c);
+            count = 1;
+        }
+        if count <= max_repeats {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatFilter, SpamCheck, SpamSettings, SpamTracker};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_chat_filter() {
+        let filter = ChatFilter {
+            strip_urls: true,
+            max_length: 20,
+            max_repeats: 3,
+            banned_words: vec!["bad".into()],
+        };
+        assert_eq!(filter.apply("visit https://example.com now"), "visit now");
+        assert_eq!(filter.apply("www.example.com"), "");
+        assert_eq!(filter.apply("so BAD"), "so ***");
+        assert!(filter.has_banned_words("so BAD"));
+        assert!(!filter.has_banned_words("so good"));
+        assert_eq!(filter.apply("nooooooo"), "nooo");
+        assert_eq!(filter.apply(&"a b ".repeat(10)).chars().count(), 20);
+        assert_eq!(
+            ChatFilter::default().apply("www.x.com bad"),
+            "www.x.com bad"
+        );
+    }
+
+    #[test]
+    fn test_spam_tracker() {
+        let settings = SpamSettings {
+            max_messages: 3,
+            interval: 5,
+            max_identical: 2,
+            mute_duration: 10,
+        };
+        let mut tracker = SpamTracker::default();
+        let now = Instant::now();
+        assert_eq!(tracker.check(&settings, "a", now), SpamCheck::Allowed);
+        assert_eq!(tracker.check(&settings, "a", now), SpamCheck::Allowed);
+        assert_eq!(
+            tracker.check(&settings, "a", now),
+            SpamCheck::Muted(Duration::from_secs(10))
+        );
+        assert_eq!(
+            tracker.check(&settings, "b", now + Duration::from_secs(4)),
+            SpamCheck::StillMuted(Duration::from_secs(6))
+        );
+
+        let now = now + Duration::from_secs(10);
+        for (i, msg) in ["a", "b", "c"].into_iter().enumerate() {
+            let time = now + Duration::from_secs(i as u64);
+            assert_eq!(tracker.check(&settings, msg, time), SpamCheck::Allowed);
+        }
+        assert_eq!(
+            tracker.check(&settings, "d", now + Duration::from_secs(5)),
+            SpamCheck::Allowed
+        );
+        assert_eq!(
+            tracker.check(&settings, "e", now + Duration::from_secs(5)),
+            SpamCheck::Muted(Duration::from_secs(10))
+        );
+    }
+}
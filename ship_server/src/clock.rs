@@ -0,0 +1,119 @@
+//! Server clock used for all gameplay timing.
+use crate::settings::ClockSettings;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+/// Weekday of the UNIX epoch (Thursday, 0 - Monday).
+const EPOCH_WEEKDAY: u64 = 3;
+
+/// Monotonic clock anchored to the system time at startup. Changes of the system time (and DST)
+/// don't affect it, so timestamps sent to clients never go backwards.
+#[derive(Clone, Copy)]
+pub struct ServerClock {
+    start: Instant,
+    start_time: Duration,
+    settings: ClockSettings,
+}
+
+impl ServerClock {
+    pub fn new(settings: ClockSettings) -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            settings,
+        }
+    }
+    /// Current time since UNIX epoch.
+    pub fn now(&self) -> Duration {
+        self.start_time + self.start.elapsed()
+    }
+    /// Current time since UNIX epoch in seconds.
+    pub fn now_secs(&self) -> u32 {
+        self.now().as_secs() as u32
+    }
+    /// Time (since UNIX epoch) of the next daily reset.
+    pub fn next_daily_reset(&self) -> Duration {
+        let reset_at = self.settings.daily_reset_hour as u64 * HOUR;
+        self.next_reset(self.now(), DAY, reset_at)
+    }
+    /// Number of the current daily reset period.
+    pub fn reset_day(&self) -> u64 {
+        self.next_daily_reset().as_secs() / DAY
+    }
+    /// Time (since UNIX epoch) of the next weekly reset.
+    pub fn next_weekly_reset(&self) -> Duration {
+        let day = (self.settings.weekly_reset_day as u64 + 7 - EPOCH_WEEKDAY) % 7;
+        let reset_at = day * DAY + self.settings.daily_reset_hour as u64 * HOUR;
+        self.next_reset(self.now(), WEEK, reset_at)
+    }
+    /// Number of the current weekly reset period.
+    pub fn reset_week(&self) -> u64 {
+        self.next_weekly_reset().as_secs() / WEEK
+    }
+    /// Returns the first time after `now` when the local time is `reset_at` into the period.
+    fn next_reset(&self, now: Duration, period: u64, reset_at: u64) -> Duration {
+        let offset = self.settings.utc_offset as i64 * 60;
+        let local = now.as_secs() as i64 + offset;
+        let since_reset = (local - reset_at as i64).rem_euclid(period as i64) as u64;
+        Duration::from_secs(now.as_secs() + period - since_reset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(utc_offset: i32) -> ServerClock {
+        ServerClock::new(ClockSettings {
+            utc_offset,
+            daily_reset_hour: 4,
+            weekly_reset_day: 2,
+        })
+    }
+
+    #[test]
+    fn test_daily_reset() {
+        // 2023-07-13 18:17:46 UTC
+        let now = Duration::from_secs(1689272266);
+        let reset = clock(0).next_reset(now, DAY, 4 * HOUR);
+        // 2023-07-14 04:00:00 UTC
+        assert_eq!(reset.as_secs(), 1689307200);
+        // 2023-07-14 04:00:00 JST
+        let reset = clock(9 * 60).next_reset(now, DAY, 4 * HOUR);
+        assert_eq!(reset.as_secs(), 1689274800);
+    }
+
+    #[test]
+    fn test_weekly_reset() {
+        let clock = clock(0);
+        let reset = clock.next_weekly_reset();
+        assert!(reset > clock.now());
+        assert!(reset <= clock.now() + Duration::from_secs(WEEK));
+        // 2023-07-19 is a Wednesday
+        let now = Duration::from_secs(1689272266);
+        let reset = clock.next_reset(now, WEEK, (2 + 7 - EPOCH_WEEKDAY) % 7 * DAY + 4 * HOUR);
+        assert_eq!(reset.as_secs(), 1689739200);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut settings = ClockSettings::default();
+        for hour in [0, 4, 23] {
+            settings.daily_reset_hour = hour;
+            assert!(settings.validate().is_ok());
+        }
+        settings.daily_reset_hour = 24;
+        assert!(settings.validate().is_err());
+        settings.daily_reset_hour = 4;
+        for day in [0, 2, 6] {
+            settings.weekly_reset_day = day;
+            assert!(settings.validate().is_ok());
+        }
+        settings.weekly_reset_day = 7;
+        assert!(settings.validate().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! Timed crafting of items.
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Maximum number of simultaneous crafts of a character.
+pub const MAX_SLOTS: usize = 3;
+
+/// Craft in progress.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CraftSlot {
+    pub recipe_id: u32,
+    /// Time (since UNIX epoch) when the craft is finished.
+    pub ready_at: Duration,
+}
+
+/// Removes and returns crafts finished at `now`.
+pub fn take_finished(slots: &mut Vec<CraftSlot>, now: Duration) -> Vec<CraftSlot> {
+    let (finished, pending) = std::mem::take(slots)
+        .into_iter()
+        .partition(|s| s.ready_at <= now);
+    *slots = pending;
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{take_finished, CraftSlot};
+    use std::time::Duration;
+
+    #[test]
+    fn test_take_finished() {
+        let slot = |recipe_id, ready_at| CraftSlot {
+            recipe_id,
+            ready_at: Duration::from_secs(ready_at),
+        };
+        let mut slots = vec![slot(1, 100), slot(2, 50), slot(3, 200)];
+        let finished = take_finished(&mut slots, Duration::from_secs(100));
+        assert_eq!(finished, vec![slot(1, 100), slot(2, 50)]);
+        assert_eq!(slots, vec![slot(3, 200)]);
+        assert!(take_finished(&mut slots, Duration::from_secs(150)).is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Daily rotation of featured quests and client orders.
+use crate::{
+    clock::ServerClock,
+    directory::PlayerDirectory,
+    events::{self, EventBus, GameEvent},
+    resets::{ResetEvent, ResetScheduler},
+    user::handlers,
+};
+use data_structs::reward::Reward;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Daily rotation settings. Picks change at the daily reset of the server clock.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DailySettings {
+    /// Quests that can be featured, in rotation order.
+    pub featured_quests: Vec<FeaturedQuest>,
+    /// Number of quests featured each day.
+    pub featured_count: usize,
+    /// Ids of client orders that can be daily orders, in rotation order.
+    pub orders: Vec<u32>,
+    /// Number of daily orders each day.
+    pub order_count: usize,
+    /// Bonus given for the first clear of each featured quest and daily order of the day.
+    pub bonus: DailyBonus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct FeaturedQuest {
+    pub name_id: u32,
+    /// Name shown at the quest counter.
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct DailyBonus {
+    pub items: Vec<Reward>,
+    pub meseta: u64,
+    pub exp: u32,
+}
+
+/// Featured quests and daily orders of one day.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DailyPicks {
+    /// Daily reset period of the picks.
+    pub day: u64,
+    /// Name ids of featured quests.
+    pub featured: Vec<u32>,
+    /// Ids of daily orders.
+    pub orders: Vec<u32>,
+}
+
+/// Featured quests and daily orders completed by a character.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DailyProgress {
+    /// Daily reset period of the progress.
+    pub day: u64,
+    /// Cleared featured quests.
+    pub quests: Vec<u32>,
+    /// Turned in daily orders.
+    pub orders: Vec<u32>,
+}
+
+/// Picks featured quests and daily orders for the current day.
+pub struct DailyRotation {
+    settings: DailySettings,
+    clock: ServerClock,
+    picks: parking_lot::Mutex<DailyPicks>,
+}
+
+impl DailyRotation {
+    /// Starts the rotation task.
+    pub fn start(
+        settings: DailySettings,
+        clock: ServerClock,
+        resets: &ResetScheduler,
+    ) -> Arc<Self> {
+        let day = clock.reset_day();
+        let rotation = Arc::new(Self {
+            picks: parking_lot::Mutex::new(pick(&settings, day)),
+            settings,
+            clock,
+        });
+        let mut events = resets.subscribe();
+        let this = rotation.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(ResetEvent::Daily) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        this.picks();
+                    }
+                    Ok(ResetEvent::Weekly) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        rotation
+    }
+    /// Returns picks of the current day.
+    pub fn picks(&self) -> DailyPicks {
+        let day = self.clock.reset_day();
+        let mut picks = self.picks.lock();
+        // blocks can ask for the picks before the rotation task handles the reset
+        if picks.day != day {
+            *picks = pick(&self.settings, day);
+            log::info!(
+                "Daily rotation: featured quests {:?}, orders {:?}",
+                picks.featured,
+                picks.orders
+            );
+        }
+        picks.clone()
+    }
+    /// Returns the counter name of the featured quest.
+    pub fn quest_name(&self, name_id: u32) -> Option<&str> {
+        self.settings
+            .featured_quests
+            .iter()
+            .find(|q| q.name_id == name_id)
+            .map(|q| q.name.as_str())
+    }
+    pub const fn bonus(&self) -> &DailyBonus {
+        &self.settings.bonus
+    }
+}
+
+impl DailyProgress {
+    /// Marks the featured quest as cleared. Returns false if it was already cleared today.
+    pub fn clear_quest(&mut self, day: u64, name_id: u32) -> bool {
+        self.refresh(day);
+        if self.quests.contains(&name_id) {
+            return false;
+        }
+        self.quests.push(name_id);
+        true
+    }
+    /// Marks the daily order as turned in. Returns false if it was already turned in today.
+    pub fn turn_in_order(&mut self, day: u64, id: u32) -> bool {
+        self.refresh(day);
+        if self.orders.contains(&id) {
+            return false;
+        }
+        self.orders.push(id);
+        true
+    }
+    /// Checks if the daily order was turned in today.
+    pub fn order_done(&self, day: u64, id: u32) -> bool {
+        self.day == day && self.orders.contains(&id)
+    }
+    pub fn quest_done(&self, day: u64, name_id: u32) -> bool {
+        self.day == day && self.quests.contains(&name_id)
+    }
+    fn refresh(&mut self, day: u64) {
+        if self.day != day {
+            *self = Self {
+                day,
+                ..Default::default()
+            };
+        }
+    }
+}
+
+fn pick(settings: &DailySettings, day: u64) -> DailyPicks {
+    let quests: Vec<_> = settings.featured_quests.iter().map(|q| q.name_id).collect();
+    DailyPicks {
+        day,
+        featured: rotate(&quests, settings.featured_count, day),
+        orders: rotate(&settings.orders, settings.order_count, day),
+    }
+}
+
+/// Returns `count` entries of the pool starting after the entries picked the previous day.
+fn rotate(pool: &[u32], count: usize, day: u64) -> Vec<u32> {
+    if pool.is_empty() {
+        return vec![];
+    }
+    let count = count.min(pool.len());
+    let start = (day % pool.len() as u64) as usize * count;
+    (0..count).map(|i| pool[(start + i) % pool.len()]).collect()
+}
+
+/// Starts tracking featured quest clears and daily order turn-ins of online players.
+pub fn subscribe(bus: &EventBus, directory: Arc<PlayerDirectory>) {
+    events::spawn_user_subscriber(
+        bus,
+        "daily",
+        directory,
+        |event| {
+            matches!(
+                event,
+                GameEvent::QuestCleared { quest: Some(_), .. } | GameEvent::OrderTurnedIn { .. }
+            )
+        },
+        |user, event| Box::pin(handlers::daily::on_event(user, event)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick, rotate, DailyProgress, DailySettings};
+
+    #[test]
+    fn test_rotation() {
+        let pool = [1, 2, 3, 4, 5];
+        assert_eq!(rotate(&pool, 2, 0), vec![1, 2]);
+        assert_eq!(rotate(&pool, 2, 1), vec![3, 4]);
+        assert_eq!(rotate(&pool, 2, 2), vec![5, 1]);
+        assert_eq!(rotate(&pool, 10, 3), vec![1, 2, 3, 4, 5]);
+        assert!(rotate(&[], 2, 1).is_empty());
+        let picks = pick(&DailySettings::default(), 7);
+        assert_eq!(picks.day, 7);
+        assert!(picks.featured.is_empty());
+    }
+
+    #[test]
+    fn test_daily_progress() {
+        let mut progress = DailyProgress::default();
+        assert!(progress.clear_quest(1, 700020));
+        assert!(!progress.clear_quest(1, 700020));
+        assert!(progress.turn_in_order(1, 5));
+        assert!(progress.order_done(1, 5));
+        // next day
+        assert!(!progress.order_done(2, 5));
+        assert!(progress.clear_quest(2, 700020));
+        assert!(progress.orders.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+//! Ship-wide directory of online players.
+use crate::{mutex::Mutex, User};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
+/// Location of an online player.
+#[derive(Clone)]
+pub struct PlayerEntry {
+    pub id: u32,
+    pub nickname: String,
+    /// Name of the selected character. Empty if the player is in the character selection.
+    pub char_name: String,
+    /// Equipped title of the character. Empty if no title is equipped.
+    pub title: String,
+    pub block_id: u32,
+    pub block_name: String,
+    /// Name of the current zone. `None` if the player isn't in game.
+    pub zone: Option<String>,
+    conn_id: usize,
+    user: Weak<Mutex<User>>,
+}
+
+/// Registry of players on all blocks of the ship.
+#[derive(Default)]
+pub struct PlayerDirectory {
+    players: parking_lot::RwLock<HashMap<u32, PlayerEntry>>,
+}
+
+impl PlayerEntry {
+    /// Returns the session of the player.
+    pub fn user(&self) -> Option<Arc<Mutex<User>>> {
+        self.user.upgrade()
+    }
+}
+
+impl PlayerDirectory {
+    /// Registers a new session of the player, replacing the previous one.
+    pub fn register(
+        &self,
+        id: u32,
+        nickname: String,
+        (block_id, block_name): (u32, String),
+        conn_id: usize,
+        user: Weak<Mutex<User>>,
+    ) {
+        let entry = PlayerEntry {
+            id,
+            nickname,
+            char_name: String::new(),
+            title: String::new(),
+            block_id,
+            block_name,
+            zone: None,
+            conn_id,
+            user,
+        };
+        self.players.write().insert(id, entry);
+    }
+    /// Removes the player if the session is still the registered one.
+    pub fn unregister(&self, id: u32, block_id: u32, conn_id: usize) {
+        let mut players = self.players.write();
+        if players
+            .get(&id)
+            .is_some_and(|p| p.block_id == block_id && p.conn_id == conn_id)
+        {
+            players.remove(&id);
+        }
+    }
+    pub fn set_nickname(&self, id: u32, nickname: &str) {
+        if let Some(entry) = self.players.write().get_mut(&id) {
+            entry.nickname = nickname.to_string();
+        }
+    }
+    pub fn set_character(&self, id: u32, char_name: &str) {
+        if let Some(entry) = self.players.write().get_mut(&id) {
+            entry.char_name = char_name.to_string();
+            entry.title.clear();
+            entry.zone = None;
+        }
+    }
+    pub fn set_title(&self, id: u32, title: &str) {
+        if let Some(entry) = self.players.write().get_mut(&id) {
+            entry.title = title.to_string();
+        }
+    }
+    pub fn set_zone(&self, id: u32, zone: Option<&str>) {
+        if let Some(entry) = self.players.write().get_mut(&id) {
+            entry.zone = zone.map(str::to_string);
+        }
+    }
+    pub fn get(&self, id: u32) -> Option<PlayerEntry> {
+        self.players.read().get(&id).cloned()
+    }
+    /// Finds a player by the id, nickname or character name (case insensitive).
+    pub fn find(&self, name: &str) -> Option<PlayerEntry> {
+        if let Ok(id) = name.parse() {
+            if let Some(entry) = self.get(id) {
+                return Some(entry);
+            }
+        }
+        self.players
+            .read()
+            .values()
+            .find(|p| {
+                p.nickname.eq_ignore_ascii_case(name) || p.char_name.eq_ignore_ascii_case(name)
+            })
+            .cloned()
+    }
+    /// Finds players whose nickname or character name contains the query (case insensitive),
+    /// sorted by the nickname.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<PlayerEntry> {
+        let query = query.to_lowercase();
+        let mut found: Vec<_> = self
+            .players
+            .read()
+            .values()
+            .filter(|p| {
+                p.nickname.to_lowercase().contains(&query)
+                    || p.char_name.to_lowercase().contains(&query)
+            })
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+        found.truncate(limit);
+        found
+    }
+    pub fn online_players(&self) -> Vec<PlayerEntry> {
+        self.players.read().values().cloned().collect()
+    }
+    pub fn online_count(&self) -> usize {
+        self.players.read().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerDirectory;
+    use std::sync::Weak;
+
+    #[test]
+    fn test_directory() {
+        let directory = PlayerDirectory::default();
+        directory.register(1, "nick".into(), (1, "Block 1".into()), 0, Weak::new());
+        directory.set_character(1, "Chara");
+        directory.set_zone(1, Some("lobby"));
+        assert_eq!(directory.find("CHARA").unwrap().id, 1);
+        assert_eq!(directory.find("1").unwrap().nickname, "nick");
+        assert_eq!(directory.search("HAR", 10).len(), 1);
+        assert!(directory.search("other", 10).is_empty());
+        directory.set_title(1, "Rookie");
+        assert_eq!(directory.get(1).unwrap().title, "Rookie");
+        // character change unequips the title
+        directory.set_character(1, "Other");
+        assert!(directory.get(1).unwrap().title.is_empty());
+        // block switch: new session registers before the old one is dropped
+        directory.register(1, "nick".into(), (2, "Block 2".into()), 0, Weak::new());
+        directory.unregister(1, 1, 0);
+        let entry = directory.get(1).unwrap();
+        assert_eq!(entry.block_name, "Block 2");
+        assert!(entry.zone.is_none());
+        directory.unregister(1, 2, 0);
+        assert_eq!(directory.online_count(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+//! Reasons of client disconnects.
+use crate::Error;
+use parking_lot::Mutex;
+use pso2packetlib::connection::ConnectionError;
+use pso2packetlib::protocol::login::Language;
+use std::{collections::BTreeMap, io};
+
+/// Number of disconnects by reason.
+static COUNTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum DisconnectReason {
+    /// Client said goodbye.
+    Goodbye,
+    /// Client stopped answering pings.
+    Timeout,
+    /// Client closed or reset the connection without saying goodbye.
+    ConnectionLost,
+    /// Connection failed with an unrecoverable error.
+    Error,
+    /// Session was replaced by a new login of the same account.
+    Kicked,
+    /// Login was rejected because of a ban.
+    Banned,
+    /// Login was rejected for any other reason.
+    LoginFailed,
+    /// Player was kicked for suspected macro use.
+    Macro,
+}
+
+impl DisconnectReason {
+    /// Returns the reason of the disconnect caused by the IO error, if the error is fatal.
+    pub fn from_io(e: &io::Error) -> Option<Self> {
+        match e.kind() {
+            io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset => {
+                Some(Self::ConnectionLost)
+            }
+            io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::NotConnected => Some(Self::Error),
+            _ => None,
+        }
+    }
+    /// Returns the message sent to the client before closing the connection. Login failures and
+    /// session kicks already explain themselves and client initiated disconnects need none.
+    pub const fn message(self, lang: Language) -> Option<&'static str> {
+        let message = match (self, lang) {
+            (Self::Timeout, Language::English) => "Connection timed out",
+            (Self::Timeout, Language::Japanese) => "接続がタイムアウトしました",
+            (Self::Macro, Language::English) => "You were disconnected for suspected macro use",
+            (Self::Macro, Language::Japanese) => "マクロ使用の疑いにより切断されました",
+            _ => return None,
+        };
+        Some(message)
+    }
+    /// Increments the counter of this reason.
+    pub fn record(self) {
+        *COUNTS.lock().entry(self.into()).or_default() += 1;
+    }
+}
+
+impl Error {
+    /// Returns the reason of the disconnect caused by this error, if the error is fatal for the
+    /// connection.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        match self {
+            Self::IOError(e) | Self::ConnError(ConnectionError::Io(e)) => {
+                DisconnectReason::from_io(e)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the number of disconnects by reason.
+pub fn counts() -> Vec<(String, u64)> {
+    COUNTS
+        .lock()
+        .iter()
+        .map(|(reason, count)| (reason.to_string(), *count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisconnectReason;
+    use crate::Error;
+    use pso2packetlib::protocol::login::Language;
+    use std::io;
+
+    #[test]
+    fn test_disconnect_reasons() {
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            DisconnectReason::from_io(&reset),
+            Some(DisconnectReason::ConnectionLost)
+        );
+        let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            DisconnectReason::from_io(&eof),
+            Some(DisconnectReason::Error)
+        );
+        let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+        assert_eq!(DisconnectReason::from_io(&interrupted), None);
+        assert_eq!(
+            Error::IOError(reset).disconnect_reason(),
+            Some(DisconnectReason::ConnectionLost)
+        );
+        assert_eq!(Error::MSUnexpected.disconnect_reason(), None);
+        assert_eq!(
+            <&str>::from(DisconnectReason::ConnectionLost),
+            "connection_lost"
+        );
+        assert!(DisconnectReason::Goodbye
+            .message(Language::English)
+            .is_none());
+        assert!(DisconnectReason::Timeout
+            .message(Language::English)
+            .is_some());
+    }
+}
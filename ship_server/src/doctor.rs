@@ -0,0 +1,75 @@
+//! Self-test of the ship configuration (`--doctor`).
+use crate::{settings::Settings, Error};
+use data_structs::doctor::{self, Report};
+use sqlx::{migrate::MigrateDatabase, Row};
+
+pub async fn run(settings: &Settings) -> Result<(), Error> {
+    let mut report = Report::default();
+    report.add("Database", check_db(&settings.db_name).await);
+    report.add(
+        "Key",
+        settings
+            .read_key()
+            .map(|key| match (key, &settings.key_file) {
+                (Some(_), _) => String::new(),
+                (None, Some(path)) => format!("{path} will be created"),
+                (None, None) => "no key file is set, a temporary key will be used".to_string(),
+            }),
+    );
+
+    report.add(
+        "Balance port",
+        doctor::check_port(("0.0.0.0", settings.balance_port)).await,
+    );
+    let mut ports = 13001;
+    for block in &settings.blocks {
+        let port = block.port.unwrap_or(ports);
+        ports += 1;
+        report.add(
+            format!("Block \"{}\" port", block.name),
+            doctor::check_port(("0.0.0.0", port)).await,
+        );
+    }
+
+    match &settings.data_file {
+        Some(path) => report.add("Server data", doctor::check_data_file(path).await),
+        None => report.add(
+            "Server data",
+            Ok::<_, Error>("not set, will be received from the master ship".to_string()),
+        ),
+    }
+
+    let mut master_addrs = vec![];
+    match &settings.master_ship {
+        Some(addr) => master_addrs.push(addr.clone()),
+        None => match data_structs::master_ship::try_discover().await {
+            Ok(addr) => master_addrs.push(addr.to_string()),
+            Err(e) => report.add("Master ship discovery", Err(e)),
+        },
+    }
+    master_addrs.extend(settings.standby_master_ships.iter().cloned());
+    for addr in master_addrs {
+        report.add(
+            format!("Master ship {addr}"),
+            doctor::check_reachable(&addr).await,
+        );
+    }
+
+    report.print();
+    match report.failures() {
+        0 => Ok(()),
+        n => Err(Error::ChecksFailed(n)),
+    }
+}
+
+async fn check_db(path: &str) -> Result<String, Error> {
+    if !sqlx::Sqlite::database_exists(path).await.unwrap_or(false) {
+        return Ok(format!("{path} will be created"));
+    }
+    let conn = sqlx::SqlitePool::connect(path).await?;
+    let row = sqlx::query("select count(*) as Count from Characters")
+        .fetch_one(&conn)
+        .await?;
+    let count: i64 = row.try_get("Count")?;
+    Ok(format!("{count} characters"))
+}
@@ -0,0 +1,123 @@
+//! Error codes of handler failures shown to clients.
+use crate::Error;
+use parking_lot::Mutex;
+use pso2packetlib::protocol::login::Language;
+use std::collections::BTreeMap;
+
+/// Number of handler failures by error code.
+static COUNTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ErrorCode {
+    Internal = 1,
+    InvalidInput = 2,
+    InvalidState = 3,
+    MasterShip = 4,
+    NotFound = 5,
+    GameData = 6,
+    Database = 7,
+    Connection = 8,
+    Login = 9,
+}
+
+impl ErrorCode {
+    /// Returns the message shown to the client.
+    pub fn message(self, lang: Language) -> String {
+        let message = match (self, lang) {
+            (Self::Internal, Language::English) => "Internal server error",
+            (Self::Internal, Language::Japanese) => "サーバー内部エラーが発生しました",
+            (Self::InvalidInput, Language::English) => "Invalid request",
+            (Self::InvalidInput, Language::Japanese) => "無効なリクエストです",
+            (Self::InvalidState, Language::English) => "This action is not available right now",
+            (Self::InvalidState, Language::Japanese) => "現在この操作はできません",
+            (Self::MasterShip, Language::English) => "Master ship is unavailable",
+            (Self::MasterShip, Language::Japanese) => "マスターシップに接続できません",
+            (Self::NotFound, Language::English) => "Requested object was not found",
+            (Self::NotFound, Language::Japanese) => "対象が見つかりません",
+            (Self::GameData, Language::English) => "Server data error",
+            (Self::GameData, Language::Japanese) => "サーバーデータエラーが発生しました",
+            (Self::Database, Language::English) => "Database error",
+            (Self::Database, Language::Japanese) => "データベースエラーが発生しました",
+            (Self::Connection, Language::English) => "Connection error",
+            (Self::Connection, Language::Japanese) => "接続エラーが発生しました",
+            (Self::Login, Language::English) => "Login error",
+            (Self::Login, Language::Japanese) => "ログインエラーが発生しました",
+        };
+        format!("{message} (E{:03})", self as u32)
+    }
+    /// Increments the counter of this code.
+    pub fn record(self) {
+        *COUNTS.lock().entry(self.into()).or_default() += 1;
+    }
+}
+
+impl Error {
+    pub const fn code(&self) -> ErrorCode {
+        match self {
+            Self::InvalidInput(_) => ErrorCode::InvalidInput,
+            Self::UserInvalidState(_) | Self::StorageFull(_) | Self::InventoryFull => {
+                ErrorCode::InvalidState
+            }
+            Self::InvalidPassword
+            | Self::PasswordResetRequested
+            | Self::OtpRequired
+            | Self::RegistrationDenied(_)
+            | Self::Maintenance(_)
+            | Self::OutdatedClient(_)
+            | Self::TooManyAttempts(_)
+            | Self::Banned { .. } => ErrorCode::Login,
+            Self::MSError(_)
+            | Self::MSUnexpected
+            | Self::StorageConflict
+            | Self::MSInvalidPSK
+            | Self::MSRevokedPSK
+            | Self::MSBlocked
+            | Self::MSNoResponse
+            | Self::NoMasterShip => ErrorCode::MasterShip,
+            Self::NoUser
+            | Self::NoUserInMap(..)
+            | Self::NoMapInMapSet(..)
+            | Self::NoMapFound(_) => ErrorCode::NotFound,
+            Self::NoItemInAttrs(..)
+            | Self::NoClothes(_)
+            | Self::NoEnemyData(_)
+            | Self::NoDamageInfo(_)
+            | Self::NoHitboxInfo(..)
+            | Self::NoShipData
+            | Self::DataError(_)
+            | Self::LuaError(_) => ErrorCode::GameData,
+            Self::SqlError(_)
+            | Self::SerdeError(_)
+            | Self::RMPEncodeError(_)
+            | Self::RMPDecodeError(_) => ErrorCode::Database,
+            Self::IOError(_) | Self::ConnError(_) | Self::PacketError(_) => ErrorCode::Connection,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Returns the number of handler failures by error code.
+pub fn counts() -> Vec<(String, u64)> {
+    COUNTS
+        .lock()
+        .iter()
+        .map(|(code, count)| (code.to_string(), *count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorCode;
+    use crate::Error;
+    use pso2packetlib::protocol::login::Language;
+
+    #[test]
+    fn test_error_codes() {
+        let code = Error::InvalidInput("test").code();
+        assert_eq!(code, ErrorCode::InvalidInput);
+        assert_eq!(code.message(Language::English), "Invalid request (E002)");
+        assert_eq!(<&str>::from(code), "invalid_input");
+        assert_eq!(Error::MSUnexpected.code(), ErrorCode::MasterShip);
+    }
+}
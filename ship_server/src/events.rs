@@ -0,0 +1,180 @@
+//! Typed game events. Gameplay code emits events without knowing about subsystems interested
+//! in them, subsystems subscribe to the bus instead.
+use crate::{directory::PlayerDirectory, Error, User};
+use pso2packetlib::protocol::{items::ItemId, models::character::Class};
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::sync::mpsc;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Event emitted by gameplay code. Players are identified by their user ids.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    /// Enemy killed by the player.
+    EnemyKilled {
+        player_id: u32,
+        exp: u32,
+    },
+    /// Enemy killed in the zone of the player, emitted for every player that got EXP for it.
+    EnemyDefeated {
+        player_id: u32,
+        /// Name of the enemy.
+        enemy: String,
+    },
+    /// Item given to the player by the server (bought, crafted, claimed, etc.).
+    ItemObtained {
+        player_id: u32,
+        item: ItemId,
+        amount: u16,
+    },
+    LevelUp {
+        player_id: u32,
+        class: Class,
+        level: u32,
+    },
+    QuestStarted {
+        player_id: u32,
+    },
+    /// Character of the player entered the game.
+    LoggedIn {
+        player_id: u32,
+    },
+    QuestCleared {
+        player_id: u32,
+        /// Name id of the cleared quest.
+        quest: Option<u32>,
+    },
+    /// Meseta given to the player by the server.
+    MesetaCreated {
+        player_id: u32,
+        amount: u64,
+    },
+    /// Client order turned in by the player after its rewards were given.
+    OrderTurnedIn {
+        player_id: u32,
+        order: u32,
+    },
+}
+
+impl GameEvent {
+    pub const fn player_id(&self) -> u32 {
+        match self {
+            Self::EnemyKilled { player_id, .. }
+            | Self::EnemyDefeated { player_id, .. }
+            | Self::ItemObtained { player_id, .. }
+            | Self::LevelUp { player_id, .. }
+            | Self::QuestStarted { player_id }
+            | Self::LoggedIn { player_id }
+            | Self::QuestCleared { player_id, .. }
+            | Self::MesetaCreated { player_id, .. }
+            | Self::OrderTurnedIn { player_id, .. } => *player_id,
+        }
+    }
+}
+
+/// Event bus. Every subscriber has its own unbounded queue, so events carrying progress are
+/// never dropped because of a slow subscriber.
+pub struct EventBus {
+    subscribers: parking_lot::Mutex<Vec<mpsc::UnboundedSender<GameEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            subscribers: Default::default(),
+        })
+    }
+    /// Sends the event to all subscribers. Events without subscribers are discarded.
+    pub fn emit(&self, event: GameEvent) {
+        self.subscribers
+            .lock()
+            .retain(|s| s.send(event.clone()).is_ok());
+    }
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<GameEvent> {
+        let (send, recv) = mpsc::unbounded_channel();
+        self.subscribers.lock().push(send);
+        recv
+    }
+}
+
+/// Runs the subscriber task, calling `handler` for every received event.
+pub fn spawn_subscriber(
+    bus: &EventBus,
+    name: &'static str,
+    mut handler: impl FnMut(GameEvent) + Send + 'static,
+) {
+    let mut events = bus.subscribe();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            handler(event);
+        }
+        log::debug!("Event subscriber {name} stopped");
+    });
+}
+
+/// Runs the subscriber task, calling `handler` with the locked user of every event accepted by
+/// `filter`. Events of players that aren't online on this ship are skipped. The character of the
+/// user could have been unloaded before the event is handled.
+pub fn spawn_user_subscriber<H>(
+    bus: &EventBus,
+    name: &'static str,
+    directory: Arc<PlayerDirectory>,
+    filter: fn(&GameEvent) -> bool,
+    handler: H,
+) where
+    H: for<'a> Fn(&'a mut User, &'a GameEvent) -> BoxFuture<'a, Result<(), Error>>
+        + Copy
+        + Send
+        + 'static,
+{
+    spawn_subscriber(bus, name, move |event| {
+        if !filter(&event) {
+            return;
+        }
+        let Some(user) = directory.get(event.player_id()).and_then(|p| p.user()) else {
+            return;
+        };
+        // events are emitted with the user locked
+        tokio::spawn(async move {
+            let mut user = user.lock().await;
+            if let Err(e) = handler(&mut user, &event).await {
+                log::warn!("Event subscriber {name} failed: {e}");
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventBus, GameEvent};
+
+    #[tokio::test]
+    async fn test_event_bus() {
+        let bus = EventBus::new();
+        // no subscribers
+        bus.emit(GameEvent::QuestStarted { player_id: 1 });
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        let event = GameEvent::MesetaCreated {
+            player_id: 2,
+            amount: 100,
+        };
+        bus.emit(event.clone());
+        assert_eq!(first.recv().await.unwrap(), event);
+        assert_eq!(second.recv().await.unwrap(), event);
+        // slow subscribers don't lose events
+        for player_id in 0..1000 {
+            bus.emit(GameEvent::QuestStarted { player_id });
+        }
+        for player_id in 0..1000 {
+            assert_eq!(
+                first.recv().await.unwrap(),
+                GameEvent::QuestStarted { player_id }
+            );
+        }
+        // closed subscribers are removed
+        drop(second);
+        bus.emit(event);
+        assert_eq!(bus.subscribers.lock().len(), 1);
+    }
+}
@@ -1,5 +1,8 @@
 use crate::Error;
-use data_structs::inventory::{AccountStorages, ItemParameters, StorageInventory};
+use data_structs::{
+    inventory::{AccountStorages, ItemParameters, StorageInventory},
+    reward::Reward,
+};
 use pso2packetlib::protocol::{
     items::{
         AddedItemPacket, DiscardItemRequestPacket, DiscardStorageItemRequestPacket, EquipedItem,
@@ -14,6 +17,7 @@ use pso2packetlib::protocol::{
     ObjectHeader, Packet, ProtocolRW,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -22,6 +26,15 @@ pub struct Inventory {
     pub(crate) character: StorageInventory,
     #[serde(skip)]
     pub(crate) storages: AccountStorages,
+    /// Storages as they were last read from or written to the master ship.
+    #[serde(skip)]
+    pub(crate) storages_base: AccountStorages,
+    #[serde(skip)]
+    pub(crate) storages_version: u64,
+    /// Invalid items removed from the inventory and storages.
+    pub(crate) quarantine: Vec<Item>,
+    /// Special abilities of inventory items by item uuid.
+    pub(crate) augments: BTreeMap<u64, Vec<u16>>,
 
     #[serde(skip)]
     loaded_items: Vec<ItemId>,
@@ -36,6 +49,18 @@ pub struct PlayerInventory {
     equiped: Vec<(u32, u64)>,
 }
 
+/// Number of invalid entries found by [`Inventory::validate`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Items over the capacity of the inventory or storage.
+    pub over_capacity: usize,
+    /// Consumables with zero amount.
+    pub empty_stacks: usize,
+    pub duplicate_uuids: usize,
+    /// Equipped slots that refer to missing items.
+    pub dangling_equips: usize,
+}
+
 enum ChangeItemResult {
     Changed {
         uuid: u64,
@@ -201,6 +226,27 @@ impl Inventory {
         }
         Ok(())
     }
+    /// Returns equiped units as (position, uuid) pairs.
+    pub fn get_equiped_units(&self) -> Vec<(u32, u64)> {
+        self.inventory
+            .equiped
+            .iter()
+            .filter(|(pos, _)| *pos <= 2)
+            .copied()
+            .collect()
+    }
+    /// Replaces all equiped units.
+    pub fn set_equiped_units(&mut self, units: &[(u32, u64)]) -> Result<(), Error> {
+        for (pos, uuid) in units {
+            let item = self.get_inv_item(*uuid)?;
+            if *pos > 2 || !matches!(item.data, ItemType::Unit(_)) {
+                return Err(Error::InvalidInput("set_equiped_units"));
+            }
+        }
+        self.inventory.equiped.retain(|(pos, _)| *pos > 2);
+        self.inventory.equiped.extend_from_slice(units);
+        Ok(())
+    }
     pub fn get_inv_item(&self, uuid: u64) -> Result<Item, Error> {
         self.inventory
             .items
@@ -209,11 +255,267 @@ impl Inventory {
             .ok_or(Error::InvalidInput("get_inv_item"))
             .cloned()
     }
+    pub fn inv_items(&self) -> &[Item] {
+        &self.inventory.items
+    }
+    /// Removes `amount` of the item from the inventory (e.g. to sell it). Abilities of the item
+    /// are lost. Equiped items can't be removed.
+    pub fn take_inv_item(&mut self, uuid: u64, amount: u16) -> Result<(Item, Packet), Error> {
+        self.take_inv_item_with_augments(uuid, amount)
+            .map(|(item, _, packet)| (item, packet))
+    }
+    /// Removes `amount` of the item from the inventory together with its abilities (e.g. to
+    /// attach it to a mail). Equiped items can't be removed.
+    pub fn take_inv_item_with_augments(
+        &mut self,
+        uuid: u64,
+        amount: u16,
+    ) -> Result<(Item, Vec<u16>, Packet), Error> {
+        if self.inventory.equiped.iter().any(|(_, u)| *u == uuid) {
+            return Err(Error::InvalidInput("take_inv_item"));
+        }
+        let mut augments = vec![];
+        let (item, updated) = match decrease_item(&mut self.inventory.items, uuid, amount)? {
+            ChangeItemResult::Changed {
+                new_amount,
+                moved,
+                item,
+                ..
+            } => (
+                item,
+                pso2packetlib::protocol::items::UpdatedInventoryItem {
+                    uuid,
+                    new_amount,
+                    moved,
+                },
+            ),
+            ChangeItemResult::Removed { item, amount } => {
+                augments = self.augments.remove(&uuid).unwrap_or_default();
+                (
+                    item,
+                    pso2packetlib::protocol::items::UpdatedInventoryItem {
+                        uuid,
+                        new_amount: 0,
+                        moved: amount,
+                    },
+                )
+            }
+            _ => unreachable!(),
+        };
+        let packet = Packet::UpdateInventory(UpdateInventoryPacket {
+            unk2: 1,
+            updated: vec![updated],
+            ..Default::default()
+        });
+        Ok((item, augments, packet))
+    }
+    /// Removes materials (item id and amount) from the inventory and then from the storages.
+    /// Equiped items are never used. Returns `None` and changes nothing if there are not enough
+    /// items, otherwise the second value is set if account storages were changed.
+    pub fn take_materials(
+        &mut self,
+        materials: &[(ItemId, u16)],
+    ) -> Result<Option<(Vec<Packet>, bool)>, Error> {
+        let backup = self.clone();
+        let result = self.remove_materials(materials);
+        if !matches!(result, Ok(Some(_))) {
+            *self = backup;
+        }
+        result
+    }
+    fn remove_materials(
+        &mut self,
+        materials: &[(ItemId, u16)],
+    ) -> Result<Option<(Vec<Packet>, bool)>, Error> {
+        let mut inv_packet = UpdateInventoryPacket {
+            unk2: 1,
+            ..Default::default()
+        };
+        let mut storage_packet = UpdateStoragePacket {
+            unk2: 1,
+            ..Default::default()
+        };
+        let mut storages_changed = false;
+        for &(id, amount) in materials {
+            let mut left = amount;
+            let found: Vec<_> = self
+                .inventory
+                .items
+                .iter()
+                .filter(|i| i.id == id && !self.is_equiped(i.uuid))
+                .map(|i| (i.uuid, item_amount(i)))
+                .collect();
+            for (uuid, count) in found {
+                let take = count.min(left);
+                if take == 0 {
+                    break;
+                }
+                let new_amount = match decrease_item(&mut self.inventory.items, uuid, take)? {
+                    ChangeItemResult::Changed { new_amount, .. } => new_amount,
+                    _ => {
+                        self.augments.remove(&uuid);
+                        0
+                    }
+                };
+                inv_packet
+                    .updated
+                    .push(pso2packetlib::protocol::items::UpdatedInventoryItem {
+                        uuid,
+                        new_amount,
+                        moved: take,
+                    });
+                left -= take;
+            }
+            for storage_id in [14, 0, 1, 2] {
+                let storage = match storage_id {
+                    14 => &mut self.character,
+                    0 => &mut self.storages.default,
+                    1 => &mut self.storages.premium,
+                    _ => &mut self.storages.extend1,
+                };
+                let found: Vec<_> = storage
+                    .items
+                    .iter()
+                    .filter(|i| i.id == id)
+                    .map(|i| (i.uuid, item_amount(i)))
+                    .collect();
+                for (uuid, count) in found {
+                    let take = count.min(left);
+                    if take == 0 {
+                        break;
+                    }
+                    let new_amount = match decrease_item(&mut storage.items, uuid, take)? {
+                        ChangeItemResult::Changed { new_amount, .. } => new_amount,
+                        _ => 0,
+                    };
+                    storage_packet.updated.push(
+                        pso2packetlib::protocol::items::UpdatedStorageItem {
+                            uuid,
+                            new_amount,
+                            moved: take,
+                            storage_id: storage.storage_id as u32,
+                        },
+                    );
+                    storages_changed |= storage_id != 14;
+                    left -= take;
+                }
+            }
+            if left != 0 {
+                return Ok(None);
+            }
+        }
+        let mut packets = vec![];
+        if !inv_packet.updated.is_empty() {
+            packets.push(Packet::UpdateInventory(inv_packet));
+        }
+        if !storage_packet.updated.is_empty() {
+            packets.push(Packet::UpdateStorage(storage_packet));
+        }
+        Ok(Some((packets, storages_changed)))
+    }
+    /// Returns special abilities of the inventory item.
+    pub fn augments(&self, uuid: u64) -> &[u16] {
+        self.augments
+            .get(&uuid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+    /// Checks if the inventory has space for the items. Consumables are added to existing stacks
+    /// with the same id, other items take a slot each.
+    pub fn has_space_for<'a>(&self, items: impl IntoIterator<Item = &'a Item>) -> bool {
+        let mut new_stacks = vec![];
+        let mut slots = 0;
+        for item in items {
+            if !matches!(item.data, ItemType::Consumable(_)) {
+                slots += 1;
+            } else if !self.inventory.items.iter().any(|i| i.id == item.id)
+                && !new_stacks.contains(&item.id)
+            {
+                new_stacks.push(item.id);
+                slots += 1;
+            }
+        }
+        self.inventory.items.len() + slots <= self.inventory.max_capacity as usize
+    }
+    pub fn is_equiped(&self, uuid: u64) -> bool {
+        self.inventory.equiped.iter().any(|(_, u)| *u == uuid)
+    }
+    /// Consumes the source items and replaces abilities of the target item. Nothing is changed
+    /// if any of the items is missing or equiped.
+    pub fn affix(
+        &mut self,
+        target: u64,
+        sources: &[u64],
+        augments: Vec<u16>,
+    ) -> Result<Vec<Packet>, Error> {
+        if self.get_inv_item(target).is_err() || sources.contains(&target) {
+            return Err(Error::InvalidInput("affix"));
+        }
+        let backup = (self.inventory.clone(), self.augments.clone());
+        let mut packets = vec![];
+        for &uuid in sources {
+            match self.take_inv_item(uuid, 1) {
+                Ok((_, packet)) => packets.push(packet),
+                Err(e) => {
+                    (self.inventory, self.augments) = backup;
+                    return Err(e);
+                }
+            }
+        }
+        if augments.is_empty() {
+            self.augments.remove(&target);
+        } else {
+            self.augments.insert(target, augments);
+        }
+        Ok(packets)
+    }
+    /// Adds slots to the character storage if it stays within `max_space`. Returns the new
+    /// capacity.
+    pub fn expand_character_storage(&mut self, space: u32, max_space: u32) -> Option<u32> {
+        let total_space = self.character.total_space.checked_add(space)?;
+        if total_space > max_space {
+            return None;
+        }
+        self.character.total_space = total_space;
+        Some(total_space)
+    }
+    fn storage(&self, id: impl TryInto<u8>) -> Option<&StorageInventory> {
+        match id.try_into().ok()? {
+            14 => Some(&self.character),
+            id => self.storages.bank(id),
+        }
+    }
+    /// Checks that the storages can hold the moved items (pairs of storage id and item).
+    fn check_space(&self, moved: &[(u8, &Item)]) -> Result<(), Error> {
+        for &(id, _) in moved {
+            let Some(storage) = self.storage(id) else {
+                continue;
+            };
+            let items = moved
+                .iter()
+                .filter(|(i, _)| *i == id)
+                .map(|(_, item)| *item);
+            if !storage.has_space_for(items) {
+                return Err(Error::StorageFull(id));
+            }
+        }
+        Ok(())
+    }
     pub fn move_to_storage(
         &mut self,
         packet: MoveToStorageRequestPacket,
         new_uuid: &mut u64,
     ) -> Result<Packet, Error> {
+        let mut moved = vec![];
+        for info in &packet.uuids {
+            let storage = self
+                .storage(info.storage_id)
+                .ok_or(Error::InvalidInput("move_to_storage"))?;
+            if let Some(item) = self.inventory.items.iter().find(|i| i.uuid == info.uuid) {
+                moved.push((storage.storage_id, item));
+            }
+        }
+        self.check_space(&moved)?;
         let mut packet_out = MoveToStoragePacket::default();
         for info in packet.uuids {
             let storage = match info.storage_id {
@@ -254,6 +556,12 @@ impl Inventory {
                 }
                 _ => unreachable!(),
             };
+            // abilities of items in account storages are stored with the storages
+            if info.storage_id != 14 {
+                if let Some(augments) = self.augments.remove(&item.uuid) {
+                    self.storages.augments.insert(item.uuid, augments);
+                }
+            }
             match increase_item(&mut storage.items, item, amount)? {
                 ChangeItemResult::Changed {
                     uuid, new_amount, ..
@@ -282,6 +590,16 @@ impl Inventory {
         packet: MoveToInventoryRequestPacket,
         new_uuid: &mut u64,
     ) -> Result<Packet, Error> {
+        let mut items = vec![];
+        for info in &packet.uuids {
+            let storage = self
+                .storage(info.storage_id)
+                .ok_or(Error::InvalidInput("move_to_inventory"))?;
+            items.extend(storage.items.iter().find(|i| i.uuid == info.uuid));
+        }
+        if !self.has_space_for(items) {
+            return Err(Error::InventoryFull);
+        }
         let mut packet_out = MoveToInventoryPacket::default();
         for info in packet.uuids {
             let storage = match info.storage_id {
@@ -324,6 +642,11 @@ impl Inventory {
                 }
                 _ => unreachable!(),
             };
+            if info.storage_id != 14 {
+                if let Some(augments) = self.storages.augments.remove(&item.uuid) {
+                    self.augments.insert(item.uuid, augments);
+                }
+            }
             match increase_item(&mut self.inventory.items, item, amount)? {
                 ChangeItemResult::Changed {
                     new_amount, item, ..
@@ -351,6 +674,21 @@ impl Inventory {
         packet: MoveStoragesRequestPacket,
         new_uuid: &mut u64,
     ) -> Result<Packet, Error> {
+        let (Some(storage_src), Some(storage_dst)) =
+            (self.storage(packet.old_id), self.storage(packet.new_id))
+        else {
+            return Err(Error::InvalidInput("move_storages"));
+        };
+        if packet.old_id == packet.new_id {
+            return Err(Error::InvalidInput("move_storages"));
+        }
+        let moved: Vec<_> = packet
+            .items
+            .iter()
+            .filter_map(|info| storage_src.items.iter().find(|i| i.uuid == info.uuid))
+            .map(|item| (storage_dst.storage_id, item))
+            .collect();
+        self.check_space(&moved)?;
         let mut packet_out = MoveStoragesPacket::default();
         for info in packet.items {
             let storage_src = match packet.old_id {
@@ -393,6 +731,17 @@ impl Inventory {
                 }
                 _ => unreachable!(),
             };
+            // the character storage shares the ability map with the inventory
+            let augments = match packet.old_id {
+                14 => self.augments.remove(&item.uuid),
+                _ => self.storages.augments.remove(&item.uuid),
+            };
+            if let Some(augments) = augments {
+                match packet.new_id {
+                    14 => self.augments.insert(item.uuid, augments),
+                    _ => self.storages.augments.insert(item.uuid, augments),
+                };
+            }
             let storage_dst = match packet.new_id {
                 0 => &mut self.storages.default,
                 1 => &mut self.storages.premium,
@@ -446,6 +795,7 @@ impl Inventory {
                         })
                 }
                 ChangeItemResult::Removed { amount, .. } => {
+                    self.augments.remove(&info.uuid);
                     packet_out
                         .updated
                         .push(pso2packetlib::protocol::items::UpdatedInventoryItem {
@@ -494,7 +844,11 @@ impl Inventory {
                             new_amount: 0,
                             moved: amount,
                             storage_id: storage.storage_id as u32,
-                        })
+                        });
+                    match info.storage_id {
+                        14 => self.augments.remove(&info.uuid),
+                        _ => self.storages.augments.remove(&info.uuid),
+                    };
                 }
                 _ => unreachable!(),
             }
@@ -524,6 +878,26 @@ impl Inventory {
         }));
         packets
     }
+    pub fn add_meseta(&mut self, amount: u64) -> Packet {
+        self.inventory.meseta = self.inventory.meseta.saturating_add(amount);
+        Packet::InventoryMeseta(InventoryMesetaPacket {
+            meseta: self.inventory.meseta,
+        })
+    }
+    /// Removes up to `amount` meseta from the inventory.
+    pub fn remove_meseta(&mut self, amount: u64) -> Packet {
+        self.inventory.meseta = self.inventory.meseta.saturating_sub(amount);
+        Packet::InventoryMeseta(InventoryMesetaPacket {
+            meseta: self.inventory.meseta,
+        })
+    }
+    /// Removes meseta from the inventory. Returns `None` if there is not enough meseta.
+    pub fn take_meseta(&mut self, amount: u64) -> Option<Packet> {
+        self.inventory.meseta = self.inventory.meseta.checked_sub(amount)?;
+        Some(Packet::InventoryMeseta(InventoryMesetaPacket {
+            meseta: self.inventory.meseta,
+        }))
+    }
     pub fn add_item(&mut self, item: Item) -> Packet {
         let packet = Packet::AddedItem(AddedItemPacket {
             item: item.clone(),
@@ -534,29 +908,191 @@ impl Inventory {
     }
     pub fn add_default_item(&mut self, uuid: &mut u64, item_id: ItemId) -> Packet {
         let item = Item {
-            uuid: *uuid,
+            uuid: 0,
             id: item_id,
             data: ItemType::default(),
         };
+        self.add_new_item(uuid, item, vec![])
+    }
+    /// Adds bought items. Consumables are added as one stack, other items are added one by one.
+    pub fn add_bought_items(
+        &mut self,
+        uuid: &mut u64,
+        item_id: ItemId,
+        amount: u16,
+    ) -> Vec<Packet> {
+        let mut packets = vec![];
+        for _ in 0..amount {
+            let mut packet = self.add_default_item(uuid, item_id);
+            let item = self
+                .inventory
+                .items
+                .last_mut()
+                .expect("Item was just added");
+            let stacked = if let ItemType::Consumable(data) = &mut item.data {
+                data.amount = amount;
+                if let Packet::AddedItem(added) = &mut packet {
+                    added.item.data = item.data.clone();
+                }
+                true
+            } else {
+                false
+            };
+            packets.push(packet);
+            if stacked {
+                break;
+            }
+        }
+        packets
+    }
+    /// Adds the item with a new uuid and its abilities (e.g. from a mail attachment).
+    pub fn add_new_item(&mut self, uuid: &mut u64, mut item: Item, augments: Vec<u16>) -> Packet {
+        item.uuid = *uuid;
         *uuid += 1;
+        if !augments.is_empty() {
+            self.augments.insert(item.uuid, augments);
+        }
 
-        // transform item data into known item data
-        let packet = Packet::AddedItem(AddedItemPacket {
+        let item = known_item(item);
+        self.inventory.items.push(item.clone());
+
+        Packet::AddedItem(AddedItemPacket {
             item,
             ..Default::default()
         })
-        .write(pso2packetlib::protocol::PacketType::NA);
-        let packet = Packet::read(&packet, pso2packetlib::protocol::PacketType::NA)
-            .expect("Reading from memory shouldn't fail")
-            .pop()
-            .expect("Should always contain an item");
-        let Packet::AddedItem(added_item) = &packet else {
-            unreachable!("Read and write impls should agree");
-        };
-        self.inventory.items.push(added_item.item.clone());
+    }
+    /// Checks if the inventory has space for `count` items added by [`Self::add_new_item`],
+    /// which never merges stacks.
+    pub fn has_space_for_new(&self, count: usize) -> bool {
+        self.inventory.items.len() + count <= self.inventory.max_capacity as usize
+    }
+    /// Checks if the inventory has space for the rewards given by [`Self::add_bought_items`].
+    pub fn has_space_for_rewards(&self, rewards: &[Reward]) -> bool {
+        let slots: usize = rewards
+            .iter()
+            .filter(|r| r.amount != 0)
+            .map(|r| {
+                let item = known_item(Item {
+                    uuid: 0,
+                    id: r.id,
+                    data: ItemType::default(),
+                });
+                match item.data {
+                    ItemType::Consumable(_) => 1,
+                    _ => r.amount as usize,
+                }
+            })
+            .sum();
+        self.inventory.items.len() + slots <= self.inventory.max_capacity as usize
+    }
+    /// Returns the number of items owned by the character (account storages are not counted).
+    pub fn item_count(&self) -> usize {
+        self.inventory.items.len() + self.character.items.len() + self.quarantine.len()
+    }
+    /// Gives every item owned by the character a new UUID starting from `last_uuid`. Returns the
+    /// map of old UUIDs to new ones.
+    pub fn renumber(&mut self, last_uuid: &mut u64) -> HashMap<u64, u64> {
+        let mut uuids = HashMap::new();
+        for items in [
+            &mut self.inventory.items,
+            &mut self.character.items,
+            &mut self.quarantine,
+        ] {
+            for item in items.iter_mut() {
+                uuids.insert(item.uuid, *last_uuid);
+                item.uuid = *last_uuid;
+                *last_uuid += 1;
+            }
+        }
+        for (_, uuid) in self.inventory.equiped.iter_mut() {
+            if let Some(&new) = uuids.get(uuid) {
+                *uuid = new;
+            }
+        }
+        self.augments = std::mem::take(&mut self.augments)
+            .into_iter()
+            .filter_map(|(uuid, augments)| Some((*uuids.get(&uuid)?, augments)))
+            .collect();
+        uuids
+    }
+    /// Moves invalid items of the inventory and storages to the quarantine. Only structural
+    /// invariants are checked: unique uuids, non-empty stacks and capacities.
+    pub fn validate(&mut self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let mut uuids = HashSet::new();
+        let equiped: Vec<_> = self.inventory.equiped.iter().map(|(_, u)| *u).collect();
+        for (items, capacity) in [
+            (&mut self.inventory.items, self.inventory.max_capacity),
+            (&mut self.character.items, self.character.total_space),
+            (
+                &mut self.storages.default.items,
+                self.storages.default.total_space,
+            ),
+            (
+                &mut self.storages.premium.items,
+                self.storages.premium.total_space,
+            ),
+            (
+                &mut self.storages.extend1.items,
+                self.storages.extend1.total_space,
+            ),
+        ] {
+            let (mut valid, invalid): (Vec<_>, Vec<_>) = std::mem::take(items)
+                .into_iter()
+                .partition(|item| check_item(item, &mut uuids, &mut report));
+            self.quarantine.extend(invalid);
+            // unequipped items added last are over the capacity
+            while valid.len() > capacity as usize {
+                let Some(pos) = valid.iter().rposition(|i| !equiped.contains(&i.uuid)) else {
+                    break;
+                };
+                self.quarantine.push(valid.remove(pos));
+                report.over_capacity += 1;
+            }
+            *items = valid;
+        }
+        let items = &self.inventory.items;
+        self.inventory.equiped.retain(|(_, uuid)| {
+            let exists = items.iter().any(|i| i.uuid == *uuid);
+            if !exists {
+                report.dangling_equips += 1;
+            }
+            exists
+        });
+        report
+    }
+}
 
-        packet
+impl ValidationReport {
+    pub const fn is_clean(&self) -> bool {
+        self.over_capacity == 0
+            && self.empty_stacks == 0
+            && self.duplicate_uuids == 0
+            && self.dangling_equips == 0
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} over capacity, {} empty stacks, {} duplicate uuids, {} dangling equips",
+            self.over_capacity, self.empty_stacks, self.duplicate_uuids, self.dangling_equips
+        )
+    }
+}
+
+/// Returns `true` if the item is valid. Records the reason otherwise.
+fn check_item(item: &Item, uuids: &mut HashSet<u64>, report: &mut ValidationReport) -> bool {
+    if !uuids.insert(item.uuid) {
+        report.duplicate_uuids += 1;
+        return false;
+    }
+    if matches!(&item.data, ItemType::Consumable(data) if data.amount == 0) {
+        report.empty_stacks += 1;
+        return false;
     }
+    true
 }
 fn load_items_inner(
     loaded: &mut Vec<ItemId>,
@@ -590,6 +1126,31 @@ fn load_items_inner(
     }
 }
 
+/// Returns the stack size of the item (1 for non-stackable items).
+pub fn item_amount(item: &Item) -> u16 {
+    match &item.data {
+        ItemType::Consumable(data) => data.amount,
+        _ => 1,
+    }
+}
+
+/// Transforms item data into known item data of the item id.
+fn known_item(item: Item) -> Item {
+    let packet = Packet::AddedItem(AddedItemPacket {
+        item,
+        ..Default::default()
+    })
+    .write(pso2packetlib::protocol::PacketType::NA);
+    let packet = Packet::read(&packet, pso2packetlib::protocol::PacketType::NA)
+        .expect("Reading from memory shouldn't fail")
+        .pop()
+        .expect("Should always contain an item");
+    let Packet::AddedItem(added_item) = packet else {
+        unreachable!("Read and write impls should agree");
+    };
+    added_item.item
+}
+
 fn decrease_item(items: &mut Vec<Item>, uuid: u64, amount: u16) -> Result<ChangeItemResult, Error> {
     let (pos, item) = items
         .iter_mut()
@@ -668,3 +1229,131 @@ impl Default for PlayerInventory {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Inventory, ValidationReport};
+    use data_structs::reward::Reward;
+    use pso2packetlib::protocol::items::{Item, ItemId, ItemType};
+
+    #[test]
+    fn test_validate() {
+        let item = |uuid| Item {
+            uuid,
+            ..Default::default()
+        };
+        let mut inventory = Inventory::default();
+        inventory.inventory.max_capacity = 2;
+        // item ids aren't checked
+        inventory.inventory.items = vec![item(1), item(2), item(5)];
+        inventory.inventory.equiped = vec![(0, 1), (1, 4)];
+        inventory.storages.default.items = vec![item(1), item(3)];
+
+        let report = inventory.validate();
+        assert_eq!(
+            report,
+            ValidationReport {
+                over_capacity: 1,
+                duplicate_uuids: 1,
+                dangling_equips: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(inventory.quarantine.len(), 2);
+        assert_eq!(inventory.inventory.items.len(), 2);
+        assert_eq!(inventory.inventory.equiped, [(0, 1)]);
+        assert!(inventory.validate().is_clean());
+    }
+
+    #[test]
+    fn test_space() {
+        let item = |uuid| Item {
+            uuid,
+            data: ItemType::Clothing(Default::default()),
+            ..Default::default()
+        };
+        let mut inventory = Inventory::default();
+        inventory.inventory.max_capacity = 3;
+        inventory.inventory.items = vec![item(1)];
+        // items with the same id take a slot each unless they are stackable
+        assert!(inventory.has_space_for(&[item(2), item(3)]));
+        assert!(!inventory.has_space_for(&[item(2), item(3), item(4)]));
+        assert!(inventory.has_space_for_new(2));
+        assert!(!inventory.has_space_for_new(3));
+    }
+
+    #[test]
+    fn test_reward_space() {
+        let reward = |item_type, amount| Reward {
+            id: ItemId {
+                item_type,
+                id: 1,
+                ..Default::default()
+            },
+            amount,
+        };
+        let mut inventory = Inventory::default();
+        inventory.inventory.max_capacity = 3;
+        inventory.inventory.items = vec![Item::default()];
+        // weapons take a slot each, consumables are added as one stack
+        assert!(inventory.has_space_for_rewards(&[reward(1, 2)]));
+        assert!(!inventory.has_space_for_rewards(&[reward(1, 3)]));
+        assert!(inventory.has_space_for_rewards(&[reward(1, 1), reward(3, 10)]));
+        assert!(inventory.has_space_for_rewards(&[reward(1, 2), reward(1, 0)]));
+    }
+
+    #[test]
+    fn test_affix() {
+        let item = |uuid| Item {
+            uuid,
+            ..Default::default()
+        };
+        let mut inventory = Inventory::default();
+        inventory.inventory.items = vec![item(1), item(2), item(3)];
+        inventory.inventory.equiped = vec![(0, 3)];
+        inventory.augments.insert(2, vec![10]);
+
+        assert!(inventory.affix(1, &[2, 3], vec![10]).is_err());
+        assert_eq!(inventory.inv_items().len(), 3);
+        assert_eq!(inventory.augments(2), [10]);
+        assert!(inventory.affix(1, &[1], vec![10]).is_err());
+
+        let packets = inventory.affix(1, &[2], vec![10]).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(inventory.inv_items().len(), 2);
+        assert_eq!(inventory.augments(1), [10]);
+        assert!(inventory.augments(2).is_empty());
+    }
+
+    #[test]
+    fn test_expand_character_storage() {
+        let mut inventory = Inventory::default();
+        inventory.character.total_space = 300;
+        assert_eq!(inventory.expand_character_storage(100, 400), Some(400));
+        assert_eq!(inventory.expand_character_storage(100, 400), None);
+        assert_eq!(inventory.character.total_space, 400);
+    }
+
+    #[test]
+    fn test_renumber() {
+        let item = |uuid| Item {
+            uuid,
+            ..Default::default()
+        };
+        let mut inventory = Inventory::default();
+        inventory.inventory.items = vec![item(1), item(2)];
+        inventory.inventory.equiped = vec![(0, 2)];
+        inventory.character.items = vec![item(3)];
+        inventory.augments.insert(1, vec![5]);
+        assert_eq!(inventory.item_count(), 3);
+
+        let mut uuid = 100;
+        let uuids = inventory.renumber(&mut uuid);
+        assert_eq!(uuid, 103);
+        assert_eq!(uuids[&3], 102);
+        assert_eq!(inventory.inventory.equiped, [(0, 101)]);
+        assert_eq!(inventory.augments(100), [5]);
+        assert!(inventory.augments(1).is_empty());
+        assert_eq!(inventory.character.items[0].uuid, 102);
+    }
+}
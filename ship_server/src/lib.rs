@@ -4,35 +4,62 @@
 #![allow(clippy::await_holding_lock)]
 #![allow(dead_code)]
 
+mod alliance;
+mod arksmission;
+mod bandwidth;
 mod battle_stats;
 mod block;
+mod cadence;
+mod casino;
+mod chat_filter;
+mod clock;
+mod craft;
+mod daily;
+mod directory;
+mod disconnect;
+mod doctor;
+mod error_code;
+mod events;
 mod inventory;
 mod invites;
+mod lifecycle;
+mod loadout;
+mod mail;
 mod map;
 mod master_conn;
+mod missionpass;
 mod mutex;
+mod orders;
 mod palette;
 mod party;
 mod quests;
+mod queue;
+mod repair;
+mod resets;
+mod schedule;
 mod settings;
 mod sql;
+mod stats;
+mod team;
+mod titles;
+mod trade;
+mod unlocks;
 mod user;
 
 use data_structs::{
+    balance,
     master_ship::{self, ShipInfo},
     SerDeFile, ServerData,
 };
 use master_conn::MasterConnection;
 use mutex::{Mutex, RwLock};
-use pso2packetlib::{
-    protocol::{login, Packet, PacketType},
-    Connection, PrivateKey, PublicKey,
-};
+use pso2packetlib::{protocol::login, PrivateKey};
 use quests::Quests;
 use rand::Rng;
 use rsa::traits::PublicKeyParts;
 use settings::Settings;
 use std::{
+    collections::HashMap,
     io,
     net::Ipv4Addr,
     sync::{atomic::AtomicU32, Arc},
@@ -48,6 +75,23 @@ pub enum Error {
     InvalidPassword,
     #[error("No user found")]
     NoUser,
+    #[error("Password reset requested")]
+    PasswordResetRequested,
+    #[error("One-time code required")]
+    OtpRequired,
+    #[error("Registration denied: {0}")]
+    RegistrationDenied(String),
+    #[error("Server is under maintenance: {0}")]
+    Maintenance(String),
+    #[error("Client is outdated, minimum version is {0}")]
+    OutdatedClient(String),
+    #[error("Too many login attempts, retry after {0:?}")]
+    TooManyAttempts(std::time::Duration),
+    #[error("User is banned: {reason}")]
+    Banned {
+        until: Option<std::time::Duration>,
+        reason: String,
+    },
     #[error("No user {0} found in mapset {1}")]
     NoUserInMap(u32, String),
     #[error("Mapid {0} not found in mapset {1}")]
@@ -56,10 +100,24 @@ pub enum Error {
     MSError(String),
     #[error("Master ship sent unexpected data")]
     MSUnexpected,
+    #[error("Storage {0} is full or disabled")]
+    StorageFull(u8),
+    #[error("Inventory is full")]
+    InventoryFull,
+    #[error("Account storage was modified concurrently")]
+    StorageConflict,
     #[error("Invalid master ship PSK")]
     MSInvalidPSK,
+    #[error("Master ship PSK was revoked")]
+    MSRevokedPSK,
+    #[error("Ship was blocked by the master ship")]
+    MSBlocked,
+    #[error("Master ship was replaced by a standby")]
+    MSFenced,
     #[error("Master server didn't respond")]
     MSNoResponse,
+    #[error("No master ship is available")]
+    NoMasterShip,
     #[error("User sent unexpected packet while being in state: {0}")]
     UserInvalidState(UserState),
     #[error("Map with name {0} doesn't exist")]
@@ -76,6 +134,10 @@ pub enum Error {
     NoHitboxInfo(String, u32),
     #[error("No ship data available")]
     NoShipData,
+    #[error("{0} self-test checks failed")]
+    ChecksFailed(usize),
+    #[error("Invalid setting: {0}")]
+    InvalidSetting(String),
 
     // passthrough errors
     #[error("SQL error: {0}")]
@@ -117,34 +179,83 @@ struct BlockInfo {
     max_players: u32,
     players: u32,
     lobby_map: String,
+    ngs: bool,
+    counter: settings::CounterSettings,
     server_data: Arc<ServerData>,
+    map_templates: Arc<HashMap<String, map::MapTemplate>>,
     quests: Arc<Quests>,
+    chat_settings: settings::ChatSettings,
+    quest_settings: settings::QuestSettings,
+    shop_settings: settings::ShopSettings,
+    exp_settings: settings::ExpSettings,
+    clock: clock::ServerClock,
+    resets: Arc<resets::ResetScheduler>,
+    events: Arc<events::EventBus>,
+    macro_settings: cadence::MacroSettings,
+    unlocks: unlocks::UnlockSettings,
+    daily: Arc<daily::DailyRotation>,
+    casino: casino::CasinoSettings,
+    schedule: Arc<schedule::EventSchedule>,
+    parties: Arc<party::Parties>,
+    directory: Arc<directory::PlayerDirectory>,
 }
 
 struct BlockData {
     sql: Arc<sql::Sql>,
     block_id: u32,
     block_name: String,
+    /// Whether the block accepts NGS clients.
+    ngs: bool,
     blocks: Arc<RwLock<Vec<BlockInfo>>>,
-    lobby: Arc<Mutex<map::Map>>,
+    /// Currently active lobby (either default or event one).
+    lobby: RwLock<Arc<Mutex<map::Map>>>,
+    /// Lobby loaded from the block settings.
+    default_lobby: Arc<Mutex<map::Map>>,
     key: PrivateKey,
     latest_mapid: AtomicU32,
-    latest_partyid: AtomicU32,
+    parties: Arc<party::Parties>,
+    directory: Arc<directory::PlayerDirectory>,
     server_data: Arc<ServerData>,
+    /// Immutable map data shared by all blocks.
+    map_templates: Arc<HashMap<String, map::MapTemplate>>,
     quests: Arc<Quests>,
+    /// Quests presented by the quest counter.
+    counter: settings::CounterSettings,
     clients: Mutex<Vec<(usize, Arc<Mutex<User>>)>>,
+    chat_settings: settings::ChatSettings,
+    quest_settings: settings::QuestSettings,
+    shop_settings: settings::ShopSettings,
+    exp_settings: settings::ExpSettings,
+    clock: clock::ServerClock,
+    resets: Arc<resets::ResetScheduler>,
+    /// Bus of game events emitted by gameplay code.
+    events: Arc<events::EventBus>,
+    macro_settings: cadence::MacroSettings,
+    /// Lobby actions and stamps unlocked by tickets.
+    unlocks: unlocks::UnlockSettings,
+    /// Featured quests and daily orders of the day.
+    daily: Arc<daily::DailyRotation>,
+    casino: casino::CasinoSettings,
+    /// Scheduled server events.
+    schedule: Arc<schedule::EventSchedule>,
 }
 
 #[derive(Default, Clone)]
 enum Action {
     #[default]
     Nothing,
-    Disconnect,
+    Disconnect(disconnect::DisconnectReason),
 }
 
 // feel free to suggest log level changes
 pub async fn run() -> Result<(), Error> {
     let settings = Settings::load("ship.toml").await?;
+    if settings.doctor {
+        return doctor::run(&settings).await;
+    }
+    if settings.repair {
+        return repair::run(&settings).await;
+    }
     // setup logging
     {
         let _ = std::fs::create_dir_all(&settings.log_dir);
@@ -175,18 +286,16 @@ pub async fn run() -> Result<(), Error> {
     let key = settings.load_key()?;
     let server_statuses = Arc::new(RwLock::new(Vec::<BlockInfo>::new()));
 
-    let master_ip = if let Some(ip) = settings.master_ship.as_ref() {
-        tokio::net::lookup_host(ip)
-            .await?
-            .next()
-            .expect("No IPs found for master ship")
+    let mut master_addrs = if let Some(ip) = settings.master_ship {
+        vec![ip]
     } else {
         log::warn!("No master ship IP provided, discovering...");
-        data_structs::master_ship::try_discover().await?
+        vec![data_structs::master_ship::try_discover().await?.to_string()]
     };
+    master_addrs.extend(settings.standby_master_ships);
     log::info!("Connecting to master ship...");
     let master_conn = MasterConnection::new(
-        master_ip,
+        master_addrs,
         settings.master_ship_psk.as_bytes(),
         &settings.hostkeys_file,
     )
@@ -203,12 +312,15 @@ pub async fn run() -> Result<(), Error> {
                 id,
                 port: settings.balance_port,
                 max_players: total_max_players,
+                players: 0,
                 name: settings.server_name.clone(),
                 status: pso2packetlib::protocol::login::ShipStatus::Online,
                 key: master_ship::KeyInfo {
                     n: key.n().to_bytes_le(),
                     e: key.e().to_bytes_le(),
                 },
+                blocks: vec![],
+                queue: Default::default(),
             },
         )
         .await?;
@@ -247,14 +359,37 @@ pub async fn run() -> Result<(), Error> {
             _ => return Err(Error::MSUnexpected),
         }
     });
+    if let Some(path) = &settings.map_overrides_file {
+        apply_map_overrides(Arc::get_mut(&mut server_data).unwrap(), path).await?;
+    }
     log::info!("Loaded server data");
     let quests = Arc::new(Quests::load(std::mem::take(
         &mut Arc::get_mut(&mut server_data).unwrap().quests,
-    )));
+    ))?);
+    // maps are instanced from shared templates, so the data isn't kept in `server_data`
+    let map_templates = Arc::new(
+        std::mem::take(&mut Arc::get_mut(&mut server_data).unwrap().maps)
+            .into_iter()
+            .map(|(name, data)| Ok((name, map::prepare_template(data)?)))
+            .collect::<Result<HashMap<_, _>, Error>>()?,
+    );
 
     let sql = Arc::new(sql::Sql::new(&settings.db_name, master_conn).await?);
     make_block_balance(server_statuses.clone(), settings.balance_port).await?;
     let mut blocks = vec![];
+    let parties = Arc::new(party::Parties::default());
+    let directory = Arc::new(directory::PlayerDirectory::default());
+    let clock = clock::ServerClock::new(settings.clock);
+    let resets = resets::ResetScheduler::start(clock);
+    let daily = daily::DailyRotation::start(settings.daily, clock, &resets);
+    let schedule = schedule::EventSchedule::start(settings.events, clock);
+    let events = events::EventBus::new();
+    stats::subscribe(&events);
+    titles::subscribe(&events, directory.clone());
+    daily::subscribe(&events, directory.clone());
+    orders::subscribe(&events, directory.clone());
+    missionpass::subscribe(&events, directory.clone());
+    arksmission::subscribe(&events, directory.clone());
     let mut ports = 13001;
     let mut blockstatus_lock = server_statuses.write().await;
     log::info!("Starting blocks...");
@@ -269,8 +404,25 @@ pub async fn run() -> Result<(), Error> {
             max_players: block.max_players,
             players: 0,
             lobby_map: block.lobby_map,
+            ngs: block.ngs,
+            counter: block.counter,
             server_data: server_data.clone(),
+            map_templates: map_templates.clone(),
             quests: quests.clone(),
+            chat_settings: settings.chat.clone(),
+            quest_settings: settings.quests,
+            shop_settings: settings.shops,
+            exp_settings: settings.exp,
+            clock,
+            resets: resets.clone(),
+            events: events.clone(),
+            macro_settings: settings.macros,
+            unlocks: settings.unlocks.clone(),
+            daily: daily.clone(),
+            casino: settings.casino.clone(),
+            schedule: schedule.clone(),
+            parties: parties.clone(),
+            directory: directory.clone(),
         };
         blockstatus_lock.push(new_block.clone());
         let server_statuses = server_statuses.clone();
@@ -285,6 +437,10 @@ pub async fn run() -> Result<(), Error> {
         }))
     }
     drop(blockstatus_lock);
+    tokio::spawn(status_updater(server_statuses.clone(), sql.clone()));
+    tokio::spawn(merge_task(sql.clone(), directory.clone()));
+    tokio::spawn(lifecycle::sweep_task());
+    tokio::spawn(bandwidth::log_task());
 
     log::info!("Server started.");
     tokio::signal::ctrl_c().await?;
@@ -292,6 +448,121 @@ pub async fn run() -> Result<(), Error> {
     Ok(())
 }
 
+/// Layers operator overrides from the `path` TOML over the loaded maps.
+async fn apply_map_overrides(server_data: &mut ServerData, path: &str) -> Result<(), Error> {
+    log::info!("Applying map overrides...");
+    let overrides: HashMap<String, data_structs::map::MapOverride> =
+        toml::from_str(&tokio::fs::read_to_string(path).await?)?;
+    for (name, map_override) in overrides {
+        let Some(map) = server_data.maps.get_mut(&name) else {
+            return Err(Error::NoMapFound(name));
+        };
+        map.apply_override(&map_override)?;
+    }
+    Ok(())
+}
+
+/// Moves characters of accounts merged on the master ship. Merges done while the ship was offline
+/// are applied on startup.
+async fn merge_task(sql: Arc<sql::Sql>, directory: Arc<directory::PlayerDirectory>) {
+    let mut merges = sql.subscribe_merges();
+    match sql.get_account_merges().await {
+        Ok(list) => {
+            for (from, into) in list {
+                if let Err(e) = sql.rehome_characters(from, into).await {
+                    log::warn!("Failed to move characters of merged user {from}: {e}");
+                }
+            }
+        }
+        Err(e) => log::warn!("Failed to get account merges: {e}"),
+    }
+    loop {
+        let (from, into) = match merges.recv().await {
+            Ok(merge) => merge,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("Missed {n} account merges, they are applied after a restart");
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+        // sessions of both accounts would overwrite the moved characters and UUIDs
+        for id in [from, into] {
+            let Some(user) = directory.get(id).and_then(|p| p.user()) else {
+                continue;
+            };
+            let result = user
+                .lock()
+                .await
+                .kick("Your account was merged with another account")
+                .await;
+            if let Err(e) = result {
+                log::warn!("Failed to save the session of user {id}: {e}");
+            }
+        }
+        if let Err(e) = sql.rehome_characters(from, into).await {
+            log::warn!("Failed to move characters of merged user {from}: {e}");
+        }
+    }
+}
+
+/// Periodically reports player counts to the master ship.
+async fn status_updater(blocks: Arc<RwLock<Vec<BlockInfo>>>, sql: Arc<sql::Sql>) {
+    const STATS_PERIOD: std::time::Duration = std::time::Duration::from_secs(3600);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    let mut last_report = std::time::Instant::now();
+    loop {
+        interval.tick().await;
+        let blocks: Vec<_> = blocks
+            .read()
+            .await
+            .iter()
+            .map(|b| master_ship::BlockStatus {
+                id: b.id,
+                name: b.name.clone(),
+                players: b.players,
+                max_players: b.max_players,
+            })
+            .collect();
+        let (players, max_players) = blocks
+            .iter()
+            .fold((0, 0), |(p, m), b| (p + b.players, m + b.max_players));
+        let status = ship_status(players, max_players);
+        stats::update_players(players);
+        if last_report.elapsed() >= STATS_PERIOD {
+            last_report = std::time::Instant::now();
+            if let Err(e) = sql.report_stats(stats::take(players)).await {
+                log::warn!("Failed to send ship statistics: {e}");
+            }
+        }
+        if let Err(e) = sql
+            .update_ship_status(
+                players,
+                max_players,
+                status,
+                blocks,
+                error_code::counts(),
+                lifecycle::counts().to_list(),
+                disconnect::counts(),
+                bandwidth::to_list(),
+                queue::status(),
+            )
+            .await
+        {
+            log::warn!("Failed to send ship status: {e}");
+        }
+    }
+}
+
+fn ship_status(players: u32, max_players: u32) -> login::ShipStatus {
+    if players >= max_players {
+        login::ShipStatus::Full
+    } else if players * 10 >= max_players * 8 {
+        login::ShipStatus::Busy
+    } else {
+        login::ShipStatus::Online
+    }
+}
+
 async fn make_block_balance(
     server_statuses: Arc<RwLock<Vec<BlockInfo>>>,
     port: u16,
@@ -318,32 +589,28 @@ async fn send_block_balance(
     stream: tokio::net::TcpStream,
     blocks: Arc<RwLock<Vec<BlockInfo>>>,
 ) -> Result<(), Error> {
-    stream.set_nodelay(true)?;
-    let local_addr = stream.local_addr()?.ip();
-    log::debug!("Block balancing {local_addr}...");
-    let mut con = Connection::<Packet>::new_async(
-        stream,
-        PacketType::Classic,
-        PrivateKey::None,
-        PublicKey::None,
-    );
+    let (remote_ip, local_ip) = balance::connection_addrs(&stream)?;
+    log::debug!("Block balancing {local_ip}...");
     let mut blocks = blocks.write().await;
-    let server_count = blocks.len() as u32;
     for block in blocks.iter_mut() {
         if block.ip == Ipv4Addr::UNSPECIFIED {
-            if let std::net::IpAddr::V4(addr) = local_addr {
-                block.ip = addr
-            }
+            block.ip = local_ip;
         }
     }
-    let block = &mut blocks[rand::thread_rng().gen_range(0..server_count) as usize];
-    let packet = login::BlockBalancePacket {
-        ip: block.ip,
-        port: block.port,
-        blockname: block.name.clone().into(),
-        ..Default::default()
+    // balancing is done for classic clients, so NGS blocks are only picked if there is no other
+    let classic: Vec<_> = blocks.iter().filter(|b| !b.ngs).collect();
+    let candidates = if classic.is_empty() {
+        blocks.iter().collect()
+    } else {
+        classic
     };
-    con.write_packet_async(&Packet::BlockBalance(packet))
-        .await?;
+    let block = candidates[rand::thread_rng().gen_range(0..candidates.len())];
+    let packet = balance::block_balance_packet(
+        balance::select_addr(remote_ip, local_ip, block.ip)?,
+        block.port,
+        block.name.clone(),
+    );
+    drop(blocks);
+    balance::send_once(stream, &packet).await?;
     Ok(())
 }
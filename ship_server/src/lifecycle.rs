@@ -0,0 +1,244 @@
+//! Lifecycle tracking of maps and parties. Counts created and dropped objects to detect leaks
+//! and clears quest maps that outlived their party, which usually means a reference cycle.
+use crate::{map::Map, mutex::Mutex};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+static MAPS: Counter = Counter::new();
+static PARTIES: Counter = Counter::new();
+/// Number of quest maps cleared by the sweep.
+static SWEPT: AtomicU64 = AtomicU64::new(0);
+/// Quest maps and tokens of their owning quests.
+static QUEST_MAPS: parking_lot::Mutex<Registry<Mutex<Map>>> =
+    parking_lot::Mutex::new(Registry::new());
+
+/// How often quest maps are checked.
+const SWEEP_PERIOD: Duration = Duration::from_secs(60);
+
+struct Counter {
+    created: AtomicU64,
+    dropped: AtomicU64,
+}
+
+struct QuestInstance<T> {
+    map: Weak<T>,
+    owner: Weak<()>,
+    /// Set when the map was found without an owner and players on the previous sweep.
+    orphaned: bool,
+}
+
+/// Registered quest maps.
+struct Registry<T> {
+    instances: Vec<QuestInstance<T>>,
+}
+
+/// Keeps the quest map registered as owned while alive.
+#[derive(Clone)]
+pub struct OwnerToken(Arc<()>);
+
+/// Created and dropped object counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub maps_created: u64,
+    pub maps_dropped: u64,
+    pub parties_created: u64,
+    pub parties_dropped: u64,
+    pub quest_maps_swept: u64,
+}
+
+impl Counter {
+    const fn new() -> Self {
+        Self {
+            created: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+pub fn map_created() {
+    MAPS.created.fetch_add(1, Ordering::Relaxed);
+}
+pub fn map_dropped() {
+    MAPS.dropped.fetch_add(1, Ordering::Relaxed);
+}
+pub fn party_created() {
+    PARTIES.created.fetch_add(1, Ordering::Relaxed);
+}
+pub fn party_dropped() {
+    PARTIES.dropped.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn counts() -> Counts {
+    Counts {
+        maps_created: MAPS.created.load(Ordering::Relaxed),
+        maps_dropped: MAPS.dropped.load(Ordering::Relaxed),
+        parties_created: PARTIES.created.load(Ordering::Relaxed),
+        parties_dropped: PARTIES.dropped.load(Ordering::Relaxed),
+        quest_maps_swept: SWEPT.load(Ordering::Relaxed),
+    }
+}
+
+impl Counts {
+    pub const fn maps_alive(&self) -> u64 {
+        self.maps_created.saturating_sub(self.maps_dropped)
+    }
+    pub const fn parties_alive(&self) -> u64 {
+        self.parties_created.saturating_sub(self.parties_dropped)
+    }
+    /// Returns counters in the form reported to the master ship.
+    pub fn to_list(self) -> Vec<(String, u64)> {
+        [
+            ("maps_created", self.maps_created),
+            ("maps_dropped", self.maps_dropped),
+            ("parties_created", self.parties_created),
+            ("parties_dropped", self.parties_dropped),
+            ("quest_maps_swept", self.quest_maps_swept),
+        ]
+        .into_iter()
+        .map(|(name, count)| (name.to_string(), count))
+        .collect()
+    }
+}
+
+impl<T> Registry<T> {
+    const fn new() -> Self {
+        Self { instances: vec![] }
+    }
+    fn register(&mut self, map: &Arc<T>) -> OwnerToken {
+        let owner = Arc::new(());
+        self.instances.push(QuestInstance {
+            map: Arc::downgrade(map),
+            owner: Arc::downgrade(&owner),
+            orphaned: false,
+        });
+        OwnerToken(owner)
+    }
+    /// Forgets dropped maps and returns alive maps whose owner token was dropped.
+    fn unowned(&mut self) -> Vec<Arc<T>> {
+        self.instances.retain(|i| i.map.strong_count() != 0);
+        self.instances
+            .iter()
+            .filter(|i| i.owner.strong_count() == 0)
+            .filter_map(|i| i.map.upgrade())
+            .collect()
+    }
+    /// Records whether the unowned map has players. Returns `true` if the map was also empty on
+    /// the previous sweep and should be cleared, the map is forgotten then.
+    fn check(&mut self, map: &Arc<T>, empty: bool) -> bool {
+        let Some(pos) = self
+            .instances
+            .iter()
+            .position(|i| std::ptr::eq(i.map.as_ptr(), Arc::as_ptr(map)))
+        else {
+            return false;
+        };
+        if empty && self.instances[pos].orphaned {
+            self.instances.remove(pos);
+            return true;
+        }
+        // give the map one more period to be dropped normally
+        self.instances[pos].orphaned = empty;
+        false
+    }
+}
+
+/// Registers a quest map for sweeping. The map is considered abandoned once the returned token
+/// is dropped.
+pub fn register_quest_map(map: &Arc<Mutex<Map>>) -> OwnerToken {
+    QUEST_MAPS.lock().register(map)
+}
+
+/// Periodically clears quest maps that have no owner and no players but are still alive.
+pub async fn sweep_task() {
+    let mut interval = tokio::time::interval(SWEEP_PERIOD);
+    loop {
+        interval.tick().await;
+        sweep().await;
+    }
+}
+
+async fn sweep() {
+    // collect candidates first, the registry lock can't be held across awaits
+    let candidates = QUEST_MAPS.lock().unowned();
+    for map in candidates {
+        let mut lock = map.lock().await;
+        let empty = lock.player_count() == 0;
+        if !QUEST_MAPS.lock().check(&map, empty) {
+            continue;
+        }
+        log::warn!(
+            "Quest map {} is still alive without an owner ({} references), clearing",
+            lock.get_obj_id(),
+            Arc::strong_count(&map) - 1
+        );
+        lock.clear_state();
+        SWEPT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counts, Registry};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_counts() {
+        let counts = Counts {
+            maps_created: 5,
+            maps_dropped: 3,
+            parties_created: 2,
+            parties_dropped: 2,
+            quest_maps_swept: 1,
+        };
+        assert_eq!(counts.maps_alive(), 2);
+        assert_eq!(counts.parties_alive(), 0);
+        let list = counts.to_list();
+        assert_eq!(list[0], ("maps_created".to_string(), 5));
+        assert_eq!(list.len(), 5);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut registry = Registry::new();
+        let owned = Arc::new(1);
+        let _token = registry.register(&owned);
+        let abandoned = Arc::new(2);
+        drop(registry.register(&abandoned));
+        let dropped = Arc::new(3);
+        drop(registry.register(&dropped));
+        drop(dropped);
+        // owned and dropped maps aren't candidates, dropped ones are forgotten
+        let unowned = registry.unowned();
+        assert_eq!(unowned.len(), 1);
+        assert!(Arc::ptr_eq(&unowned[0], &abandoned));
+        assert_eq!(registry.instances.len(), 2);
+        // empty maps are cleared on the second sweep
+        assert!(!registry.check(&abandoned, true));
+        assert!(registry.check(&abandoned, true));
+        assert!(registry.unowned().is_empty());
+        assert_eq!(registry.instances.len(), 1);
+    }
+
+    #[test]
+    fn test_sweep_players() {
+        let mut registry = Registry::new();
+        let map = Arc::new(1);
+        drop(registry.register(&map));
+        // maps with players are never cleared
+        assert!(!registry.check(&map, false));
+        assert!(!registry.check(&map, false));
+        // emptied maps get another period
+        assert!(!registry.check(&map, true));
+        assert!(!registry.check(&map, false));
+        assert!(!registry.check(&map, true));
+        assert!(registry.check(&map, true));
+        // unregistered maps
+        assert!(!registry.check(&map, true));
+        assert!(!registry.check(&Arc::new(2), true));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{
+    palette::{remap_palettes, PaletteLayout},
+    sql::CharData,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const MAX_LOADOUTS: usize = 10;
+
+/// Saved set of equipped units and palettes.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Loadout {
+    pub name: String,
+    /// Equiped units as (position, uuid) pairs.
+    units: Vec<(u32, u64)>,
+    palette: PaletteLayout,
+}
+
+impl Loadout {
+    pub fn from_character(name: String, char: &CharData) -> Self {
+        Self {
+            name,
+            units: char.inventory.get_equiped_units(),
+            palette: char.palette.get_layout(),
+        }
+    }
+    /// Checks if the loadout can still be applied (e.g. items weren't discarded).
+    pub fn validate(&self, char: &CharData) -> Result<(), Error> {
+        let mut inv = char.inventory.clone();
+        inv.set_equiped_units(&self.units)?;
+        char.palette.validate_layout(&inv, &self.palette)?;
+        Ok(())
+    }
+    /// Replaces item UUIDs after the inventory was renumbered.
+    pub fn remap_uuids(&mut self, uuids: &HashMap<u64, u64>) {
+        for (_, uuid) in self.units.iter_mut() {
+            if let Some(&new) = uuids.get(uuid) {
+                *uuid = new;
+            }
+        }
+        remap_palettes(&mut self.palette.palettes, uuids);
+    }
+    pub fn apply(&self, char: &mut CharData) -> Result<(), Error> {
+        self.validate(char)?;
+        char.inventory.set_equiped_units(&self.units)?;
+        char.palette
+            .apply_layout(&mut char.inventory, self.palette.clone())?;
+        Ok(())
+    }
+}
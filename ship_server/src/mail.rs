@@ -0,0 +1,42 @@
+//! In-game mail of characters.
+use pso2packetlib::protocol::items::Item;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Maximum number of mails in a mailbox. Mass mails ignore this limit.
+pub const MAX_MAILS: usize = 100;
+/// Maximum length of the mail message in characters.
+pub const MAX_MESSAGE_LEN: usize = 500;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mail {
+    /// Id of the mail in the mailbox (assigned by the database).
+    #[serde(skip)]
+    pub id: u32,
+    #[serde(skip)]
+    pub is_read: bool,
+    /// Character name of the sender.
+    pub sender: String,
+    pub message: String,
+    pub attachments: Vec<Item>,
+    /// Special abilities of the attachments with the same index.
+    pub augments: Vec<Vec<u16>>,
+    pub meseta: u64,
+    /// Time (since UNIX epoch) when the mail was sent.
+    pub timestamp: Duration,
+}
+
+impl Mail {
+    pub fn new(sender: impl Into<String>, message: impl Into<String>, timestamp: Duration) -> Self {
+        Self {
+            sender: sender.into(),
+            message: message.into(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+    pub fn has_attachments(&self) -> bool {
+        !self.attachments.is_empty() || self.meseta != 0
+    }
+}
@@ -1,9 +1,15 @@
 use crate::{
     battle_stats::{BattleResult, EnemyStats},
+    events::GameEvent,
     mutex::{Mutex, MutexGuard},
+    user::handlers::settings::lang_code,
     BlockData, Error, User,
 };
-use data_structs::map::MapData;
+use data_structs::{
+    inventory::LobbyItem,
+    map::{Encounter, EnemySpawn, MapData, SpawnCategoryWeight, ZoneChunk, ZoneData},
+    master_ship::gm_level,
+};
 use mlua::{Lua, LuaSerdeExt, StdLib};
 use pso2packetlib::protocol::{
     self,
@@ -16,13 +22,17 @@ use pso2packetlib::protocol::{
     symbolart::{ReceiveSymbolArtPacket, SendSymbolArtPacket},
     ObjectHeader, ObjectType, Packet, PacketType,
 };
-use rand::{prelude::Distribution, seq::IteratorRandom};
+use rand::{
+    prelude::Distribution,
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
 use std::{
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc, Weak,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 type ZoneId = u32;
@@ -36,6 +46,51 @@ struct MapPlayer {
     user: Weak<Mutex<User>>,
 }
 
+/// Enemies spawned automatically in a revealed chunk.
+struct ChunkSpawn {
+    zone_id: ZoneId,
+    chunk_id: u32,
+    spawned_at: Instant,
+    enemies: Vec<u32>,
+    /// Time when the last player left the chunk.
+    empty_since: Option<Instant>,
+}
+
+/// Short-lived object spawned by a lobby item.
+struct LobbyProp {
+    id: u32,
+    zone_id: ZoneId,
+    expires_at: Instant,
+}
+
+/// Maximum number of lobby props spawned at the same time.
+const MAX_LOBBY_PROPS: usize = 32;
+
+/// Data passed to the `on_minimap_restore` lua hook.
+#[derive(serde::Serialize)]
+struct RevealedChunks {
+    zone_id: ZoneId,
+    chunks: Vec<u32>,
+}
+
+/// Equipment of a player as it was last sent to the zone.
+struct SentEquipment {
+    player_id: PlayerId,
+    palette: Vec<u8>,
+    weapon: Vec<u8>,
+    /// Latest changes not yet sent to distant players.
+    pending_palette: Option<Packet>,
+    pending_weapon: Option<Packet>,
+    /// Players outside of [`EQUIPMENT_RANGE`] that haven't received the pending changes.
+    distant: Vec<PlayerId>,
+    pending_since: Instant,
+}
+
+/// Distance within which equipment changes are sent immediately.
+const EQUIPMENT_RANGE: f32 = 50.0;
+/// Delay after which distant players receive the latest equipment.
+const EQUIPMENT_DELAY: Duration = Duration::from_secs(3);
+
 #[derive(Clone)]
 struct OwnedMapPlayer {
     player_id: PlayerId,
@@ -49,12 +104,103 @@ pub enum MapType {
     QuestMap,
 }
 
+/// Result of the `on_chat` lua hook.
+pub enum ChatHook {
+    /// Deliver the message unchanged.
+    Pass,
+    /// Don't deliver the message.
+    Consume,
+    /// Deliver the message with the new text.
+    Replace(String),
+}
+
+/// Immutable map data shared by all instances of the map.
+pub type MapTemplate = Arc<MapData>;
+
+/// Prepares the map data for instancing: converts object data for Vita clients and adds default
+/// scripts. Done once per map, so that instances only keep their dynamic state.
+pub fn prepare_template(mut data: MapData) -> Result<MapTemplate, Error> {
+    let lua = Lua::new_with(StdLib::NONE, mlua::LuaOptions::default())?;
+    convert_vita_objects(&lua, &mut data)?;
+    add_default_luas(&mut data);
+    Ok(Arc::new(data))
+}
+
+/// Converts object data for Vita clients once, so that spawning doesn't run lua. Must be called
+/// before default scripts are added.
+fn convert_vita_objects(lua: &Lua, data: &mut MapData) -> Result<(), Error> {
+    for obj in data.objects.iter_mut() {
+        if obj.vita_data.is_some() {
+            continue;
+        }
+        let Some(lua_code) = data.luas.get(obj.data.name.as_str()) else {
+            obj.vita_data = Some(obj.default_vita_data());
+            continue;
+        };
+        let globals = lua.globals();
+        globals.set("data", obj.data.data.as_slice())?;
+        globals.set("call_type", "to_vita")?;
+        globals.set("size", obj.data.data.len())?;
+        lua.load(lua_code.as_str()).exec()?;
+        obj.vita_data = Some(globals.get::<Vec<u32>>("data")?);
+        globals.raw_remove("data")?;
+        globals.raw_remove("call_type")?;
+        globals.raw_remove("size")?;
+    }
+    Ok(())
+}
+
+fn add_default_luas(data: &mut MapData) {
+    // default object handler
+    for object in data.objects.iter() {
+        let name: &str = &object.data.name;
+        if data.luas.contains_key(name) {
+            continue;
+        }
+        data.luas.insert(
+            name.to_owned(),
+            "if call_type == \"interaction\" then
+                print(packet.object1.id, packet.action)
+            end"
+            .into(),
+        );
+    }
+    // default npc handler
+    for npc in data.npcs.iter() {
+        let name: &str = &npc.data.name;
+        if data.luas.contains_key(name) {
+            continue;
+        }
+        data.luas.insert(
+            name.to_owned(),
+            "if call_type == \"interaction\" then
+                if packet.action == \"READY\" then
+                    local ready_data = {}; 
+                    local packet_data = {};
+                    packet_data.attribute = \"FavsNeutral\";
+                    packet_data.receiver = packet.object3;
+                    packet_data.target = packet.object1;
+                    packet_data.object3 = packet.object1;
+                    ready_data.SetTag = packet_data; 
+                    send(sender, ready_data);
+                    ready_data.SetTag.attribute = \"AP\";
+                    send(sender, ready_data);
+                else
+                    print(packet.object1.id, packet.action);
+                end
+            end"
+            .into(),
+        );
+    }
+}
+
 pub struct Map {
     // lua is not `Send` so i've put it in a mutex
     // this mutex shouldn't block, because `Map` is under a mutex itself.
     lua: parking_lot::Mutex<Lua>,
     map_objs: Vec<(ZoneId, ObjectHeader)>,
-    data: MapData,
+    data: MapTemplate,
+    map_object: ObjectHeader,
     players: Vec<MapPlayer>,
     // fighting with async recursion
     to_move: Vec<(PlayerId, String)>,
@@ -63,17 +209,32 @@ pub struct Map {
     block_data: Option<Arc<BlockData>>,
     enemies: Vec<(u32, ZoneId, EnemyStats)>,
     enemy_level: u32,
-    chunk_spawns: Vec<(u32, Instant)>,
+    /// Quest difficulty used for enemy stats.
+    difficulty: u8,
+    chunk_spawns: Vec<ChunkSpawn>,
+    lobby_props: Vec<LobbyProp>,
+    sent_equipment: Vec<SentEquipment>,
     map_type: MapType,
+    /// Name under which minimap reveals are stored in characters. Reveals aren't stored if
+    /// the name is empty.
+    name: String,
+    /// Name id of the quest played on the map.
+    quest: Option<u32>,
+    /// Players that already cleared the quest on this map.
+    quest_clears: Vec<PlayerId>,
+    /// Percentage of enemy EXP given to players outside of the killer's party. `None` if the map
+    /// isn't shared by an alliance.
+    alliance_exp_share: Option<u8>,
 }
 impl Map {
-    pub fn new_from_data(data: MapData, map_obj_id: &AtomicU32) -> Result<Self, Error> {
+    pub fn new_from_template(data: MapTemplate, map_obj_id: &AtomicU32) -> Result<Self, Error> {
         // will be increased as needed
         let lua_libs = StdLib::NONE;
         let mut map = Self {
             lua: Lua::new_with(lua_libs, mlua::LuaOptions::default())?.into(),
             map_objs: vec![],
             data,
+            map_object: ObjectHeader::default(),
             players: vec![],
             to_move: vec![],
             to_lobby_move: vec![],
@@ -81,15 +242,22 @@ impl Map {
             block_data: None,
             enemies: vec![],
             enemy_level: 0,
+            difficulty: 0,
             chunk_spawns: vec![],
+            lobby_props: vec![],
+            sent_equipment: vec![],
             map_type: MapType::QuestMap,
+            name: String::new(),
+            quest: None,
+            quest_clears: vec![],
+            alliance_exp_share: None,
         };
         let map_obj = ObjectHeader {
             id: map_obj_id.fetch_add(1, Ordering::Relaxed),
             entity_type: ObjectType::Map,
             ..Default::default()
         };
-        map.data.map_data.map_object = map_obj;
+        map.map_object = map_obj;
         let def_id = map.data.init_map;
         map.map_objs.push((def_id, map_obj));
         for zone in &map.data.zones {
@@ -102,20 +270,71 @@ impl Map {
                 },
             ))
         }
-        map.init_lua()?;
         map.find_max_id();
         log::trace!("Map {} created", map_obj.id);
+        crate::lifecycle::map_created();
         Ok(map)
     }
-    pub fn set_map_type(&mut self, map_type: MapType) {
+    pub const fn set_map_type(&mut self, map_type: MapType) {
         self.map_type = map_type;
     }
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = name.into();
+    }
+    pub const fn set_quest(&mut self, name_id: u32) {
+        self.quest = Some(name_id);
+    }
+    /// Marks the quest as cleared by the player. Players are moved to the lobby by the map scripts
+    /// only after finishing the quest, so this is called for every such move.
+    fn complete_quest(&mut self, player_id: PlayerId) {
+        if self.quest.is_none() || self.quest_clears.contains(&player_id) {
+            return;
+        }
+        self.quest_clears.push(player_id);
+        if let Some(block_data) = &self.block_data {
+            block_data.events.emit(GameEvent::QuestCleared {
+                player_id,
+                quest: self.quest,
+            });
+        }
+    }
+    pub const fn set_alliance_exp_share(&mut self, share: u8) {
+        self.alliance_exp_share = Some(share);
+    }
+    pub const fn is_lobby(&self) -> bool {
+        matches!(self.map_type, MapType::Lobby)
+    }
     pub fn set_block_data(&mut self, data: Arc<BlockData>) {
         self.block_data = Some(data);
     }
-    pub fn set_enemy_level(&mut self, level: u32) {
+    pub const fn set_enemy_level(&mut self, level: u32) {
         self.enemy_level = level;
     }
+    pub const fn set_difficulty(&mut self, difficulty: u8) {
+        self.difficulty = difficulty;
+    }
+    pub const fn get_obj_id(&self) -> u32 {
+        self.map_object.id
+    }
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+    /// Drops the dynamic state of the map, breaking reference cycles through the block data,
+    /// players and lua values.
+    pub fn clear_state(&mut self) {
+        if let Ok(lua) = Lua::new_with(StdLib::NONE, mlua::LuaOptions::default()) {
+            *self.lua.get_mut() = lua;
+        }
+        self.block_data = None;
+        self.players.clear();
+        self.to_move.clear();
+        self.to_lobby_move.clear();
+        self.quest_clears.clear();
+        self.enemies.clear();
+        self.chunk_spawns.clear();
+        self.lobby_props.clear();
+        self.sent_equipment.clear();
+    }
     fn find_max_id(&mut self) {
         let obj_max = self
             .data
@@ -147,65 +366,33 @@ impl Map {
             .unwrap_or(0);
         self.max_id = obj_max.max(npc_max).max(event_max).max(transporter_max) + 1;
     }
-    fn init_lua(&mut self) -> Result<(), Error> {
-        // default object handler
-        for object in self.data.objects.iter() {
-            let name: &str = &object.data.name;
-            if self.data.luas.contains_key(name) {
-                continue;
-            }
-            self.data.luas.insert(
-                name.to_owned(),
-                "if call_type == \"interaction\" then
-                    print(packet.object1.id, packet.action)
-                elseif call_type == \"to_vita\" then
-	                for i=1,size,2 do
-		                if data[i] > 50 and data[i] < 80 then
-			                data[i] = data[i] - 1
-		                end
-	                end
-                end"
-                .into(),
-            );
-        }
-        // default npc handler
-        for npc in self.data.npcs.iter() {
-            let name: &str = &npc.data.name;
-            if self.data.luas.contains_key(name) {
-                continue;
-            }
-            self.data.luas.insert(
-                name.to_owned(),
-                "if call_type == \"interaction\" then
-                    if packet.action == \"READY\" then
-                        local ready_data = {}; 
-                        local packet_data = {};
-                        packet_data.attribute = \"FavsNeutral\";
-                        packet_data.receiver = packet.object3;
-                        packet_data.target = packet.object1;
-                        packet_data.object3 = packet.object1;
-                        ready_data.SetTag = packet_data; 
-                        send(sender, ready_data);
-                        ready_data.SetTag.attribute = \"AP\";
-                        send(sender, ready_data);
-                    else
-                        print(packet.object1.id, packet.action);
-                    end
-                end"
-                .into(),
-            );
-        }
-        Ok(())
-    }
-
     pub async fn init_add_player(&mut self, new_player: Arc<Mutex<User>>) -> Result<(), Error> {
         let mut np_lock = new_player.lock().await;
-        np_lock
-            .send_packet(&Packet::LoadLevel(self.data.map_data.clone()))
-            .await?;
+        let mut load_level = self.data.map_data.clone();
+        load_level.map_object = self.map_object;
+        load_level.receiver = ObjectHeader {
+            id: np_lock.get_user_id(),
+            entity_type: ObjectType::Player,
+            ..Default::default()
+        };
+        np_lock.send_packet(&Packet::LoadLevel(load_level)).await?;
         drop(np_lock);
         self.add_player(new_player, self.data.init_map).await
     }
+    pub fn get_zone_id_named(&self, name: &str) -> Option<ZoneId> {
+        self.data
+            .zones
+            .iter()
+            .find(|z| z.name == name)
+            .map(|z| z.zone_id)
+    }
+    pub fn get_zone_name(&self, zone_id: ZoneId) -> Option<&str> {
+        self.data
+            .zones
+            .iter()
+            .find(|z| z.zone_id == zone_id)
+            .map(|z| z.name.as_str())
+    }
     pub async fn move_player_named(&mut self, id: PlayerId, name: &str) -> Result<(), Error> {
         let Some(zone) = self.data.zones.iter().find(|z| z.name == name) else {
             return Err(Error::InvalidInput("move_player_named"));
@@ -250,11 +437,30 @@ impl Map {
         let Some(player) = self.remove_player(id).await else {
             return Err(Error::NoUserInMap(id, self.data.map_data.unk7.to_string()));
         };
-        let lobby = player.lock().await.get_blockdata().lobby.clone();
+        let lobby = player
+            .lock()
+            .await
+            .get_blockdata()
+            .lobby
+            .read()
+            .await
+            .clone();
         player.lock().await.set_map(lobby.clone());
         let mut lock = lobby.lock().await;
         lock.init_add_player(player).await
     }
+    /// Moves all players from this map to the `other` one.
+    pub async fn move_all_players(&mut self, other: Arc<Mutex<Map>>) -> Result<(), Error> {
+        let ids: Vec<_> = self.players.iter().map(|p| p.player_id).collect();
+        for id in ids {
+            let Some(player) = self.remove_player(id).await else {
+                continue;
+            };
+            player.lock().await.set_map(other.clone());
+            other.lock().await.init_add_player(player).await?;
+        }
+        Ok(())
+    }
 
     async fn add_player(
         &mut self,
@@ -277,16 +483,22 @@ impl Map {
             other_equipment.push(char_data.palette.send_change_palette(pid));
             other_equipment.push(char_data.palette.send_cur_weapon(pid, &char_data.inventory));
             other_equipment.push(char_data.inventory.send_equiped(pid));
-            other_characters.push((char_data.character.clone(), p.position, p.user_data.isgm));
+            other_characters.push((
+                char_data.character.clone(),
+                p.position,
+                p.user_data.gm_level >= gm_level::GM,
+            ));
         }
         let mut np_lock = new_player.lock().await;
         np_lock.zone_id = zone_id;
         let np_id = np_lock.get_user_id();
+        np_lock
+            .get_blockdata()
+            .directory
+            .set_zone(np_id, self.get_zone_name(zone_id));
         let Some(new_character) = np_lock.character.to_owned() else {
             unreachable!("User should be in state >= `PreInGame`")
         };
-        self.data.map_data.receiver.id = np_id;
-        self.data.map_data.receiver.entity_type = ObjectType::Player;
         np_lock
             .send_packet(&Packet::SetPlayerID(SetPlayerIDPacket {
                 player_id: np_id,
@@ -302,7 +514,7 @@ impl Map {
             .map(|z| z.default_location)
             .unwrap_or_default();
         np_lock.position = pos;
-        let np_gm = np_lock.user_data.isgm as u32;
+        let np_gm = (np_lock.user_data.gm_level >= gm_level::GM) as u32;
         np_lock
             .spawn_character(CharacterSpawnPacket {
                 position: pos,
@@ -317,14 +529,14 @@ impl Map {
                 ..Default::default()
             })
             .await?;
-        Self::load_objects(&self.lua, &self.data, zone_id, &mut np_lock)?;
-        for (character, position, isgm) in other_characters {
+        Self::load_objects(&self.data, zone_id, &mut np_lock)?;
+        for (character, position, is_gm) in other_characters {
             let player_id = character.player_id;
             np_lock
                 .spawn_character(CharacterSpawnPacket {
                     position,
                     spawn_type: CharacterSpawnType::Other,
-                    gm_flag: isgm as u32,
+                    gm_flag: is_gm as u32,
                     player_obj: ObjectHeader {
                         id: player_id,
                         entity_type: ObjectType::Player,
@@ -388,6 +600,7 @@ impl Map {
             chunk_id: 0,
             user: Arc::downgrade(&new_player),
         });
+        self.restore_minimap(np_id, zone_id, None).await?;
 
         let Some(lua) = self.data.luas.get("on_player_load").cloned() else {
             return Ok(());
@@ -396,7 +609,9 @@ impl Map {
             .await?;
         Ok(())
     }
-    pub async fn send_palette_change(&self, sender_id: PlayerId) -> Result<(), Error> {
+    /// Sends changed palette and weapon of the player to the zone. Players outside of
+    /// [`EQUIPMENT_RANGE`] only receive the latest equipment after [`EQUIPMENT_DELAY`].
+    pub async fn send_palette_change(&mut self, sender_id: PlayerId) -> Result<(), Error> {
         let Some(user) = self.players.iter().find(|p| p.player_id == sender_id) else {
             return Err(Error::NoUserInMap(
                 sender_id,
@@ -407,7 +622,7 @@ impl Map {
         let Some(player) = user.user.upgrade() else {
             return Err(Error::InvalidInput("send_palette_change"));
         };
-        let new_eqipment = {
+        let (palette, weapon, position) = {
             let p = player.lock().await;
             let Some(character) = &p.character else {
                 unreachable!("Users in map should have characters")
@@ -417,16 +632,101 @@ impl Map {
                 character
                     .palette
                     .send_cur_weapon(sender_id, &character.inventory),
+                p.position,
             )
         };
-        exec_users(&self.players, zone_id, |_, mut player| {
-            let _ = player.try_send_packet(&new_eqipment.0);
-            let _ = player.try_send_packet(&new_eqipment.1);
+        let state = match self
+            .sent_equipment
+            .iter()
+            .position(|s| s.player_id == sender_id)
+        {
+            Some(pos) => &mut self.sent_equipment[pos],
+            None => {
+                self.sent_equipment.push(SentEquipment {
+                    player_id: sender_id,
+                    palette: vec![],
+                    weapon: vec![],
+                    pending_palette: None,
+                    pending_weapon: None,
+                    distant: vec![],
+                    pending_since: Instant::now(),
+                });
+                self.sent_equipment.last_mut().unwrap()
+            }
+        };
+        let mut changed = vec![];
+        let palette_data = palette.clone().write(PacketType::NA);
+        if state.palette != palette_data {
+            state.palette = palette_data;
+            state.pending_palette = Some(palette.clone());
+            changed.push(palette);
+        }
+        let weapon_data = weapon.clone().write(PacketType::NA);
+        if state.weapon != weapon_data {
+            state.weapon = weapon_data;
+            state.pending_weapon = Some(weapon.clone());
+            changed.push(weapon);
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let range = Some((position, EQUIPMENT_RANGE));
+        let mut distant = vec![];
+        exec_users(&self.players, zone_id, |user, mut player| {
+            if user.player_id == sender_id || is_in_range(range, &player.position) {
+                for packet in &changed {
+                    let _ = player.try_send_packet(packet);
+                }
+            } else {
+                distant.push(user.player_id);
+            }
         })
         .await;
+        if state.distant.is_empty() {
+            state.pending_since = Instant::now();
+        }
+        for id in distant {
+            if !state.distant.contains(&id) {
+                state.distant.push(id);
+            }
+        }
+        if state.distant.is_empty() {
+            state.pending_palette = None;
+            state.pending_weapon = None;
+        }
 
         Ok(())
     }
+    /// Sends the latest equipment changes to distant players once [`EQUIPMENT_DELAY`] passes.
+    pub async fn flush_equipment(&mut self) {
+        for state in self
+            .sent_equipment
+            .iter_mut()
+            .filter(|s| !s.distant.is_empty() && s.pending_since.elapsed() >= EQUIPMENT_DELAY)
+        {
+            let Some(zone_id) = self
+                .players
+                .iter()
+                .find(|p| p.player_id == state.player_id)
+                .map(|p| p.zone_id)
+            else {
+                continue;
+            };
+            let distant = std::mem::take(&mut state.distant);
+            let packets: Vec<_> = [state.pending_palette.take(), state.pending_weapon.take()]
+                .into_iter()
+                .flatten()
+                .collect();
+            exec_users(&self.players, zone_id, |user, mut player| {
+                if distant.contains(&user.player_id) {
+                    for packet in &packets {
+                        let _ = player.try_send_packet(packet);
+                    }
+                }
+            })
+            .await;
+        }
+    }
     pub async fn send_to_all(&self, sender_id: PlayerId, packet: &Packet) {
         let Some(user) = self.players.iter().find(|p| p.player_id == sender_id) else {
             return;
@@ -503,10 +803,9 @@ impl Map {
     }
 
     pub async fn send_message(&self, mut packet: Packet, id: PlayerId) {
-        let Some(user) = self.players.iter().find(|p| p.player_id == id) else {
+        let Some((zone_id, range)) = self.get_chat_range(id).await else {
             return;
         };
-        let zone_id = user.zone_id;
         if let Packet::ChatMessage(ref mut data) = packet {
             data.object = ObjectHeader {
                 id,
@@ -515,16 +814,25 @@ impl Map {
             };
         }
         exec_users(&self.players, zone_id, |_, mut player| {
-            let _ = player.try_send_packet(&packet);
+            if is_in_range(range, &player.position) && !player.is_blacklisted(id) {
+                let _ = player.try_send_packet(&packet);
+            }
         })
         .await;
     }
 
     pub async fn send_sa(&self, data: SendSymbolArtPacket, id: PlayerId) {
-        let Some(user) = self.players.iter().find(|p| p.player_id == id) else {
+        let Some((zone_id, range)) = self.get_chat_range(id).await else {
             return;
         };
-        let zone_id = user.zone_id;
+        if self
+            .data
+            .zones
+            .iter()
+            .any(|z| z.zone_id == zone_id && z.disable_symbol_arts)
+        {
+            return;
+        }
         let packet = Packet::ReceiveSymbolArt(ReceiveSymbolArtPacket {
             object: ObjectHeader {
                 id,
@@ -538,10 +846,26 @@ impl Map {
             unk3: data.unk3,
         });
         exec_users(&self.players, zone_id, |_, mut player| {
-            let _ = player.try_send_packet(&packet);
+            if is_in_range(range, &player.position) && !player.is_blacklisted(id) {
+                let _ = player.try_send_packet(&packet);
+            }
         })
         .await;
     }
+    /// Returns sender's zone and position with maximum distance if map chat range is limited.
+    async fn get_chat_range(&self, id: PlayerId) -> Option<(ZoneId, Option<(Position, f32)>)> {
+        let player = self.players.iter().find(|p| p.player_id == id)?;
+        let max_dist = self
+            .block_data
+            .as_ref()
+            .map(|b| b.chat_settings.map_range)
+            .unwrap_or_default();
+        if max_dist <= 0.0 {
+            return Some((player.zone_id, None));
+        }
+        let position = player.user.upgrade()?.lock().await.position;
+        Some((player.zone_id, Some((position, max_dist))))
+    }
 
     pub async fn remove_player(&mut self, id: PlayerId) -> Option<Arc<Mutex<User>>> {
         let (pos, _) = self
@@ -550,6 +874,7 @@ impl Map {
             .enumerate()
             .find(|(_, p)| p.player_id == id)?;
         let user = self.players.swap_remove(pos);
+        self.sent_equipment.retain(|s| s.player_id != id);
         let mut packet = Packet::DespawnPlayer(protocol::objects::DespawnPlayerPacket {
             receiver: ObjectHeader {
                 id: 0,
@@ -571,18 +896,25 @@ impl Map {
         .await;
         user.user.upgrade()
     }
+    /// Spawns an enemy and returns its id.
     pub async fn spawn_enemy(
         &mut self,
         name: &str,
         pos: Position,
         zone_id: ZoneId,
-    ) -> Result<(), Error> {
+    ) -> Result<u32, Error> {
         let Some(block_data) = self.block_data.to_owned() else {
             return Err(Error::NoEnemyData(name.to_string()));
         };
         let id = self.max_id + 1;
         self.max_id += 1;
-        let data = EnemyStats::build(name, self.enemy_level, pos, &block_data.server_data)?;
+        let data = EnemyStats::build(
+            name,
+            self.enemy_level,
+            self.difficulty,
+            pos,
+            &block_data.server_data,
+        )?;
         let map_id = self
             .data
             .zones
@@ -603,7 +935,7 @@ impl Map {
         })
         .await;
 
-        Ok(())
+        Ok(id)
     }
     fn prepare_enemy_packets(enemy_id: u32, map_id: u32, enemy: &EnemyStats) -> (Packet, Packet) {
         let packet = enemy.create_spawn_packet(enemy_id, map_id as _);
@@ -640,6 +972,8 @@ impl Map {
             };
             let mut lock = inflicter.lock().await;
             let zone_id = lock.get_zone_id();
+            let inflicter_id = lock.get_user_id();
+            let inflicter_party = lock.get_current_party();
             let result = lock
                 .get_stats_mut()
                 .damage_enemy(target, &block_data.server_data, dmg)?;
@@ -668,9 +1002,23 @@ impl Map {
                     });
                     let mut dmg_packet = Packet::DamageReceive(dmg_packet);
                     let mut kill_packet = Packet::EnemyKilled(kill_packet);
+                    let enemy = self.enemies[pos].2.get_name().to_string();
+                    let exp_amount = block_data.schedule.scale_exp(exp_amount);
                     let mut exp_packets = vec![];
+                    let mut defeated_by = vec![];
+                    let exp_share = self.alliance_exp_share;
                     exec_users(&self.players, zone_id, |_, mut player| {
-                        exp_packets.push(player.add_exp(exp_amount))
+                        // players of other parties in the alliance get a part of the EXP
+                        let exp = match (exp_share, &inflicter_party, player.get_current_party()) {
+                            (Some(share), Some(killer), Some(party))
+                                if !Arc::ptr_eq(killer, &party) =>
+                            {
+                                (u64::from(exp_amount) * u64::from(share) / 100) as u32
+                            }
+                            _ => exp_amount,
+                        };
+                        exp_packets.push(player.add_exp(exp));
+                        defeated_by.push(player.get_user_id());
                     })
                     .await;
                     let exp_packets = exp_packets.into_iter().collect::<Result<Vec<_>, _>>()?;
@@ -698,6 +1046,16 @@ impl Map {
                     })
                     .await;
                     self.enemies.remove(pos);
+                    block_data.events.emit(GameEvent::EnemyKilled {
+                        player_id: inflicter_id,
+                        exp: exp_amount,
+                    });
+                    for player_id in defeated_by {
+                        block_data.events.emit(GameEvent::EnemyDefeated {
+                            player_id,
+                            enemy: enemy.clone(),
+                        });
+                    }
                 }
             }
         } else if inflicter.entity_type == ObjectType::Object
@@ -751,37 +1109,16 @@ impl Map {
 
         Ok(())
     }
-    fn load_objects(
-        lua: &parking_lot::Mutex<Lua>,
-        map_data: &MapData,
-        zone_id: ZoneId,
-        user: &mut User,
-    ) -> Result<(), Error> {
-        let lua = lua.lock();
-        for mut obj in map_data
-            .objects
-            .iter()
-            .filter(|o| o.zone_id == zone_id)
-            .cloned()
-        {
-            if user.user_data.packet_type == PacketType::Vita {
-                let lua_code = map_data
-                    .luas
-                    .get(obj.data.name.as_str())
-                    .map(|s| s.as_str())
-                    .unwrap_or("");
-                let globals = lua.globals();
-                globals.set("data", obj.data.data.as_slice())?;
-                globals.set("call_type", "to_vita")?;
-                globals.set("size", obj.data.data.len())?;
-                let chunk = lua.load(lua_code);
-                chunk.exec()?;
-                obj.data.data = globals.get::<Vec<u32>>("data")?.into();
-                globals.raw_remove("data")?;
-                globals.raw_remove("call_type")?;
-                globals.raw_remove("size")?;
+    fn load_objects(map_data: &MapData, zone_id: ZoneId, user: &mut User) -> Result<(), Error> {
+        let is_vita = user.user_data.packet_type == PacketType::Vita;
+        for obj in map_data.objects.iter().filter(|o| o.zone_id == zone_id) {
+            let mut data = obj.data.clone();
+            if is_vita {
+                if let Some(vita_data) = &obj.vita_data {
+                    data.data = vita_data.clone().into();
+                }
             }
-            user.try_send_packet(&Packet::ObjectSpawn(obj.data))?;
+            user.try_send_packet(&Packet::ObjectSpawn(data))?;
         }
         for npc in map_data
             .npcs
@@ -811,6 +1148,177 @@ impl Map {
         Ok(())
     }
 
+    async fn spawn_encounter(
+        &mut self,
+        zone: &ZoneData,
+        chunk: &ZoneChunk,
+        encounter: &Encounter,
+        min: u32,
+        max: u32,
+        user: &MapPlayer,
+    ) -> Result<Vec<u32>, Error> {
+        let count =
+            rand::distributions::Uniform::new_inclusive(min, max).sample(&mut rand::thread_rng());
+        let spawn_point = chunk
+            .enemy_spawn_points
+            .iter()
+            .choose(&mut rand::thread_rng());
+        let spawn_point = match spawn_point {
+            Some(x) => *x,
+            None => user.user.upgrade().unwrap().lock().await.position,
+        };
+        let enemies = roll_encounter(&zone.enemies, encounter, count, &mut rand::thread_rng());
+        let mut ids = vec![];
+        for enemy in enemies {
+            ids.push(
+                self.spawn_enemy(&enemy.enemy_name, spawn_point, zone.zone_id)
+                    .await?,
+            );
+        }
+        Ok(ids)
+    }
+
+    /// Despawns automatically spawned enemies in chunks that had no players for the map despawn
+    /// time. They are spawned again when the chunk is revealed.
+    pub async fn despawn_idle_enemies(&mut self) {
+        let Some(despawn_time) = self.data.enemy_despawn_time else {
+            return;
+        };
+        let now = Instant::now();
+        let players = &self.players;
+        let mut to_despawn = vec![];
+        self.chunk_spawns.retain_mut(|spawn| {
+            if players
+                .iter()
+                .any(|p| p.zone_id == spawn.zone_id && p.chunk_id == spawn.chunk_id)
+            {
+                spawn.empty_since = None;
+                return true;
+            }
+            let empty_since = *spawn.empty_since.get_or_insert(now);
+            if now.duration_since(empty_since) < despawn_time {
+                return true;
+            }
+            to_despawn.push((spawn.zone_id, std::mem::take(&mut spawn.enemies)));
+            false
+        });
+        for (zone_id, ids) in to_despawn {
+            self.enemies.retain(|(id, _, _)| !ids.contains(id));
+            let map_id = self
+                .data
+                .zones
+                .iter()
+                .find(|z| z.zone_id == zone_id)
+                .map(|z| z.settings.map_id)
+                .unwrap_or_default();
+            exec_users(&self.players, zone_id, |_, mut player| {
+                let receiver = player.create_object_header();
+                for &id in &ids {
+                    let _ = player.try_send_packet(&Packet::DespawnObject(
+                        protocol::objects::DespawnObjectPacket {
+                            player: receiver,
+                            item: ObjectHeader {
+                                id,
+                                entity_type: ObjectType::Object,
+                                map_id: map_id as _,
+                                ..Default::default()
+                            },
+                        },
+                    ));
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Spawns the object of the lobby item next to the player. Returns false if the map isn't a
+    /// lobby or has too many props.
+    pub async fn spawn_lobby_prop(
+        &mut self,
+        sender_id: PlayerId,
+        position: Position,
+        item: &LobbyItem,
+    ) -> bool {
+        if !self.is_lobby() || self.lobby_props.len() >= MAX_LOBBY_PROPS {
+            return false;
+        }
+        let Some(zone_id) = self
+            .players
+            .iter()
+            .find(|p| p.player_id == sender_id)
+            .map(|p| p.zone_id)
+        else {
+            return false;
+        };
+        let id = self.max_id + 1;
+        self.max_id += 1;
+        let map_id = self
+            .data
+            .zones
+            .iter()
+            .find(|z| z.zone_id == zone_id)
+            .map(|z| z.settings.map_id)
+            .unwrap_or_default();
+        let mut object = item.object.clone();
+        object.object = ObjectHeader {
+            id,
+            entity_type: ObjectType::Object,
+            map_id: map_id as _,
+            ..Default::default()
+        };
+        object.position = position;
+        self.lobby_props.push(LobbyProp {
+            id,
+            zone_id,
+            expires_at: Instant::now() + Duration::from_secs(item.lifetime),
+        });
+        let range = (item.range > 0.0).then_some((position, item.range));
+        let packet = Packet::ObjectSpawn(object);
+        exec_users(&self.players, zone_id, |_, mut player| {
+            if is_in_range(range, &player.position) {
+                let _ = player.try_send_packet(&packet);
+            }
+        })
+        .await;
+        true
+    }
+
+    /// Despawns lobby props with expired lifetime.
+    pub async fn despawn_expired_props(&mut self) {
+        let now = Instant::now();
+        let mut expired = vec![];
+        self.lobby_props.retain(|prop| {
+            if prop.expires_at > now {
+                return true;
+            }
+            expired.push((prop.id, prop.zone_id));
+            false
+        });
+        for (id, zone_id) in expired {
+            let map_id = self
+                .data
+                .zones
+                .iter()
+                .find(|z| z.zone_id == zone_id)
+                .map(|z| z.settings.map_id)
+                .unwrap_or_default();
+            exec_users(&self.players, zone_id, |_, mut player| {
+                let _ = player.try_send_packet(&Packet::DespawnObject(
+                    protocol::objects::DespawnObjectPacket {
+                        player: player.create_object_header(),
+                        item: ObjectHeader {
+                            id,
+                            entity_type: ObjectType::Object,
+                            map_id: map_id as _,
+                            ..Default::default()
+                        },
+                    },
+                ));
+            })
+            .await;
+        }
+    }
+
     pub async fn minimap_reveal(
         &mut self,
         sender_id: PlayerId,
@@ -839,86 +1347,66 @@ impl Map {
         };
         if let Some(chunk) = zone.chunks.iter().find(|c| c.chunk_id == packet.chunk_id) {
             // wow, how nested
-            match chunk.enemy_spawn_type {
+            match &chunk.enemy_spawn_type {
                 data_structs::map::EnemySpawnType::Disabled => {}
-                data_structs::map::EnemySpawnType::Automatic { min, max } => {
-                    let count = rand::distributions::Uniform::new_inclusive(min, max)
-                        .sample(&mut rand::thread_rng());
-                    if !self.chunk_spawns.iter().any(|s| s.0 == chunk.chunk_id) {
-                        self.chunk_spawns
-                            .push((chunk.chunk_id, std::time::Instant::now()));
-                        // this is length biased
-                        let spawn_category = zone
-                            .enemies
-                            .iter()
-                            .map(|e| e.spawn_category)
-                            .choose(&mut rand::thread_rng())
-                            .unwrap_or_default();
-                        let spawn_point = chunk
-                            .enemy_spawn_points
-                            .iter()
-                            .choose(&mut rand::thread_rng());
-                        let spawn_point = match spawn_point {
-                            Some(x) => *x,
-                            None => user.user.upgrade().unwrap().lock().await.position,
-                        };
-                        for _ in 0..count {
-                            let enemy = zone
-                                .enemies
-                                .iter()
-                                .filter(|e| e.spawn_category == spawn_category)
-                                .choose(&mut rand::thread_rng());
-                            if let Some(enemy) = enemy {
-                                self.spawn_enemy(&enemy.enemy_name, spawn_point, zone_id)
-                                    .await?;
-                            }
-                        }
+                data_structs::map::EnemySpawnType::Automatic {
+                    min,
+                    max,
+                    encounter,
+                } => {
+                    if !self
+                        .chunk_spawns
+                        .iter()
+                        .any(|s| s.zone_id == zone_id && s.chunk_id == chunk.chunk_id)
+                    {
+                        let enemies = self
+                            .spawn_encounter(&zone, chunk, encounter, *min, *max, &user)
+                            .await?;
+                        self.chunk_spawns.push(ChunkSpawn {
+                            zone_id,
+                            chunk_id: chunk.chunk_id,
+                            spawned_at: Instant::now(),
+                            enemies,
+                            empty_since: None,
+                        });
                     }
                 }
                 data_structs::map::EnemySpawnType::AutomaticWithRespawn {
                     min,
                     max,
                     respawn_time,
+                    encounter,
                 } => {
-                    let count = rand::distributions::Uniform::new_inclusive(min, max)
-                        .sample(&mut rand::thread_rng());
-                    let (spawn, is_first) = if let Some(spawn) =
-                        self.chunk_spawns.iter().find(|s| s.0 == chunk.chunk_id)
-                    {
-                        (spawn, false)
-                    } else {
-                        self.chunk_spawns
-                            .push((chunk.chunk_id, std::time::Instant::now()));
-                        (self.chunk_spawns.last().unwrap(), true)
-                    };
-
-                    if is_first || spawn.1.elapsed() > respawn_time {
-                        // this is length biased
-                        let spawn_category = zone
-                            .enemies
-                            .iter()
-                            .map(|e| e.spawn_category)
-                            .choose(&mut rand::thread_rng())
-                            .unwrap_or_default();
-                        let spawn_point = chunk
-                            .enemy_spawn_points
-                            .iter()
-                            .choose(&mut rand::thread_rng());
-                        let spawn_point = match spawn_point {
-                            Some(x) => *x,
-                            None => user.user.upgrade().unwrap().lock().await.position,
-                        };
-                        for _ in 0..count {
-                            let enemy = zone
-                                .enemies
-                                .iter()
-                                .filter(|e| e.spawn_category == spawn_category)
-                                .choose(&mut rand::thread_rng());
-                            if let Some(enemy) = enemy {
-                                self.spawn_enemy(&enemy.enemy_name, spawn_point, zone_id)
-                                    .await?;
-                            }
+                    let pos = self
+                        .chunk_spawns
+                        .iter()
+                        .position(|s| s.zone_id == zone_id && s.chunk_id == chunk.chunk_id);
+                    let pos = match pos {
+                        Some(pos)
+                            if self.chunk_spawns[pos].spawned_at.elapsed() > *respawn_time =>
+                        {
+                            Some(pos)
+                        }
+                        Some(_) => None,
+                        None => {
+                            self.chunk_spawns.push(ChunkSpawn {
+                                zone_id,
+                                chunk_id: chunk.chunk_id,
+                                spawned_at: Instant::now(),
+                                enemies: vec![],
+                                empty_since: None,
+                            });
+                            Some(self.chunk_spawns.len() - 1)
                         }
+                    };
+                    if let Some(pos) = pos {
+                        let mut enemies = self
+                            .spawn_encounter(&zone, chunk, encounter, *min, *max, &user)
+                            .await?;
+                        let spawn = &mut self.chunk_spawns[pos];
+                        spawn.enemies.append(&mut enemies);
+                        // the respawn timer starts over with every wave
+                        spawn.spawned_at = Instant::now();
                     }
                 }
                 data_structs::map::EnemySpawnType::Manual => {
@@ -930,6 +1418,9 @@ impl Map {
             }
         }
 
+        self.store_reveal(sender_id, zone_id, packet.chunk_id)
+            .await?;
+
         if let Some(lua) = self.data.luas.get("on_minimap_reveal").cloned() {
             self.run_lua(user.player_id, zone_id, &packet, "on_minimap_reveal", &lua)
                 .await?;
@@ -939,6 +1430,7 @@ impl Map {
             }
             let to_move: Vec<_> = self.to_lobby_move.drain(..).collect();
             for player in to_move {
+                self.complete_quest(player);
                 self.move_to_lobby(player).await?;
             }
         };
@@ -980,6 +1472,19 @@ impl Map {
             .await?;
         Ok(())
     }
+    /// Returns the name of the NPC in the player's zone.
+    pub fn npc_name(&self, sender_id: PlayerId, npc_id: u32) -> Option<&str> {
+        let zone_id = self
+            .players
+            .iter()
+            .find(|p| p.player_id == sender_id)?
+            .zone_id;
+        self.data
+            .npcs
+            .iter()
+            .find(|n| n.zone_id == zone_id && n.data.object.id == npc_id)
+            .map(|n| n.data.name.as_str())
+    }
     pub async fn on_questwork(
         &mut self,
         player: PlayerId,
@@ -1026,6 +1531,7 @@ impl Map {
         }
         let to_move: Vec<_> = self.to_lobby_move.drain(..).collect();
         for player in to_move {
+            self.complete_quest(player);
             self.move_to_lobby(player).await?;
         }
         Ok(())
@@ -1050,10 +1556,46 @@ impl Map {
         }
         let to_move: Vec<_> = self.to_lobby_move.drain(..).collect();
         for player in to_move {
+            self.complete_quest(player);
             self.move_to_lobby(player).await?;
         }
         Ok(())
     }
+    /// Runs the `on_chat` hook for the map message. The hook can consume the message by returning
+    /// `false` or replace it by returning a string.
+    pub async fn on_chat<S: serde::Serialize + Sync>(
+        &mut self,
+        player: PlayerId,
+        packet: &S,
+    ) -> Result<ChatHook, Error> {
+        let Some(user) = self.players.iter().find(|p| p.player_id == player) else {
+            return Err(Error::NoUserInMap(
+                player,
+                self.data.map_data.unk7.to_string(),
+            ));
+        };
+        let zone_id = user.zone_id;
+        let Some(lua) = self.data.luas.get("on_chat").cloned() else {
+            return Ok(ChatHook::Pass);
+        };
+        let result = self
+            .run_lua(player, zone_id, packet, "on_chat", &lua)
+            .await?;
+        let to_move: Vec<_> = self.to_move.drain(..).collect();
+        for (player, zone) in to_move {
+            self.move_player_named(player, &zone).await?;
+        }
+        let to_move: Vec<_> = self.to_lobby_move.drain(..).collect();
+        for player in to_move {
+            self.complete_quest(player);
+            self.move_to_lobby(player).await?;
+        }
+        Ok(match result {
+            mlua::Value::Boolean(false) => ChatHook::Consume,
+            mlua::Value::String(message) => ChatHook::Replace(message.to_string_lossy()),
+            _ => ChatHook::Pass,
+        })
+    }
     pub fn get_close_objects<F>(&self, zone_id: ZoneId, pred: F) -> Vec<ObjectSpawnPacket>
     where
         F: Fn(&Position) -> bool,
@@ -1068,6 +1610,98 @@ impl Map {
         obj
     }
 
+    /// Returns the key of revealed chunks of the zone in character data.
+    fn minimap_key(&self, zone_id: ZoneId) -> Option<String> {
+        if self.name.is_empty() {
+            return None;
+        }
+        let zone = self.get_zone_name(zone_id)?;
+        Some(format!("{}/{zone}", self.name))
+    }
+    /// Stores the revealed chunk in the character. In quests the chunk is also stored for other
+    /// players of the map (party members), players in the same zone get it restored. Other maps
+    /// only lock the revealing player.
+    async fn store_reveal(
+        &mut self,
+        sender_id: PlayerId,
+        zone_id: ZoneId,
+        chunk_id: u32,
+    ) -> Result<(), Error> {
+        let Some(key) = self.minimap_key(zone_id) else {
+            return Ok(());
+        };
+        let share = matches!(self.map_type, MapType::QuestMap);
+        let mut restore = vec![];
+        for map_player in self
+            .players
+            .iter()
+            .filter(|p| share || p.player_id == sender_id)
+        {
+            let Some(user) = map_player.user.upgrade() else {
+                continue;
+            };
+            let mut player = user.lock().await;
+            let Some(character) = player.character.as_mut() else {
+                continue;
+            };
+            let is_new = character
+                .revealed_chunks
+                .entry(key.clone())
+                .or_default()
+                .insert(chunk_id);
+            if is_new && map_player.player_id != sender_id && map_player.zone_id == zone_id {
+                restore.push(map_player.player_id);
+            }
+        }
+        for player_id in restore {
+            self.restore_minimap(player_id, zone_id, Some(chunk_id))
+                .await?;
+        }
+        Ok(())
+    }
+    /// Passes revealed chunks of the zone (or only `chunk_id`) to the `on_minimap_restore` lua
+    /// hook, which sends them to the client.
+    async fn restore_minimap(
+        &mut self,
+        player_id: PlayerId,
+        zone_id: ZoneId,
+        chunk_id: Option<u32>,
+    ) -> Result<(), Error> {
+        let Some(lua) = self.data.luas.get("on_minimap_restore").cloned() else {
+            return Ok(());
+        };
+        let Some(key) = self.minimap_key(zone_id) else {
+            return Ok(());
+        };
+        let chunks = match chunk_id {
+            Some(chunk_id) => vec![chunk_id],
+            None => {
+                let Some(player) = self
+                    .players
+                    .iter()
+                    .find(|p| p.player_id == player_id)
+                    .and_then(|p| p.user.upgrade())
+                else {
+                    return Ok(());
+                };
+                let player = player.lock().await;
+                player
+                    .character
+                    .as_ref()
+                    .and_then(|c| c.revealed_chunks.get(&key))
+                    .map(|c| c.iter().copied().collect())
+                    .unwrap_or_default()
+            }
+        };
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        let packet = RevealedChunks { zone_id, chunks };
+        self.run_lua(player_id, zone_id, &packet, "on_minimap_restore", &lua)
+            .await?;
+        Ok(())
+    }
+    /// Runs the lua script and returns its result.
     async fn run_lua<S: serde::Serialize + Sync>(
         &mut self,
         sender_id: PlayerId,
@@ -1075,7 +1709,7 @@ impl Map {
         packet: &S,
         call_type: &str,
         lua_data: &str,
-    ) -> Result<(), Error> {
+    ) -> Result<mlua::Value, Error> {
         spawn_blocking(|| self.run_lua_blocking(sender_id, zone_id, packet, call_type, lua_data))
             .await?
     }
@@ -1086,9 +1720,10 @@ impl Map {
         packet: &S,
         call_type: &str,
         lua_data: &str,
-    ) -> Result<(), Error> {
+    ) -> Result<mlua::Value, Error> {
         let mut scheduled_move = vec![];
         let mut lobby_moves = vec![];
+        let mut quest_clears = vec![];
 
         let Some(caller) = self
             .players
@@ -1103,7 +1738,7 @@ impl Map {
             return Err(Error::InvalidInput("run_lua, zone"));
         };
         drop(caller_lock);
-        {
+        let result = {
             let lua = self.lua.lock();
             let globals = lua.globals();
             let player_ids: Vec<_> = self.players.iter().map(|p| p.player_id).collect();
@@ -1112,13 +1747,14 @@ impl Map {
             globals.set("sender", sender_id)?;
             globals.set("players", player_ids)?;
             globals.set("call_type", call_type)?;
-            lua.scope(|scope| {
+            let result = lua.scope(|scope| {
                 self.setup_scope(
                     &globals,
                     scope,
                     zone_id,
                     &mut scheduled_move,
                     &mut lobby_moves,
+                    &mut quest_clears,
                 )?;
 
                 /* LUA FUNCTIONS */
@@ -1141,26 +1777,36 @@ impl Map {
                         }
                     })?,
                 )?;
+                // get language code ("en" or "jp") for dialogue selection
+                globals.set(
+                    "get_language",
+                    scope.create_function_mut(|_, ()| -> Result<&'static str, _> {
+                        Ok(lang_code(caller.lock_blocking().user_data.lang))
+                    })?,
+                )?;
 
                 /* LUA FUNCTIONS END */
 
                 let chunk = lua.load(lua_data);
-                chunk.exec()?;
-                Ok(())
+                chunk.eval::<mlua::Value>()
             })?;
             globals.raw_remove("packet")?;
             globals.raw_remove("sender")?;
             globals.raw_remove("players")?;
             globals.raw_remove("call_type")?;
             globals.raw_remove("zone")?;
-        }
+            result
+        };
         for (receiver, mapid) in scheduled_move {
             self.to_move.push((receiver, mapid));
         }
         for receiver in lobby_moves {
             self.to_lobby_move.push(receiver);
         }
-        Ok(())
+        for receiver in quest_clears {
+            self.complete_quest(receiver);
+        }
+        Ok(result)
     }
 
     fn setup_scope<'s>(
@@ -1170,6 +1816,7 @@ impl Map {
         zone_id: ZoneId,
         scheduled_move: &'s mut Vec<(PlayerId, String)>,
         lobby_moves: &'s mut Vec<PlayerId>,
+        quest_clears: &'s mut Vec<PlayerId>,
     ) -> Result<(), mlua::Error> {
         /* LUA FUNCTIONS */
 
@@ -1364,6 +2011,14 @@ impl Map {
                 },
             )?,
         )?;
+        // mark the quest as cleared by the player
+        globals.set(
+            "clear_quest",
+            scope.create_function_mut(|_, receiver: u32| {
+                quest_clears.push(receiver);
+                Ok(())
+            })?,
+        )?;
 
         /* LUA FUNCTIONS END */
         Ok(())
@@ -1372,10 +2027,37 @@ impl Map {
 
 impl Drop for Map {
     fn drop(&mut self) {
-        log::trace!("Map {} dropped", self.data.map_data.map_object.id);
+        log::trace!("Map {} dropped", self.map_object.id);
+        crate::lifecycle::map_dropped();
+    }
+}
+
+fn is_in_range(range: Option<(Position, f32)>, position: &Position) -> bool {
+    match range {
+        Some((origin, max_dist)) => origin.dist_2d(position) <= max_dist,
+        None => true,
     }
 }
 
+/// Periodically despawns idle enemies and expired lobby props of the map and sends delayed
+/// equipment changes until it is dropped.
+pub fn start_despawn_task(map: &Arc<Mutex<Map>>) {
+    let map = Arc::downgrade(map);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let Some(map) = map.upgrade() else {
+                return;
+            };
+            let mut map = map.lock().await;
+            map.despawn_idle_enemies().await;
+            map.despawn_expired_props().await;
+            map.flush_equipment().await;
+        }
+    });
+}
+
 async fn exec_users<F>(users: &[MapPlayer], zone_id: ZoneId, mut f: F)
 where
     F: FnMut(OwnedMapPlayer, MutexGuard<User>) + Send,
@@ -1414,3 +2096,48 @@ where
     let func: Box<dyn FnOnce() -> R + Send + 'static> = unsafe { std::mem::transmute(val) };
     Ok(tokio::task::spawn_blocking(func).await?)
 }
+
+/// Picks enemies for an automatic spawn. A category is picked by its weight, then up to `count`
+/// enemies of that category are picked by their weights while they fit into the budget.
+fn roll_encounter<'a>(
+    enemies: &'a [EnemySpawn],
+    encounter: &Encounter,
+    count: u32,
+    rng: &mut impl Rng,
+) -> Vec<&'a EnemySpawn> {
+    let mut categories = encounter.categories.clone();
+    if categories.is_empty() {
+        for enemy in enemies {
+            if !categories
+                .iter()
+                .any(|c| c.category == enemy.spawn_category)
+            {
+                categories.push(SpawnCategoryWeight {
+                    category: enemy.spawn_category,
+                    weight: 1,
+                });
+            }
+        }
+    }
+    let Ok(category) = categories.choose_weighted(rng, |c| c.weight) else {
+        return vec![];
+    };
+    let candidates: Vec<_> = enemies
+        .iter()
+        .filter(|e| e.spawn_category == category.category)
+        .collect();
+    let mut budget = match encounter.budget {
+        0 => u32::MAX,
+        b => b,
+    };
+    let mut picked = vec![];
+    for _ in 0..count {
+        let affordable: Vec<_> = candidates.iter().filter(|e| e.cost <= budget).collect();
+        let Ok(enemy) = affordable.choose_weighted(rng, |e| e.weight) else {
+            break;
+        };
+        budget -= enemy.cost;
+        picked.push(**enemy);
+    }
+    picked
+}
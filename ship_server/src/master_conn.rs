@@ -1,18 +1,25 @@
 use crate::Error;
 use data_structs::master_ship::{
     MasterShipAction as MAS, MasterShipComm, RegisterShipResult, SerializerFormat, ShipConnection,
-    ShipInfo, ShipLogin, ShipLoginResult,
+    ShipInfo, ShipLogin, ShipLoginResult, SupportTicket,
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr},
     sync::atomic::AtomicU32,
+    time::Duration,
+};
+use tokio::sync::{
+    broadcast,
+    mpsc::{Receiver, Sender},
 };
-use tokio::sync::mpsc::{Receiver, Sender};
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 struct HostKeyStorage {
     keys: Vec<HostKey>,
+    /// Highest term of the primary master ship seen so far.
+    #[serde(default)]
+    master_term: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -25,12 +32,26 @@ struct MasterConnectionImpl {
     id: u32,
     conn: ShipConnection,
     receive_ch: Receiver<(MAS, Sender<MAS>)>,
+    broadcasts: broadcast::Sender<String>,
+    tickets: broadcast::Sender<SupportTicket>,
+    merges: broadcast::Sender<(u32, u32)>,
+    /// Addresses of master ships used for reconnection.
+    addrs: Vec<String>,
+    psk: Vec<u8>,
+    key_file: String,
+    /// Last registration request, repeated after reconnection.
+    ship_info: Option<ShipInfo>,
+    /// Highest term of the primary master ship seen so far.
+    term: u64,
 }
 
 pub struct MasterConnection {
     send_ch: Sender<(MAS, Sender<MAS>)>,
     local_addr: Ipv4Addr,
     ship_id: AtomicU32,
+    broadcasts: broadcast::Sender<String>,
+    tickets: broadcast::Sender<SupportTicket>,
+    merges: broadcast::Sender<(u32, u32)>,
 }
 
 fn hostkey_fingerprint(key: &[u8]) -> String {
@@ -54,77 +75,175 @@ fn ident_failure(fingerprint: &str) {
     log::warn!("SHA256:{fingerprint}");
 }
 
-impl MasterConnection {
-    pub async fn new(ip: SocketAddr, psk: &[u8], key_file: &str) -> Result<Self, Error> {
-        let socket = tokio::net::TcpStream::connect(ip).await?;
-        let IpAddr::V4(local_addr) = socket.local_addr()?.ip() else {
-            unimplemented!()
-        };
-        let mut hostkeys: HostKeyStorage = toml::from_str(
-            &tokio::fs::read_to_string(key_file)
-                .await
-                .unwrap_or_default(),
-        )
-        .unwrap_or_default();
-        let conn = ShipConnection::new_client(socket, |ip, key| {
-            let fingerprint = hostkey_fingerprint(key);
-            if let Some(host) = hostkeys.keys.iter().find(|d| d.ip == ip) {
-                match host.fingerprint == fingerprint {
-                    true => return true,
-                    false => {
-                        ident_failure(&fingerprint);
-                        return false;
-                    }
+/// Connects to the master ship. If `interactive` is false then unknown host keys are rejected
+/// without asking.
+async fn connect(
+    addr: &str,
+    key_file: &str,
+    interactive: bool,
+) -> Result<(ShipConnection, Ipv4Addr), Error> {
+    let socket = tokio::net::TcpStream::connect(addr).await?;
+    let IpAddr::V4(local_addr) = socket.local_addr()?.ip() else {
+        unimplemented!()
+    };
+    let mut hostkeys: HostKeyStorage = toml::from_str(
+        &tokio::fs::read_to_string(key_file)
+            .await
+            .unwrap_or_default(),
+    )
+    .unwrap_or_default();
+    let conn = ShipConnection::new_client(socket, |ip, key| {
+        let fingerprint = hostkey_fingerprint(key);
+        if let Some(host) = hostkeys.keys.iter().find(|d| d.ip == ip) {
+            match host.fingerprint == fingerprint {
+                true => return true,
+                false => {
+                    ident_failure(&fingerprint);
+                    return false;
                 }
             }
+        }
+        if !interactive {
+            // standby master ships may share the key of the primary
+            return hostkeys.keys.iter().any(|d| d.fingerprint == fingerprint);
+        }
+        log::warn!(
+            "The authenticity of master server '{}' can't be established.",
+            local_addr
+        );
+        log::warn!("Key fingerprint is SHA256:{fingerprint}",);
+        let confirm = dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
+            .with_prompt("Are you sure you want to continue connecting?")
+            .interact()
+            .unwrap();
+        if confirm {
+            hostkeys.keys.push(HostKey { ip, fingerprint });
             log::warn!(
-                "The authenticity of master server '{}' can't be established.",
+                "Permanently added '{}' to the list of known master ships.",
                 local_addr
             );
-            log::warn!("Key fingerprint is SHA256:{fingerprint}",);
-            let confirm =
-                dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                    .with_prompt("Are you sure you want to continue connecting?")
-                    .interact()
-                    .unwrap();
-            if confirm {
-                hostkeys.keys.push(HostKey { ip, fingerprint });
-                log::warn!(
-                    "Permanently added '{}' to the list of known master ships.",
-                    local_addr
-                );
-                true
-            } else {
-                false
+            true
+        } else {
+            false
+        }
+    })
+    .await?;
+    tokio::fs::write(key_file, toml::to_string_pretty(&hostkeys)?.as_bytes()).await?;
+    Ok((conn, local_addr))
+}
+
+/// Logs in to the master ship with the highest known `term`. Returns the term of the master ship.
+async fn login(conn: &mut ShipConnection, id: u32, psk: &[u8], term: u64) -> Result<u64, Error> {
+    let login = MAS::ShipLogin(ShipLogin {
+        psk: psk.to_vec(),
+        term,
+    });
+    conn.write(MasterShipComm { id, action: login }).await?;
+    let response = loop {
+        let response = conn.read_for(Duration::from_secs(10)).await?;
+        if response.id == id {
+            break response.action;
+        }
+    };
+    match response {
+        MAS::ShipLoginResult(ShipLoginResult::Ok(term)) => Ok(term),
+        MAS::ShipLoginResult(ShipLoginResult::UnknownShip) => Err(Error::MSInvalidPSK),
+        MAS::ShipLoginResult(ShipLoginResult::Revoked) => Err(Error::MSRevokedPSK),
+        MAS::ShipLoginResult(ShipLoginResult::Blocked) => Err(Error::MSBlocked),
+        MAS::ShipLoginResult(ShipLoginResult::Fenced) => Err(Error::MSFenced),
+        _ => Err(Error::MSUnexpected),
+    }
+}
+
+async fn load_term(key_file: &str) -> u64 {
+    let hostkeys: HostKeyStorage = toml::from_str(
+        &tokio::fs::read_to_string(key_file)
+            .await
+            .unwrap_or_default(),
+    )
+    .unwrap_or_default();
+    hostkeys.master_term
+}
+
+async fn save_term(key_file: &str, term: u64) -> Result<(), Error> {
+    let mut hostkeys: HostKeyStorage = toml::from_str(
+        &tokio::fs::read_to_string(key_file)
+            .await
+            .unwrap_or_default(),
+    )
+    .unwrap_or_default();
+    if hostkeys.master_term < term {
+        hostkeys.master_term = term;
+        tokio::fs::write(key_file, toml::to_string_pretty(&hostkeys)?.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+impl MasterConnection {
+    /// Connects to the first available master ship from `addrs` that wasn't replaced by a
+    /// standby.
+    pub async fn new(addrs: Vec<String>, psk: &[u8], key_file: &str) -> Result<Self, Error> {
+        let mut term = load_term(key_file).await;
+        let mut result = Err(Error::NoMasterShip);
+        for addr in &addrs {
+            result = match connect(addr, key_file, true).await {
+                Ok((mut conn, local_addr)) => login(&mut conn, 1, psk, term)
+                    .await
+                    .map(|t| (conn, local_addr, t)),
+                Err(e) => Err(e),
+            };
+            match &result {
+                Ok(_) => break,
+                // wrong keys are not fixed by trying another master ship
+                Err(Error::MSInvalidPSK | Error::MSRevokedPSK | Error::MSBlocked) => break,
+                Err(e) => log::warn!("Failed to connect to master ship {addr}: {e}"),
             }
-        })
-        .await?;
-        tokio::fs::write(
-            "hostkeys.toml",
-            toml::to_string_pretty(&hostkeys)?.as_bytes(),
-        )
-        .await?;
+        }
+        let (conn, local_addr, master_term) = result?;
+        if master_term > term {
+            term = master_term;
+            save_term(key_file, term).await?;
+        }
         let (send, recv) = tokio::sync::mpsc::channel(10);
+        let (broadcasts, _) = broadcast::channel(16);
+        let (tickets, _) = broadcast::channel(16);
+        let (merges, _) = broadcast::channel(16);
         let master_conn = Self {
             send_ch: send,
             local_addr,
             ship_id: 0.into(),
+            broadcasts: broadcasts.clone(),
+            tickets: tickets.clone(),
+            merges: merges.clone(),
         };
 
         let master_conn_impl = MasterConnectionImpl {
-            id: 1,
+            id: 2,
             conn,
             receive_ch: recv,
+            broadcasts,
+            tickets,
+            merges,
+            addrs,
+            psk: psk.to_vec(),
+            key_file: key_file.to_string(),
+            ship_info: None,
+            term,
         };
         tokio::spawn(async move { master_conn_impl.run_loop().await });
 
-        let response = master_conn
-            .run_action(MAS::ShipLogin(ShipLogin { psk: psk.to_vec() }))
-            .await?;
-        match response {
-            MAS::ShipLoginResult(ShipLoginResult::Ok) => Ok(master_conn),
-            MAS::ShipLoginResult(ShipLoginResult::UnknownShip) => Err(Error::MSInvalidPSK),
-            _ => Err(Error::MSUnexpected),
+        Ok(master_conn)
+    }
+    /// Creates a connection without a master ship for tests that only use the local database.
+    #[cfg(test)]
+    pub fn disconnected() -> Self {
+        Self {
+            send_ch: tokio::sync::mpsc::channel(1).0,
+            local_addr: Ipv4Addr::LOCALHOST,
+            ship_id: 0.into(),
+            broadcasts: broadcast::channel(16).0,
+            tickets: broadcast::channel(16).0,
+            merges: broadcast::channel(16).0,
         }
     }
     pub async fn run_action(&self, action: MAS) -> Result<MAS, Error> {
@@ -139,6 +258,18 @@ impl MasterConnection {
             None => Err(Error::MSNoResponse),
         }
     }
+    /// Subscribes to admin messages broadcasted by the master ship.
+    pub fn subscribe_broadcasts(&self) -> broadcast::Receiver<String> {
+        self.broadcasts.subscribe()
+    }
+    /// Subscribes to support ticket updates sent by the master ship.
+    pub fn subscribe_tickets(&self) -> broadcast::Receiver<SupportTicket> {
+        self.tickets.subscribe()
+    }
+    /// Subscribes to account merges as (from, into) pairs sent by the master ship.
+    pub fn subscribe_merges(&self) -> broadcast::Receiver<(u32, u32)> {
+        self.merges.subscribe()
+    }
     async fn try_format(&self, format: SerializerFormat) -> Result<bool, Error> {
         match self.run_action(MAS::SetFormat(format)).await? {
             MAS::Ok => Ok(true),
@@ -179,18 +310,41 @@ impl MasterConnectionImpl {
                         Ok(r) => r,
                         Err(e) => {
                             log::error!("Failed to receive data from a master server: {e}");
-                            return
+                            // pending requests will fail with no response
+                            channels.clear();
+                            self.reconnect().await;
+                            continue;
                         }
                     };
+                    // id 0 is reserved for messages initiated by the master ship
+                    if result.id == 0 {
+                        match result.action {
+                            MAS::Broadcast { message, .. } => {
+                                let _ = self.broadcasts.send(message);
+                            }
+                            MAS::TicketUpdate(ticket) => {
+                                let _ = self.tickets.send(ticket);
+                            }
+                            MAS::AccountsMerged { from, into } => {
+                                let _ = self.merges.send((from, into));
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
                     let Some((pos, _)) = channels.iter().enumerate().find(|(_, (id,_))| *id == result.id) else {
-                        log::error!("Master server sent unhandled response: {result:?}");
-                        return;
+                        // the request could have been dropped on a reconnect
+                        log::warn!("Master server sent a response to an unknown request: {result:?}");
+                        continue;
                     };
                     log::trace!("Master ship sent: {result:?}");
                     let (_, ch) = channels.swap_remove(pos);
                     let _ = ch.send(result.action).await;
                 },
                 Some((action, chan)) = self.receive_ch.recv() => {
+                    if let MAS::RegisterShip(info) = &action {
+                        self.ship_info = Some(info.clone());
+                    }
                     let id = self.id;
                     self.id += 1;
                     match self.conn.write(MasterShipComm { id, action }).await {
@@ -201,6 +355,64 @@ impl MasterConnectionImpl {
             }
         }
     }
+    /// Reconnects to any of the known master ships, retrying until it succeeds.
+    async fn reconnect(&mut self) {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            for addr in self.addrs.clone() {
+                match self.try_reconnect(&addr).await {
+                    Ok(_) => {
+                        log::info!("Reconnected to master ship {addr}");
+                        return;
+                    }
+                    Err(e) => log::warn!("Failed to reconnect to master ship {addr}: {e}"),
+                }
+            }
+            // reject requests until the connection is restored
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    Some(_) = self.receive_ch.recv() => {}
+                }
+            }
+            delay = (delay * 2).min(Duration::from_secs(30));
+        }
+    }
+    async fn try_reconnect(&mut self, addr: &str) -> Result<(), Error> {
+        let (mut conn, local_addr) = connect(addr, &self.key_file, false).await?;
+        let id = self.id;
+        self.id += 1;
+        let term = login(&mut conn, id, &self.psk, self.term).await?;
+        if term > self.term {
+            self.term = term;
+            save_term(&self.key_file, term).await?;
+        }
+        if let Some(mut info) = self.ship_info.clone() {
+            info.ip = local_addr;
+            match self.request(&mut conn, MAS::RegisterShip(info)).await? {
+                MAS::RegisterShipResult(RegisterShipResult::Success) => {}
+                MAS::RegisterShipResult(RegisterShipResult::AlreadyTaken) => {
+                    return Err(Error::MSError("Ship id is already taken".into()))
+                }
+                _ => return Err(Error::MSUnexpected),
+            }
+        }
+        self.conn = conn;
+        Ok(())
+    }
+    async fn request(&mut self, conn: &mut ShipConnection, action: MAS) -> Result<MAS, Error> {
+        let id = self.id;
+        self.id += 1;
+        conn.write(MasterShipComm { id, action }).await?;
+        loop {
+            let response = conn.read_for(Duration::from_secs(10)).await?;
+            if response.id == id {
+                return Ok(response.action);
+            }
+        }
+    }
 }
 
 impl Drop for MasterConnection {
@@ -0,0 +1,125 @@
+//! Mission pass progress of accounts. Progress is kept in the account key-value store of the
+//! master ship, so it's shared by all characters and ships.
+use crate::{
+    directory::PlayerDirectory,
+    events::{self, EventBus, GameEvent},
+    user::handlers,
+};
+use data_structs::{missionpass::MissionPassSeason, reward::Reward};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Account key-value store namespace of the progress. Keys are season ids.
+pub const NAMESPACE: &str = "mission_pass";
+
+/// Mission pass progress of an account in one season.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassProgress {
+    pub stars: u32,
+    /// Set if the gold pass was bought.
+    pub gold: bool,
+    /// Tiers with claimed rewards.
+    pub claimed: Vec<u32>,
+    /// Tiers with claimed gold rewards.
+    pub gold_claimed: Vec<u32>,
+}
+
+impl PassProgress {
+    /// Adds the stars and returns the number of tiers reached with them.
+    pub fn add_stars(&mut self, season: &MissionPassSeason, stars: u32) -> u32 {
+        let old_tier = season.tier_at(self.stars);
+        self.stars = self.stars.saturating_add(stars);
+        season.tier_at(self.stars) - old_tier
+    }
+    /// Marks reached tiers as claimed and returns their rewards.
+    pub fn take_rewards(&mut self, season: &MissionPassSeason) -> Vec<Reward> {
+        let mut rewards = vec![];
+        for index in 0..season.tier_at(self.stars) {
+            let Some(tier) = season.tier(index) else {
+                continue;
+            };
+            if !self.claimed.contains(&index) {
+                self.claimed.push(index);
+                rewards.extend_from_slice(&tier.rewards);
+            }
+            if self.gold && !self.gold_claimed.contains(&index) {
+                self.gold_claimed.push(index);
+                rewards.extend_from_slice(&tier.gold_rewards);
+            }
+        }
+        rewards
+    }
+    /// Checks if any reached tier has unclaimed rewards.
+    pub fn has_rewards(&self, season: &MissionPassSeason) -> bool {
+        self.clone()
+            .take_rewards(season)
+            .into_iter()
+            .next()
+            .is_some()
+    }
+}
+
+/// Returns the season running at the time.
+pub fn current_season(seasons: &[MissionPassSeason], now: u64) -> Option<&MissionPassSeason> {
+    seasons.iter().find(|s| s.is_active(now))
+}
+
+/// Returns the last season that ended before the time.
+pub fn last_season(seasons: &[MissionPassSeason], now: u64) -> Option<&MissionPassSeason> {
+    seasons
+        .iter()
+        .filter(|s| s.end <= now)
+        .max_by_key(|s| s.end)
+}
+
+/// Starts giving stars to online players.
+pub fn subscribe(bus: &EventBus, directory: Arc<PlayerDirectory>) {
+    events::spawn_user_subscriber(
+        bus,
+        "missionpass",
+        directory,
+        |event| {
+            matches!(
+                event,
+                GameEvent::QuestCleared { .. } | GameEvent::LevelUp { .. }
+            )
+        },
+        |user, event| Box::pin(handlers::missionpass::on_event(user, event)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{current_season, PassProgress};
+    use data_structs::{
+        missionpass::{MissionPassSeason, PassTier},
+        reward::Reward,
+    };
+
+    #[test]
+    fn test_pass_rewards() {
+        let tier = PassTier {
+            rewards: vec![Reward::default()],
+            gold_rewards: vec![Reward::default(); 2],
+        };
+        let season = MissionPassSeason {
+            start: 0,
+            end: 100,
+            stars_per_tier: 10,
+            tiers: vec![tier; 3],
+            ..Default::default()
+        };
+        let mut progress = PassProgress::default();
+        assert_eq!(progress.add_stars(&season, 5), 0);
+        assert!(!progress.has_rewards(&season));
+        assert_eq!(progress.add_stars(&season, 20), 2);
+        assert_eq!(progress.take_rewards(&season).len(), 2);
+        assert!(progress.take_rewards(&season).is_empty());
+        // gold rewards of already reached tiers become claimable
+        progress.gold = true;
+        assert_eq!(progress.take_rewards(&season).len(), 4);
+        assert_eq!(progress.claimed, vec![0, 1]);
+        assert!(current_season(&[season], 100).is_none());
+    }
+}
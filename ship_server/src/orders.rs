@@ -0,0 +1,130 @@
+//! Client orders accepted from NPCs.
+use crate::{
+    directory::PlayerDirectory,
+    events::{self, EventBus, GameEvent},
+    user::handlers,
+};
+use data_structs::order::{ClientOrder, OrderKind};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+/// Maximum number of simultaneously accepted orders of a character.
+pub const MAX_ACTIVE: usize = 10;
+
+/// Client order progress of a character.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientOrders {
+    pub active: Vec<ActiveOrder>,
+    /// Turned in order ids.
+    pub completed: Vec<u32>,
+}
+
+/// Accepted order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveOrder {
+    pub id: u32,
+    /// Number of killed enemies.
+    pub progress: u16,
+}
+
+/// Finds the order and the name of the NPC giving it.
+pub fn find(orders: &HashMap<String, Vec<ClientOrder>>, id: u32) -> Option<(&str, &ClientOrder)> {
+    orders
+        .iter()
+        .find_map(|(npc, o)| o.iter().find(|o| o.id == id).map(|o| (npc.as_str(), o)))
+}
+
+impl ClientOrders {
+    pub fn is_active(&self, id: u32) -> bool {
+        self.active.iter().any(|o| o.id == id)
+    }
+    /// Counts the kill for accepted orders and returns orders that have become ready to turn in.
+    pub fn record_kill<'a>(
+        &mut self,
+        enemy: &str,
+        orders: &'a HashMap<String, Vec<ClientOrder>>,
+    ) -> Vec<&'a ClientOrder> {
+        let mut ready = vec![];
+        for active in &mut self.active {
+            let Some((_, order)) = find(orders, active.id) else {
+                continue;
+            };
+            if !order.counts_kill(enemy) || active.progress >= order.amount {
+                continue;
+            }
+            active.progress += 1;
+            if active.progress == order.amount {
+                ready.push(order);
+            }
+        }
+        ready
+    }
+    /// Removes the order if its kill requirement is met. Collect orders are always removed, their
+    /// items are checked by the caller.
+    pub fn take_ready(&mut self, order: &ClientOrder) -> Option<ActiveOrder> {
+        let pos = self.active.iter().position(|o| o.id == order.id)?;
+        if order.kind == OrderKind::Kill && self.active[pos].progress < order.amount {
+            return None;
+        }
+        let active = self.active.remove(pos);
+        if !self.completed.contains(&order.id) {
+            self.completed.push(order.id);
+        }
+        Some(active)
+    }
+}
+
+/// Starts counting enemy kills of online players for their accepted orders.
+pub fn subscribe(bus: &EventBus, directory: Arc<PlayerDirectory>) {
+    events::spawn_user_subscriber(
+        bus,
+        "orders",
+        directory,
+        |event| matches!(event, GameEvent::EnemyDefeated { .. }),
+        |user, event| Box::pin(handlers::orders::on_event(user, event)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActiveOrder, ClientOrders};
+    use data_structs::order::{ClientOrder, OrderKind};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_order_progress() {
+        let kill = ClientOrder {
+            id: 1,
+            kind: OrderKind::Kill,
+            enemy: "Falspawn".into(),
+            amount: 2,
+            ..Default::default()
+        };
+        let collect = ClientOrder {
+            id: 2,
+            kind: OrderKind::Collect,
+            amount: 5,
+            ..Default::default()
+        };
+        let orders = HashMap::from([("Npc".to_string(), vec![kill.clone(), collect.clone()])]);
+        let mut progress = ClientOrders {
+            active: vec![
+                ActiveOrder { id: 1, progress: 0 },
+                ActiveOrder { id: 2, progress: 0 },
+            ],
+            ..Default::default()
+        };
+        assert!(progress.record_kill("Falspawn", &orders).is_empty());
+        assert!(progress.take_ready(&kill).is_none());
+        assert!(progress.record_kill("Soldier Ant", &orders).is_empty());
+        assert_eq!(progress.record_kill("Falspawn", &orders)[0].id, 1);
+        // already finished
+        assert!(progress.record_kill("Falspawn", &orders).is_empty());
+        assert_eq!(progress.active[0].progress, 2);
+        assert!(progress.take_ready(&kill).is_some());
+        assert!(progress.take_ready(&collect).is_some());
+        assert!(progress.active.is_empty());
+        assert_eq!(progress.completed, vec![1, 2]);
+    }
+}
@@ -1,14 +1,15 @@
 use crate::{inventory::Inventory, Error};
 use pso2packetlib::protocol::{
-    items::{ChangeWeaponPalettePacket, EquipedWeaponPacket, Item},
+    items::{ChangeWeaponPalettePacket, EquipedWeaponPacket, Item, ItemType},
     palette::{
-        FullPaletteInfoPacket, LoadPalettePacket, NewDefaultPAsPacket, SetDefaultPAsPacket,
-        SetPalettePacket, SetSubPalettePacket, SubPalette, UpdatePalettePacket,
-        UpdateSubPalettePacket, WeaponPalette,
+        FullPaletteInfoPacket, LoadPalettePacket, NewDefaultPAsPacket, PalettePA,
+        SetDefaultPAsPacket, SetPalettePacket, SetSubPalettePacket, SubPalette,
+        UpdatePalettePacket, UpdateSubPalettePacket, WeaponPalette,
     },
     ObjectHeader, Packet,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -19,6 +20,30 @@ pub struct Palette {
     palettes: [WeaponPalette; 6],
     subpalettes: [SubPalette; 6],
     default_pas: Vec<u32>,
+    learned_pas: Vec<LearnedPA>,
+}
+
+/// Snapshot of palette contents (used in loadouts).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaletteLayout {
+    pub cur_palette: u32,
+    pub cur_subpalette: u32,
+    pub palettes: [WeaponPalette; 6],
+    pub subpalettes: [SubPalette; 6],
+}
+
+/// Photon art or technique known by the character.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LearnedPA {
+    /// Weapon category (item category) this PA can be used with.
+    pub weapon: u16,
+    pub id: u8,
+    pub category: u8,
+    pub unk: u8,
+    /// Highest learned level.
+    pub level: u8,
 }
 
 impl Palette {
@@ -46,17 +71,21 @@ impl Palette {
             default: self.default_pas.clone().into(),
         })
     }
-    pub fn set_palette(&mut self, packet: SetPalettePacket) -> Result<(), Error> {
+    pub fn set_palette(&mut self, inv: &Inventory, packet: SetPalettePacket) -> Result<(), Error> {
         if packet.palette > 5 {
             return Err(Error::InvalidInput("set_palette"));
         }
+        let uuid = self.palettes[packet.palette as usize].uuid;
+        if uuid != 0 && get_weapon_category(inv, uuid).is_none() {
+            return Err(Error::InvalidInput("set_palette"));
+        }
         self.cur_palette = packet.palette;
         Ok(())
     }
-    pub fn set_palette_data(&mut self, id: u32, palette: WeaponPalette) {
+    pub const fn set_palette_data(&mut self, id: u32, palette: WeaponPalette) {
         self.palettes[id as usize] = palette;
     }
-    pub fn set_subpalette_data(&mut self, palettes: [SubPalette; 6]) {
+    pub const fn set_subpalette_data(&mut self, palettes: [SubPalette; 6]) {
         self.subpalettes = palettes;
     }
     pub fn send_change_palette(&self, playerid: u32) -> Packet {
@@ -70,6 +99,10 @@ impl Palette {
             ..Default::default()
         })
     }
+    /// Replaces weapon UUIDs after the inventory was renumbered.
+    pub fn remap_uuids(&mut self, uuids: &HashMap<u64, u64>) {
+        remap_palettes(&mut self.palettes, uuids);
+    }
     pub fn send_cur_weapon(&self, playerid: u32, inv: &Inventory) -> Packet {
         let uuid = self.palettes[self.cur_palette as usize].uuid;
         Packet::EquipedWeapon(EquipedWeaponPacket {
@@ -89,6 +122,9 @@ impl Palette {
         if packet.cur_palette > 5 {
             return Err(Error::InvalidInput("update_palette"));
         }
+        for palette in &packet.palettes {
+            self.validate_weapon_palette(inv, palette)?;
+        }
         let old = &self.palettes;
         let item = old[self.cur_palette as usize].uuid;
         if item != 0 {
@@ -105,10 +141,13 @@ impl Palette {
         if packet.cur_subpalette > 5 || packet.cur_book > 1 {
             return Err(Error::InvalidInput("update_subpalette"));
         }
+        for subpalette in &packet.subpalettes {
+            self.validate_subpalette(subpalette)?;
+        }
         self.subpalettes = packet.subpalettes;
         Ok(self.send_palette())
     }
-    pub fn set_subpalette(&mut self, packet: SetSubPalettePacket) -> Result<(), Error> {
+    pub const fn set_subpalette(&mut self, packet: SetSubPalettePacket) -> Result<(), Error> {
         if packet.subpalette > 5 {
             return Err(Error::InvalidInput("set_subpalette"));
         }
@@ -130,4 +169,178 @@ impl Palette {
             Ok(None)
         }
     }
+    /// Adds a PA to the list of learned PAs or raises its level.
+    pub fn learn_pa(&mut self, weapon: u16, pa: &PalettePA) {
+        if pa.category == 0 {
+            return;
+        }
+        match self.learned_pas.iter_mut().find(|l| {
+            l.weapon == weapon && l.id == pa.id && l.category == pa.category && l.unk == pa.unk
+        }) {
+            Some(learned) => learned.level = learned.level.max(pa.level),
+            None => self.learned_pas.push(LearnedPA {
+                weapon,
+                id: pa.id,
+                category: pa.category,
+                unk: pa.unk,
+                level: pa.level,
+            }),
+        }
+    }
+    /// Learns the PA from a disk. Returns `false` if the PA is already known at this or a higher
+    /// level.
+    pub fn learn_from_disk(&mut self, weapon: u16, pa: &PalettePA) -> bool {
+        if pa.category == 0 || self.is_learned(Some(weapon), pa) {
+            return false;
+        }
+        self.learn_pa(weapon, pa);
+        true
+    }
+    /// Populates learned PAs from the current palettes if none are known yet (i.e. new or old
+    /// characters).
+    pub fn init_learned(&mut self, inv: &Inventory) {
+        if !self.learned_pas.is_empty() {
+            return;
+        }
+        for palette in self.palettes.clone() {
+            let Some(weapon) = get_weapon_category(inv, palette.uuid) else {
+                continue;
+            };
+            for pa in iter_weapon_pas(&palette) {
+                self.learn_pa(weapon, pa);
+            }
+        }
+        for subpalette in self.subpalettes.clone() {
+            for pa in &subpalette.items {
+                self.learn_pa(0, pa);
+            }
+        }
+    }
+    pub fn get_layout(&self) -> PaletteLayout {
+        PaletteLayout {
+            cur_palette: self.cur_palette,
+            cur_subpalette: self.cur_subpalette,
+            palettes: self.palettes.clone(),
+            subpalettes: self.subpalettes.clone(),
+        }
+    }
+    pub fn validate_layout(&self, inv: &Inventory, layout: &PaletteLayout) -> Result<(), Error> {
+        if layout.cur_palette > 5 || layout.cur_subpalette > 5 {
+            return Err(Error::InvalidInput("validate_layout"));
+        }
+        for palette in &layout.palettes {
+            self.validate_weapon_palette(inv, palette)?;
+        }
+        for subpalette in &layout.subpalettes {
+            self.validate_subpalette(subpalette)?;
+        }
+        Ok(())
+    }
+    pub fn apply_layout(
+        &mut self,
+        inv: &mut Inventory,
+        layout: PaletteLayout,
+    ) -> Result<(), Error> {
+        self.validate_layout(inv, &layout)?;
+        let item = self.palettes[self.cur_palette as usize].uuid;
+        if item != 0 {
+            inv.unequip_item(item)?;
+        }
+        self.cur_palette = layout.cur_palette;
+        self.cur_subpalette = layout.cur_subpalette;
+        self.palettes = layout.palettes;
+        self.subpalettes = layout.subpalettes;
+        let item = self.palettes[self.cur_palette as usize].uuid;
+        if item != 0 {
+            inv.equip_item(item, 9)?;
+        }
+        Ok(())
+    }
+    pub fn get_learned_pas(&self) -> &[LearnedPA] {
+        &self.learned_pas
+    }
+    fn is_learned(&self, weapon: Option<u16>, pa: &PalettePA) -> bool {
+        if pa.category == 0 {
+            return true;
+        }
+        self.learned_pas.iter().any(|l| {
+            weapon.map(|w| l.weapon == w).unwrap_or(true)
+                && l.id == pa.id
+                && l.category == pa.category
+                && l.unk == pa.unk
+                && l.level >= pa.level
+        })
+    }
+    fn validate_weapon_palette(
+        &self,
+        inv: &Inventory,
+        palette: &WeaponPalette,
+    ) -> Result<(), Error> {
+        if palette.uuid == 0 {
+            // empty palettes can't have anything assigned
+            if iter_weapon_pas(palette).any(|pa| pa.category != 0) {
+                return Err(Error::InvalidInput("validate_weapon_palette"));
+            }
+            return Ok(());
+        }
+        let weapon = get_weapon_category(inv, palette.uuid)
+            .ok_or(Error::InvalidInput("validate_weapon_palette"))?;
+        if !iter_weapon_pas(palette).all(|pa| self.is_learned(Some(weapon), pa)) {
+            return Err(Error::InvalidInput("validate_weapon_palette"));
+        }
+        Ok(())
+    }
+    fn validate_subpalette(&self, subpalette: &SubPalette) -> Result<(), Error> {
+        if !subpalette.items.iter().all(|pa| self.is_learned(None, pa)) {
+            return Err(Error::InvalidInput("validate_subpalette"));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn remap_palettes(palettes: &mut [WeaponPalette], uuids: &HashMap<u64, u64>) {
+    for palette in palettes {
+        if let Some(&uuid) = uuids.get(&palette.uuid) {
+            palette.uuid = uuid;
+        }
+    }
+}
+
+fn get_weapon_category(inv: &Inventory, uuid: u64) -> Option<u16> {
+    let item = inv.get_inv_item(uuid).ok()?;
+    match item.data {
+        ItemType::Weapon(_) => Some(item.id.id),
+        _ => None,
+    }
+}
+
+fn iter_weapon_pas(palette: &WeaponPalette) -> impl Iterator<Item = &PalettePA> {
+    [&palette.unk2, &palette.unk3, &palette.unk4]
+        .into_iter()
+        .chain(palette.skills.iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Palette;
+    use pso2packetlib::protocol::palette::PalettePA;
+
+    #[test]
+    fn test_learn_from_disk() {
+        let pa = |level| PalettePA {
+            id: 1,
+            category: 2,
+            level,
+            ..Default::default()
+        };
+        let mut palette = Palette::default();
+        assert!(palette.learn_from_disk(5, &pa(3)));
+        assert!(!palette.learn_from_disk(5, &pa(2)));
+        assert!(!palette.learn_from_disk(5, &pa(3)));
+        assert!(palette.learn_from_disk(5, &pa(4)));
+        // learned separately for each weapon category
+        assert!(palette.learn_from_disk(6, &pa(1)));
+        assert_eq!(palette.get_learned_pas().len(), 2);
+        assert_eq!(palette.get_learned_pas()[0].level, 4);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::{
+    alliance::Alliance,
     invites::PartyInvite,
     map::Map,
     mutex::{Mutex, MutexGuard, RwLock},
@@ -11,10 +12,70 @@ use pso2packetlib::protocol::{
     ObjectHeader, ObjectType, Packet,
 };
 use std::{
-    sync::{Arc, Weak},
-    time::{SystemTime, UNIX_EPOCH},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
 };
 
+const CAMPSHIP_ZONE: &str = "campship";
+const CAMPSHIP_DOWN_ZONE: &str = "campship_down";
+/// Time after which ready players launch without waiting for the rest of the campship.
+pub const LAUNCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time after which a player that didn't arrive to the new block is removed from the party.
+const BLOCK_SWITCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Ship-wide party registry shared by all blocks.
+#[derive(Default)]
+pub struct Parties {
+    latest_id: AtomicU32,
+    /// Parties of players that are switching blocks and the time when they left.
+    switching: parking_lot::Mutex<HashMap<u32, (Arc<RwLock<Party>>, Instant)>>,
+}
+
+/// Location of a party member.
+pub struct MemberLocation {
+    pub id: u32,
+    /// Character name. Empty if the member is switching blocks.
+    pub char_name: String,
+    /// Block name and map with the zone. `None` if the member is switching blocks.
+    pub location: Option<(String, Arc<Mutex<Map>>, u32)>,
+}
+
+impl Parties {
+    pub fn new_party_id(&self) -> u32 {
+        self.latest_id.fetch_add(1, Ordering::Relaxed)
+    }
+    /// Keeps the party membership of a player that left the block to switch to another one.
+    pub fn start_switch(self: &Arc<Self>, player_id: u32, party: Arc<RwLock<Party>>) {
+        let left_at = Instant::now();
+        self.switching.lock().insert(player_id, (party, left_at));
+        let parties = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(BLOCK_SWITCH_TIMEOUT).await;
+            let party = {
+                let mut switching = parties.switching.lock();
+                match switching.get(&player_id) {
+                    Some((_, time)) if *time == left_at => switching.remove(&player_id),
+                    _ => None,
+                }
+            };
+            if let Some((party, _)) = party {
+                let _ = party.write().await.remove_player(player_id).await;
+            }
+        });
+    }
+    /// Returns the party of the player that has switched blocks.
+    pub fn finish_switch(&self, player_id: u32) -> Option<Arc<RwLock<Party>>> {
+        self.switching
+            .lock()
+            .remove(&player_id)
+            .map(|(party, _)| party)
+    }
+}
+
 pub struct Party {
     id: ObjectHeader,
     leader: ObjectHeader,
@@ -24,16 +85,24 @@ pub struct Party {
     settings: party::PartySettingsPacket,
     questname: String,
     quest: Option<PartyQuest>,
+    /// Alliance sharing quest maps with the party.
+    alliance: Option<Arc<Alliance>>,
+    /// Party that invited this party to its alliance.
+    alliance_invite: Option<Weak<RwLock<Party>>>,
+    /// Party of the alliance that offered its quest to this party.
+    alliance_quest: Option<Weak<RwLock<Party>>>,
 }
 
 impl Drop for Party {
     fn drop(&mut self) {
         log::trace!("Party {} dropped", self.id.id);
+        crate::lifecycle::party_dropped();
     }
 }
 impl Party {
     pub fn new(partyid: u32) -> Self {
         log::trace!("Party {partyid} created");
+        crate::lifecycle::party_created();
         Self {
             id: ObjectHeader {
                 id: partyid,
@@ -46,6 +115,9 @@ impl Party {
             settings: Default::default(),
             questname: String::new(),
             quest: None,
+            alliance: None,
+            alliance_invite: None,
+            alliance_quest: None,
         }
     }
     fn add_color(&mut self, id: u32) -> Color {
@@ -77,6 +149,106 @@ impl Party {
         new_user.lock().await.party = Some(Arc::new(RwLock::new(party)));
         Ok(())
     }
+    /// Adds the player that switched blocks back to the party. If the player was removed from the
+    /// party in the meantime then a new party is created.
+    pub async fn rejoin(
+        user: Arc<Mutex<User>>,
+        party: Arc<RwLock<Party>>,
+        parties: &Parties,
+    ) -> Result<(), Error> {
+        let player_id = user.lock().await.get_user_id();
+        let mut lock = party.write().await;
+        if !lock.players.iter().any(|(id, _)| *id == player_id) {
+            drop(lock);
+            return Self::init_player(user, parties.new_party_id()).await;
+        }
+        let leader = lock.leader;
+        // the old entry refers to the user on the previous block
+        lock.remove_player(player_id).await?;
+        lock.add_player(user.clone()).await?;
+        if leader.id == player_id && lock.leader.id != player_id {
+            lock.change_leader(leader).await?;
+        }
+        drop(lock);
+        user.lock().await.party = Some(party);
+        Ok(())
+    }
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+    pub fn on_quest(&self) -> bool {
+        self.quest.is_some()
+    }
+    pub const fn party_id(&self) -> u32 {
+        self.id.id
+    }
+    pub const fn leader_id(&self) -> u32 {
+        self.leader.id
+    }
+    pub fn alliance(&self) -> Option<Arc<Alliance>> {
+        self.alliance.clone()
+    }
+    pub fn set_alliance(&mut self, alliance: Option<Arc<Alliance>>) {
+        self.alliance = alliance;
+    }
+    pub fn set_alliance_invite(&mut self, party: &Arc<RwLock<Self>>) {
+        self.alliance_invite = Some(Arc::downgrade(party));
+    }
+    /// Takes the pending alliance invite if the inviting party still exists.
+    pub fn take_alliance_invite(&mut self) -> Option<Arc<RwLock<Self>>> {
+        self.alliance_invite.take().and_then(|p| p.upgrade())
+    }
+    pub fn set_alliance_quest(&mut self, party: &Arc<RwLock<Self>>) {
+        self.alliance_quest = Some(Arc::downgrade(party));
+    }
+    /// Takes the pending quest offer if the offering party still exists.
+    pub fn take_alliance_quest(&mut self) -> Option<Arc<RwLock<Self>>> {
+        self.alliance_quest.take().and_then(|p| p.upgrade())
+    }
+    /// Returns a handle to the quest for another party of the alliance.
+    pub fn share_quest(&self) -> Option<PartyQuest> {
+        self.quest.as_ref().map(PartyQuest::share)
+    }
+    /// Sends the system message to all party members.
+    pub async fn send_system_msg(&self, msg: &str) {
+        exec_users(&self.players, |_, mut player| {
+            let _ = player.try_send_system_msg(msg);
+        })
+        .await;
+    }
+    /// Returns locations of all party members.
+    pub async fn member_locations(&self) -> Vec<MemberLocation> {
+        let mut locations = vec![];
+        for (id, user) in &self.players {
+            let Some(user) = user.upgrade() else {
+                locations.push(MemberLocation {
+                    id: *id,
+                    char_name: String::new(),
+                    location: None,
+                });
+                continue;
+            };
+            let user = user.lock().await;
+            let char_name = user
+                .character
+                .as_ref()
+                .map(|c| c.character.name.clone())
+                .unwrap_or_default();
+            let location = user.get_current_map().map(|map| {
+                (
+                    user.get_blockdata().block_name.clone(),
+                    map,
+                    user.get_zone_id(),
+                )
+            });
+            locations.push(MemberLocation {
+                id: *id,
+                char_name,
+                location,
+            });
+        }
+        locations
+    }
     // called by block
     pub async fn add_player(&mut self, new_id: Arc<Mutex<User>>) -> Result<(), Error> {
         if self.players.len() >= 4 {
@@ -213,7 +385,8 @@ impl Party {
             )
         };
         let mut invitee = invitee.lock().await;
-        if invitee.party_ignore == party::RejectStatus::Reject {
+        if invitee.party_ignore == party::RejectStatus::Reject || invitee.is_blacklisted(inviter_id)
+        {
             return Ok(());
         }
         for invite_id in invitee
@@ -241,10 +414,7 @@ impl Party {
         invitee.party_invites.push(PartyInvite {
             id: party.id.id,
             party: Arc::downgrade(&target_party),
-            invite_time: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as u32,
+            invite_time: invitee.blockdata.clock.now_secs(),
         });
         Ok(())
     }
@@ -525,8 +695,9 @@ impl Party {
         })
         .await;
         for (id, player) in players {
+            // players switching blocks are removed as well
+            let _ = self.remove_player(id).await;
             if let Some(player) = player.upgrade() {
-                let _ = self.remove_player(id).await;
                 let _ = Self::init_player(player.clone(), partyid).await;
             }
         }
@@ -552,9 +723,7 @@ impl Party {
         .await;
         self.remove_player(kick_id).await?;
         if let Some(player) = kicked_player.upgrade() {
-            let party_id = block
-                .latest_partyid
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let party_id = block.parties.new_party_id();
             player.lock().await.party = None;
             Self::init_player(player, party_id).await?;
         }
@@ -606,8 +775,110 @@ impl Party {
     pub fn get_quest_map(&self) -> Option<Arc<Mutex<Map>>> {
         self.quest.as_ref().map(|q| q.get_map())
     }
+    /// Marks the player as ready at the campship launch console. Players are sent down when all
+    /// party members in the campship are ready or when [`LAUNCH_TIMEOUT`] passes. Returns the
+    /// start time of the wait if the player is the first one to be ready.
+    pub async fn set_launch_ready(&mut self, id: u32) -> Result<Option<Instant>, Error> {
+        let Some(quest) = &mut self.quest else {
+            return Err(Error::InvalidInput("set_launch_ready"));
+        };
+        let map = quest.get_map();
+        let campship_zone = map.lock().await.get_zone_id_named(CAMPSHIP_ZONE);
+        let Some(campship_zone) = campship_zone else {
+            // no campship in this quest, launch immediately
+            map.lock()
+                .await
+                .move_player_named(id, CAMPSHIP_DOWN_ZONE)
+                .await?;
+            return Ok(None);
+        };
+        let started = match quest.launch_started {
+            Some(_) if !quest.launch_ready.is_empty() => None,
+            _ => {
+                let now = Instant::now();
+                quest.launch_started = Some(now);
+                Some(now)
+            }
+        };
+        if !quest.launch_ready.contains(&id) {
+            quest.launch_ready.push(id);
+        }
+        let waiting = self.launch_ready_players(campship_zone, false).await?;
+        Ok(started.filter(|_| waiting))
+    }
+    /// Sends down the ready players if they are still waiting since `started`.
+    pub async fn launch_timeout(&mut self, started: Instant) -> Result<(), Error> {
+        let Some(quest) = &self.quest else {
+            return Ok(());
+        };
+        if quest.launch_started != Some(started) {
+            return Ok(());
+        }
+        let campship_zone = quest
+            .get_map()
+            .lock()
+            .await
+            .get_zone_id_named(CAMPSHIP_ZONE);
+        if let Some(campship_zone) = campship_zone {
+            self.launch_ready_players(campship_zone, true).await?;
+        }
+        Ok(())
+    }
+    /// Moves ready players down if all members in the campship are ready (or if `force` is set).
+    /// Members outside of the campship are not waited for. Returns `true` if players are still
+    /// waiting.
+    async fn launch_ready_players(
+        &mut self,
+        campship_zone: u32,
+        force: bool,
+    ) -> Result<bool, Error> {
+        let Some(quest) = &mut self.quest else {
+            return Ok(false);
+        };
+        let map = quest.get_map();
+        let mut in_campship = vec![];
+        for (player_id, user) in self
+            .players
+            .iter()
+            .filter_map(|(i, p)| p.upgrade().map(|p| (*i, p)))
+        {
+            let lock = user.lock().await;
+            if lock.get_zone_id() == campship_zone
+                && lock
+                    .get_current_map()
+                    .is_some_and(|m| Arc::ptr_eq(&m, &map))
+            {
+                drop(lock);
+                in_campship.push((player_id, user));
+            }
+        }
+        quest
+            .launch_ready
+            .retain(|i| in_campship.iter().any(|(p, _)| p == i));
+        let (ready, total) = (quest.launch_ready.len(), in_campship.len());
+        if ready == 0 {
+            quest.launch_started = None;
+            return Ok(false);
+        }
+        if ready < total && !force {
+            let message = format!("Waiting for party members to launch ({ready}/{total})");
+            for (_, user) in in_campship {
+                let _ = user.lock().await.send_system_msg(&message).await;
+            }
+            return Ok(true);
+        }
+        let ready = std::mem::take(&mut quest.launch_ready);
+        quest.launch_started = None;
+        let mut lock = map.lock().await;
+        for player_id in ready {
+            lock.move_player_named(player_id, CAMPSHIP_DOWN_ZONE)
+                .await?;
+        }
+        Ok(false)
+    }
 
     pub async fn send_message(&self, mut packet: Packet, id: u32) {
+        let zone = self.get_chat_zone(id).await;
         if let Packet::ChatMessage(ref mut data) = packet {
             data.object = ObjectHeader {
                 id,
@@ -616,12 +887,15 @@ impl Party {
             };
         }
         exec_users(&self.players, |_, mut player| {
-            let _ = player.try_send_packet(&packet);
+            if is_in_zone(zone.as_ref(), &player) && !player.is_blacklisted(id) {
+                let _ = player.try_send_packet(&packet);
+            }
         })
         .await;
     }
 
     pub async fn send_sa(&self, data: SendSymbolArtPacket, id: u32) {
+        let zone = self.get_chat_zone(id).await;
         let packet = Packet::ReceiveSymbolArt(ReceiveSymbolArtPacket {
             object: ObjectHeader {
                 id,
@@ -635,41 +909,95 @@ impl Party {
             unk3: data.unk3,
         });
         exec_users(&self.players, |_, mut player| {
-            let _ = player.try_send_packet(&packet);
+            if is_in_zone(zone.as_ref(), &player) && !player.is_blacklisted(id) {
+                let _ = player.try_send_packet(&packet);
+            }
         })
         .await;
     }
+    /// Returns sender's map and zone if party messages shouldn't cross zones.
+    async fn get_chat_zone(&self, id: u32) -> Option<(Arc<Mutex<Map>>, u32)> {
+        let (_, user) = self.players.iter().find(|(i, _)| *i == id)?;
+        let user = user.upgrade()?;
+        let user = user.lock().await;
+        if user.get_blockdata().chat_settings.party_cross_zone {
+            return None;
+        }
+        Some((user.get_current_map()?, user.get_zone_id()))
+    }
 
+    /// Removes the quest from all members, moves players on the quest map to the lobby and
+    /// applies abandonment refunds and penalties.
     pub async fn abandon(&mut self) {
-        self.quest = None;
+        let Some(quest) = self.quest.take() else {
+            return;
+        };
         self.questname.clear();
+        let quest_map = quest.get_map();
+        let abandon_packet = Packet::Unknown((
+            pso2packetlib::protocol::PacketHeader {
+                id: 0xE,
+                subid: 0x13,
+                flag: Default::default(),
+            },
+            vec![0, 0, 0, 0],
+        ));
+        // empty party quest clears the quest from the client
+        let unset_packet = Packet::SetPartyQuest(party::SetPartyQuestPacket {
+            player: self.leader,
+            ..Default::default()
+        });
         for (id, user) in self
             .players
             .iter()
             .filter_map(|(i, p)| p.upgrade().map(|p| (*i, p)))
         {
-            //TODO: there is some packet missing, because abandoning in lobby doesn't remove the
-            //quest from the client
             let mut lock = user.lock().await;
-            let _ = lock
-                .send_packet(&Packet::Unknown((
-                    pso2packetlib::protocol::PacketHeader {
-                        id: 0xE,
-                        subid: 0x13,
-                        flag: Default::default(),
-                    },
-                    vec![0, 0, 0, 0],
-                )))
-                .await;
-            let current_map = lock
+            let _ = lock.send_packet(&abandon_packet).await;
+            let _ = lock.send_packet(&unset_packet).await;
+            let in_quest = lock
                 .get_current_map()
-                .expect("Player should have a map assigned");
+                .is_some_and(|m| Arc::ptr_eq(&m, &quest_map));
+            let settings = lock.get_blockdata().quest_settings;
+            let refund = match quest.fee_paid {
+                Some((payer, fee)) if payer == id => {
+                    // split to avoid overflowing, the refund never exceeds the paid fee
+                    let percent = settings.abandon_refund.min(100) as u64;
+                    fee / 100 * percent + fee % 100 * percent / 100
+                }
+                _ => 0,
+            };
+            let penalty = if in_quest {
+                settings.abandon_penalty
+            } else {
+                0
+            };
+            if refund != 0 || penalty != 0 {
+                if let Some(character) = lock.character.as_mut() {
+                    character.inventory.add_meseta(refund);
+                    let packet = character.inventory.remove_meseta(penalty);
+                    let _ = lock.send_packet(&packet).await;
+                }
+            }
             drop(lock);
-            let _ = current_map.lock().await.move_to_lobby(id).await;
+            if in_quest {
+                let _ = quest_map.lock().await.move_to_lobby(id).await;
+            }
         }
+        // the quest map is dropped once the last player leaves it
     }
 }
 
+fn is_in_zone(zone: Option<&(Arc<Mutex<Map>>, u32)>, player: &User) -> bool {
+    let Some((map, zone_id)) = zone else {
+        return true;
+    };
+    player.get_zone_id() == *zone_id
+        && player
+            .get_current_map()
+            .is_some_and(|m| Arc::ptr_eq(&m, map))
+}
+
 async fn exec_users<F>(users: &[(u32, Weak<Mutex<User>>)], mut f: F)
 where
     F: FnMut(u32, MutexGuard<User>) + Send,
@@ -1,6 +1,16 @@
-use std::sync::{atomic::AtomicU32, Arc};
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicU32, Arc},
+    time::Instant,
+};
 
-use crate::{map::Map, mutex::Mutex, Error};
+use crate::{
+    lifecycle,
+    map::{self, Map, MapTemplate},
+    mutex::Mutex,
+    settings::CounterSettings,
+    Error,
+};
 use data_structs::quest::QuestData;
 use pso2packetlib::protocol::{
     party::{SetPartyQuestPacket, SetQuestInfoPacket},
@@ -14,22 +24,49 @@ pub struct PartyQuest {
     quest: QuestData,
     diff: u16,
     map: Arc<Mutex<Map>>,
+    /// Players that are ready to launch from the campship.
+    pub launch_ready: Vec<u32>,
+    /// Time when the first player became ready to launch.
+    pub launch_started: Option<Instant>,
+    /// Player that paid the accept fee and the amount.
+    pub fee_paid: Option<(u32, u64)>,
+    /// Marks the map as owned for the lifecycle sweep.
+    _owner: lifecycle::OwnerToken,
 }
 
 pub struct Quests {
+    /// Quests without their maps, which are moved to `maps`.
     quests: Vec<QuestData>,
+    /// Map templates by quest name id.
+    maps: HashMap<u32, MapTemplate>,
 }
 
 impl Quests {
-    pub const fn load(quests: Vec<QuestData>) -> Self {
-        Self { quests }
+    pub fn load(mut quests: Vec<QuestData>) -> Result<Self, Error> {
+        let mut maps = HashMap::new();
+        for quest in &mut quests {
+            let map = map::prepare_template(std::mem::take(&mut quest.map))?;
+            maps.insert(quest.definition.name_id, map);
+        }
+        Ok(Self { quests, maps })
+    }
+    fn quest_map(&self, quest: &QuestData) -> Result<MapTemplate, Error> {
+        self.maps
+            .get(&quest.definition.name_id)
+            .cloned()
+            .ok_or(Error::InvalidInput("quest_map"))
     }
-    pub fn get_availiable(&self, unlocked: &[u32]) -> AvailableQuestsPacket {
+    pub fn get_availiable(
+        &self,
+        unlocked: &[u32],
+        counter: &CounterSettings,
+    ) -> AvailableQuestsPacket {
         let mut available = AvailableQuestsPacket::default();
         for quest in self
             .quests
             .iter()
             .filter(|q| unlocked.contains(&q.definition.name_id))
+            .filter(|q| counter.offers(&q.definition))
         {
             match quest.definition.quest_type {
                 QuestType::Unk0 => {
@@ -305,16 +342,26 @@ impl Quests {
         available
     }
     //FIXME: this will not work for limited time quests
-    pub fn get_category(&self, category: QuestType, unlocked: &[u32]) -> QuestCategoryPacket {
-        QuestCategoryPacket {
-            quests: self
-                .quests
-                .iter()
-                .filter(|q| unlocked.contains(&q.definition.name_id))
-                .filter(|q| q.definition.quest_type == category)
-                .map(|q| q.definition.clone())
-                .collect(),
-        }
+    pub fn get_category(
+        &self,
+        category: QuestType,
+        unlocked: &[u32],
+        counter: &CounterSettings,
+        featured: &[u32],
+    ) -> QuestCategoryPacket {
+        let mut quests: Vec<_> = self
+            .quests
+            .iter()
+            .filter(|q| unlocked.contains(&q.definition.name_id))
+            .filter(|q| q.definition.quest_type == category)
+            .filter(|q| counter.offers(&q.definition))
+            .map(|q| q.definition.clone())
+            .collect();
+        // stable sort keeps the original order of other quests
+        quests.sort_by_key(|q| {
+            !(counter.highlighted.contains(&q.name_id) || featured.contains(&q.name_id))
+        });
+        QuestCategoryPacket { quests }
     }
     pub fn get_diff(&self, id: u32) -> Option<QuestDifficulty> {
         self.quests
@@ -325,25 +372,36 @@ impl Quests {
     pub fn get_quest(
         &self,
         packet: AcceptQuestPacket,
+        counter: &CounterSettings,
         map_obj_id: &AtomicU32,
     ) -> Result<PartyQuest, Error> {
         let Some(quest) = self
             .quests
             .iter()
             .find(|q| q.definition.quest_obj.id == packet.quest_obj.id)
+            .filter(|q| counter.offers(&q.definition))
         else {
             return Err(Error::InvalidInput("get_quest"));
         };
         if packet.diff >= 8 {
             return Err(Error::InvalidInput("get_quest"));
         }
-        let mut map = Map::new_from_data(quest.map.clone(), map_obj_id)?;
+        let mut map = Map::new_from_template(self.quest_map(quest)?, map_obj_id)?;
+        map.set_name(format!("quest_{}", quest.definition.name_id));
+        map.set_quest(quest.definition.name_id);
         map.set_enemy_level(quest.difficulties.diffs[packet.diff as usize].monster_level as _);
+        map.set_difficulty(packet.diff as u8);
         let map = Arc::new(Mutex::new(map));
+        crate::map::start_despawn_task(&map);
+        let owner = lifecycle::register_quest_map(&map);
         Ok(PartyQuest {
             quest: quest.clone(),
             diff: packet.diff,
             map,
+            launch_ready: vec![],
+            launch_started: None,
+            fee_paid: None,
+            _owner: owner,
         })
     }
     pub fn get_story_quest(
@@ -358,13 +416,21 @@ impl Quests {
         else {
             return Err(Error::InvalidInput("get_quest"));
         };
-        let mut map = Map::new_from_data(quest.map.clone(), map_obj_id)?;
+        let mut map = Map::new_from_template(self.quest_map(quest)?, map_obj_id)?;
+        map.set_name(format!("quest_{}", quest.definition.name_id));
+        map.set_quest(quest.definition.name_id);
         map.set_enemy_level(quest.difficulties.diffs[0].monster_level as _);
         let map = Arc::new(Mutex::new(map));
+        crate::map::start_despawn_task(&map);
+        let owner = lifecycle::register_quest_map(&map);
         Ok(PartyQuest {
             quest: quest.clone(),
             diff: 0,
             map,
+            launch_ready: vec![],
+            launch_started: None,
+            fee_paid: None,
+            _owner: owner,
         })
     }
     pub fn get_quest_by_nameid(&self, id: u32) -> Option<&QuestData> {
@@ -397,4 +463,19 @@ impl PartyQuest {
     pub const fn is_insta_transfer(&self) -> bool {
         self.quest.immediate_move
     }
+    pub const fn name_id(&self) -> u32 {
+        self.quest.definition.name_id
+    }
+    /// Returns the quest on the same map for another party of the alliance.
+    pub fn share(&self) -> Self {
+        Self {
+            quest: self.quest.clone(),
+            diff: self.diff,
+            map: self.map.clone(),
+            launch_ready: vec![],
+            launch_started: None,
+            fee_paid: None,
+            _owner: self._owner.clone(),
+        }
+    }
 }
@@ -0,0 +1,113 @@
+//! Clients waiting for a free slot on a full ship. The client has no queue, so clients that
+//! keep retrying are counted as waiting and the wait is estimated from recent departures.
+use data_structs::master_ship::ShipQueue;
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue::new());
+
+/// Time after the last attempt when a client stops being counted as waiting.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+/// Period of departures used for the wait estimate.
+const DEPARTURE_WINDOW: Duration = Duration::from_secs(900);
+
+struct Queue {
+    /// Waiting clients with the time of their last attempt, in the order of the first attempt.
+    waiting: Vec<(Ipv4Addr, Instant)>,
+    departures: VecDeque<Instant>,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self {
+            waiting: vec![],
+            departures: VecDeque::new(),
+        }
+    }
+    fn rejected(&mut self, ip: Ipv4Addr, now: Instant) {
+        match self.waiting.iter_mut().find(|(i, _)| *i == ip) {
+            Some((_, last)) => *last = now,
+            None => self.waiting.push((ip, now)),
+        }
+    }
+    fn admitted(&mut self, ip: Ipv4Addr) {
+        self.waiting.retain(|(i, _)| *i != ip);
+    }
+    fn departed(&mut self, now: Instant) {
+        self.departures.push_back(now);
+    }
+    fn status(&mut self, now: Instant) -> ShipQueue {
+        self.waiting
+            .retain(|(_, last)| now.duration_since(*last) < WAIT_TIMEOUT);
+        while self
+            .departures
+            .front()
+            .is_some_and(|d| now.duration_since(*d) >= DEPARTURE_WINDOW)
+        {
+            self.departures.pop_front();
+        }
+        let departure_interval = (!self.departures.is_empty())
+            .then(|| (DEPARTURE_WINDOW.as_secs() / self.departures.len() as u64) as u32);
+        ShipQueue {
+            waiting: self.waiting.iter().map(|(ip, _)| *ip).collect(),
+            departure_interval,
+        }
+    }
+}
+
+/// Records a connection rejected because the block is full.
+pub fn rejected(ip: Ipv4Addr) {
+    QUEUE.lock().rejected(ip, Instant::now());
+}
+
+/// Removes the client from the queue after it's connected.
+pub fn admitted(ip: Ipv4Addr) {
+    QUEUE.lock().admitted(ip);
+}
+
+/// Records a player leaving the ship.
+pub fn departed() {
+    QUEUE.lock().departed(Instant::now());
+}
+
+/// Returns the queue in the form reported to the master ship.
+pub fn status() -> ShipQueue {
+    QUEUE.lock().status(Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Queue, DEPARTURE_WINDOW, WAIT_TIMEOUT};
+    use std::{net::Ipv4Addr, time::Instant};
+
+    #[test]
+    fn test_queue() {
+        let start = Instant::now();
+        let (a, b) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let mut queue = Queue::new();
+        queue.rejected(a, start);
+        queue.rejected(b, start);
+        queue.rejected(a, start + WAIT_TIMEOUT / 2);
+        let status = queue.status(start + WAIT_TIMEOUT / 2);
+        assert_eq!(status.waiting, vec![a, b]);
+        assert!(status.departure_interval.is_none());
+        // b stopped retrying
+        let status = queue.status(start + WAIT_TIMEOUT);
+        assert_eq!(status.waiting, vec![a]);
+
+        queue.departed(start);
+        queue.departed(start);
+        queue.departed(start);
+        let status = queue.status(start + WAIT_TIMEOUT);
+        assert_eq!(status.departure_interval, Some(300));
+        assert_eq!(status.estimated_wait(1), Some(300));
+        queue.admitted(a);
+        let status = queue.status(start + DEPARTURE_WINDOW);
+        assert!(status.waiting.is_empty());
+        assert!(status.departure_interval.is_none());
+    }
+}
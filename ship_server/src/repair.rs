@@ -0,0 +1,39 @@
+//! Offline repair of character blobs (`--repair`).
+use crate::{settings::Settings, sql::CharData, Error};
+use sqlx::Row;
+
+pub async fn run(settings: &Settings) -> Result<(), Error> {
+    let conn = sqlx::SqlitePool::connect(&settings.db_name).await?;
+    let rows = sqlx::query("select Id, Data from Characters")
+        .fetch_all(&conn)
+        .await?;
+    let (mut repaired, mut damaged) = (0, 0);
+    for row in &rows {
+        let id: i64 = row.try_get("Id")?;
+        let mut char: CharData = match rmp_serde::from_slice(row.try_get("Data")?) {
+            Ok(char) => char,
+            Err(e) => {
+                println!("Character {id} can't be decoded and is left untouched: {e}");
+                damaged += 1;
+                continue;
+            }
+        };
+        // account storages live on the master ship and are checked on login
+        let report = char.inventory.validate();
+        if report.is_clean() {
+            continue;
+        }
+        println!("Character {id}: {report}");
+        sqlx::query("update Characters set Data = ? where Id = ?")
+            .bind(rmp_serde::to_vec(&char)?)
+            .bind(id)
+            .execute(&conn)
+            .await?;
+        repaired += 1;
+    }
+    println!(
+        "{} characters checked, {repaired} repaired, {damaged} damaged",
+        rows.len()
+    );
+    Ok(())
+}
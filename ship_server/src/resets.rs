@@ -0,0 +1,46 @@
+//! Daily and weekly resets of the server clock.
+use crate::clock::ServerClock;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Reset boundary reached by the server clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetEvent {
+    Daily,
+    Weekly,
+}
+
+/// Emits reset events at the configured daily and weekly boundaries. Subsystems with periodic
+/// content subscribe to it instead of tracking the reset times themselves.
+pub struct ResetScheduler {
+    events: broadcast::Sender<ResetEvent>,
+}
+
+impl ResetScheduler {
+    /// Starts the scheduler task.
+    pub fn start(clock: ServerClock) -> Arc<Self> {
+        let (events, _) = broadcast::channel(16);
+        let sender = events.clone();
+        tokio::spawn(async move {
+            loop {
+                let next_daily = clock.next_daily_reset();
+                let next_weekly = clock.next_weekly_reset();
+                // the server clock and the timer share the monotonic clock, so the reset is never
+                // reported early
+                tokio::time::sleep(next_daily.saturating_sub(clock.now())).await;
+                log::info!("Daily reset");
+                let _ = sender.send(ResetEvent::Daily);
+                // weekly resets happen at the daily reset hour
+                if next_weekly == next_daily {
+                    log::info!("Weekly reset");
+                    let _ = sender.send(ResetEvent::Weekly);
+                }
+            }
+        });
+        Arc::new(Self { events })
+    }
+    /// Subscribes to reset events.
+    pub fn subscribe(&self) -> broadcast::Receiver<ResetEvent> {
+        self.events.subscribe()
+    }
+}
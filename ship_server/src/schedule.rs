@@ -0,0 +1,211 @@
+//! Server events scheduled for a date range. While an event runs its changes (EXP boost, event
+//! quests, event lobby and announcements) are applied and they are reverted when it ends.
+use crate::clock::ServerClock;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+
+/// How often the schedule is checked.
+const TICK: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ScheduledEvent {
+    pub name: String,
+    /// Start of the event in seconds since UNIX epoch.
+    pub start: u64,
+    /// End of the event in seconds since UNIX epoch.
+    pub end: u64,
+    /// Multiplier of EXP gained from enemies.
+    pub exp_rate: f32,
+    /// Name ids of quests offered only during the event.
+    pub quests: Vec<u32>,
+    /// Event lobby map used during the event.
+    pub lobby: Option<String>,
+    /// Messages announced in rotation during the event.
+    pub announcements: Vec<String>,
+    /// Minutes between announcements.
+    pub announce_interval: u64,
+}
+
+/// Change of the schedule sent to blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleChange {
+    /// Event with the index started.
+    Started(usize),
+    /// Event with the index ended.
+    Ended(usize),
+    Announce(String),
+}
+
+pub struct EventSchedule {
+    events: Vec<ScheduledEvent>,
+    clock: ServerClock,
+    changes: broadcast::Sender<ScheduleChange>,
+}
+
+/// State of the schedule task.
+#[derive(Default)]
+struct Ticker {
+    active: Vec<bool>,
+    /// Time of the next announcement of each event.
+    next_announce: Vec<u64>,
+    /// Index of the next announcement of each event.
+    announce_index: Vec<usize>,
+}
+
+impl Default for ScheduledEvent {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            start: 0,
+            end: 0,
+            exp_rate: 1.0,
+            quests: vec![],
+            lobby: None,
+            announcements: vec![],
+            announce_interval: 60,
+        }
+    }
+}
+
+impl ScheduledEvent {
+    /// Checks if the event is running at the time.
+    pub const fn is_active(&self, now: u64) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
+impl EventSchedule {
+    /// Starts the schedule task. Events that are already running are started on the first check.
+    pub fn start(events: Vec<ScheduledEvent>, clock: ServerClock) -> Arc<Self> {
+        let (changes, _) = broadcast::channel(16);
+        let schedule = Arc::new(Self {
+            events,
+            clock,
+            changes,
+        });
+        let this = schedule.clone();
+        tokio::spawn(async move {
+            let mut ticker = Ticker::default();
+            let mut interval = tokio::time::interval(TICK);
+            // blocks subscribe after the schedule is started, so the first check is delayed
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                for change in ticker.tick(&this.events, this.clock.now().as_secs()) {
+                    match &change {
+                        ScheduleChange::Started(i) => {
+                            log::info!("Server event started: {}", this.events[*i].name)
+                        }
+                        ScheduleChange::Ended(i) => {
+                            log::info!("Server event ended: {}", this.events[*i].name)
+                        }
+                        ScheduleChange::Announce(_) => {}
+                    }
+                    let _ = this.changes.send(change);
+                }
+            }
+        });
+        schedule
+    }
+    /// Subscribes to schedule changes.
+    pub fn subscribe(&self) -> broadcast::Receiver<ScheduleChange> {
+        self.changes.subscribe()
+    }
+    pub fn get(&self, index: usize) -> Option<&ScheduledEvent> {
+        self.events.get(index)
+    }
+    /// Returns events running now.
+    pub fn active(&self) -> impl Iterator<Item = &ScheduledEvent> {
+        let now = self.clock.now().as_secs();
+        self.events.iter().filter(move |e| e.is_active(now))
+    }
+    /// Returns events that haven't started yet, sorted by the start.
+    pub fn upcoming(&self) -> Vec<&ScheduledEvent> {
+        let now = self.clock.now().as_secs();
+        let mut events: Vec<_> = self.events.iter().filter(|e| e.start > now).collect();
+        events.sort_by_key(|e| e.start);
+        events
+    }
+    /// Returns the EXP multiplier of running events.
+    pub fn exp_rate(&self) -> f32 {
+        self.active().map(|e| e.exp_rate).product()
+    }
+    /// Applies the EXP multiplier of running events.
+    pub fn scale_exp(&self, exp: u32) -> u32 {
+        (exp as f32 * self.exp_rate()) as u32
+    }
+    /// Checks if the quest is offered now. Event quests are offered only during their events.
+    pub fn quest_available(&self, name_id: u32) -> bool {
+        !self.events.iter().any(|e| e.quests.contains(&name_id))
+            || self.active().any(|e| e.quests.contains(&name_id))
+    }
+    /// Returns the event lobby map of running events.
+    pub fn lobby(&self) -> Option<&str> {
+        self.active().find_map(|e| e.lobby.as_deref())
+    }
+}
+
+impl Ticker {
+    /// Returns changes of the schedule since the last tick.
+    fn tick(&mut self, events: &[ScheduledEvent], now: u64) -> Vec<ScheduleChange> {
+        self.active.resize(events.len(), false);
+        self.next_announce.resize(events.len(), 0);
+        self.announce_index.resize(events.len(), 0);
+        let mut changes = vec![];
+        for (i, event) in events.iter().enumerate() {
+            let active = event.is_active(now);
+            if active != self.active[i] {
+                self.active[i] = active;
+                if active {
+                    self.next_announce[i] = now;
+                    self.announce_index[i] = 0;
+                    changes.push(ScheduleChange::Started(i));
+                } else {
+                    changes.push(ScheduleChange::Ended(i));
+                }
+            }
+            if !active || event.announcements.is_empty() || now < self.next_announce[i] {
+                continue;
+            }
+            let index = self.announce_index[i] % event.announcements.len();
+            changes.push(ScheduleChange::Announce(event.announcements[index].clone()));
+            self.announce_index[i] += 1;
+            self.next_announce[i] = now + event.announce_interval.max(1) * 60;
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScheduleChange, ScheduledEvent, Ticker};
+
+    #[test]
+    fn test_tick() {
+        let events = vec![ScheduledEvent {
+            start: 1000,
+            end: 10000,
+            announcements: vec!["a".to_string(), "b".to_string()],
+            announce_interval: 60,
+            ..Default::default()
+        }];
+        let mut ticker = Ticker::default();
+        assert!(ticker.tick(&events, 500).is_empty());
+        assert_eq!(
+            ticker.tick(&events, 1000),
+            vec![
+                ScheduleChange::Started(0),
+                ScheduleChange::Announce("a".to_string())
+            ]
+        );
+        assert!(ticker.tick(&events, 1030).is_empty());
+        assert_eq!(
+            ticker.tick(&events, 4600),
+            vec![ScheduleChange::Announce("b".to_string())]
+        );
+        assert_eq!(ticker.tick(&events, 10000), vec![ScheduleChange::Ended(0)]);
+        assert!(ticker.tick(&events, 20000).is_empty());
+    }
+}
@@ -1,11 +1,26 @@
-use crate::Error;
+use crate::{
+    cadence::MacroSettings,
+    casino::CasinoSettings,
+    chat_filter::{ChatFilter, SpamSettings},
+    daily::DailySettings,
+    schedule::ScheduledEvent,
+    unlocks::UnlockSettings,
+    Error,
+};
 use clap::Parser;
+use data_structs::secrets;
+use pso2packetlib::protocol::questlist::{Quest, QuestType};
 use rsa::{
     pkcs8::{DecodePrivateKey, EncodePrivateKey},
     RsaPrivateKey,
 };
 use serde::{Deserialize, Serialize};
 
+/// Environment variable with the PEM encoded RSA private key.
+const KEY_ENV: &str = "PHANTASY_SHIP_KEY";
+/// Environment variable with the master ship PSK.
+const PSK_ENV: &str = "PHANTASY_MASTER_SHIP_PSK";
+
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
@@ -20,11 +35,38 @@ pub struct Settings {
     pub balance_port: u16,
     pub hostkeys_file: String,
     pub master_ship: Option<String>,
+    /// Master ships that are tried in order if the connection to the current one is lost.
+    pub standby_master_ships: Vec<String>,
     pub master_ship_psk: String,
+    /// File with the master ship PSK. Overrides `master_ship_psk` if set.
+    pub master_ship_psk_file: Option<String>,
     pub data_file: Option<String>,
+    /// Location of the map overrides file.
+    pub map_overrides_file: Option<String>,
     pub log_dir: String,
     pub file_log_level: log::LevelFilter,
     pub console_log_level: log::LevelFilter,
+    pub chat: ChatSettings,
+    pub quests: QuestSettings,
+    pub shops: ShopSettings,
+    pub exp: ExpSettings,
+    pub clock: ClockSettings,
+    /// Detection of macros by input timing.
+    pub macros: MacroSettings,
+    /// Lobby actions and stamps unlocked by ticket items.
+    pub unlocks: UnlockSettings,
+    /// Daily featured quests and client orders.
+    pub daily: DailySettings,
+    /// Casino coin exchange and games.
+    pub casino: CasinoSettings,
+    /// Server events scheduled for a date range.
+    pub events: Vec<ScheduledEvent>,
+    /// Run the self-test instead of starting the server. Only set from the command line.
+    #[serde(skip)]
+    pub doctor: bool,
+    /// Repair character blobs instead of starting the server. Only set from the command line.
+    #[serde(skip)]
+    pub repair: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -75,6 +117,13 @@ struct Args {
     /// Location of complied server data file
     #[arg(short, long)]
     data_path: Option<String>,
+    /// Check the configuration, database, keys, ports, server data and master ship connection,
+    /// then exit
+    #[arg(long, default_value_t = false)]
+    doctor: bool,
+    /// Quarantine invalid items of all characters in the database, then exit
+    #[arg(long, default_value_t = false)]
+    repair: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -84,6 +133,114 @@ pub struct BlockSettings {
     pub name: String,
     pub max_players: u32,
     pub lobby_map: String,
+    /// If true then the block accepts NGS clients instead of classic ones.
+    pub ngs: bool,
+    pub counter: CounterSettings,
+}
+
+/// Quests presented by the quest counter of a block.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CounterSettings {
+    /// Quest categories offered at the counter. If empty then all categories are offered.
+    pub categories: Vec<QuestType>,
+    /// Name ids of quests offered at the counter. If empty then all unlocked quests are offered.
+    pub quests: Vec<u32>,
+    /// Name ids of quests listed first in their categories.
+    pub highlighted: Vec<u32>,
+    /// Messages shown to players opening the counter.
+    pub banners: Vec<String>,
+}
+
+/// Chat and symbol art delivery settings.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Maximum distance between the sender and receivers of map messages. If 0 then messages
+    /// reach the whole zone.
+    pub map_range: f32,
+    /// If true then party messages reach members in other zones.
+    pub party_cross_zone: bool,
+    /// Sanitization rules for map messages.
+    pub map_filter: ChatFilter,
+    /// Sanitization rules for party messages.
+    pub party_filter: ChatFilter,
+    /// Sanitization rules for whispers.
+    pub whisper_filter: ChatFilter,
+    /// Sanitization rules for alliance messages.
+    pub alliance_filter: ChatFilter,
+    /// Spam protection for map and party messages.
+    pub spam: SpamSettings,
+    /// Path to a file with additional banned words (one per line) for all filters.
+    pub wordlist: Option<String>,
+    /// Record chat violations (banned words and spam) to the anomaly log.
+    pub log_violations: bool,
+    /// Add chat violations to the account notes on the master ship.
+    pub report_violations: bool,
+}
+
+/// Quest fee and abandonment rules.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct QuestSettings {
+    /// Meseta charged to the player accepting a quest.
+    pub accept_fee: u64,
+    /// Percentage of the accept fee refunded when the quest is abandoned.
+    pub abandon_refund: u8,
+    /// Meseta taken from each player on the quest map when the quest is abandoned.
+    pub abandon_penalty: u64,
+    /// Percentage of enemy EXP given to players of other parties of the alliance.
+    pub alliance_exp_share: u8,
+}
+
+/// NPC shop rules.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ShopSettings {
+    /// Number of items sold to NPCs that can be bought back during a session (0 - disabled).
+    pub buyback_limit: usize,
+}
+
+/// Experience rules.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ExpSettings {
+    /// Part of the gained experience that also goes to the subclass.
+    pub subclass_share: f32,
+    /// Level after which the subclass stops gaining experience.
+    pub subclass_max_level: u32,
+}
+
+/// Time zone and reset times of the server clock.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ClockSettings {
+    /// Offset of the server time zone from UTC in minutes. The offset is fixed, so daylight
+    /// saving time never shifts resets.
+    pub utc_offset: i32,
+    /// Local hour of the daily reset.
+    pub daily_reset_hour: u8,
+    /// Local weekday of the weekly reset (0 - Monday).
+    pub weekly_reset_day: u8,
+}
+
+impl ClockSettings {
+    /// Checks that the reset times exist.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.daily_reset_hour > 23 {
+            return Err(Error::InvalidSetting(format!(
+                "daily_reset_hour must be in 0..=23, got {}",
+                self.daily_reset_hour
+            )));
+        }
+        if self.weekly_reset_day > 6 {
+            return Err(Error::InvalidSetting(format!(
+                "weekly_reset_day must be in 0..=6, got {}",
+                self.weekly_reset_day
+            )));
+        }
+        Ok(())
+    }
 }
 
 macro_rules! args_to_settings {
@@ -120,27 +277,50 @@ impl Settings {
         args_to_settings!(args.file_log_level => settings.file_log_level);
         args_to_settings!(args.console_log_level => settings.console_log_level);
         settings.data_file = args.data_path.or(settings.data_file);
+        settings.doctor = args.doctor;
+        settings.repair = args.repair;
+        settings.clock.validate()?;
+        if let Some(psk) = secrets::from_env(PSK_ENV) {
+            settings.master_ship_psk = psk;
+        } else if let Some(path) = &settings.master_ship_psk_file {
+            let psk = secrets::read_file(path)?;
+            settings.master_ship_psk = String::from_utf8_lossy(&psk).trim_end().to_string();
+        }
+        if let Some(path) = &settings.chat.wordlist {
+            let words = tokio::fs::read_to_string(path).await?;
+            let words: Vec<_> = words
+                .lines()
+                .map(str::trim)
+                .filter(|w| !w.is_empty() && !w.starts_with('#'))
+                .map(String::from)
+                .collect();
+            log::info!("Loaded {} banned words from {path}", words.len());
+            let chat = &mut settings.chat;
+            for filter in [
+                &mut chat.map_filter,
+                &mut chat.party_filter,
+                &mut chat.whisper_filter,
+                &mut chat.alliance_filter,
+            ] {
+                filter.banned_words.extend_from_slice(&words);
+            }
+        }
 
         Ok(settings)
     }
     pub fn load_key(&self) -> Result<RsaPrivateKey, Error> {
         log::info!("Loading keypair");
-        let key = match &self.key_file {
-            Some(keyfile_path) => match std::fs::metadata(keyfile_path) {
-                Ok(..) => RsaPrivateKey::read_pkcs8_pem_file(keyfile_path)?,
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    log::warn!("Keyfile doesn't exist, creating...");
-                    let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)?;
-                    key.write_pkcs8_pem_file(keyfile_path, rsa::pkcs8::LineEnding::default())?;
-                    log::info!("Keyfile created.");
-                    key
-                }
-                Err(e) => {
-                    log::error!("Failed to load keypair: {e}");
-                    return Err(e.into());
-                }
-            },
-            None => {
+        let key = match (self.read_key()?, &self.key_file) {
+            (Some(key), _) => key,
+            (None, Some(keyfile_path)) => {
+                log::warn!("Keyfile doesn't exist, creating...");
+                let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)?;
+                let pem = key.to_pkcs8_pem(rsa::pkcs8::LineEnding::default())?;
+                secrets::write_file(keyfile_path, pem.as_bytes())?;
+                log::info!("Keyfile created.");
+                key
+            }
+            (None, None) => {
                 let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)?;
                 log::info!("Keyfile created.");
                 key
@@ -149,6 +329,29 @@ impl Settings {
         log::info!("Loaded keypair");
         Ok(key)
     }
+    /// Reads the key from the environment variable or the key file. Returns `None` if the key
+    /// file isn't set or doesn't exist.
+    pub fn read_key(&self) -> Result<Option<RsaPrivateKey>, Error> {
+        if let Some(pem) = secrets::from_env(KEY_ENV) {
+            log::info!("Loaded keypair from {KEY_ENV}");
+            return Ok(Some(RsaPrivateKey::from_pkcs8_pem(&pem)?));
+        }
+        let Some(keyfile_path) = &self.key_file else {
+            return Ok(None);
+        };
+        match std::fs::metadata(keyfile_path) {
+            Ok(..) => {
+                let pem = secrets::read_file(keyfile_path)?;
+                let key = RsaPrivateKey::from_pkcs8_pem(&String::from_utf8_lossy(&pem))?;
+                Ok(Some(key))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                log::error!("Failed to load keypair: {e}");
+                Err(e.into())
+            }
+        }
+    }
 }
 
 impl Default for Settings {
@@ -163,14 +366,84 @@ impl Default for Settings {
             key_file: None,
             hostkeys_file: String::from("hostkeys.toml"),
             master_ship: None,
+            standby_master_ships: vec![],
             master_ship_psk: String::from("master_ship_psk"),
+            master_ship_psk_file: None,
             data_file: None,
+            map_overrides_file: None,
             log_dir: String::from("logs"),
             file_log_level: log::LevelFilter::Info,
             console_log_level: log::LevelFilter::Debug,
+            chat: Default::default(),
+            quests: Default::default(),
+            shops: Default::default(),
+            exp: Default::default(),
+            clock: Default::default(),
+            macros: Default::default(),
+            unlocks: Default::default(),
+            daily: Default::default(),
+            casino: Default::default(),
+            events: vec![],
+            doctor: false,
+            repair: false,
+        }
+    }
+}
+impl Default for QuestSettings {
+    fn default() -> Self {
+        Self {
+            accept_fee: 0,
+            abandon_refund: 100,
+            abandon_penalty: 0,
+            alliance_exp_share: 50,
+        }
+    }
+}
+impl Default for ShopSettings {
+    fn default() -> Self {
+        Self { buyback_limit: 10 }
+    }
+}
+impl Default for ExpSettings {
+    fn default() -> Self {
+        Self {
+            subclass_share: 1.0,
+            subclass_max_level: 70,
+        }
+    }
+}
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            map_range: 0.0,
+            party_cross_zone: true,
+            map_filter: Default::default(),
+            party_filter: Default::default(),
+            whisper_filter: Default::default(),
+            alliance_filter: Default::default(),
+            spam: Default::default(),
+            wordlist: None,
+            log_violations: true,
+            report_violations: false,
+        }
+    }
+}
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            utc_offset: 0,
+            daily_reset_hour: 4,
+            weekly_reset_day: 2,
         }
     }
 }
+impl CounterSettings {
+    /// Checks if the quest is offered at the counter.
+    pub fn offers(&self, quest: &Quest) -> bool {
+        (self.categories.is_empty() || self.categories.contains(&quest.quest_type))
+            && (self.quests.is_empty() || self.quests.contains(&quest.name_id))
+    }
+}
 impl Default for BlockSettings {
     fn default() -> Self {
         Self {
@@ -178,6 +451,8 @@ impl Default for BlockSettings {
             name: "Block 1".to_string(),
             max_players: 32,
             lobby_map: "lobby".to_string(),
+            ngs: false,
+            counter: Default::default(),
         }
     }
 }
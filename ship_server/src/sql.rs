@@ -1,19 +1,46 @@
-use crate::{inventory::Inventory, master_conn::MasterConnection, palette::Palette, Error};
+use crate::{
+    arksmission::ArksMissions,
+    craft::CraftSlot,
+    daily::DailyProgress,
+    directory::PlayerEntry,
+    inventory::Inventory,
+    loadout::Loadout,
+    mail::Mail,
+    master_conn::MasterConnection,
+    orders::ClientOrders,
+    palette::Palette,
+    team::{Team, TeamMember, TeamRank},
+    titles::Titles,
+    unlocks::Unlocks,
+    Error,
+};
 use data_structs::{
     flags::Flags,
     inventory::AccountStorages,
-    master_ship::{MasterShipAction, SetNicknameResult, UserCreds, UserLoginResult},
+    master_ship::{
+        AccountNote, BlockStatus, ChangeNicknameResult, LoginEntry, MasterShipAction, Mute,
+        PutStorageResult, SetNicknameResult, ShipQueue, ShipStats, SupportTicket, UserCreds,
+        UserLoginResult,
+    },
 };
 use pso2packetlib::{
     protocol::{
-        login::{Language, LoginAttempt, UserInfoPacket},
+        login::{Language, ShipStatus, UserInfoPacket},
         models::character::Character,
         PacketType,
     },
     AsciiString,
 };
 use sqlx::{migrate::MigrateDatabase, Executor, Row};
-use std::{net::Ipv4Addr, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+const STORAGE_WRITE_ATTEMPTS: usize = 3;
+/// Number of character slots of an account.
+pub const MAX_CHARACTERS: usize = 30;
 
 pub struct Sql {
     connection: sqlx::SqlitePool,
@@ -27,7 +54,7 @@ pub struct User {
     pub lang: Language,
     pub packet_type: PacketType,
     pub accountflags: Flags,
-    pub isgm: bool,
+    pub gm_level: u8,
     pub last_uuid: u64,
 }
 
@@ -50,6 +77,95 @@ pub struct CharData {
     pub unlocked_quests: Vec<u32>,
     pub unlocked_quests_notif: Vec<u32>,
     pub play_time: Duration,
+    pub loadouts: Vec<Loadout>,
+    /// Unlocked lobby actions and stamps.
+    pub unlocks: Unlocks,
+    /// Comment shown on the player card of the character.
+    pub card_comment: String,
+    /// Revealed minimap chunks by map and zone name.
+    pub revealed_chunks: BTreeMap<String, BTreeSet<u32>>,
+    /// Crafts in progress.
+    pub crafts: Vec<CraftSlot>,
+    pub titles: Titles,
+    /// Accepted and turned in client orders.
+    pub orders: ClientOrders,
+    /// Featured quests and daily orders completed today.
+    pub daily: DailyProgress,
+    pub arks_missions: ArksMissions,
+    pub casino_coins: u64,
+}
+
+impl CharData {
+    /// Gives every item of the character a new UUID starting from `last_uuid`.
+    pub fn renumber_items(&mut self, last_uuid: &mut u64) {
+        let uuids = self.inventory.renumber(last_uuid);
+        self.palette.remap_uuids(&uuids);
+        for loadout in &mut self.loadouts {
+            loadout.remap_uuids(&uuids);
+        }
+    }
+}
+
+/// Relation with another player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Friend {
+    pub id: u32,
+    /// Last known nickname of the player.
+    pub nickname: String,
+    /// Last known character name of the player.
+    pub char_name: String,
+    pub status: FriendStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FriendStatus {
+    /// Friend request was sent to the player.
+    Outgoing = 0,
+    /// Player sent a friend request.
+    Incoming = 1,
+    Accepted = 2,
+}
+
+/// Suspicious player behavior recorded to the anomaly log.
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    pub user_id: u32,
+    /// Time (since UNIX epoch) of the record.
+    pub time: Duration,
+    pub kind: String,
+    pub details: String,
+}
+
+/// Player blacklisted by a character.
+#[derive(Debug, Clone)]
+pub struct BlacklistEntry {
+    pub id: u32,
+    /// Nickname of the player at the time of blacklisting.
+    pub nickname: String,
+}
+
+/// Completed trade recorded for audit.
+#[derive(Debug, Clone)]
+pub struct TradeRecord {
+    /// Time (since UNIX epoch) of the trade.
+    pub time: Duration,
+    /// Player ids of both sides.
+    pub users: (u32, u32),
+    /// Descriptions of the offers of both sides.
+    pub offers: (String, String),
+}
+
+/// Player card received from another character.
+#[derive(Debug, Clone)]
+pub struct PlayerCard {
+    /// Player id of the card owner.
+    pub user_id: u32,
+    pub char_id: u32,
+    pub char_name: String,
+    pub nickname: String,
+    pub comment: String,
+    /// Time (since UNIX epoch) when the card was received.
+    pub received: Duration,
 }
 
 #[derive(Default, serde::Serialize, serde::Deserialize)]
@@ -68,6 +184,7 @@ impl Sql {
             sqlx::query("delete from Challenges").execute(&conn).await?;
             conn
         };
+        Self::update_db(&conn).await?;
         Ok(Self {
             connection: conn,
             master_ship,
@@ -97,24 +214,251 @@ impl Sql {
         .await?;
         conn.execute(
             "
-            create table if not exists SymbolArts (
-                UUID blob,
-                Name blob,
+            create table if not exists Challenges (
+                Challenge integer,
                 Data blob
             );
         ",
         )
         .await?;
+        Ok(conn)
+    }
+
+    /// Creates tables that were added after the initial schema.
+    async fn update_db(conn: &sqlx::SqlitePool) -> Result<(), Error> {
+        conn.execute(
+            "
+            create table if not exists Friends (
+                UserId integer,
+                FriendId integer,
+                Status integer,
+                Nickname text,
+                CharName text,
+                primary key (UserId, FriendId)
+            );
+        ",
+        )
+        .await?;
         conn.execute(
             "
-            create table if not exists Challenges (
-                Challenge integer,
+            create table if not exists Mail (
+                Id integer primary key autoincrement,
+                CharacterId integer,
+                IsRead integer,
                 Data blob
             );
         ",
         )
         .await?;
-        Ok(conn)
+        conn.execute(
+            "
+            create table if not exists Blacklist (
+                CharacterId integer,
+                BlockedId integer,
+                Nickname text,
+                primary key (CharacterId, BlockedId)
+            );
+        ",
+        )
+        .await?;
+        conn.execute(
+            "
+            create table if not exists Teams (
+                Id integer primary key autoincrement,
+                Name text unique collate nocase
+            );
+            create table if not exists TeamMembers (
+                UserId integer primary key,
+                TeamId integer,
+                Rank integer,
+                Nickname text
+            );
+        ",
+        )
+        .await?;
+        conn.execute(
+            "
+            create table if not exists Anomalies (
+                Id integer primary key autoincrement,
+                UserId integer,
+                Time integer,
+                Kind text,
+                Details text
+            );
+        ",
+        )
+        .await?;
+        conn.execute(
+            "
+            create table if not exists SymbolArtData (
+                Hash blob primary key,
+                Data blob,
+                Refs integer
+            );
+            create table if not exists SymbolArtRefs (
+                UUID blob primary key,
+                Name blob,
+                Hash blob,
+                Users integer
+            );
+        ",
+        )
+        .await?;
+        // mutes are stored on the master ship
+        conn.execute("drop table if exists Mutes").await?;
+        conn.execute(
+            "
+            create table if not exists PlayerCards (
+                OwnerId integer,
+                CardCharId integer,
+                UserId integer,
+                CharName text,
+                Nickname text,
+                Comment text,
+                Received integer,
+                primary key (OwnerId, CardCharId)
+            );
+        ",
+        )
+        .await?;
+        conn.execute(
+            "
+            create table if not exists Trades (
+                Id integer primary key autoincrement,
+                Time integer,
+                UserId1 integer,
+                CharId1 integer,
+                Offer1 text,
+                UserId2 integer,
+                CharId2 integer,
+                Offer2 text
+            );
+        ",
+        )
+        .await?;
+        Self::migrate_symbol_arts(conn).await?;
+        Self::migrate_character_names(conn).await?;
+        Self::migrate_ignores(conn).await?;
+        Ok(())
+    }
+    /// Moves account ignore lists to the blacklists of every character of the account.
+    async fn migrate_ignores(conn: &sqlx::SqlitePool) -> Result<(), Error> {
+        let exists = sqlx::query("select 1 from sqlite_master where type = 'table' and name = ?")
+            .bind("Ignores")
+            .fetch_optional(conn)
+            .await?;
+        if exists.is_none() {
+            return Ok(());
+        }
+        log::info!("Migrating ignore lists");
+        let mut transaction = conn.begin().await?;
+        for row in sqlx::query("select Id, Data from Users")
+            .fetch_all(&mut *transaction)
+            .await?
+        {
+            let user_id: i64 = row.try_get("Id")?;
+            let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+            for char_id in user_data.character_ids {
+                // nicknames are not stored locally, entries are listed by id until re-added
+                sqlx::query(
+                    "insert or ignore into Blacklist (CharacterId, BlockedId, Nickname) \
+                    select ?, IgnoredId, '' from Ignores where UserId = ?",
+                )
+                .bind(char_id as i64)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+        sqlx::query("drop table Ignores")
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    /// Adds the indexed name column used to find characters by name.
+    async fn migrate_character_names(conn: &sqlx::SqlitePool) -> Result<(), Error> {
+        let exists = sqlx::query("select 1 from pragma_table_info('Characters') where name = ?")
+            .bind("Name")
+            .fetch_optional(conn)
+            .await?;
+        if exists.is_some() {
+            return Ok(());
+        }
+        log::info!("Indexing character names");
+        let mut transaction = conn.begin().await?;
+        sqlx::query("alter table Characters add column Name text collate nocase")
+            .execute(&mut *transaction)
+            .await?;
+        for row in sqlx::query("select Id, Data from Characters")
+            .fetch_all(&mut *transaction)
+            .await?
+        {
+            let char: CharData = rmp_serde::from_slice(row.try_get("Data")?)?;
+            sqlx::query("update Characters set Name = ? where Id = ?")
+                .bind(&char.character.name)
+                .bind(row.try_get::<i64, _>("Id")?)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        sqlx::query("create index if not exists CharacterNames on Characters (Name)")
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    /// Moves symbol arts from the old table (one row per upload) to deduplicated storage.
+    async fn migrate_symbol_arts(conn: &sqlx::SqlitePool) -> Result<(), Error> {
+        let exists = sqlx::query("select 1 from sqlite_master where type = 'table' and name = ?")
+            .bind("SymbolArts")
+            .fetch_optional(conn)
+            .await?;
+        if exists.is_none() {
+            return Ok(());
+        }
+        log::info!("Migrating symbol arts");
+        let mut transaction = conn.begin().await?;
+        // every listed symbol art gets a reference, even if its data was never uploaded
+        let mut users = std::collections::HashMap::<u128, i64>::new();
+        for row in sqlx::query("select Data from Users")
+            .fetch_all(&mut *transaction)
+            .await?
+        {
+            let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+            for uuid in listed_symbol_arts(&user_data.symbol_arts) {
+                *users.entry(uuid).or_default() += 1;
+            }
+        }
+        for (uuid, count) in users {
+            sqlx::query(
+                "insert into SymbolArtRefs (UUID, Users) values (?, ?) \
+                on conflict (UUID) do update set Users = Users + excluded.Users",
+            )
+            .bind(format!("{uuid:X}").as_bytes())
+            .bind(count)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        for row in sqlx::query("select * from SymbolArts")
+            .fetch_all(&mut *transaction)
+            .await?
+        {
+            let uuid: Vec<u8> = row.try_get("UUID")?;
+            let Some(uuid) = std::str::from_utf8(&uuid)
+                .ok()
+                .and_then(|u| u128::from_str_radix(u, 16).ok())
+            else {
+                continue;
+            };
+            let name: Vec<u8> = row.try_get("Name")?;
+            let data: Vec<u8> = row.try_get("Data")?;
+            Self::store_symbol_art(&mut transaction, uuid, &name, &data).await?;
+        }
+        sqlx::query("drop table SymbolArts")
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
     }
 
     pub async fn run_action(&self, action: MasterShipAction) -> Result<MasterShipAction, Error> {
@@ -126,12 +470,18 @@ impl Sql {
         username: &str,
         password: &str,
         ip: Ipv4Addr,
+        platform: PacketType,
+        client_version: &str,
+        otp: Option<&str>,
     ) -> Result<User, Error> {
         let result = self
             .run_action(MasterShipAction::UserLogin(UserCreds {
                 username: username.to_string(),
                 password: password.to_string(),
                 ip,
+                platform,
+                otp: otp.map(str::to_string),
+                client_version: client_version.to_string(),
             }))
             .await?;
         match result {
@@ -139,7 +489,7 @@ impl Sql {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
             }) => {
                 let _: UserData = if let Some(row) =
@@ -157,7 +507,7 @@ impl Sql {
                     id,
                     nickname,
                     accountflags,
-                    isgm,
+                    gm_level,
                     last_uuid,
                     ..Default::default()
                 })
@@ -166,18 +516,44 @@ impl Sql {
                 Err(Error::InvalidPassword)
             }
             MasterShipAction::UserLoginResult(UserLoginResult::NotFound) => {
-                self.create_sega_user(username, password).await
+                self.create_sega_user(username, password, ip, platform)
+                    .await
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::TooManyAttempts { retry_after }) => {
+                Err(Error::TooManyAttempts(retry_after))
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::OtpRequired) => {
+                Err(Error::OtpRequired)
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Banned { until, reason }) => {
+                Err(Error::Banned { until, reason })
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice)) => {
+                Err(Error::Maintenance(notice))
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::OutdatedClient { min_version }) => {
+                Err(Error::OutdatedClient(min_version))
             }
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }
     }
-    pub async fn get_psn_user(&self, username: &str, ip: Ipv4Addr) -> Result<User, Error> {
+    /// Logs in the PSN user, creating the account if it doesn't exist. `otp` is the one-time code
+    /// for accounts with 2FA enabled.
+    pub async fn get_psn_user(
+        &self,
+        username: &str,
+        ip: Ipv4Addr,
+        otp: Option<&str>,
+    ) -> Result<User, Error> {
         let result = self
             .run_action(MasterShipAction::UserLoginVita(UserCreds {
                 username: username.to_string(),
                 password: String::new(),
                 ip,
+                platform: PacketType::Vita,
+                otp: otp.map(str::to_string),
+                client_version: String::new(),
             }))
             .await?;
         match result {
@@ -185,7 +561,7 @@ impl Sql {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
             }) => {
                 let _: UserData = if let Some(row) =
@@ -203,27 +579,49 @@ impl Sql {
                     id,
                     nickname,
                     accountflags,
-                    isgm,
+                    gm_level,
                     last_uuid,
                     ..Default::default()
                 })
             }
+            // wrong one-time code
             MasterShipAction::UserLoginResult(UserLoginResult::InvalidPassword(_)) => {
-                Err(Error::MSUnexpected)
+                Err(Error::InvalidPassword)
             }
             MasterShipAction::UserLoginResult(UserLoginResult::NotFound) => {
                 self.create_psn_user(username).await
             }
+            MasterShipAction::UserLoginResult(UserLoginResult::TooManyAttempts { retry_after }) => {
+                Err(Error::TooManyAttempts(retry_after))
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::OtpRequired) => {
+                Err(Error::OtpRequired)
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Banned { until, reason }) => {
+                Err(Error::Banned { until, reason })
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice)) => {
+                Err(Error::Maintenance(notice))
+            }
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }
     }
-    async fn create_sega_user(&self, username: &str, password: &str) -> Result<User, Error> {
+    async fn create_sega_user(
+        &self,
+        username: &str,
+        password: &str,
+        ip: Ipv4Addr,
+        platform: PacketType,
+    ) -> Result<User, Error> {
         let result = self
             .run_action(MasterShipAction::UserRegister(UserCreds {
                 username: username.to_string(),
                 password: password.to_string(),
-                ip: Ipv4Addr::UNSPECIFIED,
+                ip,
+                platform,
+                otp: None,
+                client_version: String::new(),
             }))
             .await?;
         let user = match result {
@@ -231,16 +629,22 @@ impl Sql {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
             }) => Ok(User {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
                 ..Default::default()
             }),
+            MasterShipAction::UserLoginResult(UserLoginResult::RegistrationDenied(reason)) => {
+                Err(Error::RegistrationDenied(reason))
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice)) => {
+                Err(Error::Maintenance(notice))
+            }
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }?;
@@ -253,6 +657,9 @@ impl Sql {
                 username: username.to_string(),
                 password: String::new(),
                 ip: Ipv4Addr::UNSPECIFIED,
+                platform: PacketType::Vita,
+                otp: None,
+                client_version: String::new(),
             }))
             .await?;
         let user = match result {
@@ -260,16 +667,19 @@ impl Sql {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
             }) => Ok(User {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
                 ..Default::default()
             }),
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice)) => {
+                Err(Error::Maintenance(notice))
+            }
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }?;
@@ -353,7 +763,7 @@ impl Sql {
                 id,
                 nickname,
                 accountflags,
-                isgm,
+                gm_level,
                 last_uuid,
             }) => {
                 let row = sqlx::query("select * from Challenges where Challenge = ?")
@@ -375,7 +785,7 @@ impl Sql {
                     lang: challenge_data.lang,
                     packet_type: challenge_data.packet_type,
                     accountflags,
-                    isgm,
+                    gm_level,
                     last_uuid,
                 })
             }
@@ -383,12 +793,20 @@ impl Sql {
                 Err(Error::MSUnexpected)
             }
             MasterShipAction::UserLoginResult(UserLoginResult::NotFound) => Err(Error::NoUser),
+            MasterShipAction::UserLoginResult(UserLoginResult::Banned { until, reason }) => {
+                Err(Error::Banned { until, reason })
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice)) => {
+                Err(Error::Maintenance(notice))
+            }
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }
     }
-    pub async fn get_logins(&self, id: u32) -> Result<Vec<LoginAttempt>, Error> {
-        let result = self.run_action(MasterShipAction::GetLogins(id)).await?;
+    pub async fn get_logins(&self, id: u32, limit: u32) -> Result<Vec<LoginEntry>, Error> {
+        let result = self
+            .run_action(MasterShipAction::GetLogins { id, limit })
+            .await?;
         match result {
             MasterShipAction::GetLoginsResult(d) => Ok(d),
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
@@ -445,22 +863,41 @@ impl Sql {
         let mut char: CharData = rmp_serde::from_slice(row.try_get("Data")?)?;
         char.character.player_id = id;
         char.character.character_id = char_id;
-        char.inventory.storages = self.get_account_storage(id).await?;
+        let (storages, version) = self.get_account_storage(id).await?;
+        char.inventory.storages_base = storages.clone();
+        char.inventory.storages = storages;
+        char.inventory.storages_version = version;
         Ok(char)
     }
     pub async fn update_character(&self, char: &CharData) -> Result<(), Error> {
-        sqlx::query("update Characters set Data = ? where Id = ?")
+        sqlx::query("update Characters set Data = ?, Name = ? where Id = ?")
             .bind(rmp_serde::to_vec(&char)?)
+            .bind(&char.character.name)
             .bind(char.character.character_id as i64)
             .execute(&self.connection)
             .await?;
         Ok(())
     }
+    /// Saves all characters or none of them.
+    pub async fn update_characters(&self, chars: &[&CharData]) -> Result<(), Error> {
+        let mut transaction = self.connection.begin().await?;
+        for char in chars {
+            sqlx::query("update Characters set Data = ?, Name = ? where Id = ?")
+                .bind(rmp_serde::to_vec(char)?)
+                .bind(&char.character.name)
+                .bind(char.character.character_id as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
     pub async fn put_character(&self, id: u32, char: CharData) -> Result<u32, Error> {
         let mut transaction = self.connection.begin().await?;
         let data = rmp_serde::to_vec(&char)?;
-        let char_id = sqlx::query("insert into Characters (Data) values (?) returning Id")
+        let char_id = sqlx::query("insert into Characters (Data, Name) values (?, ?) returning Id")
             .bind(&data)
+            .bind(&char.character.name)
             .fetch_one(&mut *transaction)
             .await?
             .try_get::<i64, _>("Id")?;
@@ -477,6 +914,18 @@ impl Sql {
             .bind(char_id)
             .execute(&mut *transaction)
             .await?;
+        sqlx::query("delete from Mail where CharacterId = ?")
+            .bind(char_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("delete from Blacklist where CharacterId = ?")
+            .bind(char_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("delete from PlayerCards where OwnerId = ?")
+            .bind(char_id)
+            .execute(&mut *transaction)
+            .await?;
         transaction.commit().await?;
 
         self.update_userdata(id, |user_data| {
@@ -501,45 +950,185 @@ impl Sql {
         let user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
         Ok(user_data.symbol_arts)
     }
+    /// Sets the symbol art list of the account. Each account references a symbol art once, and
+    /// symbol arts that are no longer in any list are removed.
     pub async fn set_symbol_art_list(&self, uuids: Vec<u128>, id: u32) -> Result<(), Error> {
-        self.update_userdata(id, |user_data| user_data.symbol_arts = uuids)
-            .await
-    }
-    pub async fn get_symbol_art(&self, uuid: u128) -> Result<Option<Vec<u8>>, Error> {
-        let row = sqlx::query("select * from SymbolArts where UUID = ?")
+        // the old list is read in the same transaction, so concurrent changes can't miscount
+        let mut transaction = self.connection.begin().await?;
+        let row = sqlx::query("select Data from Users where Id = ?")
+            .bind(id as i64)
+            .fetch_one(&mut *transaction)
+            .await?;
+        let mut user_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        let old_uuids = listed_symbol_arts(&user_data.symbol_arts);
+        let new_uuids = listed_symbol_arts(&uuids);
+        for uuid in new_uuids.difference(&old_uuids) {
+            sqlx::query(
+                "insert into SymbolArtRefs (UUID, Users) values (?, 1) \
+                on conflict (UUID) do update set Users = Users + 1",
+            )
             .bind(format!("{uuid:X}").as_bytes())
-            .fetch_optional(&self.connection)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        for uuid in old_uuids.difference(&new_uuids) {
+            Self::release_symbol_art(&mut transaction, *uuid).await?;
+        }
+        user_data.symbol_arts = uuids;
+        sqlx::query("update Users set Data = ? where Id = ?")
+            .bind(rmp_serde::to_vec(&user_data)?)
+            .bind(id as i64)
+            .execute(&mut *transaction)
             .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    pub async fn get_symbol_art(&self, uuid: u128) -> Result<Option<Vec<u8>>, Error> {
+        let row = sqlx::query(
+            "select Data from SymbolArtRefs join SymbolArtData \
+            on SymbolArtRefs.Hash = SymbolArtData.Hash where UUID = ?",
+        )
+        .bind(format!("{uuid:X}").as_bytes())
+        .fetch_optional(&self.connection)
+        .await?;
         match row {
             Some(data) => Ok(Some(data.try_get::<Vec<u8>, _>("Data")?)),
             None => Ok(None),
         }
     }
-    pub async fn add_symbol_art(&self, uuid: u128, data: &[u8], name: &str) -> Result<(), Error> {
-        sqlx::query("insert into SymbolArts (UUID, Name, Data) values (?, ?, ?)")
-            .bind(format!("{uuid:X}").as_bytes())
-            .bind(name.as_bytes())
-            .bind(data)
-            .execute(&self.connection)
+    /// Stores the data of the symbol art. Data is only accepted once and only for symbol arts in
+    /// the list of the uploading account.
+    pub async fn add_symbol_art(
+        &self,
+        user_id: u32,
+        uuid: u128,
+        data: &[u8],
+        name: &str,
+    ) -> Result<(), Error> {
+        if uuid == 0 || !self.get_symbol_art_list(user_id).await?.contains(&uuid) {
+            return Ok(());
+        }
+        let mut transaction = self.connection.begin().await?;
+        Self::store_symbol_art(&mut transaction, uuid, name.as_bytes(), data).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    /// Links the referenced symbol art without data to its data, storing identical data only once.
+    async fn store_symbol_art(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        uuid: u128,
+        name: &[u8],
+        data: &[u8],
+    ) -> Result<(), Error> {
+        use sha2::Digest;
+        let hash = sha2::Sha256::digest(data).to_vec();
+        let linked = sqlx::query(
+            "update SymbolArtRefs set Name = ?, Hash = ? where UUID = ? and Hash is null",
+        )
+        .bind(name)
+        .bind(&hash)
+        .bind(format!("{uuid:X}").as_bytes())
+        .execute(&mut **transaction)
+        .await?
+        .rows_affected();
+        if linked == 0 {
+            return Ok(());
+        }
+        sqlx::query(
+            "insert into SymbolArtData (Hash, Data, Refs) values (?, ?, 1) \
+            on conflict (Hash) do update set Refs = Refs + 1",
+        )
+        .bind(&hash)
+        .bind(data)
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+    /// Removes one account reference to the symbol art, deleting unused data.
+    async fn release_symbol_art(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        uuid: u128,
+    ) -> Result<(), Error> {
+        let uuid = format!("{uuid:X}");
+        sqlx::query("update SymbolArtRefs set Users = Users - 1 where UUID = ?")
+            .bind(uuid.as_bytes())
+            .execute(&mut **transaction)
             .await?;
+        let row = sqlx::query("select Hash from SymbolArtRefs where UUID = ? and Users <= 0")
+            .bind(uuid.as_bytes())
+            .fetch_optional(&mut **transaction)
+            .await?;
+        let Some(row) = row else {
+            return Ok(());
+        };
+        sqlx::query("delete from SymbolArtRefs where UUID = ?")
+            .bind(uuid.as_bytes())
+            .execute(&mut **transaction)
+            .await?;
+        if let Some(hash) = row.try_get::<Option<Vec<u8>>, _>("Hash")? {
+            sqlx::query("update SymbolArtData set Refs = Refs - 1 where Hash = ?")
+                .bind(&hash)
+                .execute(&mut **transaction)
+                .await?;
+            sqlx::query("delete from SymbolArtData where Hash = ? and Refs <= 0")
+                .bind(&hash)
+                .execute(&mut **transaction)
+                .await?;
+        }
         Ok(())
     }
-    pub async fn get_account_storage(&self, user_id: u32) -> Result<AccountStorages, Error> {
+    pub async fn get_account_storage(&self, user_id: u32) -> Result<(AccountStorages, u64), Error> {
         let result = self
             .run_action(MasterShipAction::GetStorage(user_id))
             .await?;
         match result {
-            MasterShipAction::GetStorageResult(storages) => Ok(storages),
+            MasterShipAction::GetStorageResult { storage, version } => Ok((storage, version)),
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }
     }
-    pub async fn update_account_storage(&self, user_id: u32, inv: &Inventory) -> Result<(), Error> {
+    /// Saves account storages, merging in changes made by other sessions on conflict.
+    pub async fn update_account_storage(
+        &self,
+        user_id: u32,
+        inv: &mut Inventory,
+    ) -> Result<(), Error> {
+        for _ in 0..STORAGE_WRITE_ATTEMPTS {
+            let result = self
+                .run_action(MasterShipAction::PutStorage {
+                    id: user_id,
+                    storage: inv.storages.clone(),
+                    version: inv.storages_version,
+                })
+                .await?;
+            match result {
+                MasterShipAction::PutStorageResult(PutStorageResult::Ok { version }) => {
+                    inv.storages_base = inv.storages.clone();
+                    inv.storages_version = version;
+                    return Ok(());
+                }
+                MasterShipAction::PutStorageResult(PutStorageResult::Conflict {
+                    storage,
+                    version,
+                }) => {
+                    // changes to the same items are refused rather than overwriting remote ones
+                    let Some(merged) = inv.storages.merge(&inv.storages_base, storage.clone())
+                    else {
+                        return Err(Error::StorageConflict);
+                    };
+                    inv.storages = merged;
+                    inv.storages_base = storage;
+                    inv.storages_version = version;
+                }
+                MasterShipAction::Error(e) => return Err(Error::MSError(e)),
+                _ => return Err(Error::MSUnexpected),
+            }
+        }
+        Err(Error::StorageConflict)
+    }
+    pub async fn put_uuid(&self, user_id: u32, uuid: u64) -> Result<(), Error> {
         let result = self
-            .run_action(MasterShipAction::PutStorage {
-                id: user_id,
-                storage: inv.storages.clone(),
-            })
+            .run_action(MasterShipAction::PutUUID { id: user_id, uuid })
             .await?;
         match result {
             MasterShipAction::Ok => Ok(()),
@@ -547,9 +1136,9 @@ impl Sql {
             _ => Err(Error::MSUnexpected),
         }
     }
-    pub async fn put_uuid(&self, user_id: u32, uuid: u64) -> Result<(), Error> {
+    pub async fn set_gm_level(&self, user_id: u32, level: u8) -> Result<(), Error> {
         let result = self
-            .run_action(MasterShipAction::PutUUID { id: user_id, uuid })
+            .run_action(MasterShipAction::SetGmLevel { id: user_id, level })
             .await?;
         match result {
             MasterShipAction::Ok => Ok(()),
@@ -557,31 +1146,1080 @@ impl Sql {
             _ => Err(Error::MSUnexpected),
         }
     }
-    pub async fn set_username(
+    pub async fn add_account_note(
         &self,
         user_id: u32,
-        nickname: &str,
-    ) -> Result<SetNicknameResult, Error> {
+        author_id: u32,
+        note: &str,
+    ) -> Result<(), Error> {
         let result = self
-            .run_action(MasterShipAction::SetNickname {
+            .run_action(MasterShipAction::AddAccountNote {
                 id: user_id,
+                author_id,
+                note: note.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Opens a support ticket on the master ship. Returns the ticket id.
+    pub async fn new_ticket(
+        &self,
+        user_id: u32,
+        nickname: &str,
+        message: &str,
+    ) -> Result<u32, Error> {
+        let result = self
+            .run_action(MasterShipAction::NewTicket {
+                user_id,
                 nickname: nickname.to_string(),
+                message: message.to_string(),
             })
             .await?;
         match result {
-            MasterShipAction::SetNicknameResult(res) => Ok(res),
+            MasterShipAction::TicketCreated(id) => Ok(id),
             MasterShipAction::Error(e) => Err(Error::MSError(e)),
             _ => Err(Error::MSUnexpected),
         }
     }
-    pub async fn set_account_data(&self, data: User) -> Result<(), Error> {
-        self.put_account_flags(data.id, data.accountflags).await?;
-        self.put_uuid(data.id, data.last_uuid).await?;
-        Ok(())
+    pub async fn get_open_tickets(&self) -> Result<Vec<SupportTicket>, Error> {
+        let result = self.run_action(MasterShipAction::GetOpenTickets).await?;
+        match result {
+            MasterShipAction::Tickets(tickets) => Ok(tickets),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
     }
-    async fn update_userdata<F>(&self, user_id: u32, f: F) -> Result<(), Error>
-    where
-        F: FnOnce(&mut UserData) + Send,
+    pub async fn claim_ticket(&self, id: u32, gm_id: u32) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::ClaimTicket { id, gm_id })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn resolve_ticket(&self, id: u32, gm_id: u32, resolution: &str) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::ResolveTicket {
+                id,
+                gm_id,
+                resolution: resolution.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn get_account_notes(&self, user_id: u32) -> Result<Vec<AccountNote>, Error> {
+        let result = self
+            .run_action(MasterShipAction::GetAccountNotes(user_id))
+            .await?;
+        match result {
+            MasterShipAction::AccountNotes(notes) => Ok(notes),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn set_username(
+        &self,
+        user_id: u32,
+        nickname: &str,
+    ) -> Result<SetNicknameResult, Error> {
+        let result = self
+            .run_action(MasterShipAction::SetNickname {
+                id: user_id,
+                nickname: nickname.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::SetNicknameResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Checks the current password and returns a password change challenge. Returns `None` if
+    /// the password is incorrect or there were too many attempts.
+    pub async fn new_password_challenge(
+        &self,
+        user_id: u32,
+        password: &str,
+    ) -> Result<Option<String>, Error> {
+        let result = self
+            .run_action(MasterShipAction::NewPasswordChallenge {
+                id: user_id,
+                password: password.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::PasswordChallengeResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn change_password(
+        &self,
+        user_id: u32,
+        challenge: &str,
+        password: &str,
+    ) -> Result<bool, Error> {
+        let result = self
+            .run_action(MasterShipAction::ChangePassword {
+                id: user_id,
+                challenge: challenge.to_string(),
+                password: password.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::PasswordChangeResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn change_nickname(
+        &self,
+        user_id: u32,
+        nickname: &str,
+    ) -> Result<ChangeNicknameResult, Error> {
+        let result = self
+            .run_action(MasterShipAction::ChangeNickname {
+                id: user_id,
+                nickname: nickname.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::ChangeNicknameResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn set_email(&self, user_id: u32, email: &str) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::SetEmail {
+                id: user_id,
+                email: email.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn verify_email(&self, user_id: u32, code: &str) -> Result<bool, Error> {
+        let result = self
+            .run_action(MasterShipAction::VerifyEmail {
+                id: user_id,
+                code: code.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::VerifyEmailResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn request_password_reset(&self, username: &str, ip: Ipv4Addr) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::RequestPasswordReset {
+                username: username.to_string(),
+                ip,
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Sets a new password using the reset code and logs in.
+    pub async fn confirm_reset(
+        &self,
+        username: &str,
+        code: &str,
+        password: &str,
+    ) -> Result<User, Error> {
+        let result = self
+            .run_action(MasterShipAction::ConfirmReset {
+                username: username.to_string(),
+                code: code.to_string(),
+                password: password.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::UserLoginResult(UserLoginResult::Success {
+                id,
+                nickname,
+                accountflags,
+                gm_level,
+                last_uuid,
+            }) => {
+                if sqlx::query("select Data from Users where Id = ?")
+                    .bind(id as i64)
+                    .fetch_optional(&self.connection)
+                    .await?
+                    .is_none()
+                {
+                    self.insert_local_user(id).await?;
+                }
+                Ok(User {
+                    id,
+                    nickname,
+                    accountflags,
+                    gm_level,
+                    last_uuid,
+                    ..Default::default()
+                })
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::InvalidPassword(_)) => {
+                Err(Error::InvalidPassword)
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::OtpRequired) => {
+                Err(Error::OtpRequired)
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::NotFound) => Err(Error::NoUser),
+            MasterShipAction::UserLoginResult(UserLoginResult::Banned { until, reason }) => {
+                Err(Error::Banned { until, reason })
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::Maintenance(notice)) => {
+                Err(Error::Maintenance(notice))
+            }
+            MasterShipAction::UserLoginResult(UserLoginResult::TooManyAttempts { retry_after }) => {
+                Err(Error::TooManyAttempts(retry_after))
+            }
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Generates a new pending TOTP secret and returns it in base32.
+    pub async fn new_totp_secret(&self, user_id: u32) -> Result<String, Error> {
+        let result = self
+            .run_action(MasterShipAction::NewTotpSecret(user_id))
+            .await?;
+        match result {
+            MasterShipAction::TotpSecretResult(secret) => Ok(secret),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn enable_totp(&self, user_id: u32, code: &str) -> Result<bool, Error> {
+        let result = self
+            .run_action(MasterShipAction::EnableTotp {
+                id: user_id,
+                code: code.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::TotpResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn disable_totp(&self, user_id: u32, code: &str) -> Result<bool, Error> {
+        let result = self
+            .run_action(MasterShipAction::DisableTotp {
+                id: user_id,
+                code: code.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::TotpResult(res) => Ok(res),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Sends an admin message to the ships. If `ships` is empty then all ships receive it.
+    pub async fn broadcast(&self, ships: Vec<u32>, message: &str) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::Broadcast {
+                ships,
+                message: message.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Gets a value from the account key-value store on the master ship.
+    pub async fn get_account_value(
+        &self,
+        user_id: u32,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let result = self
+            .run_action(MasterShipAction::GetAccountValue {
+                id: user_id,
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::AccountValue(value) => Ok(value),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Sets or deletes (if `value` is `None`) a value in the account key-value store.
+    pub async fn put_account_value(
+        &self,
+        user_id: u32,
+        namespace: &str,
+        key: &str,
+        value: Option<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::PutAccountValue {
+                id: user_id,
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                value,
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Returns all account merges done on the master ship as (from, into) pairs.
+    pub async fn get_account_merges(&self) -> Result<Vec<(u32, u32)>, Error> {
+        let result = self.run_action(MasterShipAction::GetAccountMerges).await?;
+        match result {
+            MasterShipAction::AccountMerges(merges) => Ok(merges),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Reserves `count` item UUIDs of the account. Returns the first reserved UUID.
+    pub async fn reserve_uuids(&self, user_id: u32, count: u64) -> Result<u64, Error> {
+        let result = self
+            .run_action(MasterShipAction::ReserveUUIDs { id: user_id, count })
+            .await?;
+        match result {
+            MasterShipAction::UUIDsReserved(first) => Ok(first),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Moves characters of the `from` account that was merged into the `into` account. Items of
+    /// moved characters get UUIDs of the target account. Characters that don't fit into the free
+    /// slots stay on the source account until a slot is freed. Both accounts must be offline.
+    pub async fn rehome_characters(&self, from: u32, into: u32) -> Result<(), Error> {
+        let Some(row) = sqlx::query("select Data from Users where Id = ?")
+            .bind(from as i64)
+            .fetch_optional(&self.connection)
+            .await?
+        else {
+            return Ok(());
+        };
+        let from_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        if from_data.character_ids.is_empty() {
+            return Ok(());
+        }
+        let into_chars = match sqlx::query("select Data from Users where Id = ?")
+            .bind(into as i64)
+            .fetch_optional(&self.connection)
+            .await?
+        {
+            Some(row) => rmp_serde::from_slice::<UserData>(row.try_get("Data")?)?
+                .character_ids
+                .len(),
+            None => 0,
+        };
+        let free = MAX_CHARACTERS.saturating_sub(into_chars);
+        if free < from_data.character_ids.len() {
+            log::warn!(
+                "User {into} has no free slots for {} characters of merged user {from}",
+                from_data.character_ids.len() - free
+            );
+        }
+        let mut chars = vec![];
+        for &char_id in from_data.character_ids.iter().take(free) {
+            let row = sqlx::query("select Data from Characters where Id = ?")
+                .bind(char_id as i64)
+                .fetch_one(&self.connection)
+                .await?;
+            let mut char: CharData = rmp_serde::from_slice(row.try_get("Data")?)?;
+            char.character.character_id = char_id;
+            chars.push(char);
+        }
+        if chars.is_empty() {
+            return Ok(());
+        }
+        let count = chars.iter().map(|c| c.inventory.item_count() as u64).sum();
+        let mut uuid = self.reserve_uuids(into, count).await?;
+        for char in &mut chars {
+            char.renumber_items(&mut uuid);
+        }
+
+        let mut transaction = self.connection.begin().await?;
+        // re-read the rows in the transaction in case they were changed in the meantime
+        let row = sqlx::query("select Data from Users where Id = ?")
+            .bind(from as i64)
+            .fetch_one(&mut *transaction)
+            .await?;
+        let mut from_data: UserData = rmp_serde::from_slice(row.try_get("Data")?)?;
+        let mut into_data = match sqlx::query("select Data from Users where Id = ?")
+            .bind(into as i64)
+            .fetch_optional(&mut *transaction)
+            .await?
+        {
+            Some(row) => rmp_serde::from_slice(row.try_get("Data")?)?,
+            None => UserData::default(),
+        };
+        let mut moved = 0;
+        for char in &chars {
+            let char_id = char.character.character_id;
+            if !from_data.character_ids.contains(&char_id)
+                || into_data.character_ids.len() >= MAX_CHARACTERS
+            {
+                continue;
+            }
+            sqlx::query("update Characters set Data = ? where Id = ?")
+                .bind(rmp_serde::to_vec(char)?)
+                .bind(char_id as i64)
+                .execute(&mut *transaction)
+                .await?;
+            from_data.character_ids.retain(|&id| id != char_id);
+            into_data.character_ids.push(char_id);
+            moved += 1;
+        }
+        for (id, data) in [(from, &from_data), (into, &into_data)] {
+            sqlx::query(
+                "insert into Users (Id, Data) values (?, ?)
+                on conflict (Id) do update set Data = excluded.Data",
+            )
+            .bind(id as i64)
+            .bind(rmp_serde::to_vec(data)?)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        log::info!("Moved {moved} characters of merged user {from} to user {into}");
+        Ok(())
+    }
+    pub async fn update_ship_status(
+        &self,
+        players: u32,
+        max_players: u32,
+        status: ShipStatus,
+        blocks: Vec<BlockStatus>,
+        errors: Vec<(String, u64)>,
+        objects: Vec<(String, u64)>,
+        disconnects: Vec<(String, u64)>,
+        traffic: Vec<(String, u64, u64)>,
+        queue: ShipQueue,
+    ) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::ShipStatusUpdate {
+                players,
+                max_players,
+                status,
+                blocks,
+                errors,
+                objects,
+                disconnects,
+                traffic,
+                queue,
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn report_stats(&self, stats: ShipStats) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::ShipStatsReport(stats))
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    pub async fn new_link_code(&self, user_id: u32) -> Result<String, Error> {
+        let result = self
+            .run_action(MasterShipAction::NewLinkCode(user_id))
+            .await?;
+        match result {
+            MasterShipAction::LinkCodeResult(code) => Ok(code),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Returns all relations of the player, including pending requests.
+    pub async fn get_friends(&self, user_id: u32) -> Result<Vec<Friend>, Error> {
+        let rows = sqlx::query("select * from Friends where UserId = ?")
+            .bind(user_id as i64)
+            .fetch_all(&self.connection)
+            .await?;
+        let mut friends = vec![];
+        for row in rows {
+            friends.push(Friend {
+                id: row.try_get::<i64, _>("FriendId")? as u32,
+                nickname: row.try_get("Nickname")?,
+                char_name: row.try_get("CharName")?,
+                status: FriendStatus::from_int(row.try_get("Status")?),
+            });
+        }
+        Ok(friends)
+    }
+    /// Sends a friend request. If the target already sent a request to the sender, then both
+    /// become friends. Returns the new status of the relation from the sender's point of view.
+    pub async fn add_friend_request(
+        &self,
+        from: &PlayerEntry,
+        to: &PlayerEntry,
+    ) -> Result<FriendStatus, Error> {
+        let mut transaction = self.connection.begin().await?;
+        let row = sqlx::query("select Status from Friends where UserId = ? and FriendId = ?")
+            .bind(from.id as i64)
+            .bind(to.id as i64)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let status = match row {
+            Some(row) => FriendStatus::from_int(row.try_get("Status")?),
+            None => {
+                for (user, friend, status) in [
+                    (from, to, FriendStatus::Outgoing),
+                    (to, from, FriendStatus::Incoming),
+                ] {
+                    sqlx::query(
+                        "insert into Friends (UserId, FriendId, Status, Nickname, CharName) \
+                        values (?, ?, ?, ?, ?)",
+                    )
+                    .bind(user.id as i64)
+                    .bind(friend.id as i64)
+                    .bind(status as i64)
+                    .bind(&friend.nickname)
+                    .bind(&friend.char_name)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                FriendStatus::Outgoing
+            }
+        };
+        transaction.commit().await?;
+        if status == FriendStatus::Incoming {
+            self.accept_friend(from.id, to.id).await?;
+            return Ok(FriendStatus::Accepted);
+        }
+        Ok(status)
+    }
+    /// Accepts the friend request. Returns false if there was no request from the friend.
+    pub async fn accept_friend(&self, user_id: u32, friend_id: u32) -> Result<bool, Error> {
+        let mut transaction = self.connection.begin().await?;
+        let result = sqlx::query(
+            "update Friends set Status = ? where UserId = ? and FriendId = ? and Status = ?",
+        )
+        .bind(FriendStatus::Accepted as i64)
+        .bind(user_id as i64)
+        .bind(friend_id as i64)
+        .bind(FriendStatus::Incoming as i64)
+        .execute(&mut *transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("update Friends set Status = ? where UserId = ? and FriendId = ?")
+            .bind(FriendStatus::Accepted as i64)
+            .bind(friend_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+    /// Removes the friend or the pending request in both directions. Returns false if there was
+    /// no relation.
+    pub async fn remove_friend(&self, user_id: u32, friend_id: u32) -> Result<bool, Error> {
+        let result = sqlx::query(
+            "delete from Friends where (UserId = ? and FriendId = ?) or (UserId = ? and FriendId = ?)",
+        )
+        .bind(user_id as i64)
+        .bind(friend_id as i64)
+        .bind(friend_id as i64)
+        .bind(user_id as i64)
+        .execute(&self.connection)
+        .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    /// Updates the names of the player stored in the friend lists of other players.
+    pub async fn update_friend_names(&self, entry: &PlayerEntry) -> Result<(), Error> {
+        sqlx::query("update Friends set Nickname = ?, CharName = ? where FriendId = ?")
+            .bind(&entry.nickname)
+            .bind(&entry.char_name)
+            .bind(entry.id as i64)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+    pub async fn log_anomaly(
+        &self,
+        user_id: u32,
+        kind: &str,
+        details: &str,
+        time: Duration,
+    ) -> Result<(), Error> {
+        sqlx::query("insert into Anomalies (UserId, Time, Kind, Details) values (?, ?, ?, ?)")
+            .bind(user_id as i64)
+            .bind(time.as_secs() as i64)
+            .bind(kind)
+            .bind(details)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+    /// Mutes the chat of the account. Fails if the GM level of the muting GM isn't higher than
+    /// the account's.
+    pub async fn set_mute(&self, user_id: u32, mute: &Mute) -> Result<(), Error> {
+        let result = self
+            .run_action(MasterShipAction::SetMute {
+                id: user_id,
+                mute: mute.clone(),
+            })
+            .await?;
+        match result {
+            MasterShipAction::Ok => Ok(()),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Removes the mute of the player. Returns false if the player wasn't muted.
+    pub async fn remove_mute(&self, user_id: u32, gm_id: u32) -> Result<bool, Error> {
+        let result = self
+            .run_action(MasterShipAction::RemoveMute { id: user_id, gm_id })
+            .await?;
+        match result {
+            MasterShipAction::MuteRemoved(removed) => Ok(removed),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Returns the mute of the player if it hasn't ended yet.
+    pub async fn get_mute(&self, user_id: u32, now: Duration) -> Result<Option<Mute>, Error> {
+        let result = self.run_action(MasterShipAction::GetMute(user_id)).await?;
+        match result {
+            MasterShipAction::MuteResult(mute) => Ok(mute.filter(|m| m.until > now)),
+            MasterShipAction::Error(e) => Err(Error::MSError(e)),
+            _ => Err(Error::MSUnexpected),
+        }
+    }
+    /// Records the completed trade. Sides are pairs of the player id, character id and offer.
+    pub async fn log_trade(
+        &self,
+        time: Duration,
+        side1: (u32, u32, &str),
+        side2: (u32, u32, &str),
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "insert into Trades (Time, UserId1, CharId1, Offer1, UserId2, CharId2, Offer2) \
+            values (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(time.as_secs() as i64)
+        .bind(side1.0 as i64)
+        .bind(side1.1 as i64)
+        .bind(side1.2)
+        .bind(side2.0 as i64)
+        .bind(side2.1 as i64)
+        .bind(side2.2)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+    /// Returns the latest trades of the player (or all players), newest first.
+    pub async fn get_trades(
+        &self,
+        user_id: Option<u32>,
+        limit: u32,
+    ) -> Result<Vec<TradeRecord>, Error> {
+        let rows = sqlx::query(
+            "select * from Trades where ? is null or UserId1 = ? or UserId2 = ? \
+            order by Id desc limit ?",
+        )
+        .bind(user_id.map(|id| id as i64))
+        .bind(user_id.map(|id| id as i64))
+        .bind(user_id.map(|id| id as i64))
+        .bind(limit as i64)
+        .fetch_all(&self.connection)
+        .await?;
+        let mut trades = vec![];
+        for row in rows {
+            trades.push(TradeRecord {
+                time: Duration::from_secs(row.try_get::<i64, _>("Time")? as u64),
+                users: (
+                    row.try_get::<i64, _>("UserId1")? as u32,
+                    row.try_get::<i64, _>("UserId2")? as u32,
+                ),
+                offers: (row.try_get("Offer1")?, row.try_get("Offer2")?),
+            });
+        }
+        Ok(trades)
+    }
+    /// Returns the latest anomalies of the player (or all players), newest first.
+    pub async fn get_anomalies(
+        &self,
+        user_id: Option<u32>,
+        limit: u32,
+    ) -> Result<Vec<Anomaly>, Error> {
+        let rows = sqlx::query(
+            "select * from Anomalies where ? is null or UserId = ? order by Id desc limit ?",
+        )
+        .bind(user_id.map(|id| id as i64))
+        .bind(user_id.map(|id| id as i64))
+        .bind(limit as i64)
+        .fetch_all(&self.connection)
+        .await?;
+        let mut anomalies = vec![];
+        for row in rows {
+            anomalies.push(Anomaly {
+                user_id: row.try_get::<i64, _>("UserId")? as u32,
+                time: Duration::from_secs(row.try_get::<i64, _>("Time")? as u64),
+                kind: row.try_get("Kind")?,
+                details: row.try_get("Details")?,
+            });
+        }
+        Ok(anomalies)
+    }
+    /// Returns the players blacklisted by the character.
+    pub async fn get_blacklist(&self, char_id: u32) -> Result<Vec<BlacklistEntry>, Error> {
+        let rows = sqlx::query("select * from Blacklist where CharacterId = ?")
+            .bind(char_id as i64)
+            .fetch_all(&self.connection)
+            .await?;
+        let mut entries = vec![];
+        for row in rows {
+            entries.push(BlacklistEntry {
+                id: row.try_get::<i64, _>("BlockedId")? as u32,
+                nickname: row.try_get("Nickname")?,
+            });
+        }
+        Ok(entries)
+    }
+    /// Adds the player to the blacklist of the character. Returns false if the player is already
+    /// blacklisted.
+    pub async fn add_to_blacklist(
+        &self,
+        char_id: u32,
+        player: &PlayerEntry,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            "insert or ignore into Blacklist (CharacterId, BlockedId, Nickname) values (?, ?, ?)",
+        )
+        .bind(char_id as i64)
+        .bind(player.id as i64)
+        .bind(&player.nickname)
+        .execute(&self.connection)
+        .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    pub async fn remove_from_blacklist(&self, char_id: u32, player_id: u32) -> Result<bool, Error> {
+        let result = sqlx::query("delete from Blacklist where CharacterId = ? and BlockedId = ?")
+            .bind(char_id as i64)
+            .bind(player_id as i64)
+            .execute(&self.connection)
+            .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    /// Stores the card in the collection of the character, replacing the older copy.
+    pub async fn add_player_card(&self, char_id: u32, card: &PlayerCard) -> Result<(), Error> {
+        sqlx::query(
+            "insert or replace into PlayerCards \
+            (OwnerId, CardCharId, UserId, CharName, Nickname, Comment, Received) \
+            values (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(char_id as i64)
+        .bind(card.char_id as i64)
+        .bind(card.user_id as i64)
+        .bind(&card.char_name)
+        .bind(&card.nickname)
+        .bind(&card.comment)
+        .bind(card.received.as_secs() as i64)
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+    /// Returns cards received by the character sorted by the character name.
+    pub async fn get_player_cards(&self, char_id: u32) -> Result<Vec<PlayerCard>, Error> {
+        let rows = sqlx::query(
+            "select * from PlayerCards where OwnerId = ? order by CharName collate nocase",
+        )
+        .bind(char_id as i64)
+        .fetch_all(&self.connection)
+        .await?;
+        let mut cards = vec![];
+        for row in rows {
+            cards.push(PlayerCard {
+                user_id: row.try_get::<i64, _>("UserId")? as u32,
+                char_id: row.try_get::<i64, _>("CardCharId")? as u32,
+                char_name: row.try_get("CharName")?,
+                nickname: row.try_get("Nickname")?,
+                comment: row.try_get("Comment")?,
+                received: Duration::from_secs(row.try_get::<i64, _>("Received")? as u64),
+            });
+        }
+        Ok(cards)
+    }
+    pub async fn remove_player_card(&self, char_id: u32, card_char_id: u32) -> Result<bool, Error> {
+        let result = sqlx::query("delete from PlayerCards where OwnerId = ? and CardCharId = ?")
+            .bind(char_id as i64)
+            .bind(card_char_id as i64)
+            .execute(&self.connection)
+            .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    /// Returns the team of the player.
+    pub async fn get_player_team(&self, user_id: u32) -> Result<Option<Team>, Error> {
+        let row = sqlx::query("select TeamId from TeamMembers where UserId = ?")
+            .bind(user_id as i64)
+            .fetch_optional(&self.connection)
+            .await?;
+        match row {
+            Some(row) => self.get_team(row.try_get::<i64, _>("TeamId")? as u32).await,
+            None => Ok(None),
+        }
+    }
+    pub async fn get_team(&self, team_id: u32) -> Result<Option<Team>, Error> {
+        let Some(row) = sqlx::query("select Name from Teams where Id = ?")
+            .bind(team_id as i64)
+            .fetch_optional(&self.connection)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let rows = sqlx::query("select * from TeamMembers where TeamId = ? order by Rank desc")
+            .bind(team_id as i64)
+            .fetch_all(&self.connection)
+            .await?;
+        let mut members = vec![];
+        for row in rows {
+            members.push(TeamMember {
+                id: row.try_get::<i64, _>("UserId")? as u32,
+                nickname: row.try_get("Nickname")?,
+                rank: TeamRank::from_int(row.try_get("Rank")?),
+            });
+        }
+        Ok(Some(Team {
+            id: team_id,
+            name: row.try_get("Name")?,
+            members,
+        }))
+    }
+    /// Creates the team with the player as its master. Returns `None` if the name is taken.
+    pub async fn create_team(
+        &self,
+        name: &str,
+        master: &PlayerEntry,
+    ) -> Result<Option<u32>, Error> {
+        let mut transaction = self.connection.begin().await?;
+        let result = sqlx::query("insert or ignore into Teams (Name) values (?)")
+            .bind(name)
+            .execute(&mut *transaction)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        let team_id = result.last_insert_rowid() as u32;
+        sqlx::query("insert into TeamMembers (UserId, TeamId, Rank, Nickname) values (?, ?, ?, ?)")
+            .bind(master.id as i64)
+            .bind(team_id as i64)
+            .bind(TeamRank::Master as i64)
+            .bind(&master.nickname)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(Some(team_id))
+    }
+    /// Adds the player to the team. Returns false if the player is already in a team.
+    pub async fn add_team_member(&self, team_id: u32, member: &PlayerEntry) -> Result<bool, Error> {
+        let result = sqlx::query(
+            "insert or ignore into TeamMembers (UserId, TeamId, Rank, Nickname) \
+            values (?, ?, ?, ?)",
+        )
+        .bind(member.id as i64)
+        .bind(team_id as i64)
+        .bind(TeamRank::Member as i64)
+        .bind(&member.nickname)
+        .execute(&self.connection)
+        .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    pub async fn remove_team_member(&self, team_id: u32, user_id: u32) -> Result<bool, Error> {
+        let result = sqlx::query("delete from TeamMembers where TeamId = ? and UserId = ?")
+            .bind(team_id as i64)
+            .bind(user_id as i64)
+            .execute(&self.connection)
+            .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    /// Sets the rank of the member. Promoting a member to master demotes the current master to
+    /// officer.
+    pub async fn set_team_rank(
+        &self,
+        team_id: u32,
+        user_id: u32,
+        rank: TeamRank,
+    ) -> Result<bool, Error> {
+        let mut transaction = self.connection.begin().await?;
+        if rank == TeamRank::Master {
+            sqlx::query("update TeamMembers set Rank = ? where TeamId = ? and Rank = ?")
+                .bind(TeamRank::Officer as i64)
+                .bind(team_id as i64)
+                .bind(TeamRank::Master as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        let result = sqlx::query("update TeamMembers set Rank = ? where TeamId = ? and UserId = ?")
+            .bind(rank as i64)
+            .bind(team_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        transaction.commit().await?;
+        Ok(true)
+    }
+    pub async fn delete_team(&self, team_id: u32) -> Result<(), Error> {
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("delete from TeamMembers where TeamId = ?")
+            .bind(team_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("delete from Teams where Id = ?")
+            .bind(team_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    /// Finds the character id by the character name (case insensitive).
+    pub async fn find_character(&self, name: &str) -> Result<Option<u32>, Error> {
+        let row = sqlx::query("select Id from Characters where Name = ? limit 1")
+            .bind(name)
+            .fetch_optional(&self.connection)
+            .await?;
+        row.map(|r| Ok(r.try_get::<i64, _>("Id")? as u32))
+            .transpose()
+    }
+    pub async fn send_mail(&self, char_id: u32, mail: &Mail) -> Result<(), Error> {
+        sqlx::query("insert into Mail (CharacterId, IsRead, Data) values (?, 0, ?)")
+            .bind(char_id as i64)
+            .bind(rmp_serde::to_vec(mail)?)
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+    /// Sends the mail to all characters of the ship. Returns the number of recipients.
+    pub async fn send_mass_mail(&self, mail: &Mail) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "insert into Mail (CharacterId, IsRead, Data) select Id, 0, ? from Characters",
+        )
+        .bind(rmp_serde::to_vec(mail)?)
+        .execute(&self.connection)
+        .await?;
+        Ok(result.rows_affected())
+    }
+    /// Returns mails of the character, newest first.
+    pub async fn get_mails(&self, char_id: u32) -> Result<Vec<Mail>, Error> {
+        let rows = sqlx::query("select * from Mail where CharacterId = ? order by Id desc")
+            .bind(char_id as i64)
+            .fetch_all(&self.connection)
+            .await?;
+        rows.iter().map(row_to_mail).collect()
+    }
+    /// Returns the mail and marks it as read.
+    pub async fn read_mail(&self, char_id: u32, mail_id: u32) -> Result<Option<Mail>, Error> {
+        let row = sqlx::query("select * from Mail where CharacterId = ? and Id = ?")
+            .bind(char_id as i64)
+            .bind(mail_id as i64)
+            .fetch_optional(&self.connection)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        sqlx::query("update Mail set IsRead = 1 where Id = ?")
+            .bind(mail_id as i64)
+            .execute(&self.connection)
+            .await?;
+        row_to_mail(&row).map(Some)
+    }
+    /// Removes the attachments from the mail and returns the mail with them.
+    pub async fn take_mail_attachments(
+        &self,
+        char_id: u32,
+        mail_id: u32,
+    ) -> Result<Option<Mail>, Error> {
+        let mut transaction = self.connection.begin().await?;
+        let row = sqlx::query("select * from Mail where CharacterId = ? and Id = ?")
+            .bind(char_id as i64)
+            .bind(mail_id as i64)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mail = row_to_mail(&row)?;
+        let emptied = Mail {
+            attachments: vec![],
+            augments: vec![],
+            meseta: 0,
+            ..mail.clone()
+        };
+        sqlx::query("update Mail set IsRead = 1, Data = ? where Id = ?")
+            .bind(rmp_serde::to_vec(&emptied)?)
+            .bind(mail_id as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(Some(mail))
+    }
+    /// Deletes the mail. Returns false if there was no such mail.
+    pub async fn delete_mail(&self, char_id: u32, mail_id: u32) -> Result<bool, Error> {
+        let result = sqlx::query("delete from Mail where CharacterId = ? and Id = ?")
+            .bind(char_id as i64)
+            .bind(mail_id as i64)
+            .execute(&self.connection)
+            .await?;
+        Ok(result.rows_affected() != 0)
+    }
+    pub async fn unread_mail_count(&self, char_id: u32) -> Result<u32, Error> {
+        let row =
+            sqlx::query("select count(*) as Count from Mail where CharacterId = ? and IsRead = 0")
+                .bind(char_id as i64)
+                .fetch_one(&self.connection)
+                .await?;
+        Ok(row.try_get::<i64, _>("Count")? as u32)
+    }
+    pub fn subscribe_broadcasts(&self) -> tokio::sync::broadcast::Receiver<String> {
+        self.master_ship.subscribe_broadcasts()
+    }
+    pub fn subscribe_tickets(&self) -> tokio::sync::broadcast::Receiver<SupportTicket> {
+        self.master_ship.subscribe_tickets()
+    }
+    pub fn subscribe_merges(&self) -> tokio::sync::broadcast::Receiver<(u32, u32)> {
+        self.master_ship.subscribe_merges()
+    }
+    pub async fn set_account_data(&self, data: User) -> Result<(), Error> {
+        self.put_account_flags(data.id, data.accountflags).await?;
+        self.put_uuid(data.id, data.last_uuid).await?;
+        Ok(())
+    }
+    async fn update_userdata<F>(&self, user_id: u32, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut UserData) + Send,
     {
         let mut transaction = self.connection.begin().await?;
         let row = sqlx::query("select Data from Users where Id = ?")
@@ -599,3 +2237,228 @@ impl Sql {
         Ok(())
     }
 }
+
+/// Returns the distinct symbol arts in the list, skipping empty slots.
+fn listed_symbol_arts(uuids: &[u128]) -> BTreeSet<u128> {
+    uuids.iter().copied().filter(|&u| u != 0).collect()
+}
+
+fn row_to_mail(row: &sqlx::sqlite::SqliteRow) -> Result<Mail, Error> {
+    let mut mail: Mail = rmp_serde::from_slice(row.try_get("Data")?)?;
+    mail.id = row.try_get::<i64, _>("Id")? as u32;
+    mail.is_read = row.try_get::<i64, _>("IsRead")? != 0;
+    Ok(mail)
+}
+
+impl FriendStatus {
+    fn from_int(status: i64) -> Self {
+        match status {
+            0 => Self::Outgoing,
+            1 => Self::Incoming,
+            _ => Self::Accepted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_db(path: &str) -> Sql {
+        let _ = std::fs::remove_file(path);
+        let db = Sql::new(&format!("sqlite:{path}"), MasterConnection::disconnected())
+            .await
+            .expect("Failed to create DB");
+        db.insert_local_user(1).await.unwrap();
+        db.insert_local_user(2).await.unwrap();
+        db
+    }
+
+    /// Returns the number of accounts listing the symbol art and whether it has data.
+    async fn symbol_art_refs(db: &Sql, uuid: u128) -> Option<(i64, bool)> {
+        let row = sqlx::query("select Users, Hash from SymbolArtRefs where UUID = ?")
+            .bind(format!("{uuid:X}").as_bytes())
+            .fetch_optional(&db.connection)
+            .await
+            .unwrap()?;
+        let hash: Option<Vec<u8>> = row.try_get("Hash").unwrap();
+        Some((row.try_get("Users").unwrap(), hash.is_some()))
+    }
+
+    async fn symbol_art_data_refs(db: &Sql, data: &[u8]) -> Option<i64> {
+        use sha2::Digest;
+        let row = sqlx::query("select Refs from SymbolArtData where Hash = ?")
+            .bind(sha2::Sha256::digest(data).to_vec())
+            .fetch_optional(&db.connection)
+            .await
+            .unwrap()?;
+        Some(row.try_get("Refs").unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_find_character() {
+        let db = test_db("test_find_character.db").await;
+        let mut char = CharData::default();
+        char.character.name = String::from("Alice");
+        let alice = db.put_character(1, char.clone()).await.unwrap();
+        char.character.name = String::from("Bob");
+        let bob = db.put_character(2, char).await.unwrap();
+
+        assert_eq!(db.find_character("alice").await.unwrap(), Some(alice));
+        assert_eq!(db.find_character("BOB").await.unwrap(), Some(bob));
+        assert_eq!(db.find_character("Carol").await.unwrap(), None);
+
+        let mut char = db.get_characters(2).await.unwrap().remove(0);
+        char.character.name = String::from("Carol");
+        db.update_character(&char).await.unwrap();
+        assert_eq!(db.find_character("Bob").await.unwrap(), None);
+        assert_eq!(db.find_character("carol").await.unwrap(), Some(bob));
+
+        let _ = std::fs::remove_file("test_find_character.db");
+    }
+
+    #[tokio::test]
+    async fn test_mail_attachments() {
+        let db = test_db("test_mail_attachments.db").await;
+        let mut mail = Mail::new("Alice", "Hi", Duration::ZERO);
+        mail.meseta = 100;
+        db.send_mail(1, &mail).await.unwrap();
+        let id = db.get_mails(1).await.unwrap()[0].id;
+
+        // reading keeps the attachments, taking them empties the mail
+        let read = db.read_mail(1, id).await.unwrap().unwrap();
+        assert_eq!(read.meseta, 100);
+        assert_eq!(db.read_mail(2, id).await.unwrap().map(|m| m.id), None);
+        let taken = db.take_mail_attachments(1, id).await.unwrap().unwrap();
+        assert_eq!(taken.meseta, 100);
+        let taken = db.take_mail_attachments(1, id).await.unwrap().unwrap();
+        assert!(!taken.has_attachments());
+
+        let _ = std::fs::remove_file("test_mail_attachments.db");
+    }
+
+    #[tokio::test]
+    async fn test_ignores_migration() {
+        let db = test_db("test_ignores_migration.db").await;
+        let first = db.put_character(1, CharData::default()).await.unwrap();
+        let second = db.put_character(1, CharData::default()).await.unwrap();
+        let other = db.put_character(2, CharData::default()).await.unwrap();
+        db.connection
+            .execute(
+                "
+            create table Ignores (
+                UserId integer,
+                IgnoredId integer,
+                primary key (UserId, IgnoredId)
+            );
+            insert into Ignores (UserId, IgnoredId) values (1, 10), (1, 11);
+        ",
+            )
+            .await
+            .unwrap();
+
+        Sql::update_db(&db.connection)
+            .await
+            .expect("Failed to migrate DB");
+        for char_id in [first, second] {
+            let mut ids: Vec<_> = db
+                .get_blacklist(char_id)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| e.id)
+                .collect();
+            ids.sort();
+            assert_eq!(ids, [10, 11]);
+        }
+        assert!(db.get_blacklist(other).await.unwrap().is_empty());
+        let exists = sqlx::query("select 1 from sqlite_master where name = 'Ignores'")
+            .fetch_optional(&db.connection)
+            .await
+            .unwrap();
+        assert!(exists.is_none());
+
+        let _ = std::fs::remove_file("test_ignores_migration.db");
+    }
+
+    #[tokio::test]
+    async fn test_symbol_art_refs() {
+        let db = test_db("test_symbol_art_refs.db").await;
+
+        // repeated symbol arts are referenced once per account
+        db.set_symbol_art_list(vec![1, 1, 2, 0], 1).await.unwrap();
+        db.set_symbol_art_list(vec![1, 2, 1, 0], 1).await.unwrap();
+        assert_eq!(symbol_art_refs(&db, 1).await, Some((1, false)));
+        assert_eq!(symbol_art_refs(&db, 0).await, None);
+
+        // data is only accepted from accounts listing the symbol art, and only once
+        db.add_symbol_art(2, 1, b"sa", "sa").await.unwrap();
+        assert_eq!(db.get_symbol_art(1).await.unwrap(), None);
+        db.add_symbol_art(1, 1, b"sa", "sa").await.unwrap();
+        db.add_symbol_art(1, 1, b"other", "other").await.unwrap();
+        assert_eq!(db.get_symbol_art(1).await.unwrap(), Some(b"sa".to_vec()));
+        assert_eq!(symbol_art_data_refs(&db, b"sa").await, Some(1));
+        assert_eq!(symbol_art_data_refs(&db, b"other").await, None);
+
+        // identical data is stored once
+        db.add_symbol_art(1, 2, b"sa", "copy").await.unwrap();
+        assert_eq!(symbol_art_data_refs(&db, b"sa").await, Some(2));
+
+        db.set_symbol_art_list(vec![1], 2).await.unwrap();
+        assert_eq!(symbol_art_refs(&db, 1).await, Some((2, true)));
+        db.set_symbol_art_list(vec![0; 10], 1).await.unwrap();
+        assert_eq!(symbol_art_refs(&db, 1).await, Some((1, true)));
+        assert_eq!(symbol_art_refs(&db, 2).await, None);
+        assert_eq!(symbol_art_data_refs(&db, b"sa").await, Some(1));
+        db.set_symbol_art_list(vec![0; 10], 2).await.unwrap();
+        assert_eq!(symbol_art_refs(&db, 1).await, None);
+        assert_eq!(symbol_art_data_refs(&db, b"sa").await, None);
+        assert_eq!(db.get_symbol_art(1).await.unwrap(), None);
+
+        let _ = std::fs::remove_file("test_symbol_art_refs.db");
+    }
+
+    #[tokio::test]
+    async fn test_symbol_art_migration() {
+        let db = test_db("test_symbol_art_migration.db").await;
+        db.update_userdata(1, |u| u.symbol_arts = vec![1, 2, 2, 0])
+            .await
+            .unwrap();
+        db.update_userdata(2, |u| u.symbol_arts = vec![1, 0])
+            .await
+            .unwrap();
+        db.connection
+            .execute(
+                "
+            create table SymbolArts (
+                UUID blob,
+                Name blob,
+                Data blob
+            );
+        ",
+            )
+            .await
+            .unwrap();
+        for (uuid, data) in [(1u128, b"sa1"), (1, b"sa2"), (3, b"sa3")] {
+            sqlx::query("insert into SymbolArts (UUID, Name, Data) values (?, ?, ?)")
+                .bind(format!("{uuid:X}").as_bytes())
+                .bind(b"name".as_slice())
+                .bind(data.as_slice())
+                .execute(&db.connection)
+                .await
+                .unwrap();
+        }
+
+        Sql::update_db(&db.connection)
+            .await
+            .expect("Failed to migrate DB");
+        assert_eq!(symbol_art_refs(&db, 1).await, Some((2, true)));
+        // listed symbol arts without data can still be uploaded later
+        assert_eq!(symbol_art_refs(&db, 2).await, Some((1, false)));
+        assert_eq!(symbol_art_refs(&db, 3).await, None);
+        assert_eq!(db.get_symbol_art(1).await.unwrap(), Some(b"sa1".to_vec()));
+        assert_eq!(symbol_art_data_refs(&db, b"sa3").await, None);
+
+        let _ = std::fs::remove_file("test_symbol_art_migration.db");
+    }
+}
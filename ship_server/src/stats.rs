@@ -0,0 +1,36 @@
+//! Aggregate statistics periodically reported to the master ship.
+use crate::events::{self, EventBus, GameEvent};
+use data_structs::master_ship::ShipStats;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+static PEAK_PLAYERS: AtomicU32 = AtomicU32::new(0);
+static QUESTS: AtomicU64 = AtomicU64::new(0);
+static MESETA: AtomicU64 = AtomicU64::new(0);
+
+/// Updates the peak number of concurrent players.
+pub fn update_players(players: u32) {
+    PEAK_PLAYERS.fetch_max(players, Ordering::Relaxed);
+}
+
+/// Starts collecting statistics from game events.
+pub fn subscribe(bus: &EventBus) {
+    events::spawn_subscriber(bus, "stats", |event| match event {
+        GameEvent::QuestStarted { .. } => {
+            QUESTS.fetch_add(1, Ordering::Relaxed);
+        }
+        GameEvent::MesetaCreated { amount, .. } => {
+            MESETA.fetch_add(amount, Ordering::Relaxed);
+        }
+        _ => {}
+    });
+}
+
+/// Returns statistics collected since the last call and starts a new period with the current
+/// number of players.
+pub fn take(players: u32) -> ShipStats {
+    ShipStats {
+        peak_players: PEAK_PLAYERS.swap(players, Ordering::Relaxed).max(players),
+        quests: QUESTS.swap(0, Ordering::Relaxed),
+        meseta: MESETA.swap(0, Ordering::Relaxed),
+    }
+}
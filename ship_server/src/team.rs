@@ -0,0 +1,80 @@
+//! Teams (alliances) of players. Unlike parties, teams are persistent and keep offline members.
+
+/// Maximum number of team members.
+pub const MAX_MEMBERS: usize = 100;
+/// Maximum length of the team name in characters.
+pub const MAX_NAME_LEN: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Team {
+    pub id: u32,
+    pub name: String,
+    pub members: Vec<TeamMember>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TeamMember {
+    /// Player id of the member.
+    pub id: u32,
+    pub nickname: String,
+    pub rank: TeamRank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TeamRank {
+    Member = 0,
+    /// Can invite and kick members.
+    Officer = 1,
+    /// Leader of the team. Each team has exactly one master.
+    Master = 2,
+}
+
+/// Pending invitation to a team.
+#[derive(Debug, Clone)]
+pub struct TeamInvite {
+    pub team_id: u32,
+    pub team_name: String,
+    /// Nickname of the inviter.
+    pub inviter: String,
+}
+
+impl Team {
+    pub fn member(&self, id: u32) -> Option<&TeamMember> {
+        self.members.iter().find(|m| m.id == id)
+    }
+    /// Finds the member by the player id or nickname.
+    pub fn find_member(&self, name: &str) -> Option<&TeamMember> {
+        let id = name.parse::<u32>().ok();
+        self.members
+            .iter()
+            .find(|m| Some(m.id) == id || m.nickname.eq_ignore_ascii_case(name))
+    }
+}
+
+impl TeamRank {
+    pub fn from_int(rank: i64) -> Self {
+        match rank {
+            2 => Self::Master,
+            1 => Self::Officer,
+            _ => Self::Member,
+        }
+    }
+    pub fn parse(rank: &str) -> Option<Self> {
+        match rank.to_ascii_lowercase().as_str() {
+            "member" => Some(Self::Member),
+            "officer" => Some(Self::Officer),
+            "master" => Some(Self::Master),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TeamRank {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Member => write!(f, "Member"),
+            Self::Officer => write!(f, "Officer"),
+            Self::Master => write!(f, "Master"),
+        }
+    }
+}
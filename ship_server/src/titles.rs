@@ -0,0 +1,110 @@
+//! Titles earned by reaching conditions defined in the server data.
+use crate::{
+    directory::PlayerDirectory,
+    events::{self, EventBus, GameEvent},
+    user::handlers,
+};
+use data_structs::title::{Title, TitleProgress};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Title progress of a character.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Titles {
+    /// Earned title ids.
+    pub earned: Vec<u32>,
+    /// Titles with claimed rewards.
+    pub claimed: Vec<u32>,
+    /// Title shown to other players.
+    pub equipped: Option<u32>,
+    pub quests_cleared: u64,
+    pub enemies_killed: u64,
+}
+
+impl Titles {
+    /// Updates the progress with the event and returns newly earned titles.
+    pub fn record<'a>(&mut self, event: &GameEvent, titles: &'a [Title]) -> Vec<&'a Title> {
+        let mut level = None;
+        match event {
+            GameEvent::EnemyKilled { .. } => self.enemies_killed += 1,
+            GameEvent::QuestCleared { .. } => self.quests_cleared += 1,
+            GameEvent::LevelUp {
+                class, level: l, ..
+            } => level = Some((*class, *l)),
+            _ => return vec![],
+        }
+        let progress = TitleProgress {
+            level,
+            quests_cleared: self.quests_cleared,
+            enemies_killed: self.enemies_killed,
+        };
+        let earned: Vec<_> = titles
+            .iter()
+            .filter(|t| !self.earned.contains(&t.id) && t.is_met(&progress))
+            .collect();
+        self.earned.extend(earned.iter().map(|t| t.id));
+        earned
+    }
+}
+
+/// Starts tracking title conditions of online players.
+pub fn subscribe(bus: &EventBus, directory: Arc<PlayerDirectory>) {
+    events::spawn_user_subscriber(
+        bus,
+        "titles",
+        directory,
+        |event| {
+            matches!(
+                event,
+                GameEvent::EnemyKilled { .. }
+                    | GameEvent::QuestCleared { .. }
+                    | GameEvent::LevelUp { .. }
+            )
+        },
+        |user, event| Box::pin(handlers::titles::on_event(user, event)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Titles;
+    use crate::events::GameEvent;
+    use data_structs::title::{Title, TitleCondition};
+    use pso2packetlib::protocol::models::character::Class;
+
+    #[test]
+    fn test_record() {
+        let titles = vec![
+            Title {
+                id: 1,
+                condition: TitleCondition::EnemiesKilled,
+                value: 2,
+                ..Default::default()
+            },
+            Title {
+                id: 2,
+                condition: TitleCondition::Level,
+                value: 10,
+                ..Default::default()
+            },
+        ];
+        let kill = GameEvent::EnemyKilled {
+            player_id: 1,
+            exp: 10,
+        };
+        let mut progress = Titles::default();
+        assert!(progress.record(&kill, &titles).is_empty());
+        assert_eq!(progress.record(&kill, &titles)[0].id, 1);
+        // already earned
+        assert!(progress.record(&kill, &titles).is_empty());
+        assert_eq!(progress.enemies_killed, 3);
+        let level_up = GameEvent::LevelUp {
+            player_id: 1,
+            class: Class::Hunter,
+            level: 10,
+        };
+        assert_eq!(progress.record(&level_up, &titles)[0].id, 2);
+        assert_eq!(progress.earned, vec![1, 2]);
+    }
+}
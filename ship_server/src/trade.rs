@@ -0,0 +1,118 @@
+//! Item trading between players.
+
+/// Maximum number of different items in one offer.
+pub const MAX_ITEMS: usize = 10;
+
+/// Trade of one side.
+#[derive(Debug, Clone)]
+pub struct Trade {
+    /// Player id of the other side.
+    pub partner: u32,
+    pub state: TradeState,
+    pub offer: TradeOffer,
+}
+
+/// Items and meseta offered by one side.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TradeOffer {
+    /// Uuids and amounts of the offered inventory items.
+    pub items: Vec<(u64, u16)>,
+    pub meseta: u64,
+}
+
+/// State of one side of the trade. The trade is completed in two phases: both sides lock their
+/// offers and then both confirm them. Changing an offer returns both sides to `Open`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeState {
+    /// Trade request sent, waiting for the partner to accept it.
+    Requested,
+    /// Offer can be changed.
+    Open,
+    /// Offer is locked, waiting for the partner to lock theirs.
+    Locked,
+    /// Final confirmation given.
+    Confirmed,
+}
+
+impl Trade {
+    pub fn new(partner: u32, state: TradeState) -> Self {
+        Self {
+            partner,
+            state,
+            offer: TradeOffer::default(),
+        }
+    }
+}
+
+impl TradeOffer {
+    /// Adds the item to the offer, replacing the amount if it's already offered. The amount should
+    /// be between 1 and the `owned` amount of the item.
+    pub fn set_item(&mut self, uuid: u64, amount: u16, owned: u16) -> Result<(), &'static str> {
+        if amount == 0 || amount > owned {
+            return Err("Invalid amount");
+        }
+        if let Some(item) = self.items.iter_mut().find(|(u, _)| *u == uuid) {
+            item.1 = amount;
+            return Ok(());
+        }
+        if self.items.len() >= MAX_ITEMS {
+            return Err("Your offer is full");
+        }
+        self.items.push((uuid, amount));
+        Ok(())
+    }
+    pub fn remove_item(&mut self, uuid: u64) -> bool {
+        let len = self.items.len();
+        self.items.retain(|(u, _)| *u != uuid);
+        self.items.len() != len
+    }
+}
+
+/// Next state of the side that locks its offer.
+pub fn lock(own: TradeState) -> Option<TradeState> {
+    match own {
+        TradeState::Open => Some(TradeState::Locked),
+        _ => None,
+    }
+}
+
+/// Next state of the side that confirms the trade. Confirmation requires both offers to be
+/// locked.
+pub fn confirm(own: TradeState, partner: TradeState) -> Option<TradeState> {
+    match (own, partner) {
+        (TradeState::Locked, TradeState::Locked | TradeState::Confirmed) => {
+            Some(TradeState::Confirmed)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trade_states() {
+        use TradeState::*;
+        assert_eq!(lock(Open), Some(Locked));
+        assert_eq!(lock(Requested), None);
+        assert_eq!(confirm(Locked, Open), None);
+        assert_eq!(confirm(Locked, Locked), Some(Confirmed));
+        assert_eq!(confirm(Locked, Confirmed), Some(Confirmed));
+        assert_eq!(confirm(Open, Locked), None);
+
+        let mut offer = TradeOffer::default();
+        assert!(offer.set_item(1, 5, 5).is_ok());
+        assert!(offer.set_item(1, 2, 5).is_ok());
+        assert_eq!(offer.items, vec![(1, 2)]);
+        assert!(offer.set_item(1, 6, 5).is_err());
+        assert!(offer.set_item(1, 0, 5).is_err());
+        assert_eq!(offer.items, vec![(1, 2)]);
+        for uuid in 2..=MAX_ITEMS as u64 {
+            assert!(offer.set_item(uuid, 1, 1).is_ok());
+        }
+        assert!(offer.set_item(100, 1, 1).is_err());
+        assert!(offer.remove_item(1));
+        assert!(!offer.remove_item(1));
+    }
+}
@@ -0,0 +1,112 @@
+//! Lobby actions and stamps unlocked by ticket items.
+use pso2packetlib::protocol::items::ItemId;
+use serde::{Deserialize, Serialize};
+
+/// Unlockable content settings.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct UnlockSettings {
+    /// Ticket items. Lobby actions listed here are locked until the ticket is used.
+    pub tickets: Vec<UnlockTicket>,
+}
+
+/// Item that unlocks content when used.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnlockTicket {
+    pub item_type: u8,
+    pub id: u16,
+    pub subid: u16,
+    pub unlock: Unlock,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Unlock {
+    /// Lobby action by its movement action name.
+    LobbyAction(String),
+    Stamp(u32),
+}
+
+/// Content unlocked by a character.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Unlocks {
+    pub lobby_actions: Vec<String>,
+    pub stamps: Vec<u32>,
+}
+
+impl UnlockSettings {
+    /// Returns the content unlocked by the item if it's a ticket.
+    pub fn ticket(&self, item: ItemId) -> Option<&Unlock> {
+        self.tickets
+            .iter()
+            .find(|t| t.item_type == item.item_type && t.id == item.id && t.subid == item.subid)
+            .map(|t| &t.unlock)
+    }
+    /// Checks if the lobby action requires a ticket.
+    pub fn is_locked_action(&self, action: &str) -> bool {
+        self.tickets
+            .iter()
+            .any(|t| matches!(&t.unlock, Unlock::LobbyAction(a) if a == action))
+    }
+}
+
+impl Unlocks {
+    pub fn has(&self, unlock: &Unlock) -> bool {
+        match unlock {
+            Unlock::LobbyAction(action) => self.lobby_actions.contains(action),
+            Unlock::Stamp(stamp) => self.stamps.contains(stamp),
+        }
+    }
+    /// Adds the content. Returns false if it was already unlocked.
+    pub fn add(&mut self, unlock: Unlock) -> bool {
+        if self.has(&unlock) {
+            return false;
+        }
+        match unlock {
+            Unlock::LobbyAction(action) => self.lobby_actions.push(action),
+            Unlock::Stamp(stamp) => self.stamps.push(stamp),
+        }
+        true
+    }
+}
+
+impl std::fmt::Display for Unlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LobbyAction(action) => write!(f, "lobby action {action}"),
+            Self::Stamp(stamp) => write!(f, "stamp {stamp}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlocks() {
+        let settings = UnlockSettings {
+            tickets: vec![UnlockTicket {
+                item_type: 3,
+                id: 2,
+                subid: 1,
+                unlock: Unlock::LobbyAction("dance".into()),
+            }],
+        };
+        let ticket = ItemId {
+            item_type: 3,
+            id: 2,
+            subid: 1,
+            ..Default::default()
+        };
+        assert!(settings.is_locked_action("dance"));
+        assert!(!settings.is_locked_action("wave"));
+        let unlock = settings.ticket(ticket).cloned().unwrap();
+        let mut unlocks = Unlocks::default();
+        assert!(!unlocks.has(&unlock));
+        assert!(unlocks.add(unlock.clone()));
+        assert!(!unlocks.add(unlock.clone()));
+        assert!(unlocks.has(&unlock));
+    }
+}
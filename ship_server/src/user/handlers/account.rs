@@ -0,0 +1,84 @@
+use crate::{Error, User};
+use data_structs::master_ship::ChangeNicknameResult;
+
+/// Maximum length of the nickname in characters.
+const MAX_NICKNAME_LEN: usize = 16;
+
+/// Re-authenticates the player with the current password before a password change.
+pub async fn change_password(user: &mut User, password: &str) -> Result<(), Error> {
+    if password.is_empty() {
+        return user.send_system_msg("No password provided").await;
+    }
+    let id = user.get_user_id();
+    user.password_challenge = None;
+    match user
+        .blockdata
+        .sql
+        .new_password_challenge(id, password)
+        .await?
+    {
+        Some(challenge) => {
+            user.password_challenge = Some(challenge);
+            user.send_system_msg(
+                "Use !newpassword <password> within 10 minutes to set a new password",
+            )
+            .await
+        }
+        None => {
+            user.send_system_msg("Incorrect password or too many attempts")
+                .await
+        }
+    }
+}
+
+/// Sets a new password after [`change_password`].
+pub async fn new_password(user: &mut User, password: &str) -> Result<(), Error> {
+    if password.is_empty() {
+        return user.send_system_msg("No password provided").await;
+    }
+    let Some(challenge) = user.password_challenge.take() else {
+        return user
+            .send_system_msg("Use !changepassword <current password> first")
+            .await;
+    };
+    let id = user.get_user_id();
+    if user
+        .blockdata
+        .sql
+        .change_password(id, &challenge, password)
+        .await?
+    {
+        user.send_system_msg("Password changed").await
+    } else {
+        user.send_system_msg("Password change has expired, use !changepassword again")
+            .await
+    }
+}
+
+/// Changes the account nickname.
+pub async fn change_nickname(user: &mut User, nickname: &str) -> Result<(), Error> {
+    let len = nickname.chars().count();
+    if len == 0 || len > MAX_NICKNAME_LEN {
+        return user
+            .send_system_msg(&format!(
+                "Nickname should be 1 to {MAX_NICKNAME_LEN} characters long"
+            ))
+            .await;
+    }
+    let id = user.get_user_id();
+    match user.blockdata.sql.change_nickname(id, nickname).await? {
+        ChangeNicknameResult::Ok => {
+            user.user_data.nickname = nickname.to_string();
+            user.blockdata.directory.set_nickname(id, nickname);
+            user.send_system_msg("Nickname changed").await
+        }
+        ChangeNicknameResult::AlreadyTaken => {
+            user.send_system_msg("Nickname is already taken").await
+        }
+        ChangeNicknameResult::Cooldown(left) => {
+            let hours = left.as_secs().div_ceil(3600);
+            user.send_system_msg(&format!("Nickname can be changed again in {hours} hour(s)"))
+                .await
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Special ability (affix) transfer.
+use crate::{inventory::Inventory, Error, User};
+use data_structs::affix::AffixData;
+use pso2packetlib::protocol::{items::ItemType, Packet};
+use rand::Rng;
+use std::collections::BTreeMap;
+
+/// Selects the item for affixing. The first selected item is the target, others are sources.
+pub async fn select(user: &mut User, item: &str) -> Result<(), Error> {
+    let Some(uuid) = super::item::find_inv_item(user, item) else {
+        return user.send_system_msg("Item not found").await;
+    };
+    if user.affix_items.contains(&uuid) {
+        return user
+            .send_system_msg("Item is already selected, use the item uuid to select another one")
+            .await;
+    }
+    let inventory = &user.character.as_ref().unwrap().inventory;
+    if !user.affix_items.is_empty() && inventory.is_equiped(uuid) {
+        return user
+            .send_system_msg("Equiped items can't be used as sources")
+            .await;
+    }
+    user.affix_items.push(uuid);
+    let blockdata = user.blockdata.clone();
+    let data = &blockdata.server_data;
+    let inventory = &user.character.as_ref().unwrap().inventory;
+    let describe = |uuid: u64| {
+        let name = inventory
+            .get_inv_item(uuid)
+            .map(|i| super::shop::item_name(&data.item_params.names, i.id))
+            .unwrap_or_default();
+        let abilities: Vec<_> = inventory
+            .augments(uuid)
+            .iter()
+            .map(|&id| ability_name(&data.affixes, id))
+            .collect();
+        format!("{name} [{}]", abilities.join(", "))
+    };
+    let mut msg = format!("Target: {}", describe(user.affix_items[0]));
+    if user.affix_items.len() > 1 {
+        let sources: Vec<_> = user.affix_items[1..].iter().map(|&u| describe(u)).collect();
+        msg.push_str(&format!("\nSources: {}", sources.join(", ")));
+        let available: Vec<_> = ability_counts(user)
+            .into_iter()
+            .map(|(id, count)| format!("{id} {} (x{count})", ability_name(&data.affixes, id)))
+            .collect();
+        msg.push_str(&format!(
+            "\nAvailable abilities: {}\nUse !affix_preview <ability ids> to see success rates",
+            available.join(", ")
+        ));
+    } else {
+        msg.push_str("\nSelect source items with !affix_select <item>");
+    }
+    user.send_system_msg(&msg).await
+}
+
+pub async fn cancel(user: &mut User) -> Result<(), Error> {
+    user.affix_items.clear();
+    user.send_system_msg("Affix selection cleared").await
+}
+
+/// Shows success rates of the chosen abilities.
+pub async fn preview(user: &mut User, chosen: &[u16]) -> Result<(), Error> {
+    let rates = match success_rates(user, chosen) {
+        Ok(rates) => rates,
+        Err(e) => return user.send_system_msg(&e).await,
+    };
+    let blockdata = user.blockdata.clone();
+    let affixes = &blockdata.server_data.affixes;
+    let lines: Vec<_> = rates
+        .iter()
+        .map(|&(id, rate)| format!("{} - {rate}%", ability_name(affixes, id)))
+        .collect();
+    let fee = affixes.fee_per_source * (user.affix_items.len() - 1) as u64;
+    user.send_system_msg(&format!(
+        "{}\nFee: {fee} meseta\nUse !affix <ability ids> to start",
+        lines.join("\n")
+    ))
+    .await
+}
+
+/// Transfers the chosen abilities to the target item. Source items are consumed.
+pub async fn affix(user: &mut User, chosen: &[u16]) -> Result<(), Error> {
+    let rates = match success_rates(user, chosen) {
+        Ok(rates) => rates,
+        Err(e) => return user.send_system_msg(&e).await,
+    };
+    let blockdata = user.blockdata.clone();
+    let affixes = &blockdata.server_data.affixes;
+    let results: Vec<_> = {
+        let mut rng = rand::thread_rng();
+        rates
+            .into_iter()
+            .map(|(id, rate)| (id, rng.gen_range(0..100) < rate))
+            .collect()
+    };
+    let target = user.affix_items[0];
+    let sources = user.affix_items[1..].to_vec();
+    let fee = affixes.fee_per_source * sources.len() as u64;
+    let character = user.character.as_mut().unwrap();
+    let Some(meseta_packet) = character.inventory.take_meseta(fee) else {
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    let augments = results
+        .iter()
+        .filter(|(_, success)| *success)
+        .map(|(id, _)| *id)
+        .collect();
+    let packets = match character.inventory.affix(target, &sources, augments) {
+        Ok(packets) => packets,
+        Err(e) => {
+            character.inventory.add_meseta(fee);
+            return Err(e);
+        }
+    };
+    blockdata.sql.update_character(character).await?;
+    user.affix_items.clear();
+    user.send_packet(&meseta_packet).await?;
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    let lines: Vec<_> = results
+        .iter()
+        .map(|&(id, success)| {
+            let result = if success { "success" } else { "failed" };
+            format!("{} - {result}", ability_name(affixes, id))
+        })
+        .collect();
+    user.send_system_msg(&format!("Affixing finished:\n{}", lines.join("\n")))
+        .await
+}
+
+/// Returns success rates of the chosen abilities or a reason why they can't be transferred.
+fn success_rates(user: &mut User, chosen: &[u16]) -> Result<Vec<(u16, u8)>, String> {
+    let inventory = &user.character.as_ref().unwrap().inventory;
+    if user
+        .affix_items
+        .iter()
+        .any(|&uuid| inventory.get_inv_item(uuid).is_err())
+    {
+        user.affix_items.clear();
+        return Err("Selected items are no longer in the inventory, select them again".into());
+    }
+    if user.affix_items.len() < 2 {
+        return Err("Select the target and at least one source item with !affix_select".into());
+    }
+    let affixes = &user.blockdata.server_data.affixes;
+    if chosen.is_empty() {
+        return Err("No abilities chosen".into());
+    }
+    if chosen.len() > affixes.max_slots {
+        return Err(format!(
+            "Too many abilities, the maximum is {}",
+            affixes.max_slots
+        ));
+    }
+    let counts = ability_counts(user);
+    let mut rates = Vec::with_capacity(chosen.len());
+    for (i, &id) in chosen.iter().enumerate() {
+        if chosen[..i].contains(&id) {
+            return Err(format!("Ability {id} is chosen twice"));
+        }
+        let count = counts.get(&id).copied().unwrap_or_default();
+        if count == 0 {
+            return Err(format!("Ability {id} isn't on the selected items"));
+        }
+        rates.push((id, affixes.success_rate(id, count, chosen)));
+    }
+    Ok(rates)
+}
+
+/// Returns the number of selected items with each ability.
+fn ability_counts(user: &User) -> BTreeMap<u16, usize> {
+    let inventory = &user.character.as_ref().unwrap().inventory;
+    let mut counts = BTreeMap::new();
+    for &uuid in &user.affix_items {
+        for &id in inventory.augments(uuid) {
+            *counts.entry(id).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Rolls abilities of weapons and units added by the packets (e.g. bought or crafted items).
+pub fn roll_new_items(inventory: &mut Inventory, affixes: &AffixData, packets: &[Packet]) {
+    let mut rng = rand::thread_rng();
+    for packet in packets {
+        let Packet::AddedItem(added) = packet else {
+            continue;
+        };
+        if !matches!(added.item.data, ItemType::Weapon(_) | ItemType::Unit(_)) {
+            continue;
+        }
+        let augments = affixes.roll(|rate| rng.gen_range(0..100) < rate);
+        if !augments.is_empty() {
+            inventory.augments.insert(added.item.uuid, augments);
+        }
+    }
+}
+
+fn ability_name(affixes: &AffixData, id: u16) -> String {
+    match affixes.ability(id) {
+        Some(ability) => ability.name.clone(),
+        None => format!("Ability {id}"),
+    }
+}
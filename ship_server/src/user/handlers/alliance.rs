@@ -0,0 +1,235 @@
+use crate::{
+    alliance::{Alliance, MAX_PARTIES},
+    mutex::MutexGuard,
+    Error, User,
+};
+use std::sync::Arc;
+
+/// Invites the party of the player to the alliance of the user's party.
+pub async fn invite(user: &mut MutexGuard<'_, User>, name: &str) -> Result<(), Error> {
+    let Some(party) = user.get_current_party() else {
+        return user.send_system_msg("You are not in a party").await;
+    };
+    let Some(target) = user.blockdata.directory.find(name) else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let id = user.get_user_id();
+    if target.id == id {
+        return user
+            .send_system_msg("You can't invite your own party")
+            .await;
+    }
+    let Some(target_user) = target.user() else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let nickname = user.user_data.nickname.clone();
+    let msg = MutexGuard::unlocked_async(user, || async move {
+        let lock = party.read().await;
+        if lock.leader_id() != id {
+            return "Only the party leader can invite parties".to_string();
+        }
+        if lock
+            .alliance()
+            .is_some_and(|a| a.parties().len() >= MAX_PARTIES)
+        {
+            return "Your alliance is full".to_string();
+        }
+        drop(lock);
+        let target_party = target_user.lock().await.get_current_party();
+        let Some(target_party) = target_party else {
+            return format!("{} is not in a party", target.nickname);
+        };
+        if Arc::ptr_eq(&target_party, &party) {
+            return format!("{} is in your party", target.nickname);
+        }
+        let mut target_lock = target_party.write().await;
+        if target_lock.leader_id() != target.id {
+            return format!("{} is not the party leader", target.nickname);
+        }
+        if target_lock.alliance().is_some() {
+            return format!("{} is already in an alliance", target.nickname);
+        }
+        target_lock.set_alliance_invite(&party);
+        drop(target_lock);
+        let _ = target_user
+            .lock()
+            .await
+            .send_system_msg(&format!(
+                "{nickname} invited your party to an alliance. Use !alliance_accept to join"
+            ))
+            .await;
+        format!("Alliance invite sent to {}", target.nickname)
+    })
+    .await;
+    user.send_system_msg(&msg).await
+}
+
+/// Joins the alliance of the party that invited the user's party.
+pub async fn accept(user: &mut MutexGuard<'_, User>) -> Result<(), Error> {
+    let Some(party) = user.get_current_party() else {
+        return user.send_system_msg("You are not in a party").await;
+    };
+    let id = user.get_user_id();
+    let nickname = user.user_data.nickname.clone();
+    let error = MutexGuard::unlocked_async(user, || async move {
+        let mut lock = party.write().await;
+        if lock.leader_id() != id {
+            return Some("Only the party leader can accept alliance invites");
+        }
+        if lock.alliance().is_some() {
+            return Some("Your party is already in an alliance");
+        }
+        let Some(inviter) = lock.take_alliance_invite() else {
+            return Some("No alliance invites");
+        };
+        drop(lock);
+        let mut inviter_lock = inviter.write().await;
+        let alliance = match inviter_lock.alliance() {
+            Some(alliance) => {
+                if !alliance.join(&party) {
+                    return Some("The alliance is full");
+                }
+                alliance
+            }
+            None => {
+                let alliance = Alliance::new(&inviter, &party);
+                inviter_lock.set_alliance(Some(alliance.clone()));
+                alliance
+            }
+        };
+        drop(inviter_lock);
+        party.write().await.set_alliance(Some(alliance.clone()));
+        let msg = format!("{nickname}'s party joined the alliance");
+        for party in alliance.parties() {
+            party.read().await.send_system_msg(&msg).await;
+        }
+        None
+    })
+    .await;
+    match error {
+        Some(error) => user.send_system_msg(error).await,
+        None => Ok(()),
+    }
+}
+
+/// Joins the quest offered by another party of the alliance, playing on the same map.
+pub async fn join_quest(user: &mut MutexGuard<'_, User>) -> Result<(), Error> {
+    let Some(party) = user.get_current_party() else {
+        return user.send_system_msg("You are not in a party").await;
+    };
+    let id = user.get_user_id();
+    let exp_share = user.blockdata.quest_settings.alliance_exp_share;
+    let error = MutexGuard::unlocked_async(user, || async move {
+        let mut lock = party.write().await;
+        if lock.leader_id() != id {
+            return Some("Only the party leader can join alliance quests");
+        }
+        if lock.on_quest() {
+            return Some("Your party is already on a quest");
+        }
+        let Some(offerer) = lock.take_alliance_quest() else {
+            return Some("No alliance quest offers");
+        };
+        let allied = lock
+            .alliance()
+            .is_some_and(|a| a.parties().iter().any(|p| Arc::ptr_eq(p, &offerer)));
+        drop(lock);
+        if !allied {
+            return Some("The party is no longer in your alliance");
+        }
+        let Some(quest) = offerer.read().await.share_quest() else {
+            return Some("The quest is no longer running");
+        };
+        quest
+            .get_map()
+            .lock()
+            .await
+            .set_alliance_exp_share(exp_share);
+        let mut lock = party.write().await;
+        if lock.on_quest() {
+            return Some("Your party is already on a quest");
+        }
+        lock.set_quest(quest).await;
+        lock.send_system_msg("Your party joined the alliance quest")
+            .await;
+        None
+    })
+    .await;
+    match error {
+        Some(error) => user.send_system_msg(error).await,
+        None => Ok(()),
+    }
+}
+
+/// Removes the user's party from its alliance. The alliance is dissolved once only one party is
+/// left.
+pub async fn leave(user: &mut MutexGuard<'_, User>) -> Result<(), Error> {
+    let Some(party) = user.get_current_party() else {
+        return user.send_system_msg("You are not in a party").await;
+    };
+    let id = user.get_user_id();
+    let nickname = user.user_data.nickname.clone();
+    let error = MutexGuard::unlocked_async(user, || async move {
+        let mut lock = party.write().await;
+        if lock.leader_id() != id {
+            return Some("Only the party leader can leave the alliance");
+        }
+        let Some(alliance) = lock.alliance() else {
+            return Some("Your party is not in an alliance");
+        };
+        lock.set_alliance(None);
+        lock.send_system_msg("Your party left the alliance").await;
+        drop(lock);
+        let remaining = alliance.leave(&party);
+        let msg = if remaining.len() == 1 {
+            remaining[0].write().await.set_alliance(None);
+            format!("{nickname}'s party left the alliance, the alliance is dissolved")
+        } else {
+            format!("{nickname}'s party left the alliance")
+        };
+        for party in remaining {
+            party.read().await.send_system_msg(&msg).await;
+        }
+        None
+    })
+    .await;
+    match error {
+        Some(error) => user.send_system_msg(error).await,
+        None => Ok(()),
+    }
+}
+
+/// Lists parties of the user's alliance.
+pub async fn list(user: &mut MutexGuard<'_, User>) -> Result<(), Error> {
+    let Some(party) = user.get_current_party() else {
+        return user.send_system_msg("You are not in a party").await;
+    };
+    let directory = user.blockdata.directory.clone();
+    let lines = MutexGuard::unlocked_async(user, || async move {
+        let alliance = party.read().await.alliance()?;
+        let mut lines = vec![];
+        for party in alliance.parties() {
+            let lock = party.read().await;
+            let leader = directory
+                .get(lock.leader_id())
+                .map(|p| p.char_name)
+                .unwrap_or_default();
+            lines.push(format!(
+                "{leader}'s party: {}/4 players{}",
+                lock.player_count(),
+                if lock.on_quest() { ", on a quest" } else { "" }
+            ));
+        }
+        Some(lines)
+    })
+    .await;
+    match lines {
+        Some(lines) => user.send_system_msg(&lines.join("\n")).await,
+        None => {
+            user.send_system_msg(
+                "Your party is not in an alliance. Use !alliance_invite <player> to invite a party",
+            )
+            .await
+        }
+    }
+}
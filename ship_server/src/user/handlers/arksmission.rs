@@ -1,32 +1,133 @@
-use super::HResult;
-use crate::{user::User, Action};
+use super::{
+    rewards::{self, Grant},
+    HResult,
+};
+use crate::{arksmission::Periods, events::GameEvent, user::User, Action, Error};
+use data_structs::mission::ArksMission;
 use pso2packetlib::protocol::{missions, Packet};
 
 pub async fn mission_list(user: &mut User) -> HResult {
-    let mission = missions::Mission {
-        mission_type: 5,
-        start_date: 0,
-        end_date: 0,
-        id: 6040309,
-        unk5: 4,
-        completion_date: 1615045153,
-        unk7: 0,
-        unk8: 0,
-        unk9: 0,
-        unk10: 1,
-        unk11: 1023,
-        unk12: 0,
-        unk13: 0,
-        unk14: 1,
-        unk15: 0,
-    };
+    let blockdata = user.blockdata.clone();
+    let clock = blockdata.clock;
+    let periods = Periods::from_clock(&clock);
+    let progress = &user.character.as_ref().unwrap().arks_missions;
+    let missions = blockdata
+        .server_data
+        .arks_missions
+        .iter()
+        .map(|m| missions::Mission {
+            mission_type: m.mission_type,
+            start_date: 0,
+            end_date: 0,
+            id: m.id,
+            unk5: 0,
+            completion_date: progress
+                .get(m, periods)
+                .and_then(|p| p.completed)
+                .unwrap_or(0),
+            unk7: 0,
+            unk8: 0,
+            unk9: 0,
+            unk10: 0,
+            unk11: 0,
+            unk12: 0,
+            unk13: 0,
+            unk14: 0,
+            unk15: 0,
+        })
+        .collect();
+    let weekly_update = clock.next_weekly_reset().as_secs() as u32;
     let packet = missions::MissionListPacket {
-        missions: vec![mission],
-        daily_update: 1689272266,
-        weekly_update: 1689273267,
-        tier_update: 1689273267,
+        missions,
+        daily_update: clock.next_daily_reset().as_secs() as u32,
+        weekly_update,
+        tier_update: weekly_update,
         unk1: 0,
     };
     user.send_packet(&Packet::MissionList(packet)).await?;
     Ok(Action::Nothing)
 }
+
+/// Updates mission progress of the character and announces completed missions.
+pub async fn on_event(user: &mut User, event: &GameEvent) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(character) = user.character.as_mut() else {
+        return Ok(());
+    };
+    let periods = Periods::from_clock(&blockdata.clock);
+    let completed =
+        character
+            .arks_missions
+            .record(event, &blockdata.server_data.arks_missions, periods);
+    if completed.is_empty() && !matches!(event, GameEvent::LoggedIn { .. }) {
+        return Ok(());
+    }
+    blockdata.sql.update_character(character).await?;
+    for mission in completed {
+        user.send_system_msg(&format!(
+            "ARKS mission completed: {}. Use !mission_claim {} to receive the reward",
+            mission.name, mission.id
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// Lists ARKS missions with their progress.
+pub async fn list(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let periods = Periods::from_clock(&blockdata.clock);
+    let progress = &user.character.as_ref().unwrap().arks_missions;
+    let lines: Vec<_> = blockdata
+        .server_data
+        .arks_missions
+        .iter()
+        .map(|m| {
+            let status = match progress.get(m, periods) {
+                Some(p) if p.claimed => "claimed".to_string(),
+                Some(p) if p.completed.is_some() => "reward available".to_string(),
+                Some(p) => format!("{}/{}", p.value, m.value),
+                None => format!("0/{}", m.value),
+            };
+            format!("{}. {} - {status}", m.id, m.name)
+        })
+        .collect();
+    if lines.is_empty() {
+        return user.send_system_msg("No ARKS missions available").await;
+    }
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Gives rewards of the completed mission.
+pub async fn claim(user: &mut User, mission_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let periods = Periods::from_clock(&blockdata.clock);
+    let Some(mission) = find_mission(&blockdata.server_data.arks_missions, mission_id) else {
+        return user.send_system_msg("Unknown mission").await;
+    };
+    let character = user.character.as_ref().unwrap();
+    if !character.arks_missions.is_claimable(mission, periods) {
+        return user.send_system_msg("No reward to claim").await;
+    }
+    let grant = Grant {
+        items: &mission.rewards,
+        exp: mission.exp,
+        ..Default::default()
+    };
+    if !grant.fits(user) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    let character = user.character.as_mut().unwrap();
+    character.arks_missions.claim(mission, periods);
+    rewards::give(user, grant, vec![]).await?;
+    if grant.is_empty() {
+        return user.send_system_msg("Mission claimed").await;
+    }
+    let names = &blockdata.server_data.item_params.names;
+    user.send_system_msg(&format!("Received {}", grant.describe(names)))
+        .await
+}
+
+fn find_mission(missions: &[ArksMission], id: u32) -> Option<&ArksMission> {
+    missions.iter().find(|m| m.id == id)
+}
@@ -0,0 +1,80 @@
+use crate::{Error, User};
+
+/// Maximum number of blacklisted players of a character.
+const MAX_BLACKLIST: usize = 100;
+
+/// Lists players blacklisted by the current character.
+pub async fn list(user: &mut User) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let entries = user.blockdata.sql.get_blacklist(char_id).await?;
+    if entries.is_empty() {
+        return user.send_system_msg("Your blacklist is empty").await;
+    }
+    let lines: Vec<_> = entries
+        .iter()
+        .map(|e| format!("{} ({})", e.nickname, e.id))
+        .collect();
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Blacklists the online player.
+pub async fn add(user: &mut User, name: &str) -> Result<(), Error> {
+    let Some(target) = user.blockdata.directory.find(name) else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    if target.id == user.get_user_id() {
+        return user.send_system_msg("You can't blacklist yourself").await;
+    }
+    if user.blacklist.len() >= MAX_BLACKLIST {
+        return user.send_system_msg("Your blacklist is full").await;
+    }
+    let char_id = character_id(user);
+    if !user
+        .blockdata
+        .sql
+        .add_to_blacklist(char_id, &target)
+        .await?
+    {
+        return user
+            .send_system_msg(&format!("{} is already blacklisted", target.nickname))
+            .await;
+    }
+    user.blacklist.push(target.id);
+    user.send_system_msg(&format!("{} was added to the blacklist", target.nickname))
+        .await
+}
+
+/// Removes the player (by nickname or id) from the blacklist.
+pub async fn remove(user: &mut User, name: &str) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let sql = user.blockdata.sql.clone();
+    let id = name.parse::<u32>().ok();
+    let entry = sql
+        .get_blacklist(char_id)
+        .await?
+        .into_iter()
+        .find(|e| Some(e.id) == id || e.nickname.eq_ignore_ascii_case(name));
+    let Some(entry) = entry else {
+        return user
+            .send_system_msg(&format!("{name} is not blacklisted"))
+            .await;
+    };
+    sql.remove_from_blacklist(char_id, entry.id).await?;
+    user.blacklist.retain(|&id| id != entry.id);
+    user.send_system_msg(&format!(
+        "{} was removed from the blacklist",
+        entry.nickname
+    ))
+    .await
+}
+
+/// Loads the blacklist of the selected character.
+pub async fn load(user: &mut User, char_id: u32) -> Result<(), Error> {
+    let entries = user.blockdata.sql.get_blacklist(char_id).await?;
+    user.blacklist = entries.into_iter().map(|e| e.id).collect();
+    Ok(())
+}
+
+fn character_id(user: &User) -> u32 {
+    user.character.as_ref().unwrap().character.character_id
+}
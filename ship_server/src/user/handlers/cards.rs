@@ -0,0 +1,190 @@
+use crate::{mutex::MutexGuard, sql::PlayerCard, Error, User};
+
+/// Maximum length of the card comment in characters.
+const MAX_COMMENT_LEN: usize = 100;
+
+enum ExchangeResult {
+    Offered,
+    Exchanged(PlayerCard),
+    Ignored,
+    NotInGame,
+}
+
+/// Offers to exchange player cards with an online player or accepts their offer.
+pub async fn exchange(user: &mut MutexGuard<'_, User>, name: &str) -> Result<(), Error> {
+    let Some(target) = user.blockdata.directory.find(name) else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let id = user.get_user_id();
+    if target.id == id {
+        return user
+            .send_system_msg("You can't exchange cards with yourself")
+            .await;
+    }
+    let Some(target_user) = target.user() else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let Some(card) = own_card(user) else {
+        return Ok(());
+    };
+    let accepted = user.card_offers.contains(&target.id);
+    let result = MutexGuard::unlocked_async(user, || async move {
+        let mut target = target_user.lock().await;
+        if target.is_blacklisted(id) {
+            return Ok(ExchangeResult::Ignored);
+        }
+        let Some(target_card) = own_card(&target) else {
+            return Ok(ExchangeResult::NotInGame);
+        };
+        if accepted {
+            target
+                .blockdata
+                .sql
+                .add_player_card(target_card.char_id, &card)
+                .await?;
+            target.card_offers.retain(|&o| o != id);
+            let msg = format!("{} exchanged player cards with you", card.char_name);
+            target.send_system_msg(&msg).await?;
+            return Ok(ExchangeResult::Exchanged(target_card));
+        }
+        if !target.card_offers.contains(&id) {
+            target.card_offers.push(id);
+        }
+        let msg = format!(
+            "{} wants to exchange player cards. Use !card_exchange {} to accept",
+            card.char_name, card.char_name
+        );
+        target.send_system_msg(&msg).await?;
+        Ok::<_, Error>(ExchangeResult::Offered)
+    })
+    .await?;
+    match result {
+        ExchangeResult::Offered => {
+            user.send_system_msg(&format!("Offered player cards to {}", target.nickname))
+                .await
+        }
+        ExchangeResult::Exchanged(mut target_card) => {
+            user.card_offers.retain(|&o| o != target.id);
+            target_card.received = user.blockdata.clock.now();
+            let char_id = character_id(user);
+            user.blockdata
+                .sql
+                .add_player_card(char_id, &target_card)
+                .await?;
+            user.send_system_msg(&format!(
+                "Exchanged player cards with {}",
+                target_card.char_name
+            ))
+            .await
+        }
+        ExchangeResult::Ignored => {
+            user.send_system_msg(&format!("{} is ignoring you", target.nickname))
+                .await
+        }
+        ExchangeResult::NotInGame => {
+            user.send_system_msg(&format!("{} is not in game", target.nickname))
+                .await
+        }
+    }
+}
+
+/// Lists received player cards with the online status of their owners.
+pub async fn list(user: &mut User) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let cards = user.blockdata.sql.get_player_cards(char_id).await?;
+    if cards.is_empty() {
+        return user.send_system_msg("You have no player cards").await;
+    }
+    let lines: Vec<_> = cards
+        .iter()
+        .map(|c| {
+            let status = match user.blockdata.directory.get(c.user_id) {
+                Some(online) if online.char_name == c.char_name => {
+                    format!("online, {}", online.block_name)
+                }
+                _ => "offline".to_string(),
+            };
+            format!("{} ({}): {status}", c.char_name, c.nickname)
+        })
+        .collect();
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Shows the received player card.
+pub async fn show(user: &mut User, name: &str) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let cards = user.blockdata.sql.get_player_cards(char_id).await?;
+    let Some(card) = cards
+        .iter()
+        .find(|c| c.char_name.eq_ignore_ascii_case(name))
+    else {
+        return user
+            .send_system_msg(&format!("You have no player card of {name}"))
+            .await;
+    };
+    let days = user
+        .blockdata
+        .clock
+        .now()
+        .saturating_sub(card.received)
+        .as_secs()
+        / 86400;
+    let mut msg = format!(
+        "{} ({}), received {days} day(s) ago",
+        card.char_name, card.nickname
+    );
+    if !card.comment.is_empty() {
+        msg = format!("{msg}\n{}", card.comment);
+    }
+    user.send_system_msg(&msg).await
+}
+
+/// Removes the player card from the collection.
+pub async fn remove(user: &mut User, name: &str) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let sql = user.blockdata.sql.clone();
+    let cards = sql.get_player_cards(char_id).await?;
+    let Some(card) = cards
+        .iter()
+        .find(|c| c.char_name.eq_ignore_ascii_case(name))
+    else {
+        return user
+            .send_system_msg(&format!("You have no player card of {name}"))
+            .await;
+    };
+    sql.remove_player_card(char_id, card.char_id).await?;
+    user.send_system_msg(&format!("Removed the player card of {}", card.char_name))
+        .await
+}
+
+/// Sets the comment of the character's own card.
+pub async fn set_comment(user: &mut User, comment: &str) -> Result<(), Error> {
+    if comment.chars().count() > MAX_COMMENT_LEN {
+        return user
+            .send_system_msg(&format!(
+                "Comment should be at most {MAX_COMMENT_LEN} characters long"
+            ))
+            .await;
+    }
+    let character = user.character.as_mut().unwrap();
+    character.card_comment = comment.to_string();
+    user.blockdata.sql.update_character(character).await?;
+    user.send_system_msg("Player card comment updated").await
+}
+
+/// Builds the player card of the current character.
+fn own_card(user: &User) -> Option<PlayerCard> {
+    let character = user.character.as_ref()?;
+    Some(PlayerCard {
+        user_id: user.get_user_id(),
+        char_id: character.character.character_id,
+        char_name: character.character.name.clone(),
+        nickname: user.user_data.nickname.clone(),
+        comment: character.card_comment.clone(),
+        received: user.blockdata.clock.now(),
+    })
+}
+
+fn character_id(user: &User) -> u32 {
+    user.character.as_ref().unwrap().character.character_id
+}
@@ -0,0 +1,162 @@
+use super::shop::item_name;
+use crate::{
+    casino::{self, CASINO_ZONE},
+    events::GameEvent,
+    Error, User,
+};
+use data_structs::reward::Reward;
+
+/// Shows the casino coin balance of the character.
+pub async fn balance(user: &mut User) -> Result<(), Error> {
+    let coins = user.character.as_ref().unwrap().casino_coins;
+    user.send_system_msg(&format!(
+        "Casino coins: {coins}\nUse !slots <bet> to play rappy slots"
+    ))
+    .await
+}
+
+/// Buys casino coins for meseta.
+pub async fn buy_coins(user: &mut User, amount: u64) -> Result<(), Error> {
+    if !in_casino(user) {
+        return user
+            .send_system_msg("Coins can only be bought in the casino")
+            .await;
+    }
+    let blockdata = user.blockdata.clone();
+    let price = amount.saturating_mul(blockdata.casino.coin_price);
+    let character = user.character.as_mut().unwrap();
+    let Some(packet) = character.inventory.take_meseta(price) else {
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    character.casino_coins = character.casino_coins.saturating_add(amount);
+    let coins = character.casino_coins;
+    blockdata.sql.update_character(character).await?;
+    user.send_packet(&packet).await?;
+    user.send_system_msg(&format!(
+        "Bought {amount} coins for {price} meseta, casino coins: {coins}"
+    ))
+    .await
+}
+
+/// Plays rappy slots.
+pub async fn slots(user: &mut User, bet: u64) -> Result<(), Error> {
+    if !in_casino(user) {
+        return user
+            .send_system_msg("Slots can only be played in the casino")
+            .await;
+    }
+    let blockdata = user.blockdata.clone();
+    if bet == 0 || bet > blockdata.casino.max_bet {
+        return user
+            .send_system_msg(&format!(
+                "Bet should be between 1 and {}",
+                blockdata.casino.max_bet
+            ))
+            .await;
+    }
+    let character = user.character.as_mut().unwrap();
+    if character.casino_coins < bet {
+        return user.send_system_msg("Not enough casino coins").await;
+    }
+    let reels = casino::spin(&mut rand::rngs::OsRng);
+    let won = casino::payout(reels, bet);
+    character.casino_coins = character.casino_coins - bet + won;
+    let coins = character.casino_coins;
+    blockdata.sql.update_character(character).await?;
+    let reels: Vec<_> = reels.iter().map(|s| s.name()).collect();
+    let result = if won != 0 {
+        format!("You won {won} coins!")
+    } else {
+        "No luck this time".to_string()
+    };
+    user.send_system_msg(&format!(
+        "[ {} ] {result}\nCasino coins: {coins}",
+        reels.join(" | ")
+    ))
+    .await
+}
+
+/// Lists items of the coin exchange counter.
+pub async fn prizes(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let names = &blockdata.server_data.item_params.names;
+    let lines: Vec<_> = blockdata
+        .casino
+        .prizes
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            format!(
+                "{}. {} x{} - {} coins",
+                i + 1,
+                item_name(names, p.id),
+                p.amount,
+                p.price
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return user.send_system_msg("No prizes available").await;
+    }
+    let msg = format!(
+        "{}\nUse !casino_exchange <number> to exchange coins",
+        lines.join("\n")
+    );
+    user.send_system_msg(&msg).await
+}
+
+/// Exchanges casino coins for a prize.
+pub async fn exchange(user: &mut User, number: usize) -> Result<(), Error> {
+    if !in_casino(user) {
+        return user
+            .send_system_msg("Coins can only be exchanged in the casino")
+            .await;
+    }
+    let blockdata = user.blockdata.clone();
+    let Some(prize) = number
+        .checked_sub(1)
+        .and_then(|i| blockdata.casino.prizes.get(i))
+    else {
+        return user.send_system_msg("Unknown prize").await;
+    };
+    let character = user.character.as_mut().unwrap();
+    if character.casino_coins < prize.price {
+        return user.send_system_msg("Not enough casino coins").await;
+    }
+    let reward = Reward {
+        id: prize.id,
+        amount: prize.amount,
+    };
+    if !character.inventory.has_space_for_rewards(&[reward]) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    character.casino_coins -= prize.price;
+    let packets =
+        character
+            .inventory
+            .add_bought_items(&mut user.user_data.last_uuid, prize.id, prize.amount);
+    blockdata.events.emit(GameEvent::ItemObtained {
+        player_id: user.user_data.id,
+        item: prize.id,
+        amount: prize.amount,
+    });
+    blockdata.sql.update_character(character).await?;
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    let names = &blockdata.server_data.item_params.names;
+    user.send_system_msg(&format!(
+        "Received {} x{}",
+        item_name(names, prize.id),
+        prize.amount
+    ))
+    .await
+}
+
+/// Checks if the player is in the casino zone of the lobby.
+fn in_casino(user: &User) -> bool {
+    user.blockdata
+        .directory
+        .get(user.get_user_id())
+        .is_some_and(|p| p.zone.as_deref() == Some(CASINO_ZONE))
+}
@@ -1,18 +1,46 @@
-use super::HResult;
-use crate::{mutex::MutexGuard, user::User, Action};
+use super::{trade::TradeOp, HResult};
+use crate::{
+    chat_filter::{ChatFilter, SpamCheck},
+    directory::PlayerEntry,
+    map::ChatHook,
+    mutex::{Mutex, MutexGuard},
+    team::TeamRank,
+    user::User,
+    Action,
+};
+use data_structs::master_ship::{gm_level, Mute};
 use indicatif::HumanBytes;
 use memory_stats::memory_stats;
 use pso2packetlib::protocol::{
-    chat::MessageChannel, flag::FlagType, items::ItemId, playerstatus, ObjectType, Packet,
+    chat::MessageChannel, flag::FlagType, items::ItemId, playerstatus, ObjectHeader, ObjectType,
+    Packet,
 };
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
 
-pub async fn send_chat(mut user: MutexGuard<'_, User>, packet: Packet) -> HResult {
+pub async fn send_chat(mut user: MutexGuard<'_, User>, mut packet: Packet) -> HResult {
     let Packet::ChatMessage(ref data) = packet else {
         unreachable!()
     };
+    // muted players can't use commands either
+    if let Some(mute) = user.mute.clone() {
+        let now = user.blockdata.clock.now();
+        if mute.until > now {
+            let minutes = (mute.until - now).as_secs().div_ceil(60);
+            let mut msg = format!("You are muted for {minutes} more minute(s)");
+            if !mute.reason.is_empty() {
+                msg = format!("{msg}: {}", mute.reason);
+            }
+            user.send_system_msg(&msg).await?;
+            return Ok(Action::Nothing);
+        }
+        user.mute = None;
+    }
     if data.message.starts_with('!') {
         let mut args = data.message.split(' ');
         let cmd = args.next().expect("Should always contain some data");
+        if !has_gm_level(&mut user, required_gm_level(cmd)).await? {
+            return Ok(Action::Nothing);
+        }
         match cmd {
             "!mem" => {
                 let mem_data_msg = if let Some(mem) = memory_stats() {
@@ -223,17 +251,942 @@ pub async fn send_chat(mut user: MutexGuard<'_, User>, packet: Packet) -> HResul
                 drop(user);
                 map.lock().await.spawn_enemy(name, pos, map_id).await?;
             }
+            "!party" => {
+                super::party::list_members(&mut user).await?;
+            }
+            "!alliance" => super::alliance::list(&mut user).await?,
+            "!alliance_invite" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::alliance::invite(&mut user, name).await?;
+            }
+            "!alliance_accept" => super::alliance::accept(&mut user).await?,
+            "!alliance_quest" => super::alliance::join_quest(&mut user).await?,
+            "!alliance_leave" => super::alliance::leave(&mut user).await?,
+            "!friends" => {
+                super::friends::list_friends(&mut user).await?;
+            }
+            "!friend_add" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::friends::add_friend(&mut user, name).await?;
+            }
+            "!friend_accept" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::friends::accept_friend(&mut user, name).await?;
+            }
+            "!friend_decline" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::friends::decline_friend(&mut user, name).await?;
+            }
+            "!friend_remove" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::friends::remove_friend(&mut user, name).await?;
+            }
+            "!mail" => {
+                super::mail::list_mail(&mut user).await?;
+            }
+            "!read_mail" | "!claim_mail" | "!delete_mail" => {
+                let Some(id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No mail id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                match cmd {
+                    "!read_mail" => super::mail::read_mail(&mut user, id).await?,
+                    "!claim_mail" => super::mail::claim_mail(&mut user, id).await?,
+                    _ => super::mail::delete_mail(&mut user, id).await?,
+                }
+            }
+            "!send_mail" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No character provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let message = args.collect::<Vec<_>>().join(" ");
+                super::mail::send_mail(&mut user, name, &message).await?;
+            }
+            "!send_item" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No character provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let Some(amount) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No amount provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let item = args.collect::<Vec<_>>().join(" ");
+                if item.is_empty() {
+                    user.send_system_msg("No item provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::mail::send_item(&mut user, name, amount, &item).await?;
+            }
+            "!use_ticket" => {
+                let item = args.collect::<Vec<_>>().join(" ");
+                if item.is_empty() {
+                    user.send_system_msg("No item provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::item::use_ticket(&mut user, &item).await?;
+            }
+            "!shop" => {
+                let Some(shop) = args.next() else {
+                    user.send_system_msg("No shop provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::shop::list(&mut user, shop).await?;
+            }
+            "!buy" => {
+                let Some(shop) = args.next() else {
+                    user.send_system_msg("No shop provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let Some(number) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No item number provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let amount = args.next().and_then(|a| a.parse().ok()).unwrap_or(1);
+                super::shop::buy(&mut user, shop, number, amount).await?;
+            }
+            "!sell" => {
+                let items = args.collect::<Vec<_>>().join(" ");
+                let entries: Vec<_> = items
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+                    .collect();
+                if entries.is_empty() {
+                    user.send_system_msg("No items provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::shop::sell(&mut user, &entries).await?;
+            }
+            "!buyback" => match args.next().and_then(|a| a.parse().ok()) {
+                Some(number) => super::shop::buyback(&mut user, number).await?,
+                None => super::shop::list_buyback(&mut user).await?,
+            },
+            "!affix_select" => {
+                let item = args.collect::<Vec<_>>().join(" ");
+                if item.is_empty() {
+                    user.send_system_msg("No item provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::affix::select(&mut user, &item).await?;
+            }
+            "!affix_preview" | "!affix" => {
+                let Ok(chosen) = args
+                    .filter(|a| !a.is_empty())
+                    .map(|a| a.parse())
+                    .collect::<Result<Vec<u16>, _>>()
+                else {
+                    user.send_system_msg("Invalid ability id").await?;
+                    return Ok(Action::Nothing);
+                };
+                if cmd == "!affix" {
+                    super::affix::affix(&mut user, &chosen).await?;
+                } else {
+                    super::affix::preview(&mut user, &chosen).await?;
+                }
+            }
+            "!affix_cancel" => super::affix::cancel(&mut user).await?,
+            "!lang" => {
+                let code = args.next().unwrap_or_default().to_lowercase();
+                super::settings::set_language(&mut user, &code).await?;
+            }
+            "!recipes" => super::craft::recipes(&mut user).await?,
+            "!craft" => {
+                let Some(recipe_id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No recipe id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::craft::start(&mut user, recipe_id).await?;
+            }
+            "!crafts" => super::craft::status(&mut user).await?,
+            "!craft_collect" => super::craft::collect(&mut user).await?,
+            "!orders" => {
+                let npc = args.collect::<Vec<_>>().join(" ");
+                super::orders::list(&mut user, &npc).await?;
+            }
+            "!order_accept" => {
+                let Some(order_id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No order id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::orders::accept(&mut user, order_id).await?;
+            }
+            "!order_progress" => super::orders::progress(&mut user).await?,
+            "!order_turnin" => {
+                let Some(order_id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No order id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::orders::turn_in(&mut user, order_id).await?;
+            }
+            "!daily" => super::daily::list(&mut user).await?,
+            "!events" => {
+                let schedule = user.blockdata.schedule.clone();
+                let now = user.blockdata.clock.now().as_secs();
+                let mut lines = vec![];
+                for event in schedule.active() {
+                    let hours = event.end.saturating_sub(now).div_ceil(3600);
+                    lines.push(format!("{} - ends in {hours} hour(s)", event.name));
+                }
+                for event in schedule.upcoming() {
+                    let hours = event.start.saturating_sub(now).div_ceil(3600);
+                    lines.push(format!("{} - starts in {hours} hour(s)", event.name));
+                }
+                if lines.is_empty() {
+                    user.send_system_msg("No events scheduled").await?;
+                } else {
+                    user.send_system_msg(&lines.join("\n")).await?;
+                }
+            }
+            "!missions" => super::arksmission::list(&mut user).await?,
+            "!mission_claim" => {
+                let Some(mission_id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No mission id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::arksmission::claim(&mut user, mission_id).await?;
+            }
+            "!coins" => super::casino::balance(&mut user).await?,
+            "!coins_buy" => {
+                let Some(amount) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No amount provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::casino::buy_coins(&mut user, amount).await?;
+            }
+            "!slots" => {
+                let Some(bet) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No bet provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::casino::slots(&mut user, bet).await?;
+            }
+            "!casino_prizes" => super::casino::prizes(&mut user).await?,
+            "!casino_exchange" => {
+                let Some(number) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No prize number provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::casino::exchange(&mut user, number).await?;
+            }
+            "!mp" => super::missionpass::status(&mut user).await?,
+            "!mp_claim" => super::missionpass::claim(&mut user).await?,
+            "!mp_gold" => super::missionpass::buy_gold(&mut user).await?,
+            "!titles" => super::titles::list(&mut user).await?,
+            "!title_claim" => {
+                let Some(title_id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No title id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::titles::claim(&mut user, title_id).await?;
+            }
+            "!title" => {
+                let Some(title_id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No title id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::titles::equip(&mut user, title_id).await?;
+            }
+            "!use_item" => {
+                let item = args.collect::<Vec<_>>().join(" ");
+                if item.is_empty() {
+                    user.send_system_msg("No item provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::item::use_item(&mut user, &item).await?;
+            }
+            "!unlocks" => {
+                let unlocks = &user.character.as_ref().unwrap().unlocks;
+                let msg = format!(
+                    "Lobby actions: {}\nStamps: {}",
+                    unlocks.lobby_actions.join(", "),
+                    unlocks
+                        .stamps
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                user.send_system_msg(&msg).await?;
+            }
+            "!mass_mail" | "!mass_mail_item" => {
+                if !has_gm_level(&mut user, gm_level::GM).await? {
+                    return Ok(Action::Nothing);
+                }
+                let mut meseta = 0;
+                let mut item = None;
+                if cmd == "!mass_mail" {
+                    let Some(amount) = args.next().and_then(|a| a.parse().ok()) else {
+                        user.send_system_msg("No meseta amount provided").await?;
+                        return Ok(Action::Nothing);
+                    };
+                    meseta = amount;
+                } else {
+                    let item_type = args.next().and_then(|a| a.parse().ok());
+                    let id = args.next().and_then(|a| a.parse().ok());
+                    let subid = args.next().and_then(|a| a.parse().ok());
+                    let (Some(item_type), Some(id), Some(subid)) = (item_type, id, subid) else {
+                        user.send_system_msg("Item type, id and subid should be provided")
+                            .await?;
+                        return Ok(Action::Nothing);
+                    };
+                    item = Some(ItemId {
+                        id,
+                        subid,
+                        item_type,
+                        ..Default::default()
+                    });
+                }
+                let message = args.collect::<Vec<_>>().join(" ");
+                super::mail::mass_mail(&mut user, meseta, item, &message).await?;
+            }
+            "!whisper" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let message = args.collect::<Vec<_>>().join(" ");
+                if message.is_empty() {
+                    user.send_system_msg("No message provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                whisper(&mut user, name, &message).await?;
+            }
+            "!team" => super::team::team_info(&mut user).await?,
+            "!team_create" => {
+                let name = args.collect::<Vec<_>>().join(" ");
+                super::team::create_team(&mut user, &name).await?;
+            }
+            "!team_invite" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::team::invite(&mut user, name).await?;
+            }
+            "!team_accept" => super::team::accept_invite(&mut user).await?,
+            "!team_decline" => super::team::decline_invites(&mut user).await?,
+            "!team_leave" => super::team::leave(&mut user).await?,
+            "!team_kick" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No member provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::team::kick(&mut user, name).await?;
+            }
+            "!team_rank" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No member provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let Some(rank) = args.next().and_then(TeamRank::parse) else {
+                    user.send_system_msg("Rank should be member, officer or master")
+                        .await?;
+                    return Ok(Action::Nothing);
+                };
+                super::team::set_rank(&mut user, name, rank).await?;
+            }
+            "!team_disband" => super::team::disband(&mut user).await?,
+            "!blacklist" => super::blacklist::list(&mut user).await?,
+            "!ignore" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::blacklist::add(&mut user, name).await?;
+            }
+            "!unignore" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::blacklist::remove(&mut user, name).await?;
+            }
+            "!cards" => super::cards::list(&mut user).await?,
+            "!card" | "!card_exchange" | "!card_remove" => {
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                match cmd {
+                    "!card" => super::cards::show(&mut user, name).await?,
+                    "!card_exchange" => super::cards::exchange(&mut user, name).await?,
+                    _ => super::cards::remove(&mut user, name).await?,
+                }
+            }
+            "!card_comment" => {
+                let comment = args.collect::<Vec<_>>().join(" ");
+                super::cards::set_comment(&mut user, &comment).await?;
+            }
+            "!trade" => match args.next() {
+                Some(name) => super::trade::request(&mut user, name).await?,
+                None => super::trade::update(&mut user, TradeOp::Show).await?,
+            },
+            "!trade_add" | "!trade_remove" => {
+                let amount = if cmd == "!trade_add" {
+                    match args.next().and_then(|a| a.parse().ok()) {
+                        Some(amount) => amount,
+                        None => {
+                            user.send_system_msg("Invalid amount").await?;
+                            return Ok(Action::Nothing);
+                        }
+                    }
+                } else {
+                    0
+                };
+                let item_name = args.collect::<Vec<_>>().join(" ");
+                let Some(uuid) = super::item::find_inv_item(&user, &item_name) else {
+                    user.send_system_msg("Item not found").await?;
+                    return Ok(Action::Nothing);
+                };
+                let op = if cmd == "!trade_add" {
+                    TradeOp::SetItem(uuid, amount)
+                } else {
+                    TradeOp::RemoveItem(uuid)
+                };
+                super::trade::update(&mut user, op).await?;
+            }
+            "!trade_meseta" => {
+                let Some(amount) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("Invalid amount").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::trade::update(&mut user, TradeOp::SetMeseta(amount)).await?;
+            }
+            "!trade_lock" => super::trade::update(&mut user, TradeOp::Lock).await?,
+            "!trade_confirm" => super::trade::update(&mut user, TradeOp::Confirm).await?,
+            "!trade_cancel" => super::trade::update(&mut user, TradeOp::Cancel).await?,
+            "!trades" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let id = match args.next() {
+                    Some(name) => match find_player_id(&user, name) {
+                        Some(id) => Some(id),
+                        None => {
+                            user.send_system_msg("Player is not online").await?;
+                            return Ok(Action::Nothing);
+                        }
+                    },
+                    None => None,
+                };
+                let trades = user.blockdata.sql.get_trades(id, 10).await?;
+                let now = user.blockdata.clock.now();
+                let lines: Vec<_> = trades
+                    .iter()
+                    .map(|t| {
+                        let minutes = now.saturating_sub(t.time).as_secs() / 60;
+                        format!(
+                            "{minutes} min ago, player {} gave {}, player {} gave {}",
+                            t.users.0, t.offers.0, t.users.1, t.offers.1
+                        )
+                    })
+                    .collect();
+                let msg = if lines.is_empty() {
+                    "No trades recorded".to_string()
+                } else {
+                    lines.join("\n")
+                };
+                user.send_system_msg(&msg).await?;
+            }
+            "!search" => {
+                let query = args.collect::<Vec<_>>().join(" ");
+                if query.is_empty() {
+                    user.send_system_msg("No name provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::search::search(&mut user, &query).await?;
+            }
+            "!find" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No player provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let msg = match user.blockdata.directory.find(name) {
+                    Some(p) => format!(
+                        "{} ({}), character \"{}\"{}: {}, {}",
+                        p.nickname,
+                        p.id,
+                        p.char_name,
+                        super::search::title_suffix(&p.title),
+                        p.block_name,
+                        p.zone.as_deref().unwrap_or("character selection")
+                    ),
+                    None => "Player is not online".to_string(),
+                };
+                user.send_system_msg(&msg).await?;
+            }
+            "!gmcall" => {
+                let message = args.collect::<Vec<_>>().join(" ");
+                if message.trim().is_empty() {
+                    user.send_system_msg("Please describe the problem").await?;
+                    return Ok(Action::Nothing);
+                }
+                super::support::gm_call(&mut user, &message).await?;
+            }
+            "!tickets" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                super::support::list(&mut user).await?;
+            }
+            "!ticket_claim" | "!ticket_goto" | "!ticket_resolve" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No GM call id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                match cmd {
+                    "!ticket_claim" => super::support::claim(&mut user, id).await?,
+                    "!ticket_goto" => super::support::goto(&mut user, id).await?,
+                    _ => {
+                        let resolution = args.collect::<Vec<_>>().join(" ");
+                        super::support::resolve(&mut user, id, &resolution).await?;
+                    }
+                }
+            }
+            "!anomalies" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let id = match args.next() {
+                    Some(name) => match find_player_id(&user, name) {
+                        Some(id) => Some(id),
+                        None => {
+                            user.send_system_msg("Player is not online").await?;
+                            return Ok(Action::Nothing);
+                        }
+                    },
+                    None => None,
+                };
+                let anomalies = user.blockdata.sql.get_anomalies(id, 10).await?;
+                let now = user.blockdata.clock.now();
+                let lines: Vec<_> = anomalies
+                    .iter()
+                    .map(|a| {
+                        let minutes = now.saturating_sub(a.time).as_secs() / 60;
+                        format!(
+                            "Player {}, {minutes} min ago, {}: {}",
+                            a.user_id, a.kind, a.details
+                        )
+                    })
+                    .collect();
+                let msg = if lines.is_empty() {
+                    "No anomalies recorded".to_string()
+                } else {
+                    lines.join("\n")
+                };
+                user.send_system_msg(&msg).await?;
+            }
+            "!mute" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(id) = args.next().and_then(|a| find_player_id(&user, a)) else {
+                    user.send_system_msg("Unknown player").await?;
+                    return Ok(Action::Nothing);
+                };
+                let Some(minutes) = args.next().and_then(|a| a.parse::<u64>().ok()) else {
+                    user.send_system_msg("No duration in minutes provided")
+                        .await?;
+                    return Ok(Action::Nothing);
+                };
+                let reason = args.collect::<Vec<_>>().join(" ");
+                mute_player(&mut user, id, minutes, reason).await?;
+            }
+            "!unmute" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(id) = args.next().and_then(|a| find_player_id(&user, a)) else {
+                    user.send_system_msg("Unknown player").await?;
+                    return Ok(Action::Nothing);
+                };
+                unmute_player(&mut user, id).await?;
+            }
+            "!add_note" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(id) = args.next().and_then(|a| find_player_id(&user, a)) else {
+                    user.send_system_msg("Unknown player").await?;
+                    return Ok(Action::Nothing);
+                };
+                let note = args.collect::<Vec<_>>().join(" ");
+                if note.is_empty() {
+                    user.send_system_msg("No note provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                let author_id = user.get_user_id();
+                user.blockdata
+                    .sql
+                    .add_account_note(id, author_id, &note)
+                    .await?;
+                user.send_system_msg(&format!("Note added to player {id}"))
+                    .await?;
+            }
+            "!notes" => {
+                if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(id) = args.next().and_then(|a| find_player_id(&user, a)) else {
+                    user.send_system_msg("Unknown player").await?;
+                    return Ok(Action::Nothing);
+                };
+                let notes = user.blockdata.sql.get_account_notes(id).await?;
+                let msg = if notes.is_empty() {
+                    format!("No notes for player {id}")
+                } else {
+                    notes
+                        .iter()
+                        .map(|n| format!("By {}: {}", n.author_id, n.note))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                user.send_system_msg(&msg).await?;
+            }
+            "!logins" => {
+                // other players' history is only available to moderators
+                let id = match args.next() {
+                    Some(name) => {
+                        if !has_gm_level(&mut user, gm_level::MODERATOR).await? {
+                            return Ok(Action::Nothing);
+                        }
+                        let Some(id) = find_player_id(&user, name) else {
+                            user.send_system_msg("Unknown player").await?;
+                            return Ok(Action::Nothing);
+                        };
+                        id
+                    }
+                    None => user.get_user_id(),
+                };
+                let logins = user.blockdata.sql.get_logins(id, 10).await?;
+                let msg = if logins.is_empty() {
+                    format!("No logins for player {id}")
+                } else {
+                    logins
+                        .iter()
+                        .map(|l| {
+                            format!(
+                                "[{}] {} ({:?}): {:?}",
+                                l.timestamp.as_secs(),
+                                mask_ip(l.ip),
+                                l.platform,
+                                l.status
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                user.send_system_msg(&msg).await?;
+            }
+            "!loadouts" => {
+                super::loadout::list_loadouts(&mut user).await?;
+            }
+            "!save_loadout" => {
+                let Some(slot) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No slot provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let name = args.collect::<Vec<_>>().join(" ");
+                let name = (!name.is_empty()).then_some(name);
+                super::loadout::save_loadout(&mut user, slot, name).await?;
+            }
+            "!delete_loadout" => {
+                let Some(slot) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No slot provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::loadout::delete_loadout(&mut user, slot).await?;
+            }
+            "!loadout" => {
+                let Some(slot) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No slot provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                super::loadout::apply_loadout(user, slot).await?;
+            }
+            "!changepassword" => {
+                let password = args.collect::<Vec<_>>().join(" ");
+                super::account::change_password(&mut user, &password).await?;
+            }
+            "!newpassword" => {
+                let password = args.collect::<Vec<_>>().join(" ");
+                super::account::new_password(&mut user, &password).await?;
+            }
+            "!nickname" => {
+                let nickname = args.collect::<Vec<_>>().join(" ");
+                super::account::change_nickname(&mut user, &nickname).await?;
+            }
+            "!set_email" => {
+                let Some(email) = args.next() else {
+                    user.send_system_msg("No email provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let id = user.get_user_id();
+                user.blockdata.sql.set_email(id, email).await?;
+                user.send_system_msg(
+                    "Verification code was sent, use !verify_email <code> to verify the email",
+                )
+                .await?;
+            }
+            "!verify_email" => {
+                let Some(code) = args.next() else {
+                    user.send_system_msg("No code provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let id = user.get_user_id();
+                if user.blockdata.sql.verify_email(id, code).await? {
+                    user.send_system_msg("Email verified").await?;
+                } else {
+                    user.send_system_msg("Invalid or expired code").await?;
+                }
+            }
+            "!enable_2fa" => {
+                let id = user.get_user_id();
+                let secret = user.blockdata.sql.new_totp_secret(id).await?;
+                let msg = format!(
+                    "Add this secret to your authenticator app: {secret}\n\
+                    Then use !confirm_2fa <code> to enable 2FA"
+                );
+                user.send_system_msg(&msg).await?;
+            }
+            "!confirm_2fa" => {
+                let Some(code) = args.next() else {
+                    user.send_system_msg("No code provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let id = user.get_user_id();
+                if user.blockdata.sql.enable_totp(id, code).await? {
+                    user.send_system_msg("2FA enabled").await?;
+                } else {
+                    user.send_system_msg("Invalid code").await?;
+                }
+            }
+            "!disable_2fa" => {
+                let Some(code) = args.next() else {
+                    user.send_system_msg("No code provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let id = user.get_user_id();
+                if user.blockdata.sql.disable_totp(id, code).await? {
+                    user.send_system_msg("2FA disabled").await?;
+                } else {
+                    user.send_system_msg("Invalid code").await?;
+                }
+            }
+            "!broadcast" => {
+                if !has_gm_level(&mut user, gm_level::GM).await? {
+                    return Ok(Action::Nothing);
+                }
+                let message = args.collect::<Vec<_>>().join(" ");
+                if message.is_empty() {
+                    user.send_system_msg("No message provided").await?;
+                    return Ok(Action::Nothing);
+                }
+                user.blockdata.sql.broadcast(vec![], &message).await?;
+            }
+            "!linkaccount" => {
+                let id = user.get_user_id();
+                let code = user.blockdata.sql.new_link_code(id).await?;
+                let msg = format!(
+                    "Your account link code is: {code}\nIt is valid for 10 minutes, don't share it \
+                    with anyone"
+                );
+                user.send_system_msg(&msg).await?;
+            }
+            "!start_event_lobby" => {
+                if !has_gm_level(&mut user, gm_level::GM).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(name) = args.next() else {
+                    user.send_system_msg("No map name provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let blockdata = user.blockdata.clone();
+                drop(user);
+                crate::block::start_event_lobby(&blockdata, name).await?;
+            }
+            "!end_event_lobby" => {
+                if !has_gm_level(&mut user, gm_level::GM).await? {
+                    return Ok(Action::Nothing);
+                }
+                let blockdata = user.blockdata.clone();
+                drop(user);
+                crate::block::end_event_lobby(&blockdata).await?;
+            }
+            "!lifecycle" => {
+                if !has_gm_level(&mut user, gm_level::GM).await? {
+                    return Ok(Action::Nothing);
+                }
+                let counts = crate::lifecycle::counts();
+                let msg = format!(
+                    "Maps: {} alive ({} created, {} dropped)\nParties: {} alive ({} created, {} \
+                    dropped)\nQuest maps swept: {}",
+                    counts.maps_alive(),
+                    counts.maps_created,
+                    counts.maps_dropped,
+                    counts.parties_alive(),
+                    counts.parties_created,
+                    counts.parties_dropped,
+                    counts.quest_maps_swept
+                );
+                user.send_system_msg(&msg).await?;
+            }
+            "!bandwidth" => {
+                if !has_gm_level(&mut user, gm_level::GM).await? {
+                    return Ok(Action::Nothing);
+                }
+                let name = args.collect::<Vec<_>>().join(" ");
+                if !name.is_empty() {
+                    let Some(player) = user.blockdata.directory.find(&name) else {
+                        user.send_system_msg("Player not found").await?;
+                        return Ok(Action::Nothing);
+                    };
+                    let msg = match crate::bandwidth::player_traffic(player.id) {
+                        Some(traffic) => format!("{}: {traffic}", player.nickname),
+                        None => "No traffic recorded".to_string(),
+                    };
+                    user.send_system_msg(&msg).await?;
+                    return Ok(Action::Nothing);
+                }
+                let mut lines = vec!["Packets:".to_string()];
+                lines.extend(
+                    crate::bandwidth::packet_totals()
+                        .into_iter()
+                        .take(10)
+                        .map(|(id, t)| format!("{}: {t}", crate::bandwidth::packet_name(id))),
+                );
+                lines.push("Connections:".to_string());
+                lines.extend(
+                    crate::bandwidth::connection_totals()
+                        .into_iter()
+                        .take(5)
+                        .map(|(id, t)| format!("player {id}: {t}")),
+                );
+                user.send_system_msg(&lines.join("\n")).await?;
+            }
+            "!set_gm_level" => {
+                if !has_gm_level(&mut user, gm_level::ADMIN).await? {
+                    return Ok(Action::Nothing);
+                }
+                let Some(id) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No player id provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                let Some(level) = args.next().and_then(|a| a.parse().ok()) else {
+                    user.send_system_msg("No level provided").await?;
+                    return Ok(Action::Nothing);
+                };
+                user.blockdata.sql.set_gm_level(id, level).await?;
+                let msg = format!("GM level of player {id} set to {level}, relogin to apply");
+                user.send_system_msg(&msg).await?;
+            }
             _ => user.send_system_msg("Unknown command").await?,
         }
         return Ok(Action::Nothing);
     }
     let id = user.get_user_id();
+    let Packet::ChatMessage(ref mut data) = packet else {
+        unreachable!()
+    };
+    // moderators bypass sanitization and spam protection
+    let is_moderator = user.user_data.gm_level >= gm_level::MODERATOR;
+    if !is_moderator
+        && matches!(
+            data.channel,
+            MessageChannel::Map
+                | MessageChannel::Party
+                | MessageChannel::Whisper
+                | MessageChannel::Alliance
+        )
+    {
+        let blockdata = user.blockdata.clone();
+        let check = user.spam_tracker.check(
+            &blockdata.chat_settings.spam,
+            &data.message,
+            std::time::Instant::now(),
+        );
+        match check {
+            SpamCheck::Allowed => {}
+            SpamCheck::Muted(duration) => {
+                let msg = format!(
+                    "You have been muted for {} second(s) for spamming",
+                    duration.as_secs()
+                );
+                user.send_system_msg(&msg).await?;
+                record_violation(&user, &format!("spam: {}", data.message)).await?;
+                return Ok(Action::Nothing);
+            }
+            SpamCheck::StillMuted(duration) => {
+                let msg = format!(
+                    "You are muted for {} more second(s)",
+                    duration.as_secs().max(1)
+                );
+                user.send_system_msg(&msg).await?;
+                return Ok(Action::Nothing);
+            }
+        }
+    }
+    if !is_moderator {
+        let blockdata = user.blockdata.clone();
+        let settings = &blockdata.chat_settings;
+        let filter = match data.channel {
+            MessageChannel::Party => &settings.party_filter,
+            MessageChannel::Whisper => &settings.whisper_filter,
+            MessageChannel::Alliance => &settings.alliance_filter,
+            _ => &settings.map_filter,
+        };
+        // the recipient name of whispers is not filtered
+        let (name, text) = match data.channel {
+            MessageChannel::Whisper => match data.message.split_once(' ') {
+                Some((name, text)) => (Some(name), text),
+                None => (None, data.message.as_str()),
+            },
+            _ => (None, data.message.as_str()),
+        };
+        let text = sanitize(&user, filter, text).await?;
+        if text.trim().is_empty() {
+            return Ok(Action::Nothing);
+        }
+        data.message = match name {
+            Some(name) => format!("{name} {text}"),
+            None => text,
+        };
+    }
     match data.channel {
         MessageChannel::Map => {
             let map = user.get_current_map();
             drop(user);
             if let Some(map) = map {
-                map.lock().await.send_message(packet, id).await;
+                let mut map = map.lock().await;
+                match map.on_chat(id, &*data).await? {
+                    ChatHook::Pass => {}
+                    ChatHook::Consume => return Ok(Action::Nothing),
+                    ChatHook::Replace(message) => data.message = message,
+                }
+                map.send_message(packet, id).await;
             }
         }
         MessageChannel::Party => {
@@ -243,6 +1196,35 @@ pub async fn send_chat(mut user: MutexGuard<'_, User>, packet: Packet) -> HResul
                 party.read().await.send_message(packet, id).await;
             }
         }
+        MessageChannel::Alliance => super::team::send_chat(user, packet).await?,
+        MessageChannel::Whisper => {
+            // the client prefixes whisper messages with the recipient name
+            let Some((name, _)) = data.message.split_once(' ') else {
+                return Ok(Action::Nothing);
+            };
+            let name = name.to_string();
+            let Some((target, target_user)) = whisper_target(&mut user, &name).await? else {
+                return Ok(Action::Nothing);
+            };
+            data.object = ObjectHeader {
+                id,
+                entity_type: ObjectType::Player,
+                ..Default::default()
+            };
+            let delivered = MutexGuard::unlocked_async(&mut user, || async move {
+                let mut target = target_user.lock().await;
+                if target.is_blacklisted(id) {
+                    return false;
+                }
+                let _ = target.send_packet(&packet).await;
+                true
+            })
+            .await;
+            if !delivered {
+                user.send_system_msg(&format!("{} is ignoring you", target.nickname))
+                    .await?;
+            }
+        }
         _ => {}
     }
     Ok(Action::Nothing)
@@ -312,3 +1294,228 @@ async fn set_flag(
 
     Ok(())
 }
+
+/// Returns the id of an online player by name, or the id itself.
+fn find_player_id(user: &User, name: &str) -> Option<u32> {
+    match user.blockdata.directory.find(name) {
+        Some(p) => Some(p.id),
+        None => name.parse().ok(),
+    }
+}
+
+/// Sends a private message to a player on any block of the ship.
+async fn whisper(
+    user: &mut MutexGuard<'_, User>,
+    name: &str,
+    message: &str,
+) -> Result<(), crate::Error> {
+    let message = if user.user_data.gm_level >= gm_level::MODERATOR {
+        message.to_string()
+    } else {
+        let blockdata = user.blockdata.clone();
+        sanitize(user, &blockdata.chat_settings.whisper_filter, message).await?
+    };
+    if message.trim().is_empty() {
+        return Ok(());
+    }
+    let Some((target, target_user)) = whisper_target(user, name).await? else {
+        return Ok(());
+    };
+    let sender = match &user.character {
+        Some(c) => c.character.name.clone(),
+        None => user.user_data.nickname.clone(),
+    };
+    let msg = format!("{sender} whispers: {message}");
+    let id = user.get_user_id();
+    let delivered = MutexGuard::unlocked_async(user, || async move {
+        let mut target = target_user.lock().await;
+        if target.is_blacklisted(id) {
+            return Ok(false);
+        }
+        target.send_system_msg(&msg).await.map(|_| true)
+    })
+    .await?;
+    if !delivered {
+        return user
+            .send_system_msg(&format!("{} is ignoring you", target.nickname))
+            .await;
+    }
+    let msg = format!("To {}: {message}", target.nickname);
+    user.send_system_msg(&msg).await
+}
+
+/// Finds the online recipient of a whisper. Tells the sender if the recipient is offline.
+async fn whisper_target(
+    user: &mut User,
+    name: &str,
+) -> Result<Option<(PlayerEntry, Arc<Mutex<User>>)>, crate::Error> {
+    let target = user
+        .blockdata
+        .directory
+        .find(name)
+        .and_then(|p| p.user().map(|u| (p, u)));
+    if target.is_none() {
+        user.send_system_msg("Player is not online").await?;
+    }
+    Ok(target)
+}
+
+/// Mutes the player for the duration in minutes.
+async fn mute_player(
+    user: &mut MutexGuard<'_, User>,
+    id: u32,
+    minutes: u64,
+    reason: String,
+) -> Result<(), crate::Error> {
+    if minutes == 0 {
+        return unmute_player(user, id).await;
+    }
+    let mute = Mute {
+        until: user.blockdata.clock.now() + Duration::from_secs(minutes.saturating_mul(60)),
+        reason,
+        muted_by: user.get_user_id(),
+    };
+    match user.blockdata.sql.set_mute(id, &mute).await {
+        Ok(_) => {}
+        Err(crate::Error::MSError(e)) => {
+            return user
+                .send_system_msg(&format!("Failed to mute player {id}: {e}"))
+                .await
+        }
+        Err(e) => return Err(e),
+    }
+    log::info!(
+        "Player {} muted player {id} for {minutes} minute(s)",
+        mute.muted_by
+    );
+    if let Some(target) = user.blockdata.directory.get(id).and_then(|p| p.user()) {
+        MutexGuard::unlocked_async(user, || async move {
+            let mut target = target.lock().await;
+            let msg = format!("You have been muted for {minutes} minute(s)");
+            target.mute = Some(mute);
+            target.send_system_msg(&msg).await
+        })
+        .await?;
+    }
+    user.send_system_msg(&format!("Player {id} muted for {minutes} minute(s)"))
+        .await
+}
+
+/// Removes the GM mute of the player.
+async fn unmute_player(user: &mut MutexGuard<'_, User>, id: u32) -> Result<(), crate::Error> {
+    let gm_id = user.get_user_id();
+    match user.blockdata.sql.remove_mute(id, gm_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return user
+                .send_system_msg(&format!("Player {id} is not muted"))
+                .await
+        }
+        Err(crate::Error::MSError(e)) => {
+            return user
+                .send_system_msg(&format!("Failed to unmute player {id}: {e}"))
+                .await
+        }
+        Err(e) => return Err(e),
+    }
+    if let Some(target) = user.blockdata.directory.get(id).and_then(|p| p.user()) {
+        MutexGuard::unlocked_async(user, || async move {
+            let mut target = target.lock().await;
+            target.mute = None;
+            target.send_system_msg("You have been unmuted").await
+        })
+        .await?;
+    }
+    user.send_system_msg(&format!("Player {id} unmuted")).await
+}
+
+/// Hides the last two octets of the address, so game chat never shows full player addresses.
+fn mask_ip(ip: Ipv4Addr) -> String {
+    let [a, b, ..] = ip.octets();
+    format!("{a}.{b}.x.x")
+}
+
+/// Applies the filter to the message, recording banned words as a violation.
+async fn sanitize(user: &User, filter: &ChatFilter, message: &str) -> Result<String, crate::Error> {
+    if filter.has_banned_words(message) {
+        record_violation(user, &format!("banned words: {message}")).await?;
+    }
+    Ok(filter.apply(message))
+}
+
+/// Records the chat violation according to the moderation settings.
+async fn record_violation(user: &User, details: &str) -> Result<(), crate::Error> {
+    let id = user.get_user_id();
+    let settings = &user.blockdata.chat_settings;
+    log::info!("Chat violation by player {id}: {details}");
+    if settings.log_violations {
+        let time = user.blockdata.clock.now();
+        user.blockdata
+            .sql
+            .log_anomaly(id, "chat", details, time)
+            .await?;
+    }
+    if settings.report_violations {
+        let sql = user.blockdata.sql.clone();
+        let note = format!("Chat violation: {details}");
+        // the report doesn't delay the message
+        tokio::spawn(async move {
+            if let Err(e) = sql.add_account_note(id, 0, &note).await {
+                log::warn!("Failed to report chat violation of player {id}: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Returns the GM level needed for the debug command. Debug commands create items, levels and
+/// quests from nothing, so players can't use them.
+fn required_gm_level(cmd: &str) -> u8 {
+    match cmd {
+        "!set_acc_flag" | "!set_char_flag" => gm_level::ADMIN,
+        "!mem" | "!start_con" | "!start_cutscene" | "!send_con" | "!get_pos" | "!get_close_obj"
+        | "!add_item" | "!change_lvl" | "!calc_stats" | "!force_quest" | "!spawn_enemy" => {
+            gm_level::GM
+        }
+        _ => gm_level::PLAYER,
+    }
+}
+
+/// Checks that the user has at least the provided GM level, otherwise notifies the user.
+async fn has_gm_level(user: &mut User, level: u8) -> Result<bool, crate::Error> {
+    if user.user_data.gm_level >= level {
+        return Ok(true);
+    }
+    user.send_system_msg("You don't have permission to use this command")
+        .await?;
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::required_gm_level;
+    use data_structs::master_ship::gm_level;
+
+    #[test]
+    fn test_required_gm_level() {
+        for cmd in [
+            "!add_item",
+            "!change_lvl",
+            "!force_quest",
+            "!spawn_enemy",
+            "!start_con",
+            "!send_con",
+            "!start_cutscene",
+        ] {
+            assert!(gm_level::PLAYER < required_gm_level(cmd), "{cmd}");
+            assert!(gm_level::MODERATOR < required_gm_level(cmd), "{cmd}");
+            assert!(gm_level::GM >= required_gm_level(cmd), "{cmd}");
+        }
+        for cmd in ["!set_acc_flag", "!set_char_flag"] {
+            assert!(gm_level::GM < required_gm_level(cmd), "{cmd}");
+            assert!(gm_level::ADMIN >= required_gm_level(cmd), "{cmd}");
+        }
+        assert_eq!(required_gm_level("!friends"), gm_level::PLAYER);
+        assert_eq!(required_gm_level("!trade"), gm_level::PLAYER);
+    }
+}
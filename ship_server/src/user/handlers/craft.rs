@@ -0,0 +1,188 @@
+use super::shop::item_name;
+use crate::{craft, events::GameEvent, Error, User};
+use data_structs::reward::Reward;
+
+/// Lists known recipes.
+pub async fn recipes(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let names = &blockdata.server_data.item_params.names;
+    let lines: Vec<_> = blockdata
+        .server_data
+        .recipes
+        .iter()
+        .map(|r| {
+            let materials: Vec<_> = r
+                .materials
+                .iter()
+                .map(|m| format!("{} x{}", item_name(names, m.id), m.amount))
+                .collect();
+            format!(
+                "{}. {} - {} x{} ({}, {} meseta, {}s)",
+                r.id,
+                r.name,
+                item_name(names, r.result),
+                r.amount,
+                materials.join(", "),
+                r.meseta,
+                r.duration
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return user.send_system_msg("No recipes available").await;
+    }
+    let msg = format!("{}\nUse !craft <id> to start crafting", lines.join("\n"));
+    user.send_system_msg(&msg).await
+}
+
+/// Starts crafting the recipe, taking materials from the inventory and storages.
+pub async fn start(user: &mut User, recipe_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(recipe) = blockdata
+        .server_data
+        .recipes
+        .iter()
+        .find(|r| r.id == recipe_id)
+    else {
+        return user.send_system_msg("Unknown recipe").await;
+    };
+    let character = user.character.as_mut().unwrap();
+    if character.crafts.len() >= craft::MAX_SLOTS {
+        return user.send_system_msg("All craft slots are busy").await;
+    }
+    let backup = character.inventory.clone();
+    let materials: Vec<_> = recipe.materials.iter().map(|m| (m.id, m.amount)).collect();
+    let Some((mut packets, storages_changed)) = character.inventory.take_materials(&materials)?
+    else {
+        return user.send_system_msg("Not enough materials").await;
+    };
+    let Some(meseta_packet) = character.inventory.take_meseta(recipe.meseta) else {
+        character.inventory = backup;
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    packets.push(meseta_packet);
+    character.crafts.push(craft::CraftSlot {
+        recipe_id,
+        ready_at: blockdata.clock.now() + std::time::Duration::from_secs(recipe.duration),
+    });
+    // storage is written first, so that a failed write doesn't lose the materials
+    if storages_changed {
+        if let Err(e) = blockdata
+            .sql
+            .update_account_storage(user.user_data.id, &mut character.inventory)
+            .await
+        {
+            character.inventory = backup;
+            character.crafts.pop();
+            return Err(e);
+        }
+    }
+    blockdata.sql.update_character(character).await?;
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    user.send_system_msg(&format!(
+        "Started crafting {}, ready in {}s",
+        recipe.name, recipe.duration
+    ))
+    .await
+}
+
+/// Shows crafts in progress.
+pub async fn status(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let now = blockdata.clock.now();
+    let character = user.character.as_ref().unwrap();
+    if character.crafts.is_empty() {
+        return user.send_system_msg("No crafts in progress").await;
+    }
+    let lines: Vec<_> = character
+        .crafts
+        .iter()
+        .map(|slot| {
+            let name = blockdata
+                .server_data
+                .recipes
+                .iter()
+                .find(|r| r.id == slot.recipe_id)
+                .map_or("Unknown recipe", |r| r.name.as_str());
+            match slot.ready_at.checked_sub(now).filter(|d| !d.is_zero()) {
+                Some(left) => format!("{name} - {}s left", left.as_secs()),
+                None => format!("{name} - finished"),
+            }
+        })
+        .collect();
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Moves results of finished crafts to the inventory.
+pub async fn collect(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let now = blockdata.clock.now();
+    let character = user.character.as_mut().unwrap();
+    let recipes = &blockdata.server_data.recipes;
+    // recipes removed from the data are dropped silently
+    let results: Vec<_> = character
+        .crafts
+        .iter()
+        .filter(|s| s.ready_at <= now)
+        .filter_map(|s| recipes.iter().find(|r| r.id == s.recipe_id))
+        .map(|r| Reward {
+            id: r.result,
+            amount: r.amount,
+        })
+        .collect();
+    if !character.inventory.has_space_for_rewards(&results) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    let finished = craft::take_finished(&mut character.crafts, now);
+    if finished.is_empty() {
+        return user.send_system_msg("No finished crafts").await;
+    }
+    let mut packets = vec![];
+    for slot in &finished {
+        let Some(recipe) = recipes.iter().find(|r| r.id == slot.recipe_id) else {
+            continue;
+        };
+        let added = character.inventory.add_bought_items(
+            &mut user.user_data.last_uuid,
+            recipe.result,
+            recipe.amount,
+        );
+        super::affix::roll_new_items(
+            &mut character.inventory,
+            &blockdata.server_data.affixes,
+            &added,
+        );
+        packets.extend(added);
+        blockdata.events.emit(GameEvent::ItemObtained {
+            player_id: user.user_data.id,
+            item: recipe.result,
+            amount: recipe.amount,
+        });
+    }
+    blockdata.sql.update_character(character).await?;
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    user.send_system_msg(&format!("Collected {} craft(s)", finished.len()))
+        .await
+}
+
+/// Notifies the player about crafts finished while offline.
+pub async fn notify_finished(user: &mut User) -> Result<(), Error> {
+    let now = user.blockdata.clock.now();
+    let character = user.character.as_ref().unwrap();
+    let count = character
+        .crafts
+        .iter()
+        .filter(|s| s.ready_at <= now)
+        .count();
+    if count == 0 {
+        return Ok(());
+    }
+    user.send_system_msg(&format!(
+        "{count} craft(s) are finished. Use !craft_collect to collect them"
+    ))
+    .await
+}
@@ -0,0 +1,131 @@
+use super::rewards::{self, Grant};
+use crate::{events::GameEvent, orders, BlockData, Error, User};
+
+/// Lists featured quests and daily orders of the day.
+pub async fn list(user: &mut User) -> Result<(), Error> {
+    let picks = user.blockdata.daily.picks();
+    if picks.featured.is_empty() && picks.orders.is_empty() {
+        return user
+            .send_system_msg("No featured quests or daily orders today")
+            .await;
+    }
+    show(user).await
+}
+
+/// Shows featured quests and daily orders of the day at the quest counter.
+pub async fn show(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let picks = blockdata.daily.picks();
+    if picks.featured.is_empty() && picks.orders.is_empty() {
+        return Ok(());
+    }
+    let progress = &user.character.as_ref().unwrap().daily;
+    let mut lines = vec![];
+    if !picks.featured.is_empty() {
+        lines.push("Featured quests:".to_string());
+    }
+    for &quest in &picks.featured {
+        let name = blockdata
+            .daily
+            .quest_name(quest)
+            .map_or_else(|| quest.to_string(), str::to_string);
+        let status = if progress.quest_done(picks.day, quest) {
+            " (cleared)"
+        } else {
+            ""
+        };
+        lines.push(format!("- {name}{status}"));
+    }
+    if !picks.orders.is_empty() {
+        lines.push("Daily orders:".to_string());
+    }
+    for &id in &picks.orders {
+        let Some((npc, order)) = orders::find(&blockdata.server_data.client_orders, id) else {
+            continue;
+        };
+        let status = if progress.order_done(picks.day, id) {
+            " (turned in)"
+        } else {
+            ""
+        };
+        lines.push(format!("- {id}. {} ({npc}){status}", order.name));
+    }
+    lines.push("The first clear of each gives a daily bonus".to_string());
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Gives the daily bonus for featured quest clears and daily order turn-ins.
+pub async fn on_event(user: &mut User, event: &GameEvent) -> Result<(), Error> {
+    match *event {
+        GameEvent::QuestCleared {
+            quest: Some(quest), ..
+        } => on_quest_cleared(user, quest).await,
+        GameEvent::OrderTurnedIn { order, .. } => on_order_turned_in(user, order).await,
+        _ => Ok(()),
+    }
+}
+
+/// Gives the daily bonus if the cleared quest is featured today.
+async fn on_quest_cleared(user: &mut User, quest: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let picks = blockdata.daily.picks();
+    if !picks.featured.contains(&quest) {
+        return Ok(());
+    }
+    let Some(character) = user.character.as_ref() else {
+        return Ok(());
+    };
+    if character.daily.quest_done(picks.day, quest) {
+        return Ok(());
+    }
+    let grant = bonus(&blockdata);
+    if !grant.fits(user) {
+        return user
+            .send_system_msg("Not enough inventory space for the daily bonus")
+            .await;
+    }
+    let character = user.character.as_mut().unwrap();
+    character.daily.clear_quest(picks.day, quest);
+    rewards::give(user, grant, vec![]).await?;
+    user.send_system_msg("Featured quest cleared, daily bonus received")
+        .await
+}
+
+/// Gives the daily bonus if the turned in order is a daily order today.
+async fn on_order_turned_in(user: &mut User, order_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let picks = blockdata.daily.picks();
+    if !order_available(user, order_id) {
+        return Ok(());
+    }
+    let grant = bonus(&blockdata);
+    if !grant.fits(user) {
+        return user
+            .send_system_msg("Not enough inventory space for the daily bonus")
+            .await;
+    }
+    let character = user.character.as_mut().unwrap();
+    character.daily.turn_in_order(picks.day, order_id);
+    rewards::give(user, grant, vec![]).await?;
+    user.send_system_msg("Daily order completed, daily bonus received")
+        .await
+}
+
+/// Checks if the order is a daily order that wasn't turned in today.
+pub fn order_available(user: &User, order_id: u32) -> bool {
+    let picks = user.blockdata.daily.picks();
+    picks.orders.contains(&order_id)
+        && !user
+            .character
+            .as_ref()
+            .is_some_and(|c| c.daily.order_done(picks.day, order_id))
+}
+
+fn bonus(blockdata: &BlockData) -> Grant<'_> {
+    let bonus = blockdata.daily.bonus();
+    Grant {
+        items: &bonus.items,
+        meseta: bonus.meseta,
+        exp: bonus.exp,
+    }
+}
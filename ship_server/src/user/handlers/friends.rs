@@ -1,11 +1,39 @@
 use super::HResult;
-use crate::{Action, User};
-use pso2packetlib::protocol::{friends::FriendListRequestPacket, Flags, Packet, PacketHeader};
+use crate::{
+    directory::PlayerEntry,
+    mutex::MutexGuard,
+    sql::{Friend, FriendStatus},
+    Action, Error, User,
+};
+use pso2packetlib::protocol::{
+    friends::{FriendListPacket, FriendListRequestPacket},
+    Flags, Packet, PacketHeader,
+};
+
+/// Maximum number of friends and pending requests of a player.
+const MAX_FRIENDS: usize = 100;
 
 pub async fn get_friends(user: &mut User, _: FriendListRequestPacket) -> HResult {
-    let mut packet: pso2packetlib::protocol::friends::FriendListPacket =
-        serde_json::from_str(&std::fs::read_to_string("data/friend.json")?)?;
-    packet.nickname = user.user_data.nickname.clone();
+    let friends = user.blockdata.sql.get_friends(user.get_user_id()).await?;
+    let mut packet = FriendListPacket {
+        unk3: 1,
+        nickname: user.user_data.nickname.clone(),
+        ..Default::default()
+    };
+    for friend in friends
+        .into_iter()
+        .filter(|f| f.status == FriendStatus::Accepted)
+    {
+        packet.friends.push(Default::default());
+        let entry = packet.friends.last_mut().expect("Entry was just added");
+        entry.id = friend.id as _;
+        entry.nickname = friend.nickname.into();
+        entry.char_name = friend.char_name.into();
+        if let Some(online) = user.blockdata.directory.get(friend.id) {
+            entry.char_name = online.char_name.into();
+            entry.blockid = online.block_id as _;
+        }
+    }
     user.send_packet(&Packet::FriendList(packet)).await?;
     let packet = Packet::Unknown((
         PacketHeader {
@@ -28,3 +56,253 @@ pub async fn get_friends(user: &mut User, _: FriendListRequestPacket) -> HResult
 
     Ok(Action::Nothing)
 }
+
+/// Lists friends with their locations and pending friend requests.
+pub async fn list_friends(user: &mut User) -> Result<(), Error> {
+    let friends = user.blockdata.sql.get_friends(user.get_user_id()).await?;
+    if friends.is_empty() {
+        return user.send_system_msg("Your friend list is empty").await;
+    }
+    let mut lines = vec![];
+    for friend in friends {
+        let name = display_name(&friend.nickname, &friend.char_name);
+        let line = match friend.status {
+            FriendStatus::Accepted => match user.blockdata.directory.get(friend.id) {
+                Some(online) => format!("{name}: online, {}", location(&online)),
+                None => format!("{name}: offline"),
+            },
+            FriendStatus::Incoming => format!("{name}: sent you a friend request"),
+            FriendStatus::Outgoing => format!("{name}: friend request sent"),
+        };
+        lines.push(line);
+    }
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Sends a friend request to an online player or accepts the request from them.
+pub async fn add_friend(user: &mut MutexGuard<'_, User>, name: &str) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let directory = user.blockdata.directory.clone();
+    let Some(target) = directory.find(name) else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let Some(sender) = directory.get(id) else {
+        return Ok(());
+    };
+    if target.id == id {
+        return user.send_system_msg("You can't add yourself").await;
+    }
+    let friends = user.blockdata.sql.get_friends(id).await?;
+    match friends.iter().find(|f| f.id == target.id).map(|f| f.status) {
+        Some(FriendStatus::Accepted) => {
+            return user
+                .send_system_msg(&format!("{} is already your friend", target.nickname))
+                .await
+        }
+        Some(FriendStatus::Outgoing) => {
+            return user
+                .send_system_msg(&format!(
+                    "Friend request was already sent to {}",
+                    target.nickname
+                ))
+                .await
+        }
+        Some(FriendStatus::Incoming) => {
+            if !can_befriend(user, &friends, target.id, &target.nickname).await? {
+                return Ok(());
+            }
+        }
+        None if friends.len() >= MAX_FRIENDS => {
+            return user.send_system_msg("Your friend list is full").await
+        }
+        None => {
+            // the request is also listed by the target
+            let target_friends = user.blockdata.sql.get_friends(target.id).await?;
+            if target_friends.len() >= MAX_FRIENDS {
+                return user
+                    .send_system_msg(&format!("{}'s friend list is full", target.nickname))
+                    .await;
+            }
+        }
+    }
+    let status = user
+        .blockdata
+        .sql
+        .add_friend_request(&sender, &target)
+        .await?;
+    let (msg, target_msg) = match status {
+        FriendStatus::Accepted => (
+            format!("You are now friends with {}", target.nickname),
+            format!("You are now friends with {}", sender.nickname),
+        ),
+        FriendStatus::Outgoing | FriendStatus::Incoming => (
+            format!("Friend request sent to {}", target.nickname),
+            format!(
+                "{} sent you a friend request. Use !friend_accept {} to accept it",
+                sender.nickname, sender.nickname
+            ),
+        ),
+    };
+    notify(user, &target, target_msg).await?;
+    user.send_system_msg(&msg).await
+}
+
+/// Accepts the friend request from the player.
+pub async fn accept_friend(user: &mut MutexGuard<'_, User>, name: &str) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let friends = user.blockdata.sql.get_friends(id).await?;
+    let Some(friend) = find_friend(&friends, name, FriendStatus::Incoming) else {
+        return user
+            .send_system_msg(&format!("No friend request from {name}"))
+            .await;
+    };
+    if !can_befriend(user, &friends, friend.id, &friend.nickname).await? {
+        return Ok(());
+    }
+    if !user.blockdata.sql.accept_friend(id, friend.id).await? {
+        return user
+            .send_system_msg(&format!("No friend request from {name}"))
+            .await;
+    }
+    if let Some(online) = user.blockdata.directory.get(friend.id) {
+        let msg = format!("{} accepted your friend request", user.user_data.nickname);
+        notify(user, &online, msg).await?;
+    }
+    user.send_system_msg(&format!("You are now friends with {}", friend.nickname))
+        .await
+}
+
+/// Declines the friend request from the player.
+pub async fn decline_friend(user: &mut User, name: &str) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let friends = user.blockdata.sql.get_friends(id).await?;
+    let Some(friend) = find_friend(&friends, name, FriendStatus::Incoming) else {
+        return user
+            .send_system_msg(&format!("No friend request from {name}"))
+            .await;
+    };
+    user.blockdata.sql.remove_friend(id, friend.id).await?;
+    user.send_system_msg(&format!(
+        "Declined the friend request from {}",
+        friend.nickname
+    ))
+    .await
+}
+
+/// Removes the friend or cancels the sent friend request.
+pub async fn remove_friend(user: &mut User, name: &str) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let friends = user.blockdata.sql.get_friends(id).await?;
+    let friend = find_friend(&friends, name, FriendStatus::Accepted)
+        .or_else(|| find_friend(&friends, name, FriendStatus::Outgoing));
+    let Some(friend) = friend else {
+        return user
+            .send_system_msg(&format!("{name} is not in your friend list"))
+            .await;
+    };
+    user.blockdata.sql.remove_friend(id, friend.id).await?;
+    user.send_system_msg(&format!(
+        "Removed {} from your friend list",
+        friend.nickname
+    ))
+    .await
+}
+
+/// Updates the stored names of the player and tells online friends where the player is.
+pub async fn notify_presence(user: &User) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let directory = &user.blockdata.directory;
+    let Some(entry) = directory.get(id) else {
+        return Ok(());
+    };
+    let sql = &user.blockdata.sql;
+    sql.update_friend_names(&entry).await?;
+    let targets: Vec<_> = sql
+        .get_friends(id)
+        .await?
+        .into_iter()
+        .filter(|f| f.status == FriendStatus::Accepted)
+        .filter_map(|f| directory.get(f.id)?.user())
+        .collect();
+    if targets.is_empty() {
+        return Ok(());
+    }
+    let msg = format!(
+        "Your friend {} is online on {}",
+        display_name(&entry.nickname, &entry.char_name),
+        entry.block_name
+    );
+    // the friends are locked outside of this session to avoid lock cycles
+    tokio::spawn(async move {
+        for target in targets {
+            let _ = target.lock().await.send_system_msg(&msg).await;
+        }
+    });
+    Ok(())
+}
+
+/// Checks that both players have room for one more accepted friend, otherwise notifies the user.
+async fn can_befriend(
+    user: &mut User,
+    friends: &[Friend],
+    target_id: u32,
+    target_name: &str,
+) -> Result<bool, Error> {
+    let accepted = |friends: &[Friend]| {
+        friends
+            .iter()
+            .filter(|f| f.status == FriendStatus::Accepted)
+            .count()
+    };
+    if accepted(friends) >= MAX_FRIENDS {
+        user.send_system_msg("Your friend list is full").await?;
+        return Ok(false);
+    }
+    let target_friends = user.blockdata.sql.get_friends(target_id).await?;
+    if accepted(&target_friends) >= MAX_FRIENDS {
+        let msg = format!("{target_name}'s friend list is full");
+        user.send_system_msg(&msg).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Sends a system message to the online player.
+async fn notify(
+    user: &mut MutexGuard<'_, User>,
+    target: &PlayerEntry,
+    msg: String,
+) -> Result<(), Error> {
+    let Some(target_user) = target.user() else {
+        return Ok(());
+    };
+    MutexGuard::unlocked_async(user, || async move {
+        target_user.lock().await.send_system_msg(&msg).await
+    })
+    .await
+}
+
+/// Finds the relation with the status by the player id, nickname or character name.
+fn find_friend<'a>(friends: &'a [Friend], name: &str, status: FriendStatus) -> Option<&'a Friend> {
+    let id = name.parse::<u32>().ok();
+    friends.iter().filter(|f| f.status == status).find(|f| {
+        Some(f.id) == id
+            || f.nickname.eq_ignore_ascii_case(name)
+            || f.char_name.eq_ignore_ascii_case(name)
+    })
+}
+
+fn display_name(nickname: &str, char_name: &str) -> String {
+    if char_name.is_empty() {
+        nickname.to_string()
+    } else {
+        format!("{char_name} ({nickname})")
+    }
+}
+
+fn location(entry: &PlayerEntry) -> String {
+    match &entry.zone {
+        Some(zone) => format!("{}, {zone}", entry.block_name),
+        None => entry.block_name.clone(),
+    }
+}
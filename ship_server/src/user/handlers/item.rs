@@ -1,5 +1,6 @@
 use super::HResult;
-use crate::{mutex::MutexGuard, Action, Error, User};
+use crate::{inventory::Inventory, mutex::MutexGuard, Action, Error, User};
+use data_structs::inventory::{DiskItem, StorageExpansion};
 use pso2packetlib::protocol::{
     self,
     items::{
@@ -11,21 +12,72 @@ use pso2packetlib::protocol::{
     login::Language,
     Packet,
 };
+use std::time::{Duration, Instant};
+
+/// Id of the character storage, other storages belong to the account.
+const CHARACTER_STORAGE: u8 = 14;
+
+/// Saves the account storages, if they were changed, and the character. Storages are written
+/// first and the inventory is restored from `backup` if that fails, so no items are lost.
+async fn save_storages(user: &mut User, backup: Option<Inventory>) -> Result<(), Error> {
+    let sql = user.blockdata.sql.clone();
+    let user_id = user.user_data.id;
+    let character = user.character.as_mut().unwrap();
+    if let Some(backup) = backup {
+        if let Err(e) = sql
+            .update_account_storage(user_id, &mut character.inventory)
+            .await
+        {
+            character.inventory = backup;
+            return Err(e);
+        }
+    }
+    sql.update_character(character).await?;
+    Ok(())
+}
 
 pub async fn move_to_storage(user: &mut User, packet: MoveToStorageRequestPacket) -> HResult {
+    let account_changed = packet
+        .uuids
+        .iter()
+        .any(|i| i.storage_id as u8 != CHARACTER_STORAGE);
     let character = user.character.as_mut().unwrap();
-    let packet = character
+    let backup = account_changed.then(|| character.inventory.clone());
+    let packet = match character
         .inventory
-        .move_to_storage(packet, &mut user.user_data.last_uuid)?;
+        .move_to_storage(packet, &mut user.user_data.last_uuid)
+    {
+        Ok(packet) => packet,
+        Err(Error::StorageFull(_)) => {
+            user.send_error("Not enough space in the storage").await?;
+            return Ok(Action::Nothing);
+        }
+        Err(e) => return Err(e),
+    };
+    save_storages(user, backup).await?;
     user.send_packet(&packet).await?;
     Ok(Action::Nothing)
 }
 
 pub async fn move_to_inventory(user: &mut User, packet: MoveToInventoryRequestPacket) -> HResult {
+    let account_changed = packet
+        .uuids
+        .iter()
+        .any(|i| i.storage_id as u8 != CHARACTER_STORAGE);
     let character = user.character.as_mut().unwrap();
-    let packet = character
+    let backup = account_changed.then(|| character.inventory.clone());
+    let packet = match character
         .inventory
-        .move_to_inventory(packet, &mut user.user_data.last_uuid)?;
+        .move_to_inventory(packet, &mut user.user_data.last_uuid)
+    {
+        Ok(packet) => packet,
+        Err(Error::InventoryFull) => {
+            user.send_error("Not enough space in the inventory").await?;
+            return Ok(Action::Nothing);
+        }
+        Err(e) => return Err(e),
+    };
+    save_storages(user, backup).await?;
     user.send_packet(&packet).await?;
     Ok(Action::Nothing)
 }
@@ -54,8 +106,22 @@ pub async fn discard_storage(user: &mut User, packet: DiscardStorageItemRequestP
 }
 
 pub async fn move_storages(user: &mut User, packet: MoveStoragesRequestPacket) -> HResult {
+    let account_changed =
+        packet.old_id as u8 != CHARACTER_STORAGE || packet.new_id as u8 != CHARACTER_STORAGE;
     let character = user.character.as_mut().unwrap();
-    let packet = character.inventory.move_storages(packet, &mut user.user_data.last_uuid)?;
+    let backup = account_changed.then(|| character.inventory.clone());
+    let packet = match character
+        .inventory
+        .move_storages(packet, &mut user.user_data.last_uuid)
+    {
+        Ok(packet) => packet,
+        Err(Error::StorageFull(_)) => {
+            user.send_error("Not enough space in the storage").await?;
+            return Ok(Action::Nothing);
+        }
+        Err(e) => return Err(e),
+    };
+    save_storages(user, backup).await?;
     user.send_packet(&packet).await?;
     Ok(Action::Nothing)
 }
@@ -145,3 +211,186 @@ pub async fn unequip_item(
 
     Ok(Action::Nothing)
 }
+
+/// Uses the ticket item, unlocking its lobby action or stamp.
+pub async fn use_ticket(user: &mut User, item_name: &str) -> HResult {
+    let Some(uuid) = find_inv_item(user, item_name) else {
+        user.send_system_msg(&format!("No {item_name} in the inventory"))
+            .await?;
+        return Ok(Action::Nothing);
+    };
+    let character = user.character.as_mut().unwrap();
+    let item = character.inventory.get_inv_item(uuid)?;
+    let Some(unlock) = user.blockdata.unlocks.ticket(item.id).cloned() else {
+        user.send_system_msg("This item is not a ticket").await?;
+        return Ok(Action::Nothing);
+    };
+    if character.unlocks.has(&unlock) {
+        user.send_system_msg(&format!("You already have the {unlock}"))
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    let (_, packet) = character.inventory.take_inv_item(uuid, 1)?;
+    character.unlocks.add(unlock.clone());
+    user.blockdata.sql.update_character(character).await?;
+    user.send_packet(&packet).await?;
+    user.send_system_msg(&format!("Unlocked the {unlock}"))
+        .await?;
+    Ok(Action::Nothing)
+}
+
+/// Uses the inventory item by its name or uuid.
+pub async fn use_item(user: &mut MutexGuard<'_, User>, item_name: &str) -> HResult {
+    let Some(uuid) = find_inv_item(user, item_name) else {
+        user.send_system_msg(&format!("No {item_name} in the inventory"))
+            .await?;
+        return Ok(Action::Nothing);
+    };
+    let item = user
+        .character
+        .as_ref()
+        .unwrap()
+        .inventory
+        .get_inv_item(uuid)?;
+    let params = &user.blockdata.server_data.item_params;
+    let expansion = params
+        .storage_expansions
+        .iter()
+        .find(|e| e.id == item.id)
+        .cloned();
+    let disk = params.disks.iter().find(|d| d.id == item.id).cloned();
+    if let Some(expansion) = expansion {
+        expand_storage(user, uuid, &expansion).await
+    } else if let Some(disk) = disk {
+        learn_disk(user, uuid, &disk).await
+    } else {
+        use_lobby_item(user, item_name).await
+    }
+}
+
+/// Consumes the disk and teaches its photon art or technique.
+async fn learn_disk(user: &mut User, uuid: u64, disk: &DiskItem) -> HResult {
+    let character = user.character.as_mut().unwrap();
+    let backup = character.palette.clone();
+    if !character.palette.learn_from_disk(disk.weapon, &disk.pa) {
+        user.send_system_msg("This art is already known at this or a higher level")
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    let packet = match character.inventory.take_inv_item(uuid, 1) {
+        Ok((_, packet)) => packet,
+        Err(e) => {
+            character.palette = backup;
+            return Err(e);
+        }
+    };
+    user.blockdata.sql.update_character(character).await?;
+    user.send_packet(&packet).await?;
+    user.send_system_msg(&format!("Learned level {} of the art", disk.pa.level))
+        .await?;
+    Ok(Action::Nothing)
+}
+
+/// Consumes the storage expansion item and increases the capacity of the character storage.
+async fn expand_storage(user: &mut User, uuid: u64, expansion: &StorageExpansion) -> HResult {
+    let character = user.character.as_mut().unwrap();
+    let backup = character.inventory.clone();
+    let Some(capacity) = character
+        .inventory
+        .expand_character_storage(expansion.space, expansion.max_space)
+    else {
+        user.send_system_msg("Character storage can't be expanded further")
+            .await?;
+        return Ok(Action::Nothing);
+    };
+    let packet = match character.inventory.take_inv_item(uuid, 1) {
+        Ok((_, packet)) => packet,
+        Err(e) => {
+            character.inventory = backup;
+            return Err(e);
+        }
+    };
+    user.blockdata.sql.update_character(character).await?;
+    user.send_packet(&packet).await?;
+    user.send_system_msg(&format!("Character storage expanded to {capacity} slots"))
+        .await?;
+    Ok(Action::Nothing)
+}
+
+/// Uses the lobby item, spawning its object next to the player.
+async fn use_lobby_item(user: &mut MutexGuard<'_, User>, item_name: &str) -> HResult {
+    let Some(uuid) = find_inv_item(user, item_name) else {
+        user.send_system_msg(&format!("No {item_name} in the inventory"))
+            .await?;
+        return Ok(Action::Nothing);
+    };
+    let item = user
+        .character
+        .as_ref()
+        .unwrap()
+        .inventory
+        .get_inv_item(uuid)?;
+    let Some(lobby_item) = user
+        .blockdata
+        .server_data
+        .item_params
+        .lobby_items
+        .iter()
+        .find(|i| i.id == item.id)
+        .cloned()
+    else {
+        user.send_system_msg("This item can't be used in the lobby")
+            .await?;
+        return Ok(Action::Nothing);
+    };
+    let now = Instant::now();
+    if user.lobby_item_ready.is_some_and(|ready| ready > now) {
+        user.send_system_msg("You should wait before using another item")
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    let Some(map) = user.get_current_map() else {
+        return Ok(Action::Nothing);
+    };
+    let id = user.get_user_id();
+    let position = user.position;
+    let spawned = MutexGuard::unlocked_async(user, || async move {
+        map.lock()
+            .await
+            .spawn_lobby_prop(id, position, &lobby_item)
+            .await
+    })
+    .await;
+    if !spawned {
+        user.send_system_msg("This item can't be used here right now")
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    user.lobby_item_ready = Some(now + Duration::from_secs(lobby_item.cooldown));
+    if lobby_item.consumable {
+        let user: &mut User = user;
+        let character = user.character.as_mut().unwrap();
+        let (_, packet) = character.inventory.take_inv_item(uuid, 1)?;
+        user.blockdata.sql.update_character(character).await?;
+        user.send_packet(&packet).await?;
+    }
+    Ok(Action::Nothing)
+}
+
+/// Finds the inventory item by its uuid or name.
+pub fn find_inv_item(user: &User, item_name: &str) -> Option<u64> {
+    let names = &user.blockdata.server_data.item_params.names;
+    let character = user.character.as_ref()?;
+    character
+        .inventory
+        .inv_items()
+        .iter()
+        .find(|item| {
+            item_name.parse::<u64>().ok() == Some(item.uuid)
+                || names.iter().any(|n| {
+                    n.id == item.id
+                        && (n.en_name.eq_ignore_ascii_case(item_name) || n.jp_name == item_name)
+                })
+        })
+        .map(|item| item.uuid)
+}
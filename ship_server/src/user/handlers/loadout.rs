@@ -0,0 +1,93 @@
+use super::HResult;
+use crate::{
+    battle_stats::PlayerStats,
+    loadout::{Loadout, MAX_LOADOUTS},
+    mutex::MutexGuard,
+    Action, User,
+};
+
+pub async fn list_loadouts(user: &mut User) -> HResult {
+    let character = user.character.as_ref().unwrap();
+    let mut msg = String::from("Saved loadouts:");
+    for (i, loadout) in character.loadouts.iter().enumerate() {
+        msg.push_str(&format!("\n{i}: {}", loadout.name));
+    }
+    user.send_system_msg(&msg).await?;
+    Ok(Action::Nothing)
+}
+
+pub async fn save_loadout(user: &mut User, slot: usize, name: Option<String>) -> HResult {
+    if slot >= MAX_LOADOUTS {
+        user.send_system_msg(&format!("Slot should be less than {MAX_LOADOUTS}"))
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    let character = user.character.as_mut().unwrap();
+    let count = character.loadouts.len();
+    // loadouts are kept without gaps, so a new loadout can only take the next slot
+    if slot > count {
+        user.send_system_msg(&format!("Next free slot is {count}"))
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    let name = name.unwrap_or_else(|| format!("Loadout {slot}"));
+    let loadout = Loadout::from_character(name, character);
+    if slot == count {
+        character.loadouts.push(loadout);
+    } else {
+        character.loadouts[slot] = loadout;
+    }
+    user.send_system_msg(&format!("Loadout {slot} saved"))
+        .await?;
+    Ok(Action::Nothing)
+}
+
+pub async fn delete_loadout(user: &mut User, slot: usize) -> HResult {
+    let character = user.character.as_mut().unwrap();
+    if slot >= character.loadouts.len() {
+        user.send_system_msg("No such loadout").await?;
+        return Ok(Action::Nothing);
+    }
+    character.loadouts.remove(slot);
+    user.send_system_msg("Loadout deleted").await?;
+    Ok(Action::Nothing)
+}
+
+pub async fn apply_loadout(mut user: MutexGuard<'_, User>, slot: usize) -> HResult {
+    let is_lobby = match user.get_current_map() {
+        Some(map) => map.lock().await.is_lobby(),
+        None => false,
+    };
+    if !is_lobby {
+        user.send_system_msg("Loadouts can only be applied outside of combat")
+            .await?;
+        return Ok(Action::Nothing);
+    }
+    let user_id = user.get_user_id();
+    let equiped = {
+        let user: &mut User = &mut user;
+        let character = user.character.as_mut().unwrap();
+        let Some(loadout) = character.loadouts.get(slot).cloned() else {
+            user.send_system_msg("No such loadout").await?;
+            return Ok(Action::Nothing);
+        };
+        if let Err(e) = loadout.apply(character) {
+            user.send_system_msg(&format!("Loadout \"{}\" is invalid", loadout.name))
+                .await?;
+            return Err(e);
+        }
+        let palette = character.palette.send_palette();
+        let equiped = character.inventory.send_equiped(user_id);
+        user.send_packet(&palette).await?;
+        PlayerStats::update(user)?;
+        equiped
+    };
+    let map = user.get_current_map();
+    drop(user);
+    if let Some(map) = map {
+        let mut lock = map.lock().await;
+        lock.send_palette_change(user_id).await?;
+        lock.send_to_all(user_id, &equiped).await;
+    }
+    Ok(Action::Nothing)
+}
@@ -1,16 +1,30 @@
 use super::HResult;
-use crate::{battle_stats::PlayerStats, user::UserState, Action, Error, User};
-use data_structs::master_ship::SetNicknameResult;
+use crate::{
+    battle_stats::PlayerStats,
+    disconnect::DisconnectReason,
+    events::GameEvent,
+    mutex::MutexGuard,
+    sql,
+    user::{PendingLogin, UserState},
+    Action, Error, User,
+};
+use data_structs::master_ship::{SetNicknameResult, RESET_PREFIX};
 use pso2packetlib::protocol::{
     self,
     items::Item,
     login::{
-        self, AllBlocksListPacket, BlockListPacket, NicknameRequestPacket, NicknameResponsePacket,
+        self, AllBlocksListPacket, BlockListPacket, Language, NicknameRequestPacket,
+        NicknameResponsePacket,
     },
     models::character::Race,
     ObjectHeader, Packet, PacketType,
 };
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{sync::Arc, time::Instant};
+
+/// Number of entries in the login history.
+const LOGIN_HISTORY_LEN: u32 = 50;
+/// Length of the emailed password reset codes.
+const RESET_CODE_LEN: usize = 8;
 
 pub async fn encryption_request(user: &mut User, _: login::EncryptionRequestPacket) -> HResult {
     let key = user.connection.get_key();
@@ -21,53 +35,235 @@ pub async fn encryption_request(user: &mut User, _: login::EncryptionRequestPack
     Ok(Action::Nothing)
 }
 
-pub async fn login_request(user: &mut User, packet: Packet) -> HResult {
+pub async fn login_request(user: &mut MutexGuard<'_, User>, packet: Packet) -> HResult {
     let (mut status, mut error) = Default::default();
     let ip = user.get_ip()?;
     match packet {
         Packet::SegaIDLogin(packet) => {
-            user.user_data.packet_type = PacketType::NA;
-            user.connection.change_packet_type(PacketType::NA);
-            let sega_user = user
-                .blockdata
-                .sql
-                .get_sega_user(&packet.username, &packet.password, ip)
-                .await;
-            match sega_user {
-                Ok(mut data) => {
-                    data.packet_type = user.user_data.packet_type;
-                    data.lang = packet.text_lang;
-                    user.user_data = data;
-                    user.send_packet(&Packet::ChallengeRequest(login::ChallengeRequestPacket {
-                        data: vec![0x0C, 0x47, 0x29, 0x91, 0x27, 0x8E, 0x52, 0x22].into(),
-                    }))
-                    .await?;
+            let packet_type = if user.blockdata.ngs {
+                PacketType::NGS
+            } else {
+                PacketType::NA
+            };
+            user.user_data.packet_type = packet_type;
+            user.connection.change_packet_type(packet_type);
+            let client_version = client_version(&packet.ver_id);
+            let sql = user.blockdata.sql.clone();
+            // password used for the one-time code login
+            let mut password = str::to_owned(&packet.password);
+            let sega_user = if packet.password.is_empty() && !packet.username.is_empty() {
+                sql.request_password_reset(&packet.username, ip)
+                    .await
+                    .and(Err(Error::PasswordResetRequested))
+            } else if let Some(reset) = packet.password.strip_prefix(RESET_PREFIX) {
+                match reset.split_once(':') {
+                    Some((code, new_password)) if code.len() == RESET_CODE_LEN => {
+                        password = new_password.to_string();
+                        match sql
+                            .confirm_reset(&packet.username, code, new_password)
+                            .await
+                        {
+                            Err(Error::NoUser) => Err(Error::InvalidPassword),
+                            r => r,
+                        }
+                    }
+                    _ => Err(Error::InvalidPassword),
                 }
-                Err(Error::InvalidPassword) => {
-                    status = login::LoginStatus::Failure;
-                    error = "Invalid username or password".to_string();
-                }
-                Err(Error::InvalidInput(_)) => {
-                    status = login::LoginStatus::Failure;
-                    error = "Empty username or password".to_string();
-                }
-                Err(e) => return Err(e),
+            } else {
+                sql.get_sega_user(
+                    &packet.username,
+                    &packet.password,
+                    ip,
+                    packet_type,
+                    &client_version,
+                    None,
+                )
+                .await
+            };
+            if let Err(Error::OtpRequired) = sega_user {
+                let pending = PendingLogin {
+                    username: str::to_owned(&packet.username),
+                    password,
+                    lang: packet.text_lang,
+                    client_version,
+                    psn: false,
+                };
+                return request_otp(user, pending).await;
+            }
+            if let Some(e) = sega_login_result(user, sega_user, packet.text_lang).await? {
+                status = login::LoginStatus::Failure;
+                error = e;
             }
         }
         Packet::VitaLogin(packet) => {
             user.user_data.packet_type = PacketType::Vita;
             user.connection.change_packet_type(PacketType::Vita);
-            let mut user_psn = user
+            let user_psn = user
                 .blockdata
                 .sql
-                .get_psn_user(&packet.username, ip)
-                .await?;
-            user_psn.packet_type = user.user_data.packet_type;
-            user.user_data = user_psn;
+                .get_psn_user(&packet.username, ip, None)
+                .await;
+            if let Err(Error::OtpRequired) = user_psn {
+                let pending = PendingLogin {
+                    username: str::to_owned(&packet.username),
+                    password: String::new(),
+                    lang: user.user_data.lang,
+                    client_version: String::new(),
+                    psn: true,
+                };
+                return request_otp(user, pending).await;
+            }
+            if let Some(e) = psn_login_result(user, user_psn).await? {
+                status = login::LoginStatus::Failure;
+                error = e;
+            }
         }
         _ => unreachable!(),
     }
 
+    finish_login(user, status, error).await
+}
+
+/// Handles the one-time code entered in the second password prompt.
+pub async fn otp_response(
+    user: &mut MutexGuard<'_, User>,
+    packet: login::SecondPwdOperationRequestPacket,
+) -> HResult {
+    let Some(pending) = user.pending_login.take() else {
+        return Ok(Action::Nothing);
+    };
+    let ip = user.get_ip()?;
+    let (mut status, mut error) = Default::default();
+    if pending.psn {
+        let sql = user.blockdata.sql.clone();
+        let result = sql
+            .get_psn_user(&pending.username, ip, Some(&*packet.password))
+            .await;
+        if let Some(e) = psn_login_result(user, result).await? {
+            status = login::LoginStatus::Failure;
+            error = e;
+        }
+        return finish_login(user, status, error).await;
+    }
+    let sega_user = user
+        .blockdata
+        .sql
+        .get_sega_user(
+            &pending.username,
+            &pending.password,
+            ip,
+            user.user_data.packet_type,
+            &pending.client_version,
+            Some(&*packet.password),
+        )
+        .await;
+    if let Some(e) = sega_login_result(user, sega_user, pending.lang).await? {
+        status = login::LoginStatus::Failure;
+        error = e;
+    }
+    finish_login(user, status, error).await
+}
+
+/// Asks for the one-time code using the second password prompt.
+async fn request_otp(user: &mut User, pending: PendingLogin) -> HResult {
+    user.pending_login = Some(pending);
+    user.send_packet(&Packet::SecondPwdOperation(
+        login::SecondPwdOperationPacket {
+            unk2: 0,
+            is_set: 1,
+            is_unlocked: 0,
+            unk5: 1,
+            ..Default::default()
+        },
+    ))
+    .await?;
+    Ok(Action::Nothing)
+}
+
+/// Applies the PSN login result. Returns an error message if the login failed.
+async fn psn_login_result(
+    user: &mut User,
+    result: Result<sql::User, Error>,
+) -> Result<Option<String>, Error> {
+    let error = match result {
+        Ok(mut data) => {
+            data.packet_type = user.user_data.packet_type;
+            user.user_data = data;
+            return Ok(None);
+        }
+        Err(Error::InvalidPassword | Error::OtpRequired) => "Invalid one-time code".to_string(),
+        Err(Error::Banned { until, reason }) => {
+            user.shutdown_reason = Some(DisconnectReason::Banned);
+            ban_message(user, until, &reason)
+        }
+        Err(Error::Maintenance(notice)) => notice,
+        Err(Error::TooManyAttempts(retry_after)) => format!(
+            "Too many failed login attempts. Try again in {} second(s)",
+            retry_after.as_secs().max(1)
+        ),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(error))
+}
+
+/// Applies the SEGA ID login result. Returns an error message if the login failed.
+async fn sega_login_result(
+    user: &mut User,
+    result: Result<sql::User, Error>,
+    lang: Language,
+) -> Result<Option<String>, Error> {
+    let error = match result {
+        Ok(mut data) => {
+            data.packet_type = user.user_data.packet_type;
+            data.lang = lang;
+            user.user_data = data;
+            user.send_packet(&Packet::ChallengeRequest(login::ChallengeRequestPacket {
+                data: vec![0x0C, 0x47, 0x29, 0x91, 0x27, 0x8E, 0x52, 0x22].into(),
+            }))
+            .await?;
+            return Ok(None);
+        }
+        Err(Error::InvalidPassword) => "Invalid username or password".to_string(),
+        Err(Error::InvalidInput(_)) => "Empty username or password".to_string(),
+        Err(Error::OtpRequired) => "Invalid one-time code".to_string(),
+        Err(Error::PasswordResetRequested) => {
+            format!(
+                "If the account has a verified email, a password reset code was sent to it. Log \
+                in using \"{RESET_PREFIX}<code>:<new password>\" as the password to set a new \
+                password"
+            )
+        }
+        Err(Error::Banned { until, reason }) => {
+            user.shutdown_reason = Some(DisconnectReason::Banned);
+            ban_message(user, until, &reason)
+        }
+        Err(Error::RegistrationDenied(reason)) => reason,
+        Err(Error::Maintenance(notice)) => notice,
+        Err(Error::OutdatedClient(min_version)) => {
+            format!("Your client is outdated. Please update it to version {min_version} or newer")
+        }
+        Err(Error::TooManyAttempts(retry_after)) => format!(
+            "Too many failed login attempts. Try again in {} second(s)",
+            retry_after.as_secs().max(1)
+        ),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(error))
+}
+
+/// Decodes the version string reported by the client.
+fn client_version(ver_id: &[u8]) -> String {
+    String::from_utf8_lossy(ver_id)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+async fn finish_login(
+    user: &mut MutexGuard<'_, User>,
+    status: login::LoginStatus,
+    error: String,
+) -> HResult {
     if status == login::LoginStatus::Failure {
         user.send_packet(&Packet::LoginResponse(login::LoginResponsePacket {
             status,
@@ -76,9 +272,31 @@ pub async fn login_request(user: &mut User, packet: Packet) -> HResult {
             ..Default::default()
         }))
         .await?;
-        return Ok(Action::Disconnect);
+        return Ok(login_failed(user));
+    }
+
+    if user.user_data.packet_type == PacketType::NA {
+        // NA clients finish the login after answering the challenge
+        user.awaiting_challenge = true;
+        return Ok(Action::Nothing);
+    }
+    complete_login(user).await
+}
+
+/// Handles the answer to the login challenge sent after the credential check.
+pub async fn challenge_response(user: &mut MutexGuard<'_, User>) -> HResult {
+    // only NA clients answer the challenge
+    if user.user_data.packet_type != PacketType::NA {
+        user.user_data.packet_type = PacketType::NA;
+        user.connection.change_packet_type(PacketType::NA);
+    }
+    if !std::mem::take(&mut user.awaiting_challenge) {
+        return Ok(Action::Nothing);
     }
+    complete_login(user).await
+}
 
+async fn complete_login(user: &mut MutexGuard<'_, User>) -> HResult {
     if user.user_data.nickname.is_empty() {
         user.state = UserState::NewUsername;
         user.send_packet(&Packet::NicknameRequest(Default::default()))
@@ -89,8 +307,11 @@ pub async fn login_request(user: &mut User, packet: Packet) -> HResult {
     }
 }
 
-pub async fn on_successful_login(user: &mut User) -> HResult {
+pub async fn on_successful_login(user: &mut MutexGuard<'_, User>) -> HResult {
     let id = user.get_user_id();
+    kick_other_sessions(user).await?;
+    register_in_directory(user).await;
+    super::settings::load_language(user).await?;
     user.send_packet(&Packet::LoginResponse(login::LoginResponsePacket {
         status: login::LoginStatus::Success,
         error: String::new(),
@@ -121,7 +342,82 @@ pub async fn on_successful_login(user: &mut User) -> HResult {
     Ok(Action::Nothing)
 }
 
-pub async fn set_username(user: &mut User, packet: NicknameResponsePacket) -> HResult {
+/// Adds the session to the ship-wide player directory.
+async fn register_in_directory(user: &User) {
+    let conn_id = user.conn_id;
+    user.traffic.set_player(user.get_user_id());
+    let blockdata = &user.blockdata;
+    let handle = blockdata
+        .clients
+        .lock()
+        .await
+        .iter()
+        .find(|(c_conn_id, _)| *c_conn_id == conn_id)
+        .map(|(_, client)| Arc::downgrade(client))
+        .unwrap_or_default();
+    blockdata.directory.register(
+        user.get_user_id(),
+        user.user_data.nickname.clone(),
+        (blockdata.block_id, blockdata.block_name.clone()),
+        conn_id,
+        handle,
+    );
+}
+
+/// Saves and disconnects other sessions of the same account on this block.
+async fn kick_other_sessions(user: &mut MutexGuard<'_, User>) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let conn_id = user.conn_id;
+    let blockdata = user.blockdata.clone();
+    let clients: Vec<_> = blockdata
+        .clients
+        .lock()
+        .await
+        .iter()
+        .filter(|(c_conn_id, _)| *c_conn_id != conn_id)
+        .map(|(_, client)| client.clone())
+        .collect();
+    // other sessions are locked only after this one is released
+    MutexGuard::unlocked_async(user, || async move {
+        for client in clients {
+            let mut other = client.lock().await;
+            if other.state == UserState::LoggingIn || other.get_user_id() != id {
+                continue;
+            }
+            log::info!("User {id} logged in again, disconnecting the previous session");
+            let _ = other
+                .send_system_msg("Your account was logged in from another location")
+                .await;
+            other.save_session().await?;
+            // remove the old session from the map and party now, because the removal on drop
+            // would also remove the new session
+            let party = other.party.take();
+            let map = other.map.take();
+            let switching_block = other.switching_block;
+            other.state = UserState::LoggingIn;
+            other.shutdown_reason = Some(DisconnectReason::Kicked);
+            other.last_ping = Instant::now();
+            drop(other);
+            match party {
+                Some(party) if switching_block => blockdata.parties.start_switch(id, party),
+                Some(party) => {
+                    party.write().await.remove_player(id).await?;
+                }
+                None => {}
+            }
+            if let Some(map) = map {
+                map.lock().await.remove_player(id).await;
+            }
+        }
+        Ok::<_, Error>(())
+    })
+    .await
+}
+
+pub async fn set_username(
+    user: &mut MutexGuard<'_, User>,
+    packet: NicknameResponsePacket,
+) -> HResult {
     let sql = user.blockdata.sql.clone();
     let result = sql
         .set_username(user.get_user_id(), &packet.nickname)
@@ -136,58 +432,45 @@ pub async fn set_username(user: &mut User, packet: NicknameResponsePacket) -> HR
         }
     }
 
-    on_successful_login(user).await
+    let result = on_successful_login(user).await;
+    user.blockdata
+        .directory
+        .set_nickname(user.get_user_id(), &packet.nickname);
+    result
 }
 
 pub async fn block_list(user: &mut User) -> HResult {
-    let mut blocks = BlockListPacket {
-        blocks: Default::default(),
-        unk: 0,
-    };
-    let lock = user.blockdata.blocks.read().await;
-    for block in lock.iter() {
-        blocks.blocks.push(login::BlockInfo {
-            block_id: block.id as u16,
-            blockname: block.name.to_string().into(),
-            ip: block.ip,
-            port: block.port,
-            cur_capacity: block.players as f32 / block.max_players as f32,
-            unk4: 26,
-            unk5: 4,
-            unk6: 1,
-            unk8: 19,
-            unk10: 3,
-            unk11: 164,
-            ..Default::default()
-        })
-    }
-    drop(lock);
-    let pos = blocks
-        .blocks
-        .iter()
-        .enumerate()
-        .find(|(_, b)| b.block_id as u32 == user.blockdata.block_id)
-        .unwrap()
-        .0;
-    blocks.blocks[pos].unk1 = 8;
-    blocks.blocks.swap(pos, 0);
-    user.send_packet(&Packet::BlockList(blocks)).await?;
+    let blocks = block_infos(user).await;
+    user.send_packet(&Packet::BlockList(BlockListPacket { blocks, unk: 0 }))
+        .await?;
     Ok(Action::Nothing)
 }
 
 pub async fn all_block_list(user: &mut User) -> HResult {
-    let mut blocks = AllBlocksListPacket {
-        blocks: Default::default(),
+    let blocks = block_infos(user).await;
+    user.send_packet(&Packet::AllBlocksList(AllBlocksListPacket {
+        blocks,
         unk: 0,
-    };
+    }))
+    .await?;
+    Ok(Action::Nothing)
+}
+
+/// Returns the list of blocks with their current occupancy. The current block is listed first.
+async fn block_infos(user: &User) -> Vec<login::BlockInfo> {
+    let current_id = user.blockdata.block_id;
     let lock = user.blockdata.blocks.read().await;
-    for block in lock.iter() {
-        blocks.blocks.push(login::BlockInfo {
+    let mut blocks: Vec<_> = lock
+        .iter()
+        // classic and NGS clients can't switch between each other's blocks
+        .filter(|block| block.ngs == user.blockdata.ngs)
+        .map(|block| login::BlockInfo {
             block_id: block.id as u16,
             blockname: block.name.to_string().into(),
             ip: block.ip,
             port: block.port,
-            cur_capacity: block.players as f32 / block.max_players as f32,
+            cur_capacity: block_capacity(block.players, block.max_players),
+            unk1: if block.id == current_id { 8 } else { 0 },
             unk4: 26,
             unk5: 4,
             unk6: 1,
@@ -196,22 +479,26 @@ pub async fn all_block_list(user: &mut User) -> HResult {
             unk11: 164,
             ..Default::default()
         })
-    }
+        .collect();
     drop(lock);
-    let pos = blocks
-        .blocks
-        .iter()
-        .enumerate()
-        .find(|(_, b)| b.block_id as u32 == user.blockdata.block_id)
-        .unwrap()
-        .0;
-    blocks.blocks[pos].unk1 = 8;
-    blocks.blocks.swap(pos, 0);
-    user.send_packet(&Packet::AllBlocksList(blocks)).await?;
-    Ok(Action::Nothing)
+    if let Some(pos) = blocks.iter().position(|b| b.block_id as u32 == current_id) {
+        blocks.swap(pos, 0);
+    }
+    blocks
+}
+
+/// Returns the block occupancy in the range from 0 (empty) to 1 (full).
+fn block_capacity(players: u32, max_players: u32) -> f32 {
+    if max_players == 0 {
+        return 1.0;
+    }
+    (players as f32 / max_players as f32).min(1.0)
 }
 
-pub async fn challenge_login(user: &mut User, packet: login::BlockLoginPacket) -> HResult {
+pub async fn challenge_login(
+    user: &mut MutexGuard<'_, User>,
+    packet: login::BlockLoginPacket,
+) -> HResult {
     let user_id = packet.player_id as u32;
     let challenge = packet.challenge;
     let pso_user = user.blockdata.sql.login_challenge(user_id, challenge).await;
@@ -225,11 +512,21 @@ pub async fn challenge_login(user: &mut User, packet: login::BlockLoginPacket) -
             }))
             .await?;
             user.user_data = x;
+            if user.user_data.packet_type == PacketType::NA {
+                // the login response is sent after the challenge is answered
+                user.awaiting_challenge = true;
+                return Ok(Action::Nothing);
+            }
         }
         Err(Error::NoUser) => {
             status = login::LoginStatus::Failure;
             error = "Invalid user".to_string();
         }
+        Err(Error::Banned { until, reason }) => {
+            status = login::LoginStatus::Failure;
+            user.shutdown_reason = Some(DisconnectReason::Banned);
+            error = ban_message(user, until, &reason);
+        }
 
         Err(e) => return Err(e),
     }
@@ -246,7 +543,7 @@ pub async fn challenge_login(user: &mut User, packet: login::BlockLoginPacket) -
     }))
     .await?;
     if let login::LoginStatus::Failure = status {
-        return Ok(Action::Disconnect);
+        return Ok(login_failed(user));
     }
 
     on_successful_login(user).await
@@ -275,6 +572,7 @@ pub async fn switch_block(user: &mut User, packet: login::BlockSwitchRequestPack
             user_id: user.get_user_id(),
         });
         drop(lock);
+        user.switching_block = true;
         user.send_packet(&packet).await?;
     }
     Ok(Action::Nothing)
@@ -283,7 +581,7 @@ pub async fn switch_block(user: &mut User, packet: login::BlockSwitchRequestPack
 pub async fn client_ping(user: &mut User, packet: login::ClientPingPacket) -> HResult {
     let response = login::ClientPongPacket {
         client_time: packet.time,
-        server_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().into(),
+        server_time: user.blockdata.clock.now().into(),
         unk1: 0,
     };
     user.send_packet(&Packet::ClientPong(response)).await?;
@@ -462,6 +760,7 @@ pub async fn new_character(user: &mut User, packet: login::CharacterCreatePacket
         char_data
             .palette
             .set_subpalette_data(class_data.subpalettes.clone());
+        char_data.palette.init_learned(&char_data.inventory);
     }
     // first ep1 quest
     char_data.unlocked_quests.push(700000);
@@ -481,13 +780,37 @@ pub async fn new_character(user: &mut User, packet: login::CharacterCreatePacket
 }
 
 pub async fn start_game(user: &mut User, packet: login::StartGamePacket) -> HResult {
-    let char = user
+    let mut char = user
         .blockdata
         .sql
         .get_character(user.get_user_id(), packet.char_id)
         .await?;
+    let report = char.inventory.validate();
+    if !report.is_clean() {
+        log::warn!(
+            "Quarantined items of character {}: {report}",
+            char.character.character_id
+        );
+        user.blockdata.sql.update_character(&char).await?;
+    }
+    char.palette.init_learned(&char.inventory);
+    user.blockdata
+        .directory
+        .set_character(user.get_user_id(), &char.character.name);
+    super::friends::notify_presence(user).await?;
+    super::blacklist::load(user, char.character.character_id).await?;
+    let now = user.blockdata.clock.now();
+    user.mute = user.blockdata.sql.get_mute(user.get_user_id(), now).await?;
     user.character = Some(char);
+    if let Some(title) = super::titles::equipped_name(user) {
+        user.blockdata
+            .directory
+            .set_title(user.get_user_id(), title);
+    }
     user.session_start = std::time::Instant::now();
+    user.blockdata.events.emit(GameEvent::LoggedIn {
+        player_id: user.get_user_id(),
+    });
     user.send_packet(&Packet::LoadingScreenTransition).await?;
     user.state = UserState::PreInGame;
     user.battle_stats = PlayerStats::build(user)?;
@@ -495,10 +818,31 @@ pub async fn start_game(user: &mut User, packet: login::StartGamePacket) -> HRes
 }
 
 pub async fn login_history(user: &mut User) -> HResult {
-    let attempts = user.blockdata.sql.get_logins(user.get_user_id()).await?;
+    let attempts = user
+        .blockdata
+        .sql
+        .get_logins(user.get_user_id(), LOGIN_HISTORY_LEN)
+        .await?;
     user.send_packet(&Packet::LoginHistoryResponse(login::LoginHistoryPacket {
-        attempts,
+        attempts: attempts.into_iter().map(Into::into).collect(),
     }))
     .await?;
     Ok(Action::Nothing)
 }
+
+/// Returns the disconnect action for a rejected login.
+fn login_failed(user: &User) -> Action {
+    Action::Disconnect(
+        user.shutdown_reason
+            .unwrap_or(DisconnectReason::LoginFailed),
+    )
+}
+
+fn ban_message(user: &User, until: Option<std::time::Duration>, reason: &str) -> String {
+    let Some(until) = until else {
+        return format!("Your account has been permanently banned.\nReason: {reason}");
+    };
+    let now = user.blockdata.clock.now();
+    let hours = until.saturating_sub(now).as_secs().div_ceil(3600);
+    format!("Your account has been suspended for {hours} more hour(s).\nReason: {reason}")
+}
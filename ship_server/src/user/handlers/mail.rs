@@ -0,0 +1,284 @@
+use crate::{
+    events::GameEvent,
+    mail::{Mail, MAX_MAILS, MAX_MESSAGE_LEN},
+    Error, User,
+};
+use pso2packetlib::protocol::items::{Item, ItemId, ItemType};
+
+/// Number of message characters shown in the mail list.
+const PREVIEW_LEN: usize = 30;
+
+/// Lists mails of the current character.
+pub async fn list_mail(user: &mut User) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let mails = user.blockdata.sql.get_mails(char_id).await?;
+    if mails.is_empty() {
+        return user.send_system_msg("Your mailbox is empty").await;
+    }
+    let mut lines = vec![];
+    for mail in mails {
+        let mut preview: String = mail.message.chars().take(PREVIEW_LEN).collect();
+        if preview.len() < mail.message.len() {
+            preview.push_str("...");
+        }
+        lines.push(format!(
+            "{}{}: {} - {preview}{}",
+            mail.id,
+            if mail.is_read { "" } else { " (new)" },
+            mail.sender,
+            if mail.has_attachments() {
+                " [attachments]"
+            } else {
+                ""
+            }
+        ));
+    }
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Shows the full mail and marks it as read.
+pub async fn read_mail(user: &mut User, mail_id: u32) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let Some(mail) = user.blockdata.sql.read_mail(char_id, mail_id).await? else {
+        return user.send_system_msg("No such mail").await;
+    };
+    let mut msg = format!("From {}:\n{}", mail.sender, mail.message);
+    if mail.has_attachments() {
+        msg.push_str(&format!(
+            "\nAttachments: {} item(s), {} meseta. Use !claim_mail {} to receive them",
+            mail.attachments.len(),
+            mail.meseta,
+            mail.id
+        ));
+    }
+    user.send_system_msg(&msg).await
+}
+
+/// Sends a text mail to the character.
+pub async fn send_mail(user: &mut User, recipient: &str, message: &str) -> Result<(), Error> {
+    let mail = Mail::new(character_name(user), message, user.blockdata.clock.now());
+    deliver(user, recipient, mail).await
+}
+
+/// Sends `amount` of the inventory item (by name or uuid) to the character.
+pub async fn send_item(
+    user: &mut User,
+    recipient: &str,
+    amount: u16,
+    item_name: &str,
+) -> Result<(), Error> {
+    let Some(uuid) = super::item::find_inv_item(user, item_name) else {
+        return user
+            .send_system_msg(&format!("No {item_name} in the inventory"))
+            .await;
+    };
+    let Some(recipient_id) = check_recipient(user, recipient).await? else {
+        return Ok(());
+    };
+    let character = user.character.as_mut().unwrap();
+    let (item, augments, packet) = match character
+        .inventory
+        .take_inv_item_with_augments(uuid, amount)
+    {
+        Ok(r) => r,
+        Err(Error::InvalidInput(_)) => {
+            return user
+                .send_system_msg("This item can't be sent (is it equiped?)")
+                .await
+        }
+        Err(e) => return Err(e),
+    };
+    user.send_packet(&packet).await?;
+    let mut mail = Mail::new(
+        character_name(user),
+        "Sent you an item",
+        user.blockdata.clock.now(),
+    );
+    mail.attachments.push(item);
+    mail.augments.push(augments);
+    // the item is removed from the saved character before the mail exists to prevent duplicates
+    user.blockdata
+        .sql
+        .update_character(user.character.as_ref().unwrap())
+        .await?;
+    user.blockdata.sql.send_mail(recipient_id, &mail).await?;
+    notify_recipient(user, recipient, &mail.sender);
+    user.send_system_msg(&format!("Item sent to {recipient}"))
+        .await
+}
+
+/// Moves the mail attachments to the inventory.
+pub async fn claim_mail(user: &mut User, mail_id: u32) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let mail = user.blockdata.sql.read_mail(char_id, mail_id).await?;
+    let Some(mail) = mail.filter(Mail::has_attachments) else {
+        return user.send_system_msg("No attachments in this mail").await;
+    };
+    let inventory = &user.character.as_ref().unwrap().inventory;
+    // attachments are added as new entries, even stackable ones
+    if !inventory.has_space_for_new(mail.attachments.len()) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    // the mailbox belongs to the locked user, so the attachments can't change in between
+    let mail = user
+        .blockdata
+        .sql
+        .take_mail_attachments(char_id, mail_id)
+        .await?;
+    let Some(mail) = mail.filter(Mail::has_attachments) else {
+        return user.send_system_msg("No attachments in this mail").await;
+    };
+    let character = user.character.as_mut().unwrap();
+    let mut packets = vec![];
+    let mut augments = mail.augments.into_iter();
+    for item in mail.attachments {
+        packets.push(character.inventory.add_new_item(
+            &mut user.user_data.last_uuid,
+            item,
+            augments.next().unwrap_or_default(),
+        ));
+    }
+    if mail.meseta != 0 {
+        packets.push(character.inventory.add_meseta(mail.meseta));
+        user.blockdata.events.emit(GameEvent::MesetaCreated {
+            player_id: user.user_data.id,
+            amount: mail.meseta,
+        });
+    }
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    user.blockdata
+        .sql
+        .update_character(user.character.as_ref().unwrap())
+        .await?;
+    user.send_system_msg("Attachments received").await
+}
+
+/// Deletes the mail. Mails with unclaimed attachments can't be deleted.
+pub async fn delete_mail(user: &mut User, mail_id: u32) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let sql = user.blockdata.sql.clone();
+    let mails = sql.get_mails(char_id).await?;
+    let Some(mail) = mails.iter().find(|m| m.id == mail_id) else {
+        return user.send_system_msg("No such mail").await;
+    };
+    if mail.has_attachments() {
+        return user
+            .send_system_msg("Claim the attachments before deleting the mail")
+            .await;
+    }
+    sql.delete_mail(char_id, mail_id).await?;
+    user.send_system_msg("Mail deleted").await
+}
+
+/// Sends a mail with optional meseta and item attachments to every character of the ship.
+pub async fn mass_mail(
+    user: &mut User,
+    meseta: u64,
+    item: Option<ItemId>,
+    message: &str,
+) -> Result<(), Error> {
+    if message.is_empty() || message.chars().count() > MAX_MESSAGE_LEN {
+        return user.send_system_msg("Invalid message length").await;
+    }
+    let mut mail = Mail::new("Administration", message, user.blockdata.clock.now());
+    mail.meseta = meseta;
+    if let Some(id) = item {
+        mail.attachments.push(Item {
+            uuid: 0,
+            id,
+            data: ItemType::default(),
+        });
+    }
+    let count = user.blockdata.sql.send_mass_mail(&mail).await?;
+    log::info!(
+        "Player {} sent a mass mail to {count} characters",
+        user.get_user_id()
+    );
+    for online in user.blockdata.directory.online_players() {
+        if let Some(target) = online.user() {
+            tokio::spawn(async move {
+                let _ = target
+                    .lock()
+                    .await
+                    .send_system_msg("You have new mail from Administration")
+                    .await;
+            });
+        }
+    }
+    user.send_system_msg(&format!("Mail sent to {count} characters"))
+        .await
+}
+
+/// Tells the player about unread mails of the selected character.
+pub async fn notify_unread(user: &mut User) -> Result<(), Error> {
+    let char_id = character_id(user);
+    let count = user.blockdata.sql.unread_mail_count(char_id).await?;
+    if count == 0 {
+        return Ok(());
+    }
+    user.send_system_msg(&format!(
+        "You have {count} unread mail(s). Use !mail to read them"
+    ))
+    .await
+}
+
+/// Delivers the mail to the character and notifies the recipient.
+async fn deliver(user: &mut User, recipient: &str, mail: Mail) -> Result<(), Error> {
+    if mail.message.is_empty() || mail.message.chars().count() > MAX_MESSAGE_LEN {
+        return user
+            .send_system_msg(&format!(
+                "Message should contain from 1 to {MAX_MESSAGE_LEN} characters"
+            ))
+            .await;
+    }
+    let Some(recipient_id) = check_recipient(user, recipient).await? else {
+        return Ok(());
+    };
+    user.blockdata.sql.send_mail(recipient_id, &mail).await?;
+    notify_recipient(user, recipient, &mail.sender);
+    user.send_system_msg(&format!("Mail sent to {recipient}"))
+        .await
+}
+
+/// Returns the id of the recipient character if its mailbox can receive a mail.
+async fn check_recipient(user: &mut User, recipient: &str) -> Result<Option<u32>, Error> {
+    let sql = user.blockdata.sql.clone();
+    let Some(recipient_id) = sql.find_character(recipient).await? else {
+        user.send_system_msg(&format!("No character named {recipient}"))
+            .await?;
+        return Ok(None);
+    };
+    if sql.get_mails(recipient_id).await?.len() >= MAX_MAILS {
+        user.send_system_msg(&format!("Mailbox of {recipient} is full"))
+            .await?;
+        return Ok(None);
+    }
+    Ok(Some(recipient_id))
+}
+
+fn notify_recipient(user: &User, recipient: &str, sender: &str) {
+    let Some(target) = user
+        .blockdata
+        .directory
+        .find(recipient)
+        .filter(|p| p.char_name.eq_ignore_ascii_case(recipient))
+        .and_then(|p| p.user())
+    else {
+        return;
+    };
+    let msg = format!("You have new mail from {sender}");
+    // the recipient is locked outside of this session to avoid lock cycles
+    tokio::spawn(async move {
+        let _ = target.lock().await.send_system_msg(&msg).await;
+    });
+}
+
+fn character_id(user: &User) -> u32 {
+    user.character.as_ref().unwrap().character.character_id
+}
+
+fn character_name(user: &User) -> String {
+    user.character.as_ref().unwrap().character.name.clone()
+}
@@ -1,16 +1,37 @@
-use super::HResult;
-use crate::{Action, User};
-use pso2packetlib::protocol::{missionpass, Packet};
+use super::{
+    rewards::{self, Grant},
+    HResult,
+};
+use crate::{
+    events::GameEvent,
+    missionpass::{self, PassProgress},
+    Action, BlockData, Error, User,
+};
+use data_structs::missionpass::MissionPassSeason;
+use pso2packetlib::protocol::{missionpass as mp_packets, Packet};
 
 pub async fn mission_pass_info(user: &mut User) -> HResult {
     let mut temp = [0u32; 47];
     temp[10] = 1;
-    //2 - current tier
-    //3 - current stars
-    //6 - gold status
-    //7 - over run
-    //8 - already claimed
-    let packet = missionpass::MissionPassInfoPacket {
+    let blockdata = user.blockdata.clone();
+    if let Some(season) = current_season(&blockdata) {
+        let progress = load_progress(user, season).await?;
+        let tier = season.tier_at(progress.stars);
+        //2 - current tier
+        //3 - current stars
+        //6 - gold status
+        //7 - over run
+        //8 - already claimed
+        temp[2] = tier;
+        temp[3] = progress
+            .stars
+            .checked_rem(season.stars_per_tier)
+            .unwrap_or(0);
+        temp[6] = progress.gold as u32;
+        temp[7] = (tier as usize >= season.tiers.len()) as u32;
+        temp[8] = progress.claimed.len() as u32;
+    }
+    let packet = mp_packets::MissionPassInfoPacket {
         unk: temp.to_vec().into(),
     };
     user.send_packet(&Packet::MissionPassInfo(packet)).await?;
@@ -18,25 +39,185 @@ pub async fn mission_pass_info(user: &mut User) -> HResult {
 }
 
 pub async fn mission_pass(user: &mut User) -> HResult {
-    let packet = missionpass::MissionPassPacket {
+    let blockdata = user.blockdata.clone();
+    let seasons = &blockdata.server_data.mission_passes;
+    let now = blockdata.clock.now().as_secs();
+    let mut packet = mp_packets::MissionPassPacket {
         unk1: 1,
-        cur_season_id: 2,
-        cur_season: "ew".to_string(),
-        stars_per_tier: 333,
-        tiers: 3,
-        overrun_tiers: 10,
-        total_tiers: 13,
-        start_date: 6,
-        end_date: 1689272266,
-        catchup_start: 9,
-        unk11: 10,
-        cur_banner: "mp_banner_image_03".to_string(),
-        price_per_tier: 11,
-        gold_pass_price: 12,
-        last_season_id: 1,
-        last_season: "abc".to_string(),
         ..Default::default()
     };
+    if let Some(season) = missionpass::current_season(seasons, now) {
+        packet.cur_season_id = season.id;
+        packet.cur_season = season.name.clone();
+        packet.stars_per_tier = season.stars_per_tier;
+        packet.tiers = season.tiers.len() as _;
+        packet.overrun_tiers = season.overrun_tiers.len() as _;
+        packet.total_tiers = season.total_tiers();
+        packet.start_date = season.start as _;
+        packet.end_date = season.end as _;
+        packet.catchup_start = season.end as _;
+        packet.cur_banner = season.banner.clone();
+        packet.gold_pass_price = season.gold_pass_price as _;
+    }
+    if let Some(season) = missionpass::last_season(seasons, now) {
+        packet.last_season_id = season.id;
+        packet.last_season = season.name.clone();
+    }
     user.send_packet(&Packet::MissionPass(packet)).await?;
     Ok(Action::Nothing)
 }
+
+/// Gives stars for cleared quests and level ups.
+pub async fn on_event(user: &mut User, event: &GameEvent) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(season) = current_season(&blockdata) else {
+        return Ok(());
+    };
+    let stars = match event {
+        GameEvent::QuestCleared { .. } => season.quest_stars,
+        GameEvent::LevelUp { .. } => season.level_up_stars,
+        _ => 0,
+    };
+    if stars == 0 {
+        return Ok(());
+    }
+    let mut progress = load_progress(user, season).await?;
+    let new_tiers = progress.add_stars(season, stars);
+    save_progress(user, season, &progress).await?;
+    if new_tiers != 0 {
+        let tier = season.tier_at(progress.stars);
+        user.send_system_msg(&format!(
+            "Mission pass tier {tier} reached. Use !mp_claim to receive the rewards"
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// Shows mission pass progress of the account.
+pub async fn status(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(season) = current_season(&blockdata) else {
+        return user
+            .send_system_msg("No mission pass season is running")
+            .await;
+    };
+    let progress = load_progress(user, season).await?;
+    let mut msg = format!(
+        "{}: tier {}/{}, {} stars",
+        season.name,
+        season.tier_at(progress.stars),
+        season.total_tiers(),
+        progress.stars
+    );
+    if progress.gold {
+        msg.push_str(", gold pass");
+    } else if season.gold_pass_price != 0 {
+        msg.push_str(&format!(
+            "\nUse !mp_gold to buy the gold pass for {} meseta",
+            season.gold_pass_price
+        ));
+    }
+    if progress.has_rewards(season) {
+        msg.push_str("\nUse !mp_claim to receive the rewards");
+    }
+    user.send_system_msg(&msg).await
+}
+
+/// Gives rewards of all reached tiers.
+pub async fn claim(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(season) = current_season(&blockdata) else {
+        return user
+            .send_system_msg("No mission pass season is running")
+            .await;
+    };
+    let mut progress = load_progress(user, season).await?;
+    let items = progress.take_rewards(season);
+    if items.is_empty() {
+        return user.send_system_msg("No reward to claim").await;
+    }
+    let grant = Grant {
+        items: &items,
+        ..Default::default()
+    };
+    if !grant.fits(user) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    // progress is saved first so that failures don't allow claiming twice
+    save_progress(user, season, &progress).await?;
+    rewards::give(user, grant, vec![]).await?;
+    let names = &blockdata.server_data.item_params.names;
+    user.send_system_msg(&format!("Received {}", grant.describe(names)))
+        .await
+}
+
+/// Buys the gold pass of the current season.
+pub async fn buy_gold(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(season) = current_season(&blockdata) else {
+        return user
+            .send_system_msg("No mission pass season is running")
+            .await;
+    };
+    let mut progress = load_progress(user, season).await?;
+    if progress.gold {
+        return user.send_system_msg("Gold pass is already owned").await;
+    }
+    let character = user.character.as_mut().unwrap();
+    let Some(packet) = character.inventory.take_meseta(season.gold_pass_price) else {
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    // the pass is saved first, so a failed write can't take meseta without giving the pass
+    progress.gold = true;
+    if let Err(e) = save_progress(user, season, &progress).await {
+        let character = user.character.as_mut().unwrap();
+        character.inventory.add_meseta(season.gold_pass_price);
+        return Err(e);
+    }
+    let character = user.character.as_mut().unwrap();
+    blockdata.sql.update_character(character).await?;
+    user.send_packet(&packet).await?;
+    let mut msg = String::from("Gold pass bought");
+    if progress.has_rewards(season) {
+        msg.push_str(". Use !mp_claim to receive the rewards");
+    }
+    user.send_system_msg(&msg).await
+}
+
+fn current_season(blockdata: &BlockData) -> Option<&MissionPassSeason> {
+    let now = blockdata.clock.now().as_secs();
+    missionpass::current_season(&blockdata.server_data.mission_passes, now)
+}
+
+async fn load_progress(user: &User, season: &MissionPassSeason) -> Result<PassProgress, Error> {
+    let value = user
+        .blockdata
+        .sql
+        .get_account_value(
+            user.get_user_id(),
+            missionpass::NAMESPACE,
+            &season.id.to_string(),
+        )
+        .await?;
+    match value {
+        Some(data) => Ok(rmp_serde::from_slice(&data)?),
+        None => Ok(PassProgress::default()),
+    }
+}
+
+async fn save_progress(
+    user: &User,
+    season: &MissionPassSeason,
+    progress: &PassProgress,
+) -> Result<(), Error> {
+    user.blockdata
+        .sql
+        .put_account_value(
+            user.get_user_id(),
+            missionpass::NAMESPACE,
+            &season.id.to_string(),
+            Some(rmp_serde::to_vec(progress)?),
+        )
+        .await
+}
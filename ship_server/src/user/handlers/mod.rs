@@ -1,18 +1,36 @@
 use crate::{Action, Error};
 
+pub mod account;
+pub mod affix;
+pub mod alliance;
 pub mod arksmission;
+pub mod blacklist;
+pub mod cards;
+pub mod casino;
 pub mod chat;
+pub mod craft;
+pub mod daily;
 pub mod friends;
 pub mod item;
+pub mod loadout;
 pub mod login;
+pub mod mail;
 pub mod missionpass;
 pub mod object;
+pub mod orders;
 pub mod palette;
 pub mod party;
 pub mod player_status;
 pub mod quest;
+pub mod rewards;
+pub mod search;
 pub mod server;
 pub mod settings;
+pub mod shop;
+pub mod support;
 pub mod symbolart;
+pub mod team;
+pub mod titles;
+pub mod trade;
 
 type HResult = Result<Action, Error>;
@@ -1,5 +1,5 @@
 use super::HResult;
-use crate::{mutex::MutexGuard, Action, User};
+use crate::{mutex::MutexGuard, Action, Error, User};
 use pso2packetlib::protocol::{objects, Packet};
 
 pub async fn movement(mut user: MutexGuard<'_, User>, packet: objects::MovementPacket) -> HResult {
@@ -27,12 +27,20 @@ pub async fn movement(mut user: MutexGuard<'_, User>, packet: objects::MovementP
     User::send_position(user, Packet::Movement(packet)).await
 }
 
-pub async fn action(user: MutexGuard<'_, User>, packet: objects::InteractPacket) -> HResult {
+pub async fn action(mut user: MutexGuard<'_, User>, packet: objects::InteractPacket) -> HResult {
     let id = user.get_user_id();
-    let map = user.get_current_map();
-    drop(user);
-    if let Some(map) = map {
-        map.lock().await.interaction(packet, id).await?;
+    let Some(map) = user.get_current_map() else {
+        return Ok(Action::Nothing);
+    };
+    let object_id = packet.object1.id;
+    let npc = MutexGuard::unlocked_async(&mut user, || async move {
+        let mut map = map.lock().await;
+        map.interaction(packet, id).await?;
+        Ok::<_, Error>(map.npc_name(id, object_id).map(str::to_string))
+    })
+    .await?;
+    if let Some(shop) = npc.and_then(|npc| super::shop::npc_shop(&user, &npc)) {
+        super::shop::list(&mut user, &shop.to_string()).await?;
     }
     Ok(Action::Nothing)
 }
@@ -0,0 +1,209 @@
+use super::{
+    rewards::{self, Grant},
+    shop::item_name,
+};
+use crate::{
+    events::GameEvent,
+    orders::{self, ActiveOrder},
+    Error, User,
+};
+use data_structs::{
+    inventory::ItemName,
+    order::{ClientOrder, OrderKind},
+};
+
+/// Lists NPCs giving orders or orders of the NPC.
+pub async fn list(user: &mut User, npc: &str) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let all = &blockdata.server_data.client_orders;
+    if npc.is_empty() {
+        let mut npcs: Vec<_> = all.keys().map(String::as_str).collect();
+        if npcs.is_empty() {
+            return user.send_system_msg("No client orders available").await;
+        }
+        npcs.sort_unstable();
+        let msg = format!(
+            "NPCs with client orders: {}\nUse !orders <npc> to see their orders",
+            npcs.join(", ")
+        );
+        return user.send_system_msg(&msg).await;
+    }
+    let Some(npc_orders) = all
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(npc))
+        .map(|(_, o)| o)
+    else {
+        return user.send_system_msg("Unknown NPC").await;
+    };
+    let progress = &user.character.as_ref().unwrap().orders;
+    let names = &blockdata.server_data.item_params.names;
+    let lines: Vec<_> = npc_orders
+        .iter()
+        .map(|o| {
+            let status = if progress.is_active(o.id) {
+                " (accepted)"
+            } else if progress.completed.contains(&o.id) {
+                " (completed)"
+            } else {
+                ""
+            };
+            format!("{}. {} - {}{status}", o.id, o.name, describe(o, names))
+        })
+        .collect();
+    let msg = format!(
+        "{}\nUse !order_accept <id> to accept an order",
+        lines.join("\n")
+    );
+    user.send_system_msg(&msg).await
+}
+
+/// Accepts the order.
+pub async fn accept(user: &mut User, order_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some((_, order)) = orders::find(&blockdata.server_data.client_orders, order_id) else {
+        return user.send_system_msg("Unknown order").await;
+    };
+    let daily = super::daily::order_available(user, order_id);
+    let character = user.character.as_mut().unwrap();
+    let progress = &mut character.orders;
+    if progress.is_active(order_id) {
+        return user.send_system_msg("Order is already accepted").await;
+    }
+    if progress.completed.contains(&order_id) && !order.repeatable && !daily {
+        return user.send_system_msg("Order is already completed").await;
+    }
+    if progress.active.len() >= orders::MAX_ACTIVE {
+        return user
+            .send_system_msg(&format!(
+                "At most {} orders can be accepted",
+                orders::MAX_ACTIVE
+            ))
+            .await;
+    }
+    progress.active.push(ActiveOrder {
+        id: order_id,
+        progress: 0,
+    });
+    blockdata.sql.update_character(character).await?;
+    user.send_system_msg(&format!("Accepted order {}", order.name))
+        .await
+}
+
+/// Shows accepted orders.
+pub async fn progress(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let progress = &user.character.as_ref().unwrap().orders;
+    let lines: Vec<_> = progress
+        .active
+        .iter()
+        .filter_map(|a| {
+            let (npc, order) = orders::find(&blockdata.server_data.client_orders, a.id)?;
+            let status = match order.kind {
+                OrderKind::Kill => format!("{}/{}", a.progress, order.amount),
+                OrderKind::Collect => {
+                    let item = item_name(&blockdata.server_data.item_params.names, order.item);
+                    format!("bring {item} x{}", order.amount)
+                }
+            };
+            Some(format!("{}. {} ({npc}) - {status}", order.id, order.name))
+        })
+        .collect();
+    if lines.is_empty() {
+        return user.send_system_msg("No accepted orders").await;
+    }
+    let msg = format!(
+        "{}\nUse !order_turnin <id> to turn in a finished order",
+        lines.join("\n")
+    );
+    user.send_system_msg(&msg).await
+}
+
+/// Turns in the finished order and gives its rewards.
+pub async fn turn_in(user: &mut User, order_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let character = user.character.as_mut().unwrap();
+    let Some((_, order)) = orders::find(&blockdata.server_data.client_orders, order_id)
+        .filter(|_| character.orders.is_active(order_id))
+    else {
+        return user.send_system_msg("Order is not accepted").await;
+    };
+    let grant = Grant {
+        items: &order.rewards,
+        meseta: order.meseta,
+        exp: order.exp,
+    };
+    if !grant.fits(user) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    let character = user.character.as_mut().unwrap();
+    let backup = (character.orders.clone(), character.inventory.clone());
+    if character.orders.take_ready(order).is_none() {
+        return user.send_system_msg("Order is not finished").await;
+    }
+    let mut packets = vec![];
+    if order.kind == OrderKind::Collect {
+        let taken = character
+            .inventory
+            .take_materials(&[(order.item, order.amount)]);
+        let (taken, storages_changed) = match taken {
+            Ok(Some(taken)) => taken,
+            Ok(None) => {
+                (character.orders, character.inventory) = backup;
+                return user.send_system_msg("Not enough items").await;
+            }
+            Err(e) => {
+                (character.orders, character.inventory) = backup;
+                return Err(e);
+            }
+        };
+        // taken items must be gone from the storages before any reward is given
+        if storages_changed {
+            let result = blockdata
+                .sql
+                .update_account_storage(user.user_data.id, &mut character.inventory)
+                .await;
+            if let Err(e) = result {
+                (character.orders, character.inventory) = backup;
+                return Err(e);
+            }
+        }
+        packets = taken;
+    }
+    rewards::give(user, grant, packets).await?;
+    blockdata.events.emit(GameEvent::OrderTurnedIn {
+        player_id: user.user_data.id,
+        order: order_id,
+    });
+    user.send_system_msg(&format!("Order {} completed", order.name))
+        .await
+}
+
+/// Counts the enemy kill for accepted orders.
+pub async fn on_event(user: &mut User, event: &GameEvent) -> Result<(), Error> {
+    let GameEvent::EnemyDefeated { enemy, .. } = event else {
+        return Ok(());
+    };
+    let blockdata = user.blockdata.clone();
+    let Some(character) = user.character.as_mut() else {
+        return Ok(());
+    };
+    let ready = character
+        .orders
+        .record_kill(enemy, &blockdata.server_data.client_orders);
+    for order in ready {
+        user.send_system_msg(&format!(
+            "Order {} is finished. Use !order_turnin {} to receive the reward",
+            order.name, order.id
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+fn describe(order: &ClientOrder, names: &[ItemName]) -> String {
+    match order.kind {
+        OrderKind::Kill if order.enemy.is_empty() => format!("kill {} enemies", order.amount),
+        OrderKind::Kill => format!("kill {} x{}", order.enemy, order.amount),
+        OrderKind::Collect => format!("bring {} x{}", item_name(names, order.item), order.amount),
+    }
+}
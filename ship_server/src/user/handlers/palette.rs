@@ -13,7 +13,11 @@ pub async fn send_full_palette(user: &mut User) -> HResult {
 }
 pub async fn set_palette(mut user: MutexGuard<'_, User>, packet: SetPalettePacket) -> HResult {
     let character = user.character.as_mut().unwrap();
-    character.palette.set_palette(packet)?;
+    if let Err(e) = character.palette.set_palette(&character.inventory, packet) {
+        let packet = character.palette.send_palette();
+        user.send_packet(&packet).await?;
+        return Err(e);
+    }
     send_palette_update(user).await?;
     Ok(Action::Nothing)
 }
@@ -25,10 +29,16 @@ pub async fn update_palette(
     {
         let user: &mut User = &mut user;
         let character = user.character.as_mut().unwrap();
-        let out_packet = character
+        let result = character
             .palette
-            .update_palette(&mut character.inventory, packet)?;
+            .update_palette(&mut character.inventory, packet);
+        // resend old palette data to revert client changes
+        let out_packet = match &result {
+            Ok(packet) => packet.clone(),
+            Err(_) => character.palette.send_palette(),
+        };
         user.send_packet(&out_packet).await?;
+        result?;
     }
     send_palette_update(user).await?;
     Ok(Action::Nothing)
@@ -36,8 +46,13 @@ pub async fn update_palette(
 
 pub async fn update_subpalette(user: &mut User, packet: UpdateSubPalettePacket) -> HResult {
     let character = user.character.as_mut().unwrap();
-    let out_packet = character.palette.update_subpalette(packet)?;
+    let result = character.palette.update_subpalette(packet);
+    let out_packet = match &result {
+        Ok(packet) => packet.clone(),
+        Err(_) => character.palette.send_palette(),
+    };
     user.send_packet(&out_packet).await?;
+    result?;
     Ok(Action::Nothing)
 }
 
@@ -1,5 +1,5 @@
 use super::HResult;
-use crate::{mutex::MutexGuard, party, Action, User};
+use crate::{mutex::MutexGuard, party, Action, Error, User};
 use pso2packetlib::protocol::party::{
     AcceptInvitePacket, BusyState, ChatStatusPacket, KickMemberPacket, NewPartySettingsPacket,
     TransferLeaderPacket,
@@ -73,9 +73,7 @@ pub async fn leave_party(user: MutexGuard<'_, User>) -> HResult {
             .await?
             .expect("User exists at this point");
         user.lock().await.party = None;
-        let party_id = block_data
-            .latest_partyid
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let party_id = block_data.parties.new_party_id();
         party::Party::init_player(user.clone(), party_id).await?;
     }
     Ok(Action::Nothing)
@@ -86,9 +84,7 @@ pub async fn disband_party(user: MutexGuard<'_, User>) -> HResult {
     let party = user.get_current_party();
     drop(user);
     if let Some(party) = party {
-        let party_id = block_data
-            .latest_partyid
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let party_id = block_data.parties.new_party_id();
         party.write().await.disband_party(party_id).await?;
     }
     Ok(Action::Nothing)
@@ -134,12 +130,46 @@ pub async fn send_invite(user: MutexGuard<'_, User>, invitee_id: u32) -> HResult
         unreachable!();
     };
 
-    for (_, client) in &*clients {
-        if client.lock().await.get_user_id() == invitee_id {
-            party::Party::send_invite(inviter.clone(), client.clone()).await?;
-            break;
-        }
+    drop(clients);
+
+    // invites only reach players on the same block
+    let invitee = blockdata
+        .directory
+        .get(invitee_id)
+        .filter(|p| p.block_id == blockdata.block_id)
+        .and_then(|p| p.user());
+    if let Some(invitee) = invitee {
+        party::Party::send_invite(inviter, invitee).await?;
     }
 
     Ok(Action::Nothing)
 }
+
+/// Sends the list of party members with their blocks and zones.
+pub async fn list_members(user: &mut MutexGuard<'_, User>) -> Result<(), Error> {
+    let Some(party) = user.get_current_party() else {
+        return user.send_system_msg("You are not in a party").await;
+    };
+    let lines = MutexGuard::unlocked_async(user, || async move {
+        let members = party.read().await.member_locations().await;
+        let mut lines = vec![];
+        for member in members {
+            let line = match member.location {
+                Some((block, map, zone_id)) => {
+                    let zone = map
+                        .lock()
+                        .await
+                        .get_zone_name(zone_id)
+                        .unwrap_or("unknown zone")
+                        .to_string();
+                    format!("{}: {block}, {zone}", member.char_name)
+                }
+                None => format!("Player {}: switching blocks", member.id),
+            };
+            lines.push(line);
+        }
+        lines
+    })
+    .await;
+    user.send_system_msg(&lines.join("\n")).await
+}
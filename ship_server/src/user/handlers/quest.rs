@@ -1,5 +1,11 @@
 use super::HResult;
-use crate::{mutex::MutexGuard, quests::PartyQuest, Action, User};
+use crate::{
+    events::GameEvent,
+    mutex::{MutexGuard, RwLock},
+    party::Party,
+    quests::PartyQuest,
+    Action, Error, User,
+};
 use pso2packetlib::protocol::{
     flag::{CutsceneEndPacket, SkitItemAddRequestPacket},
     questlist::{
@@ -9,6 +15,7 @@ use pso2packetlib::protocol::{
     },
     Packet, PacketHeader,
 };
+use std::sync::Arc;
 
 pub async fn counter_request(user: &mut User) -> HResult {
     let data = vec![
@@ -63,6 +70,11 @@ pub async fn counter_request(user: &mut User) -> HResult {
         unlocks: unlocks.into(),
     });
     user.send_packet(&packet).await?;
+    let blockdata = user.blockdata.clone();
+    for banner in &blockdata.counter.banners {
+        user.send_system_msg(banner).await?;
+    }
+    super::daily::show(user).await?;
     Ok(Action::Nothing)
 }
 
@@ -74,8 +86,11 @@ pub async fn avaliable_quests(
         .character
         .as_ref()
         .expect("Character should be loaded at this moment");
-    let packet =
-        Packet::AvailableQuests(user.blockdata.quests.get_availiable(&char.unlocked_quests));
+    let packet = Packet::AvailableQuests(
+        user.blockdata
+            .quests
+            .get_availiable(&char.unlocked_quests, &user.blockdata.counter),
+    );
     user.send_packet(&packet).await?;
     Ok(Action::Nothing)
 }
@@ -85,10 +100,17 @@ pub async fn quest_category(user: &mut User, packet: QuestCategoryRequestPacket)
         .character
         .as_ref()
         .expect("Character should be loaded at this moment");
-    let packet = user
-        .blockdata
+    let featured = user.blockdata.daily.picks().featured;
+    let mut packet = user.blockdata.quests.get_category(
+        packet.category,
+        &char.unlocked_quests,
+        &user.blockdata.counter,
+        &featured,
+    );
+    let schedule = &user.blockdata.schedule;
+    packet
         .quests
-        .get_category(packet.category, &char.unlocked_quests);
+        .retain(|q| schedule.quest_available(q.name_id));
     user.send_packet(&Packet::QuestCategory(packet)).await?;
     user.send_packet(&Packet::QuestCategoryStopper).await?;
 
@@ -110,10 +132,14 @@ pub async fn quest_difficulty(user: &mut User, packet: QuestDifficultyRequestPac
 }
 
 pub async fn set_quest(user: MutexGuard<'_, User>, packet: AcceptQuestPacket) -> HResult {
-    let quest = user
-        .blockdata
-        .quests
-        .get_quest(packet, &user.blockdata.latest_mapid)?;
+    let quest = user.blockdata.quests.get_quest(
+        packet,
+        &user.blockdata.counter,
+        &user.blockdata.latest_mapid,
+    )?;
+    if !user.blockdata.schedule.quest_available(quest.name_id()) {
+        return Err(Error::InvalidInput("set_quest"));
+    }
     start_quest(user, quest).await
 }
 
@@ -148,7 +174,21 @@ pub async fn set_story_quest(
     start_quest(user, quest).await
 }
 
-pub async fn start_quest(user: MutexGuard<'_, User>, quest: PartyQuest) -> HResult {
+pub async fn start_quest(mut user: MutexGuard<'_, User>, mut quest: PartyQuest) -> HResult {
+    let fee = user.blockdata.quest_settings.accept_fee;
+    if fee != 0 {
+        let character = user
+            .character
+            .as_mut()
+            .expect("Character should be loaded at this moment");
+        let Some(packet) = character.inventory.take_meseta(fee) else {
+            user.send_error(&format!("Not enough meseta, quest fee is {fee}"))
+                .await?;
+            return Ok(Action::Nothing);
+        };
+        user.send_packet(&packet).await?;
+        quest.fee_paid = Some((user.get_user_id(), fee));
+    }
     let is_insta = quest.is_insta_transfer();
     let user_id = user.get_user_id();
     let old_map = user.get_current_map().expect("User should have a map");
@@ -156,9 +196,13 @@ pub async fn start_quest(user: MutexGuard<'_, User>, quest: PartyQuest) -> HResu
     // we are the only owner of the map, so this never blocks
     map.lock_blocking().set_block_data(user.blockdata.clone());
     let party = user.get_current_party();
+    user.blockdata
+        .events
+        .emit(GameEvent::QuestStarted { player_id: user_id });
     drop(user);
     if let Some(party) = party {
         party.write().await.set_quest(quest).await;
+        share_with_alliance(&party).await;
     }
     if is_insta {
         let mut lock = old_map.lock().await;
@@ -174,6 +218,29 @@ pub async fn start_quest(user: MutexGuard<'_, User>, quest: PartyQuest) -> HResu
     Ok(Action::Nothing)
 }
 
+/// Offers the quest to leaders of other parties of the alliance that aren't on a quest. Parties
+/// that accept the offer play on the same map.
+async fn share_with_alliance(party: &Arc<RwLock<Party>>) {
+    let Some(alliance) = party.read().await.alliance() else {
+        return;
+    };
+    for other in alliance.parties() {
+        if Arc::ptr_eq(&other, party) {
+            continue;
+        }
+        let mut other = other.write().await;
+        if other.on_quest() {
+            continue;
+        }
+        other.set_alliance_quest(party);
+        other
+            .send_system_msg(
+                "Your alliance has started a quest. The party leader can use !alliance_quest to join it",
+            )
+            .await;
+    }
+}
+
 pub async fn minimap_reveal(
     mut user: MutexGuard<'_, User>,
     data: MinimapRevealRequestPacket,
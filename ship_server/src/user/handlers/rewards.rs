@@ -0,0 +1,88 @@
+use super::shop::item_name;
+use crate::{events::GameEvent, Error, User};
+use data_structs::{inventory::ItemName, reward::Reward};
+use pso2packetlib::protocol::{playerstatus::GainedEXPPacket, Packet};
+
+/// Items, meseta and experience given at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Grant<'a> {
+    pub items: &'a [Reward],
+    pub meseta: u64,
+    pub exp: u32,
+}
+
+impl Grant<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.meseta == 0 && self.exp == 0 && self.items.iter().all(|r| r.amount == 0)
+    }
+    /// Checks if the inventory of the loaded character has space for the items.
+    pub fn fits(&self, user: &User) -> bool {
+        user.character
+            .as_ref()
+            .is_some_and(|c| c.inventory.has_space_for_rewards(self.items))
+    }
+    /// Lists the rewards, e.g. "Monomate x3, 100 meseta".
+    pub fn describe(&self, names: &[ItemName]) -> String {
+        let mut parts: Vec<_> = self
+            .items
+            .iter()
+            .filter(|r| r.amount != 0)
+            .map(|r| format!("{} x{}", item_name(names, r.id), r.amount))
+            .collect();
+        if self.meseta != 0 {
+            parts.push(format!("{} meseta", self.meseta));
+        }
+        if self.exp != 0 {
+            parts.push(format!("{} EXP", self.exp));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Gives the rewards to the loaded character, saves it and sends `packets` followed by the
+/// changes. Callers should check [`Grant::fits`] before changing anything else.
+pub async fn give(
+    user: &mut User,
+    grant: Grant<'_>,
+    mut packets: Vec<Packet>,
+) -> Result<(), Error> {
+    if !grant.fits(user) {
+        return Err(Error::InventoryFull);
+    }
+    let blockdata = user.blockdata.clone();
+    let player_id = user.user_data.id;
+    let character = user.character.as_mut().unwrap();
+    for reward in grant.items.iter().filter(|r| r.amount != 0) {
+        packets.extend(character.inventory.add_bought_items(
+            &mut user.user_data.last_uuid,
+            reward.id,
+            reward.amount,
+        ));
+        blockdata.events.emit(GameEvent::ItemObtained {
+            player_id,
+            item: reward.id,
+            amount: reward.amount,
+        });
+    }
+    if grant.meseta != 0 {
+        packets.push(character.inventory.add_meseta(grant.meseta));
+        blockdata.events.emit(GameEvent::MesetaCreated {
+            player_id,
+            amount: grant.meseta,
+        });
+    }
+    if grant.exp != 0 {
+        let receiver = user.add_exp(grant.exp)?;
+        packets.push(Packet::GainedEXP(GainedEXPPacket {
+            sender: user.create_object_header(),
+            receivers: vec![receiver],
+            ..Default::default()
+        }));
+    }
+    let character = user.character.as_mut().unwrap();
+    blockdata.sql.update_character(character).await?;
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    Ok(())
+}
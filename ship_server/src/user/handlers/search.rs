@@ -0,0 +1,72 @@
+use crate::{
+    mutex::{Mutex, MutexGuard},
+    Error, User,
+};
+use std::sync::Arc;
+
+/// Maximum number of players in the search results.
+const MAX_RESULTS: usize = 10;
+
+/// Searches for online players on all blocks of the ship by nickname or character name.
+pub async fn search(user: &mut MutexGuard<'_, User>, query: &str) -> Result<(), Error> {
+    let results = user.blockdata.directory.search(query, MAX_RESULTS);
+    let id = user.get_user_id();
+    // other players are locked to get their party status
+    let lines = MutexGuard::unlocked_async(user, || async move {
+        let mut lines = vec![];
+        for entry in results {
+            let party = match entry.user() {
+                Some(target) => match party_status(&target, id).await {
+                    Some(party) => party,
+                    // players that blacklisted the searcher are hidden
+                    None => continue,
+                },
+                None => String::new(),
+            };
+            let location = match &entry.zone {
+                Some(zone) => format!("{}, {zone}", entry.block_name),
+                None => format!("{}, character selection", entry.block_name),
+            };
+            lines.push(format!(
+                "{} ({}), character \"{}\"{}: {location}{party}",
+                entry.nickname,
+                entry.id,
+                entry.char_name,
+                title_suffix(&entry.title)
+            ));
+        }
+        lines
+    })
+    .await;
+    if lines.is_empty() {
+        return user
+            .send_system_msg(&format!("No players matching \"{query}\" are online"))
+            .await;
+    }
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Formats the equipped title shown after the character name.
+pub fn title_suffix(title: &str) -> String {
+    if title.is_empty() {
+        return String::new();
+    }
+    format!(" <{title}>")
+}
+
+/// Returns the party status of the player or `None` if the player blacklisted the searcher.
+async fn party_status(target: &Arc<Mutex<User>>, searcher: u32) -> Option<String> {
+    let party = {
+        let target = target.lock().await;
+        if target.is_blacklisted(searcher) {
+            return None;
+        }
+        target.party.clone()
+    };
+    let Some(party) = party else {
+        return Some(", no party".to_string());
+    };
+    let party = party.read().await;
+    let quest = if party.on_quest() { ", on a quest" } else { "" };
+    Some(format!(", party of {}{quest}", party.player_count()))
+}
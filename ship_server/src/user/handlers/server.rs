@@ -1,5 +1,9 @@
 use super::HResult;
-use crate::{mutex::MutexGuard, party, Action, Error, User, UserState};
+use crate::{
+    mutex::MutexGuard,
+    party::{self, LAUNCH_TIMEOUT},
+    Action, Error, User, UserState,
+};
 use pso2packetlib::protocol::{
     self,
     flag::{FlagType, SetFlagPacket},
@@ -10,14 +14,14 @@ use pso2packetlib::protocol::{
     },
     Packet,
 };
-use std::sync::atomic::Ordering;
 
 pub async fn initial_load(mut user: MutexGuard<'_, User>) -> HResult {
     let conn_id = user.conn_id;
     let blockdata = user.blockdata.clone();
 
-    user.set_map(blockdata.lobby.clone());
-    let party_id = blockdata.latest_partyid.fetch_add(1, Ordering::Relaxed);
+    let lobby = blockdata.lobby.read().await.clone();
+    user.set_map(lobby.clone());
+    let switched_party = blockdata.parties.finish_switch(user.get_user_id());
     drop(user);
 
     let clients = blockdata.clients.lock().await;
@@ -30,15 +34,15 @@ pub async fn initial_load(mut user: MutexGuard<'_, User>) -> HResult {
     };
     drop(clients);
 
-    party::Party::init_player(user.clone(), party_id).await?;
-    blockdata
-        .lobby
-        .lock()
-        .await
-        .init_add_player(user.clone())
-        .await?;
+    match switched_party {
+        Some(party) => party::Party::rejoin(user.clone(), party, &blockdata.parties).await?,
+        None => party::Party::init_player(user.clone(), blockdata.parties.new_party_id()).await?,
+    }
+    lobby.lock().await.init_add_player(user.clone()).await?;
     let mut user_lock = user.lock().await;
     user_lock.state = UserState::InGame;
+    super::mail::notify_unread(&mut user_lock).await?;
+    super::craft::notify_finished(&mut user_lock).await?;
     Ok(Action::Nothing)
 }
 
@@ -66,7 +70,8 @@ pub async fn move_from_bridge(user: MutexGuard<'_, User>, _: BridgeToLobbyPacket
     Ok(Action::Nothing)
 }
 
-pub async fn move_to_casino(user: MutexGuard<'_, User>, _: CasinoTransportPacket) -> HResult {
+pub async fn move_to_casino(mut user: MutexGuard<'_, User>, _: CasinoTransportPacket) -> HResult {
+    super::casino::balance(&mut user).await?;
     let map = user.get_current_map();
     let id = user.get_user_id();
     drop(user);
@@ -115,12 +120,17 @@ pub async fn move_from_cafe(user: MutexGuard<'_, User>, _: CafeToLobbyPacket) ->
 }
 
 pub async fn campship_down(user: MutexGuard<'_, User>, _: CampshipDownPacket) -> HResult {
-    let map = user.get_current_map();
+    let party = user.get_current_party();
     let id = user.get_user_id();
     drop(user);
-    if let Some(map) = map {
-        let mut lock = map.lock().await;
-        lock.move_player_named(id, "campship_down").await?;
+    if let Some(party) = party {
+        let started = party.write().await.set_launch_ready(id).await?;
+        if let Some(started) = started {
+            tokio::spawn(async move {
+                tokio::time::sleep(LAUNCH_TIMEOUT).await;
+                let _ = party.write().await.launch_timeout(started).await;
+            });
+        }
     }
 
     Ok(Action::Nothing)
@@ -233,7 +243,7 @@ pub async fn move_from_story(user: MutexGuard<'_, User>, _: StoryToLobbyPacket)
     let Some(map) = user.get_current_map() else {
         unreachable!("User should be in state >= 'PreInGame'");
     };
-    let lobby = user.blockdata.lobby.clone();
+    let lobby = user.blockdata.lobby.read().await.clone();
     let id = user.get_user_id();
     drop(user);
     let player = map
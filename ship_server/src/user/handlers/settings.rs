@@ -1,10 +1,31 @@
 use super::HResult;
-use crate::{Action, User};
+use crate::{Action, Error, User};
 use pso2packetlib::protocol::{
+    login::Language,
     settings::{LoadSettingsPacket, SaveSettingsPacket},
     Packet,
 };
 
+/// Account key-value store location of the language override.
+const LANG_NAMESPACE: &str = "settings";
+const LANG_KEY: &str = "lang";
+
+/// Returns the short language code used by commands and scripts.
+pub fn lang_code(lang: Language) -> &'static str {
+    match lang {
+        Language::English => "en",
+        Language::Japanese => "jp",
+    }
+}
+
+fn parse_lang(code: &str) -> Option<Language> {
+    match code {
+        "en" => Some(Language::English),
+        "jp" => Some(Language::Japanese),
+        _ => None,
+    }
+}
+
 pub async fn settings_request(user: &mut User) -> HResult {
     let settings = user.blockdata.sql.get_settings(user.get_user_id()).await?;
     user.send_packet(&Packet::LoadSettings(LoadSettingsPacket { settings }))
@@ -19,3 +40,47 @@ pub async fn save_settings(user: &mut User, packet: SaveSettingsPacket) -> HResu
         .await?;
     Ok(Action::Nothing)
 }
+
+/// Overrides the detected language of the account, `auto` removes the override.
+pub async fn set_language(user: &mut User, code: &str) -> Result<(), Error> {
+    let lang = parse_lang(code);
+    if lang.is_none() && code != "auto" {
+        return user.send_system_msg("Usage: !lang en|jp|auto").await;
+    }
+    user.blockdata
+        .sql
+        .put_account_value(
+            user.get_user_id(),
+            LANG_NAMESPACE,
+            LANG_KEY,
+            lang.map(|l| lang_code(l).as_bytes().to_vec()),
+        )
+        .await?;
+    match lang {
+        Some(lang) => {
+            user.user_data.lang = lang;
+            user.send_system_msg(&format!("Language set to {code}"))
+                .await
+        }
+        None => {
+            user.send_system_msg("Language override removed, it will be detected on the next login")
+                .await
+        }
+    }
+}
+
+/// Applies the language override of the account, if any.
+pub async fn load_language(user: &mut User) -> Result<(), Error> {
+    let value = user
+        .blockdata
+        .sql
+        .get_account_value(user.get_user_id(), LANG_NAMESPACE, LANG_KEY)
+        .await?;
+    let lang = value
+        .and_then(|v| String::from_utf8(v).ok())
+        .and_then(|v| parse_lang(&v));
+    if let Some(lang) = lang {
+        user.user_data.lang = lang;
+    }
+    Ok(())
+}
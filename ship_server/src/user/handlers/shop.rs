@@ -0,0 +1,274 @@
+use crate::{events::GameEvent, Error, User};
+use data_structs::{inventory::ItemName, reward::Reward, shop::ShopData, ServerData};
+use pso2packetlib::protocol::items::{Item, ItemId, ItemType};
+
+/// Item sold to an NPC that can be bought back.
+pub struct SoldItem {
+    pub item: Item,
+    /// Special abilities of the item.
+    pub augments: Vec<u16>,
+    pub price: u64,
+}
+
+/// Finds the shop by its id or name.
+fn find_shop<'a>(data: &'a ServerData, shop: &str) -> Option<&'a ShopData> {
+    data.shops
+        .iter()
+        .find(|s| shop.parse::<u32>().ok() == Some(s.id) || s.name.eq_ignore_ascii_case(shop))
+}
+
+/// Returns the id of the shop opened by the NPC.
+pub fn npc_shop(user: &User, npc: &str) -> Option<u32> {
+    user.blockdata
+        .server_data
+        .shops
+        .iter()
+        .find(|s| s.npcs.iter().any(|n| n == npc))
+        .map(|s| s.id)
+}
+
+/// Lists items currently sold by the shop.
+pub async fn list(user: &mut User, shop: &str) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(shop) = find_shop(&blockdata.server_data, shop) else {
+        return user.send_system_msg("Unknown shop").await;
+    };
+    let names = &blockdata.server_data.item_params.names;
+    let time = blockdata.clock.now().as_secs();
+    let lines: Vec<_> = shop
+        .items_at(time)
+        .enumerate()
+        .map(|(i, item)| {
+            format!(
+                "{}. {} - {} meseta",
+                i + 1,
+                item_name(names, item.id),
+                item.price
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return user
+            .send_system_msg(&format!("{} has nothing for sale", shop.name))
+            .await;
+    }
+    let msg = format!(
+        "{}:\n{}\nUse !buy {} <number> [amount] to buy",
+        shop.name,
+        lines.join("\n"),
+        shop.id
+    );
+    user.send_system_msg(&msg).await
+}
+
+/// Buys the item by its number in the shop list.
+pub async fn buy(user: &mut User, shop: &str, number: usize, amount: u16) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(shop) = find_shop(&blockdata.server_data, shop) else {
+        return user.send_system_msg("Unknown shop").await;
+    };
+    let time = blockdata.clock.now().as_secs();
+    let Some(item) = shop.items_at(time).nth(number.wrapping_sub(1)) else {
+        return user.send_system_msg("No such item in the shop").await;
+    };
+    let max_amount = item.max_amount.max(1);
+    if amount == 0 || amount > max_amount {
+        return user
+            .send_system_msg(&format!("You can buy 1 to {max_amount} items at once"))
+            .await;
+    }
+    let Some(price) = item.price.checked_mul(amount as u64) else {
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    let character = user.character.as_mut().unwrap();
+    let reward = Reward {
+        id: item.id,
+        amount,
+    };
+    if !character.inventory.has_space_for_rewards(&[reward]) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    let Some(meseta_packet) = character.inventory.take_meseta(price) else {
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    let packets =
+        character
+            .inventory
+            .add_bought_items(&mut user.user_data.last_uuid, item.id, amount);
+    super::affix::roll_new_items(
+        &mut character.inventory,
+        &blockdata.server_data.affixes,
+        &packets,
+    );
+    blockdata.sql.update_character(character).await?;
+    blockdata.events.emit(GameEvent::ItemObtained {
+        player_id: user.user_data.id,
+        item: item.id,
+        amount,
+    });
+    user.send_packet(&meseta_packet).await?;
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    let name = item_name(&blockdata.server_data.item_params.names, item.id);
+    user.send_system_msg(&format!("Bought {name} x{amount} for {price} meseta"))
+        .await
+}
+
+/// Sells inventory items to an NPC. Each entry is an item name or uuid with an optional
+/// `x<amount>` suffix, otherwise the whole stack is sold.
+pub async fn sell(user: &mut User, entries: &[&str]) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let params = &blockdata.server_data.item_params;
+    let mut sales = vec![];
+    for entry in entries {
+        let (name, amount) = parse_sell_entry(entry);
+        let Some(uuid) = super::item::find_inv_item(user, name) else {
+            return user
+                .send_system_msg(&format!("Item not found: {name}"))
+                .await;
+        };
+        let inventory = &user.character.as_ref().unwrap().inventory;
+        let item = inventory.get_inv_item(uuid)?;
+        if inventory.is_equiped(uuid) {
+            return user
+                .send_system_msg(&format!("Equiped items can't be sold: {name}"))
+                .await;
+        }
+        let Some(price) = params.sell_price(item.id) else {
+            return user.send_system_msg(&format!("{name} can't be sold")).await;
+        };
+        let stack = match &item.data {
+            ItemType::Consumable(data) => data.amount,
+            _ => 1,
+        };
+        // the same item can be listed several times, so amounts are summed per uuid
+        let sale = sales.iter().position(|(u, _, _)| *u == uuid);
+        let left = stack - sale.map_or(0, |i| sales[i].1);
+        let amount = amount.unwrap_or(left).min(left);
+        if amount == 0 {
+            return user
+                .send_system_msg(&format!("Not enough items to sell: {name}"))
+                .await;
+        }
+        match sale {
+            Some(i) => sales[i].1 += amount,
+            None => sales.push((uuid, amount, price)),
+        }
+    }
+    let character = user.character.as_mut().unwrap();
+    let backup = character.inventory.clone();
+    let mut packets = vec![];
+    let mut sold = vec![];
+    for &(uuid, amount, unit_price) in &sales {
+        match character
+            .inventory
+            .take_inv_item_with_augments(uuid, amount)
+        {
+            Ok((item, augments, packet)) => {
+                let removed = match &item.data {
+                    ItemType::Consumable(data) => data.amount,
+                    _ => 1,
+                };
+                packets.push(packet);
+                sold.push(SoldItem {
+                    item,
+                    augments,
+                    price: unit_price.saturating_mul(removed as u64),
+                });
+            }
+            Err(e) => {
+                character.inventory = backup;
+                return Err(e);
+            }
+        }
+    }
+    let total = sold.iter().fold(0u64, |acc, s| acc.saturating_add(s.price));
+    packets.push(character.inventory.add_meseta(total));
+    blockdata.sql.update_character(character).await?;
+    blockdata.events.emit(GameEvent::MesetaCreated {
+        player_id: user.user_data.id,
+        amount: total,
+    });
+    for packet in packets {
+        user.send_packet(&packet).await?;
+    }
+    let count = sold.len();
+    let limit = blockdata.shop_settings.buyback_limit;
+    user.sold_items.extend(sold);
+    let excess = user.sold_items.len().saturating_sub(limit);
+    user.sold_items.drain(..excess);
+    user.send_system_msg(&format!("Sold {count} item(s) for {total} meseta"))
+        .await
+}
+
+/// Lists items that can be bought back.
+pub async fn list_buyback(user: &mut User) -> Result<(), Error> {
+    if user.sold_items.is_empty() {
+        return user.send_system_msg("No items to buy back").await;
+    }
+    let names = &user.blockdata.server_data.item_params.names;
+    let lines: Vec<_> = user
+        .sold_items
+        .iter()
+        .enumerate()
+        .map(|(i, sold)| {
+            let amount = match &sold.item.data {
+                ItemType::Consumable(data) => data.amount,
+                _ => 1,
+            };
+            format!(
+                "{}. {} x{amount} - {} meseta",
+                i + 1,
+                item_name(names, sold.item.id),
+                sold.price
+            )
+        })
+        .collect();
+    let msg = format!("{}\nUse !buyback <number> to buy back", lines.join("\n"));
+    user.send_system_msg(&msg).await
+}
+
+/// Buys back the sold item by its number in the buy-back list for the price it was sold for.
+pub async fn buyback(user: &mut User, number: usize) -> Result<(), Error> {
+    let Some(index) = number.checked_sub(1).filter(|&i| i < user.sold_items.len()) else {
+        return user.send_system_msg("No such item to buy back").await;
+    };
+    let price = user.sold_items[index].price;
+    let character = user.character.as_mut().unwrap();
+    if !character.inventory.has_space_for_new(1) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    let Some(meseta_packet) = character.inventory.take_meseta(price) else {
+        return user.send_system_msg("Not enough meseta").await;
+    };
+    let sold = user.sold_items.remove(index);
+    let name = item_name(&user.blockdata.server_data.item_params.names, sold.item.id);
+    let item_packet =
+        character
+            .inventory
+            .add_new_item(&mut user.user_data.last_uuid, sold.item, sold.augments);
+    user.blockdata.sql.update_character(character).await?;
+    user.send_packet(&meseta_packet).await?;
+    user.send_packet(&item_packet).await?;
+    user.send_system_msg(&format!("Bought back {name} for {price} meseta"))
+        .await
+}
+
+/// Splits the `x<amount>` suffix from the sell entry.
+fn parse_sell_entry(entry: &str) -> (&str, Option<u16>) {
+    let entry = entry.trim();
+    if let Some((name, amount)) = entry.rsplit_once(' ') {
+        if let Some(amount) = amount.strip_prefix('x').and_then(|a| a.parse().ok()) {
+            return (name.trim_end(), Some(amount));
+        }
+    }
+    (entry, None)
+}
+
+pub fn item_name(names: &[ItemName], id: ItemId) -> String {
+    match names.iter().find(|n| n.id == id) {
+        Some(name) => name.en_name.clone(),
+        None => format!("{}:{}:{}", id.item_type, id.id, id.subid),
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{mutex::MutexGuard, user::UserState, Error, User};
+use data_structs::master_ship::{gm_level, SupportTicket, TicketStatus};
+use std::sync::Arc;
+
+/// Opens a support ticket for the player. Only one ticket can be open at a time.
+pub async fn gm_call(user: &mut User, message: &str) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let sql = user.blockdata.sql.clone();
+    if sql
+        .get_open_tickets()
+        .await?
+        .iter()
+        .any(|t| t.user_id == id)
+    {
+        return user
+            .send_system_msg("You already have an open GM call")
+            .await;
+    }
+    let ticket_id = sql
+        .new_ticket(id, &user.user_data.nickname, message)
+        .await?;
+    user.send_system_msg(&format!(
+        "GM call #{ticket_id} was sent. Please wait for a GM to respond"
+    ))
+    .await
+}
+
+/// Lists unresolved tickets.
+pub async fn list(user: &mut User) -> Result<(), Error> {
+    let tickets = user.blockdata.sql.get_open_tickets().await?;
+    if tickets.is_empty() {
+        return user.send_system_msg("No open GM calls").await;
+    }
+    let now = user.blockdata.clock.now();
+    let lines: Vec<_> = tickets
+        .iter()
+        .map(|t| {
+            let minutes = now.saturating_sub(t.timestamp).as_secs() / 60;
+            let status = match t.gm_id {
+                Some(gm_id) => format!("claimed by {gm_id}"),
+                None => "open".to_string(),
+            };
+            format!(
+                "#{} {} ({}), ship {}, {minutes} min ago, {status}: {}",
+                t.id, t.nickname, t.user_id, t.ship_id, t.message
+            )
+        })
+        .collect();
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+pub async fn claim(user: &mut User, id: u32) -> Result<(), Error> {
+    let gm_id = user.get_user_id();
+    match user.blockdata.sql.claim_ticket(id, gm_id).await {
+        Ok(_) => Ok(()),
+        Err(Error::MSError(_)) => {
+            user.send_system_msg("GM call is unknown, resolved or claimed by another GM")
+                .await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn resolve(user: &mut User, id: u32, resolution: &str) -> Result<(), Error> {
+    let gm_id = user.get_user_id();
+    match user
+        .blockdata
+        .sql
+        .resolve_ticket(id, gm_id, resolution)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(Error::MSError(_)) => {
+            user.send_system_msg("GM call is unknown, resolved or claimed by another GM")
+                .await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Moves the GM to the zone of the player that opened the ticket.
+pub async fn goto(user: &mut MutexGuard<'_, User>, id: u32) -> Result<(), Error> {
+    let tickets = user.blockdata.sql.get_open_tickets().await?;
+    let Some(ticket) = tickets.into_iter().find(|t| t.id == id) else {
+        return user
+            .send_system_msg("GM call is unknown, resolved or claimed by another GM")
+            .await;
+    };
+    let Some(player) = user.blockdata.directory.get(ticket.user_id) else {
+        return user
+            .send_system_msg(&format!(
+                "{} is not on this ship (GM call was sent from ship {})",
+                ticket.nickname, ticket.ship_id
+            ))
+            .await;
+    };
+    let (Some(target), Some(map)) = (player.user(), user.get_current_map()) else {
+        return user
+            .send_system_msg(&format!("{} is not in game", ticket.nickname))
+            .await;
+    };
+    let gm_id = user.get_user_id();
+    let moved = MutexGuard::unlocked_async(user, || async move {
+        let target = target.lock().await;
+        if target.state != UserState::InGame {
+            return Ok(false);
+        }
+        let target_map = target.get_current_map();
+        let zone_id = target.zone_id;
+        drop(target);
+        match target_map {
+            Some(target_map) if Arc::ptr_eq(&target_map, &map) => {
+                map.lock().await.move_player(gm_id, zone_id).await?;
+                Ok(true)
+            }
+            _ => Ok::<_, Error>(false),
+        }
+    })
+    .await?;
+    if moved {
+        return Ok(());
+    }
+    let location = player.zone.as_deref().unwrap_or("character selection");
+    user.send_system_msg(&format!(
+        "{} is in {}, {location}",
+        ticket.nickname, player.block_name
+    ))
+    .await
+}
+
+/// Notifies GMs about the ticket change and the player about the progress of their ticket.
+pub async fn on_ticket_update(user: &mut User, ticket: &SupportTicket) -> Result<(), Error> {
+    if user.state != UserState::InGame {
+        return Ok(());
+    }
+    if user.user_data.gm_level >= gm_level::MODERATOR {
+        let msg = match ticket.status {
+            TicketStatus::Open => format!(
+                "New GM call #{} from {} (ship {}): {}",
+                ticket.id, ticket.nickname, ticket.ship_id, ticket.message
+            ),
+            TicketStatus::Claimed => format!(
+                "GM call #{} was claimed by {}",
+                ticket.id,
+                ticket.gm_id.unwrap_or_default()
+            ),
+            TicketStatus::Resolved => format!("GM call #{} was resolved", ticket.id),
+        };
+        user.send_system_msg(&msg).await?;
+    } else if user.get_user_id() == ticket.user_id {
+        match ticket.status {
+            TicketStatus::Open => {}
+            TicketStatus::Claimed => {
+                user.send_system_msg("A GM is now handling your call")
+                    .await?
+            }
+            TicketStatus::Resolved if ticket.resolution.is_empty() => {
+                user.send_system_msg("Your GM call was resolved").await?
+            }
+            TicketStatus::Resolved => {
+                let msg = format!("Your GM call was resolved: {}", ticket.resolution);
+                user.send_system_msg(&msg).await?
+            }
+        }
+    }
+    Ok(())
+}
@@ -16,11 +16,7 @@ pub async fn list_sa(user: &mut User) -> HResult {
         unreachable!("User should be in state >= `PreInGame`")
     };
     let user_id = user.get_user_id();
-    let uuids = user
-        .blockdata
-        .sql
-        .get_symbol_art_list(user_id)
-        .await?;
+    let uuids = user.blockdata.sql.get_symbol_art_list(user_id).await?;
     user.send_packet(&Packet::SymbolArtList(SymbolArtListPacket {
         object: ObjectHeader {
             id: user_id,
@@ -36,11 +32,7 @@ pub async fn list_sa(user: &mut User) -> HResult {
 
 pub async fn change_sa(user: &mut User, packet: ChangeSymbolArtPacket) -> HResult {
     let user_id = user.get_user_id();
-    let mut uuids = user
-        .blockdata
-        .sql
-        .get_symbol_art_list(user_id)
-        .await?;
+    let mut uuids = user.blockdata.sql.get_symbol_art_list(user_id).await?;
     for uuid in packet.uuids {
         let slot = uuid.slot;
         let uuid = uuid.uuid;
@@ -67,9 +59,10 @@ pub async fn change_sa(user: &mut User, packet: ChangeSymbolArtPacket) -> HResul
 }
 
 pub async fn add_sa(user: &mut User, packet: SymbolArtDataPacket) -> HResult {
+    let user_id = user.get_user_id();
     user.blockdata
         .sql
-        .add_symbol_art(packet.uuid, &packet.data, &packet.name)
+        .add_symbol_art(user_id, packet.uuid, &packet.data, &packet.name)
         .await?;
     Ok(Action::Nothing)
 }
@@ -0,0 +1,281 @@
+use crate::{
+    mutex::MutexGuard,
+    team::{Team, TeamInvite, TeamRank, MAX_MEMBERS, MAX_NAME_LEN},
+    Error, User,
+};
+use pso2packetlib::protocol::{ObjectHeader, ObjectType, Packet};
+
+/// Shows the team of the player with its members.
+pub async fn team_info(user: &mut User) -> Result<(), Error> {
+    let Some(team) = get_team(user).await? else {
+        return Ok(());
+    };
+    let mut lines = vec![format!("Team {}:", team.name)];
+    for member in &team.members {
+        let status = match user.blockdata.directory.get(member.id) {
+            Some(online) => format!("online, {}", online.block_name),
+            None => "offline".to_string(),
+        };
+        lines.push(format!("{} ({}): {status}", member.nickname, member.rank));
+    }
+    user.send_system_msg(&lines.join("\n")).await
+}
+
+/// Creates a new team with the player as its master.
+pub async fn create_team(user: &mut User, name: &str) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let sql = user.blockdata.sql.clone();
+    if sql.get_player_team(id).await?.is_some() {
+        return user.send_system_msg("You are already in a team").await;
+    }
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return user
+            .send_system_msg(&format!(
+                "Team name should contain from 1 to {MAX_NAME_LEN} characters"
+            ))
+            .await;
+    }
+    let Some(entry) = user.blockdata.directory.get(id) else {
+        return Ok(());
+    };
+    if sql.create_team(name, &entry).await?.is_none() {
+        return user
+            .send_system_msg(&format!("Team {name} already exists"))
+            .await;
+    }
+    user.send_system_msg(&format!("Team {name} created")).await
+}
+
+/// Invites an online player to the team. Requires the officer rank.
+pub async fn invite(user: &mut MutexGuard<'_, User>, name: &str) -> Result<(), Error> {
+    let Some(team) = get_team_with_rank(user, TeamRank::Officer).await? else {
+        return Ok(());
+    };
+    if team.members.len() >= MAX_MEMBERS {
+        return user.send_system_msg("Your team is full").await;
+    }
+    let Some(target) = user.blockdata.directory.find(name) else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    if team.member(target.id).is_some() {
+        return user
+            .send_system_msg(&format!("{} is already in your team", target.nickname))
+            .await;
+    }
+    if user
+        .blockdata
+        .sql
+        .get_player_team(target.id)
+        .await?
+        .is_some()
+    {
+        return user
+            .send_system_msg(&format!("{} is already in a team", target.nickname))
+            .await;
+    }
+    let Some(target_user) = target.user() else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let invite = TeamInvite {
+        team_id: team.id,
+        team_name: team.name.clone(),
+        inviter: user.user_data.nickname.clone(),
+    };
+    MutexGuard::unlocked_async(user, || async move {
+        let mut target = target_user.lock().await;
+        let msg = format!(
+            "{} invited you to team {}. Use !team_accept to join it",
+            invite.inviter, invite.team_name
+        );
+        target.team_invites.retain(|i| i.team_id != invite.team_id);
+        target.team_invites.push(invite);
+        target.send_system_msg(&msg).await
+    })
+    .await?;
+    user.send_system_msg(&format!("Invited {} to the team", target.nickname))
+        .await
+}
+
+/// Joins the team of the latest invitation.
+pub async fn accept_invite(user: &mut User) -> Result<(), Error> {
+    let Some(invite) = user.team_invites.pop() else {
+        return user.send_system_msg("You have no team invitations").await;
+    };
+    user.team_invites.clear();
+    let id = user.get_user_id();
+    let sql = user.blockdata.sql.clone();
+    let team = sql.get_team(invite.team_id).await?;
+    let Some(team) = team.filter(|t| t.members.len() < MAX_MEMBERS) else {
+        return user
+            .send_system_msg(&format!("Can't join team {}", invite.team_name))
+            .await;
+    };
+    let Some(entry) = user.blockdata.directory.get(id) else {
+        return Ok(());
+    };
+    if !sql.add_team_member(team.id, &entry).await? {
+        return user.send_system_msg("You are already in a team").await;
+    }
+    notify_team(user, &team, format!("{} joined the team", entry.nickname));
+    user.send_system_msg(&format!("You joined team {}", team.name))
+        .await
+}
+
+/// Declines all team invitations.
+pub async fn decline_invites(user: &mut User) -> Result<(), Error> {
+    if user.team_invites.is_empty() {
+        return user.send_system_msg("You have no team invitations").await;
+    }
+    user.team_invites.clear();
+    user.send_system_msg("Team invitations declined").await
+}
+
+/// Leaves the team. The master has to pass the rank or disband the team first.
+pub async fn leave(user: &mut User) -> Result<(), Error> {
+    let Some(team) = get_team(user).await? else {
+        return Ok(());
+    };
+    let id = user.get_user_id();
+    if team.member(id).map(|m| m.rank) == Some(TeamRank::Master) {
+        return user
+            .send_system_msg("Pass the master rank or disband the team before leaving it")
+            .await;
+    }
+    user.blockdata.sql.remove_team_member(team.id, id).await?;
+    let msg = format!("{} left the team", user.user_data.nickname);
+    notify_team(user, &team, msg);
+    user.send_system_msg(&format!("You left team {}", team.name))
+        .await
+}
+
+/// Removes the member from the team. Only members of a lower rank can be kicked.
+pub async fn kick(user: &mut User, name: &str) -> Result<(), Error> {
+    let Some(team) = get_team_with_rank(user, TeamRank::Officer).await? else {
+        return Ok(());
+    };
+    let rank = team.member(user.get_user_id()).map(|m| m.rank);
+    let Some(member) = team.find_member(name) else {
+        return user
+            .send_system_msg(&format!("{name} is not in your team"))
+            .await;
+    };
+    if Some(member.rank) >= rank {
+        return user
+            .send_system_msg(&format!("You can't kick {}", member.nickname))
+            .await;
+    }
+    user.blockdata
+        .sql
+        .remove_team_member(team.id, member.id)
+        .await?;
+    notify_team(
+        user,
+        &team,
+        format!("{} was removed from the team", member.nickname),
+    );
+    Ok(())
+}
+
+/// Sets the rank of the member. Requires the master rank.
+pub async fn set_rank(user: &mut User, name: &str, rank: TeamRank) -> Result<(), Error> {
+    let Some(team) = get_team_with_rank(user, TeamRank::Master).await? else {
+        return Ok(());
+    };
+    let Some(member) = team.find_member(name) else {
+        return user
+            .send_system_msg(&format!("{name} is not in your team"))
+            .await;
+    };
+    if member.id == user.get_user_id() {
+        return user.send_system_msg("You can't change your own rank").await;
+    }
+    user.blockdata
+        .sql
+        .set_team_rank(team.id, member.id, rank)
+        .await?;
+    notify_team(
+        user,
+        &team,
+        format!("{} is now {rank} of the team", member.nickname),
+    );
+    Ok(())
+}
+
+/// Disbands the team. Requires the master rank.
+pub async fn disband(user: &mut User) -> Result<(), Error> {
+    let Some(team) = get_team_with_rank(user, TeamRank::Master).await? else {
+        return Ok(());
+    };
+    user.blockdata.sql.delete_team(team.id).await?;
+    notify_team(user, &team, format!("Team {} was disbanded", team.name));
+    Ok(())
+}
+
+/// Sends the chat message to the online members of the sender's team.
+pub async fn send_chat(user: MutexGuard<'_, User>, mut packet: Packet) -> Result<(), Error> {
+    let id = user.get_user_id();
+    let Some(team) = user.blockdata.sql.get_player_team(id).await? else {
+        return Ok(());
+    };
+    if let Packet::ChatMessage(ref mut data) = packet {
+        data.object = ObjectHeader {
+            id,
+            entity_type: ObjectType::Player,
+            ..Default::default()
+        };
+    }
+    let targets: Vec<_> = team
+        .members
+        .iter()
+        .filter_map(|m| user.blockdata.directory.get(m.id)?.user())
+        .collect();
+    drop(user);
+    for target in targets {
+        let mut target = target.lock().await;
+        if !target.is_blacklisted(id) {
+            let _ = target.send_packet(&packet).await;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the team of the player or tells the player that there is no team.
+async fn get_team(user: &mut User) -> Result<Option<Team>, Error> {
+    let team = user
+        .blockdata
+        .sql
+        .get_player_team(user.get_user_id())
+        .await?;
+    if team.is_none() {
+        user.send_system_msg("You are not in a team").await?;
+    }
+    Ok(team)
+}
+
+/// Returns the team of the player if the player has at least the rank.
+async fn get_team_with_rank(user: &mut User, rank: TeamRank) -> Result<Option<Team>, Error> {
+    let Some(team) = get_team(user).await? else {
+        return Ok(None);
+    };
+    if team.member(user.get_user_id()).map(|m| m.rank) < Some(rank) {
+        user.send_system_msg(&format!("This requires the {rank} rank"))
+            .await?;
+        return Ok(None);
+    }
+    Ok(Some(team))
+}
+
+/// Sends a system message to all online members of the team.
+fn notify_team(user: &User, team: &Team, msg: String) {
+    let targets: Vec<_> = team
+        .members
+        .iter()
+        .filter_map(|m| user.blockdata.directory.get(m.id)?.user())
+        .collect();
+    // the members are locked outside of this session to avoid lock cycles
+    tokio::spawn(async move {
+        for target in targets {
+            let _ = target.lock().await.send_system_msg(&msg).await;
+        }
+    });
+}
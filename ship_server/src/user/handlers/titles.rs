@@ -0,0 +1,128 @@
+use super::rewards::{self, Grant};
+use crate::{events::GameEvent, Error, User};
+use data_structs::title::Title;
+
+/// Updates title progress of the character and announces newly earned titles.
+pub async fn on_event(user: &mut User, event: &GameEvent) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let Some(character) = user.character.as_mut() else {
+        return Ok(());
+    };
+    let earned = character
+        .titles
+        .record(event, &blockdata.server_data.titles);
+    if earned.is_empty() {
+        return Ok(());
+    }
+    blockdata.sql.update_character(character).await?;
+    for title in earned {
+        let mut msg = format!("Title earned: {}", title.name);
+        if has_rewards(title) {
+            msg.push_str(&format!(
+                ". Use !title_claim {} to receive the reward",
+                title.id
+            ));
+        }
+        user.send_system_msg(&msg).await?;
+    }
+    Ok(())
+}
+
+/// Lists earned titles.
+pub async fn list(user: &mut User) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let titles = &user.character.as_ref().unwrap().titles;
+    let lines: Vec<_> = titles
+        .earned
+        .iter()
+        .filter_map(|&id| find_title(&blockdata.server_data.titles, id))
+        .map(|t| {
+            let mut line = format!("{}. {}", t.id, t.name);
+            if titles.equipped == Some(t.id) {
+                line.push_str(" (equipped)");
+            }
+            if !titles.claimed.contains(&t.id) && has_rewards(t) {
+                line.push_str(" - reward available");
+            }
+            line
+        })
+        .collect();
+    if lines.is_empty() {
+        return user.send_system_msg("No titles earned").await;
+    }
+    let msg = format!(
+        "{}\nUse !title <id> to equip a title or !title 0 to unequip it",
+        lines.join("\n")
+    );
+    user.send_system_msg(&msg).await
+}
+
+/// Gives rewards of the earned title.
+pub async fn claim(user: &mut User, title_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let character = user.character.as_mut().unwrap();
+    let Some(title) = find_title(&blockdata.server_data.titles, title_id)
+        .filter(|_| character.titles.earned.contains(&title_id))
+    else {
+        return user.send_system_msg("Title is not earned").await;
+    };
+    if character.titles.claimed.contains(&title_id) || !has_rewards(title) {
+        return user.send_system_msg("No reward to claim").await;
+    }
+    let grant = Grant {
+        items: &title.rewards,
+        meseta: title.meseta,
+        ..Default::default()
+    };
+    if !grant.fits(user) {
+        return user.send_system_msg("Not enough inventory space").await;
+    }
+    let character = user.character.as_mut().unwrap();
+    character.titles.claimed.push(title_id);
+    rewards::give(user, grant, vec![]).await?;
+    let names = &blockdata.server_data.item_params.names;
+    user.send_system_msg(&format!("Received {}", grant.describe(names)))
+        .await
+}
+
+/// Equips the earned title, showing it to other players. Title 0 unequips the current title.
+pub async fn equip(user: &mut User, title_id: u32) -> Result<(), Error> {
+    let blockdata = user.blockdata.clone();
+    let user_id = user.get_user_id();
+    let character = user.character.as_mut().unwrap();
+    if title_id == 0 {
+        character.titles.equipped = None;
+        blockdata.sql.update_character(character).await?;
+        blockdata.directory.set_title(user_id, "");
+        return user.send_system_msg("Title unequipped").await;
+    }
+    let Some(title) = find_title(&blockdata.server_data.titles, title_id)
+        .filter(|_| character.titles.earned.contains(&title_id))
+    else {
+        return user.send_system_msg("Title is not earned").await;
+    };
+    character.titles.equipped = Some(title_id);
+    blockdata.sql.update_character(character).await?;
+    blockdata.directory.set_title(user_id, &title.name);
+    user.send_system_msg(&format!("Equipped title {}", title.name))
+        .await
+}
+
+/// Returns the name of the equipped title of the loaded character.
+pub fn equipped_name(user: &User) -> Option<&str> {
+    let id = user.character.as_ref()?.titles.equipped?;
+    find_title(&user.blockdata.server_data.titles, id).map(|t| t.name.as_str())
+}
+
+fn find_title(titles: &[Title], id: u32) -> Option<&Title> {
+    titles.iter().find(|t| t.id == id)
+}
+
+fn has_rewards(title: &Title) -> bool {
+    !Grant {
+        items: &title.rewards,
+        meseta: title.meseta,
+        ..Default::default()
+    }
+    .is_empty()
+}
@@ -0,0 +1,392 @@
+use crate::{
+    inventory,
+    mutex::MutexGuard,
+    trade::{self, Trade, TradeOffer, TradeState},
+    Error, User,
+};
+use pso2packetlib::protocol::{items::Item, Packet};
+
+/// Change of the trade requested by one side.
+pub enum TradeOp {
+    SetItem(u64, u16),
+    RemoveItem(u64),
+    SetMeseta(u64),
+    Lock,
+    Confirm,
+    Cancel,
+    Show,
+}
+
+/// Sends a trade request to the online player or accepts their request.
+pub async fn request(user: &mut MutexGuard<'_, User>, name: &str) -> Result<(), Error> {
+    if user.trade.is_some() {
+        return user
+            .send_system_msg("You are already trading. Use !trade_cancel to stop")
+            .await;
+    }
+    let Some(target) = user.blockdata.directory.find(name) else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let id = user.get_user_id();
+    if target.id == id {
+        return user.send_system_msg("You can't trade with yourself").await;
+    }
+    let Some(target_user) = target.user() else {
+        return user.send_system_msg("Player is not online").await;
+    };
+    let nickname = user.user_data.nickname.clone();
+    let accepted = MutexGuard::unlocked_async(user, || async move {
+        let mut target = target_user.lock().await;
+        if target.is_blacklisted(id) || target.character.is_none() {
+            return Ok(None);
+        }
+        let accepted = target
+            .trade
+            .as_ref()
+            .is_some_and(|t| t.partner == id && t.state == TradeState::Requested);
+        let msg = if accepted {
+            target.trade = Some(Trade::new(id, TradeState::Open));
+            format!(
+                "{nickname} accepted the trade. Use !trade_add and !trade_meseta to make an offer"
+            )
+        } else {
+            format!("{nickname} wants to trade with you. Use !trade {nickname} to accept")
+        };
+        target.send_system_msg(&msg).await?;
+        Ok::<_, Error>(Some(accepted))
+    })
+    .await?;
+    match accepted {
+        Some(true) => {
+            user.trade = Some(Trade::new(target.id, TradeState::Open));
+            user.send_system_msg(&format!("Trade with {} started", target.nickname))
+                .await
+        }
+        Some(false) => {
+            user.trade = Some(Trade::new(target.id, TradeState::Requested));
+            user.send_system_msg(&format!("Trade request sent to {}", target.nickname))
+                .await
+        }
+        None => {
+            user.send_system_msg(&format!("Can't trade with {}", target.nickname))
+                .await
+        }
+    }
+}
+
+/// Applies the change to the current trade.
+pub async fn update(user: &mut MutexGuard<'_, User>, op: TradeOp) -> Result<(), Error> {
+    let Some(partner_id) = user.trade.as_ref().map(|t| t.partner) else {
+        return user.send_system_msg("You are not trading").await;
+    };
+    let id = user.get_user_id();
+    let directory = user.blockdata.directory.clone();
+    let Some(own) = directory.get(id).and_then(|p| p.user()) else {
+        return Ok(());
+    };
+    let Some(partner) = directory.get(partner_id).and_then(|p| p.user()) else {
+        user.trade = None;
+        return user
+            .send_system_msg("Trade partner is offline, the trade is cancelled")
+            .await;
+    };
+    MutexGuard::unlocked_async(user, || async move {
+        // both sides are locked in the order of player ids to avoid deadlocks
+        let (mut own, mut partner) = if id < partner_id {
+            let own = own.lock().await;
+            (own, partner.lock().await)
+        } else {
+            let partner = partner.lock().await;
+            (own.lock().await, partner)
+        };
+        apply(&mut own, &mut partner, op).await
+    })
+    .await
+}
+
+async fn apply(own: &mut User, partner: &mut User, op: TradeOp) -> Result<(), Error> {
+    let id = own.get_user_id();
+    let partner_id = partner.get_user_id();
+    // the trade could have changed while the session was unlocked
+    let Some(own_state) = own
+        .trade
+        .as_ref()
+        .filter(|t| t.partner == partner_id)
+        .map(|t| t.state)
+    else {
+        return Ok(());
+    };
+    let partner_state = partner
+        .trade
+        .as_ref()
+        .filter(|t| t.partner == id)
+        .map(|t| t.state);
+    if let TradeOp::Cancel = op {
+        own.trade = None;
+        if partner_state.is_some() {
+            partner.trade = None;
+            let msg = format!("{} cancelled the trade", own.user_data.nickname);
+            partner.send_system_msg(&msg).await?;
+        }
+        return own.send_system_msg("Trade cancelled").await;
+    }
+    if own_state == TradeState::Requested {
+        return own
+            .send_system_msg(&format!(
+                "{} hasn't accepted the trade yet",
+                partner.user_data.nickname
+            ))
+            .await;
+    }
+    let Some(partner_state) = partner_state else {
+        own.trade = None;
+        return own
+            .send_system_msg("Trade partner left, the trade is cancelled")
+            .await;
+    };
+    let nickname = own.user_data.nickname.clone();
+    match op {
+        TradeOp::SetItem(..) | TradeOp::RemoveItem(_) | TradeOp::SetMeseta(_) => {
+            let inventory = &own.character.as_ref().unwrap().inventory;
+            let meseta = inventory.inventory.meseta;
+            let owned = match op {
+                TradeOp::SetItem(uuid, _) if inventory.is_equiped(uuid) => {
+                    return own.send_system_msg("Equiped items can't be traded").await;
+                }
+                TradeOp::SetItem(uuid, _) => inventory
+                    .get_inv_item(uuid)
+                    .map_or(0, |i| inventory::item_amount(&i)),
+                _ => 0,
+            };
+            let offer = &mut own.trade.as_mut().unwrap().offer;
+            let error = match op {
+                TradeOp::SetItem(uuid, amount) => offer.set_item(uuid, amount, owned).err(),
+                TradeOp::RemoveItem(uuid) => {
+                    (!offer.remove_item(uuid)).then_some("This item is not in your offer")
+                }
+                TradeOp::SetMeseta(amount) if amount > meseta => Some("Not enough meseta"),
+                TradeOp::SetMeseta(amount) => {
+                    offer.meseta = amount;
+                    None
+                }
+                _ => unreachable!(),
+            };
+            if let Some(error) = error {
+                return own.send_system_msg(error).await;
+            }
+            // any change requires both sides to lock their offers again
+            own.trade.as_mut().unwrap().state = TradeState::Open;
+            partner.trade.as_mut().unwrap().state = TradeState::Open;
+            let offer = describe_offer(own, &own.trade.as_ref().unwrap().offer);
+            partner
+                .send_system_msg(&format!("{nickname} changed the offer: {offer}"))
+                .await?;
+            own.send_system_msg(&format!("Your offer: {offer}")).await
+        }
+        TradeOp::Lock => {
+            let Some(state) = trade::lock(own_state) else {
+                return own.send_system_msg("Your offer is already locked").await;
+            };
+            own.trade.as_mut().unwrap().state = state;
+            if partner_state == TradeState::Locked {
+                let msg = "Both offers are locked. Use !trade_confirm to complete the trade";
+                partner.send_system_msg(msg).await?;
+                return own.send_system_msg(msg).await;
+            }
+            partner
+                .send_system_msg(&format!("{nickname} locked the offer"))
+                .await?;
+            own.send_system_msg("Offer locked").await
+        }
+        TradeOp::Confirm => {
+            let Some(state) = trade::confirm(own_state, partner_state) else {
+                return own
+                    .send_system_msg("Both offers should be locked before confirming")
+                    .await;
+            };
+            own.trade.as_mut().unwrap().state = state;
+            if partner_state == TradeState::Confirmed {
+                return execute(own, partner).await;
+            }
+            partner
+                .send_system_msg(&format!("{nickname} confirmed the trade"))
+                .await?;
+            own.send_system_msg("Trade confirmed, waiting for the partner")
+                .await
+        }
+        TradeOp::Show => {
+            let own_offer = describe_offer(own, &own.trade.as_ref().unwrap().offer);
+            let partner_offer = describe_offer(partner, &partner.trade.as_ref().unwrap().offer);
+            let msg = format!(
+                "Your offer ({own_state:?}): {own_offer}\n{}'s offer ({partner_state:?}): {partner_offer}",
+                partner.user_data.nickname
+            );
+            own.send_system_msg(&msg).await
+        }
+        TradeOp::Cancel => unreachable!(),
+    }
+}
+
+/// Swaps the offers of both sides. Characters are restored if any part of the swap fails.
+async fn execute(a: &mut User, b: &mut User) -> Result<(), Error> {
+    let a_offer = a.trade.take().unwrap().offer;
+    let b_offer = b.trade.take().unwrap().offer;
+    let a_desc = describe_offer(a, &a_offer);
+    let b_desc = describe_offer(b, &b_offer);
+    let a_backup = (a.character.clone(), a.user_data.last_uuid);
+    let b_backup = (b.character.clone(), b.user_data.last_uuid);
+    let swapped = match swap(a, b, &a_offer, &b_offer) {
+        Ok(packets) => {
+            let chars = [a.character.as_ref().unwrap(), b.character.as_ref().unwrap()];
+            a.blockdata
+                .sql
+                .update_characters(&chars)
+                .await
+                .map(|_| packets)
+        }
+        Err(e) => Err(e),
+    };
+    let (a_packets, b_packets) = match swapped {
+        Ok(packets) => packets,
+        Err(e) => {
+            (a.character, a.user_data.last_uuid) = a_backup;
+            (b.character, b.user_data.last_uuid) = b_backup;
+            log::warn!(
+                "Trade between players {} and {} failed: {e}",
+                a.get_user_id(),
+                b.get_user_id()
+            );
+            let msg = match e {
+                Error::InventoryFull => "Trade failed, not enough inventory space",
+                Error::InvalidInput("take_offer") => {
+                    "Trade failed, an offered item was equiped or changed"
+                }
+                _ => "Trade failed",
+            };
+            a.send_system_msg(msg).await?;
+            return b.send_system_msg(msg).await;
+        }
+    };
+    for packet in a_packets {
+        a.send_packet(&packet).await?;
+    }
+    for packet in b_packets {
+        b.send_packet(&packet).await?;
+    }
+    let a_side = (a.get_user_id(), character_id(a), a_desc.as_str());
+    let b_side = (b.get_user_id(), character_id(b), b_desc.as_str());
+    log::info!(
+        "Trade completed: player {} gave {a_desc}, player {} gave {b_desc}",
+        a_side.0,
+        b_side.0
+    );
+    let time = a.blockdata.clock.now();
+    a.blockdata.sql.log_trade(time, a_side, b_side).await?;
+    let msg = format!("Trade with {} completed", b.user_data.nickname);
+    a.send_system_msg(&msg).await?;
+    let msg = format!("Trade with {} completed", a.user_data.nickname);
+    b.send_system_msg(&msg).await
+}
+
+/// Moves the offered items and meseta. Returns inventory update packets for both sides.
+fn swap(
+    a: &mut User,
+    b: &mut User,
+    a_offer: &TradeOffer,
+    b_offer: &TradeOffer,
+) -> Result<(Vec<Packet>, Vec<Packet>), Error> {
+    let (a_items, mut a_packets) = take_offer(a, a_offer)?;
+    let (b_items, mut b_packets) = take_offer(b, b_offer)?;
+    give(a, b_items, b_offer.meseta, &mut a_packets)?;
+    give(b, a_items, a_offer.meseta, &mut b_packets)?;
+    Ok((a_packets, b_packets))
+}
+
+/// Items with their abilities.
+type TradedItems = Vec<(Item, Vec<u16>)>;
+
+fn take_offer(user: &mut User, offer: &TradeOffer) -> Result<(TradedItems, Vec<Packet>), Error> {
+    let character = user
+        .character
+        .as_mut()
+        .ok_or(Error::InvalidInput("take_offer"))?;
+    let mut items = vec![];
+    let mut packets = vec![];
+    for &(uuid, amount) in &offer.items {
+        // the inventory could have changed after the item was offered
+        let owned = inventory::item_amount(&character.inventory.get_inv_item(uuid)?);
+        if amount == 0 || amount > owned || character.inventory.is_equiped(uuid) {
+            return Err(Error::InvalidInput("take_offer"));
+        }
+        let (item, augments, packet) = character
+            .inventory
+            .take_inv_item_with_augments(uuid, amount)?;
+        items.push((item, augments));
+        packets.push(packet);
+    }
+    if offer.meseta != 0 {
+        let packet = character
+            .inventory
+            .take_meseta(offer.meseta)
+            .ok_or(Error::InvalidInput("take_offer"))?;
+        packets.push(packet);
+    }
+    Ok((items, packets))
+}
+
+fn give(
+    user: &mut User,
+    items: TradedItems,
+    meseta: u64,
+    packets: &mut Vec<Packet>,
+) -> Result<(), Error> {
+    let character = user.character.as_mut().unwrap();
+    // traded items are added as new entries, even stackable ones
+    if !character.inventory.has_space_for_new(items.len()) {
+        return Err(Error::InventoryFull);
+    }
+    for (item, augments) in items {
+        packets.push(character.inventory.add_new_item(
+            &mut user.user_data.last_uuid,
+            item,
+            augments,
+        ));
+    }
+    if meseta != 0 {
+        packets.push(character.inventory.add_meseta(meseta));
+    }
+    Ok(())
+}
+
+fn describe_offer(user: &User, offer: &TradeOffer) -> String {
+    let names = &user.blockdata.server_data.item_params.names;
+    let mut parts: Vec<_> = offer
+        .items
+        .iter()
+        .map(|&(uuid, amount)| {
+            let item = user
+                .character
+                .as_ref()
+                .and_then(|c| c.inventory.get_inv_item(uuid).ok());
+            let name = match item {
+                Some(item) => match names.iter().find(|n| n.id == item.id) {
+                    Some(name) => name.en_name.clone(),
+                    None => format!("{}:{}:{}", item.id.item_type, item.id.id, item.id.subid),
+                },
+                None => format!("missing item {uuid}"),
+            };
+            format!("{name} x{amount}")
+        })
+        .collect();
+    if offer.meseta != 0 {
+        parts.push(format!("{} meseta", offer.meseta));
+    }
+    if parts.is_empty() {
+        return "nothing".to_string();
+    }
+    parts.join(", ")
+}
+
+fn character_id(user: &User) -> u32 {
+    user.character.as_ref().unwrap().character.character_id
+}
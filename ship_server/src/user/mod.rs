@@ -1,14 +1,22 @@
 pub(crate) mod handlers;
 use crate::{
+    bandwidth::ConnectionTraffic,
     battle_stats::PlayerStats,
+    cadence::{CadenceCheck, CadenceTracker, InputKind, MacroEnforcement},
+    chat_filter::SpamTracker,
+    disconnect::DisconnectReason,
+    error_code::ErrorCode,
+    events::GameEvent,
     invites::PartyInvite,
     map::Map,
     mutex::{Mutex, MutexGuard, RwLock},
     party::{self, Party},
     sql::{self, CharData},
+    team::TeamInvite,
+    trade::Trade,
     Action, BlockData, Error,
 };
-use data_structs::flags::Flags;
+use data_structs::{flags::Flags, master_ship::Mute};
 use pso2packetlib::{
     connection::{ConnectionError, ConnectionRead, ConnectionWrite},
     protocol::{
@@ -21,7 +29,7 @@ use pso2packetlib::{
         party::BusyState,
         playerstatus::EXPReceiver,
         spawn::CharacterSpawnPacket,
-        ObjectHeader, Packet, PacketType,
+        ObjectHeader, Packet, PacketType, ProtocolRW,
     },
     Connection, PublicKey,
 };
@@ -37,9 +45,27 @@ pub struct User {
     pub character: Option<CharData>,
     last_ping: Instant,
     failed_pings: u32,
-    ready_to_shutdown: bool,
+    /// Set when the session should be closed once pending packets are sent.
+    shutdown_reason: Option<DisconnectReason>,
     pub party_invites: Vec<PartyInvite>,
     pub party_ignore: Pr::party::RejectStatus,
+    pub team_invites: Vec<TeamInvite>,
+    /// Ids of players blacklisted by the current character.
+    pub blacklist: Vec<u32>,
+    /// Current trade with another player.
+    pub trade: Option<Trade>,
+    /// Players that offered to exchange player cards.
+    pub card_offers: Vec<u32>,
+    /// Chat mute set by a GM.
+    pub mute: Option<Mute>,
+    /// Time when the player can use the next lobby item.
+    pub lobby_item_ready: Option<Instant>,
+    /// Items selected for affixing. The first item is the target.
+    pub affix_items: Vec<u64>,
+    /// Items sold to NPCs during the session, oldest first.
+    pub sold_items: Vec<handlers::shop::SoldItem>,
+    /// Password change challenge received after re-authentication.
+    pub password_challenge: Option<String>,
     pub zone_id: u32,
     firstload: bool,
     pub state: UserState,
@@ -48,6 +74,25 @@ pub struct User {
     pub user_data: sql::User,
 
     session_start: Instant,
+    spam_tracker: SpamTracker,
+    cadence_tracker: CadenceTracker,
+    /// Credentials waiting for the one-time code.
+    pending_login: Option<PendingLogin>,
+    /// User has requested a block switch, so the party is kept after disconnection.
+    switching_block: bool,
+    /// NA client has passed the credential check, but hasn't answered the login challenge yet.
+    awaiting_challenge: bool,
+    /// Bytes and packets sent and received by the connection.
+    traffic: Arc<ConnectionTraffic>,
+}
+
+pub(crate) struct PendingLogin {
+    pub username: String,
+    pub password: String,
+    pub lang: Language,
+    pub client_version: String,
+    /// Login is done with a PSN account (Vita), password is not used.
+    pub psn: bool,
 }
 
 impl User {
@@ -57,17 +102,22 @@ impl User {
         conn_id: usize,
     ) -> Result<(User, ConnectionRead<Packet>), Error> {
         stream.set_nodelay(true)?;
-        let mut con = Connection::new_async(
-            stream,
-            PacketType::Classic,
-            blockdata.key.clone(),
-            PublicKey::None,
-        );
-        match con.write_packet(&Packet::ServerHello(Pr::server::ServerHelloPacket {
+        let packet_type = if blockdata.ngs {
+            PacketType::NGS
+        } else {
+            PacketType::Classic
+        };
+        let mut con =
+            Connection::new_async(stream, packet_type, blockdata.key.clone(), PublicKey::None);
+        let traffic = ConnectionTraffic::register();
+        let hello = Packet::ServerHello(Pr::server::ServerHelloPacket {
             unk1: 3,
             blockid: blockdata.block_id as u16,
             unk2: 68833280,
-        })) {
+        })
+        .write(packet_type);
+        traffic.record_out(&hello, packet_type);
+        match con.write_packet(&Packet::Raw(hello)) {
             Ok(_) => {}
             Err(ConnectionError::Io(x)) if x.kind() == std::io::ErrorKind::WouldBlock => {}
             Err(x) => return Err(x.into()),
@@ -83,22 +133,36 @@ impl User {
                 position: Default::default(),
                 last_ping: Instant::now(),
                 failed_pings: 0,
-                ready_to_shutdown: false,
+                shutdown_reason: None,
                 party_invites: vec![],
                 party_ignore: Default::default(),
+                team_invites: vec![],
+                blacklist: vec![],
+                mute: None,
+                lobby_item_ready: None,
+                affix_items: vec![],
+                sold_items: vec![],
+                password_challenge: None,
+                card_offers: vec![],
+                trade: None,
                 zone_id: 0,
                 firstload: true,
                 state: UserState::LoggingIn,
                 battle_stats: Default::default(),
                 conn_id,
                 user_data: sql::User {
-                    packet_type: PacketType::Classic,
+                    packet_type,
                     lang: Language::Japanese,
-                    isgm: false,
                     last_uuid: 1,
                     ..Default::default()
                 },
                 session_start: Instant::now(),
+                spam_tracker: Default::default(),
+                cadence_tracker: Default::default(),
+                pending_login: None,
+                switching_block: false,
+                awaiting_challenge: false,
+                traffic,
             },
             read,
         ))
@@ -106,11 +170,13 @@ impl User {
     // I hope async guard won't cause me troubles in the future
     pub async fn tick(mut s: MutexGuard<'_, Self>) -> Result<Action, Error> {
         let _ = s.connection.flush();
-        if s.ready_to_shutdown && s.last_ping.elapsed().as_millis() >= 500 {
-            return Ok(Action::Disconnect);
+        if let Some(reason) = s.shutdown_reason {
+            if s.last_ping.elapsed().as_millis() >= 500 {
+                return Ok(Action::Disconnect(reason));
+            }
         }
         if s.failed_pings >= 5 {
-            return Ok(Action::Disconnect);
+            return Ok(Action::Disconnect(DisconnectReason::Timeout));
         }
         if s.last_ping.elapsed().as_secs() >= 10 {
             s.last_ping = Instant::now();
@@ -123,12 +189,21 @@ impl User {
     pub fn get_ip(&self) -> Result<Ipv4Addr, Error> {
         Ok(self.connection.get_ip()?)
     }
+    /// Serializes the packet and records its size.
+    fn serialize(&self, packet: &Packet) -> Packet {
+        let packet_type = self.user_data.packet_type;
+        let data = packet.write(packet_type);
+        self.traffic.record_out(&data, packet_type);
+        Packet::Raw(data)
+    }
     pub async fn send_packet(&mut self, packet: &Packet) -> Result<(), Error> {
-        self.connection.write_packet_async(packet).await?;
+        let packet = self.serialize(packet);
+        self.connection.write_packet_async(&packet).await?;
         Ok(())
     }
     pub fn try_send_packet(&mut self, packet: &Packet) -> Result<(), Error> {
-        match self.connection.write_packet(packet) {
+        let packet = self.serialize(packet);
+        match self.connection.write_packet(&packet) {
             Ok(_) => {}
             Err(ConnectionError::Io(ref e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
             Err(e) => return Err(e.into()),
@@ -136,7 +211,8 @@ impl User {
         Ok(())
     }
     pub fn send_packet_block(&mut self, packet: &Packet) -> Result<(), Error> {
-        match self.connection.write_packet(packet) {
+        let packet = self.serialize(packet);
+        match self.connection.write_packet(&packet) {
             Ok(_) => return Ok(()),
             Err(ConnectionError::Io(ref e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
             Err(e) => return Err(e.into()),
@@ -149,6 +225,38 @@ impl User {
             }
         }
     }
+    /// Tracks the input timing and records suspected macro use to the anomaly log.
+    async fn check_cadence(&mut self, kind: InputKind) -> Result<Action, Error> {
+        let settings = self.blockdata.macro_settings;
+        let check = self.cadence_tracker.check(&settings, kind, Instant::now());
+        let CadenceCheck::Suspicious { repeats, interval } = check else {
+            return Ok(Action::Nothing);
+        };
+        let id = self.get_user_id();
+        let details = format!("{repeats} {kind} inputs every {} ms", interval.as_millis());
+        log::warn!("Player {id} is suspected of using a macro: {details}");
+        let time = self.blockdata.clock.now();
+        self.blockdata
+            .sql
+            .log_anomaly(id, "macro", &details, time)
+            .await?;
+        match settings.enforcement {
+            MacroEnforcement::Log => Ok(Action::Nothing),
+            MacroEnforcement::Kick => Ok(Action::Disconnect(DisconnectReason::Macro)),
+        }
+    }
+    /// Checks if the lobby action requires a ticket that the current character hasn't used.
+    pub fn is_action_locked(&self, action: &str) -> bool {
+        self.blockdata.unlocks.is_locked_action(action)
+            && !self
+                .character
+                .as_ref()
+                .is_some_and(|c| c.unlocks.lobby_actions.iter().any(|a| a == action))
+    }
+    /// Checks if the current character blacklisted the player.
+    pub fn is_blacklisted(&self, id: u32) -> bool {
+        self.blacklist.contains(&id)
+    }
     pub async fn spawn_character(&mut self, packet: CharacterSpawnPacket) -> Result<(), Error> {
         self.send_packet(&Packet::CharacterSpawn(packet)).await?;
         Ok(())
@@ -169,13 +277,23 @@ impl User {
     pub const fn get_user_id(&self) -> u32 {
         self.user_data.id
     }
+    /// Checks if the player left the ship with the disconnect, rather than moving to another
+    /// block or being replaced by a new login.
+    pub fn left_ship(&self, reason: DisconnectReason) -> bool {
+        self.user_data.id != 0
+            && !self.switching_block
+            && !matches!(
+                reason,
+                DisconnectReason::Kicked | DisconnectReason::Banned | DisconnectReason::LoginFailed
+            )
+    }
     pub const fn get_zone_id(&self) -> u32 {
         self.zone_id
     }
     pub const fn get_stats(&self) -> &PlayerStats {
         &self.battle_stats
     }
-    pub fn get_stats_mut(&mut self) -> &mut PlayerStats {
+    pub const fn get_stats_mut(&mut self) -> &mut PlayerStats {
         &mut self.battle_stats
     }
     pub const fn create_object_header(&self) -> ObjectHeader {
@@ -221,6 +339,29 @@ impl User {
         .await?;
         Ok(())
     }
+    /// Sends the system message without waiting for the connection.
+    pub fn try_send_system_msg(
+        &mut self,
+        msg: &(impl std::fmt::Display + ?Sized),
+    ) -> Result<(), Error> {
+        self.try_send_packet(&Packet::SystemMessage(Pr::unk19::SystemMessagePacket {
+            message: msg.to_string(),
+            msg_type: Pr::unk19::MessageType::SystemMessage,
+            ..Default::default()
+        }))
+    }
+    pub async fn send_admin_msg(
+        &mut self,
+        msg: &(impl std::fmt::Display + ?Sized + Sync),
+    ) -> Result<(), Error> {
+        self.send_packet(&Packet::SystemMessage(Pr::unk19::SystemMessagePacket {
+            message: msg.to_string(),
+            msg_type: Pr::unk19::MessageType::AdminMessage,
+            ..Default::default()
+        }))
+        .await?;
+        Ok(())
+    }
     pub async fn send_error(
         &mut self,
         msg: &(impl std::fmt::Display + ?Sized + Sync),
@@ -233,6 +374,23 @@ impl User {
         .await?;
         Ok(())
     }
+    /// Sends the message explaining the disconnect, if any, and flushes pending packets. Doesn't
+    /// wait for the client, which might be gone already.
+    pub fn send_disconnect_message(&mut self, reason: DisconnectReason) {
+        if let Some(message) = reason.message(self.user_data.lang) {
+            let _ = self.try_send_packet(&Packet::SystemMessage(Pr::unk19::SystemMessagePacket {
+                message: message.to_string(),
+                msg_type: Pr::unk19::MessageType::AdminMessageInstant,
+                ..Default::default()
+            }));
+        }
+        let _ = self.connection.flush();
+    }
+    /// Sends a localized message of the handler failure.
+    pub async fn send_error_code(&mut self, code: ErrorCode) -> Result<(), Error> {
+        let message = code.message(self.user_data.lang);
+        self.send_error(&message).await
+    }
     pub async fn send_position(
         user: MutexGuard<'_, User>,
         packet: Packet,
@@ -253,7 +411,8 @@ impl User {
             gained: exp as _,
             ..Default::default()
         };
-        let srv_data = &self.blockdata.server_data;
+        let stats = &self.blockdata.server_data.player_stats;
+        let exp_settings = self.blockdata.exp_settings;
         let char = self
             .character
             .as_mut()
@@ -261,29 +420,30 @@ impl User {
         let class_offset = char.character.classes.main_class as usize;
         let subclass_offset = char.character.classes.sub_class as usize;
 
-        fn increase_level(
-            srv_data: &data_structs::ServerData,
-            level: &mut ClassLevel,
-            offset: usize,
-            exp: u32,
-        ) {
-            let stats = &srv_data.player_stats.stats[offset][level.level1 as usize - 1];
-            let new_exp = level.exp + exp;
-            if new_exp < stats.exp_to_next as _ {
-                return;
-            }
-            level.level1 += 1;
-            level.level2 = level.level1;
-        }
-
+        let player_id = self.user_data.id;
+        let events = &self.blockdata.events;
+        let (main_class, sub_class) = (
+            char.character.classes.main_class,
+            char.character.classes.sub_class,
+        );
+        // classes that have reached the cap with this experience
+        let mut capped = vec![];
         // main class
         {
             let level = char.character.get_level_mut();
-            let new_exp = level.exp + exp;
-            if level.level1 < 100 {
-                increase_level(srv_data, level, class_offset, exp);
+            let cap = stats.level_cap(class_offset);
+            let old_level = level.level1;
+            packet.gained = add_class_exp(stats, level, class_offset, cap, exp) as _;
+            if level.level1 != old_level {
+                events.emit(GameEvent::LevelUp {
+                    player_id,
+                    class: main_class,
+                    level: level.level1 as u32,
+                });
+                if level.level1 as u32 >= cap {
+                    capped.push(main_class);
+                }
             }
-            level.exp = new_exp;
             packet.total = level.exp as _;
             packet.level2 = level.level2;
             packet.level = level.level1;
@@ -292,18 +452,34 @@ impl User {
 
         if !matches!(char.character.classes.sub_class, Class::Unknown) {
             let level = char.character.get_sublevel_mut();
-            let exp = if level.level1 >= 70 { 0 } else { exp };
-            let new_exp = level.exp + exp;
-            if level.level1 < 100 {
-                increase_level(srv_data, level, subclass_offset, exp);
+            let cap = stats
+                .level_cap(subclass_offset)
+                .min(exp_settings.subclass_max_level);
+            let exp = (exp as f32 * exp_settings.subclass_share) as u32;
+            let old_level = level.level1;
+            packet.gained_sub = add_class_exp(stats, level, subclass_offset, cap, exp) as _;
+            if level.level1 != old_level {
+                events.emit(GameEvent::LevelUp {
+                    player_id,
+                    class: sub_class,
+                    level: level.level1 as u32,
+                });
+                if level.level1 as u32 >= cap {
+                    capped.push(sub_class);
+                }
             }
-            level.exp = new_exp;
-            packet.gained_sub = exp as _;
             packet.total_sub = level.exp as _;
             packet.level2_sub = level.level2;
             packet.level_sub = level.level1;
         }
         packet.subclass = char.character.classes.sub_class;
+        for class in capped {
+            self.try_send_packet(&Packet::SystemMessage(Pr::unk19::SystemMessagePacket {
+                message: format!("{class:?} has reached the maximum level"),
+                msg_type: Pr::unk19::MessageType::SystemMessage,
+                ..Default::default()
+            }))?;
+        }
         self.battle_stats = PlayerStats::build(self)?;
         Ok(packet)
     }
@@ -359,12 +535,49 @@ impl User {
     pub fn get_char_flags(&self) -> Option<Flags> {
         self.character.as_ref().map(|c| c.flags.clone())
     }
+    /// Saves the session and disconnects the player after showing the message.
+    pub async fn kick(&mut self, message: &str) -> Result<(), Error> {
+        let _ = self.send_system_msg(message).await;
+        self.save_session().await?;
+        self.state = UserState::LoggingIn;
+        self.shutdown_reason = Some(DisconnectReason::Kicked);
+        self.last_ping = Instant::now();
+        Ok(())
+    }
+    /// Saves the character and account data and unloads the character.
+    pub async fn save_session(&mut self) -> Result<(), Error> {
+        let Some(mut char) = self.character.take() else {
+            return Ok(());
+        };
+        let sql = self.blockdata.sql.clone();
+        char.play_time += self.session_start.elapsed();
+        sql.update_character(&char).await?;
+        sql.update_account_storage(self.user_data.id, &mut char.inventory)
+            .await?;
+        sql.set_account_data(sql::User {
+            id: self.user_data.id,
+            accountflags: self.user_data.accountflags.clone(),
+            last_uuid: self.user_data.last_uuid,
+            ..Default::default()
+        })
+        .await
+    }
 }
 
 pub async fn packet_handler(
     mut user_guard: MutexGuard<'_, User>,
     packet: Packet,
 ) -> Result<Action, Error> {
+    let packet_type = user_guard.user_data.packet_type;
+    user_guard.traffic.record_in(&packet, packet_type);
+    if packet_type == PacketType::NGS {
+        return ngs_packet_handler(user_guard, packet).await;
+    }
+    if let Some(kind) = InputKind::of(&packet) {
+        if let action @ Action::Disconnect(_) = user_guard.check_cadence(kind).await? {
+            return Ok(action);
+        }
+    }
     let user: &mut User = &mut user_guard;
     let state = user.state;
     // sidestep borrow checker
@@ -391,6 +604,12 @@ pub async fn packet_handler(
 
         // Object packets
         (US::InGame, P::Movement(data)) => H::object::movement(user_guard, data).await,
+        (US::InGame, P::MovementAction(ref data))
+            if user.is_action_locked(&data.action.to_string()) =>
+        {
+            user.send_system_msg("This lobby action is locked").await?;
+            Ok(Action::Nothing)
+        }
         (US::InGame, P::MovementAction(..)) => User::send_position(user_guard, match_unit.1).await,
         (US::InGame, P::Interact(data)) => H::object::action(user_guard, data).await,
         (US::InGame, P::ChangeClassRequest(data)) => {
@@ -472,7 +691,9 @@ pub async fn packet_handler(
         }
 
         // Login packets
-        (US::LoggingIn, P::SegaIDLogin(..)) => H::login::login_request(user, match_unit.1).await,
+        (US::LoggingIn, P::SegaIDLogin(..)) => {
+            H::login::login_request(&mut user_guard, match_unit.1).await
+        }
         (US::CharacterSelect, P::CharacterListRequest) => H::login::character_list(user).await,
         (US::CharacterSelect, P::StartGame(data)) => H::login::start_game(user, data).await,
         (US::CharacterSelect, P::CharacterCreate(data)) => {
@@ -485,23 +706,28 @@ pub async fn packet_handler(
         (_, P::ClientPing(data)) => H::login::client_ping(user, data).await,
         (_, P::BlockListRequest) => H::login::block_list(user).await,
         (US::InGame, P::BlockSwitchRequest(data)) => H::login::switch_block(user, data).await,
-        (US::LoggingIn, P::BlockLogin(data)) => H::login::challenge_login(user, data).await,
-        (US::NewUsername, P::NicknameResponse(data)) => H::login::set_username(user, data).await,
+        (US::LoggingIn, P::BlockLogin(data)) => {
+            H::login::challenge_login(&mut user_guard, data).await
+        }
+        (US::LoggingIn, P::SecondPwdOperationRequest(data)) => {
+            H::login::otp_response(&mut user_guard, data).await
+        }
+        (US::NewUsername, P::NicknameResponse(data)) => {
+            H::login::set_username(&mut user_guard, data).await
+        }
         (_, P::ClientGoodbye) => {
-            user.ready_to_shutdown = true;
+            user.shutdown_reason = Some(DisconnectReason::Goodbye);
             user.last_ping = Instant::now();
             Ok(Action::Nothing)
         }
         (_, P::SystemInformation(..)) => Ok(Action::Nothing),
         (US::CharacterSelect, P::CreateCharacter1) => H::login::character_create1(user).await,
         (US::CharacterSelect, P::CreateCharacter2) => H::login::character_create2(user).await,
-        (US::LoggingIn, P::VitaLogin(..)) => H::login::login_request(user, match_unit.1).await,
-        (_, P::AllBlocksListRequest) => H::login::all_block_list(user).await,
-        (_, P::ChallengeResponse(..)) => {
-            user.user_data.packet_type = PacketType::NA;
-            user.connection.change_packet_type(PacketType::NA);
-            Ok(Action::Nothing)
+        (US::LoggingIn, P::VitaLogin(..)) => {
+            H::login::login_request(&mut user_guard, match_unit.1).await
         }
+        (_, P::AllBlocksListRequest) => H::login::all_block_list(user).await,
+        (_, P::ChallengeResponse(..)) => H::login::challenge_response(&mut user_guard).await,
         (US::CharacterSelect, P::LoginHistoryRequest) => H::login::login_history(user).await,
         (US::CharacterSelect, P::CharacterUndeletionRequest(data)) => {
             H::login::undelete_request(user, data).await
@@ -593,10 +819,75 @@ pub async fn packet_handler(
     // }
 }
 
+/// Handles packets of NGS clients. Only the login and block selection are supported for now.
+async fn ngs_packet_handler(
+    mut user_guard: MutexGuard<'_, User>,
+    packet: Packet,
+) -> Result<Action, Error> {
+    let user: &mut User = &mut user_guard;
+    let state = user.state;
+    use {handlers as H, Packet as P, UserState as US};
+
+    match (state, packet) {
+        (_, P::ServerPong) => {
+            user.failed_pings = 0;
+            Ok(Action::Nothing)
+        }
+
+        // Login packets
+        (US::LoggingIn, packet @ P::SegaIDLogin(..)) => {
+            H::login::login_request(&mut user_guard, packet).await
+        }
+        (US::LoggingIn, P::BlockLogin(data)) => {
+            H::login::challenge_login(&mut user_guard, data).await
+        }
+        (US::LoggingIn, P::SecondPwdOperationRequest(data)) => {
+            H::login::otp_response(&mut user_guard, data).await
+        }
+        (US::NewUsername, P::NicknameResponse(data)) => {
+            H::login::set_username(&mut user_guard, data).await
+        }
+        (US::CharacterSelect, P::CharacterListRequest) => H::login::character_list(user).await,
+        (US::CharacterSelect, P::LoginHistoryRequest) => H::login::login_history(user).await,
+        (_, P::EncryptionRequest(data)) => H::login::encryption_request(user, data).await,
+        (_, P::ClientPing(data)) => H::login::client_ping(user, data).await,
+        (_, P::BlockListRequest) => H::login::block_list(user).await,
+        (_, P::AllBlocksListRequest) => H::login::all_block_list(user).await,
+        (US::InGame, P::BlockSwitchRequest(data)) => H::login::switch_block(user, data).await,
+        // packet type is already negotiated
+        (_, P::ChallengeResponse(..)) => Ok(Action::Nothing),
+        (_, P::ClientGoodbye) => {
+            user.shutdown_reason = Some(DisconnectReason::Goodbye);
+            user.last_ping = Instant::now();
+            Ok(Action::Nothing)
+        }
+        (_, P::SystemInformation(..)) => Ok(Action::Nothing),
+
+        // Settings packets
+        (_, P::SettingsRequest) if state >= US::NewUsername => {
+            H::settings::settings_request(user).await
+        }
+        (_, P::SaveSettings(data)) if state >= US::NewUsername => {
+            H::settings::save_settings(user, data).await
+        }
+
+        (state, data) => {
+            log::debug!(
+                "NGS client {} in state ({state}) sent unsupported packet: {data:?}",
+                user.user_data.id
+            );
+            Ok(Action::Nothing)
+        }
+    }
+}
+
 impl Drop for User {
     fn drop(&mut self) {
         let player_id = self.user_data.id;
         log::debug!("Dropping user {player_id}");
+        self.blockdata
+            .directory
+            .unregister(player_id, self.blockdata.block_id, self.conn_id);
         if let Some(mut char) = self.character.take() {
             let sql = self.blockdata.sql.clone();
             let data = std::mem::take(&mut self.user_data);
@@ -604,12 +895,18 @@ impl Drop for User {
             char.play_time += spent;
             tokio::spawn(async move {
                 let _ = sql.update_character(&char).await;
-                let _ = sql.update_account_storage(player_id, &char.inventory).await;
+                let _ = sql
+                    .update_account_storage(player_id, &mut char.inventory)
+                    .await;
                 let _ = sql.set_account_data(data).await;
             });
         }
         if let Some(party) = self.party.take() {
-            tokio::spawn(async move { party.write().await.remove_player(player_id).await });
+            if self.switching_block {
+                self.blockdata.parties.start_switch(player_id, party);
+            } else {
+                tokio::spawn(async move { party.write().await.remove_player(player_id).await });
+            }
         }
         if let Some(map) = self.map.take() {
             tokio::spawn(async move { map.lock().await.remove_player(player_id).await });
@@ -645,11 +942,39 @@ impl Display for UserState {
     }
 }
 
+/// Adds experience to the class and levels it up until the cap. Returns the added experience.
+fn add_class_exp(
+    stats: &data_structs::stats::PlayerStats,
+    level: &mut ClassLevel,
+    offset: usize,
+    cap: u32,
+    exp: u32,
+) -> u32 {
+    if level.level1 as u32 >= cap {
+        return 0;
+    }
+    level.exp += exp;
+    let old_level = level.level1;
+    while (level.level1 as u32) < cap {
+        let next = &stats.stats[offset][level.level1 as usize - 1];
+        if (level.exp as u64) < next.exp_to_next {
+            break;
+        }
+        level.level1 += 1;
+    }
+    if level.level1 != old_level {
+        level.level2 = level.level1;
+    }
+    exp
+}
+
 #[cfg(test)]
 mod test {
     use std::cmp::Ordering;
 
-    use crate::user::UserState;
+    use crate::user::{add_class_exp, UserState};
+    use data_structs::stats::{LevelStats, PlayerStats};
+    use pso2packetlib::protocol::models::character::ClassLevel;
 
     #[test]
     fn test_userstate() {
@@ -667,4 +992,44 @@ mod test {
         );
         assert!(UserState::InGame > UserState::LoggingIn);
     }
+
+    #[test]
+    fn test_add_class_exp() {
+        let stats = PlayerStats {
+            stats: vec![[100, 300, 600, 1000, 1500]
+                .into_iter()
+                .map(|exp_to_next| LevelStats {
+                    exp_to_next,
+                    ..Default::default()
+                })
+                .collect()],
+            ..Default::default()
+        };
+        let mut level = ClassLevel {
+            level1: 1,
+            level2: 1,
+            ..Default::default()
+        };
+        // several levels at once
+        assert_eq!(add_class_exp(&stats, &mut level, 0, 5, 650), 650);
+        assert_eq!(level.level1, 4);
+        assert_eq!(level.level2, 4);
+        assert_eq!(level.exp, 650);
+        // stops at the cap
+        assert_eq!(add_class_exp(&stats, &mut level, 0, 5, 10000), 10000);
+        assert_eq!(level.level1, 5);
+        assert_eq!(level.exp, 10650);
+        // capped classes don't gain experience
+        assert_eq!(add_class_exp(&stats, &mut level, 0, 5, 100), 0);
+        assert_eq!(level.exp, 10650);
+        // cap below the stats table
+        let mut level = ClassLevel {
+            level1: 1,
+            level2: 1,
+            ..Default::default()
+        };
+        assert_eq!(add_class_exp(&stats, &mut level, 0, 3, 10000), 10000);
+        assert_eq!(level.level1, 3);
+        assert_eq!(level.level2, 3);
+    }
 }